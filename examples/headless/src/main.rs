@@ -0,0 +1,56 @@
+//! ウィンドウを開かずに、30 Hz でシーンを更新し続けるサーバーの例
+//!
+//! 3 秒分 (90 回) 更新したら終わる。
+use reverie_engine::{prelude::*, scene::resource_mut};
+
+const TICK_RATE: u32 = 30;
+const TICKS: u64 = 90;
+
+fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt().init();
+
+    let mut scene = Scene::default();
+    scene.insert_resource(Counter::default());
+    scene.register_system(CounterSystem);
+
+    let mut runner = HeadlessRunner::with_tick_rate(scene, TICK_RATE);
+    runner.setup()?;
+    runner.run_until(|scene| {
+        scene
+            .resource::<Counter>()
+            .is_some_and(|counter| counter.ticks >= TICKS)
+    });
+
+    let counter = runner.scene().resource::<Counter>().map(|c| *c);
+    tracing::info!(?counter, "done");
+    Ok(())
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+/// 更新の回数と、シミュレーション上の経過時間
+struct Counter {
+    ticks: u64,
+    seconds: f32,
+}
+
+/// [`Counter`] を進めるだけのシステム
+struct CounterSystem;
+
+impl System for CounterSystem {
+    fn setup(&mut self, _resource: Option<&RenderResource<'_>>) {}
+
+    fn update(
+        &mut self,
+        frame: &Frame<'_>,
+        world: &mut hecs::World,
+        _resource: Option<&RenderResource<'_>>,
+    ) {
+        if let Some(mut counter) = resource_mut::<Counter>(world) {
+            counter.ticks += 1;
+            counter.seconds += frame.delta_time.as_secs_f32();
+            if counter.ticks % u64::from(TICK_RATE) == 0 {
+                tracing::info!(ticks = counter.ticks, seconds = counter.seconds, "tick");
+            }
+        }
+    }
+}
@@ -1,10 +1,5 @@
 use nalgebra::{Scale3, Translation3, Unit, UnitQuaternion, Vector3};
-use reverie_engine::{
-    scene::{EntityIndex, Frame, Scene, SpriteComponent, System, TransformComponent},
-    wgpu_wrapper::WgpuResource,
-    Game,
-};
-use winit::event::{ElementState, MouseButton};
+use reverie_engine::prelude::*;
 
 fn setup_cli() {
     use tracing_subscriber::fmt::format::FmtSpan;
@@ -21,27 +16,29 @@ fn main() -> anyhow::Result<()> {
     setup_cli();
 
     let game = LineDefense::default();
-    reverie_engine::start_engine(game)
+    let config = EngineConfig {
+        settings: Settings::load_with_args(),
+        ..EngineConfig::default()
+    };
+    start_engine_with_config(game, config)
 }
 
 #[derive(Debug, Default)]
 pub struct LineDefense {}
 
 impl Game for LineDefense {
-    fn generate_scene(
-        &mut self,
-        registry: &mut reverie_engine::texture::TextureRegistry,
-    ) -> anyhow::Result<Scene> {
+    fn generate_scene(&mut self, registry: &mut TextureRegistry) -> anyhow::Result<Scene> {
         let tex_apple = registry
             .new_texture(
                 image::load_from_memory(include_bytes!("../assets/apple.png"))
                     .unwrap()
                     .to_rgba8(),
-                None,
+                Some("assets/apple.png".to_string()),
             )
             .into();
 
-        let cat_and_snake = registry.create_altas_texture(256, 512, None);
+        let cat_and_snake =
+            registry.create_altas_texture(256, 512, Some("cat_and_snake".to_string()));
 
         let tex_cat = registry
             .allocate_sub_image(
@@ -85,12 +82,12 @@ impl Game for LineDefense {
             ),
             SpriteComponent::new(tex_cat),
         );
-        let snake = scene.new_entity(
-            TransformComponent::with_translation_and_scale(
-                Translation3::new(250.0, 250.0, 0.0),
-                Scale3::new(100.0, 100.0, 1.0),
-            ),
-            SpriteComponent::new(tex_snake),
+        // プレイヤーはりんごより手前に描画する
+        let snake = scene.new_sprite(
+            TransformComponent::with_translation(Translation3::new(250.0, 250.0, 0.0)),
+            SpriteComponent::builder(tex_snake)
+                .size(100.0, 100.0)
+                .layer(1),
         );
         scene.register_system(PlayerController::with_player(snake));
 
@@ -114,9 +111,14 @@ impl PlayerController {
 }
 
 impl System for PlayerController {
-    fn setup(&mut self, _resource: &WgpuResource) {}
+    fn setup(&mut self, _resource: Option<&RenderResource>) {}
 
-    fn update(&mut self, frame: &Frame, world: &mut hecs::World, _resource: &WgpuResource) {
+    fn update(
+        &mut self,
+        frame: &Frame,
+        world: &mut hecs::World,
+        _resource: Option<&RenderResource>,
+    ) {
         if let Ok(ref mut transform) = world.get::<&mut TransformComponent>(self.id.0) {
             if self.is_moving {
                 transform.translation.x = frame.mouse_position.x as f32;
@@ -1,20 +1,19 @@
 #![cfg_attr(all(windows, not(debug_assertions)), windows_subsystem = "windows")]
 
 use player::Player;
-use re::util::math;
 use world::World;
 
 use re::gl;
 use re::shader::Program;
 use re::texture::TextureAtlasPos;
 use re::types::Const;
-use re::util::math::Deg;
-use re::util::math::Rad;
+use re::util::math::fps_camera::MoveInput;
 use re::vao::CuboidTextures;
 use re::vao::Phong3DRenderer;
 use re::vao::PhongRenderingInfo;
 use re::vao::VaoConfigBuilder;
 use re::window::winit;
+use re::window::winit::event::VirtualKeyCode;
 use re::ReverieEngine;
 use reverie_engine_opengl as re;
 
@@ -120,7 +119,7 @@ fn main() {
     };
 
     // TODO: use Phong3DRenderer
-    let _renderer = Phong3DRenderer::new(shader);
+    let _renderer = Phong3DRenderer::new(gl.clone(), shader);
     let mut vertex_obj;
 
     let mut player = Player::new(gl.clone());
@@ -137,48 +136,19 @@ fn main() {
             break;
         }
 
-        const UP: Vector3 = Vector3::new(0.0, 1.0, 0.0);
-        let (front, right, _up) =
-            math::calc_front_right_up(player.camera.yaw(), player.camera.pitch());
-        let front = util::take_xz_normalized(&front);
-        if window.keypressed(&winit::event::VirtualKeyCode::W) {
-            player.velocity += front * config.move_speed;
-        }
-        if window.keypressed(&winit::event::VirtualKeyCode::S) {
-            player.velocity -= front * config.move_speed;
-        }
-        if window.keypressed(&winit::event::VirtualKeyCode::D) {
-            player.velocity += right * config.move_speed;
-        }
-        if window.keypressed(&winit::event::VirtualKeyCode::A) {
-            player.velocity -= right * config.move_speed;
-        }
-        if window.keypressed(&winit::event::VirtualKeyCode::Space) {
-            player.velocity += UP * config.move_speed;
-        }
-        if window.keypressed(&winit::event::VirtualKeyCode::LShift) {
-            player.velocity -= UP * config.move_speed;
-        }
+        let axis = |positive, negative| {
+            f32::from(u8::from(window.keypressed(&positive)))
+                - f32::from(u8::from(window.keypressed(&negative)))
+        };
+        let input = MoveInput {
+            forward: axis(VirtualKeyCode::W, VirtualKeyCode::S),
+            right: axis(VirtualKeyCode::D, VirtualKeyCode::A),
+            up: axis(VirtualKeyCode::Space, VirtualKeyCode::LShift),
+        };
+        player.velocity += player.camera.controller().move_direction(input) * config.move_speed;
 
         let (dx, dy) = window.cursor_delta();
-        if dy != 0 {
-            player
-                .camera
-                .add_pitch(Rad(-dy as f32 * config.rotation_speed));
-            // player.camera.pitch = player.camera.pitch.normalized();
-            if player.camera.pitch() < Deg(-90_f32).to_rad() {
-                player.camera.set_pitch(Deg(-90_f32).to_rad());
-            }
-            if Deg(90_f32).to_rad() < player.camera.pitch() {
-                player.camera.set_pitch(Deg(90_f32).to_rad());
-            }
-        }
-        if dx != 0 {
-            player
-                .camera
-                .add_yaw(Rad(-dx as f32 * config.rotation_speed));
-            // player.camera.yaw = player.camera.yaw.normalized();
-        }
+        player.camera.controller_mut().look(dx as f32, dy as f32);
 
         if window.mouse_down(&winit::event::MouseButton::Left) {
             if let Some((x, y, z)) = raycast::hit_block(&player, &world) {
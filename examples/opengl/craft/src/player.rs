@@ -1,5 +1,9 @@
 use parry3d::shape::Cuboid;
-use reverie_engine_opengl::{camera::Camera, gl::Gl, math::Deg};
+use reverie_engine_opengl::{
+    camera::Camera,
+    gl::Gl,
+    math::{fps_camera::FpsMoveMode, Deg},
+};
 
 use crate::{collision, config, world::World, Point3, Vector3};
 
@@ -16,14 +20,20 @@ impl Player {
     pub fn new(gl: Gl) -> Self {
         let config = config::get();
 
+        let mut camera = Camera::new(
+            gl,
+            config.player_init_pos + config.eye,
+            Deg(config.player_init_yaw_deg).to_rad(),
+            Deg(config.player_init_pitch_deg).to_rad(),
+            Deg(config.fov),
+        );
+        let controller = camera.controller_mut();
+        controller.sensitivity = config.rotation_speed;
+        // 見上げていても水平に歩き、Space と Shift で上下に動く
+        controller.mode = FpsMoveMode::Walk;
+
         Self {
-            camera: Camera::new(
-                gl,
-                config.player_init_pos + config.eye,
-                Deg(config.player_init_yaw_deg).to_rad(),
-                Deg(config.player_init_pitch_deg).to_rad(),
-                Deg(config.fov),
-            ),
+            camera,
             pos: config.player_init_pos,
             velocity: Vector3::zeros(),
             bounding_box: Cuboid::new(config.player_bounding_vec),
@@ -0,0 +1,153 @@
+//! 床で弾むボールがぶつかるたびに、ゲームパッドを振動させる例
+//!
+//! ウィンドウを開かずに 60 Hz で 6 秒間動かす。ボールが床に着くと [`Landed`] イベントを送り、
+//! [`RumbleOnLandingSystem`] がぶつかった速さに合わせて最初のゲームパッドを振動させる。
+//! 振動は gilrs で出すので、force feedback に対応したゲームパッドをつないで実行する。
+use std::time::Duration;
+
+use nalgebra::{Translation3, Vector2};
+use reverie_engine::{
+    input::GilrsRumble,
+    physics::{ColliderComponent, KinematicBodyComponent, KinematicSystem},
+    prelude::*,
+    scene::{clear_events, resource, resource_mut, send_event, Events, SceneClock, TaskQueue},
+};
+
+const TICK_RATE: u32 = 60;
+const RUN_FOR: Duration = Duration::from_secs(6);
+/// 振動させるゲームパッド。gilrs では最初につながったものが 0 になる
+const PAD: GamepadId = GamepadId(0);
+/// この速さ以上でぶつかると最も強く振動する
+const FULL_RUMBLE_SPEED: f32 = 900.0;
+/// 跳ね返るときに残る速さの割合
+const BOUNCINESS: f32 = 0.6;
+/// これより遅くぶつかったら跳ね返らずに止まる
+const REST_SPEED: f32 = 60.0;
+
+fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt().init();
+
+    let mut scene = Scene::default();
+    match GilrsRumble::new() {
+        Ok(backend) => scene.insert_resource(Rumble::new(backend)),
+        Err(error) => tracing::warn!("rumble is disabled: {error:#}"),
+    }
+    let mut tasks = TaskQueue::default();
+    tasks.push_once(0, spawn_stage);
+    scene.insert_resource(tasks);
+
+    // 依存関係を書かないシステムは登録した順に実行される
+    scene.register_system(KinematicSystem::new());
+    scene.register_system(LandingSystem);
+    scene.register_system(RumbleOnLandingSystem);
+    scene.register_system(RumbleSystem);
+
+    let mut runner = HeadlessRunner::with_tick_rate(scene, TICK_RATE);
+    runner.setup()?;
+    runner.run_until(|scene| {
+        scene
+            .resource::<SceneClock>()
+            .is_some_and(|clock| clock.elapsed >= RUN_FOR)
+    });
+    Ok(())
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+/// 弾むボール
+struct Ball {
+    was_on_ground: bool,
+    /// 宙にいる間の、下向きの速さ
+    fall_speed: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+/// ボールが床にぶつかったことを知らせるイベント
+struct Landed {
+    ball: hecs::Entity,
+    speed: f32,
+}
+
+/// 床と、高さの違う 3 つのボールを置く
+fn spawn_stage(world: &mut hecs::World) {
+    world.spawn((
+        TransformComponent::with_translation(Translation3::new(-400.0, 400.0, 0.0)),
+        ColliderComponent::new(Vector2::new(800.0, 40.0)),
+    ));
+    for (i, height) in [300.0, 100.0, -200.0].into_iter().enumerate() {
+        world.spawn((
+            TransformComponent::with_translation(Translation3::new(
+                -200.0 + 200.0 * i as f32,
+                height,
+                0.0,
+            )),
+            KinematicBodyComponent::default(),
+            ColliderComponent::new(Vector2::new(16.0, 16.0)),
+            Ball::default(),
+        ));
+    }
+}
+
+/// 床に着いたボールを見つけて [`Landed`] を送り、跳ね返らせる
+struct LandingSystem;
+
+impl System for LandingSystem {
+    fn setup(&mut self, _resource: Option<&RenderResource<'_>>) {}
+
+    fn update(
+        &mut self,
+        _frame: &Frame<'_>,
+        world: &mut hecs::World,
+        _resource: Option<&RenderResource<'_>>,
+    ) {
+        clear_events::<Landed>(world);
+        let mut landed = Vec::new();
+        for (entity, (body, ball)) in world.query_mut::<(&mut KinematicBodyComponent, &mut Ball)>()
+        {
+            if body.on_ground() && !ball.was_on_ground {
+                landed.push(Landed {
+                    ball: entity,
+                    speed: ball.fall_speed,
+                });
+                if ball.fall_speed > REST_SPEED {
+                    body.velocity.y = -ball.fall_speed * BOUNCINESS;
+                }
+            }
+            ball.was_on_ground = body.on_ground();
+            ball.fall_speed = body.velocity.y.max(0.0);
+        }
+        for event in landed {
+            tracing::info!(ball = ?event.ball, speed = event.speed, "landed");
+            send_event(world, event);
+        }
+    }
+}
+
+/// [`Landed`] を受け取り、最も速くぶつかったものに合わせて振動を頼む
+struct RumbleOnLandingSystem;
+
+impl System for RumbleOnLandingSystem {
+    fn setup(&mut self, _resource: Option<&RenderResource<'_>>) {}
+
+    fn update(
+        &mut self,
+        _frame: &Frame<'_>,
+        world: &mut hecs::World,
+        _resource: Option<&RenderResource<'_>>,
+    ) {
+        let Some(speed) = resource::<Events<Landed>>(world).and_then(|events| {
+            events
+                .iter()
+                .map(|landed| landed.speed)
+                .max_by(f32::total_cmp)
+        }) else {
+            return;
+        };
+        let strength = (speed / FULL_RUMBLE_SPEED).min(1.0);
+        if let Some(mut input) = resource_mut::<Input>(world) {
+            // 重い衝撃は低い周波数の大きいモーターに任せ、高い方は添える程度にする
+            input
+                .gamepad(PAD)
+                .rumble(strength, strength * 0.5, Duration::from_millis(150));
+        }
+    }
+}
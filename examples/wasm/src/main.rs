@@ -0,0 +1,60 @@
+//! Web で動かすための最小のサンプル
+//!
+//! テクスチャは `include_bytes!` で埋め込むので、ファイルシステムを使わない。
+use nalgebra::{Scale3, Translation3, UnitQuaternion, Vector3};
+use reverie_engine::prelude::*;
+
+fn main() -> anyhow::Result<()> {
+    #[cfg(target_arch = "wasm32")]
+    console_error_panic_hook::set_once();
+
+    start_engine(WasmExample)
+}
+
+#[derive(Debug, Default)]
+pub struct WasmExample;
+
+impl Game for WasmExample {
+    fn generate_scene(&mut self, registry: &mut TextureRegistry) -> anyhow::Result<Scene> {
+        let tex_apple = registry
+            .new_texture_from_bytes(
+                include_bytes!("../../misc/assets/apple.png"),
+                Some("apple.png".to_string()),
+            )?
+            .into();
+
+        let mut scene = Scene::default();
+        let apple = scene.new_entity(
+            TransformComponent::with_translation_and_scale(
+                Translation3::new(200.0, 200.0, 0.0),
+                Scale3::new(100.0, 100.0, 1.0),
+            ),
+            SpriteComponent::new(tex_apple),
+        );
+        scene.register_system(RotateSystem { target: apple });
+
+        Ok(scene)
+    }
+}
+
+/// スプライトを回転させるシステム
+#[derive(Debug)]
+pub struct RotateSystem {
+    target: EntityIndex,
+}
+
+impl System for RotateSystem {
+    fn setup(&mut self, _resource: Option<&RenderResource<'_>>) {}
+
+    fn update(
+        &mut self,
+        frame: &Frame<'_>,
+        world: &mut hecs::World,
+        _resource: Option<&RenderResource<'_>>,
+    ) {
+        if let Ok(mut transform) = world.get::<&mut TransformComponent>(self.target.0) {
+            let angle = frame.delta_time.as_secs_f32();
+            transform.rotation *= UnitQuaternion::from_axis_angle(&Vector3::z_axis(), angle);
+        }
+    }
+}
@@ -0,0 +1,50 @@
+//! [`VaoBuffer`] の構築にかかる時間の計測
+//!
+//! GPU には触れず、CPU 側で頂点データを組み立てる部分だけを計測する。
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use reverie_engine_opengl::{
+    texture::{TextureAtlasPos, TextureUV},
+    types::{Const, Point3},
+    vao::{CuboidTextures, VaoBuffer, VaoBuilder3DGeometry, VertexWithNormUv},
+};
+
+type Uv = TextureUV<Const<64>, Const<64>, Const<256>, Const<256>>;
+
+const CHUNK_SIZE: usize = 16;
+
+fn build_chunk(textures: &CuboidTextures<'_, Uv>) -> VaoBuffer<VertexWithNormUv> {
+    // 1 ブロックあたり 6 面 × 6 頂点
+    let mut buffer = VaoBuffer::with_num_vertex(CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE * 36);
+    for x in 0..CHUNK_SIZE {
+        for y in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let begin = Point3::new(x as f32, y as f32, z as f32);
+                let end = Point3::new(x as f32 + 1.0, y as f32 + 1.0, z as f32 + 1.0);
+                buffer.add_cuboid(&begin, &end, textures);
+            }
+        }
+    }
+    buffer
+}
+
+fn bench_cuboid_chunk(c: &mut Criterion) {
+    let top = Uv::of_atlas(&TextureAtlasPos::new(0, 1));
+    let bottom = Uv::of_atlas(&TextureAtlasPos::new(0, 2));
+    let side = Uv::of_atlas(&TextureAtlasPos::new(0, 0));
+    let textures = CuboidTextures {
+        top: &top,
+        bottom: &bottom,
+        south: &side,
+        north: &side,
+        west: &side,
+        east: &side,
+    };
+
+    c.bench_function("vao_buffer/cuboid_chunk_16x16x16", |b| {
+        b.iter(|| black_box(build_chunk(black_box(&textures))))
+    });
+}
+
+criterion_group!(benches, bench_cuboid_chunk);
+criterion_main!(benches);
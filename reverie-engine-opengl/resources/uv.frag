@@ -18,6 +18,8 @@ in vec3 Normal;
 in vec2 TexCoords;
 
 uniform sampler2D uScreenTexture;
+uniform sampler2D uLightmap;
+uniform bool uUseLightmap;
 uniform vec3 uViewPosition;
 uniform Material uMaterial;
 uniform Light uLight;
@@ -26,6 +28,9 @@ void main()
 {
     vec4 tex = texture(uScreenTexture, TexCoords);
     vec3 texRGB = tex.rgb;
+    if (uUseLightmap) {
+        texRGB *= texture(uLightmap, TexCoords).rgb;
+    }
     float texAlpha = tex.a;
 
     /* ambient*/
@@ -1,5 +1,5 @@
 use reverie_util::math::{
-    self,
+    fps_camera::FpsCameraController,
     nalgebra::{Matrix4, Point3, Vector3},
     Deg, Rad,
 };
@@ -12,11 +12,9 @@ use crate::{
 };
 
 #[derive(Debug)]
+/// 一人称視点のカメラ。位置と向きは [`FpsCameraController`] が持つ
 pub struct Camera {
-    pos: Point3<f32>,
-    yaw: Rad<f32>,
-    pitch: Rad<f32>,
-    fov: Deg<f32>,
+    controller: FpsCameraController,
     gl: Gl,
     renderer: Phong3DRenderer,
 }
@@ -24,12 +22,9 @@ pub struct Camera {
 impl Camera {
     pub fn new(gl: Gl, pos: Point3<f32>, yaw: Rad<f32>, pitch: Rad<f32>, fov: Deg<f32>) -> Self {
         let shader = Program::default_uv(gl.clone()).unwrap();
-        let renderer = Phong3DRenderer::new(shader);
+        let renderer = Phong3DRenderer::new(gl.clone(), shader);
         Self {
-            pos,
-            yaw,
-            pitch,
-            fov,
+            controller: FpsCameraController::new(pos, yaw, pitch).with_fov(fov),
             gl,
             renderer,
         }
@@ -52,67 +47,71 @@ impl Camera {
             model_matrix,
             view_matrix: &view_matrix,
             projection_matrix: &projection_matrix,
-            camera_pos: &self.pos,
+            camera_pos: &self.controller.position,
             texture: block_atlas_texture,
+            lightmap: None,
         };
         self.renderer.render(self.gl.clone(), vao, info);
     }
 
     pub fn view_matrix(&self) -> Matrix4<f32> {
-        let (front, _right, up) = math::calc_front_right_up(self.yaw, self.pitch);
-        Matrix4::<f32>::look_at_rh(&self.pos, &(self.pos + front), &up)
+        self.controller.view_matrix()
     }
 
     pub fn projection_matrix(&self, width: u32, height: u32) -> Matrix4<f32> {
-        Matrix4::new_perspective(
-            width as f32 / height as f32,
-            self.fov.to_rad().into(),
-            0.1,
-            100.0,
-        )
+        self.controller.projection_matrix(width, height)
+    }
+
+    pub const fn controller(&self) -> &FpsCameraController {
+        &self.controller
+    }
+
+    pub fn controller_mut(&mut self) -> &mut FpsCameraController {
+        &mut self.controller
     }
 
     pub fn set_pos(&mut self, pos: Point3<f32>) {
-        self.pos = pos;
+        self.controller.position = pos;
     }
 
     pub fn move_pos(&mut self, d: Vector3<f32>) {
-        self.pos += d;
+        self.controller.position += d;
     }
 
     pub fn set_yaw(&mut self, yaw: Rad<f32>) {
-        self.yaw = yaw;
+        self.controller.set_yaw(yaw);
     }
 
     pub fn add_yaw(&mut self, yaw_d: Rad<f32>) {
-        self.yaw += yaw_d;
+        self.controller.set_yaw(self.controller.yaw() + yaw_d);
     }
 
+    /// ピッチを変える。±[`reverie_util::math::fps_camera::MAX_PITCH_DEG`] 度に収める
     pub fn set_pitch(&mut self, pitch: Rad<f32>) {
-        self.pitch = pitch;
+        self.controller.set_pitch(pitch);
     }
 
     pub fn add_pitch(&mut self, pitch_d: Rad<f32>) {
-        self.pitch += pitch_d;
+        self.controller.set_pitch(self.controller.pitch() + pitch_d);
     }
 
     pub fn set_fov(&mut self, fov: Deg<f32>) {
-        self.fov = fov;
+        self.controller.fov = fov;
     }
 
     pub const fn pos(&self) -> Point3<f32> {
-        self.pos
+        self.controller.position
     }
 
     pub const fn yaw(&self) -> Rad<f32> {
-        self.yaw
+        self.controller.yaw()
     }
 
     pub const fn pitch(&self) -> Rad<f32> {
-        self.pitch
+        self.controller.pitch()
     }
 
     pub const fn fov(&self) -> Deg<f32> {
-        self.fov
+        self.controller.fov
     }
 }
@@ -0,0 +1,141 @@
+//! OpenGLの実装ごとの制限値
+
+use std::fmt;
+
+use crate::gl;
+use crate::gl::types::{GLenum, GLint};
+use crate::gl::Gl;
+use crate::vao::VertexType;
+
+/// コンテキストを作ったときに問い合わせた制限値
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GlCapabilities {
+    /// テクスチャの幅と高さの最大値 (`GL_MAX_TEXTURE_SIZE`)
+    pub max_texture_size: u32,
+    /// 頂点属性の数の最大値 (`GL_MAX_VERTEX_ATTRIBS`)
+    pub max_vertex_attribs: u32,
+    /// 全シェーダーで使えるテクスチャユニットの数 (`GL_MAX_COMBINED_TEXTURE_IMAGE_UNITS`)
+    pub max_combined_texture_image_units: u32,
+}
+
+impl Default for GlCapabilities {
+    /// OpenGL 3.3 Core Profile で保証されている最小値
+    fn default() -> Self {
+        Self {
+            max_texture_size: 1024,
+            max_vertex_attribs: 16,
+            max_combined_texture_image_units: 48,
+        }
+    }
+}
+
+impl GlCapabilities {
+    pub fn query(gl: &Gl) -> Self {
+        let get = |name: GLenum| {
+            let mut value: GLint = 0;
+            unsafe {
+                gl.GetIntegerv(name, &mut value);
+            }
+            value.max(0) as u32
+        };
+        Self {
+            max_texture_size: get(gl::MAX_TEXTURE_SIZE),
+            max_vertex_attribs: get(gl::MAX_VERTEX_ATTRIBS),
+            max_combined_texture_image_units: get(gl::MAX_COMBINED_TEXTURE_IMAGE_UNITS),
+        }
+    }
+
+    /// テクスチャの大きさが制限内か確かめる
+    pub const fn check_texture_size(&self, width: u32, height: u32) -> Result<(), LimitError> {
+        if width > self.max_texture_size || height > self.max_texture_size {
+            return Err(LimitError::TextureTooLarge {
+                width,
+                height,
+                max: self.max_texture_size,
+            });
+        }
+        Ok(())
+    }
+
+    /// 頂点の属性の数が制限内か確かめる
+    pub fn check_vertex_type<V: VertexType>(&self) -> Result<(), LimitError> {
+        let count = V::attribute_sizes().len() as u32;
+        if count > self.max_vertex_attribs {
+            return Err(LimitError::TooManyVertexAttributes {
+                count,
+                max: self.max_vertex_attribs,
+            });
+        }
+        Ok(())
+    }
+
+    /// 同時に使うテクスチャユニットの数が制限内か確かめる
+    pub const fn check_texture_units(&self, count: u32) -> Result<(), LimitError> {
+        if count > self.max_combined_texture_image_units {
+            return Err(LimitError::TooManyTextureUnits {
+                count,
+                max: self.max_combined_texture_image_units,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// [`GlCapabilities`]の制限を超えた
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LimitError {
+    TextureTooLarge { width: u32, height: u32, max: u32 },
+    TooManyVertexAttributes { count: u32, max: u32 },
+    TooManyTextureUnits { count: u32, max: u32 },
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TextureTooLarge { width, height, max } => write!(
+                f,
+                "texture size {width}x{height} exceeds GL_MAX_TEXTURE_SIZE ({max})"
+            ),
+            Self::TooManyVertexAttributes { count, max } => write!(
+                f,
+                "{count} vertex attributes exceed GL_MAX_VERTEX_ATTRIBS ({max})"
+            ),
+            Self::TooManyTextureUnits { count, max } => write!(
+                f,
+                "{count} texture units exceed GL_MAX_COMBINED_TEXTURE_IMAGE_UNITS ({max})"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LimitError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vao::VertexWithNormUv;
+
+    #[test]
+    fn checks_against_limits() {
+        let caps = GlCapabilities {
+            max_texture_size: 4096,
+            max_vertex_attribs: 2,
+            max_combined_texture_image_units: 4,
+        };
+        assert!(caps.check_texture_size(4096, 4096).is_ok());
+        assert_eq!(
+            caps.check_texture_size(8192, 8192),
+            Err(LimitError::TextureTooLarge {
+                width: 8192,
+                height: 8192,
+                max: 4096
+            })
+        );
+        assert_eq!(
+            caps.check_vertex_type::<VertexWithNormUv>(),
+            Err(LimitError::TooManyVertexAttributes { count: 3, max: 2 })
+        );
+        assert!(caps.check_texture_units(4).is_ok());
+        assert!(caps.check_texture_units(5).is_err());
+    }
+}
@@ -1,6 +1,6 @@
 use std::ffi::c_void;
 
-use crate::{gl::Gl, window::Window};
+use crate::{capabilities::GlCapabilities, gl::Gl, memory::GpuMemoryReport, window::Window};
 
 pub trait ContextBackend {
     fn new(window: &Window) -> Self;
@@ -71,19 +71,37 @@ impl ContextBackend for glutin::RawContext<glutin::PossiblyCurrent> {
 pub struct Context<C: ContextBackend> {
     backend: C,
     gl: Gl,
+    capabilities: GlCapabilities,
 }
 
 impl<C: ContextBackend> Context<C> {
     pub fn new(window: &Window) -> Self {
         let backend = C::new(window);
         let gl = Gl::load_with(|symbol| backend.get_proc_address(symbol) as *const _);
+        let capabilities = GlCapabilities::query(&gl);
 
-        Self { backend, gl }
+        Self {
+            backend,
+            gl,
+            capabilities,
+        }
     }
     pub fn gl(&self) -> Gl {
         Gl::clone(&self.gl)
     }
 
+    /// コンテキストを作ったときに問い合わせた制限値
+    pub const fn capabilities(&self) -> &GlCapabilities {
+        &self.capabilities
+    }
+
+    /// このコンテキストで作ったテクスチャやバッファの使用量の合計と、大きい順に`top_n`個のオブジェクト
+    ///
+    /// 毎フレーム呼んでオーバーレイに表示しても良い程度に軽い。
+    pub fn gpu_memory_report(&self, top_n: usize) -> GpuMemoryReport {
+        self.gl.memory().report(top_n)
+    }
+
     /// この[`Context`]を描画先として設定する
     pub fn make_current(&self) {
         self.backend.make_current();
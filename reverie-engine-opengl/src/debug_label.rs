@@ -0,0 +1,84 @@
+//! グラフィックスデバッガ (RenderDoc など) に表示されるオブジェクトの名前
+//!
+//! `GL_KHR_debug`が使えない環境ではどの関数も何もしない。
+
+use crate::gl;
+use crate::gl::types::{GLchar, GLenum, GLsizei, GLuint};
+use crate::gl::Gl;
+
+/// 名前を付けるオブジェクトの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectKind {
+    VertexArray,
+    Buffer,
+    Texture,
+    Program,
+    Shader,
+    Framebuffer,
+    Renderbuffer,
+    Sampler,
+}
+
+impl ObjectKind {
+    pub const fn to_gl(self) -> GLenum {
+        match self {
+            Self::VertexArray => gl::VERTEX_ARRAY,
+            Self::Buffer => gl::BUFFER,
+            Self::Texture => gl::TEXTURE,
+            Self::Program => gl::PROGRAM,
+            Self::Shader => gl::SHADER,
+            Self::Framebuffer => gl::FRAMEBUFFER,
+            Self::Renderbuffer => gl::RENDERBUFFER,
+            Self::Sampler => gl::SAMPLER,
+        }
+    }
+}
+
+/// オブジェクトに名前を付ける(glObjectLabel)
+///
+/// `GL_MAX_LABEL_LENGTH`より長い名前は切り詰められる。
+pub fn set(gl: &Gl, kind: ObjectKind, id: GLuint, label: &str) {
+    if !gl.supports_khr_debug() || id == 0 {
+        return;
+    }
+    let mut max_length = 0;
+    unsafe {
+        gl.GetIntegerv(gl::MAX_LABEL_LENGTH, &mut max_length);
+    }
+    // 終端の NUL の分を除く
+    let length = label.len().min((max_length - 1).max(0) as usize);
+    unsafe {
+        gl.ObjectLabel(
+            kind.to_gl(),
+            id,
+            length as GLsizei,
+            label.as_ptr() as *const GLchar,
+        );
+    }
+}
+
+/// オブジェクトに付けた名前を読み出す(glGetObjectLabel)
+///
+/// `GL_KHR_debug`が使えなければ`None`を返す。
+pub fn get(gl: &Gl, kind: ObjectKind, id: GLuint) -> Option<String> {
+    if !gl.supports_khr_debug() {
+        return None;
+    }
+    let mut max_length = 0;
+    unsafe {
+        gl.GetIntegerv(gl::MAX_LABEL_LENGTH, &mut max_length);
+    }
+    let mut buffer = vec![0u8; max_length.max(0) as usize + 1];
+    let mut length: GLsizei = 0;
+    unsafe {
+        gl.GetObjectLabel(
+            kind.to_gl(),
+            id,
+            buffer.len() as GLsizei,
+            &mut length,
+            buffer.as_mut_ptr() as *mut GLchar,
+        );
+    }
+    buffer.truncate(length.max(0) as usize);
+    Some(String::from_utf8_lossy(&buffer).into_owned())
+}
@@ -0,0 +1,326 @@
+//! フレームバッファと、画面外への描画
+
+use std::ptr;
+
+use crate::debug_label::{self, ObjectKind};
+use crate::gl;
+use crate::gl::types::{GLint, GLsizei, GLsync, GLuint};
+use crate::gl::Gl;
+use crate::memory::{GpuMemoryCategory, TrackedAllocation};
+
+/// OpenGLのFramebuffer Object
+///
+/// 色はRGBA8のテクスチャ、深度とステンシルはレンダーバッファに描画される。
+#[derive(Debug)]
+pub struct Framebuffer {
+    gl: Gl,
+    fbo: GLuint,
+    color_texture: GLuint,
+    depth_stencil: GLuint,
+    width: u32,
+    height: u32,
+    memory: TrackedAllocation,
+}
+
+impl Framebuffer {
+    /// # Returns
+    ///
+    /// `Ok`のときは`Framebuffer`、`Err`のときはエラーメッセージ
+    pub fn new(gl: Gl, width: u32, height: u32) -> Result<Self, String> {
+        let mut fbo = 0;
+        let mut color_texture = 0;
+        let mut depth_stencil = 0;
+        let status = unsafe {
+            gl.GenFramebuffers(1, &mut fbo);
+            gl.BindFramebuffer(gl::FRAMEBUFFER, fbo);
+
+            gl.GenTextures(1, &mut color_texture);
+            gl.BindTexture(gl::TEXTURE_2D, color_texture);
+            gl.TexImage2D(
+                gl::TEXTURE_2D,
+                0,
+                gl::RGBA8 as GLint,
+                width as GLsizei,
+                height as GLsizei,
+                0,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                ptr::null(),
+            );
+            gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+            gl.TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+            gl.BindTexture(gl::TEXTURE_2D, 0);
+            gl.FramebufferTexture2D(
+                gl::FRAMEBUFFER,
+                gl::COLOR_ATTACHMENT0,
+                gl::TEXTURE_2D,
+                color_texture,
+                0,
+            );
+
+            gl.GenRenderbuffers(1, &mut depth_stencil);
+            gl.BindRenderbuffer(gl::RENDERBUFFER, depth_stencil);
+            gl.RenderbufferStorage(
+                gl::RENDERBUFFER,
+                gl::DEPTH24_STENCIL8,
+                width as GLsizei,
+                height as GLsizei,
+            );
+            gl.BindRenderbuffer(gl::RENDERBUFFER, 0);
+            gl.FramebufferRenderbuffer(
+                gl::FRAMEBUFFER,
+                gl::DEPTH_STENCIL_ATTACHMENT,
+                gl::RENDERBUFFER,
+                depth_stencil,
+            );
+
+            let status = gl.CheckFramebufferStatus(gl::FRAMEBUFFER);
+            gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
+            status
+        };
+
+        // RGBA8 と DEPTH24_STENCIL8 はどちらも 1 画素 4 バイト
+        let bytes = u64::from(width) * u64::from(height) * 4 * 2;
+        let memory =
+            gl.memory()
+                .track(GpuMemoryCategory::Framebuffer, "Unnamed Framebuffer", bytes);
+        let framebuffer = Self {
+            gl,
+            fbo,
+            color_texture,
+            depth_stencil,
+            width,
+            height,
+            memory,
+        };
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            return Err(format!("framebuffer is incomplete: 0x{status:X}"));
+        }
+        Ok(framebuffer)
+    }
+
+    pub const fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub const fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// グラフィックスデバッガに表示される名前を付ける
+    ///
+    /// 色のテクスチャには`"{name} [color]"`、深度とステンシルには`"{name} [depth_stencil]"`が付く。
+    pub fn set_label(&self, name: &str) {
+        self.memory.set_label(name);
+        debug_label::set(&self.gl, ObjectKind::Framebuffer, self.fbo, name);
+        debug_label::set(
+            &self.gl,
+            ObjectKind::Texture,
+            self.color_texture,
+            &format!("{name} [color]"),
+        );
+        debug_label::set(
+            &self.gl,
+            ObjectKind::Renderbuffer,
+            self.depth_stencil,
+            &format!("{name} [depth_stencil]"),
+        );
+    }
+
+    /// このフレームバッファを描画先にする(glBindFramebuffer)
+    pub fn bind(&self) {
+        unsafe {
+            self.gl.BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+        }
+    }
+
+    /// 描画先をデフォルトのフレームバッファ(ウィンドウ)に戻す
+    pub fn unbind(&self) {
+        unsafe {
+            self.gl.BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+    }
+
+    /// OpenGLの関数に渡すためのフレームバッファID
+    ///
+    /// # Safety
+    /// この[`Framebuffer`]がdropされていない限り安全
+    pub const unsafe fn raw_id(&self) -> GLuint {
+        self.fbo
+    }
+
+    /// 色が描画されるテクスチャのID
+    ///
+    /// # Safety
+    /// この[`Framebuffer`]がdropされていない限り安全
+    pub const unsafe fn raw_color_texture_id(&self) -> GLuint {
+        self.color_texture
+    }
+}
+
+impl Drop for Framebuffer {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.DeleteFramebuffers(1, &self.fbo);
+            self.gl.DeleteTextures(1, &self.color_texture);
+            self.gl.DeleteRenderbuffers(1, &self.depth_stencil);
+        }
+    }
+}
+
+/// 画面外の描画先
+///
+/// スクリーンショットの比較や学習データの生成に使う。
+#[derive(Debug)]
+pub struct OffscreenTarget {
+    framebuffer: Framebuffer,
+}
+
+impl OffscreenTarget {
+    pub fn new(gl: Gl, width: u32, height: u32) -> Result<Self, String> {
+        Ok(Self {
+            framebuffer: Framebuffer::new(gl, width, height)?,
+        })
+    }
+
+    pub const fn framebuffer(&self) -> &Framebuffer {
+        &self.framebuffer
+    }
+
+    /// フレームバッファを描画先にして`draw`を呼ぶ
+    ///
+    /// ビューポートはフレームバッファの大きさに設定され、終わったら元に戻る。
+    pub fn render(&self, draw: impl FnOnce()) {
+        let gl = &self.framebuffer.gl;
+        let mut viewport = [0; 4];
+        unsafe {
+            gl.GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+        }
+        self.framebuffer.bind();
+        unsafe {
+            gl.Viewport(
+                0,
+                0,
+                self.framebuffer.width as GLsizei,
+                self.framebuffer.height as GLsizei,
+            );
+        }
+        draw();
+        self.framebuffer.unbind();
+        unsafe {
+            gl.Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+        }
+    }
+
+    /// 画素の読み出しを始める
+    ///
+    /// Pixel Buffer Objectへのコピーを命令するだけで、GPUの完了を待たない。
+    pub fn request_pixels(&self) -> PixelReadRequest {
+        let gl = Gl::clone(&self.framebuffer.gl);
+        let (width, height) = (self.framebuffer.width, self.framebuffer.height);
+        let size = (width * height * 4) as usize;
+        let mut pbo = 0;
+        let fence = unsafe {
+            gl.GenBuffers(1, &mut pbo);
+            gl.BindBuffer(gl::PIXEL_PACK_BUFFER, pbo);
+            gl.BufferData(
+                gl::PIXEL_PACK_BUFFER,
+                size as _,
+                ptr::null(),
+                gl::STREAM_READ,
+            );
+            gl.BindFramebuffer(gl::READ_FRAMEBUFFER, self.framebuffer.fbo);
+            gl.PixelStorei(gl::PACK_ALIGNMENT, 4);
+            // PBOがバインドされているので最後の引数はバッファ内のオフセットになる
+            gl.ReadPixels(
+                0,
+                0,
+                width as GLsizei,
+                height as GLsizei,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                ptr::null_mut(),
+            );
+            gl.BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+            gl.BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+            let fence = gl.FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+            // フェンスがGPUに届かないと try_get がいつまでも終わらない
+            gl.Flush();
+            fence
+        };
+        PixelReadRequest {
+            gl,
+            pbo,
+            fence,
+            width,
+            height,
+        }
+    }
+}
+
+/// [`OffscreenTarget::request_pixels`]で始めた読み出し
+#[derive(Debug)]
+pub struct PixelReadRequest {
+    gl: Gl,
+    pbo: GLuint,
+    fence: GLsync,
+    width: u32,
+    height: u32,
+}
+
+impl PixelReadRequest {
+    /// 読み出しが終わっていれば、上の行から順に並んだRGBA8の画素を返す
+    ///
+    /// GPUの完了を待たずにすぐ戻る。終わっていなければ`None`を返す。
+    pub fn try_get(&self) -> Option<Vec<u8>> {
+        let status = unsafe { self.gl.ClientWaitSync(self.fence, 0, 0) };
+        if status != gl::ALREADY_SIGNALED && status != gl::CONDITION_SATISFIED {
+            return None;
+        }
+        self.copy_pixels()
+    }
+
+    /// 読み出しが終わるまで待って画素を返す
+    pub fn wait(&self) -> Option<Vec<u8>> {
+        let status = unsafe {
+            self.gl
+                .ClientWaitSync(self.fence, gl::SYNC_FLUSH_COMMANDS_BIT, gl::TIMEOUT_IGNORED)
+        };
+        if status == gl::WAIT_FAILED {
+            return None;
+        }
+        self.copy_pixels()
+    }
+
+    fn copy_pixels(&self) -> Option<Vec<u8>> {
+        let row = (self.width * 4) as usize;
+        let size = row * self.height as usize;
+        let mut pixels = Vec::with_capacity(size);
+        unsafe {
+            self.gl.BindBuffer(gl::PIXEL_PACK_BUFFER, self.pbo);
+            let mapped =
+                self.gl
+                    .MapBufferRange(gl::PIXEL_PACK_BUFFER, 0, size as _, gl::MAP_READ_BIT)
+                    as *const u8;
+            if !mapped.is_null() {
+                let data = std::slice::from_raw_parts(mapped, size);
+                // OpenGLは下の行から並んでいるので上下を反転する
+                for y in (0..self.height as usize).rev() {
+                    pixels.extend_from_slice(&data[y * row..(y + 1) * row]);
+                }
+                self.gl.UnmapBuffer(gl::PIXEL_PACK_BUFFER);
+            }
+            self.gl.BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+        }
+        (pixels.len() == size).then_some(pixels)
+    }
+}
+
+impl Drop for PixelReadRequest {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.DeleteSync(self.fence);
+            self.gl.DeleteBuffers(1, &self.pbo);
+        }
+    }
+}
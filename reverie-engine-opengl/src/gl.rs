@@ -2,7 +2,7 @@
 //!
 //! OpenGL 3.3 Core Profile
 //!
-//! 機能拡張は無し
+//! 機能拡張: GL_KHR_debug (グラフィックスデバッガのためにオブジェクトに名前を付ける)
 
 #[allow(clippy::all)]
 #[allow(clippy::nursery)]
@@ -12,12 +12,16 @@ mod bindings {
 
 pub use bindings::*;
 
+use crate::memory::GpuMemoryTracker;
+
 use std::fmt::Debug;
 use std::rc::Rc;
 #[derive(Clone)]
 /// 実体は[`std::rc::Rc`]なのでいくらでもクローンして良い
 pub struct Gl {
     inner: Rc<bindings::Gl>,
+    khr_debug: bool,
+    memory: GpuMemoryTracker,
 }
 
 impl Gl {
@@ -25,10 +29,42 @@ impl Gl {
     where
         F: FnMut(&'static str) -> *const types::GLvoid,
     {
+        let inner = bindings::Gl::load_with(loadfn);
+        let khr_debug = inner.ObjectLabel.is_loaded() && has_extension(&inner, "GL_KHR_debug");
         Self {
-            inner: Rc::new(bindings::Gl::load_with(loadfn)),
+            inner: Rc::new(inner),
+            khr_debug,
+            memory: GpuMemoryTracker::default(),
         }
     }
+
+    /// `GL_KHR_debug`が使えるか
+    ///
+    /// 使えなければ[`crate::debug_label`]の関数は何もしない。
+    pub const fn supports_khr_debug(&self) -> bool {
+        self.khr_debug
+    }
+
+    /// このコンテキストで作ったテクスチャやバッファの大きさの集計
+    pub const fn memory(&self) -> &GpuMemoryTracker {
+        &self.memory
+    }
+}
+
+fn has_extension(gl: &bindings::Gl, name: &str) -> bool {
+    if !gl.GetStringi.is_loaded() {
+        return false;
+    }
+    let mut count = 0;
+    unsafe {
+        gl.GetIntegerv(NUM_EXTENSIONS, &mut count);
+    }
+    (0..count.max(0) as types::GLuint).any(|i| {
+        let ptr = unsafe { gl.GetStringi(EXTENSIONS, i) };
+        !ptr.is_null()
+            && unsafe { std::ffi::CStr::from_ptr(ptr as *const std::ffi::c_char) }.to_bytes()
+                == name.as_bytes()
+    })
 }
 
 impl Debug for Gl {
@@ -1,15 +1,20 @@
 pub mod camera;
+pub mod capabilities;
 mod context;
+pub mod debug_label;
 mod engine;
+pub mod framebuffer;
 pub mod gl;
 pub mod gui;
 pub mod math;
+pub mod memory;
 pub mod platform;
 pub mod shader;
 pub mod texture;
 pub mod types;
 pub mod vao;
 pub mod window;
+pub use capabilities::{GlCapabilities, LimitError};
 pub use context::{Context, ContextBackend};
 pub use engine::ReverieEngine;
 pub use gui::VaoBuilder2DGui;
@@ -0,0 +1,30 @@
+//! GPU のメモリ使用量の集計
+//!
+//! OpenGL のオブジェクトを作るときに大きさを登録し、[`TrackedAllocation`]が drop されたら差し引く。
+//! 集計は[`Gl`](crate::gl::Gl)が持っていて、[`Gl::memory`](crate::gl::Gl::memory)で取り出せる。
+//! 集計そのものは wgpu のバックエンドと共通の[`reverie_util::memory`]が行い、ここでは分類を決める。
+use reverie_util::memory::{AllocationInfo, MemoryCategory, MemoryReport, MemoryTracker};
+
+/// 集計の分類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GpuMemoryCategory {
+    /// [`crate::texture::ImageManager`]のテクスチャ
+    Texture,
+    /// [`crate::vao::Vao`]の頂点バッファ
+    VertexBuffer,
+    /// [`crate::framebuffer::Framebuffer`]の色と深度・ステンシル
+    Framebuffer,
+}
+
+impl MemoryCategory for GpuMemoryCategory {
+    const ALL: &'static [Self] = &[Self::Texture, Self::VertexBuffer, Self::Framebuffer];
+}
+
+/// OpenGL のオブジェクトの大きさを集計する
+pub type GpuMemoryTracker = MemoryTracker<GpuMemoryCategory>;
+/// [`GpuMemoryTracker::track`]で登録したオブジェクト
+pub type TrackedAllocation = reverie_util::memory::TrackedAllocation<GpuMemoryCategory>;
+/// 1 つのオブジェクトの大きさ
+pub type GpuAllocationInfo = AllocationInfo<GpuMemoryCategory>;
+/// [`GpuMemoryTracker::report`]の結果
+pub type GpuMemoryReport = MemoryReport<GpuMemoryCategory>;
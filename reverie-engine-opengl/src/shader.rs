@@ -1,5 +1,6 @@
 //! シェーダプログラム
 
+use crate::debug_label::{self, ObjectKind};
 use crate::gl;
 use crate::gl::types::*;
 use crate::gl::Gl;
@@ -28,7 +29,16 @@ impl Program {
             Gl::clone(&gl),
             &CString::new(include_str!("../resources/uv.frag")).unwrap(),
         )?;
-        Self::from_shaders(gl, &[vert, frag])
+        let program = Self::from_shaders(gl, &[vert, frag])?;
+        program.set_label("default_uv");
+        Ok(program)
+    }
+
+    /// [`Self::from_shaders`]と同じだが、グラフィックスデバッガに表示される名前を付ける
+    pub fn from_shaders_named(gl: Gl, shaders: &[Shader], name: &str) -> Result<Self, String> {
+        let program = Self::from_shaders(gl, shaders)?;
+        program.set_label(name);
+        Ok(program)
     }
 
     /// 頂点シェーダーとフラグメントシェーダーをリンクしてプログラムを作る
@@ -92,6 +102,11 @@ impl Program {
         self.id
     }
 
+    /// グラフィックスデバッガに表示される名前を付ける
+    pub fn set_label(&self, name: &str) {
+        debug_label::set(&self.gl, ObjectKind::Program, self.id, name);
+    }
+
     /// このプログラムをOpenGLで使うように設定する(glUseProgram)
     pub fn set_used(&self) {
         unsafe {
@@ -238,6 +253,8 @@ impl Shader {
 
     /// ファイルからシェーダーのコードを読み込み、コンパイルする
     ///
+    /// シェーダーにはファイルのパスが名前として付く。
+    ///
     /// # Panics
     ///
     /// ファイルが開けなかった時にパニックする
@@ -250,7 +267,9 @@ impl Shader {
 
         let code = CString::new(code.as_bytes()).unwrap();
 
-        Self::from_code(gl, &code, kind)
+        let shader = Self::from_code(gl, &code, kind)?;
+        shader.set_label(path);
+        Ok(shader)
     }
 
     /// 頂点シェーダーをファイルから作る
@@ -273,6 +292,11 @@ impl Shader {
         Self::from_code(gl, code, gl::FRAGMENT_SHADER)
     }
 
+    /// グラフィックスデバッガに表示される名前を付ける
+    pub fn set_label(&self, name: &str) {
+        debug_label::set(&self.gl, ObjectKind::Shader, self.id, name);
+    }
+
     /// OpenGLの関数に渡すためのシェーダーID
     ///
     /// # Safety
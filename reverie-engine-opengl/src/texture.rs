@@ -1,9 +1,17 @@
 //! テクスチャに関するモジュール
 
+mod atlas_builder;
 mod image_manager;
+mod sampler;
 mod texture_atlas;
+mod upload;
 
 pub use {
-    image_manager::{ImageLoadInfo, ImageManager},
+    atlas_builder::{AtlasUV, TextureAtlas, TextureAtlasBuilder},
+    image_manager::{ImageLoadInfo, ImageManager, TextureError},
+    sampler::{
+        bind_textures, unbind_textures, Filter, Sampler, SamplerConfig, TextureBinding, Wrap,
+    },
     texture_atlas::{TextureAtlasPos, TextureUV},
+    upload::{unpack_alignment, upload_region, PixelFormat, PixelRegion},
 };
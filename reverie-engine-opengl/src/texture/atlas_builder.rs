@@ -0,0 +1,194 @@
+//! 複数の画像を詰め込んでテクスチャアトラスを作る
+
+use std::collections::HashMap;
+
+use etagere::{size2, AtlasAllocator};
+use image::{DynamicImage, RgbaImage};
+
+use crate::capabilities::{GlCapabilities, LimitError};
+use crate::gui::Rect;
+use crate::types::Dynamic;
+
+use super::{ImageManager, TextureError, TextureUV};
+
+/// [`TextureAtlas`]の中での位置
+pub type AtlasUV = TextureUV<Dynamic, Dynamic, Dynamic, Dynamic>;
+
+struct Page {
+    image: RgbaImage,
+    allocator: AtlasAllocator,
+}
+
+impl std::fmt::Debug for Page {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Page")
+            .field("size", &self.image.dimensions())
+            .finish_non_exhaustive()
+    }
+}
+
+/// 画像を詰め込んでテクスチャアトラスを作る
+///
+/// 1 ページに収まらなくなったら新しいページ (テクスチャ) を追加する。
+/// ページの大きさは`GL_MAX_TEXTURE_SIZE`を超えないように縮められる。
+#[derive(Debug)]
+pub struct TextureAtlasBuilder {
+    page_size: u32,
+    pages: Vec<Page>,
+    entries: HashMap<String, (usize, Rect<i32, u32>)>,
+}
+
+impl TextureAtlasBuilder {
+    pub fn new(page_size: u32, capabilities: &GlCapabilities) -> Self {
+        Self {
+            page_size: page_size.min(capabilities.max_texture_size),
+            pages: Vec::new(),
+            entries: HashMap::new(),
+        }
+    }
+
+    /// 1 ページの幅と高さ
+    pub const fn page_size(&self) -> u32 {
+        self.page_size
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// 画像を追加する
+    ///
+    /// 画像が 1 ページより大きければエラーを返す。
+    pub fn add(&mut self, id: impl Into<String>, image: &RgbaImage) -> Result<(), LimitError> {
+        let (width, height) = image.dimensions();
+        if width > self.page_size || height > self.page_size {
+            return Err(LimitError::TextureTooLarge {
+                width,
+                height,
+                max: self.page_size,
+            });
+        }
+        let size = size2(width as i32, height as i32);
+        let allocated = self
+            .pages
+            .iter_mut()
+            .enumerate()
+            .find_map(|(i, page)| page.allocator.allocate(size).map(|a| (i, a)));
+        let (page_index, allocation) = match allocated {
+            Some(allocated) => allocated,
+            None => {
+                let mut page = Page {
+                    image: RgbaImage::new(self.page_size, self.page_size),
+                    allocator: AtlasAllocator::new(size2(
+                        self.page_size as i32,
+                        self.page_size as i32,
+                    )),
+                };
+                let allocation = page
+                    .allocator
+                    .allocate(size)
+                    .expect("an image no larger than the page fits in an empty page");
+                self.pages.push(page);
+                (self.pages.len() - 1, allocation)
+            }
+        };
+
+        let min = allocation.rectangle.min;
+        image::imageops::replace(
+            &mut self.pages[page_index].image,
+            image,
+            min.x.into(),
+            min.y.into(),
+        );
+        self.entries.insert(
+            id.into(),
+            (page_index, Rect::new(min.x, min.y, width, height)),
+        );
+        Ok(())
+    }
+
+    /// 各ページをテクスチャとして読み込む
+    ///
+    /// ページは`"{id_prefix}/{ページ番号}"`という ID で`manager`に登録される。
+    pub fn build(
+        self,
+        manager: &mut ImageManager,
+        id_prefix: &str,
+    ) -> Result<TextureAtlas, TextureError> {
+        let mut pages = Vec::with_capacity(self.pages.len());
+        for (i, page) in self.pages.into_iter().enumerate() {
+            let id = format!("{id_prefix}/{i}");
+            let info = manager.load_image(DynamicImage::ImageRgba8(page.image), &id, false)?;
+            pages.push(unsafe { info.raw_gl_id() });
+        }
+        Ok(TextureAtlas {
+            page_size: self.page_size,
+            pages,
+            entries: self.entries,
+        })
+    }
+}
+
+/// [`TextureAtlasBuilder`]で作ったテクスチャアトラス
+#[derive(Debug)]
+pub struct TextureAtlas {
+    page_size: u32,
+    pages: Vec<u32>,
+    entries: HashMap<String, (usize, Rect<i32, u32>)>,
+}
+
+impl TextureAtlas {
+    /// 画像が何ページ目のどこにあるか
+    pub fn get(&self, id: &str) -> Option<(usize, AtlasUV)> {
+        self.entries
+            .get(id)
+            .map(|(page, rect)| (*page, AtlasUV::new(rect, self.page_size, self.page_size)))
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// ページのテクスチャID
+    ///
+    /// # Safety
+    /// テクスチャが削除されていない限り安全
+    pub unsafe fn raw_page_id(&self, page: usize) -> Option<u32> {
+        self.pages.get(page).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capabilities(max_texture_size: u32) -> GlCapabilities {
+        GlCapabilities {
+            max_texture_size,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn page_size_is_clamped_to_limit() {
+        let builder = TextureAtlasBuilder::new(8192, &capabilities(4096));
+        assert_eq!(builder.page_size(), 4096);
+    }
+
+    #[test]
+    fn splits_into_pages_when_full() {
+        let mut builder = TextureAtlasBuilder::new(8192, &capabilities(64));
+        for i in 0..3 {
+            builder
+                .add(format!("{i}"), &RgbaImage::new(40, 40))
+                .unwrap();
+        }
+        assert_eq!(builder.page_count(), 3);
+        builder.add("small", &RgbaImage::new(16, 16)).unwrap();
+        assert_eq!(builder.page_count(), 3);
+        assert!(matches!(
+            builder.add("huge", &RgbaImage::new(100, 100)),
+            Err(LimitError::TextureTooLarge { max: 64, .. })
+        ));
+    }
+}
@@ -1,41 +1,64 @@
 //! 画像ファイルを読み込み、管理する
 
 use std::collections::HashMap;
+use std::fmt;
 use std::os::raw::c_void;
 use std::path::Path;
 
 use image::{DynamicImage, ImageError};
 
+use crate::capabilities::{GlCapabilities, LimitError};
+use crate::debug_label::{self, ObjectKind};
 use crate::gl;
 use crate::gl::Gl;
+use crate::memory::{GpuMemoryCategory, TrackedAllocation};
+
+use super::upload::{
+    reset_unpack_state, unpack_alignment, upload_region, PixelFormat, PixelRegion,
+};
 
 /// 画像ファイルを読み込み、管理する
 #[derive(Debug)]
 pub struct ImageManager {
     gl: Gl,
+    capabilities: GlCapabilities,
     image_map: HashMap<String, u32>,
+    memory: HashMap<String, TrackedAllocation>,
 }
 
 impl ImageManager {
     pub(crate) fn new(gl: Gl) -> Self {
+        let capabilities = GlCapabilities::query(&gl);
         Self {
             gl,
+            capabilities,
             image_map: HashMap::new(),
+            memory: HashMap::new(),
         }
     }
 
+    /// テクスチャを作るときに確かめる制限値
+    pub const fn capabilities(&self) -> &GlCapabilities {
+        &self.capabilities
+    }
+
+    /// 画像をOpenGLにテクスチャとして読み込ませる
+    ///
+    /// テクスチャにはグラフィックスデバッガのための名前として`id`が付く。
     pub fn load_image<'a>(
         &mut self,
         image: DynamicImage,
         id: &'a str,
         vflip: bool,
-    ) -> Result<ImageLoadInfo<'a>, ImageError> {
+    ) -> Result<ImageLoadInfo<'a>, TextureError> {
+        self.capabilities
+            .check_texture_size(image.width(), image.height())?;
         let mut image = image;
-        let format = match image {
-            DynamicImage::ImageLuma8(_) => gl::RED,
-            DynamicImage::ImageLumaA8(_) => gl::RG,
-            DynamicImage::ImageRgb8(_) => gl::RGB,
-            DynamicImage::ImageRgba8(_) => gl::RGBA,
+        let pixel_format = match image {
+            DynamicImage::ImageLuma8(_) => PixelFormat::R,
+            DynamicImage::ImageLumaA8(_) => PixelFormat::Rg,
+            DynamicImage::ImageRgb8(_) => PixelFormat::Rgb,
+            DynamicImage::ImageRgba8(_) => PixelFormat::Rgba,
             DynamicImage::ImageLuma16(_) => todo!(),
             DynamicImage::ImageLumaA16(_) => todo!(),
             DynamicImage::ImageRgb16(_) => todo!(),
@@ -49,6 +72,8 @@ impl ImageManager {
         }
 
         let data = image.as_bytes();
+        let format = pixel_format.to_gl();
+        let row_bytes = image.width() as usize * pixel_format.channels();
 
         let mut texture = 0;
 
@@ -63,6 +88,10 @@ impl ImageManager {
                 .TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
             self.gl
                 .TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+            // 行のバイト数が 4 の倍数でない画像 (例えば幅が奇数の RGB) のために詰めて読ませる
+            reset_unpack_state(&self.gl);
+            self.gl
+                .PixelStorei(gl::UNPACK_ALIGNMENT, unpack_alignment(row_bytes));
             self.gl.TexImage2D(
                 gl::TEXTURE_2D,
                 0,
@@ -74,9 +103,19 @@ impl ImageManager {
                 gl::UNSIGNED_BYTE,
                 &data[0] as *const u8 as *const c_void,
             );
+            reset_unpack_state(&self.gl);
             self.gl.GenerateMipmap(gl::TEXTURE_2D);
             self.gl.BindTexture(gl::TEXTURE_2D, 0);
         }
+        debug_label::set(&self.gl, ObjectKind::Texture, texture, id);
+        // ミップマップの分として 1/3 を足す
+        let bytes =
+            u64::from(image.width()) * u64::from(image.height()) * pixel_format.channels() as u64;
+        let allocation = self
+            .gl
+            .memory()
+            .track(GpuMemoryCategory::Texture, id, bytes + bytes / 3);
+        self.memory.insert(id.to_string(), allocation);
 
         self.image_map.insert(id.to_string(), texture);
 
@@ -96,22 +135,50 @@ impl ImageManager {
         bytes: &[u8],
         id: &'a str,
         vflip: bool,
-    ) -> Result<ImageLoadInfo<'a>, ImageError> {
+    ) -> Result<ImageLoadInfo<'a>, TextureError> {
         let image = image::load_from_memory(bytes)?;
         self.load_image(image, id, vflip)
     }
 
     /// ファイルから画像を読み込み、OpenGLにテクスチャとして読み込ませる
     ///
-    /// 管理用のIDとして文字列を渡す必要がある
+    /// 管理用のIDとして文字列を渡す必要がある。
+    /// テクスチャにはグラフィックスデバッガのための名前としてファイルのパスが付く。
     pub fn load_from_file<'a>(
         &mut self,
         path: &Path,
         id: &'a str,
         vflip: bool,
-    ) -> Result<ImageLoadInfo<'a>, ImageError> {
+    ) -> Result<ImageLoadInfo<'a>, TextureError> {
         let image = image::open(path)?;
-        self.load_image(image, id, vflip)
+        let info = self.load_image(image, id, vflip)?;
+        if let Some(allocation) = self.memory.get(id) {
+            allocation.set_label(path.to_string_lossy());
+        }
+        debug_label::set(
+            &self.gl,
+            ObjectKind::Texture,
+            info.gl_id,
+            &path.to_string_lossy(),
+        );
+        Ok(info)
+    }
+
+    /// 読み込み済みのテクスチャの一部を書き換える
+    ///
+    /// `region`は大きなバッファの一部でも良い。
+    pub fn upload_region(
+        &self,
+        id: &str,
+        dst_x: u32,
+        dst_y: u32,
+        region: &PixelRegion<'_>,
+    ) -> Result<(), String> {
+        let texture = *self
+            .image_map
+            .get(id)
+            .ok_or_else(|| format!("texture `{id}` is not loaded"))?;
+        upload_region(&self.gl, texture, dst_x, dst_y, region)
     }
 
     /// OpenGLの関数に渡すためのテクスチャIDを得る
@@ -120,6 +187,45 @@ impl ImageManager {
     }
 }
 
+/// テクスチャを作れなかった
+#[derive(Debug)]
+pub enum TextureError {
+    /// 画像を読み込めなかった
+    Image(ImageError),
+    /// [`GlCapabilities`]の制限を超えた
+    Limit(LimitError),
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Image(e) => write!(f, "failed to load image: {e}"),
+            Self::Limit(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for TextureError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Image(e) => Some(e),
+            Self::Limit(e) => Some(e),
+        }
+    }
+}
+
+impl From<ImageError> for TextureError {
+    fn from(e: ImageError) -> Self {
+        Self::Image(e)
+    }
+}
+
+impl From<LimitError> for TextureError {
+    fn from(e: LimitError) -> Self {
+        Self::Limit(e)
+    }
+}
+
 /// 画像読み込みの結果
 #[derive(Debug)]
 pub struct ImageLoadInfo<'a> {
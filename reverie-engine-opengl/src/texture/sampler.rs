@@ -0,0 +1,211 @@
+//! サンプラーオブジェクト
+
+use crate::debug_label::{self, ObjectKind};
+use crate::gl;
+use crate::gl::types::{GLenum, GLint, GLuint};
+use crate::gl::Gl;
+
+/// EXT_texture_filter_anisotropic (OpenGL 4.6 でコアになった) の定数
+const TEXTURE_MAX_ANISOTROPY: GLenum = 0x84FE;
+
+/// テクスチャの拡大・縮小時の補間方法
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    Nearest,
+    Linear,
+}
+
+/// テクスチャ座標が 0.0 から 1.0 の範囲外のときの扱い
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wrap {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+}
+
+impl Wrap {
+    const fn to_gl(self) -> GLenum {
+        match self {
+            Self::Repeat => gl::REPEAT,
+            Self::MirroredRepeat => gl::MIRRORED_REPEAT,
+            Self::ClampToEdge => gl::CLAMP_TO_EDGE,
+        }
+    }
+}
+
+/// [`Sampler`]の設定
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SamplerConfig {
+    pub min_filter: Filter,
+    pub mag_filter: Filter,
+    /// ミップマップ間の補間方法。`None`ならミップマップを使わない
+    pub mipmap_filter: Option<Filter>,
+    pub wrap: Wrap,
+    /// 異方性フィルタリングの最大値。`None`なら使わない
+    ///
+    /// ドライバが EXT_texture_filter_anisotropic に対応していなければ無視される。
+    pub anisotropy: Option<f32>,
+}
+
+impl Default for SamplerConfig {
+    fn default() -> Self {
+        Self::linear_mipmap()
+    }
+}
+
+impl SamplerConfig {
+    /// ミップマップを含めてすべて線形補間する
+    pub const fn linear_mipmap() -> Self {
+        Self {
+            min_filter: Filter::Linear,
+            mag_filter: Filter::Linear,
+            mipmap_filter: Some(Filter::Linear),
+            wrap: Wrap::Repeat,
+            anisotropy: None,
+        }
+    }
+
+    /// 補間せずに最も近いテクセルを使う。ドット絵やライトマップのチャンネル向け
+    pub const fn nearest() -> Self {
+        Self {
+            min_filter: Filter::Nearest,
+            mag_filter: Filter::Nearest,
+            mipmap_filter: None,
+            wrap: Wrap::Repeat,
+            anisotropy: None,
+        }
+    }
+
+    const fn gl_min_filter(&self) -> GLenum {
+        match (self.min_filter, self.mipmap_filter) {
+            (Filter::Nearest, None) => gl::NEAREST,
+            (Filter::Linear, None) => gl::LINEAR,
+            (Filter::Nearest, Some(Filter::Nearest)) => gl::NEAREST_MIPMAP_NEAREST,
+            (Filter::Nearest, Some(Filter::Linear)) => gl::NEAREST_MIPMAP_LINEAR,
+            (Filter::Linear, Some(Filter::Nearest)) => gl::LINEAR_MIPMAP_NEAREST,
+            (Filter::Linear, Some(Filter::Linear)) => gl::LINEAR_MIPMAP_LINEAR,
+        }
+    }
+
+    const fn gl_mag_filter(&self) -> GLenum {
+        match self.mag_filter {
+            Filter::Nearest => gl::NEAREST,
+            Filter::Linear => gl::LINEAR,
+        }
+    }
+}
+
+/// OpenGLのサンプラーオブジェクト(glGenSamplers)
+///
+/// テクスチャ自身のパラメータの代わりに使われるので、
+/// 同じテクスチャを別々のテクスチャユニットで違う補間方法でサンプリングできる。
+#[derive(Debug)]
+pub struct Sampler {
+    gl: Gl,
+    id: GLuint,
+    config: SamplerConfig,
+}
+
+impl Sampler {
+    pub fn new(gl: Gl, config: SamplerConfig) -> Self {
+        let mut id = 0;
+        unsafe {
+            gl.GenSamplers(1, &mut id);
+            gl.SamplerParameteri(id, gl::TEXTURE_MIN_FILTER, config.gl_min_filter() as GLint);
+            gl.SamplerParameteri(id, gl::TEXTURE_MAG_FILTER, config.gl_mag_filter() as GLint);
+            let wrap = config.wrap.to_gl() as GLint;
+            gl.SamplerParameteri(id, gl::TEXTURE_WRAP_S, wrap);
+            gl.SamplerParameteri(id, gl::TEXTURE_WRAP_T, wrap);
+            gl.SamplerParameteri(id, gl::TEXTURE_WRAP_R, wrap);
+            if let Some(anisotropy) = config.anisotropy {
+                gl.SamplerParameterf(id, TEXTURE_MAX_ANISOTROPY, anisotropy);
+                // 拡張が無い環境では INVALID_ENUM になるので捨てる
+                gl.GetError();
+            }
+        }
+        Self { gl, id, config }
+    }
+
+    pub const fn config(&self) -> &SamplerConfig {
+        &self.config
+    }
+
+    /// グラフィックスデバッガに表示される名前を付ける
+    pub fn set_label(&self, name: &str) {
+        debug_label::set(&self.gl, ObjectKind::Sampler, self.id, name);
+    }
+
+    /// OpenGLの関数に渡すためのサンプラーID
+    ///
+    /// # Safety
+    /// この[`Sampler`]がdropされていない限り安全
+    pub const unsafe fn raw_id(&self) -> GLuint {
+        self.id
+    }
+}
+
+impl Drop for Sampler {
+    fn drop(&mut self) {
+        unsafe {
+            self.gl.DeleteSamplers(1, &self.id);
+        }
+    }
+}
+
+/// テクスチャユニットに割り当てるテクスチャとサンプラーの組
+#[derive(Debug, Clone, Copy)]
+pub struct TextureBinding<'a> {
+    /// OpenGLのテクスチャID
+    pub texture: GLuint,
+    /// `None`ならテクスチャ自身のパラメータでサンプリングする
+    pub sampler: Option<&'a Sampler>,
+}
+
+/// `bindings[i]`をテクスチャユニット`i`に割り当てる
+///
+/// 終わったらアクティブなユニットは 0 に戻る。
+pub fn bind_textures(gl: &Gl, bindings: &[TextureBinding<'_>]) {
+    for (unit, binding) in bindings.iter().enumerate() {
+        unsafe {
+            gl.ActiveTexture(gl::TEXTURE0 + unit as GLenum);
+            gl.BindTexture(gl::TEXTURE_2D, binding.texture);
+            gl.BindSampler(unit as GLuint, binding.sampler.map_or(0, |s| s.id));
+        }
+    }
+    unsafe {
+        gl.ActiveTexture(gl::TEXTURE0);
+    }
+}
+
+/// [`bind_textures`]で割り当てたユニット`0..count`を解除する
+pub fn unbind_textures(gl: &Gl, count: usize) {
+    for unit in 0..count {
+        unsafe {
+            gl.ActiveTexture(gl::TEXTURE0 + unit as GLenum);
+            gl.BindTexture(gl::TEXTURE_2D, 0);
+            gl.BindSampler(unit as GLuint, 0);
+        }
+    }
+    unsafe {
+        gl.ActiveTexture(gl::TEXTURE0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn min_filter_combines_mipmap_filter() {
+        assert_eq!(
+            SamplerConfig::linear_mipmap().gl_min_filter(),
+            gl::LINEAR_MIPMAP_LINEAR
+        );
+        assert_eq!(SamplerConfig::nearest().gl_min_filter(), gl::NEAREST);
+        let config = SamplerConfig {
+            mipmap_filter: Some(Filter::Nearest),
+            ..SamplerConfig::nearest()
+        };
+        assert_eq!(config.gl_min_filter(), gl::NEAREST_MIPMAP_NEAREST);
+    }
+}
@@ -0,0 +1,184 @@
+//! テクスチャへの画素の転送
+
+use std::os::raw::c_void;
+
+use crate::gl;
+use crate::gl::types::{GLenum, GLint, GLsizei, GLuint};
+use crate::gl::Gl;
+
+/// CPU 側の画素の並び
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    R,
+    Rg,
+    Rgb,
+    Rgba,
+}
+
+impl PixelFormat {
+    /// 1 画素あたりのバイト数
+    pub const fn channels(self) -> usize {
+        match self {
+            Self::R => 1,
+            Self::Rg => 2,
+            Self::Rgb => 3,
+            Self::Rgba => 4,
+        }
+    }
+
+    pub const fn to_gl(self) -> GLenum {
+        match self {
+            Self::R => gl::RED,
+            Self::Rg => gl::RG,
+            Self::Rgb => gl::RGB,
+            Self::Rgba => gl::RGBA,
+        }
+    }
+}
+
+/// 1 行のバイト数から`GL_UNPACK_ALIGNMENT`に設定する値を決める
+///
+/// 行の先頭が揃っている最大の境界 (8, 4, 2, 1) を返す。
+/// 例えば幅 3 の RGB 画像は 1 行 9 バイトなので 1 になる。
+pub const fn unpack_alignment(row_bytes: usize) -> GLint {
+    if row_bytes % 8 == 0 {
+        8
+    } else if row_bytes % 4 == 0 {
+        4
+    } else if row_bytes % 2 == 0 {
+        2
+    } else {
+        1
+    }
+}
+
+/// 大きな CPU 側のバッファの中の矩形
+#[derive(Debug, Clone, Copy)]
+pub struct PixelRegion<'a> {
+    /// 行が隙間無く並んだ画素
+    pub data: &'a [u8],
+    pub format: PixelFormat,
+    /// `data`の 1 行の画素数
+    pub row_length: u32,
+    /// 矩形の左上の画素の位置
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl<'a> PixelRegion<'a> {
+    /// バッファ全体を表す矩形
+    pub const fn whole(data: &'a [u8], format: PixelFormat, width: u32, height: u32) -> Self {
+        Self {
+            data,
+            format,
+            row_length: width,
+            x: 0,
+            y: 0,
+            width,
+            height,
+        }
+    }
+
+    /// 矩形が`data`に収まっているか確かめる
+    pub fn validate(&self) -> Result<(), String> {
+        if self.x + self.width > self.row_length {
+            return Err(format!(
+                "region x={} width={} exceeds row length {}",
+                self.x, self.width, self.row_length
+            ));
+        }
+        if self.width == 0 || self.height == 0 {
+            return Ok(());
+        }
+        let channels = self.format.channels();
+        let last_row = (self.y + self.height - 1) as usize;
+        let required =
+            (last_row * self.row_length as usize + (self.x + self.width) as usize) * channels;
+        if self.data.len() < required {
+            return Err(format!(
+                "region needs {required} bytes but the buffer has {}",
+                self.data.len()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// `region`をテクスチャの`(dst_x, dst_y)`の位置に転送する(glTexSubImage2D)
+///
+/// `GL_UNPACK_ROW_LENGTH`などを使うので、`region`を切り出してコピーする必要は無い。
+/// 終わったら転送の設定は既定値に戻る。
+pub fn upload_region(
+    gl: &Gl,
+    texture: GLuint,
+    dst_x: u32,
+    dst_y: u32,
+    region: &PixelRegion<'_>,
+) -> Result<(), String> {
+    region.validate()?;
+    let row_bytes = region.row_length as usize * region.format.channels();
+    unsafe {
+        gl.BindTexture(gl::TEXTURE_2D, texture);
+        gl.PixelStorei(gl::UNPACK_ALIGNMENT, unpack_alignment(row_bytes));
+        gl.PixelStorei(gl::UNPACK_ROW_LENGTH, region.row_length as GLint);
+        gl.PixelStorei(gl::UNPACK_SKIP_PIXELS, region.x as GLint);
+        gl.PixelStorei(gl::UNPACK_SKIP_ROWS, region.y as GLint);
+        gl.TexSubImage2D(
+            gl::TEXTURE_2D,
+            0,
+            dst_x as GLint,
+            dst_y as GLint,
+            region.width as GLsizei,
+            region.height as GLsizei,
+            region.format.to_gl(),
+            gl::UNSIGNED_BYTE,
+            region.data.as_ptr() as *const c_void,
+        );
+        reset_unpack_state(gl);
+        gl.BindTexture(gl::TEXTURE_2D, 0);
+    }
+    Ok(())
+}
+
+/// 転送の設定を既定値に戻す
+pub(crate) fn reset_unpack_state(gl: &Gl) {
+    unsafe {
+        gl.PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+        gl.PixelStorei(gl::UNPACK_ROW_LENGTH, 0);
+        gl.PixelStorei(gl::UNPACK_SKIP_PIXELS, 0);
+        gl.PixelStorei(gl::UNPACK_SKIP_ROWS, 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alignment_follows_row_bytes() {
+        assert_eq!(unpack_alignment(3 * 3), 1);
+        assert_eq!(unpack_alignment(3 * 2), 2);
+        assert_eq!(unpack_alignment(3 * 4), 4);
+        assert_eq!(unpack_alignment(4 * 4), 8);
+    }
+
+    #[test]
+    fn region_must_fit_in_buffer() {
+        let data = [0; 4 * 4 * 3];
+        let region = PixelRegion {
+            x: 1,
+            y: 1,
+            width: 3,
+            height: 3,
+            ..PixelRegion::whole(&data, PixelFormat::Rgb, 4, 4)
+        };
+        assert!(region.validate().is_ok());
+        let region = PixelRegion { width: 4, ..region };
+        assert!(region.validate().is_err());
+        assert!(PixelRegion::whole(&data[1..], PixelFormat::Rgb, 4, 4)
+            .validate()
+            .is_err());
+    }
+}
@@ -9,9 +9,11 @@ pub mod vertex;
 use std::mem;
 use std::os::raw::c_void;
 
+use crate::debug_label::{self, ObjectKind};
 use crate::gl;
 use crate::gl::types::{GLenum, GLfloat, GLint, GLsizei, GLsizeiptr};
 use crate::gl::Gl;
+use crate::memory::{GpuMemoryCategory, TrackedAllocation};
 use crate::shader::UniformVariables;
 
 pub use {
@@ -23,7 +25,7 @@ pub use {
         PhongRenderingInfo, Renderer,
     },
     texture_vao::builder::{CuboidTextures, VaoBuilder3DGeometry},
-    vertex::{VertexType, VertexWithColor, VertexWithNormUv},
+    vertex::{VertexAttribute, VertexType, VertexWithColor, VertexWithNormUv},
 };
 
 /// OpenGLのVertex Array ObjectとVertex Buffer Objectに対応する構造体
@@ -34,6 +36,7 @@ pub struct Vao<'a> {
     vbo: u32,
     vertex_num: i32,
     config: &'a VaoConfig,
+    memory: TrackedAllocation,
 }
 
 impl<'a> Vao<'a> {
@@ -88,15 +91,45 @@ impl<'a> Vao<'a> {
             gl.BindVertexArray(0);
         }
 
+        let memory = gl.memory().track(
+            GpuMemoryCategory::VertexBuffer,
+            "Unnamed Vao",
+            size.max(0) as u64,
+        );
+
         Vao {
             gl,
             vao,
             vbo,
             vertex_num,
             config,
+            memory,
         }
     }
 
+    /// OpenGLの関数に渡すための VAO と VBO の ID
+    ///
+    /// # Safety
+    /// この[`Vao`]がdropされていない限り安全
+    pub const unsafe fn raw_ids(&self) -> (u32, u32) {
+        (self.vao, self.vbo)
+    }
+
+    /// グラフィックスデバッガに表示される名前を付ける
+    ///
+    /// VBO には`"{name} [vbo]"`という名前が付く。
+    /// [`crate::memory::GpuMemoryTracker::report`]にもこの名前で表示される。
+    pub fn set_label(&self, name: &str) {
+        self.memory.set_label(name);
+        debug_label::set(&self.gl, ObjectKind::VertexArray, self.vao, name);
+        debug_label::set(
+            &self.gl,
+            ObjectKind::Buffer,
+            self.vbo,
+            &format!("{name} [vbo]"),
+        );
+    }
+
     fn draw(&self, _uniforms: &UniformVariables, draw_mode: GLenum) {
         unsafe {
             if self.config.depth_test {
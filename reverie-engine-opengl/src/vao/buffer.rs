@@ -3,6 +3,7 @@
 use std::marker::PhantomData;
 use std::mem;
 
+use crate::capabilities::{GlCapabilities, LimitError};
 use crate::gl;
 use crate::gl::types::{GLenum, GLint};
 use crate::gl::{types::GLfloat, Gl};
@@ -10,6 +11,9 @@ use crate::vao::Vao;
 use crate::vao::VaoConfig;
 use crate::vao::VertexType;
 
+#[cfg(feature = "mesh-export")]
+mod export;
+
 /// 頂点の情報を動的に追加・削除するためのバッファ
 #[derive(Debug)]
 pub struct VaoBuffer<V: VertexType> {
@@ -92,6 +96,24 @@ impl<V: VertexType> VaoBuffer<V> {
             .truncate(num_vertex_to_preserve * self.vertex_size);
     }
 
+    /// [`Self::build`]と同じだが、頂点の属性の数が制限を超えていればエラーを返す
+    pub fn try_build<'a>(
+        &self,
+        gl: &Gl,
+        config: &'a VaoConfig,
+        capabilities: &GlCapabilities,
+    ) -> Result<Vao<'a>, LimitError> {
+        capabilities.check_vertex_type::<V>()?;
+        Ok(self.build(gl, config))
+    }
+
+    /// [`Self::build`]と同じだが、グラフィックスデバッガに表示される名前を付ける
+    pub fn build_named<'a>(&self, gl: &Gl, config: &'a VaoConfig, name: &str) -> Vao<'a> {
+        let vao = self.build(gl, config);
+        vao.set_label(name);
+        vao
+    }
+
     /// 現在のバッファの内容をもとに[`Vao`]を作る
     pub fn build<'a>(&self, gl: &Gl, config: &'a VaoConfig) -> Vao<'a> {
         unsafe {
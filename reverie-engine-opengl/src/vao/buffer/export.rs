@@ -0,0 +1,170 @@
+//! 頂点を Wavefront OBJ に書き出す。メッシュを Blender などで開いて調べるため
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use super::VaoBuffer;
+use crate::vao::{VertexAttribute, VertexType};
+
+impl<V: VertexType> VaoBuffer<V> {
+    /// 頂点を OBJ のファイルに書き出す。詳しくは [`Self::write_obj`] を参照
+    pub fn export_obj(&self, path: impl AsRef<Path>, source: &str) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_obj(&mut writer, source)?;
+        writer.flush()
+    }
+
+    /// 頂点を OBJ の形式で `writer` に書く
+    ///
+    /// 3 頂点ずつを 1 つの三角形にする。位置、法線、UV は [`VertexType::attribute_roles`] から探し、
+    /// 色は位置の後ろに続ける (Blender などが読める拡張)。
+    /// 値は読み戻すと同じ f32 になる最も短い 10 進数で書くので、精度は落ちない。
+    /// `source` にはチャンクの座標など、どこから書き出したものかを渡す。行ごとにコメントにする。
+    pub fn write_obj(&self, mut writer: impl Write, source: &str) -> io::Result<()> {
+        let find = |role| attribute_range(V::attribute_roles(), V::attribute_sizes(), role);
+        let position = find(VertexAttribute::Position).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the vertex type has no position attribute",
+            )
+        })?;
+        let (normal, uv, color) = (
+            find(VertexAttribute::Normal),
+            find(VertexAttribute::Uv),
+            find(VertexAttribute::Color),
+        );
+
+        let vertices = self.buffer.chunks_exact(self.vertex_size);
+        writeln!(
+            writer,
+            "# reverie-engine-opengl VaoBuffer<{}>",
+            std::any::type_name::<V>()
+        )?;
+        for line in source.lines() {
+            writeln!(writer, "# {line}")?;
+        }
+        writeln!(writer, "# {} vertices", vertices.len())?;
+
+        for vertex in vertices.clone() {
+            write!(writer, "v")?;
+            write_values(&mut writer, &vertex[position.clone()])?;
+            if let Some(color) = &color {
+                write_values(&mut writer, &vertex[color.clone()])?;
+            }
+            writeln!(writer)?;
+            if let Some(normal) = &normal {
+                write!(writer, "vn")?;
+                write_values(&mut writer, &vertex[normal.clone()])?;
+                writeln!(writer)?;
+            }
+            if let Some(uv) = &uv {
+                write!(writer, "vt")?;
+                write_values(&mut writer, &vertex[uv.clone()])?;
+                writeln!(writer)?;
+            }
+        }
+
+        for triangle in 0..vertices.len() / 3 {
+            write!(writer, "f")?;
+            for index in 3 * triangle + 1..=3 * triangle + 3 {
+                match (uv.is_some(), normal.is_some()) {
+                    (true, true) => write!(writer, " {index}/{index}/{index}")?,
+                    (true, false) => write!(writer, " {index}/{index}")?,
+                    (false, true) => write!(writer, " {index}//{index}")?,
+                    (false, false) => write!(writer, " {index}")?,
+                }
+            }
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+}
+
+/// 頂点の中で `role` の属性が占める範囲
+fn attribute_range(
+    roles: &[VertexAttribute],
+    sizes: &[i32],
+    role: VertexAttribute,
+) -> Option<Range<usize>> {
+    let mut offset = 0;
+    for (&current, &size) in roles.iter().zip(sizes) {
+        let size = size as usize;
+        if current == role {
+            return Some(offset..offset + size);
+        }
+        offset += size;
+    }
+    None
+}
+
+fn write_values(writer: &mut impl Write, values: &[f32]) -> io::Result<()> {
+    for value in values {
+        write!(writer, " {value}")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vao::{VertexWithColor, VertexWithNormUv};
+
+    fn parse(line: &str, prefix: &str) -> Vec<f32> {
+        line.strip_prefix(prefix)
+            .unwrap()
+            .split_whitespace()
+            .map(|value| value.parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn obj_round_trips_exact_values() {
+        let mut buffer = VaoBuffer::<VertexWithNormUv>::new();
+        let mut vertices = Vec::new();
+        for a in [0.1, 1.0e-7, -3.333_333_3] {
+            vertices.extend([a, -a, 2.0 * a, 0.0, 1.0, 0.0, a / 3.0, 1.0 - a]);
+        }
+        buffer.append(&mut vertices.clone());
+
+        let mut obj = Vec::new();
+        buffer.write_obj(&mut obj, "chunk (3, -2)").unwrap();
+        let obj = String::from_utf8(obj).unwrap();
+        let lines: Vec<_> = obj.lines().collect();
+        assert!(lines.contains(&"# chunk (3, -2)"));
+
+        let mut read = Vec::new();
+        let positions = lines.iter().filter(|line| line.starts_with("v "));
+        let normals = lines.iter().filter(|line| line.starts_with("vn "));
+        let uvs = lines.iter().filter(|line| line.starts_with("vt "));
+        for ((position, normal), uv) in positions.zip(normals).zip(uvs) {
+            read.extend(parse(position, "v "));
+            read.extend(parse(normal, "vn "));
+            read.extend(parse(uv, "vt "));
+        }
+        assert_eq!(
+            read.iter().map(|value| value.to_bits()).collect::<Vec<_>>(),
+            vertices
+                .iter()
+                .map(|value| value.to_bits())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(lines.last(), Some(&"f 1/1/1 2/2/2 3/3/3"));
+    }
+
+    #[test]
+    fn colors_follow_positions() {
+        let mut buffer = VaoBuffer::<VertexWithColor>::new();
+        buffer.append(&mut vec![
+            0.0, 0.0, 0.0, 1.0, 0.0, 0.0, //
+            1.0, 0.0, 0.0, 0.0, 1.0, 0.0, //
+            0.0, 1.0, 0.0, 0.0, 0.0, 1.0,
+        ]);
+        let mut obj = Vec::new();
+        buffer.write_obj(&mut obj, "").unwrap();
+        let obj = String::from_utf8(obj).unwrap();
+        assert!(obj.contains("v 1 0 0 0 1 0\n"));
+        assert!(!obj.contains("vn"));
+        assert!(obj.ends_with("f 1 2 3\n"));
+    }
+}
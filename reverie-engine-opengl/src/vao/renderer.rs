@@ -4,7 +4,9 @@ use reverie_util::math::nalgebra::{Matrix4, Point3, Vector3};
 use crate::{
     gl::{self, Gl},
     shader::{Program, Shader, Uniform::*, UniformVariables},
-    texture::ImageLoadInfo,
+    texture::{
+        bind_textures, unbind_textures, ImageLoadInfo, Sampler, SamplerConfig, TextureBinding,
+    },
     vao::Vao,
 };
 
@@ -15,11 +17,24 @@ pub trait Renderer<T> {
 #[derive(Debug)]
 pub struct Phong3DRenderer {
     program: Program,
+    /// テクスチャユニット 0 (拡散色) のサンプラー
+    diffuse_sampler: Sampler,
+    /// テクスチャユニット 1 (ライトマップ) のサンプラー
+    lightmap_sampler: Sampler,
 }
 
 impl Phong3DRenderer {
-    pub const fn new(program: Program) -> Self {
-        Self { program }
+    /// 拡散色のテクスチャはミップマップ付きの線形補間、ライトマップは補間無しでサンプリングする
+    pub fn new(gl: Gl, program: Program) -> Self {
+        let diffuse_sampler = Sampler::new(gl.clone(), SamplerConfig::linear_mipmap());
+        diffuse_sampler.set_label("phong-diffuse-sampler");
+        let lightmap_sampler = Sampler::new(gl, SamplerConfig::nearest());
+        lightmap_sampler.set_label("phong-lightmap-sampler");
+        Self {
+            program,
+            diffuse_sampler,
+            lightmap_sampler,
+        }
     }
 }
 
@@ -42,6 +57,8 @@ pub struct Phong3DRenderingInfo<'a> {
     pub projection_matrix: &'a Matrix4<f32>,
     pub camera_pos: &'a Point3<f32>,
     pub texture: &'a ImageLoadInfo<'a>,
+    /// 拡散色に掛け合わせるライトマップ
+    pub lightmap: Option<&'a ImageLoadInfo<'a>>,
 }
 
 impl Renderer<&Phong3DRenderingInfo<'_>> for Phong3DRenderer {
@@ -83,13 +100,25 @@ impl Renderer<&Phong3DRenderingInfo<'_>> for Phong3DRenderer {
 
             self.program.set_used();
             self.program.set_uniforms(&uniforms);
+            self.program.set_uniform(c_str!("uScreenTexture"), &Int(0));
+            self.program.set_uniform(c_str!("uLightmap"), &Int(1));
+            self.program
+                .set_uniform(c_str!("uUseLightmap"), &Bool(extra.lightmap.is_some()));
         }
 
-        unsafe {
-            gl.BindTexture(gl::TEXTURE_2D, extra.texture.raw_gl_id());
-            vao.draw_triangles(&uniforms);
-            gl.BindTexture(gl::TEXTURE_2D, 0);
+        let mut bindings = vec![TextureBinding {
+            texture: unsafe { extra.texture.raw_gl_id() },
+            sampler: Some(&self.diffuse_sampler),
+        }];
+        if let Some(lightmap) = extra.lightmap {
+            bindings.push(TextureBinding {
+                texture: unsafe { lightmap.raw_gl_id() },
+                sampler: Some(&self.lightmap_sampler),
+            });
         }
+        bind_textures(&gl, &bindings);
+        vao.draw_triangles(&uniforms);
+        unbind_textures(&gl, bindings.len());
     }
 }
 
@@ -3,10 +3,26 @@ use crate::gl::{
     types::{GLenum, GLint},
 };
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 頂点の属性が何を表すか
+pub enum VertexAttribute {
+    Position,
+    Normal,
+    Uv,
+    Color,
+}
+
 pub trait VertexType {
     fn vertex_size() -> usize;
     fn attribute_types() -> &'static [GLenum];
     fn attribute_sizes() -> &'static [GLint];
+
+    /// 属性それぞれが何を表すか。[`Self::attribute_sizes`] と同じ順に並べる
+    ///
+    /// 頂点を書き出すときに使う。分からなければ空のままにする。
+    fn attribute_roles() -> &'static [VertexAttribute] {
+        &[]
+    }
 }
 
 #[derive(Debug)]
@@ -30,6 +46,14 @@ impl VertexType for VertexWithNormUv {
     fn attribute_sizes() -> &'static [GLint] {
         &VNUV_ATTR_SZ
     }
+
+    fn attribute_roles() -> &'static [VertexAttribute] {
+        &[
+            VertexAttribute::Position,
+            VertexAttribute::Normal,
+            VertexAttribute::Uv,
+        ]
+    }
 }
 
 #[derive(Debug)]
@@ -50,4 +74,8 @@ impl VertexType for VertexWithColor {
     fn attribute_sizes() -> &'static [GLint] {
         &VC_ATTR_SZ
     }
+
+    fn attribute_roles() -> &'static [VertexAttribute] {
+        &[VertexAttribute::Position, VertexAttribute::Color]
+    }
 }
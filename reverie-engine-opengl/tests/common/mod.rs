@@ -0,0 +1,75 @@
+//! 画面無しの OpenGL コンテキストを作るテスト用の道具
+//!
+//! EGL の surfaceless プラットフォーム (Mesa) を使う。
+
+use std::ffi::c_void;
+
+use khronos_egl as egl;
+use reverie_engine_opengl::gl::Gl;
+
+const PLATFORM_SURFACELESS_MESA: egl::Enum = 0x31DD;
+
+pub struct HeadlessGl {
+    pub gl: Gl,
+    _egl: egl::DynamicInstance<egl::EGL1_5>,
+}
+
+pub fn headless_gl() -> Option<HeadlessGl> {
+    let result = (|| -> Result<HeadlessGl, String> {
+        let egl = unsafe { egl::DynamicInstance::<egl::EGL1_5>::load_required() }
+            .map_err(|e| e.to_string())?;
+        let display = unsafe {
+            egl.get_platform_display(
+                PLATFORM_SURFACELESS_MESA,
+                egl::DEFAULT_DISPLAY,
+                &[egl::ATTRIB_NONE],
+            )
+        }
+        .map_err(|e| e.to_string())?;
+        egl.initialize(display).map_err(|e| e.to_string())?;
+        egl.bind_api(egl::OPENGL_API).map_err(|e| e.to_string())?;
+        let config = egl
+            .choose_first_config(
+                display,
+                &[
+                    egl::RENDERABLE_TYPE,
+                    egl::OPENGL_BIT,
+                    egl::SURFACE_TYPE,
+                    egl::PBUFFER_BIT,
+                    egl::NONE,
+                ],
+            )
+            .map_err(|e| e.to_string())?
+            .ok_or("no config")?;
+        let context = egl
+            .create_context(
+                display,
+                config,
+                None,
+                &[
+                    egl::CONTEXT_MAJOR_VERSION,
+                    3,
+                    egl::CONTEXT_MINOR_VERSION,
+                    3,
+                    egl::CONTEXT_OPENGL_PROFILE_MASK,
+                    egl::CONTEXT_OPENGL_CORE_PROFILE_BIT,
+                    egl::NONE,
+                ],
+            )
+            .map_err(|e| e.to_string())?;
+        egl.make_current(display, None, None, Some(context))
+            .map_err(|e| e.to_string())?;
+        let gl = Gl::load_with(|symbol| {
+            egl.get_proc_address(symbol)
+                .map_or(std::ptr::null(), |f| f as *const c_void)
+        });
+        Ok(HeadlessGl { gl, _egl: egl })
+    })();
+    match result {
+        Ok(headless) => Some(headless),
+        Err(e) => {
+            eprintln!("skipped: cannot create a headless OpenGL context: {e}");
+            None
+        }
+    }
+}
@@ -0,0 +1,63 @@
+//! グラフィックスデバッガのための名前が付くか確かめるテスト
+//!
+//! 画面無しの OpenGL コンテキストが作れない環境や、`GL_KHR_debug`が無い環境ではスキップする。
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use common::headless_gl;
+use image::{DynamicImage, RgbaImage};
+use reverie_engine_opengl::{
+    debug_label::{self, ObjectKind},
+    vao::{VaoBuffer, VaoConfigBuilder, VertexWithNormUv},
+    ReverieEngine,
+};
+
+#[test]
+fn texture_is_labeled_with_its_id() {
+    let Some(headless) = headless_gl() else {
+        return;
+    };
+    let gl = headless.gl;
+    if !gl.supports_khr_debug() {
+        eprintln!("skipped: GL_KHR_debug is not supported");
+        return;
+    }
+    let mut manager = ReverieEngine::new().create_image_manager(gl.clone());
+    let info = manager
+        .load_image(
+            DynamicImage::ImageRgba8(RgbaImage::new(2, 2)),
+            "textures/grass.png",
+            false,
+        )
+        .unwrap();
+
+    let label = debug_label::get(&gl, ObjectKind::Texture, unsafe { info.raw_gl_id() });
+    assert_eq!(label.as_deref(), Some("textures/grass.png"));
+}
+
+#[test]
+fn vao_and_vbo_are_labeled() {
+    let Some(headless) = headless_gl() else {
+        return;
+    };
+    let gl = headless.gl;
+    if !gl.supports_khr_debug() {
+        eprintln!("skipped: GL_KHR_debug is not supported");
+        return;
+    }
+    let config = VaoConfigBuilder::new().build();
+    let mut buffer = VaoBuffer::<VertexWithNormUv>::new();
+    buffer.append(&mut vec![0.0; 8 * 3]);
+    let vao = buffer.build_named(&gl, &config, "terrain");
+
+    let (vao_id, vbo_id) = unsafe { vao.raw_ids() };
+    assert_eq!(
+        debug_label::get(&gl, ObjectKind::VertexArray, vao_id).as_deref(),
+        Some("terrain")
+    );
+    assert_eq!(
+        debug_label::get(&gl, ObjectKind::Buffer, vbo_id).as_deref(),
+        Some("terrain [vbo]")
+    );
+}
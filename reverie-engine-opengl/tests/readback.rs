@@ -0,0 +1,66 @@
+//! 画面外の描画先から画素を読み出して確かめるテスト
+//!
+//! 画面無しの OpenGL コンテキストが作れない環境ではスキップする。
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::headless_gl;
+use reverie_engine_opengl::{
+    framebuffer::OffscreenTarget,
+    gl::{self, Gl},
+};
+
+const RED: [u8; 4] = [255, 0, 0, 255];
+const GREEN: [u8; 4] = [0, 255, 0, 255];
+const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+/// 描画先の`(x, y, width, height)`の範囲だけを`color`で塗る。座標はOpenGLと同じく左下が原点
+fn clear_rect(gl: &Gl, (x, y, width, height): (i32, i32, i32, i32), color: [u8; 4]) {
+    let [r, g, b, a] = color.map(|c| f32::from(c) / 255.0);
+    unsafe {
+        gl.Enable(gl::SCISSOR_TEST);
+        gl.Scissor(x, y, width, height);
+        gl.ClearColor(r, g, b, a);
+        gl.Clear(gl::COLOR_BUFFER_BIT);
+        gl.Disable(gl::SCISSOR_TEST);
+    }
+}
+
+#[test]
+fn pixels_are_read_from_the_top_row() {
+    let Some(headless) = headless_gl() else {
+        return;
+    };
+    let gl = headless.gl;
+    let target = OffscreenTarget::new(gl.clone(), 4, 4).unwrap();
+    target.render(|| {
+        clear_rect(&gl, (0, 0, 4, 4), BLUE);
+        // 上半分を赤、右下の 1 画素を緑にする
+        clear_rect(&gl, (0, 2, 4, 2), RED);
+        clear_rect(&gl, (3, 0, 1, 1), GREEN);
+    });
+
+    let request = target.request_pixels();
+    let started = Instant::now();
+    let pixels = loop {
+        if let Some(pixels) = request.try_get() {
+            break pixels;
+        }
+        assert!(
+            started.elapsed() < Duration::from_secs(10),
+            "the read did not finish"
+        );
+        std::thread::sleep(Duration::from_millis(1));
+    };
+
+    let mut expected = Vec::new();
+    for color in [RED; 8].into_iter().chain([BLUE; 7]).chain([GREEN]) {
+        expected.extend(color);
+    }
+    assert_eq!(pixels, expected);
+    // 待つ方法でも同じものが読める
+    assert_eq!(request.wait(), Some(expected));
+}
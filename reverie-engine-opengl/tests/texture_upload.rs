@@ -0,0 +1,89 @@
+//! テクスチャへの転送結果を読み出して確かめるテスト
+//!
+//! 画面無しの OpenGL コンテキストが作れない環境ではスキップする。
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use std::ffi::c_void;
+
+use common::headless_gl;
+use image::{DynamicImage, RgbImage};
+use reverie_engine_opengl::{
+    gl::{self, Gl},
+    texture::{PixelFormat, PixelRegion},
+    ReverieEngine,
+};
+
+/// テクスチャの内容を RGB で読み出す
+fn read_rgb(gl: &Gl, texture: u32, width: u32, height: u32) -> Vec<u8> {
+    let mut pixels = vec![0u8; (width * height * 3) as usize];
+    unsafe {
+        gl.BindTexture(gl::TEXTURE_2D, texture);
+        gl.PixelStorei(gl::PACK_ALIGNMENT, 1);
+        gl.GetTexImage(
+            gl::TEXTURE_2D,
+            0,
+            gl::RGB,
+            gl::UNSIGNED_BYTE,
+            pixels.as_mut_ptr() as *mut c_void,
+        );
+        gl.PixelStorei(gl::PACK_ALIGNMENT, 4);
+        gl.BindTexture(gl::TEXTURE_2D, 0);
+    }
+    pixels
+}
+
+fn rgb_3x3() -> Vec<u8> {
+    (0..27).map(|i| i * 9).collect()
+}
+
+#[test]
+fn rgb_image_with_odd_width_is_not_skewed() {
+    let Some(headless) = headless_gl() else {
+        return;
+    };
+    let gl = headless.gl;
+    let mut manager = ReverieEngine::new().create_image_manager(gl.clone());
+    let image = RgbImage::from_raw(3, 3, rgb_3x3()).unwrap();
+    let info = manager
+        .load_image(DynamicImage::ImageRgb8(image), "rgb", false)
+        .unwrap();
+
+    let actual = read_rgb(&gl, unsafe { info.raw_gl_id() }, 3, 3);
+    assert_eq!(actual, rgb_3x3());
+}
+
+#[test]
+fn upload_region_copies_sub_rectangle() {
+    let Some(headless) = headless_gl() else {
+        return;
+    };
+    let gl = headless.gl;
+    let mut manager = ReverieEngine::new().create_image_manager(gl.clone());
+    let image = RgbImage::from_raw(3, 3, vec![0; 27]).unwrap();
+    let info = manager
+        .load_image(DynamicImage::ImageRgb8(image), "target", false)
+        .unwrap();
+
+    // 5x4 のバッファの (1, 2) から 2x2 を (1, 1) に書き込む
+    let source: Vec<u8> = (0..5 * 4 * 3).map(|i| i as u8 + 1).collect();
+    let region = PixelRegion {
+        x: 1,
+        y: 2,
+        width: 2,
+        height: 2,
+        ..PixelRegion::whole(&source, PixelFormat::Rgb, 5, 4)
+    };
+    manager.upload_region("target", 1, 1, &region).unwrap();
+
+    let actual = read_rgb(&gl, unsafe { info.raw_gl_id() }, 3, 3);
+    let texel = |x: usize, y: usize| &actual[(y * 3 + x) * 3..][..3];
+    let source_texel = |x: usize, y: usize| &source[(y * 5 + x) * 3..][..3];
+    assert_eq!(texel(0, 0), [0, 0, 0]);
+    assert_eq!(texel(1, 1), source_texel(1, 2));
+    assert_eq!(texel(2, 1), source_texel(2, 2));
+    assert_eq!(texel(1, 2), source_texel(1, 3));
+    assert_eq!(texel(2, 2), source_texel(2, 3));
+    assert_eq!(texel(0, 2), [0, 0, 0]);
+}
@@ -0,0 +1,56 @@
+//! 親子関係のある変換の伝播にかかる時間の計測
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use nalgebra::{Translation3, UnitQuaternion, Vector3};
+use reverie_engine::scene::{
+    propagate_transforms, set_parent, EntityIndex, LocalTransformComponent, TransformComponent,
+};
+
+const ROOT_COUNT: usize = 100;
+const CHILDREN_PER_NODE: usize = 10;
+
+fn spawn_at(world: &mut hecs::World, x: f32, y: f32) -> EntityIndex {
+    EntityIndex(
+        world.spawn((TransformComponent::with_translation(Translation3::new(
+            x, y, 0.0,
+        )),)),
+    )
+}
+
+fn bench_propagation(c: &mut Criterion) {
+    // 100 個のルートに 10 個ずつの子と 10 個ずつの孫 (合わせて 11,100 個)
+    let mut world = hecs::World::new();
+    let mut roots = Vec::with_capacity(ROOT_COUNT);
+    for i in 0..ROOT_COUNT {
+        let root = spawn_at(&mut world, i as f32 * 100.0, 0.0);
+        roots.push(root);
+        for j in 0..CHILDREN_PER_NODE {
+            let child = spawn_at(&mut world, i as f32 * 100.0, j as f32 * 10.0);
+            set_parent(&mut world, child, root).unwrap();
+            for k in 0..CHILDREN_PER_NODE {
+                let grandchild = spawn_at(&mut world, k as f32, j as f32 * 10.0);
+                set_parent(&mut world, grandchild, child).unwrap();
+            }
+        }
+    }
+    for (_, local) in world.query_mut::<&mut LocalTransformComponent>() {
+        local.0.rotation = UnitQuaternion::from_axis_angle(&Vector3::z_axis(), 0.1);
+    }
+
+    c.bench_function("hierarchy/propagate_11k", |b| {
+        b.iter(|| {
+            for root in &roots {
+                world
+                    .get::<&mut TransformComponent>(root.0)
+                    .unwrap()
+                    .translation
+                    .y += 1.0;
+            }
+            propagate_transforms(&mut world);
+            black_box(&world);
+        })
+    });
+}
+
+criterion_group!(benches, bench_propagation);
+criterion_main!(benches);
@@ -0,0 +1,54 @@
+//! スプライトの描画準備にかかる時間の計測
+//!
+//! GPU には触れず、CPU 側で頂点データを組み立てる部分だけを計測する。
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use nalgebra::{Scale3, Translation3, UnitQuaternion, Vector3};
+use reverie_engine::{
+    scene::{SpriteComponent, TransformComponent},
+    texture::{TextureId, TextureRegistry},
+    wgpu_wrapper::vertex::UvVertex,
+};
+
+const SPRITE_COUNT: usize = 10_000;
+
+fn bench_sprite_preparation(c: &mut Criterion) {
+    let mut registry = TextureRegistry::default();
+    let atlas = registry.create_altas_texture(256, 256, None);
+    let texture: TextureId = registry
+        .allocate_sub_image(atlas, image::RgbaImage::new(32, 32))
+        .unwrap()
+        .into();
+
+    let mut world = hecs::World::new();
+    for i in 0..SPRITE_COUNT {
+        let x = (i % 100) as f32 * 10.0;
+        let y = (i / 100) as f32 * 10.0;
+        world.spawn((
+            TransformComponent::new(
+                Translation3::new(x, y, 0.0),
+                Scale3::new(8.0, 8.0, 1.0),
+                UnitQuaternion::from_axis_angle(&Vector3::z_axis(), i as f32 * 0.01),
+            ),
+            SpriteComponent::new(texture),
+        ));
+    }
+
+    let mut vertices: Vec<UvVertex> = Vec::with_capacity(SPRITE_COUNT * 4);
+    c.bench_function("sprite/prepare_10k", |b| {
+        b.iter(|| {
+            vertices.clear();
+            for (_, (transform, sprite)) in world
+                .query::<(&TransformComponent, &SpriteComponent)>()
+                .iter()
+            {
+                let quad = sprite.quad_vertices(&registry, transform).unwrap();
+                vertices.extend_from_slice(&quad);
+            }
+            black_box(&vertices);
+        })
+    });
+}
+
+criterion_group!(benches, bench_sprite_preparation);
+criterion_main!(benches);
@@ -0,0 +1,42 @@
+//! システムを呼び出す [`Scene::update`] の手間の計測
+//!
+//! 何もしないシステムを 50 個登録し、GPU を使わずに更新する。
+
+use std::time::Duration;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use reverie_engine::scene::{Frame, RenderResource, Scene, System};
+use web_time::Instant;
+
+const SYSTEM_COUNT: usize = 50;
+
+struct Noop;
+
+impl System for Noop {
+    fn setup(&mut self, _resource: Option<&RenderResource<'_>>) {}
+
+    fn update(
+        &mut self,
+        frame: &Frame<'_>,
+        world: &mut hecs::World,
+        _resource: Option<&RenderResource<'_>>,
+    ) {
+        black_box((frame, world));
+    }
+}
+
+fn bench_update(c: &mut Criterion) {
+    let mut scene = Scene::default();
+    for _ in 0..SYSTEM_COUNT {
+        scene.register_system(Noop);
+    }
+    scene.setup_headless().unwrap();
+    let frame = Frame::new(Instant::now(), Duration::from_millis(16));
+
+    c.bench_function("update/50_systems", |b| {
+        b.iter(|| scene.update_headless(black_box(&frame)));
+    });
+}
+
+criterion_group!(benches, bench_update);
+criterion_main!(benches);
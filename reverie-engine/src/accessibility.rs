@@ -0,0 +1,132 @@
+//! 色覚の多様性に合わせた色の変換
+//!
+//! [`crate::settings::AccessibilitySettings`] で選び、描画の最後に画面全体の色に 3x3 の行列を掛ける。
+//! 行列は線形の RGB に掛ける。描画先が sRGB でないときは、sRGB の値にそのまま掛ける近似になる。
+use nalgebra::Matrix3;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// どの色覚に合わせるか
+pub enum ColorBlindMode {
+    #[default]
+    /// 変換しない
+    Off,
+    /// 1 型 (赤の錐体が無い)
+    Protanopia,
+    /// 2 型 (緑の錐体が無い)
+    Deuteranopia,
+    /// 3 型 (青の錐体が無い)
+    Tritanopia,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+/// [`ColorBlindMode`] の色覚に対して何をするか
+pub enum ColorBlindFilter {
+    #[default]
+    /// 見分けにくい色の差を、見分けやすい色の差に移す (ダルトナイズ)
+    Daltonize,
+    /// その色覚での見え方を再現する。配色を確かめるのに使う
+    Simulate,
+}
+
+/// `mode` の色覚での見え方を再現する行列 (Machado ほか 2009、強さ 1.0)
+fn simulation(mode: ColorBlindMode) -> Matrix3<f32> {
+    match mode {
+        ColorBlindMode::Off => Matrix3::identity(),
+        ColorBlindMode::Protanopia => Matrix3::new(
+            0.152_286, 1.052_583, -0.204_868, //
+            0.114_503, 0.786_281, 0.099_216, //
+            -0.003_882, -0.048_116, 1.051_998,
+        ),
+        ColorBlindMode::Deuteranopia => Matrix3::new(
+            0.367_322, 0.860_646, -0.227_968, //
+            0.280_085, 0.672_501, 0.047_413, //
+            -0.011_820, 0.042_940, 0.968_881,
+        ),
+        ColorBlindMode::Tritanopia => Matrix3::new(
+            1.255_528, -0.076_749, -0.178_779, //
+            -0.078_411, 0.930_809, 0.147_602, //
+            0.004_733, 0.691_367, 0.303_900,
+        ),
+    }
+}
+
+/// 見えない色の差を、見える色の成分に足し込む行列
+fn error_shift(mode: ColorBlindMode) -> Matrix3<f32> {
+    match mode {
+        ColorBlindMode::Off => Matrix3::zeros(),
+        // 赤と緑の差を、緑と青の明るさに移す
+        ColorBlindMode::Protanopia | ColorBlindMode::Deuteranopia => Matrix3::new(
+            0.0, 0.0, 0.0, //
+            0.7, 1.0, 0.0, //
+            0.7, 0.0, 1.0,
+        ),
+        // 青と黄の差を、赤と緑に移す
+        ColorBlindMode::Tritanopia => Matrix3::new(
+            1.0, 0.0, 0.7, //
+            0.0, 1.0, 0.7, //
+            0.0, 0.0, 0.0,
+        ),
+    }
+}
+
+/// 画面の色に掛ける行列。変換しないときは `None`
+///
+/// 灰色は灰色のまま変わらない。
+pub fn color_matrix(mode: ColorBlindMode, filter: ColorBlindFilter) -> Option<Matrix3<f32>> {
+    if mode == ColorBlindMode::Off {
+        return None;
+    }
+    let simulation = simulation(mode);
+    Some(match filter {
+        ColorBlindFilter::Simulate => simulation,
+        ColorBlindFilter::Daltonize => {
+            Matrix3::identity() + error_shift(mode) * (Matrix3::identity() - simulation)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Vector3;
+
+    use super::*;
+
+    const MODES: [ColorBlindMode; 3] = [
+        ColorBlindMode::Protanopia,
+        ColorBlindMode::Deuteranopia,
+        ColorBlindMode::Tritanopia,
+    ];
+
+    #[test]
+    fn off_is_not_filtered_and_gray_stays_gray() {
+        assert_eq!(
+            color_matrix(ColorBlindMode::Off, ColorBlindFilter::Simulate),
+            None
+        );
+        let gray = Vector3::new(0.5, 0.5, 0.5);
+        for mode in MODES {
+            for filter in [ColorBlindFilter::Simulate, ColorBlindFilter::Daltonize] {
+                let filtered = color_matrix(mode, filter).unwrap() * gray;
+                assert!(
+                    (filtered - gray).amax() < 1e-3,
+                    "{mode:?} {filter:?}: {filtered}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn daltonize_separates_colors_that_look_alike() {
+        let (red, green) = (Vector3::new(1.0, 0.2, 0.0), Vector3::new(0.5, 0.6, 0.0));
+        let simulate =
+            color_matrix(ColorBlindMode::Deuteranopia, ColorBlindFilter::Simulate).unwrap();
+        let daltonize =
+            color_matrix(ColorBlindMode::Deuteranopia, ColorBlindFilter::Daltonize).unwrap();
+        // ダルトナイズした色を 2 型の目で見ると、そのまま見るより差が大きい
+        let seen = |m: &Matrix3<f32>| (simulate * m * red - simulate * m * green).norm();
+        assert!(seen(&daltonize) > seen(&Matrix3::identity()) * 1.5);
+    }
+}
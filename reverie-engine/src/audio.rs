@@ -0,0 +1,446 @@
+//! ワールド内の位置に応じた音の大きさと定位
+//!
+//! 音の読み込みや出力は [`AudioBackend`] を実装したものに任せる。
+//! [`Mixer`] は読み込んだ効果音と [`MusicPlayer`] の曲を混ぜる実装で、`cpal` の feature を有効にすると
+//! `CpalOutput` が既定の出力装置で鳴らす。
+//! エンジンは [`AudioListenerComponent`] と [`AudioEmitterComponent`] の位置関係から
+//! 音量と左右の定位を計算し、[`AudioSystem`] が毎フレーム鳴っている音に反映する。
+//! 曲は [`MusicPlayer`] で鳴らす。
+use std::{collections::HashMap, time::Duration};
+
+use nalgebra::Point3;
+
+use crate::scene::{resource_mut, Frame, RenderResource, System, TransformComponent};
+
+#[cfg(feature = "cpal")]
+mod cpal_output;
+mod mixer;
+mod music;
+
+#[cfg(feature = "cpal")]
+pub use cpal_output::CpalOutput;
+pub use mixer::Mixer;
+pub use music::{LoopPoints, LoopPosition, MusicId, MusicPlayer, MusicSource, OggSource};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// [`AudioBackend`] に読み込まれた音
+pub struct SoundId(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// [`AudioBackend`] で鳴っている 1 つの音
+pub struct VoiceId(pub u64);
+
+/// 音を実際に鳴らすもの
+///
+/// `gain` は 0.0 以上の音量の倍率、`pan` は -1.0 (左) から 1.0 (右) の定位。
+pub trait AudioBackend: Send + Sync {
+    /// 音を鳴らし始める。鳴らせなければ `None`
+    fn play(&mut self, sound: SoundId, looping: bool, gain: f32, pan: f32) -> Option<VoiceId>;
+
+    /// 鳴っている音の音量と定位を、`ramp` の時間をかけて目標の値へ変える
+    ///
+    /// 急に変えると波形が途切れてノイズになるので、サンプルごとに少しずつ変える。
+    /// [`GainRamp`] を使うとよい。
+    fn set_gain_and_pan(&mut self, voice: VoiceId, gain: f32, pan: f32, ramp: Duration);
+
+    fn stop(&mut self, voice: VoiceId);
+
+    /// 音がまだ鳴っているか。ループしない音は最後まで再生すると `false` になる
+    fn is_playing(&self, voice: VoiceId) -> bool;
+}
+
+/// 音を鳴らす [`AudioBackend`] を持つリソース
+///
+/// [`crate::scene::Scene::insert_resource`] で追加する。無ければ [`AudioSystem`] は何もしない。
+pub struct Audio {
+    backend: Box<dyn AudioBackend>,
+    /// 効果音の音量。[`MusicPlayer`] の音量とは別
+    sfx_volume: f32,
+}
+
+impl std::fmt::Debug for Audio {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Audio").finish_non_exhaustive()
+    }
+}
+
+impl Audio {
+    pub fn new(backend: impl AudioBackend + 'static) -> Self {
+        Self {
+            backend: Box::new(backend),
+            sfx_volume: 1.0,
+        }
+    }
+
+    /// 効果音の音量を変える。鳴っている音には次の [`AudioSystem`] の更新で反映される
+    pub fn set_sfx_volume(&mut self, volume: f32) {
+        self.sfx_volume = volume.max(0.0);
+    }
+
+    pub const fn sfx_volume(&self) -> f32 {
+        self.sfx_volume
+    }
+
+    pub fn backend(&self) -> &dyn AudioBackend {
+        self.backend.as_ref()
+    }
+
+    pub fn backend_mut(&mut self) -> &mut dyn AudioBackend {
+        self.backend.as_mut()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// 音を聞く位置と向きの印
+///
+/// 普通はカメラのエンティティに付ける。複数あれば最初に見つかったものを使う。
+pub struct AudioListenerComponent;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// ワールド内で音を出すエンティティ
+///
+/// [`AudioSystem`] がこのコンポーネントを見つけると鳴らし始め、
+/// エンティティが削除されるかこのコンポーネントが外されると止める。
+pub struct AudioEmitterComponent {
+    pub sound: SoundId,
+    /// 聞く位置と重なっているときの音量
+    pub volume: f32,
+    /// これより離れると聞こえない
+    pub max_distance: f32,
+    pub looping: bool,
+}
+
+impl AudioEmitterComponent {
+    pub const fn new(sound: SoundId) -> Self {
+        Self {
+            sound,
+            volume: 1.0,
+            max_distance: 1000.0,
+            looping: false,
+        }
+    }
+
+    pub const fn with_volume(self, volume: f32) -> Self {
+        Self { volume, ..self }
+    }
+
+    pub const fn with_max_distance(self, max_distance: f32) -> Self {
+        Self {
+            max_distance,
+            ..self
+        }
+    }
+
+    pub const fn looping(self) -> Self {
+        Self {
+            looping: true,
+            ..self
+        }
+    }
+
+    /// 聞く位置を`listener`としたときの音量と定位
+    ///
+    /// 音量は距離に比例して小さくなり、`max_distance` で 0 になる。
+    /// 定位は聞く向きから見た音の方向の左右成分で、真横なら ±1.0、正面や真後ろなら 0.0。
+    pub fn gain_and_pan(
+        &self,
+        listener: &TransformComponent,
+        position: &Point3<f32>,
+    ) -> (f32, f32) {
+        let offset = listener
+            .rotation
+            .inverse_transform_vector(&(position.coords - listener.translation.vector));
+        let distance = offset.norm();
+        let attenuation = if self.max_distance > 0.0 {
+            (1.0 - distance / self.max_distance).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let pan = if distance > f32::EPSILON {
+            (offset.x / distance).clamp(-1.0, 1.0)
+        } else {
+            0.0
+        };
+        (self.volume.max(0.0) * attenuation, pan)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// サンプルごとに目標の値へ線形に近づく値
+///
+/// [`AudioBackend`] の実装が音量や定位を滑らかに変えるのに使う。
+pub struct GainRamp {
+    current: f32,
+    target: f32,
+    step: f32,
+    remaining: u32,
+}
+
+impl GainRamp {
+    pub const fn new(value: f32) -> Self {
+        Self {
+            current: value,
+            target: value,
+            step: 0.0,
+            remaining: 0,
+        }
+    }
+
+    /// `ramp` の間に`target`へ変える。サンプリング周波数は`sample_rate`
+    pub fn set_target(&mut self, target: f32, ramp: Duration, sample_rate: u32) {
+        let samples = (ramp.as_secs_f32() * sample_rate as f32).round() as u32;
+        self.target = target;
+        if samples == 0 {
+            self.current = target;
+            self.remaining = 0;
+            return;
+        }
+        self.step = (target - self.current) / samples as f32;
+        self.remaining = samples;
+    }
+
+    /// 1 サンプル進めて、そのサンプルでの値を返す
+    pub fn next_sample(&mut self) -> f32 {
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            self.current = if self.remaining == 0 {
+                self.target
+            } else {
+                self.current + self.step
+            };
+        }
+        self.current
+    }
+
+    pub const fn current(&self) -> f32 {
+        self.current
+    }
+
+    pub const fn target(&self) -> f32 {
+        self.target
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EmitterState {
+    Playing(VoiceId),
+    /// 鳴らし終えたか、鳴らせなかった
+    Finished,
+}
+
+#[derive(Debug)]
+/// [`AudioEmitterComponent`] の音を鳴らし、聞く位置に合わせて音量と定位を更新する
+///
+/// [`AudioListenerComponent`] が無いときは、どの音も定位なしで [`AudioEmitterComponent::volume`] の音量で鳴らす。
+pub struct AudioSystem {
+    emitters: HashMap<hecs::Entity, EmitterState>,
+    /// 音量と定位を変えるのにかける時間
+    ramp: Duration,
+}
+
+impl Default for AudioSystem {
+    fn default() -> Self {
+        Self {
+            emitters: HashMap::new(),
+            ramp: Self::DEFAULT_RAMP,
+        }
+    }
+}
+
+impl AudioSystem {
+    /// 1 フレームより短く、ノイズが聞こえないくらいの長さ
+    pub const DEFAULT_RAMP: Duration = Duration::from_millis(10);
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_ramp(ramp: Duration) -> Self {
+        Self {
+            ramp,
+            ..Self::default()
+        }
+    }
+
+    /// 音を鳴らし始め、音量と定位を更新し、無くなった音源の音を止める
+    ///
+    /// [`System::update`] から呼ばれる。GPU に触れないのでテストからも直接呼べる。
+    pub fn apply(&mut self, world: &mut hecs::World) {
+        let Some(mut audio) = resource_mut::<Audio>(world) else {
+            return;
+        };
+        let listener = world
+            .query::<&TransformComponent>()
+            .with::<&AudioListenerComponent>()
+            .iter()
+            .next()
+            .map(|(_, transform)| transform.clone());
+
+        let mut seen = Vec::new();
+        for (entity, (emitter, transform)) in world
+            .query::<(&AudioEmitterComponent, Option<&TransformComponent>)>()
+            .iter()
+        {
+            seen.push(entity);
+            let (gain, pan) = match (&listener, transform) {
+                (Some(listener), Some(transform)) => {
+                    emitter.gain_and_pan(listener, &transform.translation.vector.into())
+                }
+                _ => (emitter.volume.max(0.0), 0.0),
+            };
+            let gain = gain * audio.sfx_volume;
+            let backend = audio.backend_mut();
+            let state = match self.emitters.get(&entity) {
+                None => backend
+                    .play(emitter.sound, emitter.looping, gain, pan)
+                    .map_or(EmitterState::Finished, EmitterState::Playing),
+                Some(&EmitterState::Playing(voice)) if backend.is_playing(voice) => {
+                    backend.set_gain_and_pan(voice, gain, pan, self.ramp);
+                    EmitterState::Playing(voice)
+                }
+                Some(_) => EmitterState::Finished,
+            };
+            self.emitters.insert(entity, state);
+        }
+
+        seen.sort_unstable();
+        self.emitters.retain(|entity, state| {
+            let alive = seen.binary_search(entity).is_ok();
+            if let (false, EmitterState::Playing(voice)) = (alive, state) {
+                audio.backend_mut().stop(*voice);
+            }
+            alive
+        });
+    }
+}
+
+impl System for AudioSystem {
+    fn setup(&mut self, _resource: Option<&RenderResource<'_>>) {}
+
+    fn update(
+        &mut self,
+        _frame: &Frame<'_>,
+        world: &mut hecs::World,
+        _resource: Option<&RenderResource<'_>>,
+    ) {
+        self.apply(world);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use nalgebra::{Translation3, UnitQuaternion, Vector3};
+
+    use super::*;
+    use crate::scene::insert_resource;
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Call {
+        Play(SoundId, f32, f32),
+        Set(VoiceId, f32, f32, Duration),
+        Stop(VoiceId),
+    }
+
+    #[derive(Default)]
+    struct RecordingBackend {
+        calls: Arc<Mutex<Vec<Call>>>,
+        next: u64,
+    }
+
+    impl AudioBackend for RecordingBackend {
+        fn play(&mut self, sound: SoundId, _looping: bool, gain: f32, pan: f32) -> Option<VoiceId> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(Call::Play(sound, gain, pan));
+            self.next += 1;
+            Some(VoiceId(self.next))
+        }
+
+        fn set_gain_and_pan(&mut self, voice: VoiceId, gain: f32, pan: f32, ramp: Duration) {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(Call::Set(voice, gain, pan, ramp));
+        }
+
+        fn stop(&mut self, voice: VoiceId) {
+            self.calls.lock().unwrap().push(Call::Stop(voice));
+        }
+
+        fn is_playing(&self, _voice: VoiceId) -> bool {
+            true
+        }
+    }
+
+    fn at(x: f32, y: f32) -> TransformComponent {
+        TransformComponent::with_translation(Translation3::new(x, y, 0.0))
+    }
+
+    #[test]
+    fn attenuates_and_pans_relative_to_listener() {
+        let emitter = AudioEmitterComponent::new(SoundId(0))
+            .with_volume(0.8)
+            .with_max_distance(100.0);
+        let right = Point3::new(50.0, 0.0, 0.0);
+        assert_eq!(emitter.gain_and_pan(&at(0.0, 0.0), &right), (0.4, 1.0));
+        assert_eq!(emitter.gain_and_pan(&at(50.0, 0.0), &right), (0.8, 0.0));
+        assert_eq!(emitter.gain_and_pan(&at(-60.0, 0.0), &right).0, 0.0);
+
+        // 後ろを向くと左右が入れ替わる
+        let turned = TransformComponent::with_translation_and_rotation(
+            Translation3::identity(),
+            UnitQuaternion::from_axis_angle(&Vector3::y_axis(), std::f32::consts::PI),
+        );
+        let (_, pan) = emitter.gain_and_pan(&turned, &right);
+        assert!((pan + 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn ramp_reaches_target_linearly() {
+        let mut ramp = GainRamp::new(1.0);
+        ramp.set_target(0.0, Duration::from_millis(1), 4000);
+        let samples: Vec<_> = (0..5).map(|_| ramp.next_sample()).collect();
+        assert_eq!(samples, [0.75, 0.5, 0.25, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn plays_updates_and_stops_despawned_emitters() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut world = hecs::World::new();
+        insert_resource(
+            &mut world,
+            Audio::new(RecordingBackend {
+                calls: Arc::clone(&calls),
+                next: 0,
+            }),
+        );
+        let listener = world.spawn((at(0.0, 0.0), AudioListenerComponent));
+        let emitter = AudioEmitterComponent::new(SoundId(3)).with_max_distance(100.0);
+        let source = world.spawn((at(-50.0, 0.0), emitter.looping()));
+        let mut system = AudioSystem::new();
+
+        system.apply(&mut world);
+        world
+            .get::<&mut TransformComponent>(listener)
+            .unwrap()
+            .translation
+            .x = -50.0;
+        resource_mut::<Audio>(&world).unwrap().set_sfx_volume(0.5);
+        system.apply(&mut world);
+        world.despawn(source).unwrap();
+        system.apply(&mut world);
+        system.apply(&mut world);
+
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                Call::Play(SoundId(3), 0.5, -1.0),
+                Call::Set(VoiceId(1), 0.5, 0.0, AudioSystem::DEFAULT_RAMP),
+                Call::Stop(VoiceId(1)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,89 @@
+//! cpal で既定の出力装置に音を出す
+use anyhow::Context;
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    FromSample, SampleFormat, SizedSample,
+};
+
+use super::Mixer;
+
+/// 既定の出力装置に [`Mixer`] の音を出す
+///
+/// これを持っている間だけ鳴る。`cpal::Stream` はスレッドをまたげないのでリソースにはせず、
+/// `main` などで持っておく。[`Self::mixer`] のクローンを [`super::Audio`] に渡し、
+/// [`Mixer::music`] をリソースにすると曲も鳴る。
+pub struct CpalOutput {
+    _stream: cpal::Stream,
+    mixer: Mixer,
+}
+
+impl std::fmt::Debug for CpalOutput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CpalOutput")
+            .field("mixer", &self.mixer)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CpalOutput {
+    /// 既定の出力装置を、その装置の既定の設定で開く
+    ///
+    /// ミキサーのサンプリング周波数は装置に合わせる。装置が 3 チャンネル以上なら残りは無音、1 チャンネルなら左右を混ぜる。
+    pub fn open_default() -> anyhow::Result<Self> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .context("no audio output device is available")?;
+        let config = device
+            .default_output_config()
+            .context("failed to query the audio output config")?;
+        let mixer = Mixer::new(config.sample_rate().0);
+        let stream_config = config.config();
+        let stream = match config.sample_format() {
+            SampleFormat::F32 => build_stream::<f32>(&device, &stream_config, mixer.clone()),
+            SampleFormat::I16 => build_stream::<i16>(&device, &stream_config, mixer.clone()),
+            SampleFormat::U16 => build_stream::<u16>(&device, &stream_config, mixer.clone()),
+            format => anyhow::bail!("unsupported audio sample format {format:?}"),
+        }?;
+        stream.play().context("failed to start the audio output")?;
+        Ok(Self {
+            _stream: stream,
+            mixer,
+        })
+    }
+
+    pub const fn mixer(&self) -> &Mixer {
+        &self.mixer
+    }
+}
+
+fn build_stream<T: SizedSample + FromSample<f32>>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    mixer: Mixer,
+) -> anyhow::Result<cpal::Stream> {
+    let channels = usize::from(config.channels.max(1));
+    let mut stereo = Vec::new();
+    device
+        .build_output_stream(
+            config,
+            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                // 大きさが変わったときだけ確保し直す
+                stereo.resize(data.len() / channels * 2, 0.0);
+                mixer.fill(&mut stereo);
+                for (out, frame) in data.chunks_exact_mut(channels).zip(stereo.chunks_exact(2)) {
+                    match out {
+                        [mono] => *mono = T::from_sample((frame[0] + frame[1]) * 0.5),
+                        [left, right, rest @ ..] => {
+                            *left = T::from_sample(frame[0]);
+                            *right = T::from_sample(frame[1]);
+                            rest.fill(T::EQUILIBRIUM);
+                        }
+                        [] => unreachable!("chunks_exact never yields empty chunks"),
+                    }
+                }
+            },
+            |error| tracing::warn!("audio output failed: {error}"),
+            None,
+        )
+        .context("failed to open the audio output stream")
+}
@@ -0,0 +1,314 @@
+//! 読み込んだ音と曲を混ぜてサンプルを作る [`AudioBackend`]
+//!
+//! 出力の装置には触れないので、出力側のコールバックから [`Mixer::fill`] を呼ぶ。
+//! `cpal` の feature を有効にすると、[`super::CpalOutput`] が既定の出力装置につなぐ。
+//! 装置の無いテストやサーバーでは、[`Mixer::fill`] を呼ぶものが無ければ音は捨てられる。
+use std::{
+    collections::BTreeMap,
+    io::{Read, Seek},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use super::{AudioBackend, GainRamp, MusicPlayer, MusicSource, OggSource, SoundId, VoiceId};
+
+/// 読み込んだ効果音
+struct Sound {
+    channels: usize,
+    /// インターリーブされたサンプル
+    samples: Arc<[f32]>,
+}
+
+impl Sound {
+    fn frames(&self) -> usize {
+        self.samples.len() / self.channels
+    }
+
+    /// `frame` フレーム目の左右のサンプル。モノラルなら左右に同じ値を入れる
+    fn stereo(&self, frame: usize) -> (f32, f32) {
+        let start = frame * self.channels;
+        match &self.samples[start..start + self.channels] {
+            [mono] => (*mono, *mono),
+            [left, right, ..] => (*left, *right),
+            [] => unreachable!("sounds have at least one channel"),
+        }
+    }
+}
+
+/// 鳴っている 1 つの音
+struct Voice {
+    sound: Arc<Sound>,
+    /// 次に鳴らすフレーム
+    position: usize,
+    looping: bool,
+    gain: GainRamp,
+    pan: GainRamp,
+}
+
+/// 定位を左右の音量の倍率にする
+///
+/// 反対側を弱めるだけなので、中央では左右とも元の音量になる。
+fn pan_gains(pan: f32) -> (f32, f32) {
+    let pan = pan.clamp(-1.0, 1.0);
+    ((1.0 - pan).min(1.0), (1.0 + pan).min(1.0))
+}
+
+struct Inner {
+    sample_rate: u32,
+    sounds: Vec<Arc<Sound>>,
+    voices: BTreeMap<u64, Voice>,
+    next_voice: u64,
+}
+
+impl Inner {
+    /// 鳴っている音を`out`に足す
+    fn mix_into(&mut self, out: &mut [f32]) {
+        self.voices.retain(|_, voice| {
+            let frames = voice.sound.frames();
+            for out in out.chunks_exact_mut(2) {
+                if voice.position >= frames {
+                    if !voice.looping {
+                        return false;
+                    }
+                    voice.position = 0;
+                }
+                let (left, right) = voice.sound.stereo(voice.position);
+                let gain = voice.gain.next_sample();
+                let (left_gain, right_gain) = pan_gains(voice.pan.next_sample());
+                out[0] += left * gain * left_gain;
+                out[1] += right * gain * right_gain;
+                voice.position += 1;
+            }
+            voice.looping || voice.position < frames
+        });
+    }
+}
+
+#[derive(Clone)]
+/// 効果音と曲を混ぜる [`AudioBackend`]
+///
+/// 出力はステレオ。効果音は読み込むときに全部デコードしておく。
+/// 長い曲は [`Self::music`] の [`MusicPlayer`] で鳴らすと、効果音と一緒に混ぜる。
+/// 実体は [`Arc`] なので、クローンを [`super::Audio`] に渡し、もう 1 つを出力側のスレッドで [`Self::fill`] に使う。
+pub struct Mixer {
+    inner: Arc<Mutex<Inner>>,
+    music: MusicPlayer,
+}
+
+impl std::fmt::Debug for Mixer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let inner = self.inner.lock().unwrap();
+        f.debug_struct("Mixer")
+            .field("sample_rate", &inner.sample_rate)
+            .field("sounds", &inner.sounds.len())
+            .field("voices", &inner.voices.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Mixer {
+    /// サンプリング周波数が`sample_rate`の出力に鳴らす
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                sample_rate,
+                sounds: Vec::new(),
+                voices: BTreeMap::new(),
+                next_voice: 0,
+            })),
+            music: MusicPlayer::new(sample_rate),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.inner.lock().unwrap().sample_rate
+    }
+
+    /// このミキサーが混ぜる曲のプレイヤー
+    ///
+    /// 同じものを共有するクローンを返す。[`crate::scene::Scene::insert_resource`] でリソースにする。
+    pub fn music(&self) -> MusicPlayer {
+        self.music.clone()
+    }
+
+    /// インターリーブされたサンプルを効果音として読み込む
+    ///
+    /// 3 チャンネル以上あれば最初の 2 つを左右に使う。
+    /// 変換はしないので、サンプリング周波数は出力と同じでなければならない。
+    pub fn add_sound(
+        &self,
+        samples: Vec<f32>,
+        channels: u16,
+        sample_rate: u32,
+    ) -> anyhow::Result<SoundId> {
+        let channels = usize::from(channels);
+        if channels == 0 || samples.len() % channels != 0 {
+            anyhow::bail!(
+                "{} samples cannot be split into {channels} channels",
+                samples.len()
+            );
+        }
+        let mut inner = self.inner.lock().unwrap();
+        if sample_rate != inner.sample_rate {
+            anyhow::bail!(
+                "sample rate of the sound ({sample_rate} Hz) does not match the output ({} Hz)",
+                inner.sample_rate
+            );
+        }
+        inner.sounds.push(Arc::new(Sound {
+            channels,
+            samples: samples.into(),
+        }));
+        Ok(SoundId(inner.sounds.len() as u32 - 1))
+    }
+
+    /// Ogg Vorbis を最後までデコードして効果音として読み込む
+    pub fn load_ogg(&self, reader: impl Read + Seek + Send) -> anyhow::Result<SoundId> {
+        let mut source = OggSource::new(reader)?;
+        let channels = usize::from(source.channels());
+        let mut samples = Vec::new();
+        let mut buffer = vec![0.0; 4096 * channels];
+        loop {
+            let frames = source.read(&mut buffer)?;
+            if frames == 0 {
+                break;
+            }
+            samples.extend_from_slice(&buffer[..frames * channels]);
+        }
+        self.add_sound(samples, source.channels(), source.sample_rate())
+    }
+
+    /// Ogg Vorbis のファイルを効果音として読み込む
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_ogg_file(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<SoundId> {
+        use anyhow::Context;
+
+        let path = path.as_ref();
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("failed to open sound file {}", path.display()))?;
+        self.load_ogg(std::io::BufReader::new(file))
+            .with_context(|| format!("failed to read sound file {}", path.display()))
+    }
+
+    /// 鳴っている音の数
+    pub fn voice_count(&self) -> usize {
+        self.inner.lock().unwrap().voices.len()
+    }
+
+    /// 次のステレオのサンプルを`out`に書き込む
+    ///
+    /// 出力側のコールバックから呼ぶ。`out` は左右のサンプルが交互に並ぶ。
+    /// 曲のデコードの間に効果音を鳴らす操作を待たせないように、曲を書いてから効果音のロックを取る。
+    pub fn fill(&self, out: &mut [f32]) {
+        self.music.fill(out);
+        self.inner.lock().unwrap().mix_into(out);
+    }
+}
+
+impl AudioBackend for Mixer {
+    fn play(&mut self, sound: SoundId, looping: bool, gain: f32, pan: f32) -> Option<VoiceId> {
+        let mut inner = self.inner.lock().unwrap();
+        let sound = Arc::clone(inner.sounds.get(sound.0 as usize)?);
+        if sound.frames() == 0 {
+            return None;
+        }
+        let id = inner.next_voice;
+        inner.next_voice += 1;
+        inner.voices.insert(
+            id,
+            Voice {
+                sound,
+                position: 0,
+                looping,
+                gain: GainRamp::new(gain.max(0.0)),
+                pan: GainRamp::new(pan.clamp(-1.0, 1.0)),
+            },
+        );
+        drop(inner);
+        Some(VoiceId(id))
+    }
+
+    fn set_gain_and_pan(&mut self, voice: VoiceId, gain: f32, pan: f32, ramp: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        let rate = inner.sample_rate;
+        if let Some(voice) = inner.voices.get_mut(&voice.0) {
+            voice.gain.set_target(gain.max(0.0), ramp, rate);
+            voice.pan.set_target(pan.clamp(-1.0, 1.0), ramp, rate);
+        }
+    }
+
+    fn stop(&mut self, voice: VoiceId) {
+        self.inner.lock().unwrap().voices.remove(&voice.0);
+    }
+
+    fn is_playing(&self, voice: VoiceId) -> bool {
+        self.inner.lock().unwrap().voices.contains_key(&voice.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TONE: &[u8] = include_bytes!("../../tests/audio/tone.ogg");
+
+    fn left(out: &[f32]) -> Vec<f32> {
+        out.chunks_exact(2).map(|frame| frame[0]).collect()
+    }
+
+    fn right(out: &[f32]) -> Vec<f32> {
+        out.chunks_exact(2).map(|frame| frame[1]).collect()
+    }
+
+    #[test]
+    fn voices_are_mixed_and_finish() {
+        let mut mixer = Mixer::new(1000);
+        let click = mixer.add_sound(vec![1.0, 2.0, 3.0], 1, 1000).unwrap();
+        let hum = mixer.add_sound(vec![10.0, 20.0], 2, 1000).unwrap();
+
+        let once = mixer.play(click, false, 1.0, 0.0).unwrap();
+        let looped = mixer.play(hum, true, 0.5, 1.0).unwrap();
+        let mut out = [0.0; 10];
+        mixer.fill(&mut out);
+        assert_eq!(left(&out), [1.0, 2.0, 3.0, 0.0, 0.0]);
+        // 右に寄せたので左は聞こえない
+        assert_eq!(right(&out), [11.0, 12.0, 13.0, 10.0, 10.0]);
+
+        assert!(!mixer.is_playing(once));
+        assert!(mixer.is_playing(looped));
+        mixer.stop(looped);
+        assert_eq!(mixer.voice_count(), 0);
+        mixer.fill(&mut out);
+        assert_eq!(out, [0.0; 10]);
+    }
+
+    #[test]
+    fn gain_and_pan_follow_the_ramp() {
+        let mut mixer = Mixer::new(1000);
+        let dc = mixer.add_sound(vec![1.0], 1, 1000).unwrap();
+        let voice = mixer.play(dc, true, 1.0, 0.0).unwrap();
+        mixer.set_gain_and_pan(voice, 0.0, -1.0, Duration::from_millis(4));
+        let mut out = [0.0; 10];
+        mixer.fill(&mut out);
+        // 4 ms かけて 0 になり、その間に右から小さくなっていく
+        assert_eq!(left(&out), [0.75, 0.5, 0.25, 0.0, 0.0]);
+        assert_eq!(right(&out), [0.75 * 0.75, 0.5 * 0.5, 0.25 * 0.25, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn rejects_mismatched_sounds() {
+        let mut mixer = Mixer::new(8000);
+        assert!(mixer.add_sound(vec![0.0; 3], 2, 8000).is_err());
+        assert!(mixer.add_sound(vec![0.0; 4], 2, 44100).is_err());
+        assert_eq!(mixer.play(SoundId(5), false, 1.0, 0.0), None);
+        let empty = mixer.add_sound(Vec::new(), 1, 8000).unwrap();
+        assert_eq!(mixer.play(empty, true, 1.0, 0.0), None);
+
+        let tone = mixer.load_ogg(std::io::Cursor::new(TONE)).unwrap();
+        let voice = mixer.play(tone, false, 1.0, 0.0).unwrap();
+        let mut out = vec![0.0; 2 * 8000];
+        mixer.fill(&mut out);
+        assert!(out.iter().any(|&sample| sample.abs() > 0.1));
+        assert!(mixer.is_playing(voice));
+    }
+}
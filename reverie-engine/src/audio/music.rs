@@ -0,0 +1,680 @@
+//! 音楽の再生
+//!
+//! 効果音と違い、長い曲はメモリに全部読み込まずに少しずつデコードしながら鳴らす。
+//! [`MusicPlayer`] はサンプルを作るだけなので、出力側のコールバックから [`MusicPlayer::fill`] を呼ぶ。
+//! [`super::Mixer::music`] のプレイヤーなら、[`super::Mixer::fill`] が効果音と一緒に呼ぶ。
+use std::{
+    io::{Read, Seek, SeekFrom},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Context;
+use lewton::{inside_ogg::OggStreamReader, samples::InterleavedSamples};
+
+use super::GainRamp;
+
+/// 少しずつデコードしながら読む曲のデータ
+pub trait MusicSource: Send {
+    fn channels(&self) -> u16;
+
+    fn sample_rate(&self) -> u32;
+
+    /// インターリーブされたサンプルを`out`に書き込み、書き込んだフレーム数を返す。0 なら曲の終わり
+    ///
+    /// `out` の長さはチャンネル数の倍数にする。
+    fn read(&mut self, out: &mut [f32]) -> anyhow::Result<usize>;
+
+    /// 次に読む位置を先頭から`frame`フレーム目にする
+    fn seek(&mut self, frame: u64) -> anyhow::Result<()>;
+}
+
+/// Ogg Vorbis の曲
+pub struct OggSource<R: Read + Seek> {
+    reader: Option<OggStreamReader<R>>,
+    channels: u16,
+    sample_rate: u32,
+    /// デコードしたがまだ読まれていないサンプル
+    pending: Vec<f32>,
+    pending_offset: usize,
+    /// 次に読むフレームの位置
+    position: u64,
+}
+
+impl<R: Read + Seek> std::fmt::Debug for OggSource<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OggSource")
+            .field("channels", &self.channels)
+            .field("sample_rate", &self.sample_rate)
+            .field("position", &self.position)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl OggSource<std::io::BufReader<std::fs::File>> {
+    pub fn open(path: impl AsRef<std::path::Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("failed to open music file {}", path.display()))?;
+        Self::new(std::io::BufReader::new(file))
+            .with_context(|| format!("failed to read music file {}", path.display()))
+    }
+}
+
+impl<R: Read + Seek> OggSource<R> {
+    pub fn new(reader: R) -> anyhow::Result<Self> {
+        let reader = OggStreamReader::new(reader).context("failed to read Ogg Vorbis headers")?;
+        Ok(Self {
+            channels: u16::from(reader.ident_hdr.audio_channels),
+            sample_rate: reader.ident_hdr.audio_sample_rate,
+            reader: Some(reader),
+            pending: Vec::new(),
+            pending_offset: 0,
+            position: 0,
+        })
+    }
+
+    fn reader(&mut self) -> &mut OggStreamReader<R> {
+        self.reader
+            .as_mut()
+            .expect("the reader is only taken while rewinding")
+    }
+
+    /// パケットを 1 つデコードする。曲の終わりなら `false`
+    fn decode_packet(&mut self) -> anyhow::Result<bool> {
+        let packet = self
+            .reader()
+            .read_dec_packet_generic::<InterleavedSamples<f32>>()
+            .context("failed to decode Ogg Vorbis packet")?;
+        self.pending_offset = 0;
+        match packet {
+            Some(packet) => {
+                self.pending = packet.samples;
+                Ok(true)
+            }
+            None => {
+                self.pending.clear();
+                Ok(false)
+            }
+        }
+    }
+
+    fn skip(&mut self, mut frames: u64) -> anyhow::Result<()> {
+        let channels = usize::from(self.channels);
+        while frames > 0 {
+            if self.pending_offset >= self.pending.len() && !self.decode_packet()? {
+                break;
+            }
+            let available = (self.pending.len() - self.pending_offset) / channels;
+            let n = available.min(usize::try_from(frames).unwrap_or(usize::MAX));
+            self.pending_offset += n * channels;
+            self.position += n as u64;
+            frames -= n as u64;
+        }
+        Ok(())
+    }
+
+    /// `frame` より前に戻る
+    ///
+    /// まず 1 秒手前のページに移り、位置が分かるまで読み捨てる。
+    /// ページの区切りが`frame`を越えてしまったら先頭から読み直す。
+    fn rewind(&mut self, frame: u64) -> anyhow::Result<()> {
+        self.pending.clear();
+        self.pending_offset = 0;
+        let coarse = frame.saturating_sub(u64::from(self.sample_rate));
+        if coarse > 0 {
+            self.reader()
+                .seek_absgp_pg(coarse)
+                .context("failed to seek in Ogg Vorbis stream")?;
+            while self.decode_packet()? {
+                let Some(position) = self.reader().get_last_absgp() else {
+                    continue;
+                };
+                if position <= frame {
+                    self.pending.clear();
+                    self.position = position;
+                    return Ok(());
+                }
+                break;
+            }
+        }
+
+        let mut inner = self
+            .reader
+            .take()
+            .expect("the reader is only taken while rewinding")
+            .into_inner()
+            .into_inner();
+        inner.seek(SeekFrom::Start(0))?;
+        self.reader = Some(OggStreamReader::new(inner).context("failed to reopen Ogg Vorbis")?);
+        self.pending.clear();
+        self.position = 0;
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek + Send> MusicSource for OggSource<R> {
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn read(&mut self, out: &mut [f32]) -> anyhow::Result<usize> {
+        let mut written = 0;
+        while written < out.len() {
+            if self.pending_offset >= self.pending.len() && !self.decode_packet()? {
+                break;
+            }
+            let n = (self.pending.len() - self.pending_offset).min(out.len() - written);
+            out[written..written + n]
+                .copy_from_slice(&self.pending[self.pending_offset..self.pending_offset + n]);
+            self.pending_offset += n;
+            written += n;
+        }
+        let frames = written / usize::from(self.channels);
+        self.position += frames as u64;
+        Ok(frames)
+    }
+
+    fn seek(&mut self, frame: u64) -> anyhow::Result<()> {
+        if frame < self.position {
+            self.rewind(frame)?;
+        }
+        self.skip(frame - self.position)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// 曲の中の位置
+pub enum LoopPosition {
+    /// 先頭からのサンプル数 (1 チャンネルあたり)
+    Samples(u64),
+    Seconds(f64),
+}
+
+impl LoopPosition {
+    fn to_frames(self, sample_rate: u32) -> u64 {
+        match self {
+            Self::Samples(samples) => samples,
+            Self::Seconds(seconds) => (seconds.max(0.0) * f64::from(sample_rate)).round() as u64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// 曲を繰り返す範囲
+///
+/// `start` より前はイントロとして最初の 1 回だけ鳴り、その後は `start` から `end` までを繰り返す。
+pub struct LoopPoints {
+    pub start: LoopPosition,
+    /// `None` なら曲の終わり
+    pub end: Option<LoopPosition>,
+}
+
+impl LoopPoints {
+    /// 曲全体を繰り返す
+    pub const WHOLE: Self = Self {
+        start: LoopPosition::Samples(0),
+        end: None,
+    };
+
+    pub const fn new(start: LoopPosition, end: Option<LoopPosition>) -> Self {
+        Self { start, end }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// [`MusicPlayer`] に登録した曲
+pub struct MusicId(pub u32);
+
+type Opener = Box<dyn Fn() -> anyhow::Result<Box<dyn MusicSource>> + Send + Sync>;
+
+struct Entry {
+    open: Opener,
+    loop_points: Option<LoopPoints>,
+}
+
+/// 鳴っている曲
+struct Track {
+    music: MusicId,
+    source: Box<dyn MusicSource>,
+    /// ループの範囲 (フレーム)。`None` なら 1 度だけ鳴らす
+    loop_range: Option<(u64, Option<u64>)>,
+    position: u64,
+    gain: GainRamp,
+}
+
+impl Track {
+    /// ステレオのサンプルを`out`に足す。曲が終わったら `false`
+    fn mix_into(&mut self, out: &mut [f32], scratch: &mut Vec<f32>) -> anyhow::Result<bool> {
+        let channels = usize::from(self.source.channels().max(1));
+        let mut frame = 0;
+        let frames = out.len() / 2;
+        // ループの範囲が空のときに同じ位置を読み続けないように、巻き戻しは 1 回の呼び出しで 1 度まで
+        let mut rewound = false;
+        while frame < frames {
+            let limit = match self.loop_range {
+                Some((_, Some(end))) => end.saturating_sub(self.position),
+                _ => u64::MAX,
+            };
+            let wanted = (frames - frame).min(usize::try_from(limit).unwrap_or(usize::MAX));
+            let read = if wanted == 0 {
+                0
+            } else {
+                scratch.resize(wanted * channels, 0.0);
+                self.source.read(scratch)?
+            };
+            if read == 0 {
+                match self.loop_range {
+                    Some((start, _)) if !rewound => {
+                        self.source.seek(start)?;
+                        self.position = start;
+                        rewound = true;
+                        continue;
+                    }
+                    _ => return Ok(false),
+                }
+            }
+            rewound = false;
+            for input in scratch[..read * channels].chunks_exact(channels) {
+                let gain = self.gain.next_sample();
+                let (left, right) = match input {
+                    [mono] => (*mono, *mono),
+                    [left, right, ..] => (*left, *right),
+                    [] => unreachable!("chunks_exact never yields empty chunks"),
+                };
+                out[frame * 2] += left * gain;
+                out[frame * 2 + 1] += right * gain;
+                frame += 1;
+            }
+            self.position += read as u64;
+        }
+        Ok(true)
+    }
+
+    fn is_faded_out(&self) -> bool {
+        self.gain.target() == 0.0 && self.gain.current() == 0.0
+    }
+}
+
+struct Inner {
+    sample_rate: u32,
+    library: Vec<Arc<Entry>>,
+    current: Option<Track>,
+    /// フェードアウト中の曲
+    previous: Option<Track>,
+    volume: GainRamp,
+    pause: GainRamp,
+    paused: bool,
+    scratch: Vec<f32>,
+    mix: Vec<f32>,
+}
+
+impl Track {
+    fn open(
+        music: MusicId,
+        entry: &Entry,
+        sample_rate: u32,
+        fade_in: Duration,
+    ) -> anyhow::Result<Self> {
+        let source = (entry.open)()?;
+        if source.sample_rate() != sample_rate {
+            anyhow::bail!(
+                "sample rate of the music ({} Hz) does not match the output ({} Hz)",
+                source.sample_rate(),
+                sample_rate
+            );
+        }
+        let loop_range = entry.loop_points.map(|points| {
+            (
+                points.start.to_frames(sample_rate),
+                points.end.map(|end| end.to_frames(sample_rate)),
+            )
+        });
+        let mut gain = GainRamp::new(0.0);
+        gain.set_target(1.0, fade_in, sample_rate);
+        Ok(Self {
+            music,
+            source,
+            loop_range,
+            position: 0,
+            gain,
+        })
+    }
+}
+
+impl Inner {
+    fn fade_out_current(&mut self, fade_out: Duration) {
+        if let Some(mut track) = self.current.take() {
+            track.gain.set_target(0.0, fade_out, self.sample_rate);
+            self.previous = Some(track);
+        }
+    }
+
+    fn start(&mut self, track: Track, fade_out: Duration) {
+        self.fade_out_current(fade_out);
+        self.current = Some(track);
+    }
+
+    fn fill(&mut self, out: &mut [f32]) {
+        out.fill(0.0);
+        if self.paused && self.pause.current() == 0.0 {
+            return;
+        }
+
+        self.mix.clear();
+        self.mix.resize(out.len(), 0.0);
+        for slot in [&mut self.current, &mut self.previous] {
+            let Some(track) = slot else {
+                continue;
+            };
+            let playing = track
+                .mix_into(&mut self.mix, &mut self.scratch)
+                .unwrap_or_else(|err| {
+                    tracing::warn!(music = ?track.music, "failed to decode music: {err:?}");
+                    false
+                });
+            if !playing || track.is_faded_out() {
+                *slot = None;
+            }
+        }
+
+        for (out, mix) in out.chunks_exact_mut(2).zip(self.mix.chunks_exact(2)) {
+            let gain = self.volume.next_sample() * self.pause.next_sample();
+            out[0] = mix[0] * gain;
+            out[1] = mix[1] * gain;
+        }
+    }
+}
+
+#[derive(Clone)]
+/// 曲を鳴らすリソース
+///
+/// 出力はステレオ。効果音の [`super::Audio`] とは別に音量を変えられる。
+/// 実体は [`Arc`] なので、クローンを出力側のスレッドに渡して [`Self::fill`] を呼ばせる。
+pub struct MusicPlayer {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl std::fmt::Debug for MusicPlayer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let inner = self.inner.lock().unwrap();
+        f.debug_struct("MusicPlayer")
+            .field("sample_rate", &inner.sample_rate)
+            .field("current", &inner.current.as_ref().map(|t| t.music))
+            .field("paused", &inner.paused)
+            .finish_non_exhaustive()
+    }
+}
+
+impl MusicPlayer {
+    /// 一時停止や音量の変更でノイズが出ないように、これだけの時間をかける
+    pub const DECLICK: Duration = Duration::from_millis(10);
+
+    /// サンプリング周波数が`sample_rate`の出力に鳴らす
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                sample_rate,
+                library: Vec::new(),
+                current: None,
+                previous: None,
+                volume: GainRamp::new(1.0),
+                pause: GainRamp::new(1.0),
+                paused: false,
+                scratch: Vec::new(),
+                mix: Vec::new(),
+            })),
+        }
+    }
+
+    /// 曲を登録する
+    ///
+    /// `open` は曲を鳴らすたびに呼ばれ、先頭から読む [`MusicSource`] を返す。
+    /// `loop_points` が `None` なら 1 度だけ鳴らす。
+    pub fn register(
+        &self,
+        open: impl Fn() -> anyhow::Result<Box<dyn MusicSource>> + Send + Sync + 'static,
+        loop_points: Option<LoopPoints>,
+    ) -> MusicId {
+        let mut inner = self.inner.lock().unwrap();
+        inner.library.push(Arc::new(Entry {
+            open: Box::new(open),
+            loop_points,
+        }));
+        MusicId(inner.library.len() as u32 - 1)
+    }
+
+    /// Ogg Vorbis のファイルを曲として登録する。ファイルは鳴らすときに開く
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn register_file(
+        &self,
+        path: impl Into<std::path::PathBuf>,
+        loop_points: Option<LoopPoints>,
+    ) -> MusicId {
+        let path = path.into();
+        self.register(
+            move || Ok(Box::new(OggSource::open(&path)?) as Box<dyn MusicSource>),
+            loop_points,
+        )
+    }
+
+    /// 曲のファイルを開く
+    ///
+    /// 開いている間に出力側を待たせないように、ロックを外してから開く。
+    fn open(&self, music: MusicId, fade_in: Duration) -> anyhow::Result<Track> {
+        let (entry, sample_rate) = {
+            let inner = self.inner.lock().unwrap();
+            let entry = inner.library.get(music.0 as usize).cloned();
+            (entry, inner.sample_rate)
+        };
+        let entry = entry.with_context(|| format!("music {music:?} is not registered"))?;
+        Track::open(music, &entry, sample_rate, fade_in)
+    }
+
+    /// 曲を最初から鳴らす。`fade_in` の時間をかけて音量を上げる
+    ///
+    /// 鳴っていた曲はすぐに止める。
+    pub fn play(&self, music: MusicId, fade_in: Duration) -> anyhow::Result<()> {
+        let track = self.open(music, fade_in)?;
+        self.inner.lock().unwrap().start(track, Self::DECLICK);
+        Ok(())
+    }
+
+    /// 鳴っている曲を`duration`の時間をかけて小さくしながら、次の曲を大きくしていく
+    pub fn crossfade_to(&self, music: MusicId, duration: Duration) -> anyhow::Result<()> {
+        let track = self.open(music, duration)?;
+        self.inner.lock().unwrap().start(track, duration);
+        Ok(())
+    }
+
+    /// `fade_out` の時間をかけて曲を止める
+    pub fn stop(&self, fade_out: Duration) {
+        self.inner.lock().unwrap().fade_out_current(fade_out);
+    }
+
+    pub fn pause(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        let rate = inner.sample_rate;
+        inner.paused = true;
+        inner.pause.set_target(0.0, Self::DECLICK, rate);
+    }
+
+    pub fn resume(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        let rate = inner.sample_rate;
+        inner.paused = false;
+        inner.pause.set_target(1.0, Self::DECLICK, rate);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.inner.lock().unwrap().paused
+    }
+
+    /// 音楽の音量を変える。効果音の音量には影響しない
+    pub fn set_volume(&self, volume: f32) {
+        let mut inner = self.inner.lock().unwrap();
+        let rate = inner.sample_rate;
+        inner
+            .volume
+            .set_target(volume.max(0.0), Self::DECLICK, rate);
+    }
+
+    pub fn volume(&self) -> f32 {
+        self.inner.lock().unwrap().volume.target()
+    }
+
+    /// 鳴っている曲。フェードアウト中の曲は含まない
+    pub fn current(&self) -> Option<MusicId> {
+        self.inner.lock().unwrap().current.as_ref().map(|t| t.music)
+    }
+
+    /// 次のステレオのサンプルを`out`に書き込む
+    ///
+    /// 出力側のコールバックから呼ぶ。`out` は左右のサンプルが交互に並ぶ。
+    /// デコードに失敗した曲はログを出して止める。
+    pub fn fill(&self, out: &mut [f32]) {
+        self.inner.lock().unwrap().fill(out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TONE: &[u8] = include_bytes!("../../tests/audio/tone.ogg");
+
+    /// フレーム番号をそのままサンプルの値にしたモノラルの曲
+    struct Ramp {
+        len: u64,
+        position: u64,
+    }
+
+    impl MusicSource for Ramp {
+        fn channels(&self) -> u16 {
+            1
+        }
+
+        fn sample_rate(&self) -> u32 {
+            1000
+        }
+
+        fn read(&mut self, out: &mut [f32]) -> anyhow::Result<usize> {
+            let n = out.len().min((self.len - self.position) as usize);
+            for (i, sample) in out[..n].iter_mut().enumerate() {
+                *sample = (self.position + i as u64) as f32;
+            }
+            self.position += n as u64;
+            Ok(n)
+        }
+
+        fn seek(&mut self, frame: u64) -> anyhow::Result<()> {
+            self.position = frame.min(self.len);
+            Ok(())
+        }
+    }
+
+    fn ramp(len: u64) -> impl Fn() -> anyhow::Result<Box<dyn MusicSource>> + Send {
+        move || Ok(Box::new(Ramp { len, position: 0 }) as Box<dyn MusicSource>)
+    }
+
+    fn left(out: &[f32]) -> Vec<f32> {
+        out.chunks_exact(2).map(|frame| frame[0]).collect()
+    }
+
+    #[test]
+    fn plays_intro_then_loops() {
+        let player = MusicPlayer::new(1000);
+        let music = player.register(
+            ramp(10),
+            Some(LoopPoints::new(
+                LoopPosition::Samples(4),
+                Some(LoopPosition::Seconds(0.007)),
+            )),
+        );
+        player.play(music, Duration::ZERO).unwrap();
+        let mut out = [0.0; 24];
+        player.fill(&mut out);
+        assert_eq!(
+            left(&out),
+            [0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 4.0, 5.0, 6.0, 4.0, 5.0]
+        );
+        // 左右に同じ値が入る
+        assert_eq!(out[2], out[3]);
+    }
+
+    #[test]
+    fn crossfades_and_stops_at_the_end() {
+        let player = MusicPlayer::new(1000);
+        let field = player.register(ramp(100), None);
+        let battle = player.register(ramp(2), None);
+        player.play(field, Duration::ZERO).unwrap();
+        player.fill(&mut [0.0; 20]);
+
+        player
+            .crossfade_to(battle, Duration::from_millis(2))
+            .unwrap();
+        assert_eq!(player.current(), Some(battle));
+        let mut out = [0.0; 8];
+        player.fill(&mut out);
+        // field: 10, 11 が 1/2, 0 倍、battle: 0, 1 が 1/2, 1 倍
+        assert_eq!(left(&out), [5.0, 1.0, 0.0, 0.0]);
+
+        player.fill(&mut out);
+        assert_eq!(left(&out), [0.0; 4]);
+        assert_eq!(player.current(), None);
+        assert!(player.play(MusicId(9), Duration::ZERO).is_err());
+    }
+
+    #[test]
+    fn pause_and_volume_are_smoothed() {
+        let player = MusicPlayer::new(1000);
+        let music = player.register(ramp(1000), Some(LoopPoints::WHOLE));
+        player.play(music, Duration::ZERO).unwrap();
+        player.set_volume(0.5);
+        let mut out = [0.0; 40];
+        player.fill(&mut out);
+        // 10 ms かけて 0.5 倍になる
+        assert!((out[8] - 3.0).abs() < 1e-5);
+        assert_eq!(out[38], 19.0 * 0.5);
+
+        player.pause();
+        player.fill(&mut out);
+        assert_eq!(out[38], 0.0);
+        player.fill(&mut out);
+        assert_eq!(out, [0.0; 40]);
+        // 一時停止中は進まない
+        player.resume();
+        player.fill(&mut out);
+        assert!((out[0] - 2.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn ogg_source_streams_and_seeks() {
+        let mut whole = OggSource::new(std::io::Cursor::new(TONE)).unwrap();
+        assert_eq!((whole.channels(), whole.sample_rate()), (2, 8000));
+        let mut decoded = Vec::new();
+        let mut buffer = [0.0; 512];
+        loop {
+            let frames = whole.read(&mut buffer).unwrap();
+            if frames == 0 {
+                break;
+            }
+            decoded.extend_from_slice(&buffer[..frames * 2]);
+        }
+        assert_eq!(decoded.len(), 3 * 8000 * 2);
+
+        let mut source = OggSource::new(std::io::Cursor::new(TONE)).unwrap();
+        for frame in [20_000, 3_000, 12_345] {
+            source.seek(frame).unwrap();
+            let mut out = [0.0; 64];
+            assert_eq!(source.read(&mut out).unwrap(), 32);
+            let start = frame as usize * 2;
+            assert_eq!(out[..], decoded[start..start + 64]);
+        }
+    }
+}
@@ -0,0 +1,192 @@
+//! ワールドの範囲と、範囲外に出たエンティティの後始末
+use nalgebra::Point2;
+
+use crate::scene::{
+    clear_events, for_each_ordered, resource, send_event, EntityIndex, Frame, RenderResource,
+    System, TransformComponent,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// ワールドの範囲 (XY 平面上の AABB)
+///
+/// リソースとして [`crate::scene::Scene::insert_resource`] で追加する。
+/// 無ければ [`WorldBoundsSystem`] は何もしない。
+pub struct WorldBounds {
+    pub min: Point2<f32>,
+    pub max: Point2<f32>,
+    /// 範囲の外側にこれだけ余裕を持たせてから範囲外とみなす
+    ///
+    /// スプライトが画面の端から完全に見えなくなるまで待つのに使う。
+    pub margin: f32,
+}
+
+impl WorldBounds {
+    pub const fn new(min: Point2<f32>, max: Point2<f32>) -> Self {
+        Self {
+            min,
+            max,
+            margin: 0.0,
+        }
+    }
+
+    pub const fn with_margin(self, margin: f32) -> Self {
+        Self { margin, ..self }
+    }
+
+    /// `point` が余裕も含めた範囲の中にあるか
+    pub fn contains(&self, point: &Point2<f32>) -> bool {
+        point.x >= self.min.x - self.margin
+            && point.x <= self.max.x + self.margin
+            && point.y >= self.min.y - self.margin
+            && point.y <= self.max.y + self.margin
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// 範囲外に出たときにすること
+pub enum OutOfBoundsAction {
+    /// エンティティを削除する
+    #[default]
+    Despawn,
+    /// [`OutOfBounds`] イベントを 1 度だけ送る。エンティティは残り、このコンポーネントは外される
+    Notify,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// [`WorldBounds`] の外に出たかどうかを調べる対象の印
+///
+/// 弾のように画面外へ飛んでいくエンティティに付ける。
+pub struct OutOfBoundsComponent {
+    pub action: OutOfBoundsAction,
+}
+
+impl OutOfBoundsComponent {
+    pub const fn despawn() -> Self {
+        Self {
+            action: OutOfBoundsAction::Despawn,
+        }
+    }
+
+    pub const fn notify() -> Self {
+        Self {
+            action: OutOfBoundsAction::Notify,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// [`OutOfBoundsAction::Notify`] のエンティティが範囲外に出た
+///
+/// [`crate::scene::Events`] として送られる。
+pub struct OutOfBounds(pub EntityIndex);
+
+#[derive(Default)]
+/// [`OutOfBoundsComponent`] を持つエンティティが [`WorldBounds`] の外に出たら削除するか通知する
+///
+/// 調べるのは [`OutOfBoundsComponent`] を持つエンティティだけなので、他のエンティティの数には影響されない。
+/// 削除はクエリの後にまとめて行う。[`crate::scene::Determinism`] があれば、作られた順に処理する。
+pub struct WorldBoundsSystem {
+    commands: hecs::CommandBuffer,
+}
+
+impl std::fmt::Debug for WorldBoundsSystem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WorldBoundsSystem").finish_non_exhaustive()
+    }
+}
+
+impl WorldBoundsSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 範囲外に出たエンティティを処理する
+    ///
+    /// [`System::update`] から呼ばれる。GPU に触れないのでテストからも直接呼べる。
+    pub fn apply(&mut self, world: &mut hecs::World) {
+        clear_events::<OutOfBounds>(world);
+        let Some(bounds) = resource::<WorldBounds>(world).map(|b| *b) else {
+            return;
+        };
+
+        let mut notified = Vec::new();
+        for_each_ordered::<(&TransformComponent, &OutOfBoundsComponent)>(
+            world,
+            |entity, (transform, target)| {
+                let position = transform.translation.vector.xy().into();
+                if bounds.contains(&position) {
+                    return;
+                }
+                match target.action {
+                    OutOfBoundsAction::Despawn => self.commands.despawn(entity),
+                    OutOfBoundsAction::Notify => {
+                        self.commands.remove_one::<OutOfBoundsComponent>(entity);
+                        notified.push(entity);
+                    }
+                }
+            },
+        );
+        self.commands.run_on(world);
+        for entity in notified {
+            send_event(world, OutOfBounds(EntityIndex(entity)));
+        }
+    }
+}
+
+impl System for WorldBoundsSystem {
+    fn setup(&mut self, _resource: Option<&RenderResource<'_>>) {}
+
+    fn update(
+        &mut self,
+        _frame: &Frame<'_>,
+        world: &mut hecs::World,
+        _resource: Option<&RenderResource<'_>>,
+    ) {
+        self.apply(world);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Translation3;
+
+    use super::*;
+    use crate::scene::{insert_resource, Events};
+
+    fn at(x: f32, y: f32) -> TransformComponent {
+        TransformComponent::with_translation(Translation3::new(x, y, 0.0))
+    }
+
+    #[test]
+    fn despawns_or_notifies_outside_bounds() {
+        let mut world = hecs::World::new();
+        insert_resource(
+            &mut world,
+            WorldBounds::new(Point2::new(0.0, 0.0), Point2::new(100.0, 100.0)).with_margin(10.0),
+        );
+        let inside = world.spawn((at(50.0, 50.0), OutOfBoundsComponent::despawn()));
+        let in_margin = world.spawn((at(105.0, 50.0), OutOfBoundsComponent::despawn()));
+        let outside = world.spawn((at(120.0, 50.0), OutOfBoundsComponent::despawn()));
+        let untracked = world.spawn((at(-500.0, 0.0),));
+        let notify = world.spawn((at(50.0, -20.0), OutOfBoundsComponent::notify()));
+
+        let mut system = WorldBoundsSystem::new();
+        system.apply(&mut world);
+
+        assert!(world.contains(inside));
+        assert!(world.contains(in_margin));
+        assert!(!world.contains(outside));
+        assert!(world.contains(untracked));
+        assert!(world.contains(notify));
+        let events: Vec<_> = resource::<Events<OutOfBounds>>(&world)
+            .unwrap()
+            .iter()
+            .copied()
+            .collect();
+        assert_eq!(events, [OutOfBounds(EntityIndex(notify))]);
+
+        // 通知は 1 度だけ
+        system.apply(&mut world);
+        assert!(resource::<Events<OutOfBounds>>(&world).unwrap().is_empty());
+    }
+}
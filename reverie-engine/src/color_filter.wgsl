@@ -0,0 +1,30 @@
+// 描画し終えた画面全体の色に 3x3 の行列を掛ける
+
+struct VertexOutput {
+  @builtin(position) position: vec4<f32>
+}
+
+@group(0)
+@binding(0)
+var source: texture_2d<f32>;
+
+// 行列の列。w は使わない
+@group(0)
+@binding(1)
+var<uniform> columns: array<vec4<f32>, 3>;
+
+// 画面を覆う 1 つの三角形
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+  let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+  var out: VertexOutput;
+  out.position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+  return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+  let color = textureLoad(source, vec2<i32>(in.position.xy), 0);
+  let matrix = mat3x3<f32>(columns[0].xyz, columns[1].xyz, columns[2].xyz);
+  return vec4<f32>(clamp(matrix * color.rgb, vec3<f32>(0.0), vec3<f32>(1.0)), color.a);
+}
@@ -0,0 +1,26 @@
+struct VertexInput {
+  @location(0) position: vec3<f32>,
+  @location(1) color: vec4<f32>
+}
+
+struct VertexOutput {
+  @location(0) color: vec4<f32>,
+  @builtin(position) position: vec4<f32>
+}
+
+@group(1)
+@binding(0)
+var<uniform> transform: mat4x4<f32>;
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+  var out: VertexOutput;
+  out.color = in.color;
+  out.position = transform * vec4<f32>(in.position, 1.0);
+  return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+  return in.color;
+}
@@ -0,0 +1,43 @@
+struct GlyphInput {
+  // グリフの左上
+  @location(0) position: vec3<f32>,
+  @location(1) size: vec2<f32>,
+  // アトラスの中の範囲。xy が左上、zw が右下
+  @location(2) uv: vec4<f32>,
+  @location(3) color: vec4<f32>
+}
+
+struct VertexOutput {
+  @location(0) uv: vec2<f32>,
+  @location(1) color: vec4<f32>,
+  @builtin(position) position: vec4<f32>
+}
+
+@group(0)
+@binding(0)
+var tex: texture_2d<f32>;
+
+@group(0)
+@binding(1)
+var samp: sampler;
+
+@group(1)
+@binding(0)
+var<uniform> transform: mat4x4<f32>;
+
+// 1 つのインスタンスを 4 頂点のストリップで描く。左上、右上、左下、右下の順
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32, glyph: GlyphInput) -> VertexOutput {
+  let corner = vec2<f32>(f32(index & 1u), f32(index >> 1u));
+  var out: VertexOutput;
+  out.uv = mix(glyph.uv.xy, glyph.uv.zw, corner);
+  out.color = glyph.color;
+  let position = glyph.position + vec3<f32>(glyph.size * corner, 0.0);
+  out.position = transform * vec4<f32>(position, 1.0);
+  return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+  return textureSample(tex, samp, in.uv) * in.color;
+}
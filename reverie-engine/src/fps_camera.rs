@@ -0,0 +1,117 @@
+//! 一人称視点のカメラをマウスとキーボードで動かす
+use std::time::Duration;
+
+use nalgebra::Vector2;
+use reverie_util::math::fps_camera::{FpsCameraController, MoveInput};
+
+use crate::{
+    input::Input,
+    scene::{
+        resource, CameraComponent, Frame, Projection, RenderResource, System, TransformComponent,
+    },
+};
+
+#[derive(Debug, Clone)]
+/// [`FpsCameraController`] で動かすカメラの印
+///
+/// [`FpsCameraSystem`] が毎フレーム [`TransformComponent`] を書き換える。
+/// [`CameraComponent`] があれば、視野角と描画する距離も [`FpsCameraController`] に合わせる。
+pub struct FpsCameraComponent(pub FpsCameraController);
+
+impl FpsCameraComponent {
+    fn apply_to(&self, transform: &mut TransformComponent, camera: Option<&mut CameraComponent>) {
+        let isometry = self.0.isometry();
+        transform.translation = isometry.translation;
+        transform.rotation = isometry.rotation;
+        if let Some(camera) = camera {
+            camera.projection = Projection::Perspective {
+                fovy: self.0.fov.to_rad().into(),
+                near: self.0.near,
+                far: self.0.far,
+            };
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+/// リソース [`Input`] の [`Input::cursor_delta`] と [`Input::move_input`] で [`FpsCameraComponent`] を動かす
+pub struct FpsCameraSystem;
+
+impl FpsCameraSystem {
+    /// すべての [`FpsCameraComponent`] を `look` だけ回し、`input` に従って `delta_time` だけ進める
+    ///
+    /// [`System::update`] から呼ばれる。GPU に触れないのでテストからも直接呼べる。
+    pub fn apply(
+        world: &mut hecs::World,
+        look: Vector2<f32>,
+        input: MoveInput,
+        delta_time: Duration,
+    ) {
+        for (_, (fps, transform, camera)) in world.query_mut::<(
+            &mut FpsCameraComponent,
+            &mut TransformComponent,
+            Option<&mut CameraComponent>,
+        )>() {
+            fps.0
+                .update((look.x, look.y), input, delta_time.as_secs_f32());
+            fps.apply_to(transform, camera);
+        }
+    }
+}
+
+impl System for FpsCameraSystem {
+    fn setup(&mut self, _resource: Option<&RenderResource<'_>>) {}
+
+    fn update(
+        &mut self,
+        frame: &Frame<'_>,
+        world: &mut hecs::World,
+        _resource: Option<&RenderResource<'_>>,
+    ) {
+        let Some((look, input)) =
+            resource::<Input>(world).map(|input| (input.cursor_delta(), input.move_input()))
+        else {
+            return;
+        };
+        Self::apply(world, look, input, frame.delta_time);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Point3, Vector3};
+    use reverie_util::math::Rad;
+
+    use super::*;
+
+    #[test]
+    fn moves_transform_and_camera() {
+        let mut world = hecs::World::new();
+        let controller = FpsCameraController::new(Point3::origin(), Rad(0.0), Rad(0.0))
+            .with_speed(2.0)
+            .with_sensitivity(0.001);
+        let entity = world.spawn((
+            TransformComponent::default(),
+            CameraComponent::default(),
+            FpsCameraComponent(controller),
+        ));
+        let forward = MoveInput {
+            forward: 1.0,
+            ..MoveInput::default()
+        };
+        FpsCameraSystem::apply(
+            &mut world,
+            Vector2::zeros(),
+            forward,
+            Duration::from_secs(1),
+        );
+
+        let transform = world.get::<&TransformComponent>(entity).unwrap();
+        assert!((transform.translation.vector - Vector3::new(0.0, 0.0, 2.0)).norm() < 1e-5);
+        // カメラは -Z を向くので、回すと前を向く
+        let front = transform.rotation * -Vector3::z();
+        assert!((front - Vector3::z()).norm() < 1e-5);
+        let camera = world.get::<&CameraComponent>(entity).unwrap();
+        assert!(matches!(camera.projection, Projection::Perspective { .. }));
+    }
+}
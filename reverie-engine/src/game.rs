@@ -1,5 +1,11 @@
 //! Game トレイト
-use crate::{scene::Scene, texture::TextureRegistry, winit_app::App};
+use crate::{
+    scene::Scene,
+    settings::Settings,
+    texture::TextureRegistry,
+    wgpu_wrapper::{GraphicsConfig, RenderFrame},
+    winit_app::{App, AppEvent},
+};
 
 /// ゲームが実装すべきトレイト
 pub trait Game {
@@ -7,15 +13,66 @@ pub trait Game {
     ///
     /// ゲームが開始されたときに呼ばれる。
     fn generate_scene(&mut self, registry: &mut TextureRegistry) -> anyhow::Result<Scene>;
+
+    /// 毎フレーム [`Scene::update`] の後に呼ばれ、ウィンドウに描画する
+    ///
+    /// 既定ではシーンの [`Scene::render_graph`] に従って描画する。自分でパスを組み立てるときは実装し直す。
+    /// [`RenderFrame::prepare_sprites`] と [`Scene::draw_sprites`] で、組み込みのスプライトの描画を好きなところで行える。
+    fn render(&mut self, frame: &mut RenderFrame<'_, '_>, scene: &mut Scene) {
+        frame.render_scene(scene);
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// エンジンの起動時の設定
+pub struct EngineConfig {
+    /// マウスの左ボタンの操作をタッチとしても扱う
+    ///
+    /// タッチデバイスが無い環境でタッチ操作の UI を試すのに使う。
+    pub synthesize_touch_from_mouse: bool,
+    /// GPU の初期化に関する設定
+    pub graphics: GraphicsConfig,
+    /// ウィンドウ、表示、音量などの設定
+    ///
+    /// ネイティブでは [`Settings::load_with_args`] で実行ファイルの隣のファイルとコマンドライン引数から読める。
+    /// ウィンドウはこの大きさで作り、[`Game::generate_scene`] の後にリソースとしてシーンに追加する。
+    pub settings: Settings,
 }
 
-pub fn start_engine<G: Game>(game: G) -> anyhow::Result<()> {
+/// 既定の設定でエンジンを起動する
+///
+/// ネイティブではイベントループが終わるまで戻らない。Web では
+/// `requestAnimationFrame` でループを回すため、すぐに戻る。
+pub fn start_engine<G: Game + 'static>(game: G) -> anyhow::Result<()> {
+    start_engine_with_config(game, EngineConfig::default())
+}
+
+/// 設定を指定してエンジンを起動する
+pub fn start_engine_with_config<G: Game + 'static>(
+    game: G,
+    config: EngineConfig,
+) -> anyhow::Result<()> {
     use anyhow::Context;
 
-    let event_loop = winit::event_loop::EventLoop::new().context("failed: create event loop")?;
-    event_loop.set_control_flow(winit::event_loop::ControlFlow::Poll);
-    event_loop
-        .run_app(&mut App::new(game))
-        .context("failed: run app")?;
+    let event_loop = winit::event_loop::EventLoop::<AppEvent>::with_user_event()
+        .build()
+        .context("failed: create event loop")?;
+    let app = App::new(game, config, event_loop.create_proxy());
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let mut app = app;
+        event_loop.set_control_flow(winit::event_loop::ControlFlow::Poll);
+        event_loop.run_app(&mut app).context("failed: run app")?;
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        use winit::platform::web::EventLoopExtWebSys;
+        // 毎フレーム request_redraw するので、描画は requestAnimationFrame に合わせて行われる
+        event_loop.set_control_flow(winit::event_loop::ControlFlow::Wait);
+        event_loop.spawn_app(app);
+    }
+
     Ok(())
 }
@@ -0,0 +1,363 @@
+//! ウィンドウと GPU を使わずにシーンを動かす
+//!
+//! 専用サーバーのように描画の要らないプログラムで、ゲームのシステムをそのまま使うためのもの。
+//! 描画のためのリソースには一切触れず、システムには `resource` として `None` が渡される。
+use std::time::Duration;
+
+use web_time::Instant;
+
+use crate::scene::{clear_events, send_event, CycleError, Frame, Scene};
+
+/// 既定の更新頻度 (Hz)
+pub const DEFAULT_TICK_RATE: u32 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// 処理が止まって時間が溜まったとき、1 回の [`HeadlessRunner::advance`] で追いつけなかった分をどうするか
+pub enum CatchUpPolicy {
+    #[default]
+    /// 捨てる。シミュレーションの時刻は実時間から遅れたままになる
+    Drop,
+    /// 次の [`HeadlessRunner::advance`] に持ち越し、しばらく上限の回数ずつ更新して少しずつ追いつく
+    ///
+    /// その間のシミュレーションは実時間よりゆっくり進んで見える。
+    /// 持ち越す時間は `max_backlog` までで、超えた分は捨てる。
+    Dilate { max_backlog: Duration },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// [`HeadlessRunner`] が追いつけずにシミュレーションの時間を捨てたことを知らせるイベント
+///
+/// 捨てた直後の 1 回の更新のあいだだけ、リソース [`crate::scene::Events`] にある。
+/// クールダウンのタイマーなど、実時間に合わせたいシステムはこれを読んで補う。
+pub struct TimeDropped {
+    /// 捨てた時間
+    pub dropped: Duration,
+    /// 捨てたときまでに行った更新の回数
+    pub tick: u64,
+}
+
+#[derive(Debug)]
+/// 決まった間隔で [`Scene::update_headless`] を呼び続ける
+///
+/// システムに渡す [`Frame::delta_time`] は常に [`Self::tick_interval`] に
+/// [`crate::scene::TimeScale`] を反映したものになる。
+pub struct HeadlessRunner {
+    scene: Scene,
+    tick_interval: Duration,
+    /// まだ更新に使っていない経過時間
+    accumulator: Duration,
+    /// 1 回の [`Self::advance`] で行う更新の上限
+    max_ticks_per_advance: u32,
+    catch_up: CatchUpPolicy,
+    ticks: u64,
+}
+
+impl HeadlessRunner {
+    /// [`DEFAULT_TICK_RATE`] で更新する
+    pub fn new(scene: Scene) -> Self {
+        Self::with_tick_rate(scene, DEFAULT_TICK_RATE)
+    }
+
+    /// 1 秒に`hz`回更新する。0 は 1 として扱う
+    pub fn with_tick_rate(scene: Scene, hz: u32) -> Self {
+        Self {
+            scene,
+            tick_interval: Duration::from_secs(1) / hz.max(1),
+            accumulator: Duration::ZERO,
+            max_ticks_per_advance: 8,
+            catch_up: CatchUpPolicy::Drop,
+            ticks: 0,
+        }
+    }
+
+    /// 処理が遅れたときに 1 回の [`Self::advance`] で追いつこうとする更新の回数の上限
+    ///
+    /// これを超えた分の経過時間は [`Self::with_catch_up`] に従って扱う。
+    pub const fn with_max_ticks_per_advance(mut self, max: u32) -> Self {
+        self.max_ticks_per_advance = max;
+        self
+    }
+
+    /// 更新の回数の上限を超えた分の扱い。既定では捨てる
+    pub const fn with_catch_up(mut self, policy: CatchUpPolicy) -> Self {
+        self.catch_up = policy;
+        self
+    }
+
+    /// システムを初期化する。[`Scene::setup_headless`] を参照
+    pub fn setup(&mut self) -> Result<(), CycleError> {
+        self.scene.setup_headless()
+    }
+
+    pub const fn tick_interval(&self) -> Duration {
+        self.tick_interval
+    }
+
+    /// これまでに行った更新の回数
+    pub const fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    pub const fn scene(&self) -> &Scene {
+        &self.scene
+    }
+
+    pub fn scene_mut(&mut self) -> &mut Scene {
+        &mut self.scene
+    }
+
+    pub fn into_scene(self) -> Scene {
+        self.scene
+    }
+
+    /// 1 回だけ更新する
+    pub fn tick(&mut self) {
+        let frame = Frame::new(Instant::now(), self.tick_interval);
+        self.scene.update_headless(&frame);
+        self.scene.end_frame();
+        self.ticks += 1;
+    }
+
+    /// `elapsed`だけ時間が経ったものとして、溜まった分だけ更新する
+    ///
+    /// 更新は多くても [`Self::with_max_ticks_per_advance`] 回で、超えた分は [`CatchUpPolicy`] に従う。
+    /// 時間を捨てたら [`TimeDropped`] を送り、[`crate::scene::FrameStats::dropped_time`] に記録する。
+    /// 行った更新の回数を返す。
+    pub fn advance(&mut self, elapsed: Duration) -> u32 {
+        clear_events::<TimeDropped>(&mut self.scene.world);
+        self.accumulator += elapsed;
+        let due = self.accumulator.as_nanos() / self.tick_interval.as_nanos();
+        let ticks = due.min(u128::from(self.max_ticks_per_advance)) as u32;
+        let mut dropped = Duration::ZERO;
+        if due > u128::from(ticks) {
+            let overflow = self.accumulator - self.tick_interval * ticks;
+            let kept = match self.catch_up {
+                CatchUpPolicy::Drop => Duration::ZERO,
+                CatchUpPolicy::Dilate { max_backlog } => overflow.min(max_backlog),
+            };
+            dropped = overflow - kept;
+            self.accumulator = self.tick_interval * ticks + kept;
+        }
+        self.scene.record_dropped_time(dropped);
+        if !dropped.is_zero() {
+            tracing::warn!(?dropped, "headless runner is falling behind; dropping time");
+            send_event(
+                &mut self.scene.world,
+                TimeDropped {
+                    dropped,
+                    tick: self.ticks,
+                },
+            );
+        }
+        for i in 0..ticks {
+            self.accumulator -= self.tick_interval;
+            self.tick();
+            if i == 0 {
+                clear_events::<TimeDropped>(&mut self.scene.world);
+            }
+        }
+        ticks
+    }
+
+    /// `should_stop` が `true` を返すまで、実時間に合わせて更新を続ける
+    ///
+    /// 更新の合間はスレッドを眠らせる。`should_stop` は更新のたびに呼ばれる。
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn run_until(&mut self, mut should_stop: impl FnMut(&Scene) -> bool) {
+        let mut last = Instant::now();
+        loop {
+            let now = Instant::now();
+            if self.advance(now - last) > 0 && should_stop(&self.scene) {
+                return;
+            }
+            last = now;
+            let next = self.tick_interval.saturating_sub(self.accumulator);
+            std::thread::sleep(next);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        scene::{resource, Events, RenderResource, SpriteComponent, System},
+        texture::TextureId,
+    };
+
+    #[derive(Debug, Default)]
+    struct Counter(u32);
+
+    struct CountSystem;
+
+    impl System for CountSystem {
+        fn setup(&mut self, resource: Option<&RenderResource<'_>>) {
+            assert!(resource.is_none());
+        }
+
+        fn update(
+            &mut self,
+            frame: &Frame<'_>,
+            world: &mut hecs::World,
+            resource: Option<&RenderResource<'_>>,
+        ) {
+            assert!(resource.is_none());
+            assert!((frame.delta_time.as_secs_f32() - 0.1).abs() < 1e-6);
+            for (_, counter) in world.query_mut::<&mut Counter>() {
+                counter.0 += 1;
+            }
+        }
+    }
+
+    #[test]
+    fn advance_runs_fixed_ticks() {
+        let mut scene = Scene::default();
+        let entity = scene.world.spawn((Counter::default(),));
+        scene.register_system(CountSystem);
+        let mut runner = HeadlessRunner::with_tick_rate(scene, 10);
+        runner.setup().unwrap();
+
+        assert_eq!(runner.advance(Duration::from_millis(250)), 2);
+        assert_eq!(runner.advance(Duration::from_millis(60)), 1);
+        assert_eq!(runner.ticks(), 3);
+        let count = runner.scene().world.get::<&Counter>(entity).unwrap().0;
+        assert_eq!(count, 3);
+    }
+
+    #[test]
+    fn advance_drops_time_when_falling_behind() {
+        let mut runner =
+            HeadlessRunner::with_tick_rate(Scene::default(), 10).with_max_ticks_per_advance(4);
+        assert_eq!(runner.advance(Duration::from_secs(10)), 4);
+        assert_eq!(runner.advance(Duration::ZERO), 0);
+    }
+
+    /// 更新のたびに、受け取った [`TimeDropped`] を記録する
+    struct DropListener;
+
+    #[derive(Debug, Default)]
+    struct Heard(Vec<TimeDropped>);
+
+    impl System for DropListener {
+        fn setup(&mut self, _resource: Option<&RenderResource<'_>>) {}
+
+        fn update(
+            &mut self,
+            _frame: &Frame<'_>,
+            world: &mut hecs::World,
+            _resource: Option<&RenderResource<'_>>,
+        ) {
+            let events: Vec<_> = resource::<Events<TimeDropped>>(world)
+                .map(|events| events.iter().copied().collect())
+                .unwrap_or_default();
+            for (_, heard) in world.query_mut::<&mut Heard>() {
+                heard.0.extend_from_slice(&events);
+            }
+        }
+    }
+
+    fn listening_runner(policy: CatchUpPolicy) -> (HeadlessRunner, hecs::Entity) {
+        let mut scene = Scene::default();
+        let entity = scene.world.spawn((Heard::default(),));
+        scene.register_system(DropListener);
+        let mut runner = HeadlessRunner::with_tick_rate(scene, 60)
+            .with_max_ticks_per_advance(5)
+            .with_catch_up(policy);
+        runner.setup().unwrap();
+        (runner, entity)
+    }
+
+    #[test]
+    fn long_frame_is_capped_and_reports_dropped_time() {
+        let (mut runner, entity) = listening_runner(CatchUpPolicy::default());
+        let interval = runner.tick_interval();
+        assert_eq!(runner.advance(interval), 1);
+
+        // 500 ms 止まると 30 回分溜まるが、5 回だけ更新して残りは捨てる
+        assert_eq!(runner.advance(Duration::from_millis(500)), 5);
+        let dropped = Duration::from_millis(500) - interval * 5;
+        let stats = runner.scene().frame_stats();
+        assert!(stats.dropped_time().abs_diff(dropped) < interval);
+        assert_eq!(stats.total_dropped_time(), stats.dropped_time());
+        let event = TimeDropped {
+            dropped: stats.dropped_time(),
+            tick: 1,
+        };
+        // 捨てた直後の 1 回の更新だけが受け取る
+        {
+            let heard = runner.scene().world.get::<&Heard>(entity).unwrap();
+            assert_eq!(heard.0, [event]);
+        }
+
+        assert_eq!(runner.advance(interval), 1);
+        assert_eq!(runner.scene().frame_stats().dropped_time(), Duration::ZERO);
+        assert_eq!(
+            runner.scene().frame_stats().total_dropped_time(),
+            event.dropped
+        );
+        assert!(runner
+            .scene()
+            .resource::<Events<TimeDropped>>()
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn dilation_catches_up_over_later_frames() {
+        let max_backlog = Duration::from_millis(300);
+        let (mut runner, entity) = listening_runner(CatchUpPolicy::Dilate { max_backlog });
+        let interval = runner.tick_interval();
+
+        // 30 回分のうち 5 回を行い、300 ms を持ち越して残りを捨てる
+        assert_eq!(runner.advance(Duration::from_millis(500)), 5);
+        let dropped = runner.scene().frame_stats().dropped_time();
+        assert_eq!(
+            dropped,
+            Duration::from_millis(500) - interval * 5 - max_backlog
+        );
+
+        // 持ち越した分は、その後のフレームで上限の回数ずつ更新して追いつく
+        let mut ticks = Vec::new();
+        for _ in 0..6 {
+            ticks.push(runner.advance(interval));
+        }
+        assert_eq!(ticks, [5, 5, 5, 5, 3, 1]);
+        assert_eq!(runner.ticks(), 5 + 24);
+        let heard = &runner.scene().world.get::<&Heard>(entity).unwrap().0;
+        assert_eq!(heard.len(), 1);
+
+        // 上限に収まる遅れなら何も捨てない
+        let (mut runner, _) = listening_runner(CatchUpPolicy::Dilate { max_backlog });
+        assert_eq!(runner.advance(interval * 7), 5);
+        assert_eq!(runner.advance(interval), 3);
+        assert_eq!(
+            runner.scene().frame_stats().total_dropped_time(),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn static_scene_stops_allocating() {
+        let mut scene = Scene::default();
+        let parent = scene.new_entity(Default::default(), SpriteComponent::new(TextureId::WHITE));
+        for _ in 0..10 {
+            let child =
+                scene.new_entity(Default::default(), SpriteComponent::new(TextureId::WHITE));
+            scene.set_parent(child, parent).unwrap();
+        }
+        scene.frame_arena_mut().set_strict(true);
+        let mut runner = HeadlessRunner::new(scene);
+        runner.setup().unwrap();
+
+        runner.tick();
+        let stats = runner.scene().frame_stats();
+        assert!(stats.arena_grown_bytes() > 0);
+        let high_water = stats.arena_high_water_bytes();
+        for _ in 0..3 {
+            runner.tick();
+            let stats = runner.scene().frame_stats();
+            assert_eq!(stats.arena_grown_bytes(), 0);
+            assert_eq!(stats.arena_high_water_bytes(), high_water);
+        }
+    }
+}
@@ -0,0 +1,540 @@
+//! キーボードとマウスボタンの状態と、UI による入力の横取り
+//!
+//! [`Input`] はリソースとして [`crate::scene::Scene`] が毎フレームの始めに更新する。
+//! チャット欄のような UI のシステムは、入力を受け付けている間 [`Input::claim_keyboard`] を毎フレーム呼ぶ。
+//! 横取りされている装置のキーやボタンは、[`Input`] と [`ActionMap`] からは離されているように見える。
+//! 横取りはそのフレームの終わりまで続くので、UI のシステムはゲームのシステムより先に実行する
+//! ([`crate::scene::System::dependencies`])。
+//! ゲームパッドの振動は [`Input::gamepad`] で頼む。
+use std::{collections::HashSet, hash::Hash};
+
+use nalgebra::{Point2, Vector2};
+use reverie_util::math::{fps_camera::MoveInput, orbit_camera::OrbitInput};
+use winit::{
+    event::{ElementState, MouseButton, MouseScrollDelta},
+    keyboard::{KeyCode, PhysicalKey},
+};
+
+use crate::scene::Frame;
+
+mod gamepad;
+#[cfg(feature = "gilrs")]
+mod gilrs_rumble;
+
+pub use gamepad::{Gamepad, GamepadId, Rumble, RumbleBackend, RumbleSystem};
+#[cfg(feature = "gilrs")]
+pub use gilrs_rumble::GilrsRumble;
+
+/// タッチパッドのようにピクセルで届くスクロールを、ホイールの何行分とみなすか
+pub const PIXELS_PER_WHEEL_LINE: f32 = 40.0;
+
+#[derive(Debug, Clone)]
+/// 押されているキーとマウスボタン
+struct ButtonState<T> {
+    held: HashSet<T>,
+    pressed: HashSet<T>,
+    released: HashSet<T>,
+    /// 横取りされている間に押されていたもの。離されるまで押されていないものとして扱う
+    ///
+    /// 横取りが終わったフレームに押された瞬間として扱わないようにする。
+    suppressed: HashSet<T>,
+    captured: bool,
+}
+
+impl<T> Default for ButtonState<T> {
+    fn default() -> Self {
+        Self {
+            held: HashSet::new(),
+            pressed: HashSet::new(),
+            released: HashSet::new(),
+            suppressed: HashSet::new(),
+            captured: false,
+        }
+    }
+}
+
+impl<T: Copy + Eq + Hash> ButtonState<T> {
+    fn begin_frame(&mut self) {
+        if self.captured {
+            self.suppressed.extend(self.held.iter().copied());
+        }
+        self.captured = false;
+        self.pressed.clear();
+        self.released.clear();
+    }
+
+    fn set(&mut self, button: T, state: ElementState) {
+        match state {
+            ElementState::Pressed => {
+                if self.held.insert(button) {
+                    self.pressed.insert(button);
+                }
+            }
+            ElementState::Released => {
+                if self.held.remove(&button) && !self.suppressed.remove(&button) {
+                    self.released.insert(button);
+                }
+            }
+        }
+    }
+
+    /// ゲームから見えるかどうか
+    fn visible(&self, button: &T) -> bool {
+        !self.captured && !self.suppressed.contains(button)
+    }
+
+    fn pressed(&self, button: T) -> bool {
+        self.held.contains(&button) && self.visible(&button)
+    }
+
+    fn just_pressed(&self, button: T) -> bool {
+        self.pressed.contains(&button) && self.visible(&button)
+    }
+
+    fn just_released(&self, button: T) -> bool {
+        !self.captured && self.released.contains(&button)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// キーボードとマウスボタンの状態
+///
+/// キーは物理的な位置 ([`KeyCode`]) で区別する。文字の入力には [`Frame::key_events`] の `text` を使う。
+pub struct Input {
+    keys: ButtonState<KeyCode>,
+    buttons: ButtonState<MouseButton>,
+    /// 最後に分かったカーソルの位置 (物理ピクセル)
+    cursor: Option<Point2<f32>>,
+    /// 前のフレームからのカーソルの移動量
+    cursor_delta: Vector2<f32>,
+    /// このフレームのホイールの回転量 (行)
+    wheel: f32,
+    /// [`Input::gamepad`] で頼まれて続いている振動
+    rumbles: gamepad::Rumbles,
+}
+
+impl Input {
+    /// フレームの入力を反映する。横取りは解除される
+    pub(crate) fn begin_frame(&mut self, frame: &Frame<'_>) {
+        self.keys.begin_frame();
+        self.buttons.begin_frame();
+        self.rumbles.begin_frame(frame.now);
+        for event in frame.key_events {
+            if let (PhysicalKey::Code(code), false) = (event.physical_key, event.repeat) {
+                self.keys.set(code, event.state);
+            }
+        }
+        for &(state, button, _) in frame.mouse_clicks {
+            self.buttons.set(button, state);
+        }
+        let position = frame.mouse_position;
+        let position = Point2::new(position.x as f32, position.y as f32);
+        self.cursor_delta = self
+            .cursor
+            .map_or_else(Vector2::zeros, |last| position - last);
+        self.cursor = Some(position);
+        self.wheel = frame
+            .mouse_wheels
+            .iter()
+            .map(|(delta, _, _)| match delta {
+                MouseScrollDelta::LineDelta(_, y) => *y,
+                MouseScrollDelta::PixelDelta(position) => position.y as f32 / PIXELS_PER_WHEEL_LINE,
+            })
+            .sum();
+    }
+
+    /// このフレームの間、キーボードの入力を横取りする
+    pub fn claim_keyboard(&mut self) {
+        self.keys.captured = true;
+    }
+
+    /// このフレームの間、マウスボタンの入力を横取りする
+    pub fn claim_pointer(&mut self) {
+        self.buttons.captured = true;
+    }
+
+    /// キーボードが横取りされているか。されていればゲームの操作に使わない
+    pub const fn keyboard_captured(&self) -> bool {
+        self.keys.captured
+    }
+
+    /// マウスボタンが横取りされているか
+    pub const fn pointer_captured(&self) -> bool {
+        self.buttons.captured
+    }
+
+    /// キーが押されているか。横取りされていれば `false`
+    pub fn key_pressed(&self, key: KeyCode) -> bool {
+        self.keys.pressed(key)
+    }
+
+    /// このフレームでキーが押されたか。横取りされていれば `false`
+    pub fn key_just_pressed(&self, key: KeyCode) -> bool {
+        self.keys.just_pressed(key)
+    }
+
+    /// このフレームでキーが離されたか。横取りされていれば `false`
+    pub fn key_just_released(&self, key: KeyCode) -> bool {
+        self.keys.just_released(key)
+    }
+
+    /// 横取りに関係なく、キーが押されているか。入力を横取りする UI のためのもの
+    pub fn key_held_raw(&self, key: KeyCode) -> bool {
+        self.keys.held.contains(&key)
+    }
+
+    /// マウスボタンが押されているか。横取りされていれば `false`
+    pub fn button_pressed(&self, button: MouseButton) -> bool {
+        self.buttons.pressed(button)
+    }
+
+    /// このフレームでマウスボタンが押されたか。横取りされていれば `false`
+    pub fn button_just_pressed(&self, button: MouseButton) -> bool {
+        self.buttons.just_pressed(button)
+    }
+
+    /// このフレームでマウスボタンが離されたか。横取りされていれば `false`
+    pub fn button_just_released(&self, button: MouseButton) -> bool {
+        self.buttons.just_released(button)
+    }
+
+    /// 横取りに関係なく、マウスボタンが押されているか
+    pub fn button_held_raw(&self, button: MouseButton) -> bool {
+        self.buttons.held.contains(&button)
+    }
+
+    /// カーソルの位置 (描画先の物理ピクセル)。マウスが横取りされていれば `None`
+    pub fn cursor_position(&self) -> Option<Point2<f32>> {
+        self.cursor.filter(|_| !self.buttons.captured)
+    }
+
+    /// 前のフレームからのカーソルの移動量 (物理ピクセル)。マウスが横取りされていれば 0
+    pub fn cursor_delta(&self) -> Vector2<f32> {
+        if self.buttons.captured {
+            Vector2::zeros()
+        } else {
+            self.cursor_delta
+        }
+    }
+
+    /// このフレームのホイールの回転量 (行)。奥に回すと正。マウスが横取りされていれば 0
+    ///
+    /// ピクセルで届くスクロールは [`PIXELS_PER_WHEEL_LINE`] で行に直す。
+    pub const fn wheel_delta(&self) -> f32 {
+        if self.buttons.captured {
+            0.0
+        } else {
+            self.wheel
+        }
+    }
+
+    /// `positive` が押されていれば 1.0、`negative` が押されていれば -1.0。両方なら 0.0
+    pub fn axis(&self, positive: KeyCode, negative: KeyCode) -> f32 {
+        f32::from(u8::from(self.key_pressed(positive)))
+            - f32::from(u8::from(self.key_pressed(negative)))
+    }
+
+    /// WASD で前後左右、Space と左 Shift で上下に動く入力
+    pub fn move_input(&self) -> MoveInput {
+        MoveInput {
+            forward: self.axis(KeyCode::KeyW, KeyCode::KeyS),
+            right: self.axis(KeyCode::KeyD, KeyCode::KeyA),
+            up: self.axis(KeyCode::Space, KeyCode::ShiftLeft),
+        }
+    }
+
+    /// 左ドラッグで回り、中ドラッグで注視点を動かし、ホイールで近づく入力
+    pub fn orbit_input(&self) -> OrbitInput {
+        let drag = |button| {
+            if self.button_pressed(button) {
+                self.cursor_delta()
+            } else {
+                Vector2::zeros()
+            }
+        };
+        OrbitInput {
+            orbit: drag(MouseButton::Left),
+            pan: drag(MouseButton::Middle),
+            zoom: self.wheel_delta(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+/// アクションに割り当てる入力
+///
+/// 保存するときは `"KeyW"` や `"Left"` のようにキーやボタンの名前だけを書く。
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+}
+
+impl From<KeyCode> for Binding {
+    fn from(key: KeyCode) -> Self {
+        Self::Key(key)
+    }
+}
+
+impl From<MouseButton> for Binding {
+    fn from(button: MouseButton) -> Self {
+        Self::Mouse(button)
+    }
+}
+
+#[derive(Debug, Clone)]
+/// ゲームのアクションとキーやボタンの対応
+///
+/// `A` はゲームが定義するアクションの型。1 つのアクションに複数の入力を割り当てられる。
+/// 状態は [`Input`] から読むので、横取りされている装置の入力では押されていないことになる。
+pub struct ActionMap<A> {
+    bindings: Vec<(A, Binding)>,
+}
+
+impl<A> Default for ActionMap<A> {
+    fn default() -> Self {
+        Self {
+            bindings: Vec::new(),
+        }
+    }
+}
+
+impl<A: Copy + Eq> ActionMap<A> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `action` に `binding` を割り当てる
+    pub fn with(mut self, action: A, binding: impl Into<Binding>) -> Self {
+        self.bind(action, binding);
+        self
+    }
+
+    /// `action` に `binding` を割り当てる
+    pub fn bind(&mut self, action: A, binding: impl Into<Binding>) {
+        self.bindings.push((action, binding.into()));
+    }
+
+    /// `action` の割り当てをすべて外す
+    pub fn unbind(&mut self, action: A) {
+        self.bindings.retain(|(a, _)| *a != action);
+    }
+
+    /// `action` に割り当てた入力
+    pub fn bindings(&self, action: A) -> impl Iterator<Item = Binding> + '_ {
+        self.bindings
+            .iter()
+            .filter(move |(a, _)| *a == action)
+            .map(|(_, binding)| *binding)
+    }
+
+    /// 割り当てた入力のどれかが押されているか
+    pub fn pressed(&self, input: &Input, action: A) -> bool {
+        self.bindings(action).any(|binding| match binding {
+            Binding::Key(key) => input.key_pressed(key),
+            Binding::Mouse(button) => input.button_pressed(button),
+        })
+    }
+
+    /// このフレームで押されたか。他の割り当てで既に押されていれば `false`
+    pub fn just_pressed(&self, input: &Input, action: A) -> bool {
+        let mut just_pressed = false;
+        for binding in self.bindings(action) {
+            let (pressed, just) = match binding {
+                Binding::Key(key) => (input.key_pressed(key), input.key_just_pressed(key)),
+                Binding::Mouse(button) => (
+                    input.button_pressed(button),
+                    input.button_just_pressed(button),
+                ),
+            };
+            if pressed && !just {
+                return false;
+            }
+            just_pressed |= just;
+        }
+        just_pressed
+    }
+
+    /// このフレームで離され、他の割り当ても押されていないか
+    pub fn just_released(&self, input: &Input, action: A) -> bool {
+        !self.pressed(input, action)
+            && self.bindings(action).any(|binding| match binding {
+                Binding::Key(key) => input.key_just_released(key),
+                Binding::Mouse(button) => input.button_just_released(button),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(input: &mut Input, code: KeyCode, state: ElementState) {
+        input.keys.set(code, state);
+    }
+
+    fn next_frame(input: &mut Input) {
+        input.keys.begin_frame();
+        input.buttons.begin_frame();
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Action {
+        Forward,
+        Fire,
+    }
+
+    fn actions() -> ActionMap<Action> {
+        ActionMap::new()
+            .with(Action::Forward, KeyCode::KeyW)
+            .with(Action::Forward, KeyCode::ArrowUp)
+            .with(Action::Fire, MouseButton::Left)
+    }
+
+    #[test]
+    fn claimed_keyboard_hides_actions() {
+        let actions = actions();
+        let mut input = Input::default();
+        key(&mut input, KeyCode::KeyW, ElementState::Pressed);
+        input.claim_keyboard();
+        assert!(input.keyboard_captured());
+        assert!(!input.pointer_captured());
+        assert!(!actions.pressed(&input, Action::Forward));
+        assert!(!actions.just_pressed(&input, Action::Forward));
+        assert!(input.key_held_raw(KeyCode::KeyW));
+
+        input.buttons.set(MouseButton::Left, ElementState::Pressed);
+        assert!(actions.just_pressed(&input, Action::Fire));
+    }
+
+    #[test]
+    fn releasing_focus_does_not_press_held_keys() {
+        let actions = actions();
+        let mut input = Input::default();
+        // チャット欄を開いている間に W を押した
+        key(&mut input, KeyCode::KeyW, ElementState::Pressed);
+        input.claim_keyboard();
+
+        // チャット欄を閉じても、W を押し直すまでは前進しない
+        next_frame(&mut input);
+        assert!(!input.keyboard_captured());
+        assert!(!actions.pressed(&input, Action::Forward));
+        assert!(!actions.just_pressed(&input, Action::Forward));
+
+        next_frame(&mut input);
+        key(&mut input, KeyCode::KeyW, ElementState::Released);
+        assert!(!actions.just_released(&input, Action::Forward));
+
+        next_frame(&mut input);
+        key(&mut input, KeyCode::KeyW, ElementState::Pressed);
+        assert!(actions.just_pressed(&input, Action::Forward));
+        assert!(actions.pressed(&input, Action::Forward));
+    }
+
+    #[test]
+    fn scene_releases_claims_every_frame() {
+        let frame = Frame::new(web_time::Instant::now(), std::time::Duration::ZERO);
+        let mut scene = crate::scene::Scene::default();
+        scene.update_headless(&frame);
+        assert_eq!(
+            scene.resource::<Input>().unwrap().cursor_position(),
+            Some(Point2::origin())
+        );
+        scene.resource_mut::<Input>().unwrap().claim_keyboard();
+        assert!(scene.resource::<Input>().unwrap().keyboard_captured());
+        scene.resource_mut::<Input>().unwrap().claim_pointer();
+        assert_eq!(scene.resource::<Input>().unwrap().cursor_position(), None);
+
+        scene.update_headless(&frame);
+        assert!(!scene.resource::<Input>().unwrap().keyboard_captured());
+    }
+
+    #[test]
+    fn cursor_delta_and_move_input() {
+        let mut scene = crate::scene::Scene::default();
+        let frame = |x, y| Frame {
+            mouse_position: winit::dpi::PhysicalPosition::new(x, y),
+            ..Frame::new(web_time::Instant::now(), std::time::Duration::ZERO)
+        };
+        scene.update_headless(&frame(10.0, 20.0));
+        assert_eq!(
+            scene.resource::<Input>().unwrap().cursor_delta(),
+            Vector2::zeros()
+        );
+        scene.update_headless(&frame(13.0, 16.0));
+        assert_eq!(
+            scene.resource::<Input>().unwrap().cursor_delta(),
+            Vector2::new(3.0, -4.0)
+        );
+
+        let mut input = Input::default();
+        key(&mut input, KeyCode::KeyW, ElementState::Pressed);
+        key(&mut input, KeyCode::KeyA, ElementState::Pressed);
+        key(&mut input, KeyCode::Space, ElementState::Pressed);
+        key(&mut input, KeyCode::ShiftLeft, ElementState::Pressed);
+        assert_eq!(
+            input.move_input(),
+            MoveInput {
+                forward: 1.0,
+                right: -1.0,
+                up: 0.0
+            }
+        );
+    }
+
+    #[test]
+    fn orbit_input_from_drag_and_wheel() {
+        let mut scene = crate::scene::Scene::default();
+        let wheels = [
+            (
+                MouseScrollDelta::LineDelta(0.0, 1.0),
+                winit::event::TouchPhase::Moved,
+                winit::dpi::PhysicalPosition::new(0.0, 0.0),
+            ),
+            (
+                MouseScrollDelta::PixelDelta(winit::dpi::PhysicalPosition::new(
+                    0.0,
+                    f64::from(PIXELS_PER_WHEEL_LINE),
+                )),
+                winit::event::TouchPhase::Moved,
+                winit::dpi::PhysicalPosition::new(0.0, 0.0),
+            ),
+        ];
+        let clicks = [(
+            ElementState::Pressed,
+            MouseButton::Middle,
+            winit::dpi::PhysicalPosition::new(0.0, 0.0),
+        )];
+        scene.update_headless(&Frame {
+            mouse_clicks: &clicks,
+            ..Frame::new(web_time::Instant::now(), std::time::Duration::ZERO)
+        });
+        scene.update_headless(&Frame {
+            mouse_wheels: &wheels,
+            mouse_position: winit::dpi::PhysicalPosition::new(5.0, 0.0),
+            ..Frame::new(web_time::Instant::now(), std::time::Duration::ZERO)
+        });
+        let input = scene.resource::<Input>().unwrap().orbit_input();
+        assert_eq!(input.orbit, Vector2::zeros());
+        assert_eq!(input.pan, Vector2::new(5.0, 0.0));
+        assert_eq!(input.zoom, 2.0);
+    }
+
+    #[test]
+    fn edges_consider_every_binding() {
+        let actions = actions();
+        let mut input = Input::default();
+        key(&mut input, KeyCode::KeyW, ElementState::Pressed);
+        assert!(actions.just_pressed(&input, Action::Forward));
+
+        next_frame(&mut input);
+        key(&mut input, KeyCode::ArrowUp, ElementState::Pressed);
+        assert!(!actions.just_pressed(&input, Action::Forward));
+
+        next_frame(&mut input);
+        key(&mut input, KeyCode::KeyW, ElementState::Released);
+        assert!(!actions.just_released(&input, Action::Forward));
+        key(&mut input, KeyCode::ArrowUp, ElementState::Released);
+        assert!(actions.just_released(&input, Action::Forward));
+    }
+}
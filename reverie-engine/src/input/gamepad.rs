@@ -0,0 +1,330 @@
+//! ゲームパッドの振動
+//!
+//! システムは [`Input::gamepad`] で [`Gamepad::rumble`] を呼び、振動を頼む。
+//! 重なった振動はモーターごとに強い方を使い、足し合わせない。
+//! [`RumbleSystem`] が毎フレーム強さをまとめ、[`Rumble`] リソースの [`RumbleBackend`] に渡す。
+//! 実際に振動させるのはバックエンドで、`gilrs` の feature を有効にすると `GilrsRumble` が使える。
+//! 強さは `gilrs::ff::EffectBuilder` の `BaseEffectType::Strong` と `BaseEffectType::Weak` に対応する。
+//!
+//! 物にぶつかったときに振動させる例
+//!
+//! ```ignore
+//! fn update(&mut self, _frame: &Frame<'_>, world: &mut hecs::World, _resource: Option<&RenderResource<'_>>) {
+//!     let landed = world
+//!         .query_mut::<&KinematicBodyComponent>()
+//!         .into_iter()
+//!         .any(|(_, body)| body.on_ground() && !self.was_on_ground);
+//!     if landed {
+//!         if let Some(mut input) = resource_mut::<Input>(world) {
+//!             input.gamepad(GamepadId(0)).rumble(0.6, 0.2, Duration::from_millis(120));
+//!         }
+//!     }
+//! }
+//! ```
+use std::{collections::HashMap, time::Duration};
+
+use web_time::Instant;
+
+use super::Input;
+use crate::{
+    scene::{resource, resource_mut, Frame, RenderResource, System},
+    settings::Settings,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+/// [`RumbleBackend`] が区別するゲームパッド
+///
+/// gilrs なら `gilrs::GamepadId` を `usize` にしたものを使う。
+pub struct GamepadId(pub u32);
+
+/// 振動を実際に出すもの
+///
+/// 強さは 0.0 から 1.0。`low_freq` は低い周波数の大きいモーター、`high_freq` は高い周波数の小さいモーター。
+pub trait RumbleBackend: Send + Sync {
+    /// `gamepad` が振動できるか。できなければ [`Self::set_rumble`] は呼ばれない
+    fn supports_rumble(&self, gamepad: GamepadId) -> bool;
+
+    /// `gamepad` の振動の強さを変える。両方 0.0 なら止める
+    fn set_rumble(&mut self, gamepad: GamepadId, low_freq: f32, high_freq: f32);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// 頼まれた 1 つの振動
+struct ActiveRumble {
+    gamepad: GamepadId,
+    low_freq: f32,
+    high_freq: f32,
+    remaining: Duration,
+}
+
+#[derive(Debug, Clone, Default)]
+/// 続いている振動の一覧
+pub(super) struct Rumbles {
+    active: Vec<ActiveRumble>,
+    last_frame: Option<Instant>,
+}
+
+impl Rumbles {
+    /// 前のフレームから実際に経った時間だけ進め、終わった振動を取り除く
+    ///
+    /// [`crate::scene::TimeScale`] で時間を止めていても振動は終わる。
+    pub(super) fn begin_frame(&mut self, now: Instant) {
+        let elapsed = self
+            .last_frame
+            .map_or(Duration::ZERO, |last| now.saturating_duration_since(last));
+        self.last_frame = Some(now);
+        for rumble in &mut self.active {
+            rumble.remaining = rumble.remaining.saturating_sub(elapsed);
+        }
+        self.active.retain(|rumble| !rumble.remaining.is_zero());
+    }
+
+    /// `gamepad` の今の振動の強さ `(low_freq, high_freq)`。モーターごとに強い方を使う
+    fn strength(&self, gamepad: GamepadId) -> (f32, f32) {
+        self.active
+            .iter()
+            .filter(|rumble| rumble.gamepad == gamepad)
+            .fold((0.0, 0.0), |(low, high), rumble| {
+                (low.max(rumble.low_freq), high.max(rumble.high_freq))
+            })
+    }
+}
+
+/// 1 つのゲームパッドへの振動の頼み。[`Input::gamepad`] で得る
+#[derive(Debug)]
+pub struct Gamepad<'a> {
+    id: GamepadId,
+    rumbles: &'a mut Rumbles,
+}
+
+impl Gamepad<'_> {
+    pub const fn id(&self) -> GamepadId {
+        self.id
+    }
+
+    /// `duration` の間振動させる。強さは 0.0 から 1.0 に収める
+    ///
+    /// 続いている振動があれば、モーターごとに強い方で振動する。
+    pub fn rumble(&mut self, low_freq: f32, high_freq: f32, duration: Duration) {
+        let clamp = |strength: f32| {
+            if strength.is_finite() {
+                strength.clamp(0.0, 1.0)
+            } else {
+                0.0
+            }
+        };
+        self.rumbles.active.push(ActiveRumble {
+            gamepad: self.id,
+            low_freq: clamp(low_freq),
+            high_freq: clamp(high_freq),
+            remaining: duration,
+        });
+    }
+
+    /// このゲームパッドの振動をすべて止める
+    pub fn stop_rumble(&mut self) {
+        self.rumbles
+            .active
+            .retain(|rumble| rumble.gamepad != self.id);
+    }
+
+    /// 今の振動の強さ `(low_freq, high_freq)`
+    pub fn rumble_strength(&self) -> (f32, f32) {
+        self.rumbles.strength(self.id)
+    }
+}
+
+impl Input {
+    /// `id` のゲームパッドに振動を頼む
+    pub fn gamepad(&mut self, id: GamepadId) -> Gamepad<'_> {
+        Gamepad {
+            id,
+            rumbles: &mut self.rumbles,
+        }
+    }
+}
+
+/// 振動を出す [`RumbleBackend`] を持つリソース
+///
+/// [`crate::scene::Scene::insert_resource`] で追加する。無ければ [`RumbleSystem`] は何もしない。
+pub struct Rumble {
+    backend: Box<dyn RumbleBackend>,
+    /// 最後にバックエンドに渡した強さ
+    sent: HashMap<GamepadId, (f32, f32)>,
+}
+
+impl std::fmt::Debug for Rumble {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Rumble")
+            .field("sent", &self.sent)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Rumble {
+    pub fn new(backend: impl RumbleBackend + 'static) -> Self {
+        Self {
+            backend: Box::new(backend),
+            sent: HashMap::new(),
+        }
+    }
+
+    /// `gamepad` の強さを変える。前に渡した強さと同じなら何もしない
+    fn set(&mut self, gamepad: GamepadId, strength: (f32, f32)) {
+        let last = self.sent.get(&gamepad).copied().unwrap_or((0.0, 0.0));
+        if last == strength || !self.backend.supports_rumble(gamepad) {
+            return;
+        }
+        self.backend.set_rumble(gamepad, strength.0, strength.1);
+        if strength == (0.0, 0.0) {
+            self.sent.remove(&gamepad);
+        } else {
+            self.sent.insert(gamepad, strength);
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+/// [`Input`] に頼まれた振動を [`Rumble`] のバックエンドに渡すシステム
+///
+/// [`Settings`] のリソースの [`crate::settings::GamepadSettings::rumble`] が `false` なら、すべて止める。
+/// 振動を頼むシステムより後に実行すると、同じフレームのうちに振動が始まる。
+pub struct RumbleSystem;
+
+impl RumbleSystem {
+    pub fn apply(&mut self, world: &mut hecs::World) {
+        let Some(mut rumble) = resource_mut::<Rumble>(world) else {
+            return;
+        };
+        let enabled = resource::<Settings>(world).map_or(true, |settings| settings.gamepad.rumble);
+        let Some(input) = resource::<Input>(world) else {
+            return;
+        };
+        let mut gamepads: Vec<_> = input
+            .rumbles
+            .active
+            .iter()
+            .map(|active| active.gamepad)
+            .chain(rumble.sent.keys().copied())
+            .collect();
+        gamepads.sort_unstable();
+        gamepads.dedup();
+        for gamepad in gamepads {
+            let strength = if enabled {
+                input.rumbles.strength(gamepad)
+            } else {
+                (0.0, 0.0)
+            };
+            rumble.set(gamepad, strength);
+        }
+    }
+}
+
+impl System for RumbleSystem {
+    fn setup(&mut self, _resource: Option<&RenderResource<'_>>) {}
+
+    fn update(
+        &mut self,
+        _frame: &Frame<'_>,
+        world: &mut hecs::World,
+        _resource: Option<&RenderResource<'_>>,
+    ) {
+        self.apply(world);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::scene::insert_resource;
+
+    type Calls = Arc<Mutex<Vec<(GamepadId, f32, f32)>>>;
+
+    #[derive(Default)]
+    struct RecordingBackend {
+        calls: Calls,
+    }
+
+    impl RumbleBackend for RecordingBackend {
+        fn supports_rumble(&self, gamepad: GamepadId) -> bool {
+            gamepad != UNSUPPORTED
+        }
+
+        fn set_rumble(&mut self, gamepad: GamepadId, low_freq: f32, high_freq: f32) {
+            self.calls
+                .lock()
+                .unwrap()
+                .push((gamepad, low_freq, high_freq));
+        }
+    }
+
+    const PAD: GamepadId = GamepadId(0);
+    const UNSUPPORTED: GamepadId = GamepadId(1);
+
+    fn setup() -> (hecs::World, Calls) {
+        let mut world = hecs::World::new();
+        let backend = RecordingBackend::default();
+        let calls = Arc::clone(&backend.calls);
+        insert_resource(&mut world, Rumble::new(backend));
+        insert_resource(&mut world, Input::default());
+        (world, calls)
+    }
+
+    fn advance(world: &hecs::World, now: Instant) {
+        resource_mut::<Input>(world)
+            .unwrap()
+            .rumbles
+            .begin_frame(now);
+    }
+
+    #[test]
+    fn overlapping_rumbles_use_the_strongest_and_expire() {
+        let (mut world, calls) = setup();
+        let start = Instant::now();
+        advance(&world, start);
+        {
+            let mut input = resource_mut::<Input>(&world).unwrap();
+            let mut pad = input.gamepad(PAD);
+            pad.rumble(0.8, 0.1, Duration::from_millis(100));
+            pad.rumble(0.3, 0.5, Duration::from_millis(300));
+            assert_eq!(pad.rumble_strength(), (0.8, 0.5));
+            input
+                .gamepad(UNSUPPORTED)
+                .rumble(1.0, 1.0, Duration::from_secs(1));
+        }
+        RumbleSystem.apply(&mut world);
+        // 同じ強さは送り直さない
+        RumbleSystem.apply(&mut world);
+        assert_eq!(*calls.lock().unwrap(), [(PAD, 0.8, 0.5)]);
+
+        advance(&world, start + Duration::from_millis(200));
+        RumbleSystem.apply(&mut world);
+        advance(&world, start + Duration::from_millis(400));
+        RumbleSystem.apply(&mut world);
+        advance(&world, start + Duration::from_millis(500));
+        RumbleSystem.apply(&mut world);
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [(PAD, 0.8, 0.5), (PAD, 0.3, 0.5), (PAD, 0.0, 0.0)]
+        );
+    }
+
+    #[test]
+    fn settings_toggle_stops_rumble() {
+        let (mut world, calls) = setup();
+        resource_mut::<Input>(&world).unwrap().gamepad(PAD).rumble(
+            2.0,
+            f32::NAN,
+            Duration::from_secs(1),
+        );
+        RumbleSystem.apply(&mut world);
+
+        let mut settings = Settings::default();
+        settings.gamepad.rumble = false;
+        insert_resource(&mut world, settings);
+        RumbleSystem.apply(&mut world);
+        assert_eq!(*calls.lock().unwrap(), [(PAD, 1.0, 0.0), (PAD, 0.0, 0.0)]);
+    }
+}
@@ -0,0 +1,139 @@
+//! gilrs の force feedback で振動させるバックエンド
+use std::{
+    collections::HashMap,
+    sync::{Mutex, PoisonError},
+};
+
+use anyhow::anyhow;
+use gilrs::{
+    ff::{BaseEffect, BaseEffectType, Effect, EffectBuilder},
+    Gilrs,
+};
+
+use super::{GamepadId, RumbleBackend};
+
+/// gilrs でゲームパッドを振動させる [`RumbleBackend`]
+///
+/// 強さが変わるたびに、`low_freq` を `BaseEffectType::Strong`、`high_freq` を `BaseEffectType::Weak` にした
+/// 効果を作り直し、止めるまで鳴らし続ける。force feedback に対応していないゲームパッドでは何もしない。
+///
+/// 抜き差しを知るために、届いたイベントはここで読み捨てる。ボタンの入力も gilrs で読むなら、別の [`Gilrs`] を作る。
+pub struct GilrsRumble {
+    /// [`Gilrs`] は `Sync` ではないので包む
+    gilrs: Mutex<Gilrs>,
+    /// 鳴らしている効果。drop すると止まる
+    effects: HashMap<GamepadId, Effect>,
+}
+
+impl std::fmt::Debug for GilrsRumble {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut playing: Vec<_> = self.effects.keys().collect();
+        playing.sort_unstable();
+        f.debug_struct("GilrsRumble")
+            .field("playing", &playing)
+            .finish_non_exhaustive()
+    }
+}
+
+impl GilrsRumble {
+    pub fn new() -> anyhow::Result<Self> {
+        // gilrs::Error は Gilrs を含んでいて Sync ではないので、文字列にする
+        let gilrs = Gilrs::new().map_err(|e| anyhow!("failed: initialize gilrs: {e}"))?;
+        Ok(Self::from_gilrs(gilrs))
+    }
+
+    pub fn from_gilrs(gilrs: Gilrs) -> Self {
+        Self {
+            gilrs: Mutex::new(gilrs),
+            effects: HashMap::new(),
+        }
+    }
+}
+
+/// 溜まったイベントを読み捨て、つながっているゲームパッドの一覧を新しくする
+fn poll(gilrs: &mut Gilrs) {
+    while gilrs.next_event().is_some() {}
+}
+
+/// `gamepad` がつながっていて force feedback に対応していれば、gilrs での ID
+fn ff_gamepad(gilrs: &Gilrs, gamepad: GamepadId) -> Option<gilrs::GamepadId> {
+    gilrs
+        .gamepads()
+        .find(|(id, _)| usize::from(*id) == gamepad.0 as usize)
+        .filter(|(_, pad)| pad.is_ff_supported())
+        .map(|(id, _)| id)
+}
+
+/// 0.0 から 1.0 の強さを gilrs の強さにする
+fn magnitude(strength: f32) -> u16 {
+    (strength.clamp(0.0, 1.0) * f32::from(u16::MAX)).round() as u16
+}
+
+impl RumbleBackend for GilrsRumble {
+    fn supports_rumble(&self, gamepad: GamepadId) -> bool {
+        let mut gilrs = self.gilrs.lock().unwrap_or_else(PoisonError::into_inner);
+        poll(&mut gilrs);
+        ff_gamepad(&gilrs, gamepad).is_some()
+    }
+
+    fn set_rumble(&mut self, gamepad: GamepadId, low_freq: f32, high_freq: f32) {
+        self.effects.remove(&gamepad);
+        if low_freq <= 0.0 && high_freq <= 0.0 {
+            return;
+        }
+        let gilrs = self.gilrs.get_mut().unwrap_or_else(PoisonError::into_inner);
+        let Some(id) = ff_gamepad(gilrs, gamepad) else {
+            return;
+        };
+        let effect = EffectBuilder::new()
+            .add_effect(BaseEffect {
+                kind: BaseEffectType::Strong {
+                    magnitude: magnitude(low_freq),
+                },
+                ..Default::default()
+            })
+            .add_effect(BaseEffect {
+                kind: BaseEffectType::Weak {
+                    magnitude: magnitude(high_freq),
+                },
+                ..Default::default()
+            })
+            .gamepads(&[id])
+            .finish(gilrs)
+            .and_then(|effect| effect.play().map(|()| effect));
+        match effect {
+            Ok(effect) => {
+                self.effects.insert(gamepad, effect);
+            }
+            Err(error) => tracing::warn!("failed to rumble gamepad {}: {error}", gamepad.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strength_maps_to_full_magnitude_range() {
+        assert_eq!(magnitude(0.0), 0);
+        assert_eq!(magnitude(0.5), 32768);
+        assert_eq!(magnitude(1.0), u16::MAX);
+        assert_eq!(magnitude(3.0), u16::MAX);
+    }
+
+    #[test]
+    fn unknown_gamepad_is_ignored() {
+        let mut backend = match GilrsRumble::new() {
+            Ok(backend) => backend,
+            Err(error) => {
+                eprintln!("skipped: {error}");
+                return;
+            }
+        };
+        let missing = GamepadId(u32::MAX);
+        assert!(!backend.supports_rumble(missing));
+        backend.set_rumble(missing, 1.0, 1.0);
+        assert!(backend.effects.is_empty());
+    }
+}
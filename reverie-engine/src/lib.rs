@@ -1,12 +1,51 @@
 #![deny(rust_2018_idioms)]
 #![deny(clippy::all)]
 #![deny(clippy::nursery)]
+// Web の Future は Send にならない
+#![cfg_attr(target_arch = "wasm32", allow(clippy::future_not_send))]
 
+pub mod accessibility;
+pub mod audio;
+pub mod bounds;
+pub mod fps_camera;
+#[cfg(feature = "backend-wgpu")]
 mod game;
+pub mod headless;
+pub mod input;
+pub mod lifetime;
+pub mod navmesh;
+pub mod orbit_camera;
+pub mod particle;
+pub mod path_follow;
+pub mod physics;
+pub mod prelude;
 pub mod scene;
+pub mod settings;
+#[cfg(feature = "test-harness")]
+pub mod test_harness;
+pub mod text;
 pub mod texture;
+#[cfg(feature = "tools")]
+pub mod tools;
+pub mod touch;
+pub mod ui;
+#[cfg(feature = "backend-wgpu")]
 pub mod wgpu_wrapper;
+#[cfg(feature = "backend-wgpu")]
 mod winit_app;
 
+#[cfg(feature = "backend-opengl")]
+/// OpenGL で描画するエンジン
+///
+/// feature `backend-opengl` を有効にすると使える。ウィンドウと描画は OpenGL 側が持つので、
+/// シーンは [`headless::HeadlessRunner`] などで更新する。
+pub use reverie_engine_opengl as opengl;
+
+#[cfg(feature = "backend-wgpu")]
 pub use game::start_engine;
+#[cfg(feature = "backend-wgpu")]
+pub use game::start_engine_with_config;
+#[cfg(feature = "backend-wgpu")]
+pub use game::EngineConfig;
+#[cfg(feature = "backend-wgpu")]
 pub use game::Game;
@@ -0,0 +1,234 @@
+//! 一定時間が経ったエンティティを自動で削除する
+use std::time::Duration;
+
+use crate::scene::{
+    clear_events, for_each_ordered, send_event, EntityIndex, Frame, RenderResource,
+    SpriteComponent, System,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// エンティティの寿命の長さ
+pub enum Lifespan {
+    /// シミュレーションの時間 (秒)。[`crate::scene::TimeScale`] に従って進む
+    Seconds(f32),
+    /// フレーム数。一時停止中 (`delta_time` が 0) のフレームは数えない
+    Frames(u32),
+}
+
+impl Lifespan {
+    const fn amount(self) -> f32 {
+        match self {
+            Self::Seconds(seconds) => seconds,
+            Self::Frames(frames) => frames as f32,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// 寿命が尽きたら [`LifetimeSystem`] に削除されるエンティティの印
+///
+/// マズルフラッシュや爆発のエフェクトのように、すぐに消えるエンティティに付ける。
+pub struct LifetimeComponent {
+    lifespan: Lifespan,
+    /// 残りの秒数またはフレーム数
+    remaining: f32,
+    /// 寿命の最後のこの割合の間にスプライトを透明にしていく
+    fade_out: Option<f32>,
+    /// フェードアウトを始める前のスプライトの不透明度
+    base_alpha: Option<f32>,
+}
+
+impl LifetimeComponent {
+    pub const fn new(lifespan: Lifespan) -> Self {
+        Self {
+            lifespan,
+            remaining: lifespan.amount(),
+            fade_out: None,
+            base_alpha: None,
+        }
+    }
+
+    pub const fn seconds(seconds: f32) -> Self {
+        Self::new(Lifespan::Seconds(seconds))
+    }
+
+    pub const fn frames(frames: u32) -> Self {
+        Self::new(Lifespan::Frames(frames))
+    }
+
+    /// 寿命の最後の`fraction` (0.0 から 1.0) の間に [`SpriteComponent`] の不透明度を 0 に近づける
+    pub const fn with_fade_out(mut self, fraction: f32) -> Self {
+        self.fade_out = Some(fraction);
+        self
+    }
+
+    pub const fn lifespan(&self) -> Lifespan {
+        self.lifespan
+    }
+
+    /// 残りの寿命の割合。生まれたときが 1.0、尽きたときが 0.0
+    pub fn remaining_fraction(&self) -> f32 {
+        let total = self.lifespan.amount();
+        if total <= 0.0 {
+            return 0.0;
+        }
+        (self.remaining / total).clamp(0.0, 1.0)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.remaining <= 0.0
+    }
+
+    /// 寿命を 1 フレーム分減らす
+    pub fn tick(&mut self, delta_time: Duration) {
+        match self.lifespan {
+            Lifespan::Seconds(_) => self.remaining -= delta_time.as_secs_f32(),
+            Lifespan::Frames(_) if !delta_time.is_zero() => self.remaining -= 1.0,
+            Lifespan::Frames(_) => {}
+        }
+    }
+
+    /// フェードアウトの分を掛けた不透明度の係数
+    ///
+    /// フェードアウトしないときや、まだ始まっていないときは 1.0。
+    pub fn fade_factor(&self) -> f32 {
+        match self.fade_out {
+            Some(fraction) if fraction > 0.0 => (self.remaining_fraction() / fraction).min(1.0),
+            _ => 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// [`LifetimeComponent`] の寿命が尽きてエンティティが削除された
+///
+/// [`crate::scene::Events`] として送られる。
+pub struct Expired(pub EntityIndex);
+
+#[derive(Default)]
+/// [`LifetimeComponent`] の寿命を減らし、尽きたエンティティを削除する
+///
+/// 削除はクエリの後にまとめて行う。[`crate::scene::Determinism`] があれば、作られた順に削除する。
+pub struct LifetimeSystem {
+    commands: hecs::CommandBuffer,
+}
+
+impl std::fmt::Debug for LifetimeSystem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LifetimeSystem").finish_non_exhaustive()
+    }
+}
+
+impl LifetimeSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 寿命を`delta_time`だけ減らす
+    ///
+    /// [`System::update`] から呼ばれる。GPU に触れないのでテストからも直接呼べる。
+    pub fn apply(&mut self, world: &mut hecs::World, delta_time: Duration) {
+        clear_events::<Expired>(world);
+
+        let mut expired = Vec::new();
+        for_each_ordered::<(&mut LifetimeComponent, Option<&mut SpriteComponent>)>(
+            world,
+            |entity, (lifetime, sprite)| {
+                lifetime.tick(delta_time);
+                if lifetime.is_expired() {
+                    self.commands.despawn(entity);
+                    expired.push(entity);
+                    return;
+                }
+                if let (Some(sprite), Some(_)) = (sprite, lifetime.fade_out) {
+                    let mut tint = sprite.tint();
+                    let base_alpha = *lifetime.base_alpha.get_or_insert(tint.a);
+                    tint.a = base_alpha * lifetime.fade_factor();
+                    sprite.set_tint(tint);
+                }
+            },
+        );
+        self.commands.run_on(world);
+        for entity in expired {
+            send_event(world, Expired(EntityIndex(entity)));
+        }
+    }
+}
+
+impl System for LifetimeSystem {
+    fn setup(&mut self, _resource: Option<&RenderResource<'_>>) {}
+
+    fn update(
+        &mut self,
+        frame: &Frame<'_>,
+        world: &mut hecs::World,
+        _resource: Option<&RenderResource<'_>>,
+    ) {
+        self.apply(world, frame.delta_time);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        scene::{resource, Events},
+        texture::{TextureId, TextureRegistry},
+    };
+
+    const FRAME: Duration = Duration::from_millis(50);
+
+    fn sprite() -> SpriteComponent {
+        let mut registry = TextureRegistry::default();
+        let texture = registry.new_texture(image::RgbaImage::new(1, 1), None);
+        SpriteComponent::new(TextureId::Single(texture))
+    }
+
+    #[test]
+    fn expires_after_seconds_and_sends_event() {
+        let mut world = hecs::World::new();
+        let flash = world.spawn((LifetimeComponent::seconds(0.1),));
+        let mut system = LifetimeSystem::new();
+
+        system.apply(&mut world, FRAME);
+        assert!(world.contains(flash));
+        // 一時停止中は進まない
+        system.apply(&mut world, Duration::ZERO);
+        assert!(world.contains(flash));
+        system.apply(&mut world, FRAME);
+        assert!(!world.contains(flash));
+
+        let events = resource::<Events<Expired>>(&world).unwrap();
+        assert_eq!(
+            events.iter().copied().collect::<Vec<_>>(),
+            [Expired(EntityIndex(flash))]
+        );
+    }
+
+    #[test]
+    fn frames_skip_paused_frames() {
+        let mut world = hecs::World::new();
+        let entity = world.spawn((LifetimeComponent::frames(2),));
+        let mut system = LifetimeSystem::new();
+        system.apply(&mut world, FRAME);
+        system.apply(&mut world, Duration::ZERO);
+        assert!(world.contains(entity));
+        system.apply(&mut world, FRAME);
+        assert!(!world.contains(entity));
+    }
+
+    #[test]
+    fn fades_out_over_last_fraction() {
+        let mut world = hecs::World::new();
+        let entity = world.spawn((LifetimeComponent::frames(4).with_fade_out(0.5), sprite()));
+        let mut system = LifetimeSystem::new();
+        let alpha = |world: &hecs::World| world.get::<&SpriteComponent>(entity).unwrap().tint().a;
+
+        system.apply(&mut world, FRAME);
+        assert_eq!(alpha(&world), 1.0);
+        system.apply(&mut world, FRAME);
+        assert_eq!(alpha(&world), 1.0);
+        system.apply(&mut world, FRAME);
+        assert_eq!(alpha(&world), 0.5);
+    }
+}
@@ -0,0 +1,44 @@
+// マスクの形をステンシルバッファだけに描く。色は書き込まない
+
+struct VertexInput {
+  @location(0) position: vec3<f32>,
+  @location(1) uv: vec2<f32>,
+  @location(2) color: vec4<f32>
+}
+
+struct VertexOutput {
+  @location(0) uv: vec2<f32>,
+  @location(1) color: vec4<f32>,
+  @builtin(position) position: vec4<f32>
+}
+
+@group(0)
+@binding(0)
+var tex: texture_2d<f32>;
+
+@group(0)
+@binding(1)
+var samp: sampler;
+
+@group(1)
+@binding(0)
+var<uniform> transform: mat4x4<f32>;
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+  var out: VertexOutput;
+  out.uv = in.uv;
+  out.color = in.color;
+  out.position = transform * vec4<f32>(in.position, 1.0);
+  return out;
+}
+
+// 不透明度が半分に満たない画素はマスクの外側にする
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+  let color = textureSample(tex, samp, in.uv) * in.color;
+  if color.a < 0.5 {
+    discard;
+  }
+  return color;
+}
@@ -0,0 +1,635 @@
+//! AI エージェントのための経路探索を行うモジュール
+//!
+//! 入力された三角形のうち歩行可能なものをそのままナビゲーションポリゴンとして使う。
+//! Recast のようなボクセル化や watershed による領域分割は行わないので、
+//! 入力メッシュは歩行面を適度に分割したものである必要がある。
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
+};
+
+use nalgebra::{Point3, Vector3};
+#[cfg(feature = "backend-wgpu")]
+use reverie_util::color::Color;
+
+use crate::scene::{Frame, RenderResource, System, TransformComponent};
+#[cfg(feature = "backend-wgpu")]
+use crate::wgpu_wrapper::debug_draw::DebugDraw;
+
+/// 歩行可能とみなす斜面の最大角度の cos (45°)
+const WALKABLE_SLOPE_COS: f32 = std::f32::consts::FRAC_1_SQRT_2;
+/// 頂点を同一とみなす距離
+const WELD_EPSILON: f32 = 1.0e-4;
+
+#[derive(Debug, Clone)]
+/// ナビゲーションメッシュ
+///
+/// 上方向は +Y とする。
+pub struct NavMesh {
+    vertices: Vec<Point3<f32>>,
+    polygons: Vec<NavPolygon>,
+    agent_radius: f32,
+}
+
+/// 近い頂点を 1 つにまとめる
+///
+/// `WELD_EPSILON` の大きさのセルに頂点を登録しておき、周囲 27 セルだけを調べる。
+#[derive(Debug, Default)]
+struct VertexWelder {
+    vertices: Vec<Point3<f32>>,
+    cells: HashMap<[i64; 3], Vec<usize>>,
+}
+
+impl VertexWelder {
+    fn cell(p: &Point3<f32>) -> [i64; 3] {
+        [0, 1, 2].map(|k| (p[k] / WELD_EPSILON).floor() as i64)
+    }
+
+    fn weld(&mut self, p: Point3<f32>) -> usize {
+        let [x, y, z] = Self::cell(&p);
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    let Some(candidates) = self.cells.get(&[x + dx, y + dy, z + dz]) else {
+                        continue;
+                    };
+                    if let Some(&i) = candidates.iter().find(|&&i| {
+                        (self.vertices[i] - p).norm_squared() <= WELD_EPSILON * WELD_EPSILON
+                    }) {
+                        return i;
+                    }
+                }
+            }
+        }
+        self.vertices.push(p);
+        let index = self.vertices.len() - 1;
+        self.cells.entry([x, y, z]).or_default().push(index);
+        index
+    }
+}
+
+#[derive(Debug, Clone)]
+struct NavPolygon {
+    indices: [usize; 3],
+    centroid: Point3<f32>,
+    /// 隣接するポリゴンと、共有する辺の頂点インデックス
+    neighbors: Vec<(usize, [usize; 2])>,
+}
+
+impl NavMesh {
+    /// 三角形の集合からナビゲーションメッシュを作る
+    ///
+    /// * `triangles`: ワールド座標の三角形。裏表は問わない
+    /// * `agent_radius`: エージェントの半径。通路の角からこの距離だけ離れた経路が生成される
+    /// * `agent_height`: エージェントの高さ。この高さより低い天井の下にある三角形は歩行不可能になる
+    ///
+    /// 頂点の結合はハッシュグリッドで行うので頂点数にほぼ比例する時間で済むが、
+    /// 天井の判定は三角形ごとに全ての三角形を調べるので三角形数の 2 乗に比例する時間がかかる。
+    pub fn from_geometry(
+        triangles: &[(Point3<f32>, Point3<f32>, Point3<f32>)],
+        agent_radius: f32,
+        agent_height: f32,
+    ) -> Self {
+        let mut welder = VertexWelder::default();
+
+        let mut indices = Vec::new();
+        for (a, b, c) in triangles {
+            let normal = (b - a).cross(&(c - a));
+            let Some(normal) = normal.try_normalize(f32::EPSILON) else {
+                continue;
+            };
+            if normal.y.abs() < WALKABLE_SLOPE_COS {
+                continue;
+            }
+            let centroid = Point3::from((a.coords + b.coords + c.coords) / 3.0);
+            let blocked = triangles.iter().any(|(d, e, f)| {
+                ray_up_hits_triangle(&centroid, d, e, f)
+                    .is_some_and(|t| t > WELD_EPSILON && t < agent_height)
+            });
+            if blocked {
+                continue;
+            }
+            indices.push([welder.weld(*a), welder.weld(*b), welder.weld(*c)]);
+        }
+
+        let vertices = welder.vertices;
+        let mut edges: HashMap<[usize; 2], Vec<usize>> = HashMap::new();
+        for (p, tri) in indices.iter().enumerate() {
+            for k in 0..3 {
+                let (i, j) = (tri[k], tri[(k + 1) % 3]);
+                edges.entry([i.min(j), i.max(j)]).or_default().push(p);
+            }
+        }
+
+        let mut polygons: Vec<NavPolygon> = indices
+            .iter()
+            .map(|tri| NavPolygon {
+                indices: *tri,
+                centroid: Point3::from(
+                    (vertices[tri[0]].coords + vertices[tri[1]].coords + vertices[tri[2]].coords)
+                        / 3.0,
+                ),
+                neighbors: Vec::new(),
+            })
+            .collect();
+        for (edge, shared) in edges {
+            for &p in &shared {
+                for &q in &shared {
+                    if p != q {
+                        polygons[p].neighbors.push((q, edge));
+                    }
+                }
+            }
+        }
+
+        Self {
+            vertices,
+            polygons,
+            agent_radius,
+        }
+    }
+
+    /// ナビゲーションポリゴンの数
+    pub fn polygon_count(&self) -> usize {
+        self.polygons.len()
+    }
+
+    /// 各ポリゴンの辺を線分として列挙する
+    ///
+    /// デバッグ表示に使う。共有されている辺は2回列挙される。
+    pub fn edges(&self) -> impl Iterator<Item = (Point3<f32>, Point3<f32>)> + '_ {
+        self.polygons.iter().flat_map(move |p| {
+            (0..3).map(move |k| {
+                (
+                    self.vertices[p.indices[k]],
+                    self.vertices[p.indices[(k + 1) % 3]],
+                )
+            })
+        })
+    }
+
+    /// デバッグ表示の既定の色 (黄色)
+    #[cfg(feature = "backend-wgpu")]
+    pub const DEBUG_COLOR: Color = Color::rgb(1.0, 1.0, 0.0);
+
+    /// 各ポリゴンの辺をデバッグ表示する
+    ///
+    /// 色は呼び出し側が選ぶ。特にこだわりが無ければ [`Self::DEBUG_COLOR`] を渡す。
+    #[cfg(feature = "backend-wgpu")]
+    pub fn debug_draw(&self, debug: &DebugDraw, color: Color) {
+        for (from, to) in self.edges() {
+            debug.line(from, to, color);
+        }
+    }
+
+    /// `start` から `goal` までの経路を探す
+    ///
+    /// 経路はポリゴンのグラフ上の A* で求め、ファネルアルゴリズムで滑らかにする。
+    /// 返される経路は `start` で始まり `goal` で終わる。
+    /// どちらかの点がメッシュ上に無い場合や、到達できない場合は `None` を返す。
+    pub fn find_path(&self, start: Point3<f32>, goal: Point3<f32>) -> Option<Vec<Point3<f32>>> {
+        let start_poly = self.locate(&start)?;
+        let goal_poly = self.locate(&goal)?;
+        let corridor = self.search(start_poly, goal_poly, &goal)?;
+
+        let mut portals = Vec::with_capacity(corridor.len() + 1);
+        portals.push((start, start));
+        for w in corridor.windows(2) {
+            let (from, to) = (w[0], w[1]);
+            let edge = self.polygons[from]
+                .neighbors
+                .iter()
+                .find(|(n, _)| *n == to)
+                .map(|(_, e)| *e)?;
+            portals.push(self.portal(from, edge));
+        }
+        portals.push((goal, goal));
+
+        Some(string_pull(&portals))
+    }
+
+    /// 点を含むポリゴンを探す
+    ///
+    /// XZ 平面上で点を含むポリゴンのうち、高さが最も近いものを返す。
+    fn locate(&self, point: &Point3<f32>) -> Option<usize> {
+        self.polygons
+            .iter()
+            .enumerate()
+            .filter_map(|(i, p)| {
+                let [a, b, c] = p.indices.map(|k| self.vertices[k]);
+                barycentric_xz(point, &a, &b, &c)
+                    .map(|weights| (i, (interpolate_y(&a, &b, &c, weights) - point.y).abs()))
+            })
+            .min_by(|x, y| x.1.total_cmp(&y.1))
+            .map(|(i, _)| i)
+    }
+
+    fn search(&self, start: usize, goal: usize, goal_point: &Point3<f32>) -> Option<Vec<usize>> {
+        let mut open = BinaryHeap::new();
+        let mut came_from: HashMap<usize, usize> = HashMap::new();
+        let mut cost = vec![f32::INFINITY; self.polygons.len()];
+        cost[start] = 0.0;
+        open.push(OpenNode {
+            polygon: start,
+            estimate: (self.polygons[start].centroid - goal_point).norm(),
+        });
+
+        while let Some(OpenNode { polygon, .. }) = open.pop() {
+            if polygon == goal {
+                let mut corridor = vec![goal];
+                let mut current = goal;
+                while let Some(&prev) = came_from.get(&current) {
+                    corridor.push(prev);
+                    current = prev;
+                }
+                corridor.reverse();
+                return Some(corridor);
+            }
+            let here = &self.polygons[polygon];
+            for &(next, _) in &here.neighbors {
+                let next_cost =
+                    cost[polygon] + (self.polygons[next].centroid - here.centroid).norm();
+                if next_cost < cost[next] {
+                    cost[next] = next_cost;
+                    came_from.insert(next, polygon);
+                    open.push(OpenNode {
+                        polygon: next,
+                        estimate: next_cost + (self.polygons[next].centroid - goal_point).norm(),
+                    });
+                }
+            }
+        }
+        None
+    }
+
+    /// `from` から見た左右の端点を返す。端点はエージェントの半径だけ内側に寄せる
+    fn portal(&self, from: usize, edge: [usize; 2]) -> (Point3<f32>, Point3<f32>) {
+        let a = self.vertices[edge[0]];
+        let b = self.vertices[edge[1]];
+        let center = self.polygons[from].centroid;
+        let (left, right) = if cross_xz(&(a - center), &(b - center)) > 0.0 {
+            (b, a)
+        } else {
+            (a, b)
+        };
+
+        let width = (right - left).norm();
+        if width <= f32::EPSILON {
+            return (left, right);
+        }
+        let inset = self.agent_radius.min(width / 2.0);
+        let dir = (right - left) / width;
+        (left + dir * inset, right - dir * inset)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct OpenNode {
+    polygon: usize,
+    estimate: f32,
+}
+
+impl Eq for OpenNode {}
+
+impl Ord for OpenNode {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // BinaryHeap は最大ヒープなので逆順にする
+        other.estimate.total_cmp(&self.estimate)
+    }
+}
+
+impl PartialOrd for OpenNode {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// XZ 平面上での外積の Y 成分
+///
+/// 上 (+Y) から見て `a` から `b` への回転が時計回りなら負になる。
+fn cross_xz(a: &Vector3<f32>, b: &Vector3<f32>) -> f32 {
+    a.z.mul_add(b.x, -(a.x * b.z))
+}
+
+fn barycentric_xz(
+    p: &Point3<f32>,
+    a: &Point3<f32>,
+    b: &Point3<f32>,
+    c: &Point3<f32>,
+) -> Option<(f32, f32, f32)> {
+    let det = (b.z - c.z).mul_add(a.x - c.x, (c.x - b.x) * (a.z - c.z));
+    if det.abs() <= f32::EPSILON {
+        return None;
+    }
+    let u = (b.z - c.z).mul_add(p.x - c.x, (c.x - b.x) * (p.z - c.z)) / det;
+    let v = (c.z - a.z).mul_add(p.x - c.x, (a.x - c.x) * (p.z - c.z)) / det;
+    let w = 1.0 - u - v;
+    const E: f32 = -1.0e-5;
+    (u >= E && v >= E && w >= E).then_some((u, v, w))
+}
+
+fn interpolate_y(
+    a: &Point3<f32>,
+    b: &Point3<f32>,
+    c: &Point3<f32>,
+    (u, v, w): (f32, f32, f32),
+) -> f32 {
+    a.y.mul_add(u, b.y.mul_add(v, c.y * w))
+}
+
+/// `origin` から +Y 方向に伸ばした半直線と三角形の交点までの距離
+fn ray_up_hits_triangle(
+    origin: &Point3<f32>,
+    a: &Point3<f32>,
+    b: &Point3<f32>,
+    c: &Point3<f32>,
+) -> Option<f32> {
+    let weights = barycentric_xz(origin, a, b, c)?;
+    Some(interpolate_y(a, b, c, weights) - origin.y)
+}
+
+/// Simple Stupid Funnel Algorithm
+///
+/// `portals` の最初と最後は始点と終点を両端とする縮退したポータルである必要がある。
+fn string_pull(portals: &[(Point3<f32>, Point3<f32>)]) -> Vec<Point3<f32>> {
+    let mut path = vec![portals[0].0];
+    let mut apex = portals[0].0;
+    let (mut left, mut right) = portals[0];
+    let (mut left_index, mut right_index) = (0, 0);
+
+    let mut i = 1;
+    while i < portals.len() {
+        let (new_left, new_right) = portals[i];
+
+        // 右側の境界を狭める
+        if cross_xz(&(right - apex), &(new_right - apex)) >= 0.0 {
+            if apex == right || cross_xz(&(left - apex), &(new_right - apex)) < 0.0 {
+                right = new_right;
+                right_index = i;
+            } else {
+                // 右側が左側を越えたので、左の点を経由点にする
+                apex = left;
+                let apex_index = left_index;
+                push_unique(&mut path, apex);
+                left = apex;
+                right = apex;
+                left_index = apex_index;
+                right_index = apex_index;
+                i = apex_index + 1;
+                continue;
+            }
+        }
+
+        // 左側の境界を狭める
+        if cross_xz(&(left - apex), &(new_left - apex)) <= 0.0 {
+            if apex == left || cross_xz(&(right - apex), &(new_left - apex)) > 0.0 {
+                left = new_left;
+                left_index = i;
+            } else {
+                apex = right;
+                let apex_index = right_index;
+                push_unique(&mut path, apex);
+                left = apex;
+                right = apex;
+                left_index = apex_index;
+                right_index = apex_index;
+                i = apex_index + 1;
+                continue;
+            }
+        }
+
+        i += 1;
+    }
+
+    push_unique(&mut path, portals[portals.len() - 1].0);
+    path
+}
+
+fn push_unique(path: &mut Vec<Point3<f32>>, point: Point3<f32>) {
+    if path.last() != Some(&point) {
+        path.push(point);
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// 経路に沿ってエンティティを移動させるためのコンポーネント
+///
+/// [`PathFollowerSystem`] によって `path` の先頭の点に向かって移動し、
+/// 到達した点は `path` から取り除かれる。
+pub struct NavMeshAgentComponent {
+    /// これから通る経由点
+    pub path: Vec<Point3<f32>>,
+    /// 1秒あたりの移動距離
+    pub speed: f32,
+    /// 経由点に到達したとみなす距離
+    ///
+    /// これより近い経由点へは進まず、そのまま次の経由点へ向かう。1 回の更新で `speed` より先へ飛ぶことはない。
+    pub arrived_threshold: f32,
+}
+
+impl NavMeshAgentComponent {
+    pub const fn new(speed: f32, arrived_threshold: f32) -> Self {
+        Self {
+            path: Vec::new(),
+            speed,
+            arrived_threshold,
+        }
+    }
+
+    /// 経路を使い切ったかどうか
+    pub fn has_arrived(&self) -> bool {
+        self.path.is_empty()
+    }
+}
+
+#[derive(Debug, Default)]
+/// [`NavMeshAgentComponent`] を持つエンティティを経路に沿って移動させるシステム
+pub struct PathFollowerSystem;
+
+impl PathFollowerSystem {
+    fn step(agent: &mut NavMeshAgentComponent, transform: &mut TransformComponent, dt: f32) {
+        let mut budget = (agent.speed * dt).max(0.0);
+        while let Some(&target) = agent.path.first() {
+            let position = Point3::from(transform.translation.vector);
+            let to_target = target - position;
+            let distance = to_target.norm();
+            if distance <= budget {
+                transform.translation.vector = target.coords;
+                budget = (budget - distance).max(0.0);
+                agent.path.remove(0);
+                if budget == 0.0 {
+                    break;
+                }
+                continue;
+            }
+            if distance <= agent.arrived_threshold {
+                // 届いたとみなすだけで、経由点まで飛ばない
+                agent.path.remove(0);
+                continue;
+            }
+            transform.translation.vector += to_target / distance * budget;
+            break;
+        }
+    }
+}
+
+impl System for PathFollowerSystem {
+    fn setup(&mut self, _resource: Option<&RenderResource<'_>>) {}
+
+    fn update(
+        &mut self,
+        frame: &Frame<'_>,
+        world: &mut hecs::World,
+        _resource: Option<&RenderResource<'_>>,
+    ) {
+        let dt = frame.delta_time.as_secs_f32();
+        for (_, (agent, transform)) in
+            world.query_mut::<(&mut NavMeshAgentComponent, &mut TransformComponent)>()
+        {
+            Self::step(agent, transform, dt);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Translation3;
+
+    use super::*;
+
+    fn quad(
+        x0: f32,
+        z0: f32,
+        x1: f32,
+        z1: f32,
+        y: f32,
+    ) -> [(Point3<f32>, Point3<f32>, Point3<f32>); 2] {
+        let a = Point3::new(x0, y, z0);
+        let b = Point3::new(x1, y, z0);
+        let c = Point3::new(x1, y, z1);
+        let d = Point3::new(x0, y, z1);
+        [(a, b, c), (a, c, d)]
+    }
+
+    /// (0,0)-(3,1) と (2,1)-(3,3) からなる L 字型の通路
+    fn l_shape() -> Vec<(Point3<f32>, Point3<f32>, Point3<f32>)> {
+        let mut t = Vec::new();
+        t.extend(quad(0.0, 0.0, 1.0, 1.0, 0.0));
+        t.extend(quad(1.0, 0.0, 2.0, 1.0, 0.0));
+        t.extend(quad(2.0, 0.0, 3.0, 1.0, 0.0));
+        t.extend(quad(2.0, 1.0, 3.0, 2.0, 0.0));
+        t.extend(quad(2.0, 2.0, 3.0, 3.0, 0.0));
+        t
+    }
+
+    #[test]
+    fn straight_path_has_no_waypoints() {
+        let mesh = NavMesh::from_geometry(&quad(0.0, 0.0, 4.0, 4.0, 0.0), 0.0, 2.0);
+        let start = Point3::new(0.5, 0.0, 0.5);
+        let goal = Point3::new(3.5, 0.0, 3.5);
+        assert_eq!(mesh.find_path(start, goal), Some(vec![start, goal]));
+    }
+
+    #[test]
+    fn path_bends_around_corner() {
+        let mesh = NavMesh::from_geometry(&l_shape(), 0.0, 2.0);
+        let start = Point3::new(0.5, 0.0, 0.5);
+        let goal = Point3::new(2.5, 0.0, 2.5);
+        let path = mesh.find_path(start, goal).unwrap();
+        assert_eq!(path.first(), Some(&start));
+        assert_eq!(path.last(), Some(&goal));
+        assert_eq!(path.len(), 3);
+        assert!((path[1] - Point3::new(2.0, 0.0, 1.0)).norm() < 1.0e-4);
+    }
+
+    #[test]
+    fn agent_radius_keeps_path_off_corner() {
+        let mesh = NavMesh::from_geometry(&l_shape(), 0.25, 2.0);
+        let path = mesh
+            .find_path(Point3::new(0.5, 0.0, 0.5), Point3::new(2.5, 0.0, 2.5))
+            .unwrap();
+        assert!((path[1] - Point3::new(2.0, 0.0, 1.0)).norm() > 0.2);
+    }
+
+    #[test]
+    fn unreachable_goal() {
+        let mut t = quad(0.0, 0.0, 1.0, 1.0, 0.0).to_vec();
+        t.extend(quad(5.0, 5.0, 6.0, 6.0, 0.0));
+        let mesh = NavMesh::from_geometry(&t, 0.0, 2.0);
+        assert_eq!(
+            mesh.find_path(Point3::new(0.5, 0.0, 0.5), Point3::new(5.5, 0.0, 5.5)),
+            None
+        );
+        assert_eq!(
+            mesh.find_path(Point3::new(0.5, 0.0, 0.5), Point3::new(3.0, 0.0, 3.0)),
+            None
+        );
+    }
+
+    #[test]
+    fn steep_and_low_ceiling_triangles_are_removed() {
+        let mut t = quad(0.0, 0.0, 1.0, 1.0, 0.0).to_vec();
+        // 壁
+        t.push((
+            Point3::new(0.0, 0.0, 2.0),
+            Point3::new(1.0, 0.0, 2.0),
+            Point3::new(1.0, 1.0, 2.0),
+        ));
+        // 低い天井のある床
+        t.extend(quad(3.0, 0.0, 4.0, 1.0, 0.0));
+        t.extend(quad(3.0, 0.0, 4.0, 1.0, 1.0));
+        let mesh = NavMesh::from_geometry(&t, 0.0, 1.5);
+        // 天井自身は歩行可能なので残る
+        assert_eq!(mesh.polygon_count(), 4);
+    }
+
+    #[test]
+    fn path_follower_moves_along_path() {
+        let mut agent = NavMeshAgentComponent::new(1.0, 0.01);
+        agent.path = vec![Point3::new(1.0, 0.0, 0.0), Point3::new(1.0, 0.0, 1.0)];
+        let mut transform = TransformComponent::with_translation(Translation3::new(0.0, 0.0, 0.0));
+
+        PathFollowerSystem::step(&mut agent, &mut transform, 1.5);
+        assert!((transform.translation.vector - Vector3::new(1.0, 0.0, 0.5)).norm() < 1.0e-5);
+        assert_eq!(agent.path.len(), 1);
+
+        PathFollowerSystem::step(&mut agent, &mut transform, 1.0);
+        assert!(agent.has_arrived());
+        assert_eq!(transform.translation.vector, Vector3::new(1.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn nearby_vertices_are_welded() {
+        let mut welder = VertexWelder::default();
+        let a = welder.weld(Point3::new(1.0, 0.0, 0.0));
+        // セルの境界をまたいでいても結合される
+        let b = welder.weld(Point3::new(0.999_95, 0.0, 0.0));
+        let c = welder.weld(Point3::new(1.000_3, 0.0, 0.0));
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_eq!(welder.vertices.len(), 2);
+    }
+
+    #[test]
+    fn arrived_threshold_never_exceeds_speed() {
+        // 1 回の更新で進めるのは 0.1 だけだが、0.4 先の経由点は届いたとみなす
+        let mut agent = NavMeshAgentComponent::new(1.0, 0.5);
+        agent.path = vec![Point3::new(0.4, 0.0, 0.0), Point3::new(0.4, 0.0, 1.0)];
+        let mut transform = TransformComponent::with_translation(Translation3::new(0.0, 0.0, 0.0));
+        let goal = Vector3::new(0.4, 0.0, 1.0);
+
+        let mut previous = goal.norm();
+        for _ in 0..5 {
+            let before = transform.translation.vector;
+            PathFollowerSystem::step(&mut agent, &mut transform, 0.1);
+            let moved = (transform.translation.vector - before).norm();
+            assert!(moved <= 0.1 + 1.0e-6, "moved {moved}");
+            // 次の経由点から遠ざかることはない
+            let remaining = (goal - transform.translation.vector).norm();
+            assert!(remaining < previous);
+            previous = remaining;
+        }
+        assert_eq!(agent.path.len(), 1);
+        assert!((previous - (goal.norm() - 0.5)).abs() < 1.0e-5);
+    }
+}
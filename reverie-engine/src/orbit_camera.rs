@@ -0,0 +1,102 @@
+//! エディタのように注視点の周りを回るカメラをマウスで動かす
+use std::time::Duration;
+
+use reverie_util::math::orbit_camera::{OrbitCameraController, OrbitInput};
+
+use crate::{
+    input::Input,
+    scene::{
+        resource, CameraComponent, Frame, Projection, RenderResource, System, TransformComponent,
+    },
+};
+
+#[derive(Debug, Clone)]
+/// [`OrbitCameraController`] で動かすカメラの印
+///
+/// [`OrbitCameraSystem`] が毎フレーム [`TransformComponent`] を書き換える。
+/// [`CameraComponent`] があれば、視野角と描画する距離も [`OrbitCameraController`] に合わせる。
+pub struct OrbitCameraComponent(pub OrbitCameraController);
+
+impl OrbitCameraComponent {
+    fn apply_to(&self, transform: &mut TransformComponent, camera: Option<&mut CameraComponent>) {
+        let isometry = self.0.isometry();
+        transform.translation = isometry.translation;
+        transform.rotation = isometry.rotation;
+        if let Some(camera) = camera {
+            camera.projection = Projection::Perspective {
+                fovy: self.0.fov.to_rad().into(),
+                near: self.0.near,
+                far: self.0.far,
+            };
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+/// リソース [`Input`] の [`Input::orbit_input`] で [`OrbitCameraComponent`] を動かす
+pub struct OrbitCameraSystem;
+
+impl OrbitCameraSystem {
+    /// すべての [`OrbitCameraComponent`] に `input` を渡し、`delta_time` だけ補間を進める
+    ///
+    /// [`System::update`] から呼ばれる。GPU に触れないのでテストからも直接呼べる。
+    pub fn apply(world: &mut hecs::World, input: OrbitInput, delta_time: Duration) {
+        for (_, (orbit, transform, camera)) in world.query_mut::<(
+            &mut OrbitCameraComponent,
+            &mut TransformComponent,
+            Option<&mut CameraComponent>,
+        )>() {
+            orbit.0.update(input, delta_time.as_secs_f32());
+            orbit.apply_to(transform, camera);
+        }
+    }
+}
+
+impl System for OrbitCameraSystem {
+    fn setup(&mut self, _resource: Option<&RenderResource<'_>>) {}
+
+    fn update(
+        &mut self,
+        frame: &Frame<'_>,
+        world: &mut hecs::World,
+        _resource: Option<&RenderResource<'_>>,
+    ) {
+        let Some(input) = resource::<Input>(world).map(|input| input.orbit_input()) else {
+            return;
+        };
+        Self::apply(world, input, frame.delta_time);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Point3, Vector3};
+    use reverie_util::math::Rad;
+
+    use super::*;
+
+    #[test]
+    fn keeps_focus_in_front_of_camera() {
+        let mut world = hecs::World::new();
+        let controller =
+            OrbitCameraController::new(Point3::new(1.0, 2.0, 3.0), Rad(0.5), Rad(0.3), 5.0)
+                .with_smoothing(0.0);
+        let entity = world.spawn((
+            TransformComponent::default(),
+            CameraComponent::default(),
+            OrbitCameraComponent(controller),
+        ));
+        let input = OrbitInput {
+            zoom: 1.0,
+            ..OrbitInput::default()
+        };
+        OrbitCameraSystem::apply(&mut world, input, Duration::from_millis(16));
+
+        let transform = world.get::<&TransformComponent>(entity).unwrap();
+        // カメラは -Z を向くので、注視点はカメラの前にある
+        let ahead = transform.translation.vector + transform.rotation * -Vector3::z() * 4.5;
+        assert!((ahead - Vector3::new(1.0, 2.0, 3.0)).norm() < 1e-4);
+        let camera = world.get::<&CameraComponent>(entity).unwrap();
+        assert!(matches!(camera.projection, Projection::Perspective { .. }));
+    }
+}
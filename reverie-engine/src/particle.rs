@@ -0,0 +1,575 @@
+//! 小さな四角形をたくさん飛ばすパーティクル
+//!
+//! [`ParticleEmitterComponent`] がパーティクルを生み、[`ParticleSystem`] が動かす。
+//! 1 つのエミッターのパーティクルはまとめて 1 回で描画する。
+//!
+//! # 描画の順序
+//!
+//! エミッターは同じ層 ([`crate::scene::RenderLayerComponent`]) のタイルマップとスプライトの後、文字列の前に描く。
+//! 同じ層の中では、[`ParticleBlend::Alpha`] と [`ParticleBlend::Premultiplied`] のエミッターを
+//! カメラから遠い順に並べ、[`ParticleBlend::Additive`] のエミッターはその後に作った順で描く。
+//! 加算は重ねる順序で結果が変わらないので、奥行きを求めずに済ませている。
+//!
+//! 次のものは並べ替えないので、必要なら層を分ける。
+//!
+//! * エミッターと同じ層のスプライト。スプライトの後ろに出したいパーティクルは手前の層に置く
+//! * 重なった 2 つのエミッターのパーティクル同士。エミッターの中心の奥行きで順序を決める
+//! * 加算のエミッターと、その奥にある半透明のエミッター
+//!
+//! エミッターの中のパーティクルは [`ParticleEmitterComponent::with_sorted_particles`] で遠い順に並べられる。
+use std::time::Duration;
+
+#[cfg(feature = "backend-wgpu")]
+use anyhow::Context as _;
+use nalgebra::{Point3, Vector3};
+use reverie_util::color::Color;
+#[cfg(feature = "backend-wgpu")]
+use tracing_unwrap::ResultExt;
+
+use crate::{
+    physics::current_gravity,
+    scene::{Frame, RenderResource, System, TransformComponent},
+    texture::TextureId,
+};
+#[cfg(feature = "backend-wgpu")]
+use crate::{
+    scene::Frustum,
+    wgpu_wrapper::{buffer::VertexIndexBuffer, vertex::UvVertex, WgpuResource},
+};
+
+/// 1 つのエミッターのパーティクルの数の上限。インデックスが 16 ビットに収まる数
+pub const MAX_PARTICLES: usize = (u16::MAX as usize + 1) / 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Hash)]
+/// パーティクルを描画先に重ねる方法
+pub enum ParticleBlend {
+    /// スプライトと同じく、不透明度で重ねる。煙やほこりに使う
+    #[default]
+    Alpha,
+    /// 描画先の色に足す。炎や光に使う。重ねる順序で結果が変わらないので並べ替えない
+    Additive,
+    /// 不透明度を掛けた色のテクスチャを重ねる。縁の暗い線が出ない
+    ///
+    /// テクスチャの色は不透明度を掛けたものにしておく。パーティクルの色はエンジンが掛ける。
+    Premultiplied,
+}
+
+impl ParticleBlend {
+    /// 奥から順に並べないと正しく重ならないか
+    pub const fn needs_sorting(self) -> bool {
+        !matches!(self, Self::Additive)
+    }
+
+    #[cfg(feature = "backend-wgpu")]
+    const fn pipeline_name(self) -> &'static str {
+        match self {
+            Self::Alpha => "reverie particles alpha",
+            Self::Additive => "reverie particles additive",
+            Self::Premultiplied => "reverie particles premultiplied",
+        }
+    }
+
+    #[cfg(feature = "backend-wgpu")]
+    const fn blend_state(self) -> wgpu::BlendState {
+        match self {
+            Self::Alpha => crate::wgpu_wrapper::SPRITE_BLEND,
+            Self::Additive => wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                // 描画先の不透明度は変えない
+                alpha: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::Zero,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+            },
+            Self::Premultiplied => wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// 1 つのパーティクル
+pub struct Particle {
+    /// 中心のワールド座標
+    pub position: Point3<f32>,
+    /// 1 秒あたりの移動量
+    pub velocity: Vector3<f32>,
+    /// 生まれてからの秒数
+    pub age: f32,
+    /// この秒数が経ったら消える
+    pub lifetime: f32,
+}
+
+#[derive(Debug)]
+/// パーティクルを生み、描画するコンポーネント
+///
+/// パーティクルはワールド座標で動くので、エミッターを動かしても既に生まれたものはついてこない。
+/// 新しいパーティクルは [`TransformComponent`] の位置に生まれる。[`TransformComponent`] が無いエンティティは動かさず、描画もしない。
+pub struct ParticleEmitterComponent {
+    texture: TextureId,
+    blend: ParticleBlend,
+    /// 1 秒に生むパーティクルの数
+    rate: f32,
+    lifetime: f32,
+    velocity: Vector3<f32>,
+    /// 速度の各成分に -spread から spread の乱数を足す
+    spread: Vector3<f32>,
+    /// パーティクルの一辺の長さ
+    size: f32,
+    gravity_scale: f32,
+    start_color: Color,
+    end_color: Color,
+    max_particles: usize,
+    sort_particles: bool,
+    particles: Vec<Particle>,
+    /// 生み残した端数
+    spawn_budget: f32,
+    /// xorshift の状態
+    rng: u32,
+    #[cfg(feature = "backend-wgpu")]
+    buffer: Option<VertexIndexBuffer>,
+}
+
+impl ParticleEmitterComponent {
+    /// `texture` のパーティクルを 1 秒に 10 個生むエミッター
+    pub const fn new(texture: TextureId) -> Self {
+        Self {
+            texture,
+            blend: ParticleBlend::Alpha,
+            rate: 10.0,
+            lifetime: 1.0,
+            velocity: Vector3::new(0.0, 0.0, 0.0),
+            spread: Vector3::new(0.0, 0.0, 0.0),
+            size: 1.0,
+            gravity_scale: 0.0,
+            start_color: Color::WHITE,
+            end_color: Color::WHITE,
+            max_particles: 1024,
+            sort_particles: false,
+            particles: Vec::new(),
+            spawn_budget: 0.0,
+            rng: 0x9E37_79B9,
+            #[cfg(feature = "backend-wgpu")]
+            buffer: None,
+        }
+    }
+
+    pub const fn with_blend(mut self, blend: ParticleBlend) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    /// 1 秒に生むパーティクルの数。0 なら [`Self::emit`] したときだけ生む
+    pub const fn with_rate(mut self, rate: f32) -> Self {
+        self.rate = rate;
+        self
+    }
+
+    /// パーティクルが消えるまでの秒数
+    pub const fn with_lifetime(mut self, lifetime: f32) -> Self {
+        self.lifetime = lifetime;
+        self
+    }
+
+    /// 生まれたときの速度。各成分に -`spread` から `spread` の乱数を足す
+    pub const fn with_velocity(mut self, velocity: Vector3<f32>, spread: Vector3<f32>) -> Self {
+        self.velocity = velocity;
+        self.spread = spread;
+        self
+    }
+
+    pub const fn with_size(mut self, size: f32) -> Self {
+        self.size = size;
+        self
+    }
+
+    /// [`crate::physics::PhysicsSettings::gravity`] に掛ける値。既定では煙のように重力を受けない
+    pub const fn with_gravity_scale(mut self, gravity_scale: f32) -> Self {
+        self.gravity_scale = gravity_scale;
+        self
+    }
+
+    /// 生まれたときの色と、消えるときの色。その間は線形に補間する
+    pub const fn with_colors(mut self, start: Color, end: Color) -> Self {
+        self.start_color = start;
+        self.end_color = end;
+        self
+    }
+
+    /// 同時に存在するパーティクルの数の上限。[`MAX_PARTICLES`] より大きくはできない
+    pub fn with_max_particles(mut self, max_particles: usize) -> Self {
+        self.max_particles = max_particles.min(MAX_PARTICLES);
+        self
+    }
+
+    /// エミッターの中のパーティクルもカメラから遠い順に並べて描くか
+    ///
+    /// 重なったパーティクルの前後が正しくなるが、毎フレーム並べ替える。[`ParticleBlend::Additive`] では並べ替えない。
+    pub const fn with_sorted_particles(mut self, sort: bool) -> Self {
+        self.sort_particles = sort;
+        self
+    }
+
+    /// 乱数の種。同じ種なら同じように散らばる
+    pub const fn with_seed(mut self, seed: u32) -> Self {
+        // xorshift は 0 から抜け出せない
+        self.rng = if seed == 0 { 0x9E37_79B9 } else { seed };
+        self
+    }
+
+    pub const fn texture(&self) -> TextureId {
+        self.texture
+    }
+
+    pub const fn blend(&self) -> ParticleBlend {
+        self.blend
+    }
+
+    pub fn set_blend(&mut self, blend: ParticleBlend) {
+        self.blend = blend;
+    }
+
+    pub const fn size(&self) -> f32 {
+        self.size
+    }
+
+    /// 生きているパーティクル。描画する順に並んでいる
+    pub fn particles(&self) -> &[Particle] {
+        &self.particles
+    }
+
+    /// `position` に `count` 個のパーティクルをすぐに生む
+    ///
+    /// 上限 ([`Self::with_max_particles`]) を超える分は生まない。
+    pub fn emit(&mut self, position: Point3<f32>, count: usize) {
+        let count = count.min(self.max_particles.saturating_sub(self.particles.len()));
+        for _ in 0..count {
+            let jitter = Vector3::new(self.random(), self.random(), self.random());
+            self.particles.push(Particle {
+                position,
+                velocity: self.velocity + self.spread.component_mul(&jitter),
+                age: 0.0,
+                lifetime: self.lifetime,
+            });
+        }
+    }
+
+    /// パーティクルを `delta_time` 秒だけ動かし、寿命が尽きたものを消し、`origin` に新しいものを生む
+    ///
+    /// パーティクルは `gravity` に [`Self::with_gravity_scale`] を掛けた分だけ加速する。
+    pub fn update(&mut self, origin: Point3<f32>, gravity: &Vector3<f32>, delta_time: f32) {
+        let acceleration = gravity * (self.gravity_scale * delta_time);
+        self.particles.retain_mut(|particle| {
+            particle.age += delta_time;
+            particle.velocity += acceleration;
+            particle.position += particle.velocity * delta_time;
+            particle.age < particle.lifetime
+        });
+        if self.rate > 0.0 {
+            self.spawn_budget += self.rate * delta_time;
+            let count = self.spawn_budget.floor();
+            self.spawn_budget -= count;
+            self.emit(origin, count as usize);
+        }
+    }
+
+    /// `particle` の今の色
+    pub fn color_of(&self, particle: &Particle) -> Color {
+        let t = if particle.lifetime > 0.0 {
+            (particle.age / particle.lifetime).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+        let lerp = |a: f32, b: f32| (b - a).mul_add(t, a);
+        let (s, e) = (self.start_color, self.end_color);
+        Color::new(
+            lerp(s.r, e.r),
+            lerp(s.g, e.g),
+            lerp(s.b, e.b),
+            lerp(s.a, e.a),
+        )
+    }
+
+    /// すべてのパーティクルを囲む箱。パーティクルが無ければ `None`
+    pub fn world_aabb(&self) -> Option<(Point3<f32>, Point3<f32>)> {
+        let half = Vector3::new(self.size, self.size, 0.0) / 2.0;
+        self.particles.iter().fold(None, |aabb, particle| {
+            let (min, max) = (particle.position - half, particle.position + half);
+            Some(
+                aabb.map_or((min, max), |(a, b): (Point3<f32>, Point3<f32>)| {
+                    (a.inf(&min), b.sup(&max))
+                }),
+            )
+        })
+    }
+
+    /// -1.0 から 1.0 の乱数
+    fn random(&mut self) -> f32 {
+        let mut x = self.rng;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.rng = x;
+        (x as f32 / u32::MAX as f32).mul_add(2.0, -1.0)
+    }
+
+    /// [`Self::with_sorted_particles`] なら、パーティクルを `frustum` のカメラから遠い順に並べる
+    #[cfg(feature = "backend-wgpu")]
+    pub(crate) fn sort_back_to_front(&mut self, frustum: &Frustum) {
+        if self.sort_particles && self.blend.needs_sorting() {
+            self.particles.sort_by(|a, b| {
+                frustum
+                    .depth(&b.position)
+                    .total_cmp(&frustum.depth(&a.position))
+            });
+        }
+    }
+
+    /// パーティクルの四角形の頂点。左上、右上、左下、右下の順
+    #[cfg(feature = "backend-wgpu")]
+    fn vertices(&self, particle: &Particle) -> [UvVertex; 4] {
+        let color = self.color_of(particle);
+        let color = match self.blend {
+            ParticleBlend::Premultiplied => [
+                color.r * color.a,
+                color.g * color.a,
+                color.b * color.a,
+                color.a,
+            ],
+            _ => color.into(),
+        };
+        let half = self.size / 2.0;
+        let p = particle.position;
+        [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)].map(|(x, y): (f32, f32)| UvVertex {
+            position: [x.mul_add(half, p.x), y.mul_add(half, p.y), p.z],
+            uv: [(x + 1.0) / 2.0, (y + 1.0) / 2.0],
+            color,
+        })
+    }
+
+    /// パーティクルを [`Self::blend`] の重ね方で描画する
+    ///
+    /// 描画の後、パイプラインはスプライトのものに戻っていない。
+    #[cfg(feature = "backend-wgpu")]
+    pub(crate) fn render(&mut self, rp: &mut wgpu::RenderPass<'_>, resource: &WgpuResource<'_>) {
+        if self.particles.is_empty() {
+            return;
+        }
+        let bind_group = match resource.get_texture_bind_group(self.texture) {
+            Ok(bind_group) => bind_group,
+            Err(err) => {
+                tracing::warn!(?err, "particle texture is not found");
+                return;
+            }
+        };
+        let texture = resource.texture_registry.resolve(self.texture);
+        let (min_u, min_v, max_u, max_v) = resource
+            .texture_registry
+            .get_uv(texture)
+            .unwrap_or((0.0, 0.0, 1.0, 1.0));
+        let blend = self.blend;
+        let pipeline = resource
+            .pipeline_cache
+            .get_or_create(blend.pipeline_name(), |formats| {
+                let shader = resource
+                    .device
+                    .create_shader_module(wgpu::ShaderModuleDescriptor {
+                        label: Some("Shader from shader.wgsl"),
+                        source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
+                    });
+                crate::wgpu_wrapper::setup_render_pipeline_with_blend(
+                    &shader,
+                    &[
+                        &resource.texture_bind_group_layout,
+                        &resource.uniform_bind_group_layout,
+                    ],
+                    &[UvVertex::desc()],
+                    formats.color,
+                    blend.blend_state(),
+                    &resource.device,
+                )
+                .context("failed to create the particle pipeline")
+                .unwrap_or_log()
+            });
+
+        let count = self.particles.len();
+        let vertices: Vec<UvVertex> = self
+            .particles
+            .iter()
+            .flat_map(|particle| {
+                self.vertices(particle).map(|mut vertex| {
+                    // アトラスの割り当ての中に収める
+                    vertex.uv = [
+                        (max_u - min_u).mul_add(vertex.uv[0], min_u),
+                        (max_v - min_v).mul_add(vertex.uv[1], min_v),
+                    ];
+                    vertex
+                })
+            })
+            .collect();
+        let buffer = self.buffer.get_or_insert_with(|| {
+            let capacity = count.next_power_of_two().min(MAX_PARTICLES);
+            VertexIndexBuffer::new(
+                &resource.device,
+                capacity * 4,
+                capacity * 6,
+                Some("Particle Buffer"),
+                &resource.gpu_memory,
+                &resource.frames,
+            )
+            .unwrap_or_log()
+        });
+        buffer.reserve(
+            &resource.device,
+            &resource.gpu_memory,
+            &resource.frames,
+            count * 4,
+            count * 6,
+        );
+        {
+            let mut update = buffer.start_update(&resource.queue, &resource.frames);
+            let range = {
+                let v = update.vertex_mut();
+                v.clear();
+                v.extend(vertices);
+                0..v.len()
+            };
+            update.set_vertex_update(range);
+            let range = {
+                let i = update.index_mut();
+                i.clear();
+                for quad in 0..count {
+                    let base = (quad * 4) as u16;
+                    i.extend_from_slice(&[base, base + 3, base + 1, base, base + 2, base + 3]);
+                }
+                0..i.len()
+            };
+            update.set_index_update(range.clone());
+            update.set_render_range(range.start as u32..range.end as u32);
+        }
+
+        rp.set_pipeline(&pipeline);
+        rp.set_bind_group(0, bind_group, &[]);
+        rp.set_index_buffer(buffer.index_buffer().slice(..), wgpu::IndexFormat::Uint16);
+        rp.set_vertex_buffer(0, buffer.vertex_buffer().slice(..));
+        rp.draw_indexed(buffer.index_buffer_range.clone(), 0, 0..1);
+    }
+}
+
+#[derive(Debug, Default)]
+/// [`ParticleEmitterComponent`] のパーティクルを動かし、新しいものを生む
+pub struct ParticleSystem;
+
+impl ParticleSystem {
+    pub const fn new() -> Self {
+        Self
+    }
+
+    /// パーティクルを`delta_time`だけ進める
+    ///
+    /// [`System::update`] から呼ばれる。GPU に触れないのでテストからも直接呼べる。
+    /// 重力は [`crate::physics::PhysicsSettings`] をそのたびに読む。
+    pub fn apply(world: &mut hecs::World, delta_time: Duration) {
+        let (gravity, _) = current_gravity(world);
+        for (_, (emitter, transform)) in
+            world.query_mut::<(&mut ParticleEmitterComponent, &TransformComponent)>()
+        {
+            let origin = Point3::from(transform.translation.vector);
+            emitter.update(origin, &gravity, delta_time.as_secs_f32());
+        }
+    }
+}
+
+impl System for ParticleSystem {
+    fn setup(&mut self, _resource: Option<&RenderResource<'_>>) {}
+
+    fn update(
+        &mut self,
+        frame: &Frame<'_>,
+        world: &mut hecs::World,
+        _resource: Option<&RenderResource<'_>>,
+    ) {
+        Self::apply(world, frame.delta_time);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spawns_at_rate_and_expires() {
+        let mut emitter = ParticleEmitterComponent::new(TextureId::WHITE)
+            .with_rate(8.0)
+            .with_lifetime(0.5)
+            .with_velocity(Vector3::new(4.0, 0.0, 0.0), Vector3::zeros());
+        let origin = Point3::new(1.0, 2.0, 0.0);
+        // 1.5 個分。端数は次に持ち越す
+        emitter.update(origin, &Vector3::zeros(), 0.1875);
+        assert_eq!(emitter.particles().len(), 1);
+        emitter.update(origin, &Vector3::zeros(), 0.125);
+        assert_eq!(emitter.particles().len(), 2);
+        assert_eq!(emitter.particles()[0].position.x, 1.5);
+
+        // 最初のものは 0.5 秒経って消える
+        emitter.update(origin, &Vector3::zeros(), 0.375);
+        assert_eq!(emitter.particles().len(), 4);
+        assert_eq!(emitter.particles()[0].age, 0.375);
+    }
+
+    #[test]
+    fn gravity_scale_accelerates_particles() {
+        let mut world = hecs::World::new();
+        crate::scene::insert_resource(
+            &mut world,
+            crate::physics::PhysicsSettings {
+                gravity: Vector3::new(0.0, 8.0, 0.0),
+                ..Default::default()
+            },
+        );
+        let mut falling = ParticleEmitterComponent::new(TextureId::WHITE)
+            .with_rate(0.0)
+            .with_gravity_scale(0.5);
+        falling.emit(Point3::origin(), 1);
+        let mut smoke = ParticleEmitterComponent::new(TextureId::WHITE).with_rate(0.0);
+        smoke.emit(Point3::origin(), 1);
+        let falling = world.spawn((falling, TransformComponent::default()));
+        let smoke = world.spawn((smoke, TransformComponent::default()));
+
+        ParticleSystem::apply(&mut world, Duration::from_millis(500));
+        let velocity = |entity| {
+            world
+                .get::<&ParticleEmitterComponent>(entity)
+                .unwrap()
+                .particles()[0]
+                .velocity
+        };
+        assert_eq!(velocity(falling), Vector3::new(0.0, 2.0, 0.0));
+        assert_eq!(velocity(smoke), Vector3::zeros());
+    }
+
+    #[test]
+    fn colors_fade_and_aabb_covers_particles() {
+        let mut emitter = ParticleEmitterComponent::new(TextureId::WHITE)
+            .with_rate(0.0)
+            .with_lifetime(2.0)
+            .with_size(2.0)
+            .with_colors(Color::WHITE, Color::new(1.0, 0.0, 0.0, 0.0))
+            .with_max_particles(2);
+        assert_eq!(emitter.world_aabb(), None);
+        emitter.emit(Point3::new(0.0, 0.0, 0.0), 1);
+        emitter.emit(Point3::new(4.0, 1.0, 0.5), 5);
+        assert_eq!(emitter.particles().len(), 2);
+        let (min, max) = emitter.world_aabb().unwrap();
+        assert_eq!(min, Point3::new(-1.0, -1.0, 0.0));
+        assert_eq!(max, Point3::new(5.0, 2.0, 0.5));
+
+        emitter.update(Point3::origin(), &Vector3::zeros(), 1.0);
+        let color = emitter.color_of(&emitter.particles()[0]);
+        assert_eq!(color, Color::new(1.0, 0.5, 0.5, 0.5));
+    }
+}
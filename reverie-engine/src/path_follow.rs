@@ -0,0 +1,237 @@
+//! エンティティを曲線に沿って動かす
+use std::time::Duration;
+
+use nalgebra::{Translation3, UnitQuaternion, Vector3};
+use reverie_util::math::spline::Spline;
+
+use crate::scene::{Frame, LocalTransformComponent, RenderResource, System, TransformComponent};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// 曲線の終点に着いたときにどうするか
+pub enum PathMode {
+    /// 終点で止まる
+    #[default]
+    Once,
+    /// 始点に戻って繰り返す。閉じた曲線ならそのまま回り続ける
+    Loop,
+    /// 向きを変えて往復する
+    PingPong,
+}
+
+#[derive(Debug, Clone)]
+/// [`Spline`] に沿って一定の速さで動くエンティティの印
+///
+/// [`PathFollowSystem`] が毎フレーム位置を書き換える。[`LocalTransformComponent`] があれば
+/// そちらを書き換えるので、曲線は親から見た座標になる。
+/// 曲線は [`Self::spline`] で実行中に書き換えてよい。弧長の表は自動で作り直される。
+pub struct PathFollowComponent {
+    pub spline: Spline,
+    /// 1 秒に進む弧長
+    pub speed: f32,
+    pub mode: PathMode,
+    /// 進む向きに回す。X 軸が接線の向きになる
+    pub orient_to_tangent: bool,
+    /// 始点からの弧長
+    distance: f32,
+    /// [`PathMode::PingPong`] で終点から戻っているところ
+    reversed: bool,
+}
+
+impl PathFollowComponent {
+    pub const fn new(spline: Spline, speed: f32) -> Self {
+        Self {
+            spline,
+            speed,
+            mode: PathMode::Once,
+            orient_to_tangent: false,
+            distance: 0.0,
+            reversed: false,
+        }
+    }
+
+    pub const fn with_mode(mut self, mode: PathMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub const fn with_orient_to_tangent(mut self, orient: bool) -> Self {
+        self.orient_to_tangent = orient;
+        self
+    }
+
+    /// 始点からの弧長
+    pub const fn distance(&self) -> f32 {
+        self.distance
+    }
+
+    /// 曲線の上の位置を弧長で変える
+    pub fn set_distance(&mut self, distance: f32) {
+        self.distance = distance.clamp(0.0, self.spline.length());
+    }
+
+    /// [`PathMode::PingPong`] で終点から戻っているところか
+    pub const fn is_reversed(&self) -> bool {
+        self.reversed
+    }
+
+    /// [`PathMode::Once`] で終点に着いたか
+    pub fn is_finished(&self) -> bool {
+        self.mode == PathMode::Once && self.distance >= self.spline.length()
+    }
+
+    /// `delta_time` だけ進める
+    pub fn advance(&mut self, delta_time: Duration) {
+        let length = self.spline.length();
+        if length <= 0.0 {
+            self.distance = 0.0;
+            return;
+        }
+        let step = self.speed * delta_time.as_secs_f32();
+        match self.mode {
+            PathMode::Once => self.distance = (self.distance + step).clamp(0.0, length),
+            PathMode::Loop => self.distance = (self.distance + step).rem_euclid(length),
+            PathMode::PingPong => {
+                // 往復を長さ 2 倍の輪とみなす
+                let round_trip = length * 2.0;
+                let along = if self.reversed {
+                    round_trip - self.distance
+                } else {
+                    self.distance
+                };
+                let along = (along + step).rem_euclid(round_trip);
+                self.reversed = along > length;
+                self.distance = if self.reversed {
+                    round_trip - along
+                } else {
+                    along
+                };
+            }
+        }
+    }
+
+    /// 今の位置
+    pub fn translation(&self) -> Translation3<f32> {
+        Translation3::from(self.spline.position_at_distance(self.distance).coords)
+    }
+
+    /// 進む向きに X 軸を向ける回転。接線が求まらなければ `None`
+    pub fn orientation(&self) -> Option<UnitQuaternion<f32>> {
+        let mut tangent = self.spline.tangent_at_distance(self.distance);
+        if self.reversed {
+            tangent = -tangent;
+        }
+        if tangent.norm_squared() <= f32::EPSILON {
+            return None;
+        }
+        // 真後ろを向くときは rotation_between が求まらないので、Z 軸まわりに半回転する
+        UnitQuaternion::rotation_between(&Vector3::x(), &tangent).or_else(|| {
+            Some(UnitQuaternion::from_axis_angle(
+                &Vector3::z_axis(),
+                std::f32::consts::PI,
+            ))
+        })
+    }
+
+    fn apply_to(&self, transform: &mut TransformComponent) {
+        transform.translation = self.translation();
+        if self.orient_to_tangent {
+            if let Some(rotation) = self.orientation() {
+                transform.rotation = rotation;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+/// [`PathFollowComponent`] を進め、[`TransformComponent`] を書き換える
+pub struct PathFollowSystem;
+
+impl PathFollowSystem {
+    /// すべての [`PathFollowComponent`] を`delta_time`だけ進める
+    ///
+    /// [`System::update`] から呼ばれる。GPU に触れないのでテストからも直接呼べる。
+    pub fn apply(world: &mut hecs::World, delta_time: Duration) {
+        for (_, (follow, transform, local)) in world.query_mut::<(
+            &mut PathFollowComponent,
+            &mut TransformComponent,
+            Option<&mut LocalTransformComponent>,
+        )>() {
+            follow.advance(delta_time);
+            match local {
+                Some(LocalTransformComponent(local)) => follow.apply_to(local),
+                None => follow.apply_to(transform),
+            }
+        }
+    }
+}
+
+impl System for PathFollowSystem {
+    fn setup(&mut self, _resource: Option<&RenderResource<'_>>) {}
+
+    fn update(
+        &mut self,
+        frame: &Frame<'_>,
+        world: &mut hecs::World,
+        _resource: Option<&RenderResource<'_>>,
+    ) {
+        Self::apply(world, frame.delta_time);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Point3;
+
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    fn line() -> Spline {
+        Spline::catmull_rom(vec![Point3::origin(), Point3::new(4.0, 0.0, 0.0)])
+    }
+
+    #[test]
+    fn moves_at_constant_speed_and_ping_pongs() {
+        let mut world = hecs::World::new();
+        let entity = world.spawn((
+            TransformComponent::default(),
+            PathFollowComponent::new(line(), 3.0)
+                .with_mode(PathMode::PingPong)
+                .with_orient_to_tangent(true),
+        ));
+        let x = |world: &hecs::World| {
+            world
+                .get::<&TransformComponent>(entity)
+                .unwrap()
+                .translation
+                .x
+        };
+
+        PathFollowSystem::apply(&mut world, SECOND);
+        assert!((x(&world) - 3.0).abs() < 1e-2);
+        // 終点で折り返して 2 戻る
+        PathFollowSystem::apply(&mut world, SECOND);
+        assert!((x(&world) - 2.0).abs() < 1e-2);
+        let transform = world.get::<&TransformComponent>(entity).unwrap();
+        assert!((transform.rotation * Vector3::x() + Vector3::x()).norm() < 1e-5);
+    }
+
+    #[test]
+    fn once_stops_and_loop_wraps() {
+        let mut once = PathFollowComponent::new(line(), 3.0);
+        once.advance(SECOND * 2);
+        assert!(once.is_finished());
+        assert!((once.distance() - 4.0).abs() < 1e-2);
+
+        let mut looping = PathFollowComponent::new(line(), 3.0).with_mode(PathMode::Loop);
+        looping.advance(SECOND * 2);
+        assert!(!looping.is_finished());
+        assert!((looping.distance() - 2.0).abs() < 1e-2);
+
+        // 曲線を伸ばすと、同じ弧長のまま新しい長さで進む
+        looping.spline.set_point(1, Point3::new(8.0, 0.0, 0.0));
+        looping.advance(SECOND);
+        assert!((looping.distance() - 5.0).abs() < 1e-2);
+        assert!((looping.translation().x - 5.0).abs() < 1e-2);
+    }
+}
@@ -0,0 +1,325 @@
+//! 重力などの物理の設定と、それに従って動くエンティティ
+//!
+//! [`PhysicsSettings`] をリソースとして置くと、[`KinematicSystem`] と
+//! [`crate::particle::ParticleSystem`] がフレームごとに読む。実行中に書き換えれば次のフレームから効く。
+//! リソースが無ければ [`PhysicsSettings::default`] を使う。
+//!
+//! [`ColliderComponent`] を付けた体は、壁や床、[`TileCollisionComponent`] を付けたタイルマップに沿って滑るように動く。
+//! 大きなタイルマップは [`TileColliderBakeComponent`] で、まとめた長方形の壁にしておける。
+use std::time::Duration;
+
+use anyhow::Context;
+use nalgebra::Vector3;
+use serde::{Deserialize, Serialize};
+
+use crate::scene::{resource, Frame, RenderResource, System, TransformComponent};
+
+mod collision;
+mod tile_bake;
+
+pub use collision::{
+    move_and_slide, ColliderComponent, CollisionShape, Obstacles, TileCollisionComponent,
+};
+pub use tile_bake::{TileColliderBakeComponent, TileColliderBakeSystem};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+/// 物理の全体の設定
+///
+/// レベルごとに変えたいときは、レベルのデータに [`Self::to_toml`] で書いておき、
+/// 読み込むときに [`crate::scene::Scene::insert_resource`] で置き換える。ファイルに無い項目は既定値になる。
+pub struct PhysicsSettings {
+    /// 重力加速度 (1 秒あたりの速度の変化)
+    pub gravity: Vector3<f32>,
+    /// 重力の向きに落ちる速さの上限。[`KinematicBodyComponent::max_fall_speed`] が無いものに使う
+    pub max_fall_speed: f32,
+    /// 衝突の層の名前。添字が層の番号で、デバッグの表示に使う
+    pub collision_layers: Vec<String>,
+}
+
+impl Default for PhysicsSettings {
+    /// ピクセル座標 (y が下向き) で 1 m を 100 ピクセルとしたときの地球の重力
+    fn default() -> Self {
+        Self {
+            gravity: Vector3::new(0.0, 980.0, 0.0),
+            max_fall_speed: 1000.0,
+            collision_layers: Vec::new(),
+        }
+    }
+}
+
+impl PhysicsSettings {
+    /// 衝突の層 `layer` の名前。名前が無ければ `None`
+    pub fn layer_name(&self, layer: usize) -> Option<&str> {
+        self.collision_layers.get(layer).map(String::as_str)
+    }
+
+    /// 名前が `name` の衝突の層の番号
+    pub fn layer_index(&self, name: &str) -> Option<usize> {
+        self.collision_layers.iter().position(|layer| layer == name)
+    }
+
+    /// TOML として読む
+    pub fn from_toml(toml: &str) -> anyhow::Result<Self> {
+        toml::from_str(toml).context("failed: parse physics settings")
+    }
+
+    /// TOML にする
+    pub fn to_toml(&self) -> anyhow::Result<String> {
+        toml::to_string_pretty(self).context("failed: serialize physics settings")
+    }
+}
+
+/// `world` の重力と、落ちる速さの既定の上限。[`PhysicsSettings`] が無ければ既定値
+pub(crate) fn current_gravity(world: &hecs::World) -> (Vector3<f32>, f32) {
+    resource::<PhysicsSettings>(world).map_or_else(
+        || {
+            let settings = PhysicsSettings::default();
+            (settings.gravity, settings.max_fall_speed)
+        },
+        |settings| (settings.gravity, settings.max_fall_speed),
+    )
+}
+
+/// `velocity` のうち `gravity` の向きの成分を `max_fall_speed` までに抑える
+///
+/// 重力が 0 なら何もしない。上向きに飛ぶ速さは抑えない。
+fn clamp_fall(velocity: Vector3<f32>, gravity: &Vector3<f32>, max_fall_speed: f32) -> Vector3<f32> {
+    let Some(down) = gravity.try_normalize(f32::EPSILON) else {
+        return velocity;
+    };
+    let fall = velocity.dot(&down);
+    if fall > max_fall_speed {
+        velocity - down * (fall - max_fall_speed)
+    } else {
+        velocity
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// 速度を持ち、重力で加速するエンティティ
+///
+/// [`KinematicSystem`] が [`TransformComponent`] を動かす。ジャンプは [`Self::velocity`] を直接書き換える。
+/// [`ColliderComponent`] も付けると、ぶつかったものに沿って動き、[`Self::on_ground`] で接地を調べられる。
+pub struct KinematicBodyComponent {
+    pub velocity: Vector3<f32>,
+    /// 重力に掛ける値。0 なら重力を受けない
+    pub gravity_scale: f32,
+    /// 落ちる速さの上限。`None` なら [`PhysicsSettings::max_fall_speed`]
+    pub max_fall_speed: Option<f32>,
+    /// 衝突の層の番号。[`PhysicsSettings::layer_name`] で名前を引ける
+    pub collision_layer: usize,
+    on_ground: bool,
+    on_slope: bool,
+    /// 片側だけの足場をすり抜けている途中
+    dropping: bool,
+}
+
+impl Default for KinematicBodyComponent {
+    fn default() -> Self {
+        Self {
+            velocity: Vector3::zeros(),
+            gravity_scale: 1.0,
+            max_fall_speed: None,
+            collision_layer: 0,
+            on_ground: false,
+            on_slope: false,
+            dropping: false,
+        }
+    }
+}
+
+impl KinematicBodyComponent {
+    pub fn new(velocity: Vector3<f32>) -> Self {
+        Self {
+            velocity,
+            ..Default::default()
+        }
+    }
+
+    pub const fn with_gravity_scale(mut self, gravity_scale: f32) -> Self {
+        self.gravity_scale = gravity_scale;
+        self
+    }
+
+    pub const fn with_max_fall_speed(mut self, max_fall_speed: f32) -> Self {
+        self.max_fall_speed = Some(max_fall_speed);
+        self
+    }
+
+    pub const fn with_collision_layer(mut self, layer: usize) -> Self {
+        self.collision_layer = layer;
+        self
+    }
+
+    /// 前の更新で床か斜面に立っていたか。ジャンプできるかの判定に使う
+    pub const fn on_ground(&self) -> bool {
+        self.on_ground
+    }
+
+    /// 前の更新で斜面に立っていたか。[`Self::on_ground`] も `true` になる
+    pub const fn on_slope(&self) -> bool {
+        self.on_slope
+    }
+
+    /// 乗っている片側だけの足場から下に降りる
+    ///
+    /// 下キーとジャンプの入力で呼ぶ。足場を抜けきるまで片側だけの足場に乗らない。
+    pub const fn drop_through(&mut self) {
+        self.dropping = true;
+    }
+
+    pub const fn is_dropping(&self) -> bool {
+        self.dropping
+    }
+
+    /// `gravity` で `delta_time` 秒だけ加速した速度にし、その間の移動量を返す
+    ///
+    /// 速度を先に変えてから位置を動かす (半陰的オイラー法)。
+    /// `max_fall_speed` は [`Self::max_fall_speed`] が無いときに使う上限。
+    pub fn integrate(
+        &mut self,
+        gravity: &Vector3<f32>,
+        max_fall_speed: f32,
+        delta_time: f32,
+    ) -> Vector3<f32> {
+        self.velocity += gravity * (self.gravity_scale * delta_time);
+        let max_fall_speed = self.max_fall_speed.unwrap_or(max_fall_speed);
+        self.velocity = clamp_fall(self.velocity, gravity, max_fall_speed);
+        self.velocity * delta_time
+    }
+}
+
+#[derive(Debug, Default)]
+/// [`KinematicBodyComponent`] を重力で加速させ、[`TransformComponent`] を動かす
+///
+/// [`ColliderComponent`] を付けた体は [`move_and_slide`] で動かす。z 方向にはぶつからない。
+pub struct KinematicSystem;
+
+impl KinematicSystem {
+    pub const fn new() -> Self {
+        Self
+    }
+
+    /// エンティティを`delta_time`だけ動かす
+    ///
+    /// [`System::update`] から呼ばれる。GPU に触れないのでテストからも直接呼べる。
+    /// 設定はそのたびに読むので、ジャンプの途中で重力を変えてもその後の動きだけが変わる。
+    pub fn apply(world: &mut hecs::World, delta_time: Duration) {
+        let (gravity, max_fall_speed) = current_gravity(world);
+        let delta_time = delta_time.as_secs_f32();
+        let world = &*world;
+        let obstacles = Obstacles::collect(world);
+        for (_, (body, transform, collider)) in world
+            .query::<(
+                &mut KinematicBodyComponent,
+                &mut TransformComponent,
+                Option<&ColliderComponent>,
+            )>()
+            .iter()
+        {
+            let displacement = body.integrate(&gravity, max_fall_speed, delta_time);
+            let Some(collider) = collider else {
+                transform.translation.vector += displacement;
+                continue;
+            };
+            let moved = move_and_slide(
+                body,
+                collider.rect(transform),
+                displacement.xy(),
+                &obstacles,
+            );
+            transform.translation.vector += Vector3::new(moved.x, moved.y, displacement.z);
+        }
+    }
+}
+
+impl System for KinematicSystem {
+    fn setup(&mut self, _resource: Option<&RenderResource<'_>>) {}
+
+    fn update(
+        &mut self,
+        frame: &Frame<'_>,
+        world: &mut hecs::World,
+        _resource: Option<&RenderResource<'_>>,
+    ) {
+        Self::apply(world, frame.delta_time);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::{insert_resource, resource_mut};
+
+    #[test]
+    fn gravity_change_affects_only_later_steps() {
+        let mut world = hecs::World::new();
+        insert_resource(
+            &mut world,
+            PhysicsSettings {
+                gravity: Vector3::new(0.0, 10.0, 0.0),
+                ..Default::default()
+            },
+        );
+        let body = world.spawn((
+            KinematicBodyComponent::new(Vector3::new(0.0, -10.0, 0.0)),
+            TransformComponent::default(),
+        ));
+        KinematicSystem::apply(&mut world, Duration::from_millis(500));
+        assert_eq!(
+            world.get::<&KinematicBodyComponent>(body).unwrap().velocity,
+            Vector3::new(0.0, -5.0, 0.0)
+        );
+
+        // ジャンプの途中で月の重力にする
+        resource_mut::<PhysicsSettings>(&world).unwrap().gravity = Vector3::new(0.0, 2.0, 0.0);
+        KinematicSystem::apply(&mut world, Duration::from_millis(500));
+        assert_eq!(
+            world.get::<&KinematicBodyComponent>(body).unwrap().velocity,
+            Vector3::new(0.0, -4.0, 0.0)
+        );
+        let transform = world.get::<&TransformComponent>(body).unwrap();
+        assert_eq!(transform.translation.vector, Vector3::new(0.0, -4.5, 0.0));
+    }
+
+    #[test]
+    fn fall_speed_is_clamped_along_gravity() {
+        let gravity = Vector3::new(0.0, 100.0, 0.0);
+        let mut body = KinematicBodyComponent::new(Vector3::new(3.0, 40.0, 0.0));
+        body.integrate(&gravity, 50.0, 1.0);
+        assert_eq!(body.velocity, Vector3::new(3.0, 50.0, 0.0));
+
+        // 上向きには抑えない
+        body.velocity.y = -500.0;
+        body.integrate(&gravity, 50.0, 1.0);
+        assert_eq!(body.velocity.y, -400.0);
+
+        let mut body = body.with_max_fall_speed(60.0);
+        body.velocity.y = 0.0;
+        body.integrate(&gravity, 50.0, 1.0);
+        assert_eq!(body.velocity, Vector3::new(3.0, 60.0, 0.0));
+    }
+
+    #[test]
+    fn settings_round_trip_through_toml() {
+        let settings = PhysicsSettings::from_toml(
+            r#"
+            gravity = [0.0, 160.0, 0.0]
+            collision_layers = ["world", "player"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(settings.gravity, Vector3::new(0.0, 160.0, 0.0));
+        assert_eq!(
+            settings.max_fall_speed,
+            PhysicsSettings::default().max_fall_speed
+        );
+        assert_eq!(settings.layer_name(1), Some("player"));
+        assert_eq!(settings.layer_index("world"), Some(0));
+        assert_eq!(
+            PhysicsSettings::from_toml(&settings.to_toml().unwrap()).unwrap(),
+            settings
+        );
+    }
+}
@@ -0,0 +1,616 @@
+//! 座標軸に沿った長方形の当たり判定と、それに沿って滑らせる動かし方
+//!
+//! 座標はピクセル座標 (y が下向き) で、重力は下 (+y) に向いているものとする。
+//! 片側だけの足場は上から乗れ、斜面は上り下りしても地面から離れない。
+use std::collections::BTreeMap;
+
+use nalgebra::{Point2, Vector2};
+use reverie_util::math::Rect;
+
+use crate::scene::{SpawnOrderComponent, TilemapComponent, TransformComponent};
+
+use super::KinematicBodyComponent;
+
+/// 接しているだけのものを重なりとみなさないための余裕
+const EPSILON: f32 = 1e-3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// 当たり判定の形
+///
+/// 斜面は長方形の対角線が面になり、足元 (下の辺の真ん中) だけで当たる。
+/// 正方形なら 45° の斜面になる。
+pub enum CollisionShape {
+    #[default]
+    /// どの向きからも通れない
+    Solid,
+    /// 上から落ちてきたときだけ乗れる足場。下や横からはすり抜ける
+    OneWay,
+    /// 左下から右上へ上る斜面 (`/`)
+    SlopeUpRight,
+    /// 左上から右下へ下る斜面 (`\`)
+    SlopeUpLeft,
+}
+
+impl CollisionShape {
+    pub const fn is_slope(self) -> bool {
+        matches!(self, Self::SlopeUpRight | Self::SlopeUpLeft)
+    }
+
+    /// `rect` に置いた斜面の、横の位置 `x` での面の高さ。斜面でなければ `None`
+    fn surface(self, rect: &Rect, x: f32) -> Option<f32> {
+        let t = ((x - rect.min.x) / rect.width()).clamp(0.0, 1.0);
+        match self {
+            Self::SlopeUpRight => Some(t.mul_add(-rect.height(), rect.max.y)),
+            Self::SlopeUpLeft => Some(t.mul_add(rect.height(), rect.min.y)),
+            Self::Solid | Self::OneWay => None,
+        }
+    }
+
+    /// `rect` に置いた斜面の傾き (右へ 1 進んだときの y の変化)
+    fn gradient(self, rect: &Rect) -> f32 {
+        match self {
+            Self::SlopeUpRight => -rect.height() / rect.width(),
+            Self::SlopeUpLeft => rect.height() / rect.width(),
+            Self::Solid | Self::OneWay => 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// 長方形の当たり判定
+///
+/// [`KinematicBodyComponent`] と同じエンティティに付けるとその体の形になり、
+/// 付けないエンティティに付けると動かない壁や床になる。体の形は [`CollisionShape`] を無視する。
+/// 長方形の左上は [`TransformComponent`] の位置から `offset` だけずらしたところで、回転と拡大縮小は無視する。
+pub struct ColliderComponent {
+    pub size: Vector2<f32>,
+    pub offset: Vector2<f32>,
+    pub shape: CollisionShape,
+}
+
+impl ColliderComponent {
+    pub fn new(size: Vector2<f32>) -> Self {
+        Self {
+            size,
+            offset: Vector2::zeros(),
+            shape: CollisionShape::Solid,
+        }
+    }
+
+    pub const fn with_offset(mut self, offset: Vector2<f32>) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    pub const fn with_shape(mut self, shape: CollisionShape) -> Self {
+        self.shape = shape;
+        self
+    }
+
+    /// ワールド座標での長方形
+    pub fn rect(&self, transform: &TransformComponent) -> Rect {
+        Rect::from_min_size(
+            Point2::from(transform.translation.vector.xy() + self.offset),
+            self.size,
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// タイルの番号ごとの当たり判定の形
+///
+/// [`TilemapComponent`] と同じエンティティに付ける。形を決めていないタイルと空のマスはすり抜ける。
+/// タイルの大きさには [`TransformComponent::scale`] を掛けるが、回転は無視する。
+pub struct TileCollisionComponent {
+    shapes: BTreeMap<u32, CollisionShape>,
+}
+
+impl TileCollisionComponent {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_shape(mut self, tile: u32, shape: CollisionShape) -> Self {
+        self.set_shape(tile, shape);
+        self
+    }
+
+    /// タイル `tile` の形を決める
+    pub fn set_shape(&mut self, tile: u32, shape: CollisionShape) {
+        self.shapes.insert(tile, shape);
+    }
+
+    /// タイル `tile` をすり抜けるようにする
+    pub fn remove_shape(&mut self, tile: u32) -> Option<CollisionShape> {
+        self.shapes.remove(&tile)
+    }
+
+    pub fn shape(&self, tile: u32) -> Option<CollisionShape> {
+        self.shapes.get(&tile).copied()
+    }
+}
+
+/// 体がぶつかるかもしれないもの
+#[derive(Debug, Clone, Copy)]
+struct Obstacle {
+    rect: Rect,
+    shape: CollisionShape,
+}
+
+/// 当たり判定を調べるタイルマップ
+struct TileGrid<'a> {
+    map: hecs::Ref<'a, TilemapComponent>,
+    shapes: hecs::Ref<'a, TileCollisionComponent>,
+    origin: Point2<f32>,
+    tile_size: Vector2<f32>,
+}
+
+impl TileGrid<'_> {
+    /// `area` と重なるタイルを `out` に加える
+    fn obstacles_in(&self, area: &Rect, out: &mut Vec<Obstacle>) {
+        let (width, height) = (self.map.width(), self.map.height());
+        if width == 0 || height == 0 {
+            return;
+        }
+        let cell = |p: &Point2<f32>| {
+            (p - self.origin)
+                .component_div(&self.tile_size)
+                .map(f32::floor)
+        };
+        let (min, max) = (cell(&area.min), cell(&area.max));
+        if max.x < 0.0 || max.y < 0.0 || min.x >= width as f32 || min.y >= height as f32 {
+            return;
+        }
+        let (x0, y0) = (min.x.max(0.0) as u32, min.y.max(0.0) as u32);
+        let (x1, y1) = (
+            (max.x as u32).min(width - 1),
+            (max.y as u32).min(height - 1),
+        );
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                let Some(shape) = self.map.tile(x, y).and_then(|tile| self.shapes.shape(tile))
+                else {
+                    continue;
+                };
+                let min =
+                    self.origin + Vector2::new(x as f32, y as f32).component_mul(&self.tile_size);
+                out.push(Obstacle {
+                    rect: Rect::from_min_size(min, self.tile_size),
+                    shape,
+                });
+            }
+        }
+    }
+}
+
+/// 1 回の更新で体がぶつかるもの
+pub struct Obstacles<'a> {
+    colliders: Vec<Obstacle>,
+    tilemaps: Vec<TileGrid<'a>>,
+}
+
+impl<'a> Obstacles<'a> {
+    /// `world` の動かない当たり判定を集める
+    ///
+    /// タイルマップは借りるだけなので、これを持っている間は [`TilemapComponent`] と
+    /// [`TileCollisionComponent`] を書き換えられない。
+    /// [`crate::scene::Determinism`] があれば、[`SpawnOrderComponent`] の順に並べる。
+    pub fn collect(world: &'a hecs::World) -> Self {
+        let mut colliders: Vec<_> = world
+            .query::<(
+                &ColliderComponent,
+                &TransformComponent,
+                Option<&SpawnOrderComponent>,
+            )>()
+            .without::<&KinematicBodyComponent>()
+            .iter()
+            .map(|(_, (collider, transform, order))| {
+                let obstacle = Obstacle {
+                    rect: collider.rect(transform),
+                    shape: collider.shape,
+                };
+                (order.copied(), obstacle)
+            })
+            .collect();
+        colliders.sort_by_key(|&(order, _)| order);
+        let mut placements: Vec<_> = world
+            .query::<(
+                &TilemapComponent,
+                &TransformComponent,
+                Option<&SpawnOrderComponent>,
+            )>()
+            .with::<&TileCollisionComponent>()
+            .iter()
+            .map(|(entity, (map, transform, order))| {
+                let origin = Point2::from(transform.translation.vector.xy());
+                let tile_size = map.tile_size().component_mul(&transform.scale.vector.xy());
+                (order.copied(), entity, origin, tile_size)
+            })
+            .collect();
+        placements.sort_by_key(|&(order, ..)| order);
+        let colliders = colliders
+            .into_iter()
+            .map(|(_, obstacle)| obstacle)
+            .collect();
+        let tilemaps = placements
+            .into_iter()
+            .filter_map(|(_, entity, origin, tile_size)| {
+                Some(TileGrid {
+                    map: world.get::<&TilemapComponent>(entity).ok()?,
+                    shapes: world.get::<&TileCollisionComponent>(entity).ok()?,
+                    origin,
+                    tile_size,
+                })
+            })
+            .collect();
+        Self {
+            colliders,
+            tilemaps,
+        }
+    }
+
+    fn in_area(&self, area: &Rect) -> Vec<Obstacle> {
+        let mut out: Vec<_> = self
+            .colliders
+            .iter()
+            .filter(|obstacle| obstacle.rect.intersects(area))
+            .copied()
+            .collect();
+        for grid in &self.tilemaps {
+            grid.obstacles_in(area, &mut out);
+        }
+        out
+    }
+}
+
+fn translated(rect: &Rect, offset: Vector2<f32>) -> Rect {
+    Rect {
+        min: rect.min + offset,
+        max: rect.max + offset,
+    }
+}
+
+/// 接しているだけでなく、本当に重なっているか
+fn overlaps(a: &Rect, b: &Rect) -> bool {
+    a.min.x < b.max.x - EPSILON
+        && b.min.x < a.max.x - EPSILON
+        && a.min.y < b.max.y - EPSILON
+        && b.min.y < a.max.y - EPSILON
+}
+
+/// `rect` の足元にある斜面の中で、いちばん高い面の高さとその斜面
+///
+/// 足元から上に `above`、下に `below` だけ離れた面まで探す。
+/// 横に `reach` だけ外れた斜面は、端の高さが続いているとみなす。斜面の端を越えた 1 回の移動で壁に引っかからないようにするため。
+fn slope_under_foot(
+    obstacles: &Obstacles<'_>,
+    rect: &Rect,
+    reach: f32,
+    above: f32,
+    below: f32,
+) -> Option<(f32, Obstacle)> {
+    let foot = Point2::new(rect.center().x, rect.max.y);
+    let area = Rect::new(
+        Point2::new(foot.x - reach - EPSILON, foot.y - above),
+        Point2::new(foot.x + reach + EPSILON, foot.y + below),
+    );
+    obstacles
+        .in_area(&area)
+        .into_iter()
+        .filter_map(|obstacle| {
+            let surface = obstacle.shape.surface(&obstacle.rect, foot.x)?;
+            (surface >= area.min.y && surface <= area.max.y).then_some((surface, obstacle))
+        })
+        .min_by(|(a, _), (b, _)| a.total_cmp(b))
+}
+
+/// `rect` の体を `displacement` だけ動かし、ぶつかったところで止めて、本当に動いた量を返す
+///
+/// 横、縦の順に動かし、壁や天井にぶつかった向きの速度を 0 にする。
+/// 足元が斜面の中にあるときは、斜面の上の端より低いタイルと壁に横からぶつからない。
+/// 斜面では面の上に押し上げ、下り坂では速度を斜面に沿う向きに直して地面から浮かないようにする。
+/// 下り坂に吸い付くのは、傾きが 2 (およそ 63°) までの斜面。
+pub fn move_and_slide(
+    body: &mut KinematicBodyComponent,
+    rect: Rect,
+    displacement: Vector2<f32>,
+    obstacles: &Obstacles<'_>,
+) -> Vector2<f32> {
+    let was_on_ground = body.on_ground;
+    body.on_ground = false;
+    body.on_slope = false;
+    let start = rect.min;
+
+    // 足元が斜面の上なら、斜面の上の端から下にある壁は斜面の続きとみなす
+    let mut rect = translated(&rect, Vector2::new(displacement.x, 0.0));
+    let step = displacement.x.abs() + rect.height() / 2.0;
+    let slope_top = slope_under_foot(obstacles, &rect, displacement.x.abs(), step, step)
+        .map(|(_, slope)| slope.rect.min.y - EPSILON);
+    let blocks = |obstacle: &Obstacle| {
+        obstacle.shape == CollisionShape::Solid
+            && slope_top.map_or(true, |top| obstacle.rect.min.y < top)
+    };
+
+    for obstacle in obstacles.in_area(&rect) {
+        if !blocks(&obstacle) || !overlaps(&rect, &obstacle.rect) {
+            continue;
+        }
+        let push = if displacement.x > 0.0
+            || (displacement.x == 0.0 && rect.center().x < obstacle.rect.center().x)
+        {
+            obstacle.rect.min.x - rect.max.x
+        } else {
+            obstacle.rect.max.x - rect.min.x
+        };
+        rect = translated(&rect, Vector2::new(push, 0.0));
+        body.velocity.x = 0.0;
+    }
+
+    let bottom_before = rect.max.y;
+    rect = translated(&rect, Vector2::new(0.0, displacement.y));
+    for obstacle in obstacles.in_area(&rect) {
+        if !overlaps(&rect, &obstacle.rect) {
+            continue;
+        }
+        let falling = displacement.y > 0.0;
+        let push = match obstacle.shape {
+            CollisionShape::Solid if blocks(&obstacle) => {
+                if falling || (displacement.y == 0.0 && rect.center().y < obstacle.rect.center().y)
+                {
+                    obstacle.rect.min.y - rect.max.y
+                } else {
+                    obstacle.rect.max.y - rect.min.y
+                }
+            }
+            CollisionShape::OneWay
+                if falling && !body.dropping && bottom_before <= obstacle.rect.min.y + EPSILON =>
+            {
+                obstacle.rect.min.y - rect.max.y
+            }
+            _ => continue,
+        };
+        rect = translated(&rect, Vector2::new(0.0, push));
+        body.velocity.y = 0.0;
+        if push < 0.0 {
+            body.on_ground = true;
+        }
+    }
+
+    // 斜面に埋まっていれば押し上げ、下り坂で少し浮いたなら下ろす
+    let snap = if was_on_ground && body.velocity.y >= 0.0 {
+        displacement.x.abs().mul_add(2.0, EPSILON)
+    } else {
+        0.0
+    };
+    if let Some((surface, slope)) = slope_under_foot(
+        obstacles,
+        &rect,
+        displacement.x.abs(),
+        rect.height() / 2.0,
+        snap,
+    ) {
+        rect = translated(&rect, Vector2::new(0.0, surface - rect.max.y));
+        body.on_ground = true;
+        body.on_slope = true;
+        let along = body.velocity.x * slope.shape.gradient(&slope.rect);
+        body.velocity.y = along.max(0.0);
+    }
+
+    if body.dropping
+        && !obstacles.in_area(&rect).iter().any(|obstacle| {
+            obstacle.shape == CollisionShape::OneWay && overlaps(&rect, &obstacle.rect)
+        })
+    {
+        body.dropping = false;
+    }
+
+    rect.min - start
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use nalgebra::{Translation3, Vector3};
+
+    use super::*;
+    use crate::{physics::KinematicSystem, scene::Tileset, texture::TextureId};
+
+    const TILE: f32 = 16.0;
+    const STEP: Duration = Duration::from_nanos(16_666_667);
+
+    /// `#` が壁、`-` が片側だけの足場、`/` と `\` が斜面の 16 ピクセル四方のタイルマップ
+    fn world_with_map(rows: &[&str]) -> hecs::World {
+        let shapes = [
+            ('#', CollisionShape::Solid),
+            ('-', CollisionShape::OneWay),
+            ('/', CollisionShape::SlopeUpRight),
+            ('\\', CollisionShape::SlopeUpLeft),
+        ];
+        let mut map = TilemapComponent::new(
+            Tileset::new(TextureId::WHITE, 4, 1),
+            rows[0].len() as u32,
+            rows.len() as u32,
+            Vector2::new(TILE, TILE),
+        );
+        let mut collision = TileCollisionComponent::new();
+        for (tile, (c, shape)) in shapes.into_iter().enumerate() {
+            collision.set_shape(tile as u32, shape);
+            for (y, row) in rows.iter().enumerate() {
+                for (x, _) in row.chars().enumerate().filter(|&(_, cell)| cell == c) {
+                    map.set_tile(x as u32, y as u32, Some(tile as u32));
+                }
+            }
+        }
+        let mut world = hecs::World::new();
+        world.spawn((map, collision, TransformComponent::default()));
+        world
+    }
+
+    /// 8x16 の体を左上が `(x, y)` になるように置く
+    fn spawn_body(world: &mut hecs::World, x: f32, y: f32) -> hecs::Entity {
+        world.spawn((
+            KinematicBodyComponent::default(),
+            ColliderComponent::new(Vector2::new(8.0, 16.0)),
+            TransformComponent::with_translation(Translation3::new(x, y, 0.0)),
+        ))
+    }
+
+    fn position(world: &hecs::World, body: hecs::Entity) -> Vector2<f32> {
+        world
+            .get::<&TransformComponent>(body)
+            .unwrap()
+            .translation
+            .vector
+            .xy()
+    }
+
+    fn body(world: &hecs::World, body: hecs::Entity) -> KinematicBodyComponent {
+        *world.get::<&KinematicBodyComponent>(body).unwrap()
+    }
+
+    fn assert_near(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 0.05,
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    #[rustfmt::skip]
+    const SLOPE: &[&str] = &[
+        "........",
+        "........",
+        "........",
+        "........",
+        "../#####",
+        "./######",
+        "########",
+    ];
+
+    #[test]
+    fn walks_up_a_slope_without_stopping() {
+        let mut world = world_with_map(SLOPE);
+        let player = spawn_body(&mut world, 0.0, 80.0);
+        let mut touched_slope = false;
+        for _ in 0..90 {
+            world
+                .get::<&mut KinematicBodyComponent>(player)
+                .unwrap()
+                .velocity
+                .x = 60.0;
+            KinematicSystem::apply(&mut world, STEP);
+            let state = body(&world, player);
+            assert!(
+                state.on_ground(),
+                "left the ground at {:?}",
+                position(&world, player)
+            );
+            touched_slope |= state.on_slope();
+        }
+        assert!(touched_slope);
+        let position = position(&world, player);
+        assert_near(position.x, 90.0);
+        assert_near(position.y, 64.0 - 16.0);
+    }
+
+    #[test]
+    fn walks_down_a_slope_without_floating() {
+        let mut world = world_with_map(SLOPE);
+        let player = spawn_body(&mut world, 60.0, 48.0);
+        KinematicSystem::apply(&mut world, STEP);
+        for _ in 0..60 {
+            world
+                .get::<&mut KinematicBodyComponent>(player)
+                .unwrap()
+                .velocity
+                .x = -60.0;
+            KinematicSystem::apply(&mut world, STEP);
+            assert!(
+                body(&world, player).on_ground(),
+                "floated at {:?}",
+                position(&world, player)
+            );
+        }
+        let position = position(&world, player);
+        assert_near(position.x, 0.0);
+        assert_near(position.y, 80.0);
+    }
+
+    #[test]
+    fn stands_still_on_a_slope() {
+        let mut world = world_with_map(SLOPE);
+        // 足元が x = 28 の斜面の上
+        let player = spawn_body(&mut world, 24.0, 60.0);
+        for _ in 0..30 {
+            KinematicSystem::apply(&mut world, STEP);
+        }
+        let state = body(&world, player);
+        assert!(state.on_ground() && state.on_slope());
+        let position = position(&world, player);
+        assert_near(position.x, 24.0);
+        assert_near(position.y, 96.0 - 12.0 - 16.0);
+    }
+
+    #[test]
+    fn one_way_platforms_land_from_above_and_drop_through() {
+        #[rustfmt::skip]
+        let mut world = world_with_map(&[
+            "....",
+            "....",
+            "----",
+            "....",
+            "....",
+            "####",
+        ]);
+        let player = spawn_body(&mut world, 8.0, 0.0);
+        for _ in 0..30 {
+            KinematicSystem::apply(&mut world, STEP);
+        }
+        assert!(body(&world, player).on_ground());
+        assert_near(position(&world, player).y, 16.0);
+
+        world
+            .get::<&mut KinematicBodyComponent>(player)
+            .unwrap()
+            .drop_through();
+        for _ in 0..60 {
+            KinematicSystem::apply(&mut world, STEP);
+        }
+        let state = body(&world, player);
+        assert!(state.on_ground() && !state.is_dropping());
+        assert_near(position(&world, player).y, 64.0);
+
+        // 下からは跳んですり抜ける
+        world
+            .get::<&mut KinematicBodyComponent>(player)
+            .unwrap()
+            .velocity
+            .y = -400.0;
+        let mut highest = f32::MAX;
+        for _ in 0..60 {
+            KinematicSystem::apply(&mut world, STEP);
+            highest = highest.min(position(&world, player).y);
+        }
+        assert!(highest < 32.0 - 16.0, "highest {highest}");
+        assert_near(position(&world, player).y, 16.0);
+    }
+
+    #[test]
+    fn collider_entities_block_bodies() {
+        let mut world = hecs::World::new();
+        world.spawn((
+            ColliderComponent::new(Vector2::new(16.0, 64.0)),
+            TransformComponent::with_translation(Translation3::new(32.0, 0.0, 0.0)),
+        ));
+        let player = world.spawn((
+            KinematicBodyComponent::new(Vector3::new(120.0, 0.0, 0.0)).with_gravity_scale(0.0),
+            ColliderComponent::new(Vector2::new(8.0, 16.0)),
+            TransformComponent::default(),
+        ));
+        for _ in 0..30 {
+            KinematicSystem::apply(&mut world, STEP);
+        }
+        assert_eq!(position(&world, player), Vector2::new(24.0, 0.0));
+        assert_eq!(body(&world, player).velocity.x, 0.0);
+    }
+}
@@ -0,0 +1,521 @@
+//! タイルマップの当たり判定を、まとめた長方形の [`ColliderComponent`] にする
+use std::collections::BTreeMap;
+
+use nalgebra::{Translation3, Vector2};
+
+use crate::scene::{
+    despawn_recursive, set_parent, EntityIndex, Frame, RenderResource, System, TilemapComponent,
+    TransformComponent, CHUNK_SIZE,
+};
+
+use super::{ColliderComponent, CollisionShape, TileCollisionComponent};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// 同じ形のマスをまとめた長方形。単位はマス
+struct CellRect {
+    shape: Shape,
+    y: u32,
+    x: u32,
+    height: u32,
+    width: u32,
+}
+
+/// 並べ替えられる [`CollisionShape`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Shape(u8);
+
+impl Shape {
+    const fn new(shape: CollisionShape) -> Self {
+        Self(match shape {
+            CollisionShape::Solid => 0,
+            CollisionShape::OneWay => 1,
+            CollisionShape::SlopeUpRight => 2,
+            CollisionShape::SlopeUpLeft => 3,
+        })
+    }
+
+    const fn get(self) -> CollisionShape {
+        match self.0 {
+            0 => CollisionShape::Solid,
+            1 => CollisionShape::OneWay,
+            2 => CollisionShape::SlopeUpRight,
+            _ => CollisionShape::SlopeUpLeft,
+        }
+    }
+
+    /// 横に並んだマスを 1 つにまとめてよいか
+    const fn merges_across(self) -> bool {
+        matches!(self.get(), CollisionShape::Solid | CollisionShape::OneWay)
+    }
+
+    /// 縦に並んだマスを 1 つにまとめてよいか。片側だけの足場は上の面だけが効くのでまとめない
+    const fn merges_down(self) -> bool {
+        matches!(self.get(), CollisionShape::Solid)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// タイルマップの当たり判定を、長方形の [`ColliderComponent`] を持つ子のエンティティにして置くコンポーネント
+///
+/// [`TilemapComponent`] と同じエンティティに、[`TileCollisionComponent`] の代わりに付ける。
+/// [`TileColliderBakeSystem`] が形の同じ隣り合ったマスをできるだけ大きな長方形にまとめて子として置くので、
+/// [`super::Obstacles`] はほかの壁や床と同じに扱う。
+///
+/// * 壁 ([`CollisionShape::Solid`]) は縦にも横にもまとめる。横 100 マス、縦 1 マスの壁は 1 つになる。
+/// * 片側だけの足場は上の面だけが効くので、横にだけまとめる。
+/// * 斜面はマスごとに置く。
+///
+/// タイルを置き換えると、変わった [`CHUNK_SIZE`] 四方のチャンクだけを調べ直し、
+/// 変わらなかった長方形のエンティティはそのまま残す。
+/// 子はタイルマップの [`TransformComponent`] の位置と拡大縮小に付いていく。[`ColliderComponent`] と同じく回転は無視する。
+/// タイルマップを [`despawn_recursive`] で消すと一緒に消える。
+pub struct TileColliderBakeComponent {
+    shapes: TileCollisionComponent,
+    /// チャンクごとの、前に調べたときの [`TilemapComponent::chunk_revision`] とまとめた長方形
+    chunks: Vec<Option<(u64, Vec<CellRect>)>>,
+    /// 置いた子のエンティティ
+    colliders: BTreeMap<CellRect, hecs::Entity>,
+    /// 子を置いたときの、ワールドでのタイルの大きさ
+    tile_size: Option<Vector2<f32>>,
+}
+
+impl TileColliderBakeComponent {
+    pub fn new(shapes: TileCollisionComponent) -> Self {
+        Self {
+            shapes,
+            ..Default::default()
+        }
+    }
+
+    pub const fn shapes(&self) -> &TileCollisionComponent {
+        &self.shapes
+    }
+
+    /// タイルの番号ごとの形を変える。次の [`TileColliderBakeSystem`] ですべて調べ直す
+    pub fn set_shapes(&mut self, shapes: TileCollisionComponent) {
+        self.shapes = shapes;
+        self.chunks.clear();
+    }
+
+    /// 置いた子のエンティティの数
+    pub fn collider_count(&self) -> usize {
+        self.colliders.len()
+    }
+
+    /// 置いた子のエンティティ
+    pub fn colliders(&self) -> impl Iterator<Item = EntityIndex> + '_ {
+        self.colliders.values().map(|&entity| EntityIndex(entity))
+    }
+
+    /// タイルが変わったチャンクを調べ直し、置くべき長方形を返す。何も変わっていなければ `None`
+    fn rebake(&mut self, map: &TilemapComponent, tile_size: Vector2<f32>) -> Option<Vec<CellRect>> {
+        if self.chunks.len() != map.chunk_count() {
+            self.chunks = vec![None; map.chunk_count()];
+        }
+        let mut changed = self.tile_size != Some(tile_size);
+        let columns = map.width().div_ceil(CHUNK_SIZE);
+        for (index, cached) in self.chunks.iter_mut().enumerate() {
+            let revision = map.chunk_revision(index).unwrap_or_default();
+            if cached.as_ref().is_some_and(|(seen, _)| *seen == revision) {
+                continue;
+            }
+            let (cx, cy) = (index as u32 % columns, index as u32 / columns);
+            let origin = (cx * CHUNK_SIZE, cy * CHUNK_SIZE);
+            let size = (
+                CHUNK_SIZE.min(map.width() - origin.0),
+                CHUNK_SIZE.min(map.height() - origin.1),
+            );
+            let shape_at = |x: u32, y: u32| {
+                map.tile(origin.0 + x, origin.1 + y)
+                    .and_then(|tile| self.shapes.shape(tile))
+                    .map(Shape::new)
+            };
+            let mut rects = greedy_merge(size, shape_at);
+            for rect in &mut rects {
+                rect.x += origin.0;
+                rect.y += origin.1;
+            }
+            *cached = Some((revision, rects));
+            changed = true;
+        }
+        if !changed {
+            return None;
+        }
+        self.tile_size = Some(tile_size);
+        let rects = self
+            .chunks
+            .iter()
+            .flatten()
+            .flat_map(|(_, rects)| rects.iter().copied())
+            .collect();
+        Some(stitch(rects))
+    }
+}
+
+/// `width` x `height` のマスのうち形の同じものを、左上から順にできるだけ大きな長方形にまとめる
+///
+/// 横に伸ばせるだけ伸ばしてから、その幅のまま下に伸ばせるだけ伸ばす。
+fn greedy_merge(
+    (width, height): (u32, u32),
+    shape_at: impl Fn(u32, u32) -> Option<Shape>,
+) -> Vec<CellRect> {
+    let mut used = vec![false; (width * height) as usize];
+    let free = |used: &[bool], x: u32, y: u32, shape: Shape| {
+        !used[(y * width + x) as usize] && shape_at(x, y) == Some(shape)
+    };
+    let mut rects = Vec::new();
+    for y in 0..height {
+        for x in 0..width {
+            let Some(shape) = shape_at(x, y).filter(|_| !used[(y * width + x) as usize]) else {
+                continue;
+            };
+            let mut w = 1;
+            while shape.merges_across() && x + w < width && free(&used, x + w, y, shape) {
+                w += 1;
+            }
+            let mut h = 1;
+            while shape.merges_down()
+                && y + h < height
+                && (x..x + w).all(|cx| free(&used, cx, y + h, shape))
+            {
+                h += 1;
+            }
+            for cy in y..y + h {
+                for cx in x..x + w {
+                    used[(cy * width + cx) as usize] = true;
+                }
+            }
+            rects.push(CellRect {
+                shape,
+                y,
+                x,
+                height: h,
+                width: w,
+            });
+        }
+    }
+    rects
+}
+
+/// チャンクの境目で切れた長方形をつなぐ
+///
+/// 横に接していて高さの同じもの、縦に接していて幅の同じものを 1 つにする。
+fn stitch(mut rects: Vec<CellRect>) -> Vec<CellRect> {
+    // 形、行、高さ、列の順に並べると、横につながるものが隣り合う
+    rects.sort_unstable_by_key(|r| (r.shape, r.y, r.height, r.x));
+    rects.dedup_by(|next, current| {
+        let joins = current.shape.merges_across()
+            && (current.shape, current.y, current.height) == (next.shape, next.y, next.height)
+            && current.x + current.width == next.x;
+        if joins {
+            current.width += next.width;
+        }
+        joins
+    });
+    rects.sort_unstable_by_key(|r| (r.shape, r.x, r.width, r.y));
+    rects.dedup_by(|next, current| {
+        let joins = current.shape.merges_down()
+            && (current.shape, current.x, current.width) == (next.shape, next.x, next.width)
+            && current.y + current.height == next.y;
+        if joins {
+            current.height += next.height;
+        }
+        joins
+    });
+    rects.sort_unstable();
+    rects
+}
+
+#[derive(Debug, Default)]
+/// [`TileColliderBakeComponent`] を付けたタイルマップの当たり判定を、子のエンティティにして置く
+///
+/// 同じフレームでタイルに沿って体を動かすには、[`super::KinematicSystem`] より前に追加する。
+pub struct TileColliderBakeSystem;
+
+impl TileColliderBakeSystem {
+    pub const fn new() -> Self {
+        Self
+    }
+
+    /// タイルが変わったタイルマップの子を置き直す
+    ///
+    /// [`System::update`] から呼ばれる。GPU に触れないのでテストからも直接呼べる。
+    pub fn apply(world: &mut hecs::World) {
+        let mut changed = Vec::new();
+        for (entity, (map, transform, bake)) in world.query_mut::<(
+            &TilemapComponent,
+            &TransformComponent,
+            &mut TileColliderBakeComponent,
+        )>() {
+            let tile_size = map.tile_size().component_mul(&transform.scale.vector.xy());
+            if let Some(rects) = bake.rebake(map, tile_size) {
+                changed.push((entity, map.tile_size(), tile_size, rects));
+            }
+        }
+        for (entity, local_size, tile_size, rects) in changed {
+            place_colliders(world, entity, local_size, tile_size, rects);
+        }
+    }
+}
+
+/// タイルマップ `tilemap` の子を `rects` に合わせる。同じ長方形の子は残し、大きさだけ合わせる
+fn place_colliders(
+    world: &mut hecs::World,
+    tilemap: hecs::Entity,
+    local_size: Vector2<f32>,
+    tile_size: Vector2<f32>,
+    rects: Vec<CellRect>,
+) {
+    let Ok(mut bake) = world.get::<&mut TileColliderBakeComponent>(tilemap) else {
+        return;
+    };
+    let mut previous = std::mem::take(&mut bake.colliders);
+    drop(bake);
+    let parent = world
+        .get::<&TransformComponent>(tilemap)
+        .map(|transform| (*transform).clone());
+    let Ok(parent) = parent else {
+        return;
+    };
+    let collider = |rect: &CellRect| {
+        ColliderComponent::new(Vector2::new(
+            rect.width as f32 * tile_size.x,
+            rect.height as f32 * tile_size.y,
+        ))
+        .with_shape(rect.shape.get())
+    };
+
+    let mut colliders = BTreeMap::new();
+    for rect in rects {
+        if let Some(entity) = previous.remove(&rect) {
+            if let Ok(mut current) = world.get::<&mut ColliderComponent>(entity) {
+                *current = collider(&rect);
+                colliders.insert(rect, entity);
+                continue;
+            }
+        }
+        // 子の位置は、タイルマップを動かしたり拡大縮小したりしても付いていくように親から見た位置で決める
+        let local = TransformComponent::with_translation(Translation3::new(
+            rect.x as f32 * local_size.x,
+            rect.y as f32 * local_size.y,
+            0.0,
+        ));
+        let entity = world.spawn((collider(&rect), parent.compose(&local)));
+        set_parent(world, EntityIndex(entity), EntityIndex(tilemap))
+            .expect("both entities exist and the child is new");
+        colliders.insert(rect, entity);
+    }
+    for entity in previous.into_values() {
+        let _ = despawn_recursive(world, EntityIndex(entity));
+    }
+    if let Ok(mut bake) = world.get::<&mut TileColliderBakeComponent>(tilemap) {
+        bake.colliders = colliders;
+    }
+}
+
+impl System for TileColliderBakeSystem {
+    fn setup(&mut self, _resource: Option<&RenderResource<'_>>) {}
+
+    fn update(
+        &mut self,
+        _frame: &Frame<'_>,
+        world: &mut hecs::World,
+        _resource: Option<&RenderResource<'_>>,
+    ) {
+        Self::apply(world);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::time::Duration;
+
+    use nalgebra::{Point2, Scale3, Vector3};
+    use reverie_util::math::Rect;
+
+    use super::*;
+    use crate::{
+        physics::{KinematicBodyComponent, KinematicSystem},
+        scene::{children, Tileset},
+        texture::TextureId,
+    };
+
+    const TILE: f32 = 16.0;
+
+    /// `#` が壁、`-` が片側だけの足場、`/` が斜面のタイルマップ
+    fn baked_map(rows: &[&str]) -> (hecs::World, hecs::Entity) {
+        let mut map = TilemapComponent::new(
+            Tileset::new(TextureId::WHITE, 4, 1),
+            rows[0].len() as u32,
+            rows.len() as u32,
+            Vector2::new(TILE, TILE),
+        );
+        for (y, row) in rows.iter().enumerate() {
+            for (x, cell) in row.chars().enumerate() {
+                let tile = "#-/".find(cell).map(|tile| tile as u32);
+                map.set_tile(x as u32, y as u32, tile);
+            }
+        }
+        let shapes = TileCollisionComponent::new()
+            .with_shape(0, CollisionShape::Solid)
+            .with_shape(1, CollisionShape::OneWay)
+            .with_shape(2, CollisionShape::SlopeUpRight);
+        let mut world = hecs::World::new();
+        let tilemap = world.spawn((
+            map,
+            TileColliderBakeComponent::new(shapes),
+            TransformComponent::default(),
+        ));
+        TileColliderBakeSystem::apply(&mut world);
+        (world, tilemap)
+    }
+
+    fn colliders(world: &hecs::World, tilemap: hecs::Entity) -> Vec<(hecs::Entity, Rect)> {
+        let bake = world.get::<&TileColliderBakeComponent>(tilemap).unwrap();
+        bake.colliders()
+            .map(|entity| {
+                let collider = world.get::<&ColliderComponent>(entity.0).unwrap();
+                let transform = world.get::<&TransformComponent>(entity.0).unwrap();
+                (entity.0, collider.rect(&transform))
+            })
+            .collect()
+    }
+
+    /// 長方形が覆うマス。重なっていれば失敗する
+    fn covered(rects: &[(hecs::Entity, Rect)]) -> BTreeSet<(u32, u32)> {
+        let mut cells = BTreeSet::new();
+        for (_, rect) in rects {
+            let (x0, y0) = ((rect.min.x / TILE) as u32, (rect.min.y / TILE) as u32);
+            let (x1, y1) = ((rect.max.x / TILE) as u32, (rect.max.y / TILE) as u32);
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    assert!(cells.insert((x, y)), "({x}, {y}) is covered twice");
+                }
+            }
+        }
+        cells
+    }
+
+    #[test]
+    fn long_wall_becomes_one_collider() {
+        let wall = "#".repeat(100);
+        let (world, tilemap) = baked_map(&[&wall]);
+        let rects = colliders(&world, tilemap);
+        assert_eq!(rects.len(), 1);
+        assert_eq!(
+            rects[0].1,
+            Rect::new(Point2::origin(), Point2::new(100.0 * TILE, TILE))
+        );
+        // 縦の柱もチャンクをまたいでつながる
+        let column: Vec<_> = (0..40).map(|_| "..##").collect();
+        let (world, tilemap) = baked_map(&column);
+        assert_eq!(colliders(&world, tilemap).len(), 1);
+    }
+
+    #[test]
+    fn merged_colliders_cover_each_solid_cell_once() {
+        let rows = [
+            "####....####......",
+            "####..----..#####.",
+            "##....../.....##..",
+            "##################",
+        ];
+        let (world, tilemap) = baked_map(&rows);
+        let rects = colliders(&world, tilemap);
+        let expected: BTreeSet<_> = rows
+            .iter()
+            .enumerate()
+            .flat_map(|(y, row)| {
+                row.char_indices()
+                    .filter(|&(_, cell)| cell != '.')
+                    .map(move |(x, _)| (x as u32, y as u32))
+            })
+            .collect();
+        assert_eq!(covered(&rects), expected);
+        // 上の 2 つのかたまり、右の段、足場、斜面、左右の柱 (床の一部を含む)、残りの床 2 つ
+        assert_eq!(rects.len(), 9);
+
+        // 足場と斜面は形を保つ
+        let shapes: BTreeSet<_> = rects
+            .iter()
+            .map(|(entity, _)| Shape::new(world.get::<&ColliderComponent>(*entity).unwrap().shape))
+            .collect();
+        assert_eq!(shapes.len(), 3);
+        assert_eq!(children(&world, EntityIndex(tilemap)).len(), rects.len());
+    }
+
+    #[test]
+    fn only_changed_chunks_are_rebaked() {
+        let mut rows = vec![".".repeat(40); 3];
+        rows[2] = "#".repeat(40);
+        rows[0].replace_range(0..4, "####");
+        let rows: Vec<_> = rows.iter().map(String::as_str).collect();
+        let (mut world, tilemap) = baked_map(&rows);
+        let before = colliders(&world, tilemap);
+
+        // 何も変えなければ置き直さない
+        TileColliderBakeSystem::apply(&mut world);
+        assert_eq!(colliders(&world, tilemap), before);
+
+        // 右のチャンクに壁を置いても、左上の壁と床はそのまま
+        world
+            .get::<&mut TilemapComponent>(tilemap)
+            .unwrap()
+            .set_tile(35, 0, Some(0));
+        TileColliderBakeSystem::apply(&mut world);
+        let after = colliders(&world, tilemap);
+        assert_eq!(after.len(), before.len() + 1);
+        for collider in &before {
+            assert!(after.contains(collider));
+        }
+
+        // 床に穴を開けると床だけを置き直す
+        world
+            .get::<&mut TilemapComponent>(tilemap)
+            .unwrap()
+            .set_tile(20, 2, None);
+        TileColliderBakeSystem::apply(&mut world);
+        let holed = colliders(&world, tilemap);
+        assert_eq!(holed.len(), after.len() + 1);
+        let floor = after
+            .iter()
+            .find(|(_, rect)| rect.min.y == 2.0 * TILE)
+            .unwrap();
+        assert!(!holed.contains(floor));
+        assert!(!world.contains(floor.0));
+    }
+
+    #[test]
+    fn colliders_follow_tilemap_scale_and_stop_bodies() {
+        let (mut world, tilemap) = baked_map(&["......", "......", "######"]);
+        world.get::<&mut TransformComponent>(tilemap).unwrap().scale = Scale3::new(2.0, 2.0, 1.0);
+        TileColliderBakeSystem::apply(&mut world);
+        crate::scene::propagate_transforms(&mut world);
+        let rects = colliders(&world, tilemap);
+        assert_eq!(
+            rects[0].1,
+            Rect::new(
+                Point2::new(0.0, 4.0 * TILE),
+                Point2::new(12.0 * TILE, 6.0 * TILE)
+            )
+        );
+
+        // 焼き込んだ床にも、ほかの壁と同じように乗る
+        let body = world.spawn((
+            KinematicBodyComponent::new(Vector3::zeros()),
+            ColliderComponent::new(Vector2::new(8.0, 16.0)),
+            TransformComponent::with_translation(Translation3::new(20.0, 0.0, 0.0)),
+        ));
+        for _ in 0..120 {
+            KinematicSystem::apply(&mut world, Duration::from_nanos(16_666_667));
+        }
+        let transform = world.get::<&TransformComponent>(body).unwrap();
+        let floor = 4.0 * TILE;
+        assert!((transform.translation.y + 16.0 - floor).abs() < 1e-2);
+        assert!(world
+            .get::<&KinematicBodyComponent>(body)
+            .unwrap()
+            .on_ground());
+    }
+}
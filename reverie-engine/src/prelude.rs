@@ -0,0 +1,29 @@
+//! よく使う型をまとめて読み込むためのモジュール
+//!
+//! `use reverie_engine::prelude::*;` で、ゲームを書くのに必要な型がひととおり使えるようになる。
+//! ここに無いものはそれぞれのモジュールから読み込む。
+
+pub use reverie_util::{
+    color::Color,
+    math::{Deg, Rad, Rect},
+};
+pub use winit::{
+    dpi::PhysicalPosition,
+    event::{ElementState, KeyEvent, MouseButton, MouseScrollDelta, TouchPhase},
+    keyboard::{KeyCode, PhysicalKey},
+};
+
+pub use crate::{
+    headless::{CatchUpPolicy, HeadlessRunner, TimeDropped},
+    input::{ActionMap, Binding, GamepadId, Input, Rumble, RumbleSystem},
+    scene::{
+        CameraComponent, EntityIndex, Frame, RenderLayerComponent, RenderResource, Scene,
+        SpriteBuilder, SpriteComponent, System, TextComponent, TransformComponent,
+    },
+    settings::Settings,
+    texture::{TextureId, TextureRegistry},
+    touch::{Gesture, Touch},
+};
+
+#[cfg(feature = "backend-wgpu")]
+pub use crate::{start_engine, start_engine_with_config, EngineConfig, Game};
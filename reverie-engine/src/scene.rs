@@ -1,22 +1,110 @@
 //! シーンに関するモジュール
 
+use std::any::TypeId;
+use std::time::Duration;
+
+use nalgebra::Point2;
 use tracing_unwrap::ResultExt;
+use web_time::Instant;
+
+use crate::{input::Input, particle::ParticleEmitterComponent};
 
-use crate::wgpu_wrapper::WgpuResource;
+#[cfg(feature = "backend-wgpu")]
+use crate::wgpu_wrapper::{render_graph::RenderGraph, WgpuResource};
 
+mod arena;
 mod components;
+mod determinism;
+mod draw_list;
 mod entity;
+mod hierarchy;
+mod merge;
+#[cfg(all(feature = "backend-wgpu", feature = "mesh-export"))]
+mod mesh_export;
+mod picking;
+#[cfg(feature = "backend-wgpu")]
+mod render;
+mod resource;
+mod save;
+mod stats;
 mod system;
+mod tasks;
+mod time;
+mod timing;
+mod validation;
+mod wrap;
 
-pub use components::{sprite::SpriteComponent, transform::TransformComponent};
+pub use arena::FrameArena;
+#[cfg(feature = "backend-wgpu")]
+pub(crate) use components::camera::get_matrix_pixel_to_render_coordinate;
+#[cfg(feature = "backend-wgpu")]
+pub use components::text::TextQuad;
+pub use components::{
+    camera::{CameraComponent, Frustum, Projection},
+    decal::{Decal, DecalLayerComponent, MAX_DECAL_CANVAS_SIZE},
+    floating_text::{
+        FloatingText, FloatingTextAnimation, FloatingTextComponent, FloatingTextPool,
+        FloatingTextSystem,
+    },
+    mask::{MaskComponent, MaskShape},
+    parallax::{tile_range, ParallaxLayerComponent, MAX_PARALLAX_TILES},
+    render_layer::{LayerSortMode, LayerSortModes, RenderLayerComponent},
+    screen_space::ScreenSpaceComponent,
+    sprite::{NineSlice, SpriteBuilder, SpriteComponent},
+    text::TextComponent,
+    tilemap::{
+        TileAnimation, TilemapComponent, Tileset, CHUNK_SIZE, MAX_TILE_ANIMATIONS,
+        MAX_TILE_ANIMATION_FRAMES,
+    },
+    transform::TransformComponent,
+};
+pub use determinism::{
+    for_each_ordered, sync_spawn_order, world_checksum, Determinism, SpawnOrderComponent,
+};
+pub use draw_list::{DrawItem, DrawKind, DrawList, ParallelPrepare};
 pub use entity::EntityIndex;
-pub use system::{Frame, System};
+pub use hierarchy::{
+    children, despawn, despawn_recursive, parent, propagate_transforms, remove_parent, set_parent,
+    world_transform, ChildrenComponent, HierarchyError, LocalTransformComponent, OrphanPolicy,
+    ParentComponent,
+};
+pub use merge::{EntityMap, MapEntities};
+#[cfg(all(feature = "backend-wgpu", feature = "mesh-export"))]
+pub use mesh_export::SpriteBatchDump;
+pub use picking::{entities_at_point, screen_entities_at_point, AlphaTest};
+#[cfg(feature = "backend-wgpu")]
+pub use render::SpriteBatches;
+pub use resource::{
+    clear_events, insert_resource, remove_resource, resource, resource_mut, send_event, Events,
+};
+pub use save::{
+    PreservedComponentsComponent, SaveComponent, SaveData, SaveFile, SaveRegistry, SavedComponent,
+    SavedEntity, UnknownComponents, SAVE_FORMAT_VERSION,
+};
+pub use stats::{FrameStats, ViewStats};
+pub use system::{CycleError, Frame, RenderResource, RenderStage, System};
+pub use tasks::{Task, TaskId, TaskQueue, TaskStats, TaskStatus};
+pub use time::{SceneClock, TimeScale};
+pub use timing::{SystemTimings, TimingOverlaySystem, TimingStats, TIMING_WINDOW};
+pub use validation::{Validation, ValidationCheck};
+pub use wrap::WorldWrap;
 
 #[derive(Default)]
 /// シーン内には複数のエンティティが存在する。
 pub struct Scene {
     pub(crate) world: hecs::World,
-    systems: Vec<Box<dyn System>>,
+    systems: Vec<RegisteredSystem>,
+    active_camera: Option<EntityIndex>,
+    #[cfg(feature = "backend-wgpu")]
+    render_graph: RenderGraph,
+    orphan_policy: OrphanPolicy,
+    frame_stats: FrameStats,
+    frame_arena: FrameArena,
+    validation: Validation,
+    /// [`Self::setup`] か [`Self::setup_headless`] を呼んだか
+    is_set_up: bool,
+    /// [`Self::merge`] で [`EntityIndex`] を書き換えるコンポーネント
+    entity_mappers: Vec<(TypeId, merge::EntityMapper)>,
 }
 
 impl Scene {
@@ -29,6 +117,268 @@ impl Scene {
         EntityIndex(entity)
     }
 
+    /// [`SpriteComponent::builder`] の設定でスプライトのエンティティを作る
+    ///
+    /// [`SpriteBuilder::layer`] を指定していれば [`RenderLayerComponent`] も付ける。
+    pub fn new_sprite(
+        &mut self,
+        transform: TransformComponent,
+        sprite: SpriteBuilder,
+    ) -> EntityIndex {
+        let layer = sprite.layer_component();
+        let entity = self.world.spawn((transform, sprite.build()));
+        if let Some(layer) = layer {
+            self.world.insert_one(entity, layer).unwrap_or_log();
+        }
+        EntityIndex(entity)
+    }
+
+    /// カメラのエンティティを作る
+    pub fn new_camera(
+        &mut self,
+        transform: TransformComponent,
+        camera: CameraComponent,
+    ) -> EntityIndex {
+        let entity = self.world.spawn((transform, camera));
+        EntityIndex(entity)
+    }
+
+    /// 文字列を描くエンティティを作る
+    ///
+    /// 描画するにはリソース [`Fonts`](crate::text::Fonts) が必要。
+    pub fn new_text(&mut self, transform: TransformComponent, text: TextComponent) -> EntityIndex {
+        let entity = self.world.spawn((transform, text));
+        EntityIndex(entity)
+    }
+
+    /// タイルマップのエンティティを作る
+    pub fn new_tilemap(
+        &mut self,
+        transform: TransformComponent,
+        tilemap: TilemapComponent,
+    ) -> EntityIndex {
+        let entity = self.world.spawn((transform, tilemap));
+        EntityIndex(entity)
+    }
+
+    /// パーティクルのエミッターのエンティティを作る
+    ///
+    /// パーティクルを動かすには [`ParticleSystem`](crate::particle::ParticleSystem) を追加する。
+    pub fn new_particle_emitter(
+        &mut self,
+        transform: TransformComponent,
+        emitter: ParticleEmitterComponent,
+    ) -> EntityIndex {
+        let entity = self.world.spawn((transform, emitter));
+        EntityIndex(entity)
+    }
+
+    /// デカールの層のエンティティを作る
+    ///
+    /// キャンバスを [`DecalLayerComponent::bounds`] に重ねて描く [`SpriteComponent`] も付ける。
+    pub fn new_decal_layer(&mut self, layer: DecalLayerComponent) -> EntityIndex {
+        let entity = self.world.spawn((layer.transform(), layer.sprite(), layer));
+        EntityIndex(entity)
+    }
+
+    /// デカールの層 `entity` のキャンバスを PNG にする
+    ///
+    /// シーンを保存するときに、ほかのデータと一緒に書き出す。[`DecalLayerComponent::save_png`] を参照。
+    #[cfg(feature = "backend-wgpu")]
+    pub fn save_decal_layer(
+        &mut self,
+        entity: EntityIndex,
+        resource: &WgpuResource<'_>,
+    ) -> anyhow::Result<Vec<u8>> {
+        self.world
+            .get::<&mut DecalLayerComponent>(entity.0)?
+            .save_png(resource)
+    }
+
+    /// [`Self::save_decal_layer`] で保存した内容をデカールの層 `entity` のキャンバスに戻す
+    #[cfg(feature = "backend-wgpu")]
+    pub fn load_decal_layer(
+        &mut self,
+        entity: EntityIndex,
+        resource: &mut WgpuResource<'_>,
+        bytes: &[u8],
+    ) -> anyhow::Result<()> {
+        self.world
+            .get::<&mut DecalLayerComponent>(entity.0)?
+            .load_png(resource, bytes)
+    }
+
+    /// [`Self::render`] で使うカメラを設定する
+    ///
+    /// 設定しなければ、ウィンドウのピクセル座標をそのまま使う。
+    pub fn set_active_camera(&mut self, entity: EntityIndex) {
+        self.active_camera = Some(entity);
+    }
+
+    pub const fn active_camera(&self) -> Option<EntityIndex> {
+        self.active_camera
+    }
+
+    /// 最後に描画したフレームの統計
+    pub const fn frame_stats(&self) -> &FrameStats {
+        &self.frame_stats
+    }
+
+    /// フレームの中だけで使う一時的なデータの置き場
+    pub const fn frame_arena(&self) -> &FrameArena {
+        &self.frame_arena
+    }
+
+    pub fn frame_arena_mut(&mut self) -> &mut FrameArena {
+        &mut self.frame_arena
+    }
+
+    /// フレームを終える
+    ///
+    /// [`Self::frame_arena`] の集計を締めて [`Self::frame_stats`] に記録し、
+    /// リソース [`SystemTimings`] の描画の段階の時間を締める。
+    /// 描画するときは [`WgpuResource::render_to_view`] が描画の後に、[`crate::headless::HeadlessRunner`] では更新の後に呼ぶ。
+    pub fn end_frame(&mut self) {
+        if let Some(mut timings) = self.resource_mut::<SystemTimings>() {
+            timings.end_frame();
+        }
+        self.frame_arena.end_frame();
+        self.frame_stats.set_arena(
+            self.frame_arena.high_water_bytes(),
+            self.frame_arena.grown_bytes(),
+        );
+    }
+
+    /// 固定の間隔の更新で追いつけずに捨てた時間を [`FrameStats`] に記録する
+    pub(crate) fn record_dropped_time(&mut self, dropped: Duration) {
+        self.frame_stats.set_dropped_time(dropped);
+    }
+
+    /// 描画に使うレンダーパスの並び
+    #[cfg(feature = "backend-wgpu")]
+    pub const fn render_graph(&self) -> &RenderGraph {
+        &self.render_graph
+    }
+
+    #[cfg(feature = "backend-wgpu")]
+    pub fn set_render_graph(&mut self, render_graph: RenderGraph) {
+        self.render_graph = render_graph;
+    }
+
+    /// リソースを追加する。同じ型のリソースがあれば置き換える
+    ///
+    /// システムからは [`resource()`] や [`resource_mut()`] で読み書きできる。
+    pub fn insert_resource<R: hecs::Component>(&mut self, resource: R) {
+        insert_resource(&mut self.world, resource);
+    }
+
+    pub fn remove_resource<R: hecs::Component>(&mut self) -> Option<R> {
+        remove_resource(&mut self.world)
+    }
+
+    pub fn resource<R: hecs::Component>(&self) -> Option<hecs::Ref<'_, R>> {
+        resource(&self.world)
+    }
+
+    pub fn resource_mut<R: hecs::Component>(&mut self) -> Option<hecs::RefMut<'_, R>> {
+        resource_mut(&self.world)
+    }
+
+    /// 設定を反映する
+    ///
+    /// 音量はすぐに反映し、ウィンドウと表示の設定はこのフレームの描画の前にエンジンが反映する。
+    /// 設定は [`crate::settings::Settings`] のリソースとして読める。
+    pub fn apply_settings(&mut self, settings: &crate::settings::Settings) {
+        crate::settings::apply_settings(&mut self.world, settings);
+    }
+
+    /// [`Self::despawn`] で子を持つエンティティを削除したときの扱いを設定する
+    pub fn set_orphan_policy(&mut self, policy: OrphanPolicy) {
+        self.orphan_policy = policy;
+    }
+
+    pub const fn orphan_policy(&self) -> OrphanPolicy {
+        self.orphan_policy
+    }
+
+    /// `child` を `parent` の子にする。ワールドでの位置は変わらない
+    ///
+    /// 以後、子の位置は [`LocalTransformComponent`] で親から見た位置として動かす。
+    pub fn set_parent(
+        &mut self,
+        child: EntityIndex,
+        parent: EntityIndex,
+    ) -> Result<(), HierarchyError> {
+        set_parent(&mut self.world, child, parent)
+    }
+
+    /// `child` を親から外してルートにする。ワールドでの位置は変わらない
+    pub fn remove_parent(&mut self, child: EntityIndex) -> Result<(), HierarchyError> {
+        remove_parent(&mut self.world, child)
+    }
+
+    pub fn parent(&self, entity: EntityIndex) -> Option<EntityIndex> {
+        parent(&self.world, entity)
+    }
+
+    pub fn children(&self, entity: EntityIndex) -> Vec<EntityIndex> {
+        children(&self.world, entity)
+    }
+
+    /// ワールド座標の点に重なるスプライトを、手前に描かれるものから順に返す
+    ///
+    /// クリックした位置は [`CameraComponent::screen_to_world`] でワールド座標に直して渡す。
+    /// `layers` が `Some` ならその層だけを調べる。詳しくは [`entities_at_point`]。
+    pub fn entities_at_point(
+        &self,
+        world_pos: Point2<f32>,
+        layers: Option<&[i32]>,
+    ) -> Vec<EntityIndex> {
+        entities_at_point(&self.world, &world_pos, layers, None)
+    }
+
+    /// [`Self::entities_at_point`] と同じだが、テクスチャの透明な部分は当たりにしない
+    pub fn entities_at_point_with_alpha(
+        &self,
+        world_pos: Point2<f32>,
+        layers: Option<&[i32]>,
+        alpha: AlphaTest<'_>,
+    ) -> Vec<EntityIndex> {
+        entities_at_point(&self.world, &world_pos, layers, Some(alpha))
+    }
+
+    /// UI のピクセル座標の点に重なる [`ScreenSpaceComponent`] のスプライトを、手前から順に返す
+    ///
+    /// 詳しくは [`screen_entities_at_point`]。
+    pub fn screen_entities_at_point(
+        &self,
+        ui_pos: Point2<f32>,
+        layers: Option<&[i32]>,
+    ) -> Vec<EntityIndex> {
+        screen_entities_at_point(&self.world, &ui_pos, layers, None)
+    }
+
+    /// エンティティを削除する
+    ///
+    /// 子があれば [`Self::set_orphan_policy`] で設定した [`OrphanPolicy`] に従う。
+    pub fn despawn(&mut self, entity: EntityIndex) -> Result<(), HierarchyError> {
+        despawn(&mut self.world, entity, self.orphan_policy)
+    }
+
+    /// 使い方の誤りを調べる設定
+    pub const fn validation(&self) -> &Validation {
+        &self.validation
+    }
+
+    pub fn validation_mut(&mut self) -> &mut Validation {
+        &mut self.validation
+    }
+
+    /// エンティティとその子孫をすべて削除する
+    pub fn despawn_recursive(&mut self, entity: EntityIndex) -> Result<(), HierarchyError> {
+        despawn_recursive(&mut self.world, entity)
+    }
+
     pub fn attach_component<C: hecs::Component + 'static>(
         &mut self,
         entity: EntityIndex,
@@ -37,36 +387,157 @@ impl Scene {
         self.world.insert_one(entity.0, component).unwrap_or_log();
     }
 
+    /// システムを登録する
+    ///
+    /// [`System::dependencies`] のシステムは後から登録してもよい。実行順は [`Self::setup`] で決まる。
     pub fn register_system<S: System + 'static>(&mut self, system: S) {
-        self.systems.push(Box::new(system));
+        if self.is_set_up {
+            self.validation.system_registered_after_setup(system.name());
+        }
+        self.systems.push(RegisteredSystem {
+            type_id: TypeId::of::<S>(),
+            name: system.name(),
+            system: Box::new(system),
+        });
     }
 
-    pub fn setup(&mut self, resource: &WgpuResource<'_>) {
+    /// シーンを初期化する
+    ///
+    /// システムは [`System::dependencies`] に従って並べ替えられる。
+    #[cfg(feature = "backend-wgpu")]
+    pub fn setup(&mut self, resource: &WgpuResource<'_>) -> Result<(), CycleError> {
+        self.sort_systems()?;
+
         for (_, sprite) in self.world.query_mut::<&mut SpriteComponent>() {
             sprite.setup(resource)
         }
 
         for system in &mut self.systems {
-            system.setup(resource);
+            system.system.setup(Some(resource));
         }
+        self.is_set_up = true;
+        Ok(())
     }
 
+    /// GPU を使わずにシーンを初期化する
+    ///
+    /// システムの [`System::setup`] には `None` が渡される。スプライトの準備はしない。
+    pub fn setup_headless(&mut self) -> Result<(), CycleError> {
+        self.sort_systems()?;
+        for system in &mut self.systems {
+            system.system.setup(None);
+        }
+        self.is_set_up = true;
+        Ok(())
+    }
+
+    /// すべてのシステムを 1 回ずつ実行する
+    ///
+    /// システムに渡す [`Frame::delta_time`] にはリソース [`TimeScale`] が反映される。
+    /// システムより先に、リソース [`Input`] に `frame` の入力を反映し、リソース [`SceneClock`] を進める。
+    /// システムごとの実行時間はリソース [`SystemTimings`] に記録する。
+    /// システムの後に、リソース [`TaskQueue`] のタスクを予算の分だけ進める。いずれも無ければ作る。
+    /// 最後に、リソース [`WorldWrap`] があれば親の無いエンティティの x 座標を収めてから、
+    /// 親子関係のある [`TransformComponent`] を [`propagate_transforms`] で更新する。
+    /// [`Validation`] が有効なら、その後でエンティティの誤りを調べる。
+    /// リソース [`Determinism`] があれば、システムの前とすべてのシステムの後に [`sync_spawn_order`] を呼び、
+    /// それぞれのシステムの後には作られたエンティティに番号を付ける。
+    #[cfg(feature = "backend-wgpu")]
     pub fn update(&mut self, frame: &Frame<'_>, resource: &WgpuResource<'_>) {
+        self.update_with(frame, Some(resource));
+    }
+
+    /// GPU を使わずにすべてのシステムを 1 回ずつ実行する
+    ///
+    /// システムの [`System::update`] には `None` が渡される。それ以外は [`Self::update`] と同じ。
+    pub fn update_headless(&mut self, frame: &Frame<'_>) {
+        self.update_with(frame, None);
+    }
+
+    fn update_with(&mut self, frame: &Frame<'_>, resource: Option<&RenderResource<'_>>) {
+        let time_scale = self.resource::<TimeScale>().map(|t| *t).unwrap_or_default();
+        let frame = Frame {
+            delta_time: time_scale.apply(frame.delta_time),
+            ..*frame
+        };
+        if self.resource::<Input>().is_none() {
+            self.insert_resource(Input::default());
+        }
+        if let Some(mut input) = self.resource_mut::<Input>() {
+            input.begin_frame(&frame);
+        }
+        if self.resource::<SceneClock>().is_none() {
+            self.insert_resource(SceneClock::default());
+        }
+        if let Some(mut clock) = self.resource_mut::<SceneClock>() {
+            clock.elapsed += frame.delta_time;
+        }
+        if self.resource::<SystemTimings>().is_none() {
+            self.insert_resource(SystemTimings::default());
+        }
+        sync_spawn_order(&mut self.world);
         for system in &mut self.systems {
-            system.update(frame, &mut self.world, resource);
+            let start = Instant::now();
+            system.system.update(&frame, &mut self.world, resource);
+            let elapsed = start.elapsed();
+            if let Some(mut timings) = resource_mut::<SystemTimings>(&self.world) {
+                timings.record_system(system.name, elapsed);
+            }
+            determinism::number_spawned_entities(&mut self.world);
+        }
+        sync_spawn_order(&mut self.world);
+        let mut tasks = self.remove_resource::<TaskQueue>().unwrap_or_default();
+        tasks.run(&mut self.world);
+        self.insert_resource(tasks);
+        wrap::wrap_transforms(&mut self.world);
+        hierarchy::propagate_transforms_in(&mut self.world, &mut self.frame_arena);
+        if self.validation.is_enabled() {
+            #[cfg(feature = "backend-wgpu")]
+            let textures = resource.map(|resource| &resource.texture_registry);
+            #[cfg(not(feature = "backend-wgpu"))]
+            let textures = None;
+            self.validation.check_world(&self.world, textures);
         }
     }
 
-    pub fn render(&mut self, rp: &mut wgpu::RenderPass<'_>, resource: &WgpuResource<'_>) {
-        for (_, (transform, sprite)) in self
-            .world
-            .query_mut::<(&TransformComponent, &mut SpriteComponent)>()
-        {
-            sprite.render(rp, resource, transform);
+    /// システムを [`System::dependencies`] に従って並べ替える。登録されていない依存先は警告して無視する
+    fn sort_systems(&mut self) -> Result<(), CycleError> {
+        for system in &self.systems {
+            for dependency in system.system.dependencies() {
+                if !self.systems.iter().any(|s| s.type_id == *dependency) {
+                    tracing::warn!(
+                        system = system.name,
+                        ?dependency,
+                        "dependency of the system is not registered"
+                    );
+                }
+            }
         }
+        let nodes: Vec<_> = self
+            .systems
+            .iter()
+            .map(|s| (s.type_id, s.system.dependencies()))
+            .collect();
+        let order = system::topological_order(&nodes).map_err(|cycle| CycleError {
+            systems: cycle.iter().map(|&i| self.systems[i].name).collect(),
+        })?;
+
+        let mut systems: Vec<_> = self.systems.drain(..).map(Some).collect();
+        self.systems = order
+            .into_iter()
+            .filter_map(|i| systems[i].take())
+            .collect();
+        Ok(())
     }
 }
 
+/// 登録されたシステムと、その型の情報
+struct RegisteredSystem {
+    type_id: TypeId,
+    name: &'static str,
+    system: Box<dyn System>,
+}
+
 impl std::fmt::Debug for Scene {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Scene")
@@ -0,0 +1,189 @@
+//! フレームの中だけで使う一時的なデータの置き場
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+};
+
+/// 型ごとに、使い終わったバッファを容量を残したまま取っておく
+struct Pool<T> {
+    buffers: Vec<Vec<T>>,
+}
+
+trait AnyPool: Send + Sync {
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    /// 取っておいたバッファの容量の合計のバイト数
+    fn reserved_bytes(&self) -> usize;
+}
+
+impl<T: Send + Sync + 'static> AnyPool for Pool<T> {
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn reserved_bytes(&self) -> usize {
+        self.buffers
+            .iter()
+            .map(|buffer| buffer.capacity() * size_of::<T>())
+            .sum()
+    }
+}
+
+struct Entry {
+    pool: Box<dyn AnyPool>,
+    type_name: &'static str,
+    /// 前のフレームの終わりの [`AnyPool::reserved_bytes`]
+    reserved_bytes: usize,
+}
+
+#[derive(Default)]
+/// フレームの中だけで使う [`Vec`] の置き場
+///
+/// [`Self::take`] で借りたバッファを [`Self::recycle`] で返すと、容量を残したまま次のフレームで使い回す。
+/// 毎フレーム同じくらいの量を使うなら、数フレームでメモリの確保が起きなくなる。
+/// 同じ型のバッファは最後に返したものから貸すので、借りたときと逆の順に返すと毎フレーム同じものが当たる。
+/// [`super::Scene::end_frame`] で 1 フレームの集計を締める。
+///
+/// 描画の並び、親子の位置の伝え、`DebugDraw` の頂点がここから借りる。
+/// [`super::Events`] は借りない。システムは `world` だけを受け取ってイベントを消すので、
+/// シーンが持つこの置き場には手が届かない。イベントの [`Vec`] も容量は残すが、ここの集計には入らない。
+///
+/// [`Self::set_strict`] を有効にすると、フレームの中でバッファが伸びたときに警告を出す。
+pub struct FrameArena {
+    pools: HashMap<TypeId, Entry>,
+    strict: bool,
+    high_water_bytes: usize,
+    grown_bytes: usize,
+}
+
+impl FrameArena {
+    /// 空のバッファを借りる。前のフレームで返されたものがあれば、その容量を引き継ぐ
+    pub fn take<T: Send + Sync + 'static>(&mut self) -> Vec<T> {
+        self.pool::<T>().buffers.pop().unwrap_or_default()
+    }
+
+    /// 借りたバッファを返す。中身は消す
+    pub fn recycle<T: Send + Sync + 'static>(&mut self, mut buffer: Vec<T>) {
+        buffer.clear();
+        // 容量の無いバッファは取っておいても役に立たない
+        if buffer.capacity() > 0 {
+            self.pool::<T>().buffers.push(buffer);
+        }
+    }
+
+    /// 伸びたバッファを警告するかどうか
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    pub const fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// これまでにフレームで使ったバッファの容量の最大値のバイト数
+    pub const fn high_water_bytes(&self) -> usize {
+        self.high_water_bytes
+    }
+
+    /// 最後に締めたフレームで新しく確保したバイト数。安定すれば 0 になる
+    pub const fn grown_bytes(&self) -> usize {
+        self.grown_bytes
+    }
+
+    /// フレームの集計を締める
+    ///
+    /// 返されずに残ったバッファは次のフレームで作り直すことになる。
+    pub(crate) fn end_frame(&mut self) {
+        let mut total = 0;
+        let mut grown = 0;
+        for entry in self.pools.values_mut() {
+            let reserved = entry.pool.reserved_bytes();
+            if reserved > entry.reserved_bytes {
+                let growth = reserved - entry.reserved_bytes;
+                if self.strict {
+                    tracing::warn!(
+                        r#type = entry.type_name,
+                        from = entry.reserved_bytes,
+                        to = reserved,
+                        "frame arena grew"
+                    );
+                }
+                grown += growth;
+            }
+            entry.reserved_bytes = reserved;
+            total += reserved;
+        }
+        self.grown_bytes = grown;
+        self.high_water_bytes = self.high_water_bytes.max(total);
+    }
+
+    fn pool<T: Send + Sync + 'static>(&mut self) -> &mut Pool<T> {
+        self.pools
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Entry {
+                pool: Box::new(Pool::<T> {
+                    buffers: Vec::new(),
+                }),
+                type_name: std::any::type_name::<T>(),
+                reserved_bytes: 0,
+            })
+            .pool
+            .as_any_mut()
+            .downcast_mut()
+            .expect("pool is keyed by its element type")
+    }
+}
+
+impl std::fmt::Debug for FrameArena {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FrameArena")
+            .field("#pools", &self.pools.len())
+            .field("strict", &self.strict)
+            .field("high_water_bytes", &self.high_water_bytes)
+            .field("grown_bytes", &self.grown_bytes)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuses_capacity_across_frames() {
+        let mut arena = FrameArena::default();
+        let mut buffer = arena.take::<u32>();
+        buffer.extend(0..100);
+        let capacity = buffer.capacity();
+        arena.recycle(buffer);
+        arena.end_frame();
+        assert_eq!(arena.grown_bytes(), capacity * 4);
+
+        for _ in 0..3 {
+            let mut buffer = arena.take::<u32>();
+            assert!(buffer.is_empty());
+            assert_eq!(buffer.capacity(), capacity);
+            buffer.extend(0..100);
+            arena.recycle(buffer);
+            arena.end_frame();
+            assert_eq!(arena.grown_bytes(), 0);
+        }
+        assert_eq!(arena.high_water_bytes(), capacity * 4);
+    }
+
+    #[test]
+    fn counts_growth_per_type() {
+        let mut arena = FrameArena::default();
+        arena.recycle(Vec::<u8>::with_capacity(16));
+        arena.recycle(Vec::<u64>::with_capacity(2));
+        arena.end_frame();
+        assert_eq!(arena.grown_bytes(), 32);
+
+        let mut bytes = arena.take::<u8>();
+        bytes.extend([0; 64]);
+        let capacity = bytes.capacity();
+        arena.recycle(bytes);
+        arena.end_frame();
+        assert_eq!(arena.grown_bytes(), capacity - 16);
+        assert_eq!(arena.high_water_bytes(), capacity + 16);
+    }
+}
@@ -1,2 +1,11 @@
+pub(super) mod camera;
+pub(super) mod decal;
+pub(super) mod floating_text;
+pub(super) mod mask;
+pub(super) mod parallax;
+pub(super) mod render_layer;
+pub(super) mod screen_space;
 pub(super) mod sprite;
+pub(super) mod text;
+pub(super) mod tilemap;
 pub(super) mod transform;
@@ -0,0 +1,882 @@
+use std::num::NonZeroU32;
+
+use nalgebra::{
+    Isometry3, Matrix4, Perspective3, Point2, Point3, Scale3, Translation3, Vector2, Vector3,
+    Vector4,
+};
+use reverie_util::math::Rect;
+#[cfg(feature = "backend-wgpu")]
+use wgpu::util::DeviceExt;
+
+#[cfg(feature = "backend-wgpu")]
+use crate::wgpu_wrapper::{upload_ring::UploadRing, WgpuResource};
+use crate::{
+    input::Input,
+    scene::{ParallaxLayerComponent, TransformComponent},
+};
+
+/// ピクセル座標 (左上が原点、y 軸は下向き) から正規化デバイス座標への変換行列
+pub fn get_matrix_pixel_to_render_coordinate(
+    width: NonZeroU32,
+    height: NonZeroU32,
+) -> Matrix4<f32> {
+    let width = width.get() as f32;
+    let height = height.get() as f32;
+    Translation3::from([-1.0, 1.0, 0.0]).to_homogeneous()
+        * Scale3::new(2.0 / width, -2.0 / height, 1.0).to_homogeneous()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// カメラの投影方法
+pub enum Projection {
+    /// 1 ピクセルを 1 単位とする平行投影
+    ///
+    /// カメラの位置が画面の左上に対応し、y 軸は下向き。
+    Orthographic {
+        /// 拡大率。2 なら 2 倍に拡大して表示する
+        zoom: f32,
+    },
+    /// 透視投影
+    Perspective {
+        /// 縦方向の視野角 (ラジアン)
+        fovy: f32,
+        near: f32,
+        far: f32,
+    },
+}
+
+impl Default for Projection {
+    fn default() -> Self {
+        Self::Orthographic { zoom: 1.0 }
+    }
+}
+
+#[derive(Debug)]
+/// シーンを描画する視点を表すコンポーネント
+///
+/// 視点の位置と向きは同じエンティティの [`TransformComponent`] で決まる。
+pub struct CameraComponent {
+    pub projection: Projection,
+    /// 描画先の大きさ。`None` ならウィンドウの大きさを使う
+    pub target_size: Option<(NonZeroU32, NonZeroU32)>,
+    /// 平行投影の拡大率の最小値
+    pub min_zoom: f32,
+    /// 平行投影の拡大率の最大値
+    pub max_zoom: f32,
+    /// 平行投影で回転していないとき、視点の位置を描画先のピクセルの境目に合わせる
+    ///
+    /// 拡大率が半端でもスプライトがピクセルの途中に来なくなり、動かしたときのちらつきが減る。
+    pub pixel_snap: bool,
+    /// 描画先のうちカメラが描く範囲。描画先全体を 0.0 から 1.0 とする
+    ///
+    /// 画面分割に使う。`None` なら描画先全体に描く。
+    pub viewport: Option<Rect>,
+    /// 仮想的な解像度
+    ///
+    /// 投影はこの大きさの画面に描くものとして計算し、[`Self::viewport`] の範囲に収まる最大の整数倍に拡大して
+    /// 中央に置く。余った部分は描かない (レターボックス)。
+    pub virtual_resolution: Option<(NonZeroU32, NonZeroU32)>,
+    /// このカメラで描かない [`super::render_layer::RenderLayerComponent`] の層
+    ///
+    /// ミニマップのカメラにダメージの数値や UI を写さないのに使う。
+    pub hidden_layers: Vec<i32>,
+    #[cfg(feature = "backend-wgpu")]
+    binding: Option<UploadRing<CameraBinding>>,
+}
+
+impl Default for CameraComponent {
+    fn default() -> Self {
+        Self {
+            projection: Projection::default(),
+            target_size: None,
+            min_zoom: 0.0,
+            max_zoom: f32::INFINITY,
+            pixel_snap: false,
+            viewport: None,
+            virtual_resolution: None,
+            hidden_layers: Vec::new(),
+            #[cfg(feature = "backend-wgpu")]
+            binding: None,
+        }
+    }
+}
+
+#[cfg(feature = "backend-wgpu")]
+#[derive(Debug)]
+struct CameraBinding {
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl CameraComponent {
+    pub fn new(projection: Projection) -> Self {
+        Self {
+            projection,
+            ..Default::default()
+        }
+    }
+
+    /// 拡大率の範囲を決める
+    pub fn with_zoom_limits(mut self, min: f32, max: f32) -> Self {
+        self.min_zoom = min;
+        self.max_zoom = max;
+        self.set_zoom(self.zoom().unwrap_or(1.0));
+        self
+    }
+
+    /// 視点の位置をピクセルの境目に合わせる
+    pub const fn with_pixel_snap(mut self) -> Self {
+        self.pixel_snap = true;
+        self
+    }
+
+    /// 描画先のうち`viewport`の範囲に描く
+    pub const fn with_viewport(mut self, viewport: Rect) -> Self {
+        self.viewport = Some(viewport);
+        self
+    }
+
+    /// `width`x`height`の画面として描き、整数倍に拡大して表示する
+    pub const fn with_virtual_resolution(mut self, width: NonZeroU32, height: NonZeroU32) -> Self {
+        self.virtual_resolution = Some((width, height));
+        self
+    }
+
+    /// `layers` の層を描かない
+    pub fn with_hidden_layers(mut self, layers: impl IntoIterator<Item = i32>) -> Self {
+        self.hidden_layers.extend(layers);
+        self
+    }
+
+    /// 描画先のうちカメラが描く範囲 (ピクセル)
+    ///
+    /// [`Self::viewport`] の範囲の中に、[`Self::virtual_resolution`] があればその整数倍の大きさで中央に置く。
+    /// 1 倍にも収まらないときは縦横比を保って縮める。
+    pub fn viewport_rect(&self, width: NonZeroU32, height: NonZeroU32) -> Rect {
+        let (w, h) = (width.get() as f32, height.get() as f32);
+        let area = self.viewport.map_or_else(
+            || Rect::new(Point2::origin(), Point2::new(w, h)),
+            |v| {
+                Rect::new(
+                    Point2::new(v.min.x * w, v.min.y * h),
+                    Point2::new(v.max.x * w, v.max.y * h),
+                )
+            },
+        );
+        let Some((vw, vh)) = self.virtual_resolution else {
+            return area;
+        };
+        let virtual_size = Vector2::new(vw.get() as f32, vh.get() as f32);
+        let fit = (area.width() / virtual_size.x).min(area.height() / virtual_size.y);
+        let scale = if fit >= 1.0 { fit.floor() } else { fit };
+        let size = virtual_size * scale;
+        // ピクセルの境目に揃える
+        let min = (area.center() - size / 2.0).map(f32::round);
+        Rect::from_min_size(min, size)
+    }
+
+    /// 投影に使う画面の大きさ
+    ///
+    /// [`Self::virtual_resolution`] があればそれ、無ければ [`Self::viewport_rect`] の大きさ。
+    pub fn view_size(&self, width: NonZeroU32, height: NonZeroU32) -> (NonZeroU32, NonZeroU32) {
+        if let Some(size) = self.virtual_resolution {
+            return size;
+        }
+        let rect = self.viewport_rect(width, height);
+        let pixels = |v: f32| NonZeroU32::new(v.round() as u32).unwrap_or(NonZeroU32::MIN);
+        (pixels(rect.width()), pixels(rect.height()))
+    }
+
+    /// [`Self::viewport`] か [`Self::virtual_resolution`] で、描画先の一部にだけ描くか
+    pub const fn has_viewport(&self) -> bool {
+        self.viewport.is_some() || self.virtual_resolution.is_some()
+    }
+
+    /// 描画先全体のピクセル座標をワールド座標に変換する
+    ///
+    /// [`Self::viewport_rect`] の外 (画面分割の別の領域やレターボックスの帯) なら `None`。
+    /// 透視投影では手前のクリップ面上の点を返す。
+    pub fn target_to_world(
+        &self,
+        transform: &TransformComponent,
+        width: NonZeroU32,
+        height: NonZeroU32,
+        pixel: &Point2<f32>,
+    ) -> Option<Point3<f32>> {
+        let rect = self.viewport_rect(width, height);
+        if !rect.contains(pixel) {
+            return None;
+        }
+        let (vw, vh) = self.view_size(width, height);
+        let local = Point2::new(
+            (pixel.x - rect.min.x) / rect.width() * vw.get() as f32,
+            (pixel.y - rect.min.y) / rect.height() * vh.get() as f32,
+        );
+        self.screen_to_world(transform, vw, vh, &local)
+    }
+
+    /// ワールド座標を描画先全体のピクセル座標に変換する。[`Self::target_to_world`] の逆
+    pub fn world_to_target(
+        &self,
+        transform: &TransformComponent,
+        width: NonZeroU32,
+        height: NonZeroU32,
+        world: &Point3<f32>,
+    ) -> Option<Point2<f32>> {
+        let rect = self.viewport_rect(width, height);
+        let (vw, vh) = self.view_size(width, height);
+        let local = self.world_to_screen(transform, vw, vh, world)?;
+        Some(Point2::new(
+            rect.min.x + local.x * rect.width() / vw.get() as f32,
+            rect.min.y + local.y * rect.height() / vh.get() as f32,
+        ))
+    }
+
+    /// [`Self::min_zoom`] と [`Self::max_zoom`] の範囲に収めた拡大率
+    fn clamp_zoom(&self, zoom: f32) -> f32 {
+        zoom.max(self.min_zoom).min(self.max_zoom)
+    }
+
+    /// 平行投影の拡大率。透視投影なら `None`
+    pub fn zoom(&self) -> Option<f32> {
+        match self.projection {
+            Projection::Orthographic { zoom } => Some(self.clamp_zoom(zoom)),
+            Projection::Perspective { .. } => None,
+        }
+    }
+
+    /// 平行投影の拡大率を範囲に収めて変える。透視投影なら何もしない
+    pub fn set_zoom(&mut self, zoom: f32) {
+        let zoom = self.clamp_zoom(zoom);
+        if let Projection::Orthographic { zoom: z } = &mut self.projection {
+            *z = zoom;
+        }
+    }
+
+    /// 描画先の `cursor` の下にあるワールドの点を動かさずに、拡大率を `factor` 倍にする
+    ///
+    /// マウスホイールでの拡大縮小に使う。`transform` はカメラの [`TransformComponent`] で、
+    /// 視点の位置をずらす。透視投影では何もせず `false` を返す。
+    pub fn zoom_around(
+        &mut self,
+        transform: &mut TransformComponent,
+        width: NonZeroU32,
+        height: NonZeroU32,
+        cursor: &Point2<f32>,
+        factor: f32,
+    ) -> bool {
+        let Some(zoom) = self.zoom() else {
+            return false;
+        };
+        // スナップすると拡大率が変わる前後で位置がずれるので、スナップせずに計算する
+        let snap = std::mem::replace(&mut self.pixel_snap, false);
+        let before = self.screen_to_world(transform, width, height, cursor);
+        self.set_zoom(zoom * factor);
+        let after = self.screen_to_world(transform, width, height, cursor);
+        self.pixel_snap = snap;
+        let (Some(before), Some(after)) = (before, after) else {
+            return false;
+        };
+        transform.translation.vector += before - after;
+        true
+    }
+
+    /// [`crate::input::Input::cursor_position`] を中心に拡大率を `factor` 倍にする
+    ///
+    /// マウスが UI に横取りされているか、カーソルの位置が分からなければ何もせず `false` を返す。
+    pub fn zoom_around_cursor(
+        &mut self,
+        transform: &mut TransformComponent,
+        width: NonZeroU32,
+        height: NonZeroU32,
+        input: &Input,
+        factor: f32,
+    ) -> bool {
+        input
+            .cursor_position()
+            .is_some_and(|cursor| self.zoom_around(transform, width, height, &cursor, factor))
+    }
+
+    /// 描画に使う視点の位置。[`Self::pixel_snap`] ならピクセルの境目に合わせる
+    fn view_translation(&self, transform: &TransformComponent, zoom: f32) -> Translation3<f32> {
+        let t = transform.translation;
+        if !self.pixel_snap || transform.rotation.angle() > f32::EPSILON {
+            return t;
+        }
+        // 1 ピクセルはワールドでは 1 / zoom
+        let snap = |v: f32| (v * zoom).round() / zoom;
+        Translation3::new(snap(t.x), snap(t.y), t.z)
+    }
+
+    /// ワールド座標から正規化デバイス座標への変換行列を計算する
+    ///
+    /// 拡大率は [`Self::min_zoom`] と [`Self::max_zoom`] の範囲に収める。
+    pub fn view_projection(
+        &self,
+        transform: &TransformComponent,
+        width: NonZeroU32,
+        height: NonZeroU32,
+    ) -> Matrix4<f32> {
+        match self.projection {
+            Projection::Orthographic { zoom } => {
+                let zoom = self.clamp_zoom(zoom);
+                let view = Isometry3::from_parts(
+                    self.view_translation(transform, zoom),
+                    transform.rotation,
+                )
+                .inverse()
+                .to_homogeneous();
+                get_matrix_pixel_to_render_coordinate(width, height)
+                    * Scale3::new(zoom, zoom, 1.0).to_homogeneous()
+                    * view
+            }
+            Projection::Perspective { fovy, near, far } => {
+                let view = transform.to_isometry3().inverse().to_homogeneous();
+                let aspect = width.get() as f32 / height.get() as f32;
+                // nalgebra の深度は -1 から 1 なので、wgpu の 0 から 1 に直す
+                let depth_to_wgpu = Matrix4::new(
+                    1.0, 0.0, 0.0, 0.0, //
+                    0.0, 1.0, 0.0, 0.0, //
+                    0.0, 0.0, 0.5, 0.5, //
+                    0.0, 0.0, 0.0, 1.0, //
+                );
+                depth_to_wgpu * Perspective3::new(aspect, fovy, near, far).to_homogeneous() * view
+            }
+        }
+    }
+
+    /// カメラの視錐台を計算する
+    pub fn frustum(
+        &self,
+        transform: &TransformComponent,
+        width: NonZeroU32,
+        height: NonZeroU32,
+    ) -> Frustum {
+        Frustum::from_matrix(&self.view_projection(transform, width, height))
+    }
+
+    /// 描画先のピクセル座標をワールド座標に変換する
+    ///
+    /// 透視投影では手前のクリップ面上の点を返す。
+    pub fn screen_to_world(
+        &self,
+        transform: &TransformComponent,
+        width: NonZeroU32,
+        height: NonZeroU32,
+        screen: &Point2<f32>,
+    ) -> Option<Point3<f32>> {
+        let inverse = self
+            .view_projection(transform, width, height)
+            .try_inverse()?;
+        let ndc = Point3::new(
+            2.0 * screen.x / width.get() as f32 - 1.0,
+            1.0 - 2.0 * screen.y / height.get() as f32,
+            0.0,
+        );
+        Point3::from_homogeneous(inverse * ndc.to_homogeneous())
+    }
+
+    /// 描画先のピクセル座標を、[`ParallaxLayerComponent`] の層の座標に変換する
+    ///
+    /// 層のスプライトはカメラの位置に合わせてずらして描くので、[`Self::screen_to_world`] の点から
+    /// そのずれを引く。[`crate::scene::entities_at_point`] に渡すとその層の当たりを調べられる。
+    pub fn screen_to_layer(
+        &self,
+        transform: &TransformComponent,
+        width: NonZeroU32,
+        height: NonZeroU32,
+        screen: &Point2<f32>,
+        layer: &ParallaxLayerComponent,
+    ) -> Option<Point3<f32>> {
+        let world = self.screen_to_world(transform, width, height, screen)?;
+        Some(layer.world_to_layer(&transform.translation.vector.xy().into(), &world))
+    }
+
+    /// ワールド座標を描画先のピクセル座標に変換する
+    pub fn world_to_screen(
+        &self,
+        transform: &TransformComponent,
+        width: NonZeroU32,
+        height: NonZeroU32,
+        world: &Point3<f32>,
+    ) -> Option<Point2<f32>> {
+        let matrix = self.view_projection(transform, width, height);
+        let ndc = Point3::from_homogeneous(matrix * world.to_homogeneous())?;
+        Some(Point2::new(
+            (ndc.x + 1.0) * width.get() as f32 / 2.0,
+            (1.0 - ndc.y) * height.get() as f32 / 2.0,
+        ))
+    }
+
+    /// 描画先の大きさを取得する
+    #[cfg(feature = "backend-wgpu")]
+    pub(crate) fn target_size(&self, resource: &WgpuResource<'_>) -> (NonZeroU32, NonZeroU32) {
+        self.target_size.unwrap_or_else(|| {
+            (
+                NonZeroU32::new(resource.surface_config.width).unwrap_or(NonZeroU32::MIN),
+                NonZeroU32::new(resource.surface_config.height).unwrap_or(NonZeroU32::MIN),
+            )
+        })
+    }
+
+    /// 最後に [`Self::prepare`] で作ったバインドグループ
+    #[cfg(feature = "backend-wgpu")]
+    pub(crate) fn bind_group(&self) -> Option<&wgpu::BindGroup> {
+        self.binding
+            .as_ref()
+            .map(|binding| &binding.current().bind_group)
+    }
+
+    /// 変換行列を GPU に送り、描画に使うバインドグループを返す
+    #[cfg(feature = "backend-wgpu")]
+    pub(crate) fn prepare(
+        &mut self,
+        resource: &WgpuResource<'_>,
+        matrix: &Matrix4<f32>,
+    ) -> &wgpu::BindGroup {
+        let ring = self.binding.get_or_insert_with(|| {
+            UploadRing::new(&resource.frames, |_| CameraBinding::new(resource, matrix))
+        });
+        let binding = ring.advance(&resource.frames);
+        resource
+            .queue
+            .write_buffer(&binding.buffer, 0, bytemuck::cast_slice(matrix.as_slice()));
+        &binding.bind_group
+    }
+}
+
+#[cfg(feature = "backend-wgpu")]
+impl CameraBinding {
+    fn new(resource: &WgpuResource<'_>, matrix: &Matrix4<f32>) -> Self {
+        let buffer = resource
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Camera Matrix Buffer"),
+                contents: bytemuck::cast_slice(matrix.as_slice()),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+        let bind_group = resource
+            .device
+            .create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Camera Bind Group"),
+                layout: &resource.uniform_bind_group_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                }],
+            });
+        Self { buffer, bind_group }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// 視錐台。カメラに写らない物体を描画対象から外すのに使う
+pub struct Frustum {
+    /// 左、右、下、上、手前、奥の順の平面。`ax + by + cz + d >= 0` が内側
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// ワールド座標から正規化デバイス座標への変換行列から視錐台を作る
+    ///
+    /// 深度の範囲は wgpu と同じ 0 から 1 とする。
+    pub fn from_matrix(m: &Matrix4<f32>) -> Self {
+        let row = |i: usize| m.row(i).transpose();
+        let (r0, r1, r2, r3) = (row(0), row(1), row(2), row(3));
+        let planes = [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r2, r3 - r2].map(|p| {
+            let length = p.xyz().norm();
+            if length > 0.0 {
+                p / length
+            } else {
+                p
+            }
+        });
+        Self { planes }
+    }
+
+    /// 球が視錐台と重なるかどうか
+    pub fn intersects_sphere(&self, center: &Point3<f32>, radius: f32) -> bool {
+        self.planes
+            .iter()
+            .all(|p| p.xyz().dot(&center.coords) + p.w >= -radius)
+    }
+
+    /// 座標軸に沿った箱が視錐台と重なるかどうか
+    ///
+    /// 各平面について、箱の頂点のうち最も内側にあるものを調べる。
+    /// 視錐台の角の近くでは重ならない箱を重なると判定することがある。
+    pub fn intersects_aabb(&self, min: &Point3<f32>, max: &Point3<f32>) -> bool {
+        self.planes.iter().all(|p| {
+            let inner = Point3::new(
+                if p.x >= 0.0 { max.x } else { min.x },
+                if p.y >= 0.0 { max.y } else { min.y },
+                if p.z >= 0.0 { max.z } else { min.z },
+            );
+            p.xyz().dot(&inner.coords) + p.w >= 0.0
+        })
+    }
+
+    /// `point` のカメラからの遠さ。手前の平面からの距離で、大きいほど遠い
+    pub fn depth(&self, point: &Point3<f32>) -> f32 {
+        let near = self.planes[4];
+        near.xyz().dot(&point.coords) + near.w
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// ワールド座標と描画先のピクセルの対応
+///
+/// [`super::sprite::SpriteComponent::pixel_snap`] と [`super::text::TextComponent::pixel_snap`] で、
+/// 位置を描画先の物理ピクセルに合わせるのに使う。
+#[cfg_attr(not(feature = "backend-wgpu"), allow(dead_code))]
+pub struct PixelGrid {
+    /// ワールド座標から正規化デバイス座標への変換行列
+    matrix: Matrix4<f32>,
+    inverse: Matrix4<f32>,
+    /// 正規化デバイス座標の -1.0 から 1.0 が写る、描画先の物理ピクセルの範囲
+    rect: Rect,
+}
+
+#[cfg_attr(not(feature = "backend-wgpu"), allow(dead_code))]
+impl PixelGrid {
+    pub fn new(matrix: Matrix4<f32>, rect: Rect) -> Self {
+        let inverse = matrix.try_inverse().unwrap_or_else(Matrix4::identity);
+        Self {
+            matrix,
+            inverse,
+            rect,
+        }
+    }
+
+    /// ワールド座標から正規化デバイス座標への変換行列
+    pub const fn matrix(&self) -> &Matrix4<f32> {
+        &self.matrix
+    }
+
+    /// 描画先に写る範囲の x 座標の最小と最大。奥行きは正規化デバイス座標の 0 の面で調べる
+    pub fn visible_x(&self) -> (f32, f32) {
+        [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)]
+            .map(|(x, y)| self.inverse.transform_point(&Point3::new(x, y, 0.0)).x)
+            .into_iter()
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), x| {
+                (min.min(x), max.max(x))
+            })
+    }
+
+    /// ワールド座標の `point` を、描画先で最も近いピクセルの境目に動かすずれ
+    ///
+    /// 描画先での奥行きは変えない。
+    pub fn snap_offset(&self, point: &Point3<f32>) -> Vector3<f32> {
+        let ndc = self.matrix.transform_point(point);
+        let (min, size) = (self.rect.min, self.rect.size());
+        let pixel = Point2::new(
+            ((ndc.x + 1.0) * 0.5).mul_add(size.x, min.x),
+            ((1.0 - ndc.y) * 0.5).mul_add(size.y, min.y),
+        )
+        .map(f32::round);
+        let snapped = Point3::new(
+            ((pixel.x - min.x) / size.x).mul_add(2.0, -1.0),
+            ((pixel.y - min.y) / size.y).mul_add(-2.0, 1.0),
+            ndc.z,
+        );
+        self.inverse.transform_point(&snapped) - point
+    }
+
+    /// `transform` の位置だけをピクセルに合わせる。回転と拡大縮小は変えない
+    pub fn snap(&self, transform: &TransformComponent) -> TransformComponent {
+        let mut snapped = transform.clone();
+        snapped.translation.vector += self.snap_offset(&Point3::from(transform.translation.vector));
+        snapped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Translation3, UnitQuaternion};
+    use winit::dpi::{LogicalPosition, PhysicalPosition, PhysicalSize};
+
+    use super::*;
+    use crate::scene::Frame;
+
+    fn size(width: u32, height: u32) -> (NonZeroU32, NonZeroU32) {
+        (
+            NonZeroU32::new(width).unwrap(),
+            NonZeroU32::new(height).unwrap(),
+        )
+    }
+
+    #[test]
+    fn default_camera_matches_pixel_coordinate() {
+        let (width, height) = size(800, 600);
+        let camera = CameraComponent::default();
+        let matrix = camera.view_projection(&TransformComponent::default(), width, height);
+        assert_eq!(matrix, get_matrix_pixel_to_render_coordinate(width, height));
+    }
+
+    #[test]
+    fn orthographic_frustum_culls_outside_of_screen() {
+        let (width, height) = size(800, 600);
+        let camera = CameraComponent::default();
+        let transform = TransformComponent::with_translation(Translation3::new(100.0, 0.0, 0.0));
+        let frustum = camera.frustum(&transform, width, height);
+
+        assert!(frustum.intersects_sphere(&Point3::new(500.0, 300.0, 0.0), 1.0));
+        assert!(frustum.intersects_sphere(&Point3::new(95.0, 300.0, 0.0), 10.0));
+        assert!(!frustum.intersects_sphere(&Point3::new(50.0, 300.0, 0.0), 10.0));
+        assert!(!frustum.intersects_sphere(&Point3::new(950.0, 300.0, 0.0), 10.0));
+        assert!(!frustum.intersects_sphere(&Point3::new(500.0, -50.0, 0.0), 10.0));
+    }
+
+    #[test]
+    fn aabb_against_screen() {
+        let (width, height) = size(800, 600);
+        let camera = CameraComponent::default();
+        let frustum = camera.frustum(&TransformComponent::default(), width, height);
+
+        let aabb = |x0, y0, x1, y1| {
+            frustum.intersects_aabb(&Point3::new(x0, y0, 0.0), &Point3::new(x1, y1, 0.0))
+        };
+        assert!(aabb(10.0, 10.0, 20.0, 20.0));
+        assert!(aabb(-20.0, -20.0, 5.0, 5.0));
+        assert!(aabb(-100.0, -100.0, 1000.0, 1000.0));
+        assert!(!aabb(-20.0, 10.0, -5.0, 20.0));
+        assert!(!aabb(810.0, 10.0, 900.0, 20.0));
+        assert!(!aabb(10.0, 610.0, 20.0, 700.0));
+    }
+
+    #[test]
+    fn zoom_shrinks_visible_area() {
+        let (width, height) = size(800, 600);
+        let camera = CameraComponent::new(Projection::Orthographic { zoom: 2.0 });
+        let frustum = camera.frustum(&TransformComponent::default(), width, height);
+
+        assert!(frustum.intersects_sphere(&Point3::new(350.0, 250.0, 0.0), 1.0));
+        assert!(!frustum.intersects_sphere(&Point3::new(500.0, 250.0, 0.0), 1.0));
+    }
+
+    #[test]
+    fn screen_world_round_trip() {
+        let (width, height) = size(800, 600);
+        let camera = CameraComponent::new(Projection::Orthographic { zoom: 2.0 });
+        let transform = TransformComponent::with_translation(Translation3::new(100.0, 50.0, 0.0));
+
+        let world = camera
+            .screen_to_world(&transform, width, height, &Point2::new(200.0, 100.0))
+            .unwrap();
+        assert!((world - Point3::new(200.0, 100.0, 0.0)).norm() < 1e-3);
+
+        let screen = camera
+            .world_to_screen(&transform, width, height, &world)
+            .unwrap();
+        assert!((screen - Point2::new(200.0, 100.0)).norm() < 1e-3);
+    }
+
+    #[test]
+    fn screen_to_layer_undoes_parallax_offset() {
+        let (width, height) = size(800, 600);
+        let camera = CameraComponent::new(Projection::Orthographic { zoom: 1.0 });
+        let transform = TransformComponent::with_translation(Translation3::new(200.0, 100.0, 0.0));
+        let screen = Point2::new(400.0, 300.0);
+        let world = camera
+            .screen_to_world(&transform, width, height, &screen)
+            .unwrap();
+
+        // 半分の速さの背景は、カメラが動いた半分だけ手前にずれて写っている
+        let layer = ParallaxLayerComponent::new(0.5, 0.5);
+        let point = camera
+            .screen_to_layer(&transform, width, height, &screen, &layer)
+            .unwrap();
+        assert!((point - (world - Vector3::new(100.0, 50.0, 0.0))).norm() < 1e-3);
+
+        // factor が 1 ならワールド座標と同じ
+        let layer = ParallaxLayerComponent::new(1.0, 1.0);
+        let point = camera
+            .screen_to_layer(&transform, width, height, &screen, &layer)
+            .unwrap();
+        assert!((point - world).norm() < 1e-3);
+    }
+
+    #[test]
+    fn frame_converts_through_letterbox() {
+        let (w, h) = size(320, 180);
+        let camera = CameraComponent::default().with_virtual_resolution(w, h);
+        let frame = Frame::new(web_time::Instant::now(), std::time::Duration::ZERO)
+            .with_window(PhysicalSize::new(1000, 700), 2.0);
+        let transform = TransformComponent::default();
+
+        // 3 倍の 960x540 を中央に置き、上下左右に帯が残る
+        let rect = camera.viewport_rect(size(1000, 700).0, size(1000, 700).1);
+        assert_eq!(rect.min, Point2::new(20.0, 80.0));
+        assert_eq!(rect.size(), Vector2::new(960.0, 540.0));
+
+        let to_world =
+            |x, y| frame.screen_to_world(PhysicalPosition::new(x, y), &camera, &transform);
+        assert_eq!(to_world(20.0, 80.0), Some(Point2::new(0.0, 0.0)));
+        assert_eq!(to_world(500.0, 350.0), Some(Point2::new(160.0, 90.0)));
+        assert_eq!(to_world(10.0, 300.0), None);
+        assert_eq!(to_world(500.0, 650.0), None);
+        // 論理ピクセルは倍率を掛けてから変換する
+        let logical = frame.logical_to_physical(LogicalPosition::new(250.0, 175.0));
+        assert_eq!(
+            frame.screen_to_world(logical, &camera, &transform),
+            Some(Point2::new(160.0, 90.0))
+        );
+
+        let screen = frame
+            .world_to_screen(&Point3::new(320.0, 180.0, 0.0), &camera, &transform)
+            .unwrap();
+        assert_eq!(screen, PhysicalPosition::new(980.0, 620.0));
+    }
+
+    #[test]
+    fn pixel_grid_snaps_only_translation() {
+        // 右半分に描く、1 ワールド単位が 3 ピクセルのカメラ
+        let camera = CameraComponent::new(Projection::Orthographic { zoom: 3.0 })
+            .with_viewport(Rect::new(Point2::new(0.5, 0.0), Point2::new(1.0, 1.0)));
+        let view = TransformComponent::with_translation(Translation3::new(0.3, 0.1, 0.0));
+        let (width, height) = size(101, 64);
+        let (view_width, view_height) = camera.view_size(width, height);
+        let grid = PixelGrid::new(
+            camera.view_projection(&view, view_width, view_height),
+            camera.viewport_rect(width, height),
+        );
+
+        let sprite = TransformComponent {
+            translation: Translation3::new(10.1, 5.3, 0.5),
+            rotation: UnitQuaternion::from_axis_angle(&Vector3::z_axis(), 0.3),
+            scale: Scale3::new(1.5, 2.0, 1.0),
+        };
+        let snapped = grid.snap(&sprite);
+        let pixel = camera
+            .world_to_target(&view, width, height, &snapped.translation.vector.into())
+            .unwrap();
+        assert!((pixel - pixel.map(f32::round)).norm() < 1e-3, "{pixel}");
+        assert!((snapped.translation.vector - sprite.translation.vector).norm() < 0.5 / 3.0 * 2.0);
+        assert_eq!(snapped.translation.z, sprite.translation.z);
+        assert_eq!(snapped.rotation, sprite.rotation);
+        assert_eq!(snapped.scale, sprite.scale);
+    }
+
+    #[test]
+    fn frame_converts_for_split_screen() {
+        let half = |x0| Rect::new(Point2::new(x0, 0.0), Point2::new(x0 + 0.5, 1.0));
+        let left = CameraComponent::default().with_viewport(half(0.0));
+        let right =
+            CameraComponent::new(Projection::Orthographic { zoom: 2.0 }).with_viewport(half(0.5));
+        let left_transform = TransformComponent::default();
+        let right_transform =
+            TransformComponent::with_translation(Translation3::new(1000.0, 0.0, 0.0));
+        let frame = Frame::new(web_time::Instant::now(), std::time::Duration::ZERO)
+            .with_window(PhysicalSize::new(800, 600), 1.0);
+
+        let click = PhysicalPosition::new(100.0, 50.0);
+        assert_eq!(
+            frame.screen_to_world(click, &left, &left_transform),
+            Some(Point2::new(100.0, 50.0))
+        );
+        assert_eq!(frame.screen_to_world(click, &right, &right_transform), None);
+
+        let click = PhysicalPosition::new(500.0, 50.0);
+        assert_eq!(frame.screen_to_world(click, &left, &left_transform), None);
+        let world = frame
+            .screen_to_world(click, &right, &right_transform)
+            .unwrap();
+        assert!((world - Point2::new(1050.0, 25.0)).norm() < 1e-3);
+        assert_eq!(
+            frame.world_to_screen(&Point3::new(1000.0, 0.0, 0.0), &right, &right_transform),
+            Some(PhysicalPosition::new(400.0, 0.0))
+        );
+        // ウィンドウの大きさが分からなければ変換できない
+        let headless = Frame::new(web_time::Instant::now(), std::time::Duration::ZERO);
+        assert_eq!(
+            headless.screen_to_world(click, &left, &left_transform),
+            None
+        );
+    }
+
+    #[test]
+    fn zoom_is_clamped_to_limits() {
+        let (width, height) = size(800, 600);
+        let mut camera =
+            CameraComponent::new(Projection::Orthographic { zoom: 8.0 }).with_zoom_limits(0.5, 4.0);
+        assert_eq!(camera.zoom(), Some(4.0));
+        camera.set_zoom(0.1);
+        assert_eq!(camera.zoom(), Some(0.5));
+
+        // 直接書き換えても描画には範囲内の拡大率を使う
+        camera.projection = Projection::Orthographic { zoom: 100.0 };
+        let clamped = CameraComponent::new(Projection::Orthographic { zoom: 4.0 });
+        let transform = TransformComponent::default();
+        assert_eq!(
+            camera.view_projection(&transform, width, height),
+            clamped.view_projection(&transform, width, height)
+        );
+    }
+
+    #[test]
+    fn zoom_around_keeps_point_under_cursor() {
+        let (width, height) = size(800, 600);
+        let mut camera = CameraComponent::new(Projection::Orthographic { zoom: 1.0 })
+            .with_zoom_limits(0.25, 3.0);
+        let mut transform =
+            TransformComponent::with_translation(Translation3::new(30.0, -20.0, 0.0));
+        let cursor = Point2::new(300.0, 120.0);
+        let before = camera
+            .screen_to_world(&transform, width, height, &cursor)
+            .unwrap();
+
+        for factor in [1.5, 1.5, 1.5, 0.1] {
+            assert!(camera.zoom_around(&mut transform, width, height, &cursor, factor));
+            let after = camera
+                .screen_to_world(&transform, width, height, &cursor)
+                .unwrap();
+            assert!((after - before).norm() < 1e-3, "{after} != {before}");
+        }
+        // 1.5 倍を 3 回で上限の 3 に止まり、その 0.1 倍
+        assert!((camera.zoom().unwrap() - 0.3).abs() < 1e-6);
+    }
+
+    #[test]
+    fn pixel_snap_rounds_unrotated_views() {
+        let (width, height) = size(800, 600);
+        let camera = CameraComponent::new(Projection::Orthographic { zoom: 2.0 }).with_pixel_snap();
+        let transform = TransformComponent::with_translation(Translation3::new(10.3, 4.8, 0.0));
+        // 拡大率 2 なら 0.5 単位に合わせる
+        let snapped = TransformComponent::with_translation(Translation3::new(10.5, 5.0, 0.0));
+        let plain = CameraComponent::new(Projection::Orthographic { zoom: 2.0 });
+        assert_eq!(
+            camera.view_projection(&transform, width, height),
+            plain.view_projection(&snapped, width, height)
+        );
+
+        let rotated = TransformComponent::with_translation_and_rotation(
+            transform.translation,
+            UnitQuaternion::from_axis_angle(&Vector3::z_axis(), 0.3),
+        );
+        assert_eq!(
+            camera.view_projection(&rotated, width, height),
+            plain.view_projection(&rotated, width, height)
+        );
+    }
+
+    #[test]
+    fn perspective_frustum() {
+        let (width, height) = size(100, 100);
+        let camera = CameraComponent::new(Projection::Perspective {
+            fovy: std::f32::consts::FRAC_PI_2,
+            near: 0.1,
+            far: 100.0,
+        });
+        // nalgebra の透視投影は -z 方向を向く。y 軸まわりに半回転させて +z を向かせる
+        let transform = TransformComponent::with_translation_and_rotation(
+            Translation3::identity(),
+            UnitQuaternion::from_axis_angle(&Vector3::y_axis(), std::f32::consts::PI),
+        );
+        let frustum = camera.frustum(&transform, width, height);
+
+        assert!(frustum.intersects_sphere(&Point3::new(0.0, 0.0, 10.0), 1.0));
+        assert!(!frustum.intersects_sphere(&Point3::new(0.0, 0.0, -10.0), 1.0));
+        assert!(!frustum.intersects_sphere(&Point3::new(0.0, 0.0, 200.0), 1.0));
+        assert!(!frustum.intersects_sphere(&Point3::new(30.0, 0.0, 10.0), 1.0));
+    }
+}
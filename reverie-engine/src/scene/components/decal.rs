@@ -0,0 +1,534 @@
+//! 貼り付けたスプライトを 1 枚のテクスチャに溜めて描くコンポーネント
+#[cfg(feature = "backend-wgpu")]
+use anyhow::Context;
+use nalgebra::{Point2, Translation3, Vector2};
+use reverie_util::{
+    color::Color,
+    math::{Rad, Rect},
+};
+#[cfg(feature = "backend-wgpu")]
+use wgpu::util::DeviceExt;
+
+#[cfg(feature = "backend-wgpu")]
+use crate::wgpu_wrapper::{offscreen::PixelReadRequest, vertex::UvVertex, WgpuResource};
+use crate::{
+    scene::{SpriteComponent, TransformComponent},
+    texture::{TextureId, TextureIndex, TextureRegistry},
+};
+
+/// キャンバスの一辺のピクセル数の上限
+pub const MAX_DECAL_CANVAS_SIZE: u32 = 8192;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// キャンバスに描き込むスプライト
+pub struct Decal {
+    pub texture: TextureId,
+    /// 中心のワールド座標
+    pub position: Point2<f32>,
+    /// z 軸周りの回転
+    pub rotation: Rad<f32>,
+    /// テクスチャの元の大きさを [`DecalLayerComponent::pixels_per_unit`] で割った大きさに掛ける倍率
+    pub scale: Vector2<f32>,
+    pub tint: Color,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// まだ GPU に送っていない描き込み
+enum DecalOp {
+    Splat(Decal),
+    /// スプライトの不透明な部分だけキャンバスを透明にする
+    Erase(Decal),
+    Clear,
+}
+
+#[derive(Debug)]
+/// 血痕や弾痕のように、ワールドに貼り付けたスプライトを溜めておく層
+///
+/// ワールドの長方形 [`Self::bounds`] に重なるキャンバス ([`TextureRegistry::new_canvas`]) を持ち、
+/// [`Self::splat`] したスプライトをキャンバスに描き込む。描き込みは次の描画の始めに GPU 上でまとめて行う。
+/// いくつ貼り付けても、描画するのはキャンバスの四角形 1 枚だけになる。
+///
+/// キャンバスを描くには [`crate::scene::Scene::new_decal_layer`] で [`SpriteComponent`] と一緒にエンティティを作る。
+pub struct DecalLayerComponent {
+    canvas: TextureIndex,
+    bounds: Rect,
+    pixels_per_unit: f32,
+    pending: Vec<DecalOp>,
+    #[cfg(feature = "backend-wgpu")]
+    gpu: Option<DecalGpu>,
+}
+
+impl DecalLayerComponent {
+    /// ワールドの `bounds` を、1 単位あたり `pixels_per_unit` ピクセルのキャンバスで覆う
+    ///
+    /// キャンバスの大きさは切り上げて整数のピクセルにし、[`Self::bounds`] はそれに合わせて右下に広げる。
+    pub fn new(
+        registry: &mut TextureRegistry,
+        bounds: Rect,
+        pixels_per_unit: f32,
+    ) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            pixels_per_unit.is_finite() && pixels_per_unit > 0.0,
+            "pixels per unit must be positive: {pixels_per_unit}"
+        );
+        let (width, height) = canvas_size(&bounds, pixels_per_unit);
+        anyhow::ensure!(
+            width <= MAX_DECAL_CANVAS_SIZE && height <= MAX_DECAL_CANVAS_SIZE,
+            "decal canvas is too large: {width}x{height}"
+        );
+        let canvas = registry.new_canvas(width, height, Some("Decal Canvas".to_owned()));
+        Ok(Self {
+            canvas,
+            bounds: Rect::from_min_size(
+                bounds.min,
+                Vector2::new(width as f32, height as f32) / pixels_per_unit,
+            ),
+            pixels_per_unit,
+            pending: Vec::new(),
+            #[cfg(feature = "backend-wgpu")]
+            gpu: None,
+        })
+    }
+
+    pub const fn canvas(&self) -> TextureIndex {
+        self.canvas
+    }
+
+    /// キャンバスが覆うワールドの範囲
+    pub const fn bounds(&self) -> &Rect {
+        &self.bounds
+    }
+
+    pub const fn pixels_per_unit(&self) -> f32 {
+        self.pixels_per_unit
+    }
+
+    /// キャンバスの幅と高さ (ピクセル)
+    pub fn canvas_size(&self) -> (u32, u32) {
+        canvas_size(&self.bounds, self.pixels_per_unit)
+    }
+
+    /// まだ GPU に送っていない描き込みの数
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// キャンバスを [`Self::bounds`] に重ねて描くための位置
+    pub fn transform(&self) -> TransformComponent {
+        let center = self.bounds.center();
+        TransformComponent::with_translation(Translation3::new(center.x, center.y, 0.0))
+    }
+
+    /// キャンバスを [`Self::bounds`] に重ねて描くスプライト
+    pub fn sprite(&self) -> SpriteComponent {
+        let size = self.bounds.size();
+        SpriteComponent::builder(self.canvas.into())
+            .size(size.x, size.y)
+            .build()
+    }
+
+    /// `texture` を中心 `position` に貼り付ける
+    ///
+    /// `texture` はテクスチャ全体か、アトラスの割り当て。大きさはテクスチャの元の大きさを
+    /// [`Self::pixels_per_unit`] で割り、`scale` を掛けたもの。`rotation` は z 軸周りの回転。
+    pub fn splat(
+        &mut self,
+        texture: impl Into<TextureId>,
+        position: Point2<f32>,
+        rotation: Rad<f32>,
+        scale: Vector2<f32>,
+        tint: Color,
+    ) {
+        self.pending.push(DecalOp::Splat(Decal {
+            texture: texture.into(),
+            position,
+            rotation,
+            scale,
+            tint,
+        }));
+    }
+
+    /// `texture` の不透明な部分の形に、貼り付けたものを消す
+    ///
+    /// 丸いテクスチャを渡せば、爆発の跡のように丸く消せる。引数は [`Self::splat`] と同じ。
+    pub fn erase(
+        &mut self,
+        texture: impl Into<TextureId>,
+        position: Point2<f32>,
+        rotation: Rad<f32>,
+        scale: Vector2<f32>,
+    ) {
+        self.pending.push(DecalOp::Erase(Decal {
+            texture: texture.into(),
+            position,
+            rotation,
+            scale,
+            tint: Color::WHITE,
+        }));
+    }
+
+    /// ワールドの長方形 `rect` の中の貼り付けたものを消す
+    pub fn erase_rect(&mut self, rect: Rect) {
+        // 組み込みの白いテクスチャは 1x1 ピクセルとみなすので、ピクセル数をそのまま倍率にする
+        self.erase(
+            TextureId::WHITE,
+            rect.center(),
+            Rad(0.0),
+            rect.size() * self.pixels_per_unit,
+        );
+    }
+
+    /// 貼り付けたものをすべて消す。まだ送っていない描き込みも取りやめる
+    pub fn clear(&mut self) {
+        self.pending.clear();
+        self.pending.push(DecalOp::Clear);
+    }
+
+    /// まだ送っていない描き込みに使うテクスチャ
+    #[cfg_attr(not(feature = "backend-wgpu"), allow(dead_code))]
+    pub(crate) fn pending_textures(&self) -> impl Iterator<Item = TextureId> + '_ {
+        self.pending.iter().filter_map(|op| match op {
+            DecalOp::Splat(decal) | DecalOp::Erase(decal) => Some(decal.texture),
+            DecalOp::Clear => None,
+        })
+    }
+
+    /// `decal` の四隅の頂点。キャンバスへの描き込みに使う
+    #[cfg(feature = "backend-wgpu")]
+    fn quad(&self, registry: &TextureRegistry, decal: &Decal) -> anyhow::Result<[UvVertex; 4]> {
+        let (width, height) = registry.region_size(registry.resolve(decal.texture))?;
+        let size = Vector2::new(width as f32, height as f32).component_mul(&decal.scale)
+            / self.pixels_per_unit;
+        let sprite = SpriteComponent::builder(decal.texture)
+            .size(size.x, size.y)
+            .tint(decal.tint)
+            .build();
+        let transform = TransformComponent::with_translation_and_rotation(
+            Translation3::new(decal.position.x, decal.position.y, 0.0),
+            nalgebra::UnitQuaternion::from_axis_angle(
+                &nalgebra::Vector3::z_axis(),
+                decal.rotation.0,
+            ),
+        );
+        sprite.quad_vertices(registry, &transform)
+    }
+
+    /// 溜まっている描き込みをキャンバスに描くコマンドを `encoder` に積む
+    ///
+    /// キャンバスがまだ GPU に無ければ、描き込みは次に回す。
+    #[cfg(feature = "backend-wgpu")]
+    pub(crate) fn flush(
+        &mut self,
+        resource: &WgpuResource<'_>,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let registry = &resource.texture_registry;
+        if !registry.contains(self.canvas) {
+            tracing::warn!("decal canvas was unloaded");
+            self.pending.clear();
+            return;
+        }
+        let Ok(canvas) = registry.canvas(self.canvas) else {
+            return;
+        };
+        let ops = std::mem::take(&mut self.pending);
+        let mut vertices = Vec::with_capacity(ops.len() * 6);
+        let mut draws = Vec::with_capacity(ops.len());
+        for op in &ops {
+            let (decal, erase) = match op {
+                DecalOp::Splat(decal) => (decal, false),
+                DecalOp::Erase(decal) => (decal, true),
+                DecalOp::Clear => continue,
+            };
+            match self.quad(registry, decal) {
+                Ok(quad) => {
+                    vertices.extend([0, 2, 1, 1, 2, 3].map(|i| quad[i]));
+                    draws.push((decal.texture, erase));
+                }
+                Err(err) => tracing::warn!(?err, "failed: splat decal"),
+            }
+        }
+
+        let bounds = self.bounds;
+        let gpu = self
+            .gpu
+            .get_or_insert_with(|| DecalGpu::new(resource, &bounds));
+        let vertex_buffer = (!vertices.is_empty()).then(|| {
+            resource
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Decal Vertex Buffer"),
+                    contents: bytemuck::cast_slice(&vertices),
+                    usage: wgpu::BufferUsages::VERTEX,
+                })
+        });
+        let format = canvas.texture.format();
+        let splat = resource
+            .pipeline_cache
+            .get_or_create("reverie decal splat", |_| {
+                DecalGpu::pipeline(resource, format, false)
+            });
+        let erase = resource
+            .pipeline_cache
+            .get_or_create("reverie decal erase", |_| {
+                DecalGpu::pipeline(resource, format, true)
+            });
+
+        let load = if matches!(ops.first(), Some(DecalOp::Clear)) {
+            wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT)
+        } else {
+            wgpu::LoadOp::Load
+        };
+        let mut rp = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Decal Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &canvas.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        let Some(vertex_buffer) = vertex_buffer else {
+            return;
+        };
+        rp.set_bind_group(1, &gpu.bind_group, &[]);
+        rp.set_vertex_buffer(0, vertex_buffer.slice(..));
+        for (i, (texture, is_erase)) in draws.into_iter().enumerate() {
+            let bind_group = match resource.get_texture_bind_group(texture) {
+                Ok(bind_group) => bind_group,
+                Err(err) => {
+                    tracing::warn!(?err, "failed: splat decal");
+                    continue;
+                }
+            };
+            rp.set_pipeline(if is_erase { &erase } else { &splat });
+            rp.set_bind_group(0, bind_group, &[]);
+            let first = i as u32 * 6;
+            rp.draw(first..first + 6, 0..1);
+        }
+    }
+
+    /// キャンバスの内容を PNG にする
+    ///
+    /// 溜まっている描き込みを先に GPU に送り、読み出しが終わるまで待つ。
+    /// シーンを保存するときに、ほかのデータと一緒に書き出しておき、[`Self::load_png`] で戻す。
+    #[cfg(feature = "backend-wgpu")]
+    pub fn save_png(&mut self, resource: &WgpuResource<'_>) -> anyhow::Result<Vec<u8>> {
+        let mut encoder = resource
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Decal Flush"),
+            });
+        self.flush(resource, &mut encoder);
+        resource.queue.submit(Some(encoder.finish()));
+
+        let canvas = resource.texture_registry.canvas(self.canvas)?;
+        let pixels = PixelReadRequest::new(resource, &canvas.texture).wait(resource)?;
+        let image = image::RgbaImage::from_raw(canvas.width(), canvas.height(), pixels)
+            .context("failed: read decal canvas")?;
+        let mut png = std::io::Cursor::new(Vec::new());
+        image
+            .write_to(&mut png, image::ImageFormat::Png)
+            .context("failed: encode decal canvas")?;
+        Ok(png.into_inner())
+    }
+
+    /// [`Self::save_png`] で保存した内容をキャンバスに戻す
+    ///
+    /// まだ送っていない描き込みは取りやめる。画像の大きさは [`Self::canvas_size`] と同じでなければならない。
+    #[cfg(feature = "backend-wgpu")]
+    pub fn load_png(
+        &mut self,
+        resource: &mut WgpuResource<'_>,
+        bytes: &[u8],
+    ) -> anyhow::Result<()> {
+        let image = image::load_from_memory(bytes)
+            .context("failed: decode decal canvas")?
+            .to_rgba8();
+        resource
+            .texture_registry
+            .replace_canvas_image(self.canvas, image, &resource.queue)?;
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+/// `bounds` を 1 単位あたり `pixels_per_unit` ピクセルで覆うキャンバスの大きさ
+fn canvas_size(bounds: &Rect, pixels_per_unit: f32) -> (u32, u32) {
+    // 浮動小数点の誤差で 1 ピクセル増えないように、少しだけ切り下げてから切り上げる
+    let pixels = |length: f32| (length.mul_add(pixels_per_unit, -1e-3).ceil() as u32).max(1);
+    (pixels(bounds.width()), pixels(bounds.height()))
+}
+
+#[cfg(feature = "backend-wgpu")]
+#[derive(Debug)]
+/// デカールの層ごとの GPU のリソース
+struct DecalGpu {
+    /// ワールド座標をキャンバスの描画先の座標にする行列
+    _uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+#[cfg(feature = "backend-wgpu")]
+impl DecalGpu {
+    fn new(resource: &WgpuResource<'_>, bounds: &Rect) -> Self {
+        // 上端 (y の小さい側) がテクスチャの 1 行目になるように、スプライトと同じく y を反転する
+        let sx = 2.0 / bounds.width();
+        let sy = -2.0 / bounds.height();
+        #[rustfmt::skip]
+        let matrix = nalgebra::Matrix4::new(
+            sx, 0.0, 0.0, sx.mul_add(-bounds.min.x, -1.0),
+            0.0, sy, 0.0, sy.mul_add(-bounds.min.y, 1.0),
+            0.0, 0.0, 0.0, 0.0,
+            0.0, 0.0, 0.0, 1.0,
+        );
+        let uniform_buffer =
+            resource
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Decal Canvas Matrix Buffer"),
+                    contents: bytemuck::cast_slice(matrix.as_slice()),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                });
+        let bind_group = resource
+            .device
+            .create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Decal Canvas Bind Group"),
+                layout: &resource.uniform_bind_group_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                }],
+            });
+        Self {
+            _uniform_buffer: uniform_buffer,
+            bind_group,
+        }
+    }
+
+    /// スプライトのシェーダーでキャンバスに描くパイプライン
+    ///
+    /// `erase` ならスプライトの不透明度の分だけキャンバスを透明にする。
+    fn pipeline(
+        resource: &WgpuResource<'_>,
+        format: wgpu::TextureFormat,
+        erase: bool,
+    ) -> wgpu::RenderPipeline {
+        let device = &resource.device;
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shader from shader.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../../shader.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Decal Pipeline Layout"),
+            bind_group_layouts: &[
+                &resource.texture_bind_group_layout,
+                &resource.uniform_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let blend = if erase {
+            let erase = wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::Zero,
+                dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                operation: wgpu::BlendOperation::Add,
+            };
+            wgpu::BlendState {
+                color: erase,
+                alpha: erase,
+            }
+        } else {
+            wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: wgpu::BlendComponent::OVER,
+            }
+        };
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(if erase {
+                "Decal Erase Pipeline"
+            } else {
+                "Decal Splat Pipeline"
+            }),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                compilation_options: Default::default(),
+                buffers: &[UvVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(blend),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            // 負の倍率で裏返したものも描く
+            primitive: wgpu::PrimitiveState {
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canvas_covers_bounds_in_whole_pixels() {
+        let mut registry = TextureRegistry::default();
+        let bounds = Rect::new(Point2::new(-2.0, 1.0), Point2::new(3.0, 2.3));
+        let layer = DecalLayerComponent::new(&mut registry, bounds, 10.0).unwrap();
+        assert_eq!(layer.canvas_size(), (50, 13));
+        assert_eq!(layer.bounds().min, bounds.min);
+        assert!((layer.bounds().height() - 1.3).abs() < 1e-5);
+        assert!(registry.contains(layer.canvas()));
+
+        let huge = Rect::new(Point2::origin(), Point2::new(1000.0, 1.0));
+        assert!(DecalLayerComponent::new(&mut registry, huge, 10.0).is_err());
+        assert!(DecalLayerComponent::new(&mut registry, bounds, 0.0).is_err());
+    }
+
+    #[test]
+    fn clear_drops_earlier_splats() {
+        let mut registry = TextureRegistry::default();
+        let bounds = Rect::new(Point2::origin(), Point2::new(4.0, 4.0));
+        let mut layer = DecalLayerComponent::new(&mut registry, bounds, 8.0).unwrap();
+        layer.splat(
+            TextureId::MISSING,
+            Point2::new(1.0, 1.0),
+            Rad(0.0),
+            Vector2::new(1.0, 1.0),
+            Color::WHITE,
+        );
+        layer.erase_rect(bounds);
+        assert_eq!(
+            layer.pending_textures().collect::<Vec<_>>(),
+            [TextureId::MISSING, TextureId::WHITE]
+        );
+        layer.clear();
+        assert_eq!(layer.pending(), 1);
+        assert_eq!(layer.pending_textures().count(), 0);
+    }
+}
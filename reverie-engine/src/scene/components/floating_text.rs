@@ -0,0 +1,578 @@
+//! ダメージの数値や名前のように、ワールド座標に浮かべてすぐに消す文字列
+//!
+//! [`FloatingText::spawn`] で出し、[`FloatingTextSystem`] が上へ動かして薄くしていく。
+//! 消えた文字列のエンティティは [`FloatingTextPool`] に戻して次の文字列に使い回すので、
+//! たくさん出し続けてもエンティティと GPU のバッファは増えない。
+//!
+//! 描画では、同じ層の浮かぶ文字列のグリフをまとめて 1 回のインスタンス描画で描く。
+//! ミニマップのカメラなどに写したくなければ、[`FloatingTextAnimation::layer`] を
+//! [`CameraComponent::hidden_layers`](super::camera::CameraComponent::hidden_layers) に入れる。
+use std::time::Duration;
+
+use nalgebra::{Point3, Scale3, Vector2, Vector3};
+
+use super::{render_layer::RenderLayerComponent, text::TextComponent};
+use crate::{
+    scene::{
+        for_each_ordered, insert_resource, resource, resource_mut, EntityIndex, Frame,
+        RenderResource, Scene, System, TransformComponent,
+    },
+    text::TextStyle,
+};
+#[cfg(feature = "backend-wgpu")]
+use crate::{
+    text::{Fonts, GlyphSource, TextLayout},
+    wgpu_wrapper::{
+        memory::{GpuMemoryCategory, TrackedAllocation},
+        vertex::GlyphInstance,
+        WgpuResource,
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// 浮かぶ文字列の動き方
+pub struct FloatingTextAnimation {
+    /// 1 秒あたりの移動量
+    pub rise: Vector3<f32>,
+    /// 寿命の最後のこの割合 (0.0 から 1.0) の間に透明にしていく
+    pub fade_out: f32,
+    /// グリフの 1 ピクセルのワールド座標での大きさ。y が上向きのカメラでは y を負にする
+    pub scale: Vector2<f32>,
+    /// 描画する層
+    pub layer: i32,
+}
+
+impl FloatingTextAnimation {
+    /// 既定の層。ほかのものより手前に描くように大きくしてある
+    pub const DEFAULT_LAYER: i32 = 1000;
+}
+
+impl Default for FloatingTextAnimation {
+    /// ピクセル座標で 1 秒に 32 ピクセル上がり、後半で消えていく
+    fn default() -> Self {
+        Self {
+            rise: Vector3::new(0.0, -32.0, 0.0),
+            fade_out: 0.5,
+            scale: Vector2::new(1.0, 1.0),
+            layer: Self::DEFAULT_LAYER,
+        }
+    }
+}
+
+#[derive(Debug)]
+/// 浮かぶ文字列
+///
+/// [`FloatingText::spawn`] で作る。[`TransformComponent`] の位置が文字列の中心になる。
+/// 回転は無視する。
+pub struct FloatingTextComponent {
+    text: TextComponent,
+    origin: Point3<f32>,
+    age: f32,
+    lifetime: f32,
+    animation: FloatingTextAnimation,
+    /// 消えて [`FloatingTextPool`] に戻っているときは `false`
+    active: bool,
+}
+
+impl FloatingTextComponent {
+    fn new(
+        text: &str,
+        origin: Point3<f32>,
+        style: TextStyle,
+        lifetime: f32,
+        animation: FloatingTextAnimation,
+    ) -> Self {
+        Self {
+            text: TextComponent::new(text, style),
+            origin,
+            age: 0.0,
+            lifetime,
+            animation,
+            active: true,
+        }
+    }
+
+    /// 使い回すときに、新しく作ったときと同じ状態にする
+    fn reset(
+        &mut self,
+        text: &str,
+        origin: Point3<f32>,
+        style: TextStyle,
+        lifetime: f32,
+        animation: FloatingTextAnimation,
+    ) {
+        self.text.set_text(text);
+        self.text.set_style(style);
+        self.origin = origin;
+        self.age = 0.0;
+        self.lifetime = lifetime;
+        self.animation = animation;
+        self.active = true;
+    }
+
+    pub fn text(&self) -> &str {
+        self.text.text()
+    }
+
+    pub const fn animation(&self) -> &FloatingTextAnimation {
+        &self.animation
+    }
+
+    /// 出してからの秒数
+    pub const fn age(&self) -> f32 {
+        self.age
+    }
+
+    pub const fn lifetime(&self) -> f32 {
+        self.lifetime
+    }
+
+    /// まだ消えていないか
+    pub const fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// 今の中心の位置
+    pub fn position(&self) -> Point3<f32> {
+        self.origin + self.animation.rise * self.age
+    }
+
+    /// 今の不透明度に掛ける値
+    pub fn alpha(&self) -> f32 {
+        let fade = self.animation.fade_out.clamp(0.0, 1.0) * self.lifetime;
+        let remaining = self.lifetime - self.age;
+        if fade <= 0.0 || remaining >= fade {
+            1.0
+        } else {
+            (remaining / fade).clamp(0.0, 1.0)
+        }
+    }
+
+    #[cfg(feature = "backend-wgpu")]
+    pub(crate) fn update_layout(&mut self, fonts: &Fonts) -> &TextLayout {
+        self.text.update_layout(fonts)
+    }
+
+    #[cfg(feature = "backend-wgpu")]
+    pub(crate) fn prepare(&mut self, fonts: &mut Fonts) {
+        self.text.prepare(fonts);
+    }
+
+    /// グリフのインスタンスを `out` に加える
+    ///
+    /// [`Self::prepare`] の後に呼ぶ。アトラスに無いグリフは飛ばす。
+    #[cfg(feature = "backend-wgpu")]
+    fn push_instances(
+        &self,
+        fonts: &Fonts,
+        transform: &TransformComponent,
+        out: &mut Vec<GlyphInstance>,
+    ) {
+        let Some(layout) = self.text.layout() else {
+            return;
+        };
+        let atlas = fonts.atlas();
+        let scale = transform.scale.vector.xy();
+        // 中心が原点に来るようにずらす
+        let center = layout.size() / 2.0;
+        let origin = transform.translation.vector;
+        let alpha = self.alpha();
+        for positioned in layout.glyphs() {
+            let GlyphSource::Font { font, glyph } = positioned.source else {
+                continue;
+            };
+            let Some(glyph) = atlas.get(font, glyph, positioned.size) else {
+                continue;
+            };
+            // ビットマップがピクセルの格子に揃うように、ペンの位置を丸める
+            let min = Vector2::new(
+                positioned.position.x.round() + glyph.offset.x - center.x,
+                positioned.position.y.round() + glyph.offset.y - center.y,
+            );
+            let size = Vector2::new(glyph.size[0] as f32, glyph.size[1] as f32);
+            let color = positioned.color;
+            out.push(GlyphInstance {
+                position: [
+                    min.x.mul_add(scale.x, origin.x),
+                    min.y.mul_add(scale.y, origin.y),
+                    origin.z,
+                ],
+                size: size.component_mul(&scale).into(),
+                uv: atlas.uv(&glyph).into(),
+                color: [color.r, color.g, color.b, color.a * alpha],
+            });
+        }
+    }
+}
+
+/// 浮かぶ文字列を出す
+pub struct FloatingText;
+
+impl FloatingText {
+    /// `world_pos` を中心に `text` を出し、[`FloatingTextAnimation::default`] の動きで `lifetime` 秒後に消す
+    ///
+    /// 動かすには [`FloatingTextSystem`] を追加する。
+    pub fn spawn(
+        scene: &mut Scene,
+        text: &str,
+        world_pos: Point3<f32>,
+        style: TextStyle,
+        lifetime: f32,
+    ) -> EntityIndex {
+        Self::spawn_animated(
+            scene,
+            text,
+            world_pos,
+            style,
+            lifetime,
+            FloatingTextAnimation::default(),
+        )
+    }
+
+    /// [`Self::spawn`] と同じだが、動き方を `animation` にする
+    ///
+    /// [`FloatingTextPool`] に消えた文字列があれば、そのエンティティを使い回す。
+    pub fn spawn_animated(
+        scene: &mut Scene,
+        text: &str,
+        world_pos: Point3<f32>,
+        style: TextStyle,
+        lifetime: f32,
+        animation: FloatingTextAnimation,
+    ) -> EntityIndex {
+        let world = &mut scene.world;
+        if resource::<FloatingTextPool>(world).is_none() {
+            insert_resource(world, FloatingTextPool::default());
+        }
+        let recycled = resource_mut::<FloatingTextPool>(world).and_then(|mut pool| {
+            // 使い回す前に外から削除されたものは飛ばす
+            std::iter::from_fn(|| pool.free.pop()).find(|&entity| world.contains(entity))
+        });
+        let transform = transform_at(world_pos, &animation);
+        if let Some(entity) = recycled {
+            if let Ok((floating, current, layer)) = world.query_one_mut::<(
+                &mut FloatingTextComponent,
+                &mut TransformComponent,
+                &mut RenderLayerComponent,
+            )>(entity)
+            {
+                floating.reset(text, world_pos, style, lifetime, animation);
+                *current = transform;
+                *layer = RenderLayerComponent(animation.layer);
+                return EntityIndex(entity);
+            }
+        }
+        let floating = FloatingTextComponent::new(text, world_pos, style, lifetime, animation);
+        let entity = world.spawn((transform, floating, RenderLayerComponent(animation.layer)));
+        if let Some(mut pool) = resource_mut::<FloatingTextPool>(world) {
+            pool.created += 1;
+        }
+        EntityIndex(entity)
+    }
+}
+
+fn transform_at(position: Point3<f32>, animation: &FloatingTextAnimation) -> TransformComponent {
+    let mut transform = TransformComponent::default();
+    transform.translation.vector = position.coords;
+    transform.scale = Scale3::new(animation.scale.x, animation.scale.y, 1.0);
+    transform
+}
+
+#[derive(Debug, Default)]
+/// 消えた浮かぶ文字列のエンティティと、描画に使う GPU のバッファを取っておくリソース
+///
+/// 最初の [`FloatingText::spawn`] で追加される。
+pub struct FloatingTextPool {
+    free: Vec<hecs::Entity>,
+    /// これまでに作ったエンティティの数
+    created: usize,
+    #[cfg(feature = "backend-wgpu")]
+    instances: Vec<GlyphInstance>,
+    #[cfg(feature = "backend-wgpu")]
+    gpu: Option<InstanceBuffer>,
+}
+
+impl FloatingTextPool {
+    /// 使い回すのを待っているエンティティの数
+    pub fn pooled(&self) -> usize {
+        self.free.len()
+    }
+
+    /// これまでに作ったエンティティの数。使い回したものは数えない
+    pub const fn created(&self) -> usize {
+        self.created
+    }
+}
+
+#[cfg(feature = "backend-wgpu")]
+#[derive(Debug)]
+/// グリフのインスタンスを入れるバッファ
+///
+/// 1 フレームの中では描くたびに後ろへ書き足すので、カメラが複数あっても前の描画の内容を上書きしない。
+struct InstanceBuffer {
+    buffer: wgpu::Buffer,
+    capacity: usize,
+    /// このフレームで次に書き込む位置
+    cursor: usize,
+    frame: u64,
+    _memory: TrackedAllocation,
+}
+
+#[cfg(feature = "backend-wgpu")]
+impl InstanceBuffer {
+    fn new(resource: &WgpuResource<'_>, capacity: usize) -> Self {
+        let size = (capacity * size_of::<GlyphInstance>()) as u64;
+        let buffer = resource.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Floating Text Instances"),
+            size,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let memory = resource.gpu_memory.track(
+            GpuMemoryCategory::SpriteBuffer,
+            "Floating Text Instances",
+            size,
+        );
+        Self {
+            buffer,
+            capacity,
+            cursor: 0,
+            frame: resource.frames.current_frame(),
+            _memory: memory,
+        }
+    }
+}
+
+/// `entities` の浮かぶ文字列をまとめて描く
+///
+/// [`crate::scene::Scene::prepare_sprites`] でグリフをアトラスに描き込んだ後に呼ぶ。
+/// 描画の後、パイプラインはスプライトのものに戻っていない。
+#[cfg(feature = "backend-wgpu")]
+pub fn draw_floating_texts(
+    world: &hecs::World,
+    entities: impl Iterator<Item = hecs::Entity>,
+    fonts: &Fonts,
+    rp: &mut wgpu::RenderPass<'_>,
+    resource: &WgpuResource<'_>,
+) {
+    let Some(atlas_bind_group) = fonts.atlas().bind_group() else {
+        tracing::warn!("glyph atlas is not prepared");
+        return;
+    };
+    let Some(mut pool) = resource_mut::<FloatingTextPool>(world) else {
+        return;
+    };
+    let pool = &mut *pool;
+    pool.instances.clear();
+    for entity in entities {
+        let Ok(mut query) =
+            world.query_one::<(&FloatingTextComponent, &TransformComponent)>(entity)
+        else {
+            continue;
+        };
+        if let Some((floating, transform)) = query.get() {
+            floating.push_instances(fonts, transform, &mut pool.instances);
+        }
+    }
+    let count = pool.instances.len();
+    if count == 0 {
+        return;
+    }
+
+    let frame = resource.frames.current_frame();
+    let gpu = pool
+        .gpu
+        .get_or_insert_with(|| InstanceBuffer::new(resource, count.next_power_of_two()));
+    if gpu.frame != frame {
+        gpu.frame = frame;
+        gpu.cursor = 0;
+    }
+    if gpu.cursor + count > gpu.capacity {
+        // 前に描いたものは古いバッファを使い続けるので、新しいバッファは先頭から使う
+        *gpu = InstanceBuffer::new(resource, (gpu.cursor + count).next_power_of_two());
+    }
+    let start = gpu.cursor;
+    resource.queue.write_buffer(
+        &gpu.buffer,
+        (start * size_of::<GlyphInstance>()) as u64,
+        bytemuck::cast_slice(&pool.instances),
+    );
+    gpu.cursor += count;
+
+    let pipeline = resource
+        .pipeline_cache
+        .get_or_create("reverie floating text", |formats| {
+            floating_text_pipeline(resource, formats.color)
+        });
+    rp.set_pipeline(&pipeline);
+    rp.set_bind_group(0, atlas_bind_group, &[]);
+    rp.set_vertex_buffer(0, gpu.buffer.slice(..));
+    rp.draw(0..4, start as u32..(start + count) as u32);
+}
+
+/// グリフを 4 頂点のストリップのインスタンスとして描くパイプラインを作る
+#[cfg(feature = "backend-wgpu")]
+fn floating_text_pipeline(
+    resource: &WgpuResource<'_>,
+    format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    let device = &resource.device;
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Shader from floating_text.wgsl"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("../../floating_text.wgsl").into()),
+    });
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Floating Text Pipeline Layout"),
+        bind_group_layouts: &[
+            &resource.texture_bind_group_layout,
+            &resource.uniform_bind_group_layout,
+        ],
+        push_constant_ranges: &[],
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Floating Text Pipeline"),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: Some("vs_main"),
+            compilation_options: Default::default(),
+            buffers: &[GlyphInstance::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: Some("fs_main"),
+            compilation_options: Default::default(),
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(crate::wgpu_wrapper::SPRITE_BLEND),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        // 負の倍率で上下を返したものも描く
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleStrip,
+            cull_mode: None,
+            ..Default::default()
+        },
+        // スプライトと同じく、描画した順に重ねる
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: WgpuResource::DEPTH_STENCIL_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Always,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
+
+#[derive(Debug, Default)]
+/// [`FloatingTextComponent`] を動かして薄くし、寿命が尽きたものを [`FloatingTextPool`] に戻す
+pub struct FloatingTextSystem;
+
+impl FloatingTextSystem {
+    pub const fn new() -> Self {
+        Self
+    }
+
+    /// 浮かぶ文字列を`delta_time`だけ進める
+    ///
+    /// [`System::update`] から呼ばれる。GPU に触れないのでテストからも直接呼べる。
+    /// 消えた文字列は [`crate::scene::Determinism`] があれば作られた順にプールに戻す。
+    pub fn apply(world: &mut hecs::World, delta_time: Duration) {
+        if resource::<FloatingTextPool>(world).is_none() {
+            return;
+        }
+        let delta_time = delta_time.as_secs_f32();
+        let mut freed = Vec::new();
+        for_each_ordered::<(&mut FloatingTextComponent, &mut TransformComponent)>(
+            world,
+            |entity, (floating, transform)| {
+                if !floating.active {
+                    return;
+                }
+                floating.age += delta_time;
+                if floating.age >= floating.lifetime {
+                    floating.active = false;
+                    freed.push(entity);
+                    return;
+                }
+                transform.translation.vector = floating.position().coords;
+            },
+        );
+        if let Some(mut pool) = resource_mut::<FloatingTextPool>(world) {
+            pool.free.extend(freed);
+        }
+    }
+}
+
+impl System for FloatingTextSystem {
+    fn setup(&mut self, _resource: Option<&RenderResource<'_>>) {}
+
+    fn update(
+        &mut self,
+        frame: &Frame<'_>,
+        world: &mut hecs::World,
+        _resource: Option<&RenderResource<'_>>,
+    ) {
+        Self::apply(world, frame.delta_time);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spawn(scene: &mut Scene, text: &str) -> EntityIndex {
+        FloatingText::spawn(
+            scene,
+            text,
+            Point3::new(10.0, 20.0, 0.0),
+            TextStyle::debug(16.0),
+            1.0,
+        )
+    }
+
+    #[test]
+    fn rises_fades_and_returns_to_pool() {
+        let mut scene = Scene::default();
+        let entity = spawn(&mut scene, "12");
+        FloatingTextSystem::apply(&mut scene.world, Duration::from_millis(750));
+        {
+            let (floating, transform) = scene
+                .world
+                .query_one_mut::<(&FloatingTextComponent, &TransformComponent)>(entity.0)
+                .unwrap();
+            assert!(floating.is_active());
+            assert_eq!(transform.translation.vector, Vector3::new(10.0, -4.0, 0.0));
+            assert_eq!(floating.alpha(), 0.5);
+        }
+        FloatingTextSystem::apply(&mut scene.world, Duration::from_millis(250));
+        let floating = scene.world.get::<&FloatingTextComponent>(entity.0).unwrap();
+        assert!(!floating.is_active());
+        drop(floating);
+        assert_eq!(scene.resource::<FloatingTextPool>().unwrap().pooled(), 1);
+    }
+
+    #[test]
+    fn reuses_expired_entities() {
+        let mut scene = Scene::default();
+        let first = spawn(&mut scene, "1");
+        FloatingTextSystem::apply(&mut scene.world, Duration::from_secs(2));
+        let second = spawn(&mut scene, "2");
+        assert_eq!(first, second);
+        let floating = scene.world.get::<&FloatingTextComponent>(second.0).unwrap();
+        assert!(floating.is_active());
+        assert_eq!(floating.text(), "2");
+        assert_eq!(floating.age(), 0.0);
+        drop(floating);
+
+        // 使い回せるものが無ければ作る
+        let third = spawn(&mut scene, "3");
+        assert_ne!(second, third);
+        let pool = scene.resource::<FloatingTextPool>().unwrap();
+        assert_eq!((pool.pooled(), pool.created()), (0, 2));
+    }
+}
@@ -0,0 +1,572 @@
+//! 子孫の描画を切り抜くマスク
+//!
+//! [`MaskComponent`] を付けたエンティティの子孫 ([`crate::scene::ParentComponent`] でたどる) は、
+//! マスクの形の内側だけに描かれる。入れ子になったマスクは、すべての内側だけに描かれる。
+//! マスクを付けたエンティティ自身のスプライトは切り抜かない。
+//!
+//! GPU ではステンシルバッファを使う。子孫を描く前にマスクの形の内側のステンシルを 1 増やし、
+//! スプライトのパイプラインは値が重なったマスクの数と等しい画素だけに描く。
+//! 浮かぶ文字列 ([`crate::scene::FloatingTextComponent`]) とデバッグ描画は切り抜かない。
+#[cfg(feature = "backend-wgpu")]
+use anyhow::Context;
+use nalgebra::{Point2, Vector2};
+#[cfg(feature = "backend-wgpu")]
+use tracing_unwrap::ResultExt;
+
+#[cfg(feature = "backend-wgpu")]
+use crate::wgpu_wrapper::{buffer::VertexIndexBuffer, vertex::UvVertex, WgpuResource};
+use crate::{
+    scene::{parent, EntityIndex, SpriteComponent, TransformComponent},
+    texture::{TextureId, TextureRegistry},
+};
+
+/// [`MaskShape::SpriteAlpha`] で内側とする不透明度の下限。シェーダーの 0.5 と同じ
+const ALPHA_THRESHOLD: u8 = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// [`MaskComponent`] の形
+pub enum MaskShape {
+    /// 位置を中心とする `size` の長方形。[`TransformComponent`] の回転と拡大縮小が掛かる
+    ///
+    /// スクロールする UI のリストを枠の中に収めるのに使う。
+    Rect { size: Vector2<f32> },
+    /// 同じエンティティの [`SpriteComponent`] のうち、不透明度が半分以上の部分
+    ///
+    /// 円いミニマップのように、形をテクスチャで決めるのに使う。
+    /// スプライトが無ければ内側が無いので、子孫は何も描かれない。
+    SpriteAlpha,
+}
+
+#[derive(Debug)]
+/// 子孫のスプライトや文字列を、形の内側だけに描くコンポーネント
+///
+/// 切り抜かれて見えない部分は [`crate::scene::entities_at_point`] でも当たらない。
+pub struct MaskComponent {
+    shape: MaskShape,
+    #[cfg(feature = "backend-wgpu")]
+    buffer: Option<VertexIndexBuffer>,
+}
+
+impl MaskComponent {
+    pub const fn new(shape: MaskShape) -> Self {
+        Self {
+            shape,
+            #[cfg(feature = "backend-wgpu")]
+            buffer: None,
+        }
+    }
+
+    /// 位置を中心とする `width` x `height` の長方形で切り抜く
+    pub const fn rect(width: f32, height: f32) -> Self {
+        Self::new(MaskShape::Rect {
+            size: Vector2::new(width, height),
+        })
+    }
+
+    /// 同じエンティティのスプライトの不透明な部分で切り抜く
+    pub const fn sprite_alpha() -> Self {
+        Self::new(MaskShape::SpriteAlpha)
+    }
+
+    pub const fn shape(&self) -> MaskShape {
+        self.shape
+    }
+
+    pub fn set_shape(&mut self, shape: MaskShape) {
+        self.shape = shape;
+    }
+
+    /// マスクの形を 1 枚のスプライトとして `f` に渡す。内側が無ければ `None`
+    fn with_quad<R>(
+        &self,
+        sprite: Option<&SpriteComponent>,
+        f: impl FnOnce(&SpriteComponent) -> R,
+    ) -> Option<R> {
+        match self.shape {
+            MaskShape::Rect { size } => Some(f(&SpriteComponent::builder(TextureId::WHITE)
+                .size(size.x, size.y)
+                .build())),
+            MaskShape::SpriteAlpha => sprite.map(f),
+        }
+    }
+
+    /// ワールド座標の点 `point` がマスクの内側にあるか
+    ///
+    /// `sprite` は同じエンティティのスプライト。[`MaskShape::SpriteAlpha`] の不透明度は
+    /// `textures` があれば CPU 上の画像で調べ、無ければスプライトの四角形全体を内側とする。
+    pub fn contains(
+        &self,
+        transform: &TransformComponent,
+        sprite: Option<&SpriteComponent>,
+        point: &Point2<f32>,
+        textures: Option<&TextureRegistry>,
+    ) -> bool {
+        self.with_quad(sprite, |quad| {
+            let Some(uv) = quad.hit_uv(transform, point) else {
+                return false;
+            };
+            let Some(textures) = textures.filter(|_| self.shape == MaskShape::SpriteAlpha) else {
+                return true;
+            };
+            match textures.alpha_at(quad.texture(), uv) {
+                Ok(alpha) => alpha >= ALPHA_THRESHOLD,
+                Err(e) => {
+                    tracing::warn!("failed: read mask alpha: {e:#}");
+                    true
+                }
+            }
+        })
+        .unwrap_or(false)
+    }
+
+    /// マスクの形の内側のステンシルを `op` に従って変える
+    ///
+    /// ステンシルの参照値が今の値と等しい画素だけを変える。頂点は [`MaskOp::Push`] のときに送る。
+    #[cfg(feature = "backend-wgpu")]
+    fn render(
+        &mut self,
+        op: MaskOp,
+        sprite: Option<&SpriteComponent>,
+        transform: &TransformComponent,
+        rp: &mut wgpu::RenderPass<'_>,
+        resource: &WgpuResource<'_>,
+    ) {
+        let Some((texture, vertices)) = self.with_quad(sprite, |quad| {
+            let vertices = (op == MaskOp::Push).then(|| {
+                quad.quad_vertices(&resource.texture_registry, transform)
+                    .unwrap_or_log()
+            });
+            (quad.texture(), vertices)
+        }) else {
+            return;
+        };
+        let buffer = self.buffer.get_or_insert_with(|| {
+            VertexIndexBuffer::new(
+                &resource.device,
+                4,
+                6,
+                Some("Mask Buffer"),
+                &resource.gpu_memory,
+                &resource.frames,
+            )
+            .unwrap_or_log()
+        });
+        if let Some(vertices) = vertices {
+            let mut update = buffer.start_update(&resource.queue, &resource.frames);
+            let v = update.vertex_mut();
+            v.clear();
+            v.extend_from_slice(&vertices);
+            update.set_vertex_update(0..4);
+            let i = update.index_mut();
+            i.clear();
+            i.extend([0, 3, 1, 0, 2, 3]);
+            update.set_index_update(0..6);
+            update.set_render_range(0..6);
+        }
+
+        let name = match op {
+            MaskOp::Push => "reverie mask push",
+            MaskOp::Pop => "reverie mask pop",
+        };
+        let pipeline = resource
+            .pipeline_cache
+            .get_or_create(name, |formats| mask_pipeline(resource, formats.color, op));
+        let bind_group = resource
+            .get_texture_bind_group(texture)
+            .context("texture not found for index")
+            .unwrap_or_log();
+        rp.set_pipeline(&pipeline);
+        rp.set_bind_group(0, bind_group, &[]);
+        rp.set_index_buffer(buffer.index_buffer().slice(..), wgpu::IndexFormat::Uint16);
+        rp.set_vertex_buffer(0, buffer.vertex_buffer().slice(..));
+        rp.draw_indexed(buffer.index_buffer_range.clone(), 0, 0..1);
+    }
+}
+
+/// `point` が `entity` の祖先の [`MaskComponent`] すべての内側にあるか
+///
+/// 入れ子になったマスクは、すべての内側が重なった部分だけが内側になる。
+/// `textures` は [`MaskComponent::contains`] と同じく、[`MaskShape::SpriteAlpha`] の不透明度を調べるのに使う。
+pub fn inside_ancestor_masks(
+    world: &hecs::World,
+    entity: EntityIndex,
+    point: &Point2<f32>,
+    textures: Option<&TextureRegistry>,
+) -> bool {
+    let mut current = entity;
+    while let Some(ancestor) = parent(world, current) {
+        current = ancestor;
+        let Ok(mut query) = world.query_one::<(
+            &MaskComponent,
+            &TransformComponent,
+            Option<&SpriteComponent>,
+        )>(ancestor.0) else {
+            continue;
+        };
+        let Some((mask, transform, sprite)) = query.get() else {
+            continue;
+        };
+        if !mask.contains(transform, sprite, point, textures) {
+            return false;
+        }
+    }
+    true
+}
+
+#[cfg(feature = "backend-wgpu")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// マスクをステンシルに描き込むか、描き込んだものを消すか
+enum MaskOp {
+    Push,
+    Pop,
+}
+
+/// マスクの形をステンシルだけに描くパイプラインを作る
+#[cfg(feature = "backend-wgpu")]
+fn mask_pipeline(
+    resource: &WgpuResource<'_>,
+    format: wgpu::TextureFormat,
+    op: MaskOp,
+) -> wgpu::RenderPipeline {
+    let device = &resource.device;
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Shader from mask.wgsl"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("../../mask.wgsl").into()),
+    });
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Mask Pipeline Layout"),
+        bind_group_layouts: &[
+            &resource.texture_bind_group_layout,
+            &resource.uniform_bind_group_layout,
+        ],
+        push_constant_ranges: &[],
+    });
+    let face = wgpu::StencilFaceState {
+        compare: wgpu::CompareFunction::Equal,
+        fail_op: wgpu::StencilOperation::Keep,
+        depth_fail_op: wgpu::StencilOperation::Keep,
+        pass_op: match op {
+            MaskOp::Push => wgpu::StencilOperation::IncrementClamp,
+            MaskOp::Pop => wgpu::StencilOperation::DecrementClamp,
+        },
+    };
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Mask Pipeline"),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: Some("vs_main"),
+            compilation_options: Default::default(),
+            buffers: &[UvVertex::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: Some("fs_main"),
+            compilation_options: Default::default(),
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::empty(),
+            })],
+        }),
+        // 反転したスプライトの形でも切り抜けるように、裏面も描く
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: WgpuResource::DEPTH_STENCIL_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Always,
+            stencil: wgpu::StencilState {
+                front: face,
+                back: face,
+                read_mask: 0xff,
+                write_mask: 0xff,
+            },
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
+
+#[cfg(feature = "backend-wgpu")]
+#[derive(Debug)]
+/// ステンシルに描き込んであるマスク
+///
+/// [`Self::enter`] で描くもの 1 つごとに祖先のマスクに合わせ、最後に [`Self::clear`] で消す。
+pub struct MaskStack {
+    /// マスクが 1 つも無ければ何もしない
+    enabled: bool,
+    /// 描き込んであるマスク。根に近いものから順
+    active: Vec<hecs::Entity>,
+    /// 次に描き込むマスク
+    chain: Vec<hecs::Entity>,
+}
+
+#[cfg(feature = "backend-wgpu")]
+impl MaskStack {
+    pub fn new(world: &hecs::World) -> Self {
+        Self {
+            enabled: world.query::<&MaskComponent>().iter().next().is_some(),
+            active: Vec::new(),
+            chain: Vec::new(),
+        }
+    }
+
+    /// `entity` を描く前に呼び、祖先のマスクの内側だけに描かれるようにする
+    pub fn enter(
+        &mut self,
+        world: &hecs::World,
+        entity: hecs::Entity,
+        rp: &mut wgpu::RenderPass<'_>,
+        resource: &WgpuResource<'_>,
+    ) {
+        if !self.enabled {
+            return;
+        }
+        self.chain.clear();
+        let mut current = EntityIndex(entity);
+        while let Some(parent) = parent(world, current) {
+            if world.satisfies::<&MaskComponent>(parent.0).unwrap_or(false) {
+                self.chain.push(parent.0);
+            }
+            current = parent;
+        }
+        self.chain.reverse();
+        self.apply(world, rp, resource);
+    }
+
+    /// 描き込んだマスクをすべて消す
+    pub fn clear(
+        &mut self,
+        world: &hecs::World,
+        rp: &mut wgpu::RenderPass<'_>,
+        resource: &WgpuResource<'_>,
+    ) {
+        self.chain.clear();
+        self.apply(world, rp, resource);
+    }
+
+    /// `active` のうち `chain` と違う部分を消し、`chain` の残りを描き込む
+    fn apply(
+        &mut self,
+        world: &hecs::World,
+        rp: &mut wgpu::RenderPass<'_>,
+        resource: &WgpuResource<'_>,
+    ) {
+        let steps = stencil_steps(&self.active, &self.chain);
+        if steps.is_empty() {
+            return;
+        }
+        for (op, mask, reference) in steps {
+            rp.set_stencil_reference(reference);
+            render_mask(world, mask, op, rp, resource);
+        }
+        rp.set_stencil_reference(self.chain.len() as u32);
+        rp.set_pipeline(&resource.render_pipeline);
+        std::mem::swap(&mut self.active, &mut self.chain);
+    }
+}
+
+/// 描き込んであるマスク `active` を `chain` に変えるために描くものと、そのときのステンシルの参照値
+///
+/// 共通する根の側はそのままにして、違う部分を深い方から消し、`chain` の残りを浅い方から描き込む。
+#[cfg(feature = "backend-wgpu")]
+fn stencil_steps<T: Copy + PartialEq>(active: &[T], chain: &[T]) -> Vec<(MaskOp, T, u32)> {
+    let common = active.iter().zip(chain).take_while(|(a, b)| a == b).count();
+    // 深さ d のマスクの内側は、ステンシルが d + 1 になっている
+    let pops = (common..active.len())
+        .rev()
+        .map(|depth| (MaskOp::Pop, active[depth], depth as u32 + 1));
+    let pushes = (common..chain.len()).map(|depth| (MaskOp::Push, chain[depth], depth as u32));
+    pops.chain(pushes).collect()
+}
+
+#[cfg(feature = "backend-wgpu")]
+fn render_mask(
+    world: &hecs::World,
+    entity: hecs::Entity,
+    op: MaskOp,
+    rp: &mut wgpu::RenderPass<'_>,
+    resource: &WgpuResource<'_>,
+) {
+    let (Ok(mut mask), Ok(transform)) = (
+        world.get::<&mut MaskComponent>(entity),
+        world.get::<&TransformComponent>(entity),
+    ) else {
+        return;
+    };
+    let sprite = world.get::<&SpriteComponent>(entity).ok();
+    mask.render(op, sprite.as_deref(), &transform, rp, resource);
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Translation3;
+
+    use super::*;
+    use crate::scene::set_parent;
+
+    /// `(x, y)` を中心とする `mask` を持つエンティティを作り、`parent` の子にする
+    fn spawn_mask(
+        world: &mut hecs::World,
+        parent: Option<EntityIndex>,
+        (x, y): (f32, f32),
+        mask: MaskComponent,
+        sprite: Option<SpriteComponent>,
+    ) -> EntityIndex {
+        let transform = TransformComponent::with_translation(Translation3::new(x, y, 0.0));
+        let entity = EntityIndex(world.spawn((transform, mask)));
+        if let Some(sprite) = sprite {
+            world.insert_one(entity.0, sprite).unwrap();
+        }
+        if let Some(parent) = parent {
+            set_parent(world, entity, parent).unwrap();
+        }
+        entity
+    }
+
+    fn spawn_child(world: &mut hecs::World, parent: EntityIndex) -> EntityIndex {
+        let child = EntityIndex(world.spawn((TransformComponent::default(),)));
+        set_parent(world, child, parent).unwrap();
+        child
+    }
+
+    fn inside(world: &hecs::World, entity: EntityIndex, x: f32, y: f32) -> bool {
+        inside_ancestor_masks(world, entity, &Point2::new(x, y), None)
+    }
+
+    #[test]
+    fn nested_rects_intersect() {
+        let mut world = hecs::World::new();
+        // x が -10 から 10 と 0 から 20、y はどちらも -5 から 5
+        let outer = spawn_mask(
+            &mut world,
+            None,
+            (0.0, 0.0),
+            MaskComponent::rect(20.0, 10.0),
+            None,
+        );
+        let inner = spawn_mask(
+            &mut world,
+            Some(outer),
+            (10.0, 0.0),
+            MaskComponent::rect(20.0, 10.0),
+            None,
+        );
+        let child = spawn_child(&mut world, inner);
+
+        assert!(inside(&world, child, 5.0, 0.0));
+        assert!(inside(&world, child, 9.0, 4.0));
+        // 片方の内側だけでは切り抜かれる
+        assert!(!inside(&world, child, -5.0, 0.0));
+        assert!(!inside(&world, child, 15.0, 0.0));
+        assert!(!inside(&world, child, 5.0, 6.0));
+        // 内側のマスク自身は外側のマスクだけで切り抜かれる
+        assert!(inside(&world, inner, -5.0, 0.0));
+        assert!(!inside(&world, inner, 15.0, 0.0));
+        // マスクの無い祖先は関係しない
+        assert!(inside(&world, outer, 100.0, 100.0));
+    }
+
+    #[test]
+    fn disjoint_rects_leave_nothing_inside() {
+        let mut world = hecs::World::new();
+        let left = spawn_mask(
+            &mut world,
+            None,
+            (-10.0, 0.0),
+            MaskComponent::rect(10.0, 10.0),
+            None,
+        );
+        let right = spawn_mask(
+            &mut world,
+            Some(left),
+            (10.0, 0.0),
+            MaskComponent::rect(10.0, 10.0),
+            None,
+        );
+        let child = spawn_child(&mut world, right);
+
+        for x in (-20..=20).map(|x| x as f32) {
+            for y in [-4.0, 0.0, 4.0] {
+                assert!(!inside(&world, child, x, y), "({x}, {y})");
+            }
+        }
+    }
+
+    #[test]
+    fn sprite_alpha_mask_combines_with_rect() {
+        let mut textures = TextureRegistry::default();
+        // 左半分が透明、右半分が不透明
+        let image = image::RgbaImage::from_fn(4, 4, |x, _| {
+            image::Rgba([255, 255, 255, if x < 2 { 0 } else { 255 }])
+        });
+        let texture = textures.new_texture(image, None);
+        let mut world = hecs::World::new();
+        // x が -10 から 10 のうち、不透明なのは 0 から 10
+        let minimap = spawn_mask(
+            &mut world,
+            None,
+            (0.0, 0.0),
+            MaskComponent::sprite_alpha(),
+            Some(
+                SpriteComponent::builder(texture.into())
+                    .size(20.0, 20.0)
+                    .build(),
+            ),
+        );
+        // x が -6 から 6 の枠
+        let frame = spawn_mask(
+            &mut world,
+            Some(minimap),
+            (0.0, 0.0),
+            MaskComponent::rect(12.0, 12.0),
+            None,
+        );
+        let marker = spawn_child(&mut world, frame);
+        let inside = |x: f32, textures| {
+            inside_ancestor_masks(&world, marker, &Point2::new(x, 0.0), textures)
+        };
+
+        assert!(inside(3.0, Some(&textures)));
+        // 枠の内側でも透明な部分は切り抜かれる
+        assert!(!inside(-3.0, Some(&textures)));
+        // 不透明な部分でも枠の外は切り抜かれる
+        assert!(!inside(8.0, Some(&textures)));
+        // CPU 上の画像を調べなければ、スプライトの四角形を内側とする
+        assert!(inside(-3.0, None));
+        assert!(!inside(8.0, None));
+
+        // スプライトが無ければ内側は無い
+        world.remove_one::<SpriteComponent>(minimap.0).unwrap();
+        let point = Point2::new(3.0, 0.0);
+        assert!(!inside_ancestor_masks(
+            &world,
+            marker,
+            &point,
+            Some(&textures)
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "backend-wgpu")]
+    fn stencil_steps_keep_the_common_root() {
+        use MaskOp::{Pop, Push};
+
+        assert_eq!(
+            stencil_steps(&[], &['a', 'b']),
+            [(Push, 'a', 0), (Push, 'b', 1)]
+        );
+        assert_eq!(stencil_steps(&['a', 'b'], &['a', 'b']), []);
+        // 兄弟のマスクに移るときは、共通の親を描き直さない
+        assert_eq!(
+            stencil_steps(&['a', 'b', 'c'], &['a', 'd']),
+            [(Pop, 'c', 3), (Pop, 'b', 2), (Push, 'd', 1)]
+        );
+        assert_eq!(
+            stencil_steps(&['a', 'b'], &[]),
+            [(Pop, 'b', 2), (Pop, 'a', 1)]
+        );
+    }
+}
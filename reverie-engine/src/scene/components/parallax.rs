@@ -0,0 +1,113 @@
+//! カメラと違う速さで動いて見える背景や前景
+use std::ops::RangeInclusive;
+
+use nalgebra::{Point2, Point3, Vector2, Vector3};
+
+/// 横に並べるタイルの数の上限。ズームアウトしすぎても頂点が増えすぎないようにする
+pub const MAX_PARALLAX_TILES: i32 = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// カメラが動いたとき、`factor` の割合で画面上を動くように描くスプライトにつけるコンポーネント
+///
+/// 1.0 ならほかのスプライトと同じ、0.5 なら半分しか動かない遠くの背景、0.0 ならカメラに付いてくる。
+/// 1.0 より大きいと、ほかより速く流れる手前の前景になる。
+/// 描くときだけ [`super::transform::TransformComponent`] の位置をカメラの位置の `1 - factor` 倍ずらし、
+/// 位置そのものは変えない。画面の座標から当たりを調べるときは
+/// [`super::camera::CameraComponent::screen_to_layer`] でこの層の座標に直す。
+///
+/// `repeat_x` なら、スプライトをその幅ごとに横に並べて、写る範囲をどのズームでも埋める。
+/// 並べたものは描くだけで、当たりは元の位置のものしか無い。
+/// [`crate::scene::WorldWrap`] の継ぎ目の反対側には描かない。
+pub struct ParallaxLayerComponent {
+    pub factor: Vector2<f32>,
+    pub repeat_x: bool,
+}
+
+impl ParallaxLayerComponent {
+    pub const fn new(factor_x: f32, factor_y: f32) -> Self {
+        Self {
+            factor: Vector2::new(factor_x, factor_y),
+            repeat_x: false,
+        }
+    }
+
+    /// 横に並べて写る範囲を埋める
+    pub const fn repeat_x(mut self) -> Self {
+        self.repeat_x = true;
+        self
+    }
+
+    /// カメラが `camera` にあるとき、描く位置をずらす量
+    pub fn offset(&self, camera: &Point2<f32>) -> Vector2<f32> {
+        camera
+            .coords
+            .component_mul(&(Vector2::repeat(1.0) - self.factor))
+    }
+
+    /// カメラが `camera` にあるとき、ワールド座標の `world` に描かれているこの層の座標
+    pub fn world_to_layer(&self, camera: &Point2<f32>, world: &Point3<f32>) -> Point3<f32> {
+        let offset = self.offset(camera);
+        world - Vector3::new(offset.x, offset.y, 0.0)
+    }
+}
+
+/// x 座標が `min_x..=max_x` のスプライトを `period` ごとに並べたとき、`visible_min..=visible_max` に
+/// かかるものが何個目か。元の位置が 0
+///
+/// 多くても [`MAX_PARALLAX_TILES`] 個までにする。幅が無ければ元の位置だけ。
+pub fn tile_range(
+    (min_x, max_x): (f32, f32),
+    period: f32,
+    (visible_min, visible_max): (f32, f32),
+) -> RangeInclusive<i32> {
+    if period <= f32::EPSILON {
+        return 0..=0;
+    }
+    let first = ((visible_min - max_x) / period).ceil();
+    let last = ((visible_max - min_x) / period).floor();
+    if !(first.is_finite() && last.is_finite()) {
+        return 0..=0;
+    }
+    let first = first.clamp(i32::MIN as f32, i32::MAX as f32) as i32;
+    let last = last.clamp(i32::MIN as f32, i32::MAX as f32) as i32;
+    first..=last.min(first.saturating_add(MAX_PARALLAX_TILES - 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offset_follows_camera_by_factor() {
+        let camera = Point2::new(100.0, -40.0);
+        let background = ParallaxLayerComponent::new(0.5, 1.0);
+        assert_eq!(background.offset(&camera), Vector2::new(50.0, 0.0));
+        // 前景は逆向きにずれて、カメラより速く流れる
+        let foreground = ParallaxLayerComponent::new(1.5, 1.5);
+        assert_eq!(foreground.offset(&camera), Vector2::new(-50.0, 20.0));
+
+        let world = Point3::new(120.0, 10.0, 3.0);
+        let layer = background.world_to_layer(&camera, &world);
+        assert_eq!(layer, Point3::new(70.0, 10.0, 3.0));
+    }
+
+    #[test]
+    fn tiles_cover_visible_range() {
+        // 幅 100 のスプライトを -250..=250 に並べる
+        let range = tile_range((0.0, 100.0), 100.0, (-250.0, 250.0));
+        assert_eq!(range, -3..=2);
+        let covered = (
+            *range.start() as f32 * 100.0,
+            (*range.end() as f32 + 1.0) * 100.0,
+        );
+        assert!(covered.0 <= -250.0 && covered.1 >= 250.0);
+
+        // 写る範囲の外にしか無くても、並べれば届く
+        assert_eq!(tile_range((1000.0, 1100.0), 100.0, (10.0, 50.0)), -10..=-10);
+        // 幅が無ければ並べない
+        assert_eq!(tile_range((0.0, 0.0), 0.0, (-10.0, 10.0)), 0..=0);
+        // 大きくズームアウトしても数は抑える
+        let range = tile_range((0.0, 1.0), 1.0, (-1e6, 1e6));
+        assert_eq!(range.count(), MAX_PARALLAX_TILES as usize);
+    }
+}
@@ -0,0 +1,52 @@
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+/// 描画する層。小さい層から順に描画し、同じ層の中では作った順に描画する
+///
+/// 層の中の並べ方はリソース [`LayerSortModes`] で変えられる。
+/// 付けていないエンティティは層 0 になる。層の間では
+/// [`crate::scene::RenderStage::BetweenLayers`] でシステムが描画できる。
+pub struct RenderLayerComponent(pub i32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+/// 層の中での並べ方
+pub enum LayerSortMode {
+    /// 作った順に描画する
+    #[default]
+    Insertion,
+    /// スプライトを [`TransformComponent::translation`](crate::scene::TransformComponent::translation) の Y 座標と
+    /// [`SpriteComponent::sort_offset`](crate::scene::SpriteComponent::sort_offset) の和の小さい順に、毎フレーム並べ直して描画する
+    ///
+    /// 見下ろし視点のゲームで、キャラクターが木の前後に回り込むのに使う。
+    /// Y 座標が同じものは作った順になる。
+    Y,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+/// 層ごとの並べ方
+///
+/// リソースとして [`super::super::Scene::insert_resource`] で追加する。
+/// 無いか、指定していない層は [`LayerSortMode::Insertion`] になる。
+pub struct LayerSortModes {
+    modes: BTreeMap<i32, LayerSortMode>,
+}
+
+impl LayerSortModes {
+    pub fn set(&mut self, layer: i32, mode: LayerSortMode) {
+        if mode == LayerSortMode::Insertion {
+            self.modes.remove(&layer);
+        } else {
+            self.modes.insert(layer, mode);
+        }
+    }
+
+    /// 層 `layer` の並べ方
+    pub fn get(&self, layer: i32) -> LayerSortMode {
+        self.modes.get(&layer).copied().unwrap_or_default()
+    }
+
+    /// Y 座標で並べる層があるか
+    pub fn any_y(&self) -> bool {
+        self.modes.values().any(|&mode| mode == LayerSortMode::Y)
+    }
+}
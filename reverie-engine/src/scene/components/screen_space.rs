@@ -0,0 +1,5 @@
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+/// カメラに関係なく、ウィンドウのピクセル座標で描画するエンティティにつける目印
+///
+/// HUD などの UI に使う。ワールドのスプライトを描いた後に重ねて描画される。
+pub struct ScreenSpaceComponent;
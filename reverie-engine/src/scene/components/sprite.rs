@@ -1,79 +1,453 @@
+#[cfg(feature = "backend-wgpu")]
 use anyhow::Context;
-use nalgebra::{Matrix4, Point3};
+use nalgebra::{Matrix4, Point2, Point3, Scale3, Vector2, Vector3};
+use reverie_util::{color::Color, math::Rect};
+#[cfg(feature = "backend-wgpu")]
 use tracing_unwrap::ResultExt;
+#[cfg(feature = "backend-wgpu")]
+use wgpu::util::DeviceExt;
 
+#[cfg(feature = "backend-wgpu")]
 use crate::{
-    scene::TransformComponent,
+    scene::components::camera::PixelGrid,
+    texture::TextureRegistry,
+    wgpu_wrapper::{
+        buffer::VertexIndexBuffer,
+        vertex::{UvScrollInstance, UvVertex},
+        WgpuResource,
+    },
+};
+use crate::{
+    scene::{RenderLayerComponent, TransformComponent},
     texture::TextureId,
-    wgpu_wrapper::{buffer::VertexIndexBuffer, vertex::UvVertex, WgpuResource},
 };
 
 #[derive(Debug)]
 /// エンティティの見た目を表すコンポーネント
+///
+/// [`Self::builder`] で大きさや描画する範囲を指定して作れる。
 pub struct SpriteComponent {
     texture: TextureId,
+    tint: Color,
+    uv_rect: Rect,
+    size: Vector2<f32>,
+    sort_offset: f32,
+    corner_offsets: [[f32; 2]; 4],
+    uv_scroll: Vector2<f32>,
+    uv_rotation: f32,
+    pixel_snap: Option<bool>,
+    nine_slice: Option<NineSlice>,
+    #[cfg(feature = "backend-wgpu")]
     buffer: Option<VertexIndexBuffer>,
+    #[cfg(feature = "backend-wgpu")]
+    scroll_buffer: Option<(wgpu::Buffer, UvScrollInstance)>,
+    /// [`Self::prepare`] で求めておいた頂点
+    #[cfg(feature = "backend-wgpu")]
+    prepared: Option<[UvVertex; 4]>,
+    /// 次の [`Self::render`] で描く位置のずれ。空なら本来の位置に 1 つだけ描く
+    ///
+    /// [`crate::scene::WorldWrap`] の継ぎ目をまたぐスプライトを両側に描くのと、
+    /// [`crate::scene::ParallaxLayerComponent`] の層をずらして並べるのに使う。
+    #[cfg(feature = "backend-wgpu")]
+    shifts: Vec<Vector2<f32>>,
 }
 
 impl SpriteComponent {
+    /// テクスチャ全体を 1x1 の大きさで描画するスプライトを作る
     pub const fn new(texture: TextureId) -> Self {
         Self {
             texture,
+            tint: Color::WHITE,
+            uv_rect: FULL_UV_RECT,
+            size: Vector2::new(1.0, 1.0),
+            sort_offset: 0.0,
+            corner_offsets: [[0.0; 2]; 4],
+            uv_scroll: Vector2::new(0.0, 0.0),
+            uv_rotation: 0.0,
+            pixel_snap: None,
+            nine_slice: None,
+            #[cfg(feature = "backend-wgpu")]
             buffer: None,
+            #[cfg(feature = "backend-wgpu")]
+            scroll_buffer: None,
+            #[cfg(feature = "backend-wgpu")]
+            prepared: None,
+            #[cfg(feature = "backend-wgpu")]
+            shifts: Vec::new(),
+        }
+    }
+
+    /// 設定を順に指定してスプライトを作る
+    ///
+    /// ```ignore
+    /// let sprite = SpriteComponent::builder(texture)
+    ///     .size(2.0, 1.0)
+    ///     .tint(Color::rgb(1.0, 0.0, 0.0))
+    ///     .build();
+    /// ```
+    pub const fn builder(texture: TextureId) -> SpriteBuilder {
+        SpriteBuilder {
+            sprite: Self::new(texture),
+            layer: None,
+        }
+    }
+
+    /// テクスチャの色に`tint`を掛けて描画する
+    pub const fn with_tint(mut self, tint: Color) -> Self {
+        self.tint = tint;
+        self
+    }
+
+    /// テクスチャの色に掛ける色。既定は白 (そのままの色)
+    pub const fn tint(&self) -> Color {
+        self.tint
+    }
+
+    pub fn set_tint(&mut self, tint: Color) {
+        self.tint = tint;
+    }
+
+    /// テクスチャを取得する
+    pub const fn texture(&self) -> TextureId {
+        self.texture
+    }
+
+    /// テクスチャのうち描画する範囲。テクスチャ全体を 0.0 から 1.0 とする
+    pub const fn uv_rect(&self) -> Rect {
+        self.uv_rect
+    }
+
+    pub fn set_uv_rect(&mut self, uv_rect: Rect) {
+        self.uv_rect = uv_rect;
+    }
+
+    /// [`TransformComponent::scale`] を掛ける前の大きさ。既定は 1x1
+    pub const fn size(&self) -> Vector2<f32> {
+        self.size
+    }
+
+    pub fn set_size(&mut self, size: Vector2<f32>) {
+        self.size = size;
+    }
+
+    /// Y 座標で並べる層で、位置の Y 座標に足して並べる値。既定は 0
+    ///
+    /// スプライトの中心ではなく足元で前後を決めるときは、足元までの距離を指定する。
+    /// [`crate::scene::LayerSortMode::Y`] を参照。
+    pub const fn sort_offset(&self) -> f32 {
+        self.sort_offset
+    }
+
+    pub fn set_sort_offset(&mut self, sort_offset: f32) {
+        self.sort_offset = sort_offset;
+    }
+
+    /// 四隅の頂点をずらす量。左上、右上、左下、右下の順。既定はすべて 0
+    ///
+    /// [`TransformComponent`] を掛けた後のワールド座標に足すので、回転や拡大縮小の影響を受けない。
+    /// 伸び縮みやカードをめくる演出に使う。[`Self::hit_uv`] はずらす前の四角形で判定する。
+    pub const fn corner_offsets(&self) -> [[f32; 2]; 4] {
+        self.corner_offsets
+    }
+
+    pub fn set_corner_offsets(&mut self, corner_offsets: [[f32; 2]; 4]) {
+        self.corner_offsets = corner_offsets;
+    }
+
+    /// 四隅をずらして平行四辺形に傾ける
+    ///
+    /// 上の辺を下の辺より `x` だけ右に、右の辺を左の辺より `y` だけ下にずらす。
+    /// 中心は動かない。[`Self::set_corner_offsets`] で指定したずれは上書きされる。
+    pub fn set_skew(&mut self, x: f32, y: f32) {
+        self.corner_offsets = skew_offsets(x, y);
+    }
+
+    /// 1 秒あたりに動かす UV。テクスチャ全体を 1.0 とする。既定は 0
+    ///
+    /// 流れる雲やベルトコンベアに使う。UV は [`crate::scene::SceneClock`] の時刻からシェーダーで求めるので、
+    /// フレームごとに CPU で計算しない。はみ出した UV はテクスチャの範囲の中で折り返すので、
+    /// アトラスに入ったテクスチャにも使える。ただし線形補間では折り返す所にアトラスの隣の画素が混ざる。
+    pub const fn uv_scroll(&self) -> Vector2<f32> {
+        self.uv_scroll
+    }
+
+    pub fn set_uv_scroll(&mut self, uv_scroll: Vector2<f32>) {
+        self.uv_scroll = uv_scroll;
+    }
+
+    /// 1 秒あたりに UV を回す角度 (ラジアン)。テクスチャの中心を軸に回す。既定は 0
+    pub const fn uv_rotation(&self) -> f32 {
+        self.uv_rotation
+    }
+
+    pub fn set_uv_rotation(&mut self, uv_rotation: f32) {
+        self.uv_rotation = uv_rotation;
+    }
+
+    /// 描画先のピクセルに位置を合わせるか。`None` (既定) なら [`crate::scene::ScreenSpaceComponent`] を持つときだけ合わせる
+    ///
+    /// カメラの変換の後の左上の角を最も近い物理ピクセルの境目に動かす。動かすのは位置だけで、
+    /// 回転と拡大縮小は変えない。小数のピクセルだけ動かしたときに縁がにじんで揺れるのを防ぐ。
+    /// [`crate::scene::CameraComponent::pixel_snap`] とは別に、スプライトごとに効く。
+    pub const fn pixel_snap(&self) -> Option<bool> {
+        self.pixel_snap
+    }
+
+    pub fn set_pixel_snap(&mut self, pixel_snap: bool) {
+        self.pixel_snap = Some(pixel_snap);
+    }
+
+    /// 9 分割して描画するときの縁の幅。`None` (既定) なら分割しない
+    ///
+    /// 四隅はテクスチャのまま、辺は一方向だけ、中央は両方向に伸ばして描く。ウィンドウの枠やボタンに使う。
+    /// [`Self::hit_uv`] は分割しない四角形として UV を返す。
+    pub const fn nine_slice(&self) -> Option<NineSlice> {
+        self.nine_slice
+    }
+
+    pub fn set_nine_slice(&mut self, nine_slice: Option<NineSlice>) {
+        self.nine_slice = nine_slice;
+    }
+
+    /// [`Self::pixel_snap`] に従い、ピクセルに合わせるか
+    #[cfg(feature = "backend-wgpu")]
+    pub(crate) fn snaps(&self, screen_space: bool) -> bool {
+        self.pixel_snap.unwrap_or(screen_space)
+    }
+
+    /// UV を時間で動かすか
+    pub fn is_scrolling(&self) -> bool {
+        self.uv_scroll != Vector2::zeros() || self.uv_rotation != 0.0
+    }
+
+    /// Y 座標で並べるときの位置を求める
+    #[cfg(feature = "backend-wgpu")]
+    pub(crate) fn sort_y(&self, transform: &TransformComponent) -> f32 {
+        transform.translation.y + self.sort_offset
+    }
+
+    /// スプライトの四隅の頂点を計算する
+    ///
+    /// 左上、右上、左下、右下の順に返す。GPU には触れないので、CPU 側の準備処理だけを行う。
+    #[cfg(feature = "backend-wgpu")]
+    pub fn quad_vertices(
+        &self,
+        registry: &TextureRegistry,
+        transform: &TransformComponent,
+    ) -> anyhow::Result<[UvVertex; 4]> {
+        let (min_u, min_v, max_u, max_v) = registry.get_uv(registry.resolve(self.texture))?;
+        // テクスチャの範囲のうち uv_rect の部分を使う
+        let uv = |p: Point2<f32>| {
+            [
+                (max_u - min_u).mul_add(p.x, min_u),
+                (max_v - min_v).mul_add(p.y, min_v),
+            ]
+        };
+        let [min_u, min_v] = uv(self.uv_rect.min);
+        let [max_u, max_v] = uv(self.uv_rect.max);
+        let [top_left, top_right, bottom_left, bottom_right] = self.corners(transform);
+        let color = self.tint.into();
+
+        Ok([
+            UvVertex {
+                position: top_left.into(),
+                uv: [min_u, min_v],
+                color,
+            },
+            UvVertex {
+                position: top_right.into(),
+                uv: [max_u, min_v],
+                color,
+            },
+            UvVertex {
+                position: bottom_left.into(),
+                uv: [min_u, max_v],
+                color,
+            },
+            UvVertex {
+                position: bottom_right.into(),
+                uv: [max_u, max_v],
+                color,
+            },
+        ])
+    }
+
+    /// 次の [`Self::render`] で使う頂点を求めておく
+    ///
+    /// 描画するスプライトを選ぶときに、ほかのスプライトと並列に呼ばれる。
+    /// `snap` があれば、左上の角がピクセルの境目に来るように四隅を同じだけ動かす。
+    #[cfg(feature = "backend-wgpu")]
+    pub(crate) fn prepare(
+        &mut self,
+        registry: &TextureRegistry,
+        transform: &TransformComponent,
+        snap: Option<&PixelGrid>,
+    ) {
+        self.prepared = self
+            .quad_vertices(registry, transform)
+            .ok()
+            .map(|mut vertices| {
+                if let Some(grid) = snap {
+                    let offset = grid.snap_offset(&vertices[0].position.into());
+                    for vertex in &mut vertices {
+                        vertex.position = (Vector3::from(vertex.position) + offset).into();
+                    }
+                }
+                vertices
+            });
+    }
+
+    /// 次の [`Self::render`] で、`shifts` だけずらした位置それぞれに描く
+    #[cfg(feature = "backend-wgpu")]
+    pub(crate) fn set_shifts(&mut self, shifts: impl IntoIterator<Item = Vector2<f32>>) {
+        self.shifts.clear();
+        self.shifts.extend(shifts);
+        if self.shifts == [Vector2::zeros()] {
+            self.shifts.clear();
+        }
+    }
+
+    /// [`Self::prepare`] で求めた頂点
+    #[cfg(all(test, feature = "parallel"))]
+    pub(crate) const fn prepared(&self) -> Option<&[UvVertex; 4]> {
+        self.prepared.as_ref()
+    }
+
+    /// [`Self::render`] で描く四角形それぞれの頂点。[`Self::prepare`] の後に呼ぶ
+    #[cfg(all(feature = "backend-wgpu", feature = "mesh-export"))]
+    pub(crate) fn batched_quads(&self) -> Vec<[UvVertex; 4]> {
+        let Some(vertices) = &self.prepared else {
+            return Vec::new();
+        };
+        let placed = if self.shifts.is_empty() {
+            vec![*vertices]
+        } else {
+            self.shifts
+                .iter()
+                .map(|shift| shifted(vertices, shift))
+                .collect()
+        };
+        match &self.nine_slice {
+            Some(slice) => placed.iter().flat_map(|quad| slice.split(quad)).collect(),
+            None => placed,
+        }
+    }
+
+    /// 四隅のワールド座標。左上、右上、左下、右下の順
+    fn corners(&self, transform: &TransformComponent) -> [Point3<f32>; 4] {
+        const POINTS: Matrix4<f32> = Matrix4::new(
+            -0.5, 0.5, -0.5, 0.5, //
+            -0.5, -0.5, 0.5, 0.5, //
+            0.0, 0.0, 0.0, 0.0, //
+            1.0, 1.0, 1.0, 1.0, //
+        );
+        let size = Scale3::new(self.size.x, self.size.y, 1.0).to_homogeneous();
+        let points = transform.to_affine3().matrix() * size * POINTS;
+        let corners =
+            [0, 1, 2, 3].map(|i| Point3::from_homogeneous(points.column(i).into()).unwrap());
+        // ずらさないスプライトでは足し算を省く
+        if self.corner_offsets == [[0.0; 2]; 4] {
+            return corners;
+        }
+        [0, 1, 2, 3].map(|i| {
+            let [dx, dy] = self.corner_offsets[i];
+            corners[i] + Vector3::new(dx, dy, 0.0)
+        })
+    }
+
+    /// ワールド座標の点 `point` がスプライトの上にあれば、その位置のテクスチャ座標を返す
+    ///
+    /// 回転、拡大縮小、負の拡大率による反転を考える。z 座標は無視して xy 平面で判定する。
+    /// テクスチャ座標は [`Self::uv_rect`] の中の位置で、アトラスの中の位置ではない。
+    pub fn hit_uv(
+        &self,
+        transform: &TransformComponent,
+        point: &Point2<f32>,
+    ) -> Option<Point2<f32>> {
+        let m = transform.to_affine3().matrix()
+            * Scale3::new(self.size.x, self.size.y, 1.0).to_homogeneous();
+        // スプライトの中心と、横と縦の辺のベクトル
+        let origin = Vector2::new(m[(0, 3)], m[(1, 3)]);
+        let axes = nalgebra::Matrix2::new(m[(0, 0)], m[(0, 1)], m[(1, 0)], m[(1, 1)]);
+        let local = axes.try_inverse()? * (point.coords - origin);
+        if local.x.abs() > 0.5 || local.y.abs() > 0.5 {
+            return None;
         }
+        let t = local.add_scalar(0.5);
+        let (min, max) = (self.uv_rect.min, self.uv_rect.max);
+        Some(min + (max - min).component_mul(&t))
+    }
+
+    /// ワールド座標での、座標軸に沿った外接箱の最小点と最大点
+    ///
+    /// テクスチャには触れないので、GPU の準備前でも使える。
+    pub fn world_aabb(&self, transform: &TransformComponent) -> (Point3<f32>, Point3<f32>) {
+        let corners = self.corners(transform);
+        corners[1..]
+            .iter()
+            .fold((corners[0], corners[0]), |(min, max), p| {
+                (min.inf(p), max.sup(p))
+            })
     }
 
+    #[cfg(feature = "backend-wgpu")]
     pub(crate) fn setup(&mut self, resource: &WgpuResource<'_>) {
-        let buffer = VertexIndexBuffer::new(&resource.device, 4, 6, None).unwrap_or_log();
+        let buffer = VertexIndexBuffer::new(
+            &resource.device,
+            4,
+            6,
+            Some("Sprite Buffer"),
+            &resource.gpu_memory,
+            &resource.frames,
+        )
+        .unwrap_or_log();
         self.buffer = Some(buffer);
     }
 
+    #[cfg(feature = "backend-wgpu")]
     pub(crate) fn render(
         &mut self,
         rp: &mut wgpu::RenderPass<'_>,
         resource: &WgpuResource<'_>,
         transform: &TransformComponent,
     ) {
+        let vertices = self.prepared.take().unwrap_or_else(|| {
+            self.quad_vertices(&resource.texture_registry, transform)
+                .unwrap_or_log()
+        });
+        // Scene::setup の後に作られたスプライトはここで準備する
+        if self.buffer.is_none() {
+            self.setup(resource);
+        }
+        let scrolling = self.is_scrolling();
+        let shifts = std::mem::take(&mut self.shifts);
+        let nine_slice = self.nine_slice;
         if let Some(buffer) = &mut self.buffer {
+            let quads = shifts.len().max(1) * if nine_slice.is_some() { 9 } else { 1 };
+            buffer.reserve(
+                &resource.device,
+                &resource.gpu_memory,
+                &resource.frames,
+                4 * quads,
+                6 * quads,
+            );
             // バッファのアップデート
             {
-                let mut update = buffer.start_update(&resource.queue);
-                let (min_u, min_v, max_u, max_v) = resource
-                    .texture_registry
-                    .get_uv(self.texture)
-                    .unwrap_or_log();
-                let affine = transform.to_affine3();
-                const POINTS: Matrix4<f32> = Matrix4::new(
-                    -0.5, 0.5, -0.5, 0.5, //
-                    -0.5, -0.5, 0.5, 0.5, //
-                    0.0, 0.0, 0.0, 0.0, //
-                    1.0, 1.0, 1.0, 1.0, //
-                );
-                let points = affine.matrix() * POINTS;
-                let top_left = Point3::from_homogeneous(points.column(0).into()).unwrap();
-                let top_right = Point3::from_homogeneous(points.column(1).into()).unwrap();
-                let bottom_left = Point3::from_homogeneous(points.column(2).into()).unwrap();
-                let bottom_right = Point3::from_homogeneous(points.column(3).into()).unwrap();
+                let mut update = buffer.start_update(&resource.queue, &resource.frames);
 
                 let range = {
                     let v = update.vertex_mut();
                     v.clear();
-                    v.push(UvVertex {
-                        position: top_left.into(),
-                        uv: [min_u, min_v],
-                    });
-                    v.push(UvVertex {
-                        position: top_right.into(),
-                        uv: [max_u, min_v],
-                    });
-                    v.push(UvVertex {
-                        position: bottom_left.into(),
-                        uv: [min_u, max_v],
-                    });
-                    v.push(UvVertex {
-                        position: bottom_right.into(),
-                        uv: [max_u, max_v],
-                    });
+                    let mut push = |quad: &[UvVertex; 4]| match &nine_slice {
+                        Some(slice) => v.extend(slice.split(quad).iter().flatten()),
+                        None => v.extend_from_slice(quad),
+                    };
+                    if shifts.is_empty() {
+                        push(&vertices);
+                    }
+                    for shift in &shifts {
+                        push(&shifted(&vertices, shift));
+                    }
                     0..v.len()
                 };
                 update.set_vertex_update(range);
@@ -81,23 +455,418 @@ impl SpriteComponent {
                 let range = {
                     let i = update.index_mut();
                     i.clear();
-                    i.extend_from_slice(&[0, 3, 1, 0, 2, 3]);
+                    for quad in 0..quads as u16 {
+                        i.extend([0, 3, 1, 0, 2, 3].map(|index| index + 4 * quad));
+                    }
                     0..i.len()
                 };
                 update.set_index_update(range.clone());
                 update.set_render_range(range.start as u32..range.end as u32);
             }
+            // 次のフレームのために領域を残しておく
+            let mut shifts = shifts;
+            shifts.clear();
+            self.shifts = shifts;
 
             let bind_group = resource
                 .get_texture_bind_group(self.texture)
                 .context("texture not found for index")
                 .unwrap_or_log();
             rp.set_bind_group(0, bind_group, &[]);
-            rp.set_index_buffer(buffer.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-            rp.set_vertex_buffer(0, buffer.vertex_buffer.slice(..));
+            rp.set_index_buffer(buffer.index_buffer().slice(..), wgpu::IndexFormat::Uint16);
+            rp.set_vertex_buffer(0, buffer.vertex_buffer().slice(..));
+            if !scrolling {
+                rp.draw_indexed(buffer.index_buffer_range.clone(), 0, 0..1);
+                return;
+            }
+
+            let instance = UvScrollInstance {
+                rect: resource
+                    .texture_registry
+                    .get_uv(resource.texture_registry.resolve(self.texture))
+                    .unwrap_or_log()
+                    .into(),
+                velocity: self.uv_scroll.into(),
+                rotation: self.uv_rotation,
+                _pad: 0.0,
+            };
+            // 設定が変わったときだけ書き込む
+            match &mut self.scroll_buffer {
+                Some((scroll, last)) if *last != instance => {
+                    resource
+                        .queue
+                        .write_buffer(scroll, 0, bytemuck::bytes_of(&instance));
+                    *last = instance;
+                }
+                Some(_) => {}
+                None => {
+                    let scroll =
+                        resource
+                            .device
+                            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                                label: Some("Sprite UV Scroll"),
+                                contents: bytemuck::bytes_of(&instance),
+                                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                            });
+                    self.scroll_buffer = Some((scroll, instance));
+                }
+            }
+            let pipeline = resource
+                .pipeline_cache
+                .get_or_create("reverie uv scroll", |formats| {
+                    uv_scroll_pipeline(resource, formats.color)
+                });
+            rp.set_pipeline(&pipeline);
+            rp.set_bind_group(2, resource.globals.bind_group(), &[]);
+            if let Some((scroll, _)) = &self.scroll_buffer {
+                rp.set_vertex_buffer(1, scroll.slice(..));
+            }
             rp.draw_indexed(buffer.index_buffer_range.clone(), 0, 0..1);
+            rp.set_pipeline(&resource.render_pipeline);
         } else {
             tracing::warn!("buffer is not initialized");
         }
     }
 }
+
+/// 四角形の頂点を `shift` だけずらす
+#[cfg(feature = "backend-wgpu")]
+fn shifted(vertices: &[UvVertex; 4], shift: &Vector2<f32>) -> [UvVertex; 4] {
+    vertices.map(|vertex| {
+        let [x, y, z] = vertex.position;
+        UvVertex {
+            position: [x + shift.x, y + shift.y, z],
+            ..vertex
+        }
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// スプライトを 9 分割して描画するときの縁の幅。[`SpriteComponent::nine_slice`] を参照
+///
+/// 幅は左、上、右、下の順に並べる。
+pub struct NineSlice {
+    /// テクスチャの縁の幅。[`SpriteComponent::uv_rect`] の幅と高さに対する割合
+    pub uv_border: [f32; 4],
+    /// 描画する縁の幅。[`TransformComponent`] を掛けた後のワールド座標での長さ
+    ///
+    /// スプライトが縁の合計より小さいときは、縁を同じ比率で縮める。
+    pub border: [f32; 4],
+}
+
+impl NineSlice {
+    pub const fn new(uv_border: [f32; 4], border: [f32; 4]) -> Self {
+        Self { uv_border, border }
+    }
+
+    /// 四角形を 9 つに分ける。左上から行ごとに並べる
+    #[cfg(feature = "backend-wgpu")]
+    fn split(&self, quad: &[UvVertex; 4]) -> [[UvVertex; 4]; 9] {
+        let corners = quad.map(|vertex| Vector3::from(vertex.position));
+        let [left, top, right, bottom] = self.border;
+        let xs = border_fractions(left, right, (corners[1] - corners[0]).norm());
+        let ys = border_fractions(top, bottom, (corners[2] - corners[0]).norm());
+        let [left, top, right, bottom] = self.uv_border;
+        let us = [0.0, left, 1.0 - right, 1.0];
+        let vs = [0.0, top, 1.0 - bottom, 1.0];
+        let (min_uv, max_uv) = (quad[0].uv, quad[3].uv);
+        let vertex = |i: usize, j: usize| {
+            // 四隅をずらしたスプライトでも形に沿うように、四隅から双線形に補間する
+            let upper = corners[0].lerp(&corners[1], xs[i]);
+            let lower = corners[2].lerp(&corners[3], xs[i]);
+            UvVertex {
+                position: upper.lerp(&lower, ys[j]).into(),
+                uv: [
+                    (max_uv[0] - min_uv[0]).mul_add(us[i], min_uv[0]),
+                    (max_uv[1] - min_uv[1]).mul_add(vs[j], min_uv[1]),
+                ],
+                color: quad[0].color,
+            }
+        };
+        std::array::from_fn(|k| {
+            let (i, j) = (k % 3, k / 3);
+            [
+                vertex(i, j),
+                vertex(i + 1, j),
+                vertex(i, j + 1),
+                vertex(i + 1, j + 1),
+            ]
+        })
+    }
+}
+
+/// 長さ `length` の辺を、両端の縁 `start` と `end` で分ける位置を割合で求める
+#[cfg(feature = "backend-wgpu")]
+fn border_fractions(start: f32, end: f32, length: f32) -> [f32; 4] {
+    let total = start + end;
+    if length <= f32::EPSILON || total <= 0.0 {
+        return [0.0, 0.0, 1.0, 1.0];
+    }
+    let shrink = (length / total).min(1.0);
+    [
+        0.0,
+        start * shrink / length,
+        1.0 - end * shrink / length,
+        1.0,
+    ]
+}
+
+/// UV をスクロールするスプライトのパイプラインを作る
+#[cfg(feature = "backend-wgpu")]
+fn uv_scroll_pipeline(
+    resource: &WgpuResource<'_>,
+    format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    let shader = resource
+        .device
+        .create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shader from scroll.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../../scroll.wgsl").into()),
+        });
+    crate::wgpu_wrapper::setup_render_pipeline_with_vertex(
+        &shader,
+        &[
+            &resource.texture_bind_group_layout,
+            &resource.uniform_bind_group_layout,
+            resource.globals.bind_group_layout(),
+        ],
+        &[UvVertex::desc(), UvScrollInstance::desc()],
+        format,
+        &resource.device,
+    )
+    .context("failed to create the UV scroll pipeline")
+    .unwrap_or_log()
+}
+
+/// テクスチャ全体を表す UV の範囲
+const FULL_UV_RECT: Rect = Rect {
+    min: Point2::new(0.0, 0.0),
+    max: Point2::new(1.0, 1.0),
+};
+
+#[derive(Debug)]
+/// [`SpriteComponent::builder`] で作る、スプライトの設定を順に指定するためのもの
+pub struct SpriteBuilder {
+    sprite: SpriteComponent,
+    layer: Option<RenderLayerComponent>,
+}
+
+impl SpriteBuilder {
+    /// テクスチャのうち描画する範囲。テクスチャ全体を 0.0 から 1.0 とする
+    pub const fn uv_rect(mut self, uv_rect: Rect) -> Self {
+        self.sprite.uv_rect = uv_rect;
+        self
+    }
+
+    /// テクスチャの色に`tint`を掛けて描画する
+    pub const fn tint(mut self, tint: Color) -> Self {
+        self.sprite.tint = tint;
+        self
+    }
+
+    /// [`TransformComponent::scale`] を掛ける前の大きさ
+    pub const fn size(mut self, width: f32, height: f32) -> Self {
+        self.sprite.size = Vector2::new(width, height);
+        self
+    }
+
+    /// 四隅の頂点をずらす量。[`SpriteComponent::corner_offsets`] を参照
+    pub const fn corner_offsets(mut self, corner_offsets: [[f32; 2]; 4]) -> Self {
+        self.sprite.corner_offsets = corner_offsets;
+        self
+    }
+
+    /// 四隅をずらして平行四辺形に傾ける。[`SpriteComponent::set_skew`] を参照
+    pub fn skew(mut self, x: f32, y: f32) -> Self {
+        self.sprite.corner_offsets = skew_offsets(x, y);
+        self
+    }
+
+    /// 1 秒あたりに動かす UV。[`SpriteComponent::uv_scroll`] を参照
+    pub const fn uv_scroll(mut self, u: f32, v: f32) -> Self {
+        self.sprite.uv_scroll = Vector2::new(u, v);
+        self
+    }
+
+    /// 1 秒あたりに UV を回す角度。[`SpriteComponent::uv_rotation`] を参照
+    pub const fn uv_rotation(mut self, uv_rotation: f32) -> Self {
+        self.sprite.uv_rotation = uv_rotation;
+        self
+    }
+
+    /// 9 分割して描画する。[`SpriteComponent::nine_slice`] を参照
+    pub const fn nine_slice(mut self, nine_slice: NineSlice) -> Self {
+        self.sprite.nine_slice = Some(nine_slice);
+        self
+    }
+
+    /// 描画先のピクセルに位置を合わせるか。[`SpriteComponent::pixel_snap`] を参照
+    pub const fn pixel_snap(mut self, pixel_snap: bool) -> Self {
+        self.sprite.pixel_snap = Some(pixel_snap);
+        self
+    }
+
+    /// Y 座標で並べる層で、位置の Y 座標に足して並べる値
+    pub const fn sort_offset(mut self, sort_offset: f32) -> Self {
+        self.sprite.sort_offset = sort_offset;
+        self
+    }
+
+    /// 描画する層
+    ///
+    /// [`crate::scene::Scene::new_sprite`] で作ると [`RenderLayerComponent`] として付けられる。
+    /// [`Self::build`] で作ったときは [`Self::layer_component`] を自分で付ける。
+    pub const fn layer(mut self, layer: i32) -> Self {
+        self.layer = Some(RenderLayerComponent(layer));
+        self
+    }
+
+    /// [`Self::layer`] で指定した層
+    pub const fn layer_component(&self) -> Option<RenderLayerComponent> {
+        self.layer
+    }
+
+    // wgpu のバッファを持つときは const fn にできない
+    #[cfg_attr(not(feature = "backend-wgpu"), allow(clippy::missing_const_for_fn))]
+    pub fn build(self) -> SpriteComponent {
+        self.sprite
+    }
+}
+
+/// 上の辺を `x` だけ右に、右の辺を `y` だけ下にずらす、四隅のずれ
+fn skew_offsets(x: f32, y: f32) -> [[f32; 2]; 4] {
+    let (x, y) = (x / 2.0, y / 2.0);
+    [[x, -y], [x, y], [-x, -y], [-x, y]]
+}
+
+impl From<SpriteBuilder> for SpriteComponent {
+    fn from(builder: SpriteBuilder) -> Self {
+        builder.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::TextureRegistry;
+
+    fn texture() -> TextureId {
+        let mut registry = TextureRegistry::default();
+        TextureId::Single(registry.new_texture(image::RgbaImage::new(1, 1), None))
+    }
+
+    #[test]
+    fn builder_defaults_match_new() {
+        let texture = texture();
+        let built = SpriteComponent::builder(texture).build();
+        let plain = SpriteComponent::new(texture);
+        assert_eq!(built.tint(), plain.tint());
+        assert_eq!(built.uv_rect(), plain.uv_rect());
+        assert_eq!(built.size(), plain.size());
+        assert_eq!(SpriteComponent::builder(texture).layer_component(), None);
+        assert!(!built.is_scrolling());
+        assert!(SpriteComponent::builder(texture)
+            .uv_rotation(1.0)
+            .build()
+            .is_scrolling());
+    }
+
+    #[test]
+    fn size_scales_world_aabb() {
+        let builder = SpriteComponent::builder(texture())
+            .size(4.0, 2.0)
+            .tint(Color::rgb(1.0, 0.0, 0.0))
+            .layer(3);
+        assert_eq!(builder.layer_component(), Some(RenderLayerComponent(3)));
+        let sprite = builder.build();
+        assert_eq!(sprite.tint(), Color::rgb(1.0, 0.0, 0.0));
+
+        let transform = TransformComponent::default();
+        let (min, max) = sprite.world_aabb(&transform);
+        assert_eq!(min, Point3::new(-2.0, -1.0, 0.0));
+        assert_eq!(max, Point3::new(2.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn new_sprite_attaches_layer() {
+        let mut scene = crate::scene::Scene::default();
+        let transform = TransformComponent::default();
+        let plain = scene.new_sprite(transform.clone(), SpriteComponent::builder(texture()));
+        let layered = scene.new_sprite(transform, SpriteComponent::builder(texture()).layer(2));
+        let layer = |entity: crate::scene::EntityIndex| {
+            scene
+                .world
+                .get::<&RenderLayerComponent>(entity.0)
+                .ok()
+                .map(|layer| *layer)
+        };
+        assert_eq!(layer(plain), None);
+        assert_eq!(layer(layered), Some(RenderLayerComponent(2)));
+    }
+
+    #[test]
+    #[cfg(feature = "backend-wgpu")]
+    fn uv_rect_selects_part_of_texture() {
+        let mut registry = TextureRegistry::default();
+        let texture = TextureId::Single(registry.new_texture(image::RgbaImage::new(4, 4), None));
+        let sprite = SpriteComponent::builder(texture)
+            .uv_rect(Rect::new(Point2::new(0.5, 0.0), Point2::new(1.0, 0.25)))
+            .build();
+        let vertices = sprite
+            .quad_vertices(&registry, &TransformComponent::default())
+            .unwrap();
+        assert_eq!(vertices[0].uv, [0.5, 0.0]);
+        assert_eq!(vertices[3].uv, [1.0, 0.25]);
+    }
+
+    #[test]
+    #[cfg(feature = "backend-wgpu")]
+    fn nine_slice_keeps_corner_size() {
+        let mut registry = TextureRegistry::default();
+        let texture = TextureId::Single(registry.new_texture(image::RgbaImage::new(4, 4), None));
+        let slice = NineSlice::new([0.25; 4], [1.0, 1.0, 2.0, 2.0]);
+        let sprite = SpriteComponent::builder(texture)
+            .size(10.0, 4.0)
+            .nine_slice(slice)
+            .build();
+        let vertices = sprite
+            .quad_vertices(&registry, &TransformComponent::default())
+            .unwrap();
+        let quads = slice.split(&vertices);
+
+        // 左上の角はテクスチャの左上 1/4 を 1x1 で描く
+        assert_eq!(quads[0][0].position, [-5.0, -2.0, 0.0]);
+        assert_eq!(quads[0][3].position, [-4.0, -1.0, 0.0]);
+        assert_eq!(quads[0][3].uv, [0.25, 0.25]);
+        // 中央だけが伸びる
+        assert_eq!(quads[4][0].position, [-4.0, -1.0, 0.0]);
+        assert_eq!(quads[4][3].position, [3.0, 0.0, 0.0]);
+        assert_eq!(quads[4][3].uv, [0.75, 0.75]);
+        assert_eq!(quads[8][3].position, [5.0, 2.0, 0.0]);
+
+        // 縁の合計より小さいスプライトでは縁を縮める
+        assert_eq!(border_fractions(1.0, 3.0, 2.0), [0.0, 0.25, 0.25, 1.0]);
+    }
+
+    #[test]
+    fn corner_offsets_apply_after_transform() {
+        let transform = TransformComponent::with_translation_and_scale(
+            nalgebra::Translation3::new(10.0, 0.0, 0.0),
+            Scale3::new(2.0, 2.0, 1.0),
+        );
+        let mut sprite = SpriteComponent::builder(texture()).skew(4.0, 0.0).build();
+        let corners = sprite.corners(&transform);
+        assert_eq!(corners[0], Point3::new(11.0, -1.0, 0.0));
+        assert_eq!(corners[3], Point3::new(9.0, 1.0, 0.0));
+        assert_eq!(
+            sprite.world_aabb(&transform),
+            (Point3::new(7.0, -1.0, 0.0), Point3::new(13.0, 1.0, 0.0))
+        );
+
+        // 右下だけを引っ張る
+        sprite.set_corner_offsets([[0.0; 2], [0.0; 2], [0.0; 2], [1.0, 3.0]]);
+        let corners = sprite.corners(&transform);
+        assert_eq!(corners[0], Point3::new(9.0, -1.0, 0.0));
+        assert_eq!(corners[3], Point3::new(12.0, 4.0, 0.0));
+    }
+}
@@ -0,0 +1,405 @@
+#[cfg(feature = "backend-wgpu")]
+use nalgebra::Point3;
+#[cfg(feature = "backend-wgpu")]
+use tracing_unwrap::ResultExt;
+
+use crate::text::{
+    layout_rich, Fonts, GlyphSource, RichText, TextLayout, TextSpan, TextStyle, Typewriter,
+};
+#[cfg(feature = "backend-wgpu")]
+use crate::{
+    scene::TransformComponent,
+    texture::{TextureId, TextureRegistry},
+    wgpu_wrapper::{buffer::VertexIndexBuffer, vertex::UvVertex, WgpuResource},
+};
+
+#[cfg(feature = "backend-wgpu")]
+/// 1 つの [`TextComponent`] で描けるグリフの数。インデックスが u16 に収まる数
+const MAX_GLYPHS: usize = (u16::MAX as usize + 1) / 4;
+
+#[cfg(feature = "backend-wgpu")]
+#[derive(Debug, Clone, Copy)]
+/// [`TextComponent::quads`] が返すグリフやアイコンの四角形
+pub struct TextQuad {
+    /// `None` ならグリフのアトラス、`Some` ならアイコンのテクスチャ
+    pub texture: Option<TextureId>,
+    /// 左上、右上、左下、右下の頂点
+    pub vertices: [UvVertex; 4],
+}
+
+#[derive(Debug)]
+/// 文字列を描画するコンポーネント
+///
+/// [`crate::text::Fonts`] をリソースとして追加しておく必要がある。
+/// 文字列の左上が [`TransformComponent`] の位置に来る。大きさはピクセル単位で、
+/// [`TransformComponent::scale`] を掛けたものになる。
+pub struct TextComponent {
+    content: RichText,
+    style: TextStyle,
+    max_width: Option<f32>,
+    typewriter: Option<Typewriter>,
+    /// `None` なら次に使うときに配置し直す
+    layout: Option<TextLayout>,
+    pixel_snap: Option<bool>,
+    #[cfg(feature = "backend-wgpu")]
+    buffer: Option<VertexIndexBuffer>,
+}
+
+impl TextComponent {
+    pub fn new(text: impl Into<String>, style: TextStyle) -> Self {
+        Self::from_rich_text(RichText::plain(text), style)
+    }
+
+    /// マークアップから作る。書き方は [`RichText::parse`] を参照
+    pub fn from_markup(markup: &str, style: TextStyle) -> Self {
+        Self::from_rich_text(RichText::parse(markup), style)
+    }
+
+    pub const fn from_rich_text(content: RichText, style: TextStyle) -> Self {
+        Self {
+            content,
+            style,
+            max_width: None,
+            typewriter: None,
+            layout: None,
+            pixel_snap: None,
+            #[cfg(feature = "backend-wgpu")]
+            buffer: None,
+        }
+    }
+
+    /// `max_width` (ピクセル) を超える行を折り返す
+    pub const fn with_max_width(mut self, max_width: f32) -> Self {
+        self.max_width = Some(max_width);
+        self
+    }
+
+    /// 1 秒に`chars_per_second`文字ずつ表示する。[`crate::text::TextSystem`] が進める
+    pub fn with_typewriter(mut self, chars_per_second: f32) -> Self {
+        self.start_typewriter(chars_per_second);
+        self
+    }
+
+    /// 描画先のピクセルに位置を合わせるか決める。[`Self::pixel_snap`] を参照
+    pub const fn with_pixel_snap(mut self, pixel_snap: bool) -> Self {
+        self.pixel_snap = Some(pixel_snap);
+        self
+    }
+
+    /// 文字列の一部の見た目を変える
+    pub fn with_span(mut self, span: TextSpan) -> Self {
+        self.add_span(span);
+        self
+    }
+
+    /// タグを取り除いた文字列。アイコンの位置には [`crate::text::ICON_PLACEHOLDER`] が入る
+    pub fn text(&self) -> &str {
+        &self.content.text
+    }
+
+    /// 文字列を置き換える。範囲ごとの見た目とアイコンも取り除く
+    pub fn set_text(&mut self, text: impl Into<String>) {
+        self.set_rich_text(RichText::plain(text));
+    }
+
+    pub fn set_markup(&mut self, markup: &str) {
+        self.set_rich_text(RichText::parse(markup));
+    }
+
+    pub const fn rich_text(&self) -> &RichText {
+        &self.content
+    }
+
+    /// 描画先のピクセルに位置を合わせるか。`None` (既定) なら [`crate::scene::ScreenSpaceComponent`] を持つときだけ合わせる
+    ///
+    /// カメラの変換の後の原点を最も近い物理ピクセルの境目に動かす。回転と拡大縮小は変えない。
+    /// UI の文字列を小数のピクセルだけ動かしたときに、グリフの縁が揺れるのを防ぐ。
+    pub const fn pixel_snap(&self) -> Option<bool> {
+        self.pixel_snap
+    }
+
+    pub fn set_pixel_snap(&mut self, pixel_snap: bool) {
+        self.pixel_snap = Some(pixel_snap);
+    }
+
+    /// [`Self::pixel_snap`] に従い、ピクセルに合わせるか
+    #[cfg(feature = "backend-wgpu")]
+    pub(crate) fn snaps(&self, screen_space: bool) -> bool {
+        self.pixel_snap.unwrap_or(screen_space)
+    }
+
+    /// 文字列を置き換える。タイプライター効果は最初からやり直す
+    pub fn set_rich_text(&mut self, content: RichText) {
+        self.content = content;
+        self.layout = None;
+        if let Some(typewriter) = &self.typewriter {
+            self.start_typewriter(typewriter.chars_per_second());
+        }
+    }
+
+    /// 最初の文字からタイプライター効果を始める
+    pub fn start_typewriter(&mut self, chars_per_second: f32) {
+        self.typewriter = Some(Typewriter::new(chars_per_second, &self.content.text));
+    }
+
+    /// タイプライター効果をやめて、すべての文字を表示する
+    pub fn stop_typewriter(&mut self) {
+        self.typewriter = None;
+    }
+
+    /// タイプライター効果の途中でも、すべての文字をすぐに表示する
+    ///
+    /// [`crate::text::RevealCompleted`] は次の [`crate::text::TextSystem`] の更新で送られる。
+    pub fn complete_reveal(&mut self) {
+        if let Some(typewriter) = &mut self.typewriter {
+            typewriter.finish();
+        }
+    }
+
+    pub const fn typewriter(&self) -> Option<&Typewriter> {
+        self.typewriter.as_ref()
+    }
+
+    /// タイプライター効果を進める。このときに初めて最後まで表示したなら `true`
+    pub(crate) fn advance_reveal(&mut self, delta_time: std::time::Duration) -> bool {
+        self.typewriter
+            .as_mut()
+            .is_some_and(|typewriter| typewriter.advance(delta_time))
+    }
+
+    pub fn spans(&self) -> &[TextSpan] {
+        &self.content.spans
+    }
+
+    pub fn add_span(&mut self, span: TextSpan) {
+        self.content.spans.push(span);
+        self.layout = None;
+    }
+
+    pub fn clear_spans(&mut self) {
+        self.content.spans.clear();
+        self.layout = None;
+    }
+
+    pub const fn style(&self) -> &TextStyle {
+        &self.style
+    }
+
+    pub fn set_style(&mut self, style: TextStyle) {
+        self.style = style;
+        self.layout = None;
+    }
+
+    pub const fn max_width(&self) -> Option<f32> {
+        self.max_width
+    }
+
+    pub fn set_max_width(&mut self, max_width: Option<f32>) {
+        self.max_width = max_width;
+        self.layout = None;
+    }
+
+    /// 最後に配置した結果。文字列や見た目を変えた後は `None`
+    pub const fn layout(&self) -> Option<&TextLayout> {
+        self.layout.as_ref()
+    }
+
+    /// 必要なら配置し直して、その結果を返す
+    ///
+    /// 描画する前に文字列の大きさを測りたいときに使う。
+    pub fn update_layout(&mut self, fonts: &Fonts) -> &TextLayout {
+        self.layout
+            .get_or_insert_with(|| layout_rich(fonts, &self.content, &self.style, self.max_width))
+    }
+
+    /// 配置し直して、使うグリフをアトラスに描き込む
+    pub fn prepare(&mut self, fonts: &mut Fonts) {
+        let glyphs: Vec<_> = self
+            .update_layout(fonts)
+            .glyphs()
+            .iter()
+            .filter_map(|glyph| match glyph.source {
+                GlyphSource::Font { font, glyph: id } => Some((font, id, glyph.size)),
+                GlyphSource::Icon { .. } => None,
+            })
+            .collect();
+        for (font, glyph, size) in glyphs {
+            fonts.glyph(font, glyph, size);
+        }
+    }
+
+    /// グリフとアイコンの四角形を計算する
+    ///
+    /// グリフを先に、アイコンを後に並べる。アトラスに無いグリフは飛ばすので、
+    /// [`Self::prepare`] の後に呼ぶ。タイプライター効果でまだ表示していない文字も飛ばす。
+    #[cfg(feature = "backend-wgpu")]
+    pub fn quads(
+        &self,
+        fonts: &Fonts,
+        registry: &TextureRegistry,
+        transform: &TransformComponent,
+    ) -> Vec<TextQuad> {
+        let Some(layout) = &self.layout else {
+            return Vec::new();
+        };
+        let affine = transform.to_affine3();
+        let atlas = fonts.atlas();
+        let revealed = self
+            .typewriter
+            .as_ref()
+            .map_or(self.content.text.len(), |typewriter| {
+                typewriter.revealed_len(&self.content.text)
+            });
+        let quad = |texture, [min_x, min_y, max_x, max_y]: [f32; 4], uv, color: [f32; 4]| {
+            let (min_u, min_v, max_u, max_v) = uv;
+            let vertex = |x, y, u, v| UvVertex {
+                position: (affine * Point3::new(x, y, 0.0)).into(),
+                uv: [u, v],
+                color,
+            };
+            TextQuad {
+                texture,
+                vertices: [
+                    vertex(min_x, min_y, min_u, min_v),
+                    vertex(max_x, min_y, max_u, min_v),
+                    vertex(min_x, max_y, min_u, max_v),
+                    vertex(max_x, max_y, max_u, max_v),
+                ],
+            }
+        };
+
+        let mut glyphs = Vec::with_capacity(layout.glyphs().len());
+        let mut icons = Vec::new();
+        for positioned in layout
+            .glyphs()
+            .iter()
+            .filter(|glyph| glyph.byte_index < revealed)
+        {
+            // ビットマップがピクセルの格子に揃うように、ペンの位置を丸める
+            let x = positioned.position.x.round();
+            let y = positioned.position.y.round();
+            let color = positioned.color.into();
+            match positioned.source {
+                GlyphSource::Font { font, glyph } => {
+                    let Some(glyph) = atlas.get(font, glyph, positioned.size) else {
+                        continue;
+                    };
+                    let min_x = x + glyph.offset.x;
+                    let min_y = y + glyph.offset.y;
+                    let rect = [
+                        min_x,
+                        min_y,
+                        min_x + glyph.size[0] as f32,
+                        min_y + glyph.size[1] as f32,
+                    ];
+                    glyphs.push(quad(None, rect, atlas.uv(&glyph), color));
+                }
+                GlyphSource::Icon { texture, height } => {
+                    let uv = match registry.get_uv(texture) {
+                        Ok(uv) => uv,
+                        Err(error) => {
+                            tracing::warn!("icon texture is not found: {error}");
+                            continue;
+                        }
+                    };
+                    // アイコンは文字の色を掛けずに、不透明度だけを使う
+                    let color = [1.0, 1.0, 1.0, color[3]];
+                    let rect = [x, y - height, x + positioned.advance, y];
+                    icons.push(quad(Some(texture), rect, uv, color));
+                }
+            }
+        }
+        glyphs.extend(icons);
+        glyphs
+    }
+
+    #[cfg(feature = "backend-wgpu")]
+    pub(crate) fn render(
+        &mut self,
+        rp: &mut wgpu::RenderPass<'_>,
+        resource: &WgpuResource<'_>,
+        transform: &TransformComponent,
+        fonts: &Fonts,
+    ) {
+        let Some(atlas_bind_group) = fonts.atlas().bind_group() else {
+            tracing::warn!("glyph atlas is not prepared");
+            return;
+        };
+        let mut quads = self.quads(fonts, &resource.texture_registry, transform);
+        if quads.is_empty() {
+            return;
+        }
+        if quads.len() > MAX_GLYPHS {
+            tracing::warn!(
+                "too many glyphs in a text ({}); only {MAX_GLYPHS} are drawn",
+                quads.len()
+            );
+            quads.truncate(MAX_GLYPHS);
+        }
+
+        let buffer = self.buffer.get_or_insert_with(|| {
+            let capacity = quads.len().next_power_of_two().min(MAX_GLYPHS);
+            VertexIndexBuffer::new(
+                &resource.device,
+                capacity * 4,
+                capacity * 6,
+                Some("Text Buffer"),
+                &resource.gpu_memory,
+                &resource.frames,
+            )
+            .unwrap_or_log()
+        });
+        // 文字列が長くなったらバッファを大きくする
+        buffer.reserve(
+            &resource.device,
+            &resource.gpu_memory,
+            &resource.frames,
+            quads.len() * 4,
+            quads.len() * 6,
+        );
+
+        {
+            let mut update = buffer.start_update(&resource.queue, &resource.frames);
+            let range = {
+                let v = update.vertex_mut();
+                v.clear();
+                v.extend(quads.iter().flat_map(|quad| quad.vertices));
+                0..v.len()
+            };
+            update.set_vertex_update(range);
+
+            let range = {
+                let i = update.index_mut();
+                i.clear();
+                for quad in 0..quads.len() {
+                    let base = (quad * 4) as u16;
+                    i.extend_from_slice(&[base, base + 3, base + 1, base, base + 2, base + 3]);
+                }
+                0..i.len()
+            };
+            update.set_index_update(range.clone());
+            update.set_render_range(range.start as u32..range.end as u32);
+        }
+
+        rp.set_index_buffer(buffer.index_buffer().slice(..), wgpu::IndexFormat::Uint16);
+        rp.set_vertex_buffer(0, buffer.vertex_buffer().slice(..));
+        // 同じテクスチャを使う四角形をまとめて描く
+        let mut end = 0;
+        for run in quads.chunk_by(|a, b| a.texture == b.texture) {
+            let start = end;
+            end += run.len();
+            let bind_group = match run[0].texture {
+                None => atlas_bind_group,
+                Some(texture) => match resource.get_texture_bind_group(texture) {
+                    Ok(bind_group) => bind_group,
+                    Err(error) => {
+                        tracing::warn!("icon texture is not found: {error}");
+                        continue;
+                    }
+                },
+            };
+            rp.set_bind_group(0, bind_group, &[]);
+            rp.draw_indexed((start * 6) as u32..(end * 6) as u32, 0, 0..1);
+        }
+    }
+}
@@ -0,0 +1,623 @@
+//! 格子状にタイルを並べて描くコンポーネント
+use std::{collections::BTreeMap, time::Duration};
+
+#[cfg(feature = "backend-wgpu")]
+use anyhow::Context;
+#[cfg(feature = "backend-wgpu")]
+use bytemuck::Zeroable;
+use nalgebra::{Point3, Vector2};
+use reverie_util::color::Color;
+#[cfg(feature = "backend-wgpu")]
+use tracing_unwrap::ResultExt;
+#[cfg(feature = "backend-wgpu")]
+use wgpu::util::DeviceExt;
+
+#[cfg(feature = "backend-wgpu")]
+use crate::wgpu_wrapper::{
+    memory::{GpuMemoryCategory, TrackedAllocation},
+    vertex::TileVertex,
+    WgpuResource,
+};
+use crate::{scene::TransformComponent, texture::TextureId};
+
+/// 1 つのチャンクの一辺のタイルの数
+pub const CHUNK_SIZE: u32 = 16;
+/// 1 つのタイルマップに定義できるアニメーションの数
+pub const MAX_TILE_ANIMATIONS: usize = 32;
+/// 1 つのタイルマップのアニメーションのコマの合計の上限
+pub const MAX_TILE_ANIMATION_FRAMES: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 同じ大きさのタイルを格子状に並べたテクスチャ
+///
+/// タイルの番号は左上から右へ、行の終わりで次の行へと数える。
+pub struct Tileset {
+    pub texture: TextureId,
+    /// 横に並ぶタイルの数
+    pub columns: u32,
+    /// 縦に並ぶタイルの数
+    pub rows: u32,
+}
+
+impl Tileset {
+    pub const fn new(texture: TextureId, columns: u32, rows: u32) -> Self {
+        Self {
+            texture,
+            columns,
+            rows,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// タイルのアニメーション。コマのタイルと表示する時間を順に並べる
+///
+/// すべてのタイルマップで共通の時計 ([`crate::scene::SceneClock`]) で進むので、
+/// 同じアニメーションのタイルは揃って動く。
+pub struct TileAnimation {
+    frames: Vec<(u32, Duration)>,
+}
+
+impl TileAnimation {
+    pub fn new(frames: impl IntoIterator<Item = (u32, Duration)>) -> Self {
+        Self {
+            frames: frames.into_iter().collect(),
+        }
+    }
+
+    /// 同じ時間ずつ `tiles` を順に表示する
+    pub fn uniform(tiles: impl IntoIterator<Item = u32>, frame_time: Duration) -> Self {
+        Self::new(tiles.into_iter().map(|tile| (tile, frame_time)))
+    }
+
+    pub fn frames(&self) -> &[(u32, Duration)] {
+        &self.frames
+    }
+
+    /// 1 周の時間のミリ秒
+    fn period_ms(&self) -> u32 {
+        self.frames
+            .iter()
+            .map(|(_, duration)| duration.as_millis() as u32)
+            .sum()
+    }
+
+    /// 時計が `elapsed` のときに表示するタイル。シェーダーと同じ計算をする
+    pub fn tile_at(&self, elapsed: Duration) -> Option<u32> {
+        let t = (elapsed.as_millis() as u32) % self.period_ms().max(1);
+        let mut end = 0;
+        for &(tile, duration) in &self.frames {
+            end += duration.as_millis() as u32;
+            if t < end {
+                return Some(tile);
+            }
+        }
+        self.frames.last().map(|&(tile, _)| tile)
+    }
+}
+
+#[derive(Debug)]
+/// タイルマップを [`CHUNK_SIZE`] 四方に分けた一部分
+struct Chunk {
+    /// 頂点を作り直す必要がある
+    dirty: bool,
+    /// タイルを置き換えた回数
+    revision: u64,
+    #[cfg(feature = "backend-wgpu")]
+    gpu: Option<ChunkBuffer>,
+}
+
+#[cfg(feature = "backend-wgpu")]
+#[derive(Debug)]
+struct ChunkBuffer {
+    vertex_buffer: wgpu::Buffer,
+    /// 描画するタイルの数
+    tiles: u32,
+    _memory: TrackedAllocation,
+}
+
+#[derive(Debug)]
+/// 格子状にタイルを並べて描くコンポーネント
+///
+/// タイルマップの左上が [`TransformComponent`] の位置になり、右と下に広がる。
+/// タイルの頂点は [`CHUNK_SIZE`] 四方のチャンクごとに作り、タイルや色を変えたチャンクだけを作り直す。
+/// アニメーションするタイルは、シェーダーが時刻からコマを選ぶので、頂点を作り直さない。
+pub struct TilemapComponent {
+    tileset: Tileset,
+    width: u32,
+    height: u32,
+    tile_size: Vector2<f32>,
+    tiles: Vec<Option<u32>>,
+    tints: Vec<Color>,
+    /// タイルの番号からアニメーションへの対応
+    animations: BTreeMap<u32, TileAnimation>,
+    chunks: Vec<Chunk>,
+    #[cfg(feature = "backend-wgpu")]
+    gpu: Option<TilemapGpu>,
+    /// アニメーションの表を GPU に送り直す必要がある
+    #[cfg(feature = "backend-wgpu")]
+    animations_dirty: bool,
+}
+
+impl TilemapComponent {
+    /// `width` x `height` の空のタイルマップを作る
+    pub fn new(tileset: Tileset, width: u32, height: u32, tile_size: Vector2<f32>) -> Self {
+        let cells = (width * height) as usize;
+        let chunks = (width.div_ceil(CHUNK_SIZE) * height.div_ceil(CHUNK_SIZE)) as usize;
+        Self {
+            tileset,
+            width,
+            height,
+            tile_size,
+            tiles: vec![None; cells],
+            tints: vec![Color::WHITE; cells],
+            animations: BTreeMap::new(),
+            chunks: (0..chunks)
+                .map(|_| Chunk {
+                    dirty: true,
+                    revision: 0,
+                    #[cfg(feature = "backend-wgpu")]
+                    gpu: None,
+                })
+                .collect(),
+            #[cfg(feature = "backend-wgpu")]
+            gpu: None,
+            #[cfg(feature = "backend-wgpu")]
+            animations_dirty: true,
+        }
+    }
+
+    pub const fn tileset(&self) -> &Tileset {
+        &self.tileset
+    }
+
+    pub const fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub const fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// [`TransformComponent::scale`] を掛ける前の 1 つのタイルの大きさ
+    pub const fn tile_size(&self) -> Vector2<f32> {
+        self.tile_size
+    }
+
+    fn index(&self, x: u32, y: u32) -> Option<usize> {
+        (x < self.width && y < self.height).then(|| (y * self.width + x) as usize)
+    }
+
+    const fn chunk_index(&self, x: u32, y: u32) -> usize {
+        let columns = self.width.div_ceil(CHUNK_SIZE);
+        ((y / CHUNK_SIZE) * columns + x / CHUNK_SIZE) as usize
+    }
+
+    fn mark_dirty(&mut self, x: u32, y: u32) {
+        let chunk = self.chunk_index(x, y);
+        self.chunks[chunk].dirty = true;
+    }
+
+    /// `(x, y)` のタイルの番号。範囲外か空なら `None`
+    pub fn tile(&self, x: u32, y: u32) -> Option<u32> {
+        self.index(x, y).and_then(|i| self.tiles[i])
+    }
+
+    /// `(x, y)` にタイルを置く。`None` なら空にする。範囲外なら何もしない
+    pub fn set_tile(&mut self, x: u32, y: u32, tile: Option<u32>) {
+        let Some(i) = self.index(x, y) else {
+            return;
+        };
+        if self.tiles[i] != tile {
+            self.tiles[i] = tile;
+            self.mark_dirty(x, y);
+            let chunk = self.chunk_index(x, y);
+            self.chunks[chunk].revision += 1;
+        }
+    }
+
+    /// `(x, y)` のタイルに掛ける色。範囲外なら `None`
+    pub fn tint(&self, x: u32, y: u32) -> Option<Color> {
+        self.index(x, y).map(|i| self.tints[i])
+    }
+
+    /// `(x, y)` のタイルに掛ける色を変える。霧で暗くするのに使う
+    ///
+    /// そのタイルのチャンクだけを作り直す。
+    pub fn set_tint(&mut self, x: u32, y: u32, tint: Color) {
+        let Some(i) = self.index(x, y) else {
+            return;
+        };
+        if self.tints[i] != tint {
+            self.tints[i] = tint;
+            self.mark_dirty(x, y);
+        }
+    }
+
+    /// タイル `tile` を置いたところを `animation` で動かす
+    ///
+    /// アニメーションの数やコマの合計が上限を超えるとエラーになる。
+    pub fn define_animation(&mut self, tile: u32, animation: TileAnimation) -> anyhow::Result<()> {
+        anyhow::ensure!(!animation.frames.is_empty(), "tile animation has no frames");
+        let previous = self.animations.insert(tile, animation);
+        let frames: usize = self.animations.values().map(|a| a.frames.len()).sum();
+        if self.animations.len() > MAX_TILE_ANIMATIONS || frames > MAX_TILE_ANIMATION_FRAMES {
+            match previous {
+                Some(previous) => self.animations.insert(tile, previous),
+                None => self.animations.remove(&tile),
+            };
+            anyhow::bail!(
+                "too many tile animations; up to {MAX_TILE_ANIMATIONS} animations and \
+                 {MAX_TILE_ANIMATION_FRAMES} frames in total are supported"
+            );
+        }
+        self.animations_changed();
+        Ok(())
+    }
+
+    /// タイル `tile` のアニメーションをやめる
+    pub fn remove_animation(&mut self, tile: u32) -> Option<TileAnimation> {
+        let removed = self.animations.remove(&tile);
+        if removed.is_some() {
+            self.animations_changed();
+        }
+        removed
+    }
+
+    pub fn animation(&self, tile: u32) -> Option<&TileAnimation> {
+        self.animations.get(&tile)
+    }
+
+    /// アニメーションの番号が変わるので、すべてのチャンクを作り直す
+    fn animations_changed(&mut self) {
+        for chunk in &mut self.chunks {
+            chunk.dirty = true;
+        }
+        #[cfg(feature = "backend-wgpu")]
+        {
+            self.animations_dirty = true;
+        }
+    }
+
+    /// 頂点を作り直すのを待っているチャンクの数
+    pub fn dirty_chunks(&self) -> usize {
+        self.chunks.iter().filter(|chunk| chunk.dirty).count()
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// チャンク `chunk` のタイルを置き換えた回数。範囲外なら `None`
+    ///
+    /// 前に見たときの値と比べると、タイルが変わったチャンクが分かる。色やアニメーションでは変わらない。
+    pub fn chunk_revision(&self, chunk: usize) -> Option<u64> {
+        self.chunks.get(chunk).map(|chunk| chunk.revision)
+    }
+
+    /// ワールド座標での、座標軸に沿った外接箱の最小点と最大点
+    pub fn world_aabb(&self, transform: &TransformComponent) -> (Point3<f32>, Point3<f32>) {
+        let w = self.width as f32 * self.tile_size.x;
+        let h = self.height as f32 * self.tile_size.y;
+        let affine = transform.to_affine3();
+        let corners =
+            [(0.0, 0.0), (w, 0.0), (0.0, h), (w, h)].map(|(x, y)| affine * Point3::new(x, y, 0.0));
+        corners[1..]
+            .iter()
+            .fold((corners[0], corners[0]), |(min, max), p| {
+                (min.inf(p), max.sup(p))
+            })
+    }
+
+    /// チャンク `chunk` の頂点を作る。タイルごとに左上、右上、左下、右下の順
+    #[cfg(feature = "backend-wgpu")]
+    fn chunk_vertices(&self, chunk: usize) -> Vec<TileVertex> {
+        let columns = self.width.div_ceil(CHUNK_SIZE);
+        let (cx, cy) = (chunk as u32 % columns, chunk as u32 / columns);
+        let animation_ids: BTreeMap<u32, u32> = self
+            .animations
+            .keys()
+            .enumerate()
+            .map(|(i, &tile)| (tile, i as u32))
+            .collect();
+        let mut vertices = Vec::new();
+        for y in cy * CHUNK_SIZE..((cy + 1) * CHUNK_SIZE).min(self.height) {
+            for x in cx * CHUNK_SIZE..((cx + 1) * CHUNK_SIZE).min(self.width) {
+                let i = (y * self.width + x) as usize;
+                let Some(tile) = self.tiles[i] else {
+                    continue;
+                };
+                let animation = animation_ids
+                    .get(&tile)
+                    .copied()
+                    .unwrap_or(TileVertex::NO_ANIMATION);
+                for corner in 0..4 {
+                    let (dx, dy) = ((corner & 1) as f32, (corner >> 1) as f32);
+                    vertices.push(TileVertex {
+                        position: [
+                            (x as f32 + dx) * self.tile_size.x,
+                            (y as f32 + dy) * self.tile_size.y,
+                        ],
+                        corner,
+                        tile,
+                        animation,
+                        tint: self.tints[i].into(),
+                    });
+                }
+            }
+        }
+        vertices
+    }
+
+    /// アニメーションの表。[`TilemapUniform::animations`] と [`TilemapUniform::frames`]
+    #[cfg(feature = "backend-wgpu")]
+    fn animation_tables(&self, uniform: &mut TilemapUniform) {
+        let mut first = 0;
+        for (i, animation) in self.animations.values().enumerate() {
+            let count = animation.frames.len() as u32;
+            uniform.animations[i] = [first, count, animation.period_ms(), 0];
+            let mut end = 0;
+            for (j, &(tile, duration)) in animation.frames.iter().enumerate() {
+                end += duration.as_millis() as u32;
+                uniform.frames[first as usize + j] = [tile, end, 0, 0];
+            }
+            first += count;
+        }
+    }
+
+    /// 変わったチャンクを作り直し、タイルマップを描画する
+    ///
+    /// `elapsed` はアニメーションの時計。
+    #[cfg(feature = "backend-wgpu")]
+    pub(crate) fn render(
+        &mut self,
+        rp: &mut wgpu::RenderPass<'_>,
+        resource: &WgpuResource<'_>,
+        transform: &TransformComponent,
+        elapsed: Duration,
+    ) {
+        let pipeline = resource
+            .pipeline_cache
+            .get_or_create("reverie tilemap", |formats| {
+                TilemapGpu::pipeline(resource, formats.color)
+            });
+        self.rebuild_chunks(resource);
+
+        let mut uniform = TilemapUniform::zeroed();
+        let (min_u, min_v, max_u, max_v) = resource
+            .texture_registry
+            .get_uv(self.tileset.texture)
+            .unwrap_or((0.0, 0.0, 1.0, 1.0));
+        uniform.model = transform.to_affine3().to_homogeneous().into();
+        uniform.uv_min = [min_u, min_v];
+        uniform.uv_max = [max_u, max_v];
+        uniform.grid = [self.tileset.columns.max(1), self.tileset.rows.max(1)];
+        uniform.time_ms = elapsed.as_millis() as u32;
+        // 表は変わったときだけ送る
+        let header = std::mem::offset_of!(TilemapUniform, animations);
+        let upload_tables = std::mem::take(&mut self.animations_dirty);
+        if upload_tables {
+            self.animation_tables(&mut uniform);
+        }
+        let gpu = self.gpu.get_or_insert_with(|| TilemapGpu::new(resource));
+        if upload_tables {
+            resource
+                .queue
+                .write_buffer(&gpu.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+        } else {
+            resource.queue.write_buffer(
+                &gpu.uniform_buffer,
+                0,
+                &bytemuck::bytes_of(&uniform)[..header],
+            );
+        }
+
+        let bind_group = resource
+            .get_texture_bind_group(self.tileset.texture)
+            .context("texture not found for tileset")
+            .unwrap_or_log();
+        rp.set_pipeline(&pipeline);
+        rp.set_bind_group(0, bind_group, &[]);
+        rp.set_bind_group(2, &gpu.bind_group, &[]);
+        rp.set_index_buffer(gpu.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        for chunk in &self.chunks {
+            if let Some(buffer) = &chunk.gpu {
+                rp.set_vertex_buffer(0, buffer.vertex_buffer.slice(..));
+                rp.draw_indexed(0..buffer.tiles * 6, 0, 0..1);
+            }
+        }
+    }
+
+    /// 変わったチャンクの頂点を作り直す
+    #[cfg(feature = "backend-wgpu")]
+    fn rebuild_chunks(&mut self, resource: &WgpuResource<'_>) {
+        for i in 0..self.chunks.len() {
+            if !std::mem::take(&mut self.chunks[i].dirty) {
+                continue;
+            }
+            let vertices = self.chunk_vertices(i);
+            self.chunks[i].gpu = (!vertices.is_empty()).then(|| ChunkBuffer {
+                vertex_buffer: resource.device.create_buffer_init(
+                    &wgpu::util::BufferInitDescriptor {
+                        label: Some("Tilemap Chunk"),
+                        contents: bytemuck::cast_slice(&vertices),
+                        usage: wgpu::BufferUsages::VERTEX,
+                    },
+                ),
+                tiles: vertices.len() as u32 / 4,
+                _memory: resource.gpu_memory.track(
+                    GpuMemoryCategory::SpriteBuffer,
+                    "Tilemap Chunk",
+                    (vertices.len() * size_of::<TileVertex>()) as u64,
+                ),
+            });
+        }
+    }
+}
+
+#[cfg(feature = "backend-wgpu")]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+/// `tilemap.wgsl` の `Tilemap`
+struct TilemapUniform {
+    model: [[f32; 4]; 4],
+    uv_min: [f32; 2],
+    uv_max: [f32; 2],
+    grid: [u32; 2],
+    time_ms: u32,
+    _pad: u32,
+    animations: [[u32; 4]; MAX_TILE_ANIMATIONS],
+    frames: [[u32; 4]; MAX_TILE_ANIMATION_FRAMES],
+}
+
+#[cfg(feature = "backend-wgpu")]
+#[derive(Debug)]
+/// タイルマップごとの GPU のリソース
+struct TilemapGpu {
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    /// すべてのチャンクで共有する、タイルを四角形にするインデックス
+    index_buffer: wgpu::Buffer,
+}
+
+#[cfg(feature = "backend-wgpu")]
+impl TilemapGpu {
+    fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Tilemap Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: wgpu::BufferSize::new(size_of::<TilemapUniform>() as u64),
+                },
+                count: None,
+            }],
+        })
+    }
+
+    fn new(resource: &WgpuResource<'_>) -> Self {
+        let device = &resource.device;
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Tilemap Uniform Buffer"),
+            size: size_of::<TilemapUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Tilemap Bind Group"),
+            layout: &Self::bind_group_layout(device),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+        let indices: Vec<u16> = (0..(CHUNK_SIZE * CHUNK_SIZE) as u16)
+            .flat_map(|tile| [0, 3, 1, 0, 2, 3].map(|i| tile * 4 + i))
+            .collect();
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Tilemap Index Buffer"),
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        Self {
+            uniform_buffer,
+            bind_group,
+            index_buffer,
+        }
+    }
+
+    fn pipeline(resource: &WgpuResource<'_>, format: wgpu::TextureFormat) -> wgpu::RenderPipeline {
+        let device = &resource.device;
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shader from tilemap.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../../tilemap.wgsl").into()),
+        });
+        crate::wgpu_wrapper::setup_render_pipeline_with_vertex(
+            &shader,
+            &[
+                &resource.texture_bind_group_layout,
+                &resource.uniform_bind_group_layout,
+                &Self::bind_group_layout(device),
+            ],
+            &[TileVertex::desc()],
+            format,
+            device,
+        )
+        .context("failed to create the tilemap pipeline")
+        .unwrap_or_log()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tilemap() -> TilemapComponent {
+        TilemapComponent::new(
+            Tileset::new(TextureId::WHITE, 4, 4),
+            40,
+            20,
+            Vector2::new(8.0, 8.0),
+        )
+    }
+
+    fn clean(tilemap: &mut TilemapComponent) {
+        for chunk in &mut tilemap.chunks {
+            chunk.dirty = false;
+        }
+    }
+
+    #[test]
+    fn tint_dirties_only_its_chunk() {
+        let mut map = tilemap();
+        assert_eq!(map.chunk_count(), 3 * 2);
+        clean(&mut map);
+
+        map.set_tint(20, 3, Color::BLACK);
+        assert_eq!(map.dirty_chunks(), 1);
+        assert!(map.chunks[1].dirty);
+        assert_eq!(map.tint(20, 3), Some(Color::BLACK));
+
+        // 同じ色なら作り直さない。範囲外は無視する
+        clean(&mut map);
+        map.set_tint(20, 3, Color::BLACK);
+        map.set_tint(40, 0, Color::BLACK);
+        map.set_tile(5, 19, Some(2));
+        assert_eq!(map.dirty_chunks(), 1);
+        assert!(map.chunks[3].dirty);
+        // タイルを置き換えたチャンクだけ数が増える
+        assert_eq!(map.chunk_revision(1), Some(0));
+        assert_eq!(map.chunk_revision(3), Some(1));
+        assert_eq!(map.chunk_revision(6), None);
+    }
+
+    #[test]
+    fn animation_follows_shared_clock() {
+        let water = TileAnimation::new([
+            (4, Duration::from_millis(100)),
+            (5, Duration::from_millis(300)),
+        ]);
+        let at = |ms| water.tile_at(Duration::from_millis(ms));
+        assert_eq!(at(0), Some(4));
+        assert_eq!(at(99), Some(4));
+        assert_eq!(at(100), Some(5));
+        assert_eq!(at(399), Some(5));
+        assert_eq!(at(400), Some(4));
+
+        let mut map = tilemap();
+        map.define_animation(4, water).unwrap();
+        assert_eq!(map.dirty_chunks(), map.chunk_count());
+        assert!(map.define_animation(6, TileAnimation::new([])).is_err());
+        for tile in 0..MAX_TILE_ANIMATIONS as u32 - 1 {
+            map.define_animation(100 + tile, TileAnimation::uniform([1], Duration::ZERO))
+                .unwrap();
+        }
+        assert!(map
+            .define_animation(7, TileAnimation::uniform([1], Duration::ZERO))
+            .is_err());
+        assert!(map.animation(7).is_none());
+    }
+}
@@ -1,6 +1,6 @@
 use nalgebra::{Affine3, Isometry3, Matrix4, Scale3, Translation3, UnitQuaternion};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 /// エンティティの位置、回転、拡大縮小を表すコンポーネント
 pub struct TransformComponent {
     pub translation: Translation3<f32>,
@@ -67,4 +67,32 @@ impl TransformComponent {
     pub fn to_isometry3(&self) -> Isometry3<f32> {
         Isometry3::from_parts(self.translation, self.rotation)
     }
+
+    /// 親の変換を`self`として、子のローカルな変換`local`をワールドの変換にする
+    ///
+    /// 子の位置は親の拡大縮小と回転を受ける。回転した親の中で子を拡大縮小したときの歪みは表せない。
+    pub fn compose(&self, local: &Self) -> Self {
+        let offset = self
+            .rotation
+            .transform_vector(&self.scale.vector.component_mul(&local.translation.vector));
+        Self {
+            translation: Translation3::from(self.translation.vector + offset),
+            rotation: self.rotation * local.rotation,
+            scale: Scale3::from(self.scale.vector.component_mul(&local.scale.vector)),
+        }
+    }
+
+    /// [`Self::compose`] の逆。ワールドの変換`world`を、親の変換`self`から見たローカルな変換にする
+    ///
+    /// 親の拡大率に 0 があると結果は無限大になる。
+    pub fn relative(&self, world: &Self) -> Self {
+        let inverse = self.rotation.inverse();
+        let offset =
+            inverse.transform_vector(&(world.translation.vector - self.translation.vector));
+        Self {
+            translation: Translation3::from(offset.component_div(&self.scale.vector)),
+            rotation: inverse * world.rotation,
+            scale: Scale3::from(world.scale.vector.component_div(&self.scale.vector)),
+        }
+    }
 }
@@ -0,0 +1,323 @@
+//! 決まった順序でエンティティをたどる
+//!
+//! hecs のクエリはアーキタイプごとにたどるので、順序はコンポーネントを付け外しした履歴で変わる。
+//! ロックステップの通信のように、どのピアでも同じ順序で更新したいときは [`Determinism`] のリソースを入れる。
+//! するとエンティティに作られた順の番号 [`SpawnOrderComponent`] が付き、
+//! エンジンのシステムのうち順序で結果が変わるものは [`for_each_ordered`] で番号順にたどる。
+//!
+//! * [`crate::lifetime::LifetimeSystem`] と [`crate::bounds::WorldBoundsSystem`] は、
+//!   番号順に削除を [`hecs::CommandBuffer`] に積む。hecs は積んだ順に実行するので、削除した後に
+//!   使い回されるエンティティの番号も決まる。イベントも番号順に送る。
+//! * [`crate::text::TextSystem`] は、表示し終えたイベントを番号順に送る。
+//! * [`super::FloatingTextSystem`] は、消えた文字列を番号順に [`super::FloatingTextPool`] に戻す。
+//! * [`crate::physics::KinematicSystem`] は、ぶつかる相手を番号順に調べる。
+//!
+//! ほかのシステムはエンティティごとに別々に更新するので、順序で結果は変わらない。
+//! ゲームのシステムで順序が結果に効くものは、[`for_each_ordered`] を使う。
+//!
+//! 組み合わせて使えるもの
+//!
+//! * `parallel` フィーチャー: 並列に動くのは描画の準備 ([`super::ParallelPrepare`]) だけで、ワールドは変えない。
+//! * 使い回すエンティティ ([`super::FloatingTextPool`]): 使い回しても番号は変わらず、最初に作られた順のまま。
+//! * [`super::Scene::load`] や [`super::Scene::merge`] で加えたエンティティ: 次に番号を付けるときに hecs の番号順に付く。
+//!   どのピアでも同じデータを同じ順序で加えれば同じになる。
+//!
+//! 浮動小数点の計算がプラットフォームによって違うかどうかは扱わない。
+//! ずれたことは [`world_checksum`] を毎ティック比べて見つける。
+use super::{resource, resource_mut, SaveRegistry, Scene};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// エンティティが作られた順の番号
+///
+/// [`Determinism`] のリソースがあるとき、[`sync_spawn_order`] が付ける。
+pub struct SpawnOrderComponent(pub u64);
+
+#[derive(Debug, Clone, Default)]
+/// 決まった順序でエンティティをたどるモード
+///
+/// [`super::Scene::insert_resource`] で追加すると有効になる。
+/// [`super::Scene::update`] はシステムの前と、すべてのシステムの後に [`sync_spawn_order`] を呼ぶ。
+/// それぞれのシステムの後には、そのシステムが作ったエンティティに番号を付けるだけにする。
+pub struct Determinism {
+    /// 次に付ける番号
+    next: u64,
+    /// 番号の付いたエンティティ。番号の小さい方から順
+    entities: Vec<hecs::Entity>,
+}
+
+impl Determinism {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 番号の付いたエンティティ。作られた順
+    pub fn entities(&self) -> &[hecs::Entity] {
+        &self.entities
+    }
+}
+
+/// 番号の無いエンティティに番号を付け、消えたエンティティを一覧から除く
+///
+/// 前に呼んだ後に作られたエンティティには、hecs の番号の小さい方から順に番号を付ける。
+/// 一覧をたどり直すのは、番号の付いたエンティティの数が減っていたときだけ。
+/// [`Determinism`] のリソースが無ければ何もしない。
+pub fn sync_spawn_order(world: &mut hecs::World) {
+    with_state(world, |world, state| {
+        forget_despawned(world, state);
+        number_spawned(world, state);
+    });
+}
+
+/// 番号の無いエンティティに番号を付ける。消えたエンティティは一覧に残す
+///
+/// 新しいエンティティの無いアーキタイプは飛ばすので、かかる時間はエンティティの総数によらない。
+/// 一覧に残った消えたエンティティは [`for_each_ordered`] が飛ばす。
+pub(super) fn number_spawned_entities(world: &mut hecs::World) {
+    with_state(world, number_spawned);
+}
+
+/// [`Determinism`] を取り出して `f` に渡す。無ければ何もしない
+fn with_state(world: &mut hecs::World, f: impl FnOnce(&mut hecs::World, &mut Determinism)) {
+    let Some(mut state) =
+        resource_mut::<Determinism>(world).map(|mut state| std::mem::take(&mut *state))
+    else {
+        return;
+    };
+    f(world, &mut state);
+    if let Some(mut current) = resource_mut::<Determinism>(world) {
+        *current = state;
+    }
+}
+
+fn forget_despawned(world: &hecs::World, state: &mut Determinism) {
+    let numbered = world.query::<&SpawnOrderComponent>().iter().len();
+    if numbered == state.entities.len() {
+        return;
+    }
+    state.entities.retain(|&entity| {
+        world
+            .satisfies::<&SpawnOrderComponent>(entity)
+            .unwrap_or(false)
+    });
+}
+
+fn number_spawned(world: &mut hecs::World, state: &mut Determinism) {
+    let mut spawned: Vec<_> = world
+        .query::<()>()
+        .without::<&SpawnOrderComponent>()
+        .iter()
+        .map(|(entity, ())| entity)
+        .collect();
+    spawned.sort_unstable_by_key(|entity| entity.id());
+    for entity in spawned {
+        if world
+            .insert_one(entity, SpawnOrderComponent(state.next))
+            .is_ok()
+        {
+            state.next += 1;
+            state.entities.push(entity);
+        }
+    }
+}
+
+/// `Q` に合うエンティティを `f` に渡す
+///
+/// [`Determinism`] のリソースがあれば作られた順に、無ければ `world.query_mut` と同じ順に渡す。
+/// [`sync_spawn_order`] の後に作られたエンティティは渡さない。
+pub fn for_each_ordered<Q: hecs::Query>(
+    world: &mut hecs::World,
+    mut f: impl FnMut(hecs::Entity, Q::Item<'_>),
+) {
+    let order =
+        resource_mut::<Determinism>(world).map(|mut state| std::mem::take(&mut state.entities));
+    let Some(order) = order else {
+        for (entity, item) in world.query_mut::<Q>() {
+            f(entity, item);
+        }
+        return;
+    };
+    for &entity in &order {
+        if let Ok(item) = world.query_one_mut::<Q>(entity) {
+            f(entity, item);
+        }
+    }
+    if let Some(mut state) = resource_mut::<Determinism>(world) {
+        state.entities = order;
+    }
+}
+
+/// `registry` で保存するコンポーネントの値から、ワールドの状態のチェックサムを求める
+///
+/// ロックステップの各ピアで毎ティック求めて比べると、ずれ始めたティックが分かる。
+/// エンティティは [`Determinism`] の作られた順に、無ければ hecs の番号順に並べる。
+/// 値は TOML にしてから FNV-1a でまとめるので、プラットフォームによらない。リソースは含まない。
+pub fn world_checksum(world: &hecs::World, registry: &SaveRegistry) -> anyhow::Result<u64> {
+    let holder = super::resource::holder(world);
+    let entities = match resource::<Determinism>(world) {
+        Some(state) => state.entities.clone(),
+        None => {
+            let mut entities: Vec<_> = world.iter().map(|entity| entity.entity()).collect();
+            entities.sort_unstable_by_key(|entity| entity.id());
+            entities
+        }
+    };
+    let mut hash = Fnv1a::new();
+    for entity in entities {
+        if Some(entity) == holder || !world.contains(entity) {
+            continue;
+        }
+        let components = registry.save_entity(world, entity)?;
+        if components.is_empty() {
+            continue;
+        }
+        let order = world
+            .get::<&SpawnOrderComponent>(entity)
+            .map_or(0, |order| order.0);
+        hash.write(&order.to_le_bytes());
+        for (name, component) in components {
+            hash.write(name.as_bytes());
+            hash.write(&component.version.to_le_bytes());
+            hash.write(component.value.to_string().as_bytes());
+        }
+    }
+    Ok(hash.0)
+}
+
+impl Scene {
+    /// シーンの状態のチェックサム。詳しくは [`world_checksum`]
+    pub fn checksum(&self, registry: &SaveRegistry) -> anyhow::Result<u64> {
+        world_checksum(&self.world, registry)
+    }
+}
+
+/// 64 bit の FNV-1a ハッシュ
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    const fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+        // 区切りを入れて、"ab" + "c" と "a" + "bc" を区別する
+        self.0 = (self.0 ^ 0xff).wrapping_mul(0x0100_0000_01b3);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::{
+        lifetime::{Expired, LifetimeComponent, LifetimeSystem},
+        scene::{insert_resource, EntityIndex, Events, SaveComponent},
+    };
+
+    #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+    struct Health(i32);
+
+    impl SaveComponent for Health {
+        const NAME: &'static str = "health";
+    }
+
+    #[derive(Debug)]
+    struct Marker;
+
+    fn ordered(world: &mut hecs::World) -> Vec<hecs::Entity> {
+        let mut entities = Vec::new();
+        for_each_ordered::<&Health>(world, |entity, _| entities.push(entity));
+        entities
+    }
+
+    #[test]
+    fn spawn_order_survives_archetype_changes() {
+        let mut world = hecs::World::new();
+        insert_resource(&mut world, Determinism::new());
+        let a = world.spawn((Health(1),));
+        let b = world.spawn((Health(2), Marker));
+        let c = world.spawn((Health(3),));
+        sync_spawn_order(&mut world);
+        assert_eq!(ordered(&mut world), [a, b, c]);
+
+        world.insert_one(a, Marker).unwrap();
+        world.remove_one::<Marker>(b).unwrap();
+        world.despawn(c).unwrap();
+        let d = world.spawn((Health(4),));
+        // 番号を付けるまでは渡さない
+        assert_eq!(ordered(&mut world), [a, b]);
+        sync_spawn_order(&mut world);
+        assert_eq!(ordered(&mut world), [a, b, d]);
+    }
+
+    #[test]
+    fn despawned_entities_are_forgotten_on_sync() {
+        let mut world = hecs::World::new();
+        insert_resource(&mut world, Determinism::new());
+        let a = world.spawn((Health(1),));
+        let b = world.spawn((Health(2),));
+        sync_spawn_order(&mut world);
+
+        world.despawn(a).unwrap();
+        // 同じ番号を使い回しても、世代が違うので消えたものとは混ざらない
+        let c = world.spawn((Health(3),));
+        number_spawned_entities(&mut world);
+        assert_eq!(ordered(&mut world), [b, c]);
+        // リソースを持つエンティティにも番号が付く
+        let entities = resource::<Determinism>(&world).unwrap().entities().to_vec();
+        assert_eq!(entities[1..], [a, b, c]);
+
+        sync_spawn_order(&mut world);
+        let entities = resource::<Determinism>(&world).unwrap().entities().to_vec();
+        assert_eq!(entities[1..], [b, c]);
+        assert_eq!(ordered(&mut world), [b, c]);
+    }
+
+    #[test]
+    fn lifetime_expires_in_spawn_order() {
+        let mut world = hecs::World::new();
+        insert_resource(&mut world, Determinism::new());
+        let entities: Vec<_> = (0..4)
+            .map(|i| {
+                let entity = world.spawn((LifetimeComponent::frames(1),));
+                if i % 2 == 0 {
+                    world.insert_one(entity, Marker).unwrap();
+                }
+                entity
+            })
+            .collect();
+        sync_spawn_order(&mut world);
+        LifetimeSystem::new().apply(&mut world, Duration::from_millis(16));
+        let expired: Vec<_> = resource::<Events<Expired>>(&world)
+            .unwrap()
+            .iter()
+            .map(|&Expired(EntityIndex(entity))| entity)
+            .collect();
+        assert_eq!(expired, entities);
+    }
+
+    #[test]
+    fn checksum_ignores_history_but_not_state() {
+        let mut registry = SaveRegistry::new();
+        registry.register::<Health>();
+        let build = |churn: bool, last: i32| {
+            let mut world = hecs::World::new();
+            insert_resource(&mut world, Determinism::new());
+            for health in [10, 20, last] {
+                let entity = world.spawn((Health(health),));
+                if churn && health == 10 {
+                    world.insert_one(entity, Marker).unwrap();
+                }
+            }
+            sync_spawn_order(&mut world);
+            world_checksum(&world, &registry).unwrap()
+        };
+        assert_eq!(build(false, 30), build(true, 30));
+        assert_ne!(build(false, 30), build(false, 31));
+    }
+}
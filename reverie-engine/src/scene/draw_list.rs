@@ -0,0 +1,656 @@
+//! フレームごとに作る、描画するエンティティの並び
+#[cfg(feature = "backend-wgpu")]
+use nalgebra::{Point2, Point3, Vector2, Vector3};
+
+use super::EntityIndex;
+#[cfg(feature = "backend-wgpu")]
+use super::{
+    resource, tile_range, FloatingTextComponent, FrameArena, Frustum, LayerSortMode,
+    LayerSortModes, ParallaxLayerComponent, RenderLayerComponent, ScreenSpaceComponent,
+    SpriteComponent, TextComponent, TilemapComponent, TransformComponent, WorldWrap,
+};
+#[cfg(feature = "backend-wgpu")]
+use crate::{
+    particle::ParticleEmitterComponent, scene::components::camera::PixelGrid, text::Fonts,
+    texture::TextureRegistry,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// 描画するものの種類。同じ層の中ではタイルマップ、スプライト、パーティクル、文字列、浮かぶ文字列の順に描画する
+pub enum DrawKind {
+    Tilemap,
+    Sprite,
+    Particles,
+    Text,
+    /// [`super::FloatingTextComponent`]。同じ層のものはまとめて描く
+    FloatingText,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 描画する 1 つのエンティティ
+pub struct DrawItem {
+    pub entity: EntityIndex,
+    pub layer: i32,
+    pub kind: DrawKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+/// 描画する順に並べたエンティティ
+///
+/// カメラに写らないものは含まない。[`super::System::render`] に渡される。
+pub struct DrawList {
+    items: Vec<DrawItem>,
+    /// カメラに写るか調べたスプライトの数
+    total_sprites: usize,
+}
+
+impl DrawList {
+    /// 層と種類で並べ替える。同じ層と種類の中では元の順序を保つ
+    pub fn new(mut items: Vec<DrawItem>) -> Self {
+        items.sort_by_key(|item| (item.layer, item.kind));
+        let total_sprites = items
+            .iter()
+            .filter(|item| item.kind == DrawKind::Sprite)
+            .count();
+        Self {
+            items,
+            total_sprites,
+        }
+    }
+
+    /// `world`のうち`frustum`に写るものを集める
+    ///
+    /// `screen_space` が `true` なら [`ScreenSpaceComponent`] を持つものだけ、`false` なら持たないものだけを集める。
+    /// 文字列の大きさを測るので、[`TextComponent`] は必要なら配置し直す。
+    /// `textures` を渡すと、写るスプライトの頂点もここで求めておく ([`ParallelPrepare`] を参照)。
+    /// `grid` を渡すと、[`SpriteComponent::pixel_snap`] なスプライトの位置をそのピクセルに合わせる。
+    /// リソース [`WorldWrap`] があれば、スプライトは継ぎ目の反対側にずらした位置も調べ、写る位置すべてに描く。
+    /// `camera` はワールドを写すカメラの位置で、[`ParallaxLayerComponent`] のスプライトをずらして調べる。
+    /// 横に並べる層は `grid` に写る範囲を埋める。
+    /// 並びのバッファは `arena` から借りるので、使い終わったら [`Self::into_items`] で返す。
+    #[cfg(feature = "backend-wgpu")]
+    pub(crate) fn build(
+        world: &hecs::World,
+        frustum: &Frustum,
+        screen_space: bool,
+        textures: Option<&TextureRegistry>,
+        grid: Option<&PixelGrid>,
+        camera: Option<Point2<f32>>,
+        arena: &mut FrameArena,
+    ) -> Self {
+        let mut items = arena.take();
+        let settings = resource::<ParallelPrepare>(world)
+            .map(|settings| *settings)
+            .unwrap_or_default();
+        let wrap = resource::<WorldWrap>(world)
+            .map(|wrap| *wrap)
+            .filter(|_| !screen_space);
+        let total_sprites = collect_sprites(
+            world,
+            &SpriteFilter {
+                frustum,
+                screen_space,
+                textures,
+                grid,
+                wrap,
+                camera,
+            },
+            settings,
+            &mut items,
+        );
+
+        for (entity, (transform, tilemap, layer, is_screen_space)) in world
+            .query::<(
+                &TransformComponent,
+                &TilemapComponent,
+                Option<&RenderLayerComponent>,
+                hecs::Satisfies<&ScreenSpaceComponent>,
+            )>()
+            .iter()
+        {
+            if is_screen_space != screen_space {
+                continue;
+            }
+            let (min, max) = tilemap.world_aabb(transform);
+            if frustum.intersects_aabb(&min, &max) {
+                items.push(DrawItem {
+                    entity: EntityIndex(entity),
+                    layer: layer.copied().unwrap_or_default().0,
+                    kind: DrawKind::Tilemap,
+                });
+            }
+        }
+
+        collect_particles(world, frustum, screen_space, textures.is_some(), &mut items);
+
+        // 文字列は Fonts が無ければ描画しない
+        if let Some(fonts) = resource::<Fonts>(world) {
+            for (entity, (transform, text, layer, is_screen_space)) in world
+                .query::<(
+                    &TransformComponent,
+                    &mut TextComponent,
+                    Option<&RenderLayerComponent>,
+                    hecs::Satisfies<&ScreenSpaceComponent>,
+                )>()
+                .iter()
+            {
+                if is_screen_space != screen_space {
+                    continue;
+                }
+                // 文字列を囲む四角形を、外接する球で判定する
+                let size = text.update_layout(&fonts).size();
+                let center = transform.to_affine3() * Point3::new(size.x / 2.0, size.y / 2.0, 0.0);
+                let radius = 0.5 * (size.x * transform.scale.x).hypot(size.y * transform.scale.y);
+                if frustum.intersects_sphere(&center, radius) {
+                    items.push(DrawItem {
+                        entity: EntityIndex(entity),
+                        layer: layer.copied().unwrap_or_default().0,
+                        kind: DrawKind::Text,
+                    });
+                }
+            }
+            for (entity, (transform, floating, layer, is_screen_space)) in world
+                .query::<(
+                    &TransformComponent,
+                    &mut FloatingTextComponent,
+                    Option<&RenderLayerComponent>,
+                    hecs::Satisfies<&ScreenSpaceComponent>,
+                )>()
+                .iter()
+            {
+                if !floating.is_active() || is_screen_space != screen_space {
+                    continue;
+                }
+                // 浮かぶ文字列は位置が中心になる
+                let size = floating.update_layout(&fonts).size();
+                let center = Point3::from(transform.translation.vector);
+                let radius = 0.5 * (size.x * transform.scale.x).hypot(size.y * transform.scale.y);
+                if frustum.intersects_sphere(&center, radius) {
+                    items.push(DrawItem {
+                        entity: EntityIndex(entity),
+                        layer: layer.copied().unwrap_or_default().0,
+                        kind: DrawKind::FloatingText,
+                    });
+                }
+            }
+        }
+        if let Some(modes) = resource::<LayerSortModes>(world) {
+            sort_by_y(&mut items, world, &modes);
+        }
+        Self {
+            total_sprites,
+            ..Self::new(items)
+        }
+    }
+
+    /// 描画するスプライトの数
+    pub fn visible_sprites(&self) -> usize {
+        self.items
+            .iter()
+            .filter(|item| item.kind == DrawKind::Sprite)
+            .count()
+    }
+
+    /// カメラに写るか調べたスプライトの数。写らなかったものも含む
+    pub const fn total_sprites(&self) -> usize {
+        self.total_sprites
+    }
+
+    pub fn items(&self) -> &[DrawItem] {
+        &self.items
+    }
+
+    pub fn into_items(self) -> Vec<DrawItem> {
+        self.items
+    }
+
+    /// `layers` の層のものを取り除く
+    ///
+    /// [`super::CameraComponent::hidden_layers`] に使う。[`Self::total_sprites`] は変えない。
+    pub fn hide_layers(&mut self, layers: &[i32]) {
+        if !layers.is_empty() {
+            self.items.retain(|item| !layers.contains(&item.layer));
+        }
+    }
+
+    /// 層ごとに分けた並び。層の小さい順
+    pub fn layers(&self) -> impl Iterator<Item = &[DrawItem]> {
+        self.items.chunk_by(|a, b| a.layer == b.layer)
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 描画するスプライトを選び、頂点を求める処理を並列に行うかどうか
+///
+/// リソースとして [`super::Scene::insert_resource`] で追加すると切り替えられる。無ければ既定値になる。
+/// 並列に処理するには feature `parallel` が必要で、無ければ常に 1 つのスレッドで処理する。
+/// 並列に処理しても、描画する順序と頂点は 1 つのスレッドで処理したときと同じになる。
+pub struct ParallelPrepare {
+    pub enabled: bool,
+    /// スプライトがこれより少なければ、スレッドを使う手間の方が大きいので 1 つのスレッドで処理する
+    pub min_sprites: usize,
+    /// 1 つのスレッドにまとめて渡すスプライトの数
+    pub chunk_size: u32,
+}
+
+impl Default for ParallelPrepare {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_sprites: 4096,
+            chunk_size: 1024,
+        }
+    }
+}
+
+#[cfg(feature = "backend-wgpu")]
+/// 集めるスプライトの条件
+struct SpriteFilter<'a> {
+    frustum: &'a Frustum,
+    screen_space: bool,
+    textures: Option<&'a TextureRegistry>,
+    /// [`SpriteComponent::pixel_snap`] で位置を合わせるピクセル
+    grid: Option<&'a PixelGrid>,
+    wrap: Option<WorldWrap>,
+    /// ワールドを写すカメラの位置。[`ParallaxLayerComponent`] の層をずらすのに使う
+    camera: Option<Point2<f32>>,
+}
+
+#[cfg(feature = "backend-wgpu")]
+type SpriteQuery<'a> = (
+    &'a TransformComponent,
+    &'a mut SpriteComponent,
+    Option<&'a RenderLayerComponent>,
+    hecs::Satisfies<&'a ScreenSpaceComponent>,
+    Option<&'a ParallaxLayerComponent>,
+);
+
+#[cfg(feature = "backend-wgpu")]
+impl SpriteFilter<'_> {
+    /// スプライトが条件に合えば、頂点を求めて [`DrawItem`] を返す
+    ///
+    /// 2 つ目の値は、[`DrawList::total_sprites`] に数えるかどうか。
+    fn visit(
+        &self,
+        entity: hecs::Entity,
+        (transform, sprite, layer, is_screen_space, parallax): (
+            &TransformComponent,
+            &mut SpriteComponent,
+            Option<&RenderLayerComponent>,
+            bool,
+            Option<&ParallaxLayerComponent>,
+        ),
+    ) -> (Option<DrawItem>, bool) {
+        if is_screen_space != self.screen_space {
+            return (None, false);
+        }
+        let (min, max) = sprite.world_aabb(transform);
+        let parallax = parallax.zip(self.camera);
+        // 継ぎ目でつながらなければ本来の位置だけを調べる。視差のある層は継ぎ目をまたがない
+        let (wraps, count) = self
+            .wrap
+            .filter(|_| parallax.is_none())
+            .map_or(([0.0; 3], 1), |wrap| (wrap.shifts(), 3));
+        let offset = parallax.map_or_else(Vector2::zeros, |(parallax, camera)| {
+            parallax.offset(&camera)
+        });
+        let period = max.x - min.x;
+        let tiles = match (parallax, self.grid) {
+            (Some((parallax, _)), Some(grid)) if parallax.repeat_x => tile_range(
+                (min.x + offset.x, max.x + offset.x),
+                period,
+                grid.visible_x(),
+            ),
+            _ => 0..=0,
+        };
+        let mut visible = tiles
+            .flat_map(|tile| {
+                wraps[..count].iter().map(move |&wrap| {
+                    offset + Vector2::new((tile as f32).mul_add(period, wrap), 0.0)
+                })
+            })
+            .filter(|shift| {
+                let shift = Vector3::new(shift.x, shift.y, 0.0);
+                self.frustum.intersects_aabb(&(min + shift), &(max + shift))
+            });
+        let Some(first) = visible.next() else {
+            return (None, true);
+        };
+        if let Some(textures) = self.textures {
+            let snap = self.grid.filter(|_| sprite.snaps(self.screen_space));
+            sprite.prepare(textures, transform, snap);
+            sprite.set_shifts(std::iter::once(first).chain(visible));
+        }
+        let item = DrawItem {
+            entity: EntityIndex(entity),
+            layer: layer.copied().unwrap_or_default().0,
+            kind: DrawKind::Sprite,
+        };
+        (Some(item), true)
+    }
+}
+
+/// 写るスプライトを `items` に加え、調べたスプライトの数を返す
+#[cfg(feature = "backend-wgpu")]
+fn collect_sprites(
+    world: &hecs::World,
+    filter: &SpriteFilter<'_>,
+    settings: ParallelPrepare,
+    items: &mut Vec<DrawItem>,
+) -> usize {
+    let mut query = world.query::<SpriteQuery<'_>>();
+    #[cfg(feature = "parallel")]
+    if settings.enabled && query.iter().len() >= settings.min_sprites {
+        use rayon::prelude::*;
+
+        let batches: Vec<_> = query.iter_batched(settings.chunk_size.max(1)).collect();
+        // スレッドごとに集めたものを、元の順序でつなぐ
+        let chunks: Vec<(Vec<DrawItem>, usize)> = batches
+            .into_par_iter()
+            .map(|batch| {
+                let mut chunk = Vec::new();
+                let mut total = 0;
+                for (entity, components) in batch {
+                    let (item, counted) = filter.visit(entity, components);
+                    total += usize::from(counted);
+                    chunk.extend(item);
+                }
+                (chunk, total)
+            })
+            .collect();
+        let mut total = 0;
+        for (chunk, count) in chunks {
+            items.extend(chunk);
+            total += count;
+        }
+        return total;
+    }
+    #[cfg(not(feature = "parallel"))]
+    let _ = settings;
+
+    let mut total = 0;
+    for (entity, components) in query.iter() {
+        let (item, counted) = filter.visit(entity, components);
+        total += usize::from(counted);
+        items.extend(item);
+    }
+    total
+}
+
+/// 写るパーティクルのエミッターを描画する順に `items` に加える
+///
+/// 重ね方が [`crate::particle::ParticleBlend::needs_sorting`] なものを `frustum` のカメラから遠い順に並べ、加算のものはその後に置く。
+/// 後で [`DrawList::new`] が層と種類で安定に並べ替えるので、層ごとにこの順序になる。
+/// `prepare` なら、エミッターの中のパーティクルもここで並べ替える。
+#[cfg(feature = "backend-wgpu")]
+fn collect_particles(
+    world: &hecs::World,
+    frustum: &Frustum,
+    screen_space: bool,
+    prepare: bool,
+    items: &mut Vec<DrawItem>,
+) {
+    let mut sorted = Vec::new();
+    let mut additive = Vec::new();
+    for (entity, (_, emitter, layer, is_screen_space)) in world
+        .query::<(
+            &TransformComponent,
+            &mut ParticleEmitterComponent,
+            Option<&RenderLayerComponent>,
+            hecs::Satisfies<&ScreenSpaceComponent>,
+        )>()
+        .iter()
+    {
+        if is_screen_space != screen_space {
+            continue;
+        }
+        let Some((min, max)) = emitter.world_aabb() else {
+            continue;
+        };
+        if !frustum.intersects_aabb(&min, &max) {
+            continue;
+        }
+        if prepare {
+            emitter.sort_back_to_front(frustum);
+        }
+        let item = DrawItem {
+            entity: EntityIndex(entity),
+            layer: layer.copied().unwrap_or_default().0,
+            kind: DrawKind::Particles,
+        };
+        if emitter.blend().needs_sorting() {
+            let center = Point3::from((min.coords + max.coords) / 2.0);
+            sorted.push((frustum.depth(&center), item));
+        } else {
+            additive.push(item);
+        }
+    }
+    sorted.sort_by(|a, b| b.0.total_cmp(&a.0));
+    items.extend(sorted.into_iter().map(|(_, item)| item));
+    items.extend(additive);
+}
+
+/// [`LayerSortMode::Y`] の層のスプライトを Y 座標の順に並べる
+///
+/// 後で [`DrawList::new`] が層と種類で安定に並べ替えるので、ほかの層の順序は変わらない。
+#[cfg(feature = "backend-wgpu")]
+fn sort_by_y(items: &mut [DrawItem], world: &hecs::World, modes: &LayerSortModes) {
+    if !modes.any_y() {
+        return;
+    }
+    let mut keyed: Vec<(f32, DrawItem)> = items
+        .iter()
+        .map(|&item| {
+            let entity = item.entity.0;
+            let key = match (
+                world.get::<&TransformComponent>(entity),
+                world.get::<&SpriteComponent>(entity),
+            ) {
+                (Ok(transform), Ok(sprite))
+                    if item.kind == DrawKind::Sprite
+                        && modes.get(item.layer) == LayerSortMode::Y =>
+                {
+                    sprite.sort_y(&transform)
+                }
+                _ => 0.0,
+            };
+            (key, item)
+        })
+        .collect();
+    keyed.sort_by(|a, b| a.0.total_cmp(&b.0));
+    for (item, (_, sorted)) in items.iter_mut().zip(keyed) {
+        *item = sorted;
+    }
+}
+
+#[cfg(all(test, feature = "backend-wgpu"))]
+mod tests {
+    use nalgebra::{Matrix4, Translation3};
+    use reverie_util::math::Rect;
+
+    use super::*;
+    use crate::texture::{TextureId, TextureRegistry};
+
+    fn sprite() -> SpriteComponent {
+        let mut registry = TextureRegistry::default();
+        let texture = registry.new_texture(image::RgbaImage::new(1, 1), None);
+        SpriteComponent::new(TextureId::Single(texture))
+    }
+
+    #[test]
+    fn parallax_layers_shift_with_camera() {
+        let mut world = hecs::World::new();
+        let at = |x| TransformComponent::with_translation(Translation3::new(x, 0.0, 0.0));
+        // カメラに付いてくる層は、元の位置が写る範囲の外でも写る
+        let hud = world.spawn((at(3.0), sprite(), ParallaxLayerComponent::new(0.0, 0.0)));
+        let world_sprite = world.spawn((at(3.0), sprite()));
+        // 遠くに置いた背景も、横に並べれば写る範囲に届く
+        let far = world.spawn((
+            at(40.0),
+            sprite(),
+            ParallaxLayerComponent::new(0.5, 1.0).repeat_x(),
+        ));
+        let lone = world.spawn((at(40.0), sprite(), ParallaxLayerComponent::new(0.5, 1.0)));
+
+        let frustum = Frustum::from_matrix(&Matrix4::identity());
+        let grid = PixelGrid::new(
+            Matrix4::identity(),
+            Rect::new(Point2::origin(), Point2::new(100.0, 100.0)),
+        );
+        let build = |camera| {
+            let list = DrawList::build(
+                &world,
+                &frustum,
+                false,
+                None,
+                Some(&grid),
+                camera,
+                &mut FrameArena::default(),
+            );
+            let mut entities: Vec<_> = list.items().iter().map(|item| item.entity.0).collect();
+            entities.sort();
+            entities
+        };
+        let mut expected = vec![hud, far];
+        expected.sort();
+        assert_eq!(build(Some(Point2::new(-3.0, 0.0))), expected);
+        assert!(!build(Some(Point2::new(-3.0, 0.0))).contains(&lone));
+        assert!(!build(Some(Point2::new(-3.0, 0.0))).contains(&world_sprite));
+        // カメラの位置を渡さなければずらさない
+        assert_eq!(build(None), Vec::<hecs::Entity>::new());
+    }
+
+    #[test]
+    fn sorts_by_layer_and_culls() {
+        let mut world = hecs::World::new();
+        let at = |x| TransformComponent::with_translation(Translation3::new(x, 0.0, 0.0));
+        let units = world.spawn((at(0.0), sprite(), RenderLayerComponent(1)));
+        let tiles = world.spawn((at(0.0), sprite()));
+        world.spawn((at(100.0), sprite()));
+        let ui = world.spawn((at(0.0), sprite(), ScreenSpaceComponent));
+
+        // -1 から 1 の範囲を写す
+        let frustum = Frustum::from_matrix(&Matrix4::identity());
+        let list = DrawList::build(
+            &world,
+            &frustum,
+            false,
+            None,
+            None,
+            None,
+            &mut FrameArena::default(),
+        );
+        let entities: Vec<_> = list.items().iter().map(|item| item.entity.0).collect();
+        assert_eq!(entities, [tiles, units]);
+        assert_eq!((list.visible_sprites(), list.total_sprites()), (2, 3));
+        assert_eq!(
+            list.layers()
+                .map(|items| items[0].layer)
+                .collect::<Vec<_>>(),
+            [0, 1]
+        );
+
+        let list = DrawList::build(
+            &world,
+            &frustum,
+            true,
+            None,
+            None,
+            None,
+            &mut FrameArena::default(),
+        );
+        assert_eq!(list.items()[0].entity.0, ui);
+    }
+
+    #[test]
+    fn y_sorted_layer_orders_by_feet() {
+        let mut world = hecs::World::new();
+        let at = |y| TransformComponent::with_translation(Translation3::new(0.0, y, 0.0));
+        let low = world.spawn((at(0.5), sprite(), RenderLayerComponent(1)));
+        let high = world.spawn((at(-0.5), sprite(), RenderLayerComponent(1)));
+        // 中心は上だが、足元は low より下
+        let tall = world.spawn((
+            at(0.0),
+            SpriteComponent::builder(TextureId::WHITE)
+                .sort_offset(0.6)
+                .build(),
+            RenderLayerComponent(1),
+        ));
+        let ground_a = world.spawn((at(0.5), sprite()));
+        let ground_b = world.spawn((at(-0.5), sprite()));
+
+        let mut modes = LayerSortModes::default();
+        modes.set(1, LayerSortMode::Y);
+        crate::scene::insert_resource(&mut world, modes);
+
+        let frustum = Frustum::from_matrix(&Matrix4::identity());
+        let list = DrawList::build(
+            &world,
+            &frustum,
+            false,
+            None,
+            None,
+            None,
+            &mut FrameArena::default(),
+        );
+        let entities: Vec<_> = list.items().iter().map(|item| item.entity.0).collect();
+        assert_eq!(entities, [ground_a, ground_b, high, low, tall]);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_prepare_matches_serial() {
+        let mut registry = TextureRegistry::default();
+        let texture = TextureId::Single(registry.new_texture(image::RgbaImage::new(4, 4), None));
+        let mut world = hecs::World::new();
+        for i in 0..500_u16 {
+            let x = f32::from(i % 25) * 0.1 - 1.2;
+            let y = f32::from(i / 25) * 0.1 - 1.0;
+            let transform = TransformComponent::with_translation(Translation3::new(x, y, 0.0));
+            let sprite = SpriteComponent::builder(texture)
+                .size(0.05 + f32::from(i % 3) * 0.01, 0.05)
+                .skew(f32::from(i % 5) * 0.01, 0.0)
+                .build();
+            world.spawn((transform, sprite, RenderLayerComponent(i32::from(i % 4))));
+        }
+
+        let frustum = Frustum::from_matrix(&Matrix4::identity());
+        let mut build = |settings: ParallelPrepare| {
+            crate::scene::insert_resource(&mut world, settings);
+            let list = DrawList::build(
+                &world,
+                &frustum,
+                false,
+                Some(&registry),
+                None,
+                None,
+                &mut FrameArena::default(),
+            );
+            let mut bytes = Vec::<u8>::new();
+            for item in list.items() {
+                let sprite = world.get::<&SpriteComponent>(item.entity.0).unwrap();
+                bytes.extend_from_slice(bytemuck::cast_slice(sprite.prepared().unwrap()));
+            }
+            (list.items().to_vec(), list.total_sprites(), bytes)
+        };
+        let serial = build(ParallelPrepare {
+            enabled: false,
+            ..ParallelPrepare::default()
+        });
+        let parallel = build(ParallelPrepare {
+            enabled: true,
+            min_sprites: 0,
+            chunk_size: 7,
+        });
+        assert!(serial.0.len() > 100 && serial.0.len() < 500);
+        assert_eq!(serial, parallel);
+    }
+}
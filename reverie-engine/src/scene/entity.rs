@@ -1,3 +1,3 @@
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 /// [`super::Scene`]に登録したエンティティのインデックス
 pub struct EntityIndex(pub hecs::Entity);
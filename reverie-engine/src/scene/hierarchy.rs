@@ -0,0 +1,479 @@
+//! エンティティの親子関係
+//!
+//! 子は親から見た位置を [`LocalTransformComponent`] に持ち、[`TransformComponent`] には
+//! [`propagate_transforms`] がワールドの変換を書き込む。描画や他のシステムはこれまで通り
+//! [`TransformComponent`] を読めばよい。
+//!
+//! 親子関係は [`set_parent`] などの関数か [`super::Scene`] の同名のメソッドで変更する。
+//! コンポーネントを直接付け外しすると親と子の記録が食い違う。
+
+use super::{EntityIndex, EntityMap, FrameArena, MapEntities, TransformComponent};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 親のエンティティ
+pub struct ParentComponent {
+    parent: EntityIndex,
+}
+
+impl ParentComponent {
+    pub const fn parent(&self) -> EntityIndex {
+        self.parent
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// 子のエンティティの一覧。親子関係を作った順に並ぶ
+pub struct ChildrenComponent {
+    children: Vec<EntityIndex>,
+}
+
+impl ChildrenComponent {
+    pub fn children(&self) -> &[EntityIndex] {
+        &self.children
+    }
+}
+
+impl MapEntities for ParentComponent {
+    fn map_entities(&mut self, map: &EntityMap) {
+        self.parent = map.map(self.parent);
+    }
+}
+
+impl MapEntities for ChildrenComponent {
+    fn map_entities(&mut self, map: &EntityMap) {
+        // 元のシーンで既に削除されていた子は落とす
+        self.children = self
+            .children
+            .iter()
+            .filter_map(|&child| map.get(child))
+            .collect();
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// 親から見た子の変換
+///
+/// 子を動かすときはこちらを書き換える。
+pub struct LocalTransformComponent(pub TransformComponent);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// 子を持つエンティティを [`despawn`] したときに子をどうするか
+pub enum OrphanPolicy {
+    /// 子を親から外してルートにする。子のワールドでの変換はそのまま保たれる
+    #[default]
+    DetachToRoot,
+    /// 削除せずに [`HierarchyError::HasChildren`] を返す
+    Forbid,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 親子関係の操作に失敗した
+pub enum HierarchyError {
+    /// エンティティが存在しない
+    NoSuchEntity(EntityIndex),
+    /// [`OrphanPolicy::Forbid`] のもとで子を持つエンティティを削除しようとした
+    HasChildren(EntityIndex),
+    /// `child` を `parent` の子にすると親子関係が循環する
+    Cycle {
+        child: EntityIndex,
+        parent: EntityIndex,
+    },
+}
+
+impl std::fmt::Display for HierarchyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoSuchEntity(entity) => write!(f, "entity {:?} does not exist", entity.0),
+            Self::HasChildren(entity) => {
+                write!(
+                    f,
+                    "entity {:?} has children and cannot be despawned",
+                    entity.0
+                )
+            }
+            Self::Cycle { child, parent } => write!(
+                f,
+                "making {:?} a child of {:?} would create a cycle",
+                child.0, parent.0
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HierarchyError {}
+
+fn ensure_exists(world: &hecs::World, entity: EntityIndex) -> Result<(), HierarchyError> {
+    if world.contains(entity.0) {
+        Ok(())
+    } else {
+        Err(HierarchyError::NoSuchEntity(entity))
+    }
+}
+
+/// 親のエンティティ
+pub fn parent(world: &hecs::World, entity: EntityIndex) -> Option<EntityIndex> {
+    world
+        .get::<&ParentComponent>(entity.0)
+        .ok()
+        .map(|p| p.parent)
+}
+
+/// 子のエンティティ
+pub fn children(world: &hecs::World, entity: EntityIndex) -> Vec<EntityIndex> {
+    world
+        .get::<&ChildrenComponent>(entity.0)
+        .map(|c| c.children.clone())
+        .unwrap_or_default()
+}
+
+/// `ancestor` が `entity` 自身かその祖先か
+fn is_ancestor_or_self(world: &hecs::World, ancestor: EntityIndex, entity: EntityIndex) -> bool {
+    let mut current = Some(entity);
+    while let Some(e) = current {
+        if e == ancestor {
+            return true;
+        }
+        current = parent(world, e);
+    }
+    false
+}
+
+/// 親を辿ってワールドでの変換を計算する
+///
+/// [`propagate_transforms`] を待たずに、このフレームで書き換えた [`LocalTransformComponent`] を反映した値が得られる。
+pub fn world_transform(world: &hecs::World, entity: EntityIndex) -> TransformComponent {
+    let local = world
+        .get::<&LocalTransformComponent>(entity.0)
+        .ok()
+        .map(|l| l.0.clone());
+    match (parent(world, entity), local) {
+        (Some(parent), Some(local)) if world.contains(parent.0) => {
+            world_transform(world, parent).compose(&local)
+        }
+        _ => world
+            .get::<&TransformComponent>(entity.0)
+            .map_or_else(|_| TransformComponent::default(), |t| (*t).clone()),
+    }
+}
+
+/// 親の子の一覧から外し、親子関係のコンポーネントを取り除く
+fn unlink(world: &mut hecs::World, child: EntityIndex) {
+    let Some(parent) = parent(world, child) else {
+        return;
+    };
+    if let Ok(mut children) = world.get::<&mut ChildrenComponent>(parent.0) {
+        children.children.retain(|&c| c != child);
+    }
+    let _ = world.remove_one::<ParentComponent>(child.0);
+    let _ = world.remove_one::<LocalTransformComponent>(child.0);
+}
+
+/// `child` を `parent` の子にする
+///
+/// 既に親があれば付け替える。`child` のワールドでの変換は変わらないように
+/// [`LocalTransformComponent`] が決まる。
+pub fn set_parent(
+    world: &mut hecs::World,
+    child: EntityIndex,
+    parent: EntityIndex,
+) -> Result<(), HierarchyError> {
+    ensure_exists(world, child)?;
+    ensure_exists(world, parent)?;
+    if is_ancestor_or_self(world, child, parent) {
+        return Err(HierarchyError::Cycle { child, parent });
+    }
+
+    let child_world = world_transform(world, child);
+    let parent_world = world_transform(world, parent);
+    unlink(world, child);
+    let local = LocalTransformComponent(parent_world.relative(&child_world));
+    world
+        .insert(child.0, (ParentComponent { parent }, local, child_world))
+        .expect("the child was checked to exist");
+
+    if let Ok(mut children) = world.get::<&mut ChildrenComponent>(parent.0) {
+        children.children.push(child);
+        return Ok(());
+    }
+    world
+        .insert_one(
+            parent.0,
+            ChildrenComponent {
+                children: vec![child],
+            },
+        )
+        .expect("the parent was checked to exist");
+    Ok(())
+}
+
+/// `child` を親から外してルートにする。ワールドでの変換はそのまま保たれる
+pub fn remove_parent(world: &mut hecs::World, child: EntityIndex) -> Result<(), HierarchyError> {
+    ensure_exists(world, child)?;
+    if parent(world, child).is_none() {
+        return Ok(());
+    }
+    let child_world = world_transform(world, child);
+    unlink(world, child);
+    world
+        .insert_one(child.0, child_world)
+        .expect("the child was checked to exist");
+    Ok(())
+}
+
+/// エンティティを削除する。子は`policy`に従って扱う
+pub fn despawn(
+    world: &mut hecs::World,
+    entity: EntityIndex,
+    policy: OrphanPolicy,
+) -> Result<(), HierarchyError> {
+    ensure_exists(world, entity)?;
+    let children = children(world, entity);
+    if !children.is_empty() {
+        match policy {
+            OrphanPolicy::Forbid => return Err(HierarchyError::HasChildren(entity)),
+            OrphanPolicy::DetachToRoot => {
+                for child in children {
+                    if world.contains(child.0) {
+                        remove_parent(world, child)?;
+                    }
+                }
+            }
+        }
+    }
+    unlink(world, entity);
+    world
+        .despawn(entity.0)
+        .expect("the entity was checked to exist");
+    Ok(())
+}
+
+/// エンティティとその子孫をすべて削除する
+pub fn despawn_recursive(
+    world: &mut hecs::World,
+    entity: EntityIndex,
+) -> Result<(), HierarchyError> {
+    ensure_exists(world, entity)?;
+    unlink(world, entity);
+    let mut stack = vec![entity];
+    while let Some(e) = stack.pop() {
+        stack.extend(children(world, e));
+        // 他の方法で先に削除された子は記録だけ残っていることがある
+        let _ = world.despawn(e.0);
+    }
+    Ok(())
+}
+
+/// 子の [`TransformComponent`] に親から辿ったワールドの変換を書き込む
+///
+/// [`super::Scene::update`] がすべてのシステムの後に呼ぶ。
+/// 親子関係の関数を使わずに削除されたエンティティの記録もここで片付ける。
+/// 親が無くなった子はルートになり、最後に計算されたワールドの変換に留まる。
+pub fn propagate_transforms(world: &mut hecs::World) {
+    propagate_transforms_in(world, &mut FrameArena::default());
+}
+
+/// [`propagate_transforms`] と同じだが、作業用のバッファを `arena` から借りる
+pub(super) fn propagate_transforms_in(world: &mut hecs::World, arena: &mut FrameArena) {
+    let mut orphans = arena.take();
+    orphans.extend(
+        world
+            .query::<&ParentComponent>()
+            .iter()
+            .filter(|(_, p)| !world.contains(p.parent.0))
+            .map(|(entity, _)| entity),
+    );
+    for &entity in &orphans {
+        let _ = world.remove_one::<ParentComponent>(entity);
+        let _ = world.remove_one::<LocalTransformComponent>(entity);
+    }
+    arena.recycle(orphans);
+
+    let mut roots = arena.take();
+    roots.extend(
+        world
+            .query::<Option<&TransformComponent>>()
+            .with::<&ChildrenComponent>()
+            .without::<&ParentComponent>()
+            .iter()
+            .map(|(root, transform)| (root, transform.cloned().unwrap_or_default())),
+    );
+    let mut despawned = arena.take();
+    let mut stack = arena.take();
+    {
+        let children = world.view::<&ChildrenComponent>();
+        let mut transforms = world.view::<(&LocalTransformComponent, &mut TransformComponent)>();
+        for root in &roots {
+            stack.push(root.clone());
+            while let Some((parent, parent_world)) = stack.pop() {
+                let Some(list) = children.get(parent) else {
+                    continue;
+                };
+                for &child in &list.children {
+                    if !world.contains(child.0) {
+                        despawned.push((parent, child));
+                        continue;
+                    }
+                    if let Some((local, transform)) = transforms.get_mut(child.0) {
+                        *transform = parent_world.compose(&local.0);
+                        stack.push((child.0, transform.clone()));
+                    }
+                }
+            }
+        }
+    }
+    for &(parent, child) in &despawned {
+        if let Ok(mut list) = world.get::<&mut ChildrenComponent>(parent) {
+            list.children.retain(|&c| c != child);
+        }
+    }
+    arena.recycle(stack);
+    arena.recycle(despawned);
+    arena.recycle(roots);
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Scale3, Translation3, Vector3};
+
+    use super::*;
+
+    fn at(x: f32) -> TransformComponent {
+        TransformComponent::with_translation(Translation3::new(x, 0.0, 0.0))
+    }
+
+    fn x(world: &hecs::World, entity: EntityIndex) -> f32 {
+        world
+            .get::<&TransformComponent>(entity.0)
+            .unwrap()
+            .translation
+            .x
+    }
+
+    /// ルート (x = 100, 2 倍) → 中間 (ワールドで x = 120) → 葉 (ワールドで x = 130)
+    fn three_levels(world: &mut hecs::World) -> [EntityIndex; 3] {
+        let root = EntityIndex(world.spawn((TransformComponent::with_translation_and_scale(
+            Translation3::new(100.0, 0.0, 0.0),
+            Scale3::new(2.0, 2.0, 1.0),
+        ),)));
+        let middle = EntityIndex(world.spawn((at(120.0),)));
+        let leaf = EntityIndex(world.spawn((at(130.0),)));
+        set_parent(world, middle, root).unwrap();
+        set_parent(world, leaf, middle).unwrap();
+        [root, middle, leaf]
+    }
+
+    #[test]
+    fn propagates_and_despawns_three_levels() {
+        let mut world = hecs::World::new();
+        let [root, middle, leaf] = three_levels(&mut world);
+        assert_eq!(
+            world
+                .get::<&LocalTransformComponent>(middle.0)
+                .unwrap()
+                .0
+                .translation
+                .vector,
+            Vector3::new(10.0, 0.0, 0.0)
+        );
+        assert_eq!(
+            set_parent(&mut world, root, leaf),
+            Err(HierarchyError::Cycle {
+                child: root,
+                parent: leaf
+            })
+        );
+
+        world
+            .get::<&mut TransformComponent>(root.0)
+            .unwrap()
+            .translation
+            .x = 0.0;
+        propagate_transforms(&mut world);
+        assert_eq!(x(&world, middle), 20.0);
+        assert_eq!(x(&world, leaf), 30.0);
+        assert_eq!(
+            world.get::<&TransformComponent>(leaf.0).unwrap().scale.x,
+            1.0
+        );
+
+        assert_eq!(
+            despawn(&mut world, middle, OrphanPolicy::Forbid),
+            Err(HierarchyError::HasChildren(middle))
+        );
+        despawn(&mut world, leaf, OrphanPolicy::Forbid).unwrap();
+        assert!(children(&world, middle).is_empty());
+
+        let leaf = EntityIndex(world.spawn((at(0.0),)));
+        set_parent(&mut world, leaf, middle).unwrap();
+        despawn_recursive(&mut world, middle).unwrap();
+        assert!(!world.contains(middle.0));
+        assert!(!world.contains(leaf.0));
+        assert!(children(&world, root).is_empty());
+        assert_eq!(
+            despawn_recursive(&mut world, middle),
+            Err(HierarchyError::NoSuchEntity(middle))
+        );
+    }
+
+    #[test]
+    fn detached_children_keep_world_transform() {
+        let mut world = hecs::World::new();
+        let [root, middle, leaf] = three_levels(&mut world);
+        // 伝播する前に動かしても、削除の時点の位置で切り離される
+        world
+            .get::<&mut LocalTransformComponent>(middle.0)
+            .unwrap()
+            .0
+            .translation
+            .x = 20.0;
+
+        despawn(&mut world, middle, OrphanPolicy::DetachToRoot).unwrap();
+        assert_eq!(parent(&world, leaf), None);
+        assert!(children(&world, root).is_empty());
+        assert_eq!(x(&world, leaf), 150.0);
+
+        propagate_transforms(&mut world);
+        assert_eq!(x(&world, leaf), 150.0);
+    }
+
+    #[test]
+    fn reparenting_in_the_same_frame_as_despawn() {
+        let mut world = hecs::World::new();
+        let [root, middle, leaf] = three_levels(&mut world);
+        let other = EntityIndex(world.spawn((at(-50.0),)));
+
+        // 伝播を挟まずに葉を付け替えてから元の親を削除する
+        set_parent(&mut world, leaf, other).unwrap();
+        despawn_recursive(&mut world, middle).unwrap();
+        assert!(world.contains(leaf.0));
+        assert_eq!(parent(&world, leaf), Some(other));
+        assert_eq!(children(&world, other), [leaf]);
+
+        // 親を削除したのと同じフレームで、その子を別の親に移す
+        let middle = EntityIndex(world.spawn((at(120.0),)));
+        set_parent(&mut world, middle, root).unwrap();
+        set_parent(&mut world, leaf, middle).unwrap();
+        despawn(&mut world, root, OrphanPolicy::DetachToRoot).unwrap();
+        set_parent(&mut world, middle, other).unwrap();
+        world
+            .get::<&mut TransformComponent>(other.0)
+            .unwrap()
+            .translation
+            .x = 0.0;
+        propagate_transforms(&mut world);
+        assert_eq!(x(&world, middle), 170.0);
+        assert_eq!(x(&world, leaf), 180.0);
+    }
+
+    #[test]
+    fn cleans_up_after_plain_despawn() {
+        let mut world = hecs::World::new();
+        let [root, middle, leaf] = three_levels(&mut world);
+        // システムが親子関係を知らずに削除した場合
+        world.despawn(middle.0).unwrap();
+        propagate_transforms(&mut world);
+        assert!(children(&world, root).is_empty());
+        assert_eq!(parent(&world, leaf), None);
+        assert_eq!(x(&world, leaf), 130.0);
+    }
+}
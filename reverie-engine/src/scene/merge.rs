@@ -0,0 +1,237 @@
+//! 別のシーンのエンティティを取り込む
+use std::{any::TypeId, collections::HashMap};
+
+use super::{
+    resource, ChildrenComponent, EntityIndex, ParentComponent, Scene, ScreenSpaceComponent,
+    TransformComponent,
+};
+
+/// [`Scene::merge`] で移したエンティティの、元のシーンでのインデックスから新しいインデックスへの対応
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EntityMap {
+    map: HashMap<EntityIndex, EntityIndex>,
+}
+
+impl EntityMap {
+    /// 元のシーンの `old` を移した先。移していなければ `None`
+    pub fn get(&self, old: EntityIndex) -> Option<EntityIndex> {
+        self.map.get(&old).copied()
+    }
+
+    /// 元のシーンの `old` を移した先。移していなければ、どのエンティティも指さないインデックス
+    ///
+    /// 元のシーンで既に削除されていたエンティティを指す記録に使う。
+    pub fn map(&self, old: EntityIndex) -> EntityIndex {
+        self.get(old).unwrap_or(EntityIndex(hecs::Entity::DANGLING))
+    }
+
+    /// `(元のインデックス, 新しいインデックス)` の組
+    pub fn iter(&self) -> impl Iterator<Item = (EntityIndex, EntityIndex)> + '_ {
+        self.map.iter().map(|(old, new)| (*old, *new))
+    }
+
+    pub(super) fn insert(&mut self, old: EntityIndex, new: EntityIndex) {
+        self.map.insert(old, new);
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+/// [`EntityIndex`] を持つコンポーネント
+///
+/// [`Scene::register_entity_refs`] で登録しておくと、[`Scene::merge`] が移した後のインデックスに書き換える。
+/// 親子関係のコンポーネントは登録しなくても書き換える。
+///
+/// ```ignore
+/// struct DoorComponent { to: EntityIndex }
+///
+/// impl MapEntities for DoorComponent {
+///     fn map_entities(&mut self, map: &EntityMap) {
+///         self.to = map.map(self.to);
+///     }
+/// }
+/// ```
+pub trait MapEntities {
+    fn map_entities(&mut self, map: &EntityMap);
+}
+
+/// 登録されたコンポーネントの [`MapEntities::map_entities`] を呼ぶ関数
+pub(super) type EntityMapper = fn(&mut hecs::World, &EntityMap);
+
+pub(super) fn entity_mapper<C: MapEntities + hecs::Component>() -> (TypeId, EntityMapper) {
+    (TypeId::of::<C>(), map_component::<C>)
+}
+
+fn map_component<C: MapEntities + hecs::Component>(world: &mut hecs::World, map: &EntityMap) {
+    for (_, new) in map.iter() {
+        if let Ok(mut component) = world.get::<&mut C>(new.0) {
+            component.map_entities(map);
+        }
+    }
+}
+
+impl Scene {
+    /// `C` が持つ [`EntityIndex`] を [`Self::merge`] で書き換えるように登録する
+    ///
+    /// 取り込む側のシーンに登録する。
+    pub fn register_entity_refs<C: MapEntities + hecs::Component>(&mut self) {
+        let (type_id, mapper) = entity_mapper::<C>();
+        if !self.entity_mappers.iter().any(|(t, _)| *t == type_id) {
+            self.entity_mappers.push((type_id, mapper));
+        }
+    }
+
+    /// `other` のエンティティをすべてこのシーンに移す
+    ///
+    /// [`TransformComponent`] には `offset` を合成するので、`other` 全体が `offset` だけ動いた位置に来る。
+    /// 子の [`super::LocalTransformComponent`] と、[`ScreenSpaceComponent`] を持つものの位置はそのまま。
+    /// 親子関係と [`Self::register_entity_refs`] で登録したコンポーネントの [`EntityIndex`] は
+    /// 移した先のインデックスに書き換える。`other` のリソース、システム、アクティブなカメラは移さない。
+    /// 同じプレハブを何度取り込んでもよい。戻り値で元のインデックスから新しいインデックスを引ける。
+    pub fn merge(&mut self, mut other: Self, offset: TransformComponent) -> EntityMap {
+        let holder = resource::holder(&other.world);
+        let entities: Vec<_> = other
+            .world
+            .iter()
+            .map(|entity| entity.entity())
+            .filter(|entity| Some(*entity) != holder)
+            .collect();
+        let mut map = EntityMap::default();
+        for entity in entities {
+            let taken = other
+                .world
+                .take(entity)
+                .expect("the entity was listed just now");
+            let new = self.world.spawn(taken);
+            map.map.insert(EntityIndex(entity), EntityIndex(new));
+        }
+
+        for (_, new) in map.iter() {
+            if let Ok(mut parent) = self.world.get::<&mut ParentComponent>(new.0) {
+                parent.map_entities(&map);
+            }
+            if let Ok(mut children) = self.world.get::<&mut ChildrenComponent>(new.0) {
+                children.map_entities(&map);
+            }
+            let Ok((transform, screen_space)) = self
+                .world
+                .query_one_mut::<(&mut TransformComponent, Option<&ScreenSpaceComponent>)>(new.0)
+            else {
+                continue;
+            };
+            if screen_space.is_none() {
+                // 子もワールドの変換を持つので、すべてに合成すれば親子の関係は崩れない
+                *transform = offset.compose(transform);
+            }
+        }
+        for (_, mapper) in &self.entity_mappers {
+            mapper(&mut self.world, &map);
+        }
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Translation3;
+
+    use super::*;
+    use crate::scene::{resource, LocalTransformComponent};
+
+    #[derive(Debug)]
+    struct DoorComponent {
+        to: EntityIndex,
+    }
+
+    impl MapEntities for DoorComponent {
+        fn map_entities(&mut self, map: &EntityMap) {
+            self.to = map.map(self.to);
+        }
+    }
+
+    fn at(x: f32) -> TransformComponent {
+        TransformComponent::with_translation(Translation3::new(x, 0.0, 0.0))
+    }
+
+    fn x(scene: &Scene, entity: EntityIndex) -> f32 {
+        scene
+            .world
+            .get::<&TransformComponent>(entity.0)
+            .unwrap()
+            .translation
+            .x
+    }
+
+    /// 扉と、その子のランプがある部屋
+    fn room() -> (Scene, EntityIndex, EntityIndex) {
+        let mut room = Scene::default();
+        let door = EntityIndex(room.world.spawn((at(1.0),)));
+        let lamp = EntityIndex(room.world.spawn((at(3.0),)));
+        room.set_parent(lamp, door).unwrap();
+        room.world
+            .insert_one(door.0, DoorComponent { to: lamp })
+            .unwrap();
+        room.insert_resource(7_u32);
+        (room, door, lamp)
+    }
+
+    #[test]
+    fn merges_rooms_at_offsets() {
+        let mut dungeon = Scene::default();
+        dungeon.register_entity_refs::<DoorComponent>();
+        dungeon.insert_resource(1_u32);
+
+        let (first, door, lamp) = room();
+        let first = dungeon.merge(first, at(100.0));
+        let (second, _, _) = room();
+        let second = dungeon.merge(second, at(200.0));
+        assert_eq!(first.len(), 2);
+
+        // 同じ部屋を 2 回取り込んでも別のエンティティになる
+        let (door1, lamp1) = (first.get(door).unwrap(), first.get(lamp).unwrap());
+        let (door2, lamp2) = (second.get(door).unwrap(), second.get(lamp).unwrap());
+        assert_ne!(door1, door2);
+        assert_eq!(dungeon.parent(lamp1), Some(door1));
+        assert_eq!(dungeon.children(door2), [lamp2]);
+        assert_eq!(
+            dungeon.world.get::<&DoorComponent>(door2.0).unwrap().to,
+            lamp2
+        );
+
+        assert_eq!(x(&dungeon, door1), 101.0);
+        assert_eq!(x(&dungeon, lamp2), 203.0);
+        let local = dungeon
+            .world
+            .get::<&LocalTransformComponent>(lamp2.0)
+            .unwrap()
+            .0
+            .translation
+            .x;
+        assert_eq!(local, 2.0);
+        // 親子関係を辿り直しても同じ位置になる
+        dungeon.update_headless(&crate::scene::Frame::new(
+            web_time::Instant::now(),
+            std::time::Duration::ZERO,
+        ));
+        assert_eq!(x(&dungeon, lamp2), 203.0);
+
+        // 部屋のリソースは持ち込まない
+        assert_eq!(*resource::<u32>(&dungeon.world).unwrap(), 1);
+    }
+
+    #[test]
+    fn screen_space_entities_keep_their_position() {
+        let mut hud = Scene::default();
+        let label = EntityIndex(hud.world.spawn((at(8.0), ScreenSpaceComponent)));
+        let mut scene = Scene::default();
+        let map = scene.merge(hud, at(100.0));
+        assert_eq!(x(&scene, map.get(label).unwrap()), 8.0);
+        assert_eq!(map.get(EntityIndex(hecs::Entity::DANGLING)), None);
+    }
+}
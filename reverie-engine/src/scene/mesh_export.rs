@@ -0,0 +1,185 @@
+//! 描画するスプライトの四角形を PLY に書き出す。メッシュを Blender などで開いて調べるため
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use super::{remove_resource, DrawKind, DrawList, EntityIndex, Scene, SpriteComponent};
+use crate::wgpu_wrapper::vertex::UvVertex;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// 次の [`Scene::prepare_sprites`] で準備するスプライトを、一度だけ PLY に書き出す要求
+///
+/// [`Scene::dump_sprite_batch`] で入れ、書き出すと取り除かれる。
+pub struct SpriteBatchDump {
+    pub path: PathBuf,
+}
+
+impl Scene {
+    /// 次のフレームでカメラに写るスプライトの四角形を、`path` に PLY で書き出す
+    ///
+    /// 頂点は描画するときと同じワールド座標で、色はスプライトの色合い。
+    /// 継ぎ目の反対側や視差の層で並べたものも含む。[`super::ScreenSpaceComponent`] の UI は含まない。
+    /// 書き出せなければログに残して描画は続ける。
+    pub fn dump_sprite_batch(&mut self, path: impl Into<PathBuf>) {
+        self.insert_resource(SpriteBatchDump { path: path.into() });
+    }
+}
+
+/// 1 つのスプライトの四角形
+#[derive(Debug)]
+struct SpriteQuads {
+    entity: EntityIndex,
+    layer: i32,
+    quads: Vec<[UvVertex; 4]>,
+}
+
+/// [`SpriteBatchDump`] があれば、`draw_list` のスプライトを書き出す
+pub(super) fn dump_requested(
+    world: &mut hecs::World,
+    draw_list: &DrawList,
+    camera: Option<EntityIndex>,
+) {
+    let Some(dump) = remove_resource::<SpriteBatchDump>(world) else {
+        return;
+    };
+    let sprites: Vec<_> = draw_list
+        .items()
+        .iter()
+        .filter(|item| item.kind == DrawKind::Sprite)
+        .filter_map(|item| {
+            let sprite = world.get::<&SpriteComponent>(item.entity.0).ok()?;
+            Some(SpriteQuads {
+                entity: item.entity,
+                layer: item.layer,
+                quads: sprite.batched_quads(),
+            })
+        })
+        .collect();
+    let source = camera.map_or_else(
+        || "screen view (no camera)".to_owned(),
+        |camera| format!("camera entity {:?}", camera.0),
+    );
+    if let Err(error) = export_ply(&dump.path, &source, &sprites) {
+        tracing::warn!(
+            "failed to dump sprite batch to {}: {error}",
+            dump.path.display()
+        );
+    }
+}
+
+fn export_ply(path: &Path, source: &str, sprites: &[SpriteQuads]) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_ply(&mut writer, source, sprites)?;
+    writer.flush()
+}
+
+/// ASCII の PLY で書く
+///
+/// 四角形は描画するときと同じ 2 つの三角形にする。
+/// 値は読み戻すと同じ f32 になる最も短い 10 進数で書くので、精度は落ちない。
+fn write_ply(mut writer: impl Write, source: &str, sprites: &[SpriteQuads]) -> io::Result<()> {
+    let quads: usize = sprites.iter().map(|sprite| sprite.quads.len()).sum();
+    writeln!(writer, "ply")?;
+    writeln!(writer, "format ascii 1.0")?;
+    writeln!(writer, "comment reverie-engine sprite batch from {source}")?;
+    let mut first = 0;
+    for sprite in sprites {
+        let count = 2 * sprite.quads.len();
+        writeln!(
+            writer,
+            "comment entity {:?} layer {}: faces {first}..{}",
+            sprite.entity.0,
+            sprite.layer,
+            first + count
+        )?;
+        first += count;
+    }
+    writeln!(writer, "element vertex {}", 4 * quads)?;
+    for property in ["x", "y", "z", "s", "t", "red", "green", "blue", "alpha"] {
+        writeln!(writer, "property float {property}")?;
+    }
+    writeln!(writer, "element face {}", 2 * quads)?;
+    writeln!(writer, "property list uchar uint vertex_indices")?;
+    writeln!(writer, "end_header")?;
+
+    let quads = sprites.iter().flat_map(|sprite| &sprite.quads);
+    for vertex in quads.clone().flatten() {
+        let [x, y, z] = vertex.position;
+        let [s, t] = vertex.uv;
+        let [r, g, b, a] = vertex.color;
+        writeln!(writer, "{x} {y} {z} {s} {t} {r} {g} {b} {a}")?;
+    }
+    for (quad, _) in quads.enumerate() {
+        let base = 4 * quad;
+        // SpriteComponent::render と同じ順
+        for [a, b, c] in [[0, 3, 1], [0, 2, 3]] {
+            writeln!(writer, "3 {} {} {}", base + a, base + b, base + c)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(x: f32, y: f32, color: [f32; 4]) -> UvVertex {
+        UvVertex {
+            position: [x, y, 0.1],
+            uv: [x / 3.0, y],
+            color,
+        }
+    }
+
+    #[test]
+    fn ply_lists_quads_with_tint_and_sources() {
+        let mut world = hecs::World::new();
+        let a = EntityIndex(world.spawn(()));
+        let b = EntityIndex(world.spawn(()));
+        let red = [1.0, 0.0, 0.0, 0.5];
+        let quad = |x: f32, color| {
+            [
+                vertex(x, 0.0, color),
+                vertex(x + 1.0, 0.0, color),
+                vertex(x, 1.0, color),
+                vertex(x + 1.0, 1.0, color),
+            ]
+        };
+        let sprites = [
+            SpriteQuads {
+                entity: a,
+                layer: 0,
+                quads: vec![quad(0.0, red)],
+            },
+            // 継ぎ目の両側に描くもの
+            SpriteQuads {
+                entity: b,
+                layer: 2,
+                quads: vec![quad(-0.3, [0.1; 4]), quad(99.7, [0.1; 4])],
+            },
+        ];
+        let mut ply = Vec::new();
+        write_ply(&mut ply, "camera entity 7v0", &sprites).unwrap();
+        let ply = String::from_utf8(ply).unwrap();
+        let (header, body) = ply.split_once("end_header\n").unwrap();
+
+        assert!(header.contains("comment reverie-engine sprite batch from camera entity 7v0\n"));
+        assert!(header.contains(&format!("comment entity {:?} layer 2: faces 2..6\n", b.0)));
+        assert!(header.contains("element vertex 12\n"));
+        assert!(header.contains("element face 6\n"));
+
+        let lines: Vec<_> = body.lines().collect();
+        assert_eq!(lines.len(), 12 + 6);
+        let values: Vec<f32> = lines[1]
+            .split(' ')
+            .map(|value| value.parse().unwrap())
+            .collect();
+        assert_eq!(values, [1.0, 0.0, 0.1, 1.0 / 3.0, 0.0, 1.0, 0.0, 0.0, 0.5]);
+        let values: Vec<f32> = lines[4]
+            .split(' ')
+            .map(|value| value.parse().unwrap())
+            .collect();
+        assert_eq!(values[0].to_bits(), (-0.3_f32).to_bits());
+        assert_eq!(lines[12 + 5], "3 8 10 11");
+    }
+}
@@ -0,0 +1,254 @@
+//! ワールド座標の点にあるスプライトを探す
+use nalgebra::Point2;
+
+use super::{
+    components::mask::inside_ancestor_masks, EntityIndex, RenderLayerComponent,
+    ScreenSpaceComponent, SpriteComponent, TransformComponent,
+};
+use crate::texture::TextureRegistry;
+
+#[derive(Debug, Clone, Copy)]
+/// テクスチャの透明な部分を当たりにしないための設定
+pub struct AlphaTest<'a> {
+    /// テクスチャの CPU 上の画像を読むレジストリ
+    pub textures: &'a TextureRegistry,
+    /// 不透明度がこれ以上の画素だけを当たりにする
+    pub threshold: u8,
+}
+
+/// `point` に重なるスプライトを持つエンティティを、手前に描かれるものから順に返す
+///
+/// `layers` が `Some` なら、その [`RenderLayerComponent`] の層のものだけを返す。
+/// [`ScreenSpaceComponent`] を持つものは座標の意味が違うので含まない。[`screen_entities_at_point`] で探す。
+/// 順序は描画と同じく層の大きい方が手前で、同じ層では後に描かれる方が手前になる。
+/// 祖先の [`super::MaskComponent`] で切り抜かれて見えない部分は当たりにしない。
+pub fn entities_at_point(
+    world: &hecs::World,
+    point: &Point2<f32>,
+    layers: Option<&[i32]>,
+    alpha: Option<AlphaTest<'_>>,
+) -> Vec<EntityIndex> {
+    pick(world, point, layers, alpha, false)
+}
+
+/// [`ScreenSpaceComponent`] を持つスプライトのうち、`point` に重なるものを手前から順に返す
+///
+/// `point` は UI のピクセル座標で、ウィンドウのピクセル座標を [`crate::ui::ui_scale`] で割ったもの。
+/// クリックできるボタンを探すのに使う。ほかは [`entities_at_point`] と同じ。
+pub fn screen_entities_at_point(
+    world: &hecs::World,
+    point: &Point2<f32>,
+    layers: Option<&[i32]>,
+    alpha: Option<AlphaTest<'_>>,
+) -> Vec<EntityIndex> {
+    pick(world, point, layers, alpha, true)
+}
+
+/// `screen_space` が [`ScreenSpaceComponent`] の有無と合うスプライトから、`point` に重なるものを探す
+fn pick(
+    world: &hecs::World,
+    point: &Point2<f32>,
+    layers: Option<&[i32]>,
+    alpha: Option<AlphaTest<'_>>,
+    screen_space: bool,
+) -> Vec<EntityIndex> {
+    let mut hits = Vec::new();
+    for (entity, (transform, sprite, layer, screen)) in world
+        .query::<(
+            &TransformComponent,
+            &SpriteComponent,
+            Option<&RenderLayerComponent>,
+            Option<&ScreenSpaceComponent>,
+        )>()
+        .iter()
+    {
+        if screen.is_some() != screen_space {
+            continue;
+        }
+        let layer = layer.copied().unwrap_or_default().0;
+        if layers.is_some_and(|layers| !layers.contains(&layer)) {
+            continue;
+        }
+        // 外接箱で先に除く
+        let (min, max) = sprite.world_aabb(transform);
+        if point.x < min.x || point.x > max.x || point.y < min.y || point.y > max.y {
+            continue;
+        }
+        let Some(uv) = sprite.hit_uv(transform, point) else {
+            continue;
+        };
+        if let Some(alpha) = alpha {
+            match alpha.textures.alpha_at(sprite.texture(), uv) {
+                Ok(a) if a >= alpha.threshold => {}
+                Ok(_) => continue,
+                Err(e) => {
+                    tracing::warn!(?entity, "failed: read sprite alpha: {e:#}");
+                    continue;
+                }
+            }
+        }
+        let textures = alpha.map(|alpha| alpha.textures);
+        if !inside_ancestor_masks(world, EntityIndex(entity), point, textures) {
+            continue;
+        }
+        hits.push((EntityIndex(entity), layer));
+    }
+    // 描画は層ごとに安定に並べるので、その逆順にする
+    hits.sort_by_key(|&(_, layer)| layer);
+    hits.into_iter().rev().map(|(entity, _)| entity).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::{Scale3, Translation3, UnitQuaternion, Vector3};
+
+    use super::*;
+    use crate::{
+        scene::{MaskComponent, Scene},
+        texture::{TextureId, TextureIndex},
+    };
+
+    fn square(scene: &mut Scene, texture: TextureId, x: f32, y: f32, size: f32) -> EntityIndex {
+        scene.new_sprite(
+            TransformComponent::with_translation_and_scale(
+                Translation3::new(x, y, 0.0),
+                Scale3::new(size, size, 1.0),
+            ),
+            SpriteComponent::builder(texture),
+        )
+    }
+
+    #[test]
+    fn top_most_first_and_layer_filter() {
+        let mut scene = Scene::default();
+        let ground = square(&mut scene, TextureId::WHITE, 0.0, 0.0, 100.0);
+        let unit = square(&mut scene, TextureId::WHITE, 10.0, 0.0, 10.0);
+        scene.attach_component(unit, RenderLayerComponent(1));
+        let decal = square(&mut scene, TextureId::WHITE, 5.0, 0.0, 20.0);
+        let hud = square(&mut scene, TextureId::WHITE, 10.0, 0.0, 100.0);
+        scene.attach_component(hud, ScreenSpaceComponent);
+
+        let point = Point2::new(12.0, 2.0);
+        assert_eq!(scene.entities_at_point(point, None), [unit, decal, ground]);
+        assert_eq!(scene.entities_at_point(point, Some(&[0])), [decal, ground]);
+        assert_eq!(scene.entities_at_point(Point2::new(80.0, 0.0), None), []);
+    }
+
+    #[test]
+    fn respects_rotation_and_flip() {
+        let mut scene = Scene::default();
+        // 横長のスプライトを 90 度回して縦長にする
+        let sprite = scene.new_sprite(
+            TransformComponent::new(
+                Translation3::identity(),
+                Scale3::new(-40.0, 10.0, 1.0),
+                UnitQuaternion::from_axis_angle(&Vector3::z_axis(), std::f32::consts::FRAC_PI_2),
+            ),
+            SpriteComponent::builder(TextureId::WHITE),
+        );
+        assert_eq!(
+            scene.entities_at_point(Point2::new(0.0, 15.0), None),
+            [sprite]
+        );
+        assert_eq!(scene.entities_at_point(Point2::new(15.0, 0.0), None), []);
+    }
+
+    #[test]
+    fn alpha_test_uses_cpu_image() {
+        let mut textures = TextureRegistry::default();
+        // 左半分が透明、右半分が不透明
+        let image = image::RgbaImage::from_fn(4, 4, |x, _| {
+            image::Rgba([255, 255, 255, if x < 2 { 0 } else { 255 }])
+        });
+        let index: TextureIndex = textures.new_texture(image, None);
+        let mut scene = Scene::default();
+        let sprite = square(&mut scene, index.into(), 0.0, 0.0, 8.0);
+
+        let alpha = AlphaTest {
+            textures: &textures,
+            threshold: 128,
+        };
+        let left = Point2::new(-3.0, 0.0);
+        let right = Point2::new(3.0, 0.0);
+        assert_eq!(scene.entities_at_point(left, None), [sprite]);
+        assert_eq!(scene.entities_at_point_with_alpha(left, None, alpha), []);
+        assert_eq!(
+            scene.entities_at_point_with_alpha(right, None, alpha),
+            [sprite]
+        );
+    }
+
+    /// 親子にすると問い合わせの順序が変わるので、順序は比べない
+    fn sorted(mut entities: Vec<EntityIndex>) -> Vec<EntityIndex> {
+        entities.sort_unstable();
+        entities
+    }
+
+    #[test]
+    fn nested_masks_clip_descendants() {
+        let mut scene = Scene::default();
+        // x が -10 から 10 の枠と、0 から 20 の枠が重なるのは 0 から 10
+        let panel = square(&mut scene, TextureId::WHITE, 0.0, 0.0, 20.0);
+        scene.attach_component(panel, MaskComponent::rect(1.0, 1.0));
+        let inner = square(&mut scene, TextureId::WHITE, 10.0, 0.0, 20.0);
+        scene.attach_component(inner, MaskComponent::rect(1.0, 1.0));
+        scene.set_parent(inner, panel).unwrap();
+        let button = square(&mut scene, TextureId::WHITE, 10.0, 0.0, 10.0);
+        scene.set_parent(button, inner).unwrap();
+
+        assert_eq!(
+            sorted(scene.entities_at_point(Point2::new(7.0, 0.0), None)),
+            [panel, inner, button]
+        );
+        // 外側の枠で切り抜かれたボタンは押せない
+        assert_eq!(scene.entities_at_point(Point2::new(12.0, 0.0), None), []);
+        // マスク自身は切り抜かない
+        assert_eq!(
+            scene.entities_at_point(Point2::new(-8.0, 0.0), None),
+            [panel]
+        );
+    }
+
+    #[test]
+    fn screen_space_sprite_alpha_mask() {
+        let mut textures = TextureRegistry::default();
+        // 左半分が透明、右半分が不透明
+        let image = image::RgbaImage::from_fn(4, 4, |x, _| {
+            image::Rgba([255, 255, 255, if x < 2 { 0 } else { 255 }])
+        });
+        let index: TextureIndex = textures.new_texture(image, None);
+        let mut scene = Scene::default();
+        let minimap = square(&mut scene, index.into(), 0.0, 0.0, 8.0);
+        scene.attach_component(minimap, ScreenSpaceComponent);
+        scene.attach_component(minimap, MaskComponent::sprite_alpha());
+        let marker = square(&mut scene, TextureId::WHITE, 0.0, 0.0, 8.0);
+        scene.attach_component(marker, ScreenSpaceComponent);
+        scene.set_parent(marker, minimap).unwrap();
+
+        let alpha = AlphaTest {
+            textures: &textures,
+            threshold: 128,
+        };
+        let left = Point2::new(-3.0, 0.0);
+        let right = Point2::new(3.0, 0.0);
+        assert_eq!(scene.entities_at_point(right, None), []);
+        // CPU 上の画像が無ければスプライトの四角形で切り抜く
+        assert_eq!(
+            sorted(scene.screen_entities_at_point(left, None)),
+            [minimap, marker]
+        );
+        assert_eq!(
+            screen_entities_at_point(&scene.world, &left, None, Some(alpha)),
+            []
+        );
+        assert_eq!(
+            sorted(screen_entities_at_point(
+                &scene.world,
+                &right,
+                None,
+                Some(alpha)
+            )),
+            [minimap, marker]
+        );
+    }
+}
@@ -0,0 +1,435 @@
+//! wgpu でシーンを描画する部分
+
+use std::num::NonZeroU32;
+
+use anyhow::Context;
+use nalgebra::{Point2, Scale3};
+use reverie_util::math::Rect;
+use tracing_unwrap::ResultExt;
+use web_time::Instant;
+
+use super::{
+    components::{camera::PixelGrid, floating_text::draw_floating_texts, mask::MaskStack},
+    resource, resource_mut, CameraComponent, DecalLayerComponent, DrawItem, DrawKind, DrawList,
+    EntityIndex, FloatingTextComponent, Frustum, RenderStage, Scene, SceneClock, SpriteComponent,
+    SystemTimings, TextComponent, TilemapComponent, TransformComponent, ViewStats,
+};
+use crate::{
+    particle::ParticleEmitterComponent,
+    text::Fonts,
+    texture::TextureRegistry,
+    ui::ui_scale,
+    wgpu_wrapper::{
+        get_matrix_pixel_to_render_coordinate, render_graph::RenderPassDesc, WgpuResource,
+    },
+};
+
+/// ピクセル座標を `scale` 倍に拡大して描くときに画面に写る範囲と、画面のピクセル
+fn screen_view(resource: &WgpuResource<'_>, scale: f32) -> (Frustum, PixelGrid) {
+    let width = NonZeroU32::new(resource.surface_config.width).unwrap_or(NonZeroU32::MIN);
+    let height = NonZeroU32::new(resource.surface_config.height).unwrap_or(NonZeroU32::MIN);
+    let matrix = get_matrix_pixel_to_render_coordinate(width, height)
+        * Scale3::new(scale, scale, 1.0).to_homogeneous();
+    let rect = Rect::new(
+        Point2::origin(),
+        Point2::new(width.get() as f32, height.get() as f32),
+    );
+    (Frustum::from_matrix(&matrix), PixelGrid::new(matrix, rect))
+}
+
+/// [`Scene::prepare_sprites`] で描画の準備をしたスプライトと文字列
+///
+/// カメラに写るものが層と奥行きの順に並んでいる。[`Scene::draw_sprites`] に渡すと描画する。
+/// 準備したフレームのうちに描画する。
+#[derive(Debug)]
+pub struct SpriteBatches {
+    camera: Option<EntityIndex>,
+    draw_list: DrawList,
+    /// カメラの描く範囲と、描画先全体の大きさ
+    viewport: Option<(Rect, NonZeroU32, NonZeroU32)>,
+    grid: PixelGrid,
+}
+
+impl SpriteBatches {
+    /// 描画に使うカメラ。`None` ならピクセル座標で描く
+    pub const fn camera(&self) -> Option<EntityIndex> {
+        self.camera
+    }
+
+    pub const fn draw_list(&self) -> &DrawList {
+        &self.draw_list
+    }
+}
+
+impl Scene {
+    /// シーンのコンポーネントが指すテクスチャを使われていると記録する
+    pub(crate) fn mark_used_textures(&self, registry: &mut TextureRegistry) {
+        for (_, sprite) in self.world.query::<&SpriteComponent>().iter() {
+            registry.mark_used(sprite.texture());
+        }
+        for (_, tilemap) in self.world.query::<&TilemapComponent>().iter() {
+            registry.mark_used(tilemap.tileset().texture);
+        }
+        for (_, emitter) in self.world.query::<&ParticleEmitterComponent>().iter() {
+            registry.mark_used(emitter.texture());
+        }
+        for (_, layer) in self.world.query::<&DecalLayerComponent>().iter() {
+            for texture in layer.pending_textures() {
+                registry.mark_used(texture);
+            }
+        }
+        if let Some(fonts) = self::resource::<Fonts>(&self.world) {
+            for icon in fonts.icons() {
+                registry.mark_used(icon.texture);
+            }
+        }
+    }
+
+    /// [`DecalLayerComponent`] に溜まっている描き込みをキャンバスに描くコマンドを積む
+    ///
+    /// シーンを描くパスより前に積むので、そのフレームに貼り付けたものも写る。
+    pub(crate) fn flush_decals(
+        &mut self,
+        resource: &WgpuResource<'_>,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        for (_, layer) in self.world.query_mut::<&mut DecalLayerComponent>() {
+            layer.flush(resource, encoder);
+        }
+    }
+
+    /// フレームの描画を始める前に統計を空にする
+    pub(crate) fn begin_frame(&mut self) {
+        self.frame_stats.clear();
+    }
+
+    /// [`RenderPassDesc`] で指定されたカメラからシーンを描画する
+    pub fn render_pass(
+        &mut self,
+        desc: &RenderPassDesc,
+        rp: &mut wgpu::RenderPass<'_>,
+        resource: &WgpuResource<'_>,
+    ) {
+        if let Some(camera) = desc.camera {
+            self.render_from_camera(camera, rp, resource)
+                .context("failed: render from camera")
+                .unwrap_or_log();
+        } else {
+            self.render(rp, resource);
+        }
+    }
+
+    /// アクティブなカメラからシーンを描画する
+    ///
+    /// その後、[`ScreenSpaceComponent`](super::ScreenSpaceComponent) を持つスプライトをピクセル座標で重ねて描画する。
+    /// ピクセル座標は [`crate::ui::ui_scale`] 倍に拡大する。
+    /// 描画の段階ごとにシステムの [`System::render`](super::System::render) を呼ぶ。
+    pub fn render(&mut self, rp: &mut wgpu::RenderPass<'_>, resource: &WgpuResource<'_>) {
+        let (screen_frustum, screen_grid) = screen_view(resource, ui_scale(&self.world));
+        self.write_globals(resource);
+
+        let batches = self
+            .prepare_sprites(self.active_camera, resource)
+            .context("failed: render from active camera")
+            .unwrap_or_log();
+        self.draw_sprites(batches, rp, resource);
+        resource.debug_draw.render(rp, resource);
+
+        resource.queue.write_buffer(
+            &resource.ui_uniform_buffer,
+            0,
+            bytemuck::cast_slice(screen_grid.matrix().as_slice()),
+        );
+        rp.set_bind_group(1, &resource.ui_bind_group, &[]);
+        let draw_list = DrawList::build(
+            &self.world,
+            &screen_frustum,
+            true,
+            Some(&resource.texture_registry),
+            Some(&screen_grid),
+            None,
+            &mut self.frame_arena,
+        );
+        self.record_view(&draw_list, None, true);
+        self.prepare_texts(&draw_list, resource);
+        self.draw(draw_list.items(), (&screen_grid, true), rp, resource);
+        self.run_render_stage(RenderStage::AfterUi, &draw_list, rp, resource, None);
+        self.frame_arena.recycle(draw_list.into_items());
+    }
+
+    /// 指定したカメラからシーンを描画する
+    ///
+    /// アクティブなカメラは変わらない。描画先は `rp` で決まるので、テクスチャに描画すれば
+    /// ミラーやリフレクションプローブに使える。[`ScreenSpaceComponent`](super::ScreenSpaceComponent) を持つスプライトは描画しない。
+    pub fn render_from_camera(
+        &mut self,
+        entity: EntityIndex,
+        rp: &mut wgpu::RenderPass<'_>,
+        resource: &WgpuResource<'_>,
+    ) -> anyhow::Result<()> {
+        let batches = self.prepare_sprites(Some(entity), resource)?;
+        self.draw_sprites(batches, rp, resource);
+        Ok(())
+    }
+
+    /// `camera` から見たワールドのスプライトと文字列を、描画できるように準備する
+    ///
+    /// カメラに写るものを選び、カメラの行列と文字列のグリフを GPU に送る。
+    /// `camera` が `None` ならピクセル座標で描く。[`ScreenSpaceComponent`](super::ScreenSpaceComponent) を持つものは含まない。
+    /// 返した [`SpriteBatches`] を [`Self::draw_sprites`] に渡すと描画する。
+    pub fn prepare_sprites(
+        &mut self,
+        camera: Option<EntityIndex>,
+        resource: &WgpuResource<'_>,
+    ) -> anyhow::Result<SpriteBatches> {
+        self.write_globals(resource);
+        let mut viewport = None;
+        let (frustum, grid, position) = match camera {
+            Some(entity) => {
+                let (camera, transform) = self
+                    .world
+                    .query_one_mut::<(&mut CameraComponent, Option<&TransformComponent>)>(entity.0)
+                    .context("entity does not have CameraComponent")?;
+                let transform = transform.map_or_else(TransformComponent::default, |t| t.clone());
+                let (width, height) = camera.target_size(resource);
+                let rect = camera.viewport_rect(width, height);
+                if camera.has_viewport() {
+                    viewport = Some((rect, width, height));
+                }
+                let (view_width, view_height) = camera.view_size(width, height);
+                let matrix = camera.view_projection(&transform, view_width, view_height);
+                camera.prepare(resource, &matrix);
+                (
+                    Frustum::from_matrix(&matrix),
+                    PixelGrid::new(matrix, rect),
+                    transform.translation.vector.xy().into(),
+                )
+            }
+            None => {
+                let (frustum, grid) = screen_view(resource, 1.0);
+                (frustum, grid, Point2::origin())
+            }
+        };
+        let mut draw_list = DrawList::build(
+            &self.world,
+            &frustum,
+            false,
+            Some(&resource.texture_registry),
+            Some(&grid),
+            Some(position),
+            &mut self.frame_arena,
+        );
+        if let Some(camera) =
+            camera.and_then(|camera| self.world.get::<&CameraComponent>(camera.0).ok())
+        {
+            draw_list.hide_layers(&camera.hidden_layers);
+        }
+        self.record_view(&draw_list, camera, false);
+        #[cfg(feature = "mesh-export")]
+        super::mesh_export::dump_requested(&mut self.world, &draw_list, camera);
+        self.prepare_texts(&draw_list, resource);
+        Ok(SpriteBatches {
+            camera,
+            draw_list,
+            viewport,
+            grid,
+        })
+    }
+
+    /// [`Self::prepare_sprites`] で準備したものを層の順に描画し、段階ごとにシステムを呼ぶ
+    ///
+    /// `rp` はどのレンダーパスでもよいが、スプライトのパイプラインが設定されている必要がある。
+    pub fn draw_sprites(
+        &mut self,
+        batches: SpriteBatches,
+        rp: &mut wgpu::RenderPass<'_>,
+        resource: &WgpuResource<'_>,
+    ) {
+        let SpriteBatches {
+            camera,
+            draw_list,
+            viewport,
+            grid,
+        } = batches;
+        if let Some((rect, _, _)) = viewport {
+            rp.set_viewport(
+                rect.min.x,
+                rect.min.y,
+                rect.width(),
+                rect.height(),
+                0.0,
+                1.0,
+            );
+        }
+        self.bind_camera(camera, rp, resource);
+        self.run_render_stage(RenderStage::BeforeWorld, &draw_list, rp, resource, camera);
+        {
+            let mut layers = draw_list.layers().peekable();
+            while let Some(items) = layers.next() {
+                self.draw(items, (&grid, false), rp, resource);
+                if layers.peek().is_some() {
+                    let stage = RenderStage::BetweenLayers(items[0].layer);
+                    self.run_render_stage(stage, &draw_list, rp, resource, camera);
+                }
+            }
+        }
+        self.run_render_stage(RenderStage::AfterWorld, &draw_list, rp, resource, camera);
+        self.frame_arena.recycle(draw_list.into_items());
+        // 同じパスで続けて描くものは描画先全体に描く
+        if let Some((_, width, height)) = viewport {
+            rp.set_viewport(0.0, 0.0, width.get() as f32, height.get() as f32, 0.0, 1.0);
+        }
+    }
+
+    /// シェーダーで共有するユニフォームにシーンの時計を書き込む
+    fn write_globals(&self, resource: &WgpuResource<'_>) {
+        let elapsed = self::resource::<SceneClock>(&self.world)
+            .map(|clock| clock.elapsed)
+            .unwrap_or_default();
+        resource.globals.write_time(&resource.queue, elapsed);
+    }
+
+    fn record_view(
+        &mut self,
+        draw_list: &DrawList,
+        camera: Option<EntityIndex>,
+        screen_space: bool,
+    ) {
+        self.frame_stats.push(ViewStats {
+            camera,
+            screen_space,
+            visible_sprites: draw_list.visible_sprites(),
+            total_sprites: draw_list.total_sprites(),
+        });
+    }
+
+    /// すべてのシステムの [`System::render`](super::System::render) を呼び、パイプラインとカメラを元に戻す
+    fn run_render_stage(
+        &mut self,
+        stage: RenderStage,
+        draw_list: &DrawList,
+        rp: &mut wgpu::RenderPass<'_>,
+        resource: &WgpuResource<'_>,
+        camera: Option<EntityIndex>,
+    ) {
+        let start = Instant::now();
+        for system in &mut self.systems {
+            system.system.render(stage, draw_list, rp, resource);
+        }
+        if let Some(mut timings) = resource_mut::<SystemTimings>(&self.world) {
+            timings.record_stage(stage, start.elapsed());
+        }
+        rp.set_pipeline(&resource.render_pipeline);
+        self.bind_camera(camera, rp, resource);
+    }
+
+    /// `camera` の行列を使うように設定する。`camera` が無ければピクセル座標を使う
+    fn bind_camera(
+        &self,
+        camera: Option<EntityIndex>,
+        rp: &mut wgpu::RenderPass<'_>,
+        resource: &WgpuResource<'_>,
+    ) {
+        let camera = camera.and_then(|camera| self.world.get::<&CameraComponent>(camera.0).ok());
+        match camera.as_ref().and_then(|camera| camera.bind_group()) {
+            Some(bind_group) => rp.set_bind_group(1, bind_group, &[]),
+            None => rp.set_bind_group(1, &resource.uniform_bind_group, &[]),
+        }
+    }
+
+    /// `draw_list` の文字列のグリフをアトラスに描き込み、GPU に送る
+    fn prepare_texts(&self, draw_list: &DrawList, resource: &WgpuResource<'_>) {
+        let Some(mut fonts) = resource_mut::<Fonts>(&self.world) else {
+            return;
+        };
+        for item in draw_list.items() {
+            match item.kind {
+                DrawKind::Text => {
+                    if let Ok(mut text) = self.world.get::<&mut TextComponent>(item.entity.0) {
+                        text.prepare(&mut fonts);
+                    }
+                }
+                DrawKind::FloatingText => {
+                    if let Ok(mut floating) =
+                        self.world.get::<&mut FloatingTextComponent>(item.entity.0)
+                    {
+                        floating.prepare(&mut fonts);
+                    }
+                }
+                _ => {}
+            }
+        }
+        fonts.atlas_mut().prepare(resource);
+    }
+
+    /// [`Self::prepare_texts`] の後に、`items` を順に描画する
+    ///
+    /// `(grid, screen_space)` は描画先のピクセルと、`items` が [`super::ScreenSpaceComponent`] を持つものか。
+    /// [`TextComponent::pixel_snap`] な文字列の位置を合わせるのに使う。
+    /// 祖先の [`super::MaskComponent`] はそれぞれを描く前にステンシルに描き込み、最後にすべて消す。
+    fn draw(
+        &self,
+        items: &[DrawItem],
+        (grid, screen_space): (&PixelGrid, bool),
+        rp: &mut wgpu::RenderPass<'_>,
+        resource: &WgpuResource<'_>,
+    ) {
+        let fonts = self::resource::<Fonts>(&self.world);
+        let elapsed = self::resource::<SceneClock>(&self.world)
+            .map(|clock| clock.elapsed)
+            .unwrap_or_default();
+        let mut masks = MaskStack::new(&self.world);
+        // 浮かぶ文字列は続くものをまとめて描き、ほかのものは 1 つずつ描く
+        let floating = |item: &DrawItem| item.kind == DrawKind::FloatingText;
+        for run in items.chunk_by(|a, b| floating(a) && floating(b)) {
+            let item = &run[0];
+            if floating(item) {
+                if let Some(fonts) = &fonts {
+                    let entities = run.iter().map(|item| item.entity.0);
+                    draw_floating_texts(&self.world, entities, fonts, rp, resource);
+                    rp.set_pipeline(&resource.render_pipeline);
+                }
+                continue;
+            }
+            let entity = item.entity.0;
+            let Ok(transform) = self.world.get::<&TransformComponent>(entity) else {
+                continue;
+            };
+            masks.enter(&self.world, entity, rp, resource);
+            match item.kind {
+                DrawKind::Tilemap => {
+                    if let Ok(mut tilemap) = self.world.get::<&mut TilemapComponent>(entity) {
+                        tilemap.render(rp, resource, &transform, elapsed);
+                        rp.set_pipeline(&resource.render_pipeline);
+                    }
+                }
+                DrawKind::Sprite => {
+                    if let Ok(mut sprite) = self.world.get::<&mut SpriteComponent>(entity) {
+                        sprite.render(rp, resource, &transform);
+                    }
+                }
+                DrawKind::Particles => {
+                    if let Ok(mut emitter) = self.world.get::<&mut ParticleEmitterComponent>(entity)
+                    {
+                        emitter.render(rp, resource);
+                        rp.set_pipeline(&resource.render_pipeline);
+                    }
+                }
+                DrawKind::Text => {
+                    if let (Ok(mut text), Some(fonts)) =
+                        (self.world.get::<&mut TextComponent>(entity), &fonts)
+                    {
+                        if text.snaps(screen_space) {
+                            text.render(rp, resource, &grid.snap(&transform), fonts);
+                        } else {
+                            text.render(rp, resource, &transform, fonts);
+                        }
+                    }
+                }
+                // 上でまとめて描いている
+                DrawKind::FloatingText => {}
+            }
+        }
+        masks.clear(&self.world, rp, resource);
+    }
+}
@@ -0,0 +1,132 @@
+//! シーン全体で 1 つだけ存在するデータ (リソース)
+//!
+//! リソースは [`hecs::World`] の中の専用のエンティティにコンポーネントとして持たせる。
+//! そのため [`super::System::update`] に渡される `world` からも読み書きできる。
+
+/// リソースを持つエンティティの印
+struct ResourceHolder;
+
+pub(super) fn holder(world: &hecs::World) -> Option<hecs::Entity> {
+    world
+        .query::<()>()
+        .with::<&ResourceHolder>()
+        .iter()
+        .next()
+        .map(|(entity, ())| entity)
+}
+
+/// リソースを追加する。同じ型のリソースがあれば置き換える
+pub fn insert_resource<R: hecs::Component>(world: &mut hecs::World, resource: R) {
+    let entity = holder(world).unwrap_or_else(|| world.spawn((ResourceHolder,)));
+    world
+        .insert_one(entity, resource)
+        .expect("the resource holder was found or spawned just now");
+}
+
+/// リソースを取り除いて返す
+pub fn remove_resource<R: hecs::Component>(world: &mut hecs::World) -> Option<R> {
+    let entity = holder(world)?;
+    world.remove_one::<R>(entity).ok()
+}
+
+/// リソースを読む
+pub fn resource<R: hecs::Component>(world: &hecs::World) -> Option<hecs::Ref<'_, R>> {
+    world.get::<&R>(holder(world)?).ok()
+}
+
+/// リソースを書き換える
+pub fn resource_mut<R: hecs::Component>(world: &hecs::World) -> Option<hecs::RefMut<'_, R>> {
+    world.get::<&mut R>(holder(world)?).ok()
+}
+
+#[derive(Debug)]
+/// システムから他のシステムへ送るイベントの列
+///
+/// リソースとして [`hecs::World`] に持たせる。イベントを送るシステムは毎フレームの始めに
+/// [`Self::clear`] するので、受け取る側は [`super::System::dependencies`] で送る側より後に実行する。
+pub struct Events<E> {
+    events: Vec<E>,
+}
+
+impl<E> Default for Events<E> {
+    fn default() -> Self {
+        Self { events: Vec::new() }
+    }
+}
+
+impl<E> Events<E> {
+    pub fn send(&mut self, event: E) {
+        self.events.push(event);
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, E> {
+        self.events.iter()
+    }
+
+    pub fn drain(&mut self) -> std::vec::Drain<'_, E> {
+        self.events.drain(..)
+    }
+
+    /// イベントを消す。容量は次のフレームに持ち越す
+    ///
+    /// バッファは [`super::FrameArena`] から借りていないので、伸びてもその集計や警告には出ない。
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+/// `world` のリソース [`Events<E>`] を空にする。無ければ作る
+pub fn clear_events<E: hecs::Component>(world: &mut hecs::World) {
+    if let Some(mut events) = resource_mut::<Events<E>>(world) {
+        events.clear();
+        return;
+    }
+    insert_resource(world, Events::<E>::default());
+}
+
+/// `world` のリソース [`Events<E>`] にイベントを送る。無ければ作る
+pub fn send_event<E: hecs::Component>(world: &mut hecs::World, event: E) {
+    if let Some(mut events) = resource_mut::<Events<E>>(world) {
+        events.send(event);
+        return;
+    }
+    let mut events = Events::default();
+    events.send(event);
+    insert_resource(world, events);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Gravity(f32);
+
+    #[test]
+    fn resources_live_on_one_entity() {
+        let mut world = hecs::World::new();
+        assert!(resource::<Gravity>(&world).is_none());
+
+        insert_resource(&mut world, Gravity(9.8));
+        insert_resource(&mut world, Events::<u32>::default());
+        assert_eq!(world.len(), 1);
+        resource_mut::<Gravity>(&world).unwrap().0 = 1.6;
+        assert_eq!(*resource::<Gravity>(&world).unwrap(), Gravity(1.6));
+
+        send_event(&mut world, 7u32);
+        assert_eq!(resource::<Events<u32>>(&world).unwrap().len(), 1);
+        clear_events::<u32>(&mut world);
+        assert!(resource::<Events<u32>>(&world).unwrap().is_empty());
+
+        assert_eq!(remove_resource::<Gravity>(&mut world), Some(Gravity(1.6)));
+        assert!(resource::<Gravity>(&world).is_none());
+    }
+}
@@ -0,0 +1,580 @@
+//! エンティティをセーブデータにして保存し、読み込む
+//!
+//! 保存するコンポーネントは [`SaveComponent`] を実装し、[`SaveRegistry::register`] で登録する。
+//! コンポーネントごとにバージョンを持ち、古いバージョンの値は [`SaveComponent::migrate`] で今の形に直してから読む。
+//! セーブデータ全体の形式のバージョンは [`SAVE_FORMAT_VERSION`] で、[`SaveData::format_version`] に書く。
+use std::{
+    collections::BTreeMap,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{resource, EntityIndex, EntityMap, Scene};
+
+/// このエンジンが書くセーブデータの形式のバージョン
+///
+/// これより新しい形式のセーブデータは読めない。
+pub const SAVE_FORMAT_VERSION: u32 = 1;
+
+/// gzip で圧縮したファイルの先頭
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// セーブデータに保存するコンポーネント
+///
+/// 値は TOML のテーブルとして保存する。フィールドを増やしたり形を変えたりしたら [`Self::VERSION`] を上げ、
+/// [`Self::migrate`] で古い値を直す。`#[serde(default)]` で足りるなら上げなくてもよい。
+///
+/// ```ignore
+/// #[derive(Serialize, Deserialize)]
+/// struct PlayerComponent { name: String, health: Health }
+///
+/// impl SaveComponent for PlayerComponent {
+///     const NAME: &'static str = "player";
+///     const VERSION: u32 = 2;
+///
+///     fn migrate(old_version: u32, mut value: toml::Value) -> anyhow::Result<toml::Value> {
+///         if old_version < 2 {
+///             // バージョン 1 では体力が hp だけだった
+///             let table = value.as_table_mut().context("player is not a table")?;
+///             let hp = table.remove("hp").context("player has no hp")?;
+///             let health = toml::Table::from_iter([("current".into(), hp.clone()), ("max".into(), hp)]);
+///             table.insert("health".into(), health.into());
+///         }
+///         Ok(value)
+///     }
+/// }
+/// ```
+pub trait SaveComponent: hecs::Component + Serialize + DeserializeOwned {
+    /// セーブデータでの名前。型の名前を変えてもこれを変えなければ読める
+    const NAME: &'static str;
+    /// 値の形のバージョン
+    const VERSION: u32 = 1;
+
+    /// バージョン `old_version` で保存された値を、[`Self::VERSION`] の形に直す
+    ///
+    /// 古いバージョンの値を読むときだけ呼ばれる。間のバージョンをすべて `old_version` から順に直す。
+    fn migrate(old_version: u32, value: toml::Value) -> anyhow::Result<toml::Value> {
+        let _ = old_version;
+        Ok(value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// セーブデータの中の 1 つのコンポーネント
+pub struct SavedComponent {
+    /// 保存したときの [`SaveComponent::VERSION`]
+    pub version: u32,
+    pub value: toml::Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// セーブデータの中の 1 つのエンティティ
+pub struct SavedEntity {
+    /// 保存したときのエンティティの番号。読み込んだ [`EntityMap`] で新しいエンティティを引ける
+    pub id: u64,
+    /// [`SaveComponent::NAME`] からコンポーネントへの対応
+    #[serde(default)]
+    pub components: BTreeMap<String, SavedComponent>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// セーブデータ
+///
+/// [`SaveRegistry::save`] で作り、[`SaveFile`] でファイルに書く。
+pub struct SaveData {
+    /// 保存したときの [`SAVE_FORMAT_VERSION`]
+    pub format_version: u32,
+    #[serde(default)]
+    pub entities: Vec<SavedEntity>,
+}
+
+impl Default for SaveData {
+    fn default() -> Self {
+        Self {
+            format_version: SAVE_FORMAT_VERSION,
+            entities: Vec::new(),
+        }
+    }
+}
+
+impl SaveData {
+    /// TOML として読む
+    pub fn from_toml(toml: &str) -> anyhow::Result<Self> {
+        let data: Self = toml::from_str(toml).context("failed: parse save data")?;
+        anyhow::ensure!(
+            data.format_version <= SAVE_FORMAT_VERSION,
+            "save format version {} is newer than the supported version {SAVE_FORMAT_VERSION}",
+            data.format_version
+        );
+        Ok(data)
+    }
+
+    /// TOML にする
+    pub fn to_toml(&self) -> anyhow::Result<String> {
+        toml::to_string_pretty(self).context("failed: serialize save data")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// 登録されていないコンポーネントを読んだときにどうするか
+pub enum UnknownComponents {
+    #[default]
+    /// [`PreservedComponentsComponent`] に取っておき、次に保存するときにそのまま書き戻す
+    ///
+    /// 新しいバージョンのゲームで保存したデータを古いバージョンで読んで保存し直しても、消えない。
+    Preserve,
+    /// 警告を出して捨てる
+    Skip,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+/// 読み込んだときに登録されていなかったコンポーネント
+///
+/// [`UnknownComponents::Preserve`] のときに付き、[`SaveRegistry::save`] が値を変えずに書き戻す。
+pub struct PreservedComponentsComponent {
+    pub components: BTreeMap<String, SavedComponent>,
+}
+
+/// `entity` が `C` を持っていれば値にする
+type SaveFn = fn(&hecs::World, hecs::Entity) -> Option<anyhow::Result<toml::Value>>;
+/// バージョン `version` の値を今の形に直して `C` にし、エンティティに加える
+type LoadFn = fn(&mut hecs::EntityBuilder, u32, toml::Value) -> anyhow::Result<()>;
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    version: u32,
+    save: SaveFn,
+    load: LoadFn,
+}
+
+fn save_component<C: SaveComponent>(
+    world: &hecs::World,
+    entity: hecs::Entity,
+) -> Option<anyhow::Result<toml::Value>> {
+    let component = world.get::<&C>(entity).ok()?;
+    Some(toml::Value::try_from(&*component).map_err(anyhow::Error::from))
+}
+
+fn load_component<C: SaveComponent>(
+    builder: &mut hecs::EntityBuilder,
+    version: u32,
+    value: toml::Value,
+) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        version <= C::VERSION,
+        "version {version} is newer than the supported version {}",
+        C::VERSION
+    );
+    let value = if version < C::VERSION {
+        C::migrate(version, value)
+            .with_context(|| format!("failed: migrate from version {version}"))?
+    } else {
+        value
+    };
+    builder.add(value.try_into::<C>()?);
+    Ok(())
+}
+
+#[derive(Debug, Clone, Default)]
+/// セーブデータに保存するコンポーネントの一覧
+pub struct SaveRegistry {
+    entries: BTreeMap<&'static str, Entry>,
+    unknown: UnknownComponents,
+}
+
+impl SaveRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub const fn with_unknown_components(mut self, unknown: UnknownComponents) -> Self {
+        self.unknown = unknown;
+        self
+    }
+
+    /// `C` を保存するように登録する
+    ///
+    /// 同じ [`SaveComponent::NAME`] の型を登録し直すと置き換える。
+    pub fn register<C: SaveComponent>(&mut self) -> &mut Self {
+        self.entries.insert(
+            C::NAME,
+            Entry {
+                version: C::VERSION,
+                save: save_component::<C>,
+                load: load_component::<C>,
+            },
+        );
+        self
+    }
+
+    /// 登録したコンポーネントか取っておいたコンポーネントを持つエンティティを保存する
+    ///
+    /// リソースは保存しない。
+    pub fn save(&self, world: &hecs::World) -> anyhow::Result<SaveData> {
+        let holder = resource::holder(world);
+        let mut data = SaveData::default();
+        for entity in world.iter().map(|entity| entity.entity()) {
+            if Some(entity) == holder {
+                continue;
+            }
+            let components = self.save_entity(world, entity)?;
+            if !components.is_empty() {
+                data.entities.push(SavedEntity {
+                    id: entity.to_bits().get(),
+                    components,
+                });
+            }
+        }
+        Ok(data)
+    }
+
+    /// `entity` の保存するコンポーネント。取っておいたコンポーネントも含む
+    pub(super) fn save_entity(
+        &self,
+        world: &hecs::World,
+        entity: hecs::Entity,
+    ) -> anyhow::Result<BTreeMap<String, SavedComponent>> {
+        let mut components = world
+            .get::<&PreservedComponentsComponent>(entity)
+            .map(|preserved| preserved.components.clone())
+            .unwrap_or_default();
+        for (&name, entry) in &self.entries {
+            let Some(value) = (entry.save)(world, entity) else {
+                continue;
+            };
+            let value = value.with_context(|| format!("failed: serialize component {name}"))?;
+            components.insert(
+                name.to_owned(),
+                SavedComponent {
+                    version: entry.version,
+                    value,
+                },
+            );
+        }
+        Ok(components)
+    }
+
+    /// `data` のエンティティを `world` に加える
+    ///
+    /// 古いバージョンのコンポーネントは [`SaveComponent::migrate`] で直してから読む。
+    /// 読めないコンポーネントが 1 つでもあればエラーを返し、`world` には何も加えない。
+    pub fn load(&self, world: &mut hecs::World, data: &SaveData) -> anyhow::Result<EntityMap> {
+        anyhow::ensure!(
+            data.format_version <= SAVE_FORMAT_VERSION,
+            "save format version {} is newer than the supported version {SAVE_FORMAT_VERSION}",
+            data.format_version
+        );
+        let mut builders = Vec::with_capacity(data.entities.len());
+        for saved in &data.entities {
+            let mut builder = hecs::EntityBuilder::new();
+            let mut preserved = PreservedComponentsComponent::default();
+            for (name, component) in &saved.components {
+                let Some(entry) = self.entries.get(name.as_str()) else {
+                    match self.unknown {
+                        UnknownComponents::Preserve => {
+                            preserved.components.insert(name.clone(), component.clone());
+                        }
+                        UnknownComponents::Skip => {
+                            tracing::warn!("skipped unknown component in save data: {name}");
+                        }
+                    }
+                    continue;
+                };
+                (entry.load)(&mut builder, component.version, component.value.clone())
+                    .with_context(|| {
+                        format!("failed: load component {name} of entity {}", saved.id)
+                    })?;
+            }
+            if !preserved.components.is_empty() {
+                builder.add(preserved);
+            }
+            builders.push((saved.id, builder));
+        }
+
+        let mut map = EntityMap::default();
+        for (id, mut builder) in builders {
+            let new = world.spawn(builder.build());
+            if let Some(old) = hecs::Entity::from_bits(id) {
+                map.insert(EntityIndex(old), EntityIndex(new));
+            }
+        }
+        Ok(map)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// セーブデータを書くファイル
+///
+/// 一時ファイルに書いてから名前を変えるので、書いている途中で落ちても前のセーブデータが残る。
+pub struct SaveFile {
+    path: PathBuf,
+    compression: bool,
+}
+
+impl SaveFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            compression: false,
+        }
+    }
+
+    /// gzip で圧縮して書く。読むときは圧縮してあるかを自動で見分ける
+    pub const fn with_compression(mut self, compression: bool) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 書いている途中の一時ファイル
+    fn temp_path(&self) -> PathBuf {
+        let mut name = self.path.file_name().unwrap_or_default().to_owned();
+        name.push(".tmp");
+        self.path.with_file_name(name)
+    }
+
+    /// `data` を書く
+    pub fn write(&self, data: &SaveData) -> anyhow::Result<()> {
+        let toml = data.to_toml()?;
+        let bytes = if self.compression {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(toml.as_bytes())?;
+            encoder.finish().context("failed: compress save data")?
+        } else {
+            toml.into_bytes()
+        };
+
+        let temp = self.temp_path();
+        let mut file = std::fs::File::create(&temp)
+            .with_context(|| format!("failed: create {}", temp.display()))?;
+        file.write_all(&bytes)
+            .and_then(|()| file.sync_all())
+            .with_context(|| format!("failed: write {}", temp.display()))?;
+        drop(file);
+        std::fs::rename(&temp, &self.path).with_context(|| {
+            format!(
+                "failed: rename {} to {}",
+                temp.display(),
+                self.path.display()
+            )
+        })
+    }
+
+    /// セーブデータを読む
+    pub fn read(&self) -> anyhow::Result<SaveData> {
+        let bytes = std::fs::read(&self.path)
+            .with_context(|| format!("failed: read {}", self.path.display()))?;
+        let toml = if bytes.starts_with(&GZIP_MAGIC) {
+            let mut toml = String::new();
+            GzDecoder::new(bytes.as_slice())
+                .read_to_string(&mut toml)
+                .with_context(|| format!("failed: decompress {}", self.path.display()))?;
+            toml
+        } else {
+            String::from_utf8(bytes)
+                .with_context(|| format!("failed: decode {}", self.path.display()))?
+        };
+        SaveData::from_toml(&toml).with_context(|| format!("failed: load {}", self.path.display()))
+    }
+}
+
+impl Scene {
+    /// `registry` に登録したコンポーネントを持つエンティティを保存する
+    pub fn save(&self, registry: &SaveRegistry) -> anyhow::Result<SaveData> {
+        registry.save(&self.world)
+    }
+
+    /// `data` のエンティティをこのシーンに加える
+    ///
+    /// [`Self::register_entity_refs`] で登録したコンポーネントの [`EntityIndex`] は、
+    /// [`Self::merge`] と同じように読み込んだ先のインデックスに書き換える。
+    pub fn load(&mut self, registry: &SaveRegistry, data: &SaveData) -> anyhow::Result<EntityMap> {
+        let map = registry.load(&mut self.world, data)?;
+        for (_, mapper) in &self.entity_mappers {
+            mapper(&mut self.world, &map);
+        }
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Health {
+        current: i64,
+        max: i64,
+    }
+
+    /// バージョン 1 では体力が `hp` だけだった
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct PlayerComponent {
+        name: String,
+        health: Health,
+    }
+
+    impl SaveComponent for PlayerComponent {
+        const NAME: &'static str = "player";
+        const VERSION: u32 = 2;
+
+        fn migrate(old_version: u32, mut value: toml::Value) -> anyhow::Result<toml::Value> {
+            if old_version < 2 {
+                let table = value.as_table_mut().context("player is not a table")?;
+                let hp = table.remove("hp").context("player has no hp")?;
+                let mut health = toml::Table::new();
+                health.insert("current".to_owned(), hp.clone());
+                health.insert("max".to_owned(), hp);
+                table.insert("health".to_owned(), health.into());
+            }
+            Ok(value)
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct CoinComponent {
+        value: u32,
+    }
+
+    impl SaveComponent for CoinComponent {
+        const NAME: &'static str = "coin";
+    }
+
+    fn registry(unknown: UnknownComponents) -> SaveRegistry {
+        let mut registry = SaveRegistry::new().with_unknown_components(unknown);
+        registry
+            .register::<PlayerComponent>()
+            .register::<CoinComponent>();
+        registry
+    }
+
+    /// 体力が `hp` だったころのセーブデータ。知らないコンポーネント `lantern` も入っている
+    const V1_FIXTURE: &str = include_str!("../../tests/saves/v1.toml");
+
+    #[test]
+    fn migrates_components_from_older_versions() {
+        let data = SaveData::from_toml(V1_FIXTURE).unwrap();
+        let mut scene = Scene::default();
+        let map = scene
+            .load(&registry(UnknownComponents::Skip), &data)
+            .unwrap();
+        assert_eq!(map.len(), 2);
+
+        let player = map.get(EntityIndex(hecs::Entity::from_bits(1 << 32).unwrap()));
+        let player = scene
+            .world
+            .get::<&PlayerComponent>(player.unwrap().0)
+            .unwrap();
+        assert_eq!(
+            *player,
+            PlayerComponent {
+                name: "Ayu".to_owned(),
+                health: Health { current: 7, max: 7 },
+            }
+        );
+        assert_eq!(
+            scene
+                .world
+                .query::<&PreservedComponentsComponent>()
+                .iter()
+                .count(),
+            0
+        );
+
+        // 保存し直すと今のバージョンになる
+        let saved = scene.save(&registry(UnknownComponents::Skip)).unwrap();
+        let player = saved
+            .entities
+            .iter()
+            .find_map(|entity| entity.components.get(PlayerComponent::NAME))
+            .unwrap();
+        assert_eq!(player.version, 2);
+    }
+
+    #[test]
+    fn unknown_components_are_preserved_and_written_back() {
+        let data = SaveData::from_toml(V1_FIXTURE).unwrap();
+        let registry = registry(UnknownComponents::Preserve);
+        let mut scene = Scene::default();
+        scene.load(&registry, &data).unwrap();
+
+        let saved = scene.save(&registry).unwrap();
+        let lantern = saved
+            .entities
+            .iter()
+            .find_map(|entity| entity.components.get("lantern"))
+            .unwrap();
+        let original = data
+            .entities
+            .iter()
+            .find_map(|entity| entity.components.get("lantern"))
+            .unwrap();
+        assert_eq!(lantern, original);
+    }
+
+    #[test]
+    fn newer_versions_are_rejected() {
+        let newer_format = format!("format_version = {}\n", SAVE_FORMAT_VERSION + 1);
+        assert!(SaveData::from_toml(&newer_format).is_err());
+
+        let newer_component = SaveData::from_toml(
+            r#"
+            format_version = 1
+
+            [[entities]]
+            id = 4294967296
+
+            [entities.components.coin]
+            version = 2
+            value = { value = 5 }
+            "#,
+        )
+        .unwrap();
+        let mut world = hecs::World::new();
+        let registry = registry(UnknownComponents::Skip);
+        assert!(registry.load(&mut world, &newer_component).is_err());
+        assert_eq!(world.len(), 0);
+    }
+
+    #[test]
+    fn save_file_round_trips_with_and_without_compression() {
+        let mut world = hecs::World::new();
+        world.spawn((CoinComponent { value: 3 },));
+        world.spawn((CoinComponent { value: 9 }, 1.5_f32));
+        world.spawn((2_u8,));
+        let registry = registry(UnknownComponents::Skip);
+        let data = registry.save(&world).unwrap();
+        assert_eq!(data.entities.len(), 2);
+
+        for compression in [false, true] {
+            let path = std::env::temp_dir().join(format!(
+                "reverie-save-{}-{compression}.toml",
+                std::process::id()
+            ));
+            let file = SaveFile::new(&path).with_compression(compression);
+            file.write(&data).unwrap();
+            assert!(!file.temp_path().exists());
+            let bytes = std::fs::read(&path).unwrap();
+            assert_eq!(bytes.starts_with(&GZIP_MAGIC), compression);
+            assert_eq!(file.read().unwrap(), data);
+
+            let mut loaded = hecs::World::new();
+            registry.load(&mut loaded, &file.read().unwrap()).unwrap();
+            let mut values: Vec<_> = loaded
+                .query::<&CoinComponent>()
+                .iter()
+                .map(|(_, coin)| coin.value)
+                .collect();
+            values.sort_unstable();
+            assert_eq!(values, [3, 9]);
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+}
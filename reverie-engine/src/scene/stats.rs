@@ -0,0 +1,87 @@
+//! 描画の統計
+use std::time::Duration;
+
+use super::EntityIndex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 1 つの視点から描画したものの数
+pub struct ViewStats {
+    /// 描画に使ったカメラ。`None` ならピクセル座標で描画した
+    pub camera: Option<EntityIndex>,
+    /// [`super::ScreenSpaceComponent`] を持つものを描画したかどうか
+    pub screen_space: bool,
+    /// 描画したスプライトの数
+    pub visible_sprites: usize,
+    /// カメラに写るか調べたスプライトの数
+    pub total_sprites: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+/// 最後に描画したフレームの統計
+///
+/// [`super::Scene::frame_stats`] で取得する。カメラごとに 1 つの [`ViewStats`] がある。
+pub struct FrameStats {
+    views: Vec<ViewStats>,
+    arena_high_water_bytes: usize,
+    arena_grown_bytes: usize,
+    dropped_time: Duration,
+    total_dropped_time: Duration,
+}
+
+impl FrameStats {
+    pub fn views(&self) -> &[ViewStats] {
+        &self.views
+    }
+
+    /// すべての視点で描画したスプライトの数の合計
+    pub fn visible_sprites(&self) -> usize {
+        self.views.iter().map(|view| view.visible_sprites).sum()
+    }
+
+    /// すべての視点で調べたスプライトの数の合計
+    pub fn total_sprites(&self) -> usize {
+        self.views.iter().map(|view| view.total_sprites).sum()
+    }
+
+    /// [`super::FrameArena`] が使ったバッファの容量の最大値のバイト数
+    pub const fn arena_high_water_bytes(&self) -> usize {
+        self.arena_high_water_bytes
+    }
+
+    /// このフレームで [`super::FrameArena`] が新しく確保したバイト数
+    ///
+    /// 動きの無いシーンでは数フレームで 0 になる。
+    pub const fn arena_grown_bytes(&self) -> usize {
+        self.arena_grown_bytes
+    }
+
+    /// 最後に [`crate::headless::HeadlessRunner::advance`] で追いつけずに捨てた時間
+    pub const fn dropped_time(&self) -> Duration {
+        self.dropped_time
+    }
+
+    /// これまでに追いつけずに捨てた時間の合計
+    pub const fn total_dropped_time(&self) -> Duration {
+        self.total_dropped_time
+    }
+
+    pub(crate) fn set_dropped_time(&mut self, dropped: Duration) {
+        self.dropped_time = dropped;
+        self.total_dropped_time += dropped;
+    }
+
+    pub(crate) fn set_arena(&mut self, high_water_bytes: usize, grown_bytes: usize) {
+        self.arena_high_water_bytes = high_water_bytes;
+        self.arena_grown_bytes = grown_bytes;
+    }
+
+    #[cfg(feature = "backend-wgpu")]
+    pub(crate) fn clear(&mut self) {
+        self.views.clear();
+    }
+
+    #[cfg(feature = "backend-wgpu")]
+    pub(crate) fn push(&mut self, view: ViewStats) {
+        self.views.push(view);
+    }
+}
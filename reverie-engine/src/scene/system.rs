@@ -1,25 +1,356 @@
-use std::time::{Duration, Instant};
+use std::{any::TypeId, num::NonZeroU32, time::Duration};
 
+use web_time::Instant;
+
+use nalgebra::{Point2, Point3};
 use winit::{
-    dpi::PhysicalPosition,
+    dpi::{LogicalPosition, PhysicalPosition, PhysicalSize},
     event::{ElementState, KeyEvent, MouseButton, MouseScrollDelta, TouchPhase},
 };
 
-use crate::wgpu_wrapper::WgpuResource;
+#[cfg(feature = "backend-wgpu")]
+use super::DrawList;
+use super::{CameraComponent, TransformComponent};
+use crate::touch::{Gesture, Touch};
+
+#[cfg(feature = "backend-wgpu")]
+/// システムに渡される描画のためのリソース
+///
+/// 描画のバックエンドによって変わる。feature `backend-wgpu` では
+/// [`crate::wgpu_wrapper::WgpuResource`]。
+pub type RenderResource<'window> = crate::wgpu_wrapper::WgpuResource<'window>;
+
+#[cfg(not(feature = "backend-wgpu"))]
+/// システムに渡される描画のためのリソース
+///
+/// feature `backend-wgpu` が無いときは値を作れない型で、システムには常に `None` が渡される。
+pub struct RenderResource<'window> {
+    never: std::convert::Infallible,
+    _window: std::marker::PhantomData<&'window ()>,
+}
+
+#[cfg(not(feature = "backend-wgpu"))]
+impl std::fmt::Debug for RenderResource<'_> {
+    fn fmt(&self, _f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.never {}
+    }
+}
 
 #[derive(Debug)]
 /// フレームごとに更新される情報
 pub struct Frame<'a> {
     pub now: Instant,
+    /// 前のフレームからの経過時間
+    ///
+    /// [`super::Scene::update`] からシステムに渡されるときは [`super::TimeScale`] が反映されている。
     pub delta_time: Duration,
     pub key_events: &'a [KeyEvent],
     pub mouse_clicks: &'a [(ElementState, MouseButton, PhysicalPosition<f64>)],
     pub mouse_wheels: &'a [(MouseScrollDelta, TouchPhase, PhysicalPosition<f64>)],
     pub mouse_position: PhysicalPosition<f64>,
+    /// 画面に触れている指の一覧
+    pub touches: &'a [Touch],
+    /// このフレームで認識されたジェスチャー
+    pub gestures: &'a [Gesture],
+    /// ウィンドウの中の描画できる部分の大きさ (物理ピクセル)
+    pub window_size: PhysicalSize<u32>,
+    /// 論理ピクセルあたりの物理ピクセルの数
+    pub scale_factor: f64,
+}
+
+impl Frame<'static> {
+    /// 入力の無いフレーム
+    ///
+    /// ウィンドウの無い環境で [`super::Scene::update_headless`] を呼ぶのに使う。
+    pub const fn new(now: Instant, delta_time: Duration) -> Self {
+        Self {
+            now,
+            delta_time,
+            key_events: &[],
+            mouse_clicks: &[],
+            mouse_wheels: &[],
+            mouse_position: PhysicalPosition::new(0.0, 0.0),
+            touches: &[],
+            gestures: &[],
+            window_size: PhysicalSize::new(0, 0),
+            scale_factor: 1.0,
+        }
+    }
 }
 
+impl Frame<'_> {
+    /// ウィンドウの大きさを決める。[`Self::new`] で作ったフレームで座標を変換するのに使う
+    pub const fn with_window(mut self, size: PhysicalSize<u32>, scale_factor: f64) -> Self {
+        self.window_size = size;
+        self.scale_factor = scale_factor;
+        self
+    }
+
+    /// 論理ピクセルの位置を、[`Self::mouse_position`] と同じ物理ピクセルの位置に直す
+    pub fn logical_to_physical(&self, logical: LogicalPosition<f64>) -> PhysicalPosition<f64> {
+        logical.to_physical(self.scale_factor)
+    }
+
+    /// カメラの描画先の大きさ。カメラの `target_size` が無ければウィンドウの大きさ
+    fn camera_target_size(&self, camera: &CameraComponent) -> Option<(NonZeroU32, NonZeroU32)> {
+        camera.target_size.or_else(|| {
+            Some((
+                NonZeroU32::new(self.window_size.width)?,
+                NonZeroU32::new(self.window_size.height)?,
+            ))
+        })
+    }
+
+    /// ウィンドウの物理ピクセルの位置を、`camera` から見たワールドの点に直す
+    ///
+    /// [`CameraComponent::viewport`] と [`CameraComponent::virtual_resolution`] を考えるので、
+    /// 画面分割やレターボックスでも正しい。`pixel` がカメラの描く範囲の外なら `None`。
+    /// 論理ピクセルの位置は [`Self::logical_to_physical`] で直してから渡す。
+    /// `transform` はカメラの [`TransformComponent`]。
+    pub fn screen_to_world(
+        &self,
+        pixel: PhysicalPosition<f64>,
+        camera: &CameraComponent,
+        transform: &TransformComponent,
+    ) -> Option<Point2<f32>> {
+        let (width, height) = self.camera_target_size(camera)?;
+        let pixel = Point2::new(pixel.x as f32, pixel.y as f32);
+        camera
+            .target_to_world(transform, width, height, &pixel)
+            .map(|world| world.xy())
+    }
+
+    /// ワールドの点を、`camera` で描いたときのウィンドウの物理ピクセルの位置に直す
+    ///
+    /// [`Self::screen_to_world`] の逆。カメラの描く範囲の外の点でも位置を返す。
+    pub fn world_to_screen(
+        &self,
+        world: &Point3<f32>,
+        camera: &CameraComponent,
+        transform: &TransformComponent,
+    ) -> Option<PhysicalPosition<f64>> {
+        let (width, height) = self.camera_target_size(camera)?;
+        let pixel = camera.world_to_target(transform, width, height, world)?;
+        Some(PhysicalPosition::new(
+            f64::from(pixel.x),
+            f64::from(pixel.y),
+        ))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// [`System::render`] が呼ばれる描画の段階
+pub enum RenderStage {
+    /// ワールドのスプライトを描画する前
+    BeforeWorld,
+    /// [`super::RenderLayerComponent`] が指定した層を描画した後、次の層を描画する前
+    ///
+    /// 描画するものがある層のうち、最後の層の後では呼ばれない。
+    BetweenLayers(i32),
+    /// ワールドのスプライトをすべて描画した後
+    AfterWorld,
+    /// [`super::ScreenSpaceComponent`] を持つスプライトをすべて描画した後
+    AfterUi,
+}
+
+/// シーンのエンティティを毎フレーム更新する処理
+///
+/// `resource` はウィンドウ無しで動かすとき ([`crate::headless::HeadlessRunner`]) は `None` になる。
+/// GPU に触れないシステムは `None` でも動くようにしておくと、サーバーでも使い回せる。
+/// [`RenderResource`] と書いておけば、描画のバックエンドを変えても書き直さずに済む。
 pub trait System {
-    fn setup(&mut self, resource: &WgpuResource<'_>);
+    fn setup(&mut self, resource: Option<&RenderResource<'_>>);
+
+    fn update(
+        &mut self,
+        frame: &Frame<'_>,
+        world: &mut hecs::World,
+        resource: Option<&RenderResource<'_>>,
+    );
+
+    /// 描画の段階ごとに呼ばれる
+    ///
+    /// `draw_list` はその段階で描画している並び。`rp` にはスプライトのパイプラインと
+    /// カメラのバインドグループが設定されている。パイプラインなどを変えてもよく、
+    /// 呼び出しの後でエンジンが元に戻す。独自のパイプラインは
+    /// [`crate::wgpu_wrapper::PipelineCache`] に入れておくと毎フレーム作らずに済む。
+    #[cfg(feature = "backend-wgpu")]
+    fn render(
+        &mut self,
+        _stage: RenderStage,
+        _draw_list: &DrawList,
+        _rp: &mut wgpu::RenderPass<'_>,
+        _resource: &RenderResource<'_>,
+    ) {
+    }
+
+    /// 実行時間の記録 ([`super::SystemTimings`]) などで使う名前。既定は型名
+    fn name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+
+    /// このシステムより先に実行されるべきシステムの型
+    ///
+    /// [`super::Scene::setup`] で実行順が並べ替えられる。
+    fn dependencies(&self) -> &'static [TypeId] {
+        &[]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// システムの依存関係が循環している
+pub struct CycleError {
+    /// 循環に含まれるシステムの型名
+    pub systems: Vec<&'static str>,
+}
+
+impl std::fmt::Display for CycleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "system dependencies have a cycle: {}",
+            self.systems.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for CycleError {}
+
+/// 依存関係を満たす実行順を求める
+///
+/// `nodes` の各要素は自身の型と依存先の型。存在しない依存先は無視する。
+/// 依存関係に縛られないものは元の順序を保つ。
+/// 循環がある場合は、循環を成す要素の添字を `Err` で返す。循環が複数あれば最も前にあるものだけを返す。
+/// 循環に依存しているだけの要素は含まない。
+pub fn topological_order(nodes: &[(TypeId, &[TypeId])]) -> Result<Vec<usize>, Vec<usize>> {
+    let mut placed = vec![false; nodes.len()];
+    let mut order = Vec::with_capacity(nodes.len());
+    let is_ready = |placed: &[bool], deps: &[TypeId]| {
+        deps.iter().all(|dep| {
+            nodes
+                .iter()
+                .enumerate()
+                .all(|(i, (id, _))| id != dep || placed[i])
+        })
+    };
+
+    while order.len() < nodes.len() {
+        let next = (0..nodes.len()).find(|&i| !placed[i] && is_ready(&placed, nodes[i].1));
+        match next {
+            Some(i) => {
+                placed[i] = true;
+                order.push(i);
+            }
+            None => {
+                let unplaced: Vec<_> = (0..nodes.len()).filter(|&i| !placed[i]).collect();
+                return Err(first_cycle(nodes, &unplaced));
+            }
+        }
+    }
+    Ok(order)
+}
+
+/// `unplaced` のうち最も前にある要素を含む循環 (強連結成分) の添字
+///
+/// `unplaced` の要素はどれも `unplaced` の中に依存先を持つので、必ずどこかに循環がある。
+fn first_cycle(nodes: &[(TypeId, &[TypeId])], unplaced: &[usize]) -> Vec<usize> {
+    // `from` から依存をたどって着くもの。`from` 自身は循環していなければ含まない
+    let reachable = |from: usize| {
+        let mut seen = vec![false; nodes.len()];
+        let mut stack = vec![from];
+        while let Some(i) = stack.pop() {
+            for &j in unplaced {
+                if !seen[j] && nodes[i].1.contains(&nodes[j].0) {
+                    seen[j] = true;
+                    stack.push(j);
+                }
+            }
+        }
+        seen
+    };
+    for &i in unplaced {
+        let from_i = reachable(i);
+        if from_i[i] {
+            return unplaced
+                .iter()
+                .copied()
+                .filter(|&j| from_i[j] && reachable(j)[i])
+                .collect();
+        }
+    }
+    unplaced.to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct A;
+    struct B;
+    struct C;
+
+    #[test]
+    fn keeps_registration_order_without_dependencies() {
+        let a = TypeId::of::<A>();
+        let b = TypeId::of::<B>();
+        assert_eq!(topological_order(&[(a, &[]), (b, &[])]), Ok(vec![0, 1]));
+    }
+
+    #[test]
+    fn dependency_runs_first() {
+        let a = TypeId::of::<A>();
+        let b = TypeId::of::<B>();
+        let c = TypeId::of::<C>();
+        let deps_a = [c];
+        let deps_c = [b];
+        assert_eq!(
+            topological_order(&[(a, &deps_a), (b, &[]), (c, &deps_c)]),
+            Ok(vec![1, 2, 0])
+        );
+    }
+
+    #[test]
+    fn missing_dependency_is_ignored() {
+        let a = TypeId::of::<A>();
+        let deps_a = [TypeId::of::<B>()];
+        assert_eq!(topological_order(&[(a, &deps_a)]), Ok(vec![0]));
+    }
+
+    #[test]
+    fn cycle_is_detected() {
+        let a = TypeId::of::<A>();
+        let b = TypeId::of::<B>();
+        let c = TypeId::of::<C>();
+        let deps_a = [b];
+        let deps_b = [a];
+        assert_eq!(
+            topological_order(&[(a, &deps_a), (b, &deps_b), (c, &[])]),
+            Err(vec![0, 1])
+        );
+    }
 
-    fn update(&mut self, frame: &Frame<'_>, world: &mut hecs::World, resource: &WgpuResource<'_>);
+    #[test]
+    fn cycle_excludes_systems_that_only_depend_on_it() {
+        struct D;
+        let a = TypeId::of::<A>();
+        let b = TypeId::of::<B>();
+        let c = TypeId::of::<C>();
+        let d = TypeId::of::<D>();
+        // D は循環している B と C に依存しているだけ
+        let deps_a = [];
+        let deps_b = [c];
+        let deps_c = [b];
+        let deps_d = [b, a];
+        assert_eq!(
+            topological_order(&[(d, &deps_d), (a, &deps_a), (b, &deps_b), (c, &deps_c)]),
+            Err(vec![2, 3])
+        );
+        // 自分自身に依存するものは 1 つだけで循環になる
+        let deps_self = [a];
+        assert_eq!(
+            topological_order(&[(b, &deps_b), (c, &deps_c), (a, &deps_self)]),
+            Err(vec![0, 1])
+        );
+        assert_eq!(topological_order(&[(a, &deps_self)]), Err(vec![0]));
+    }
 }
@@ -0,0 +1,217 @@
+//! 重い処理を何フレームかに分けて進める
+use std::{cmp::Reverse, collections::BTreeMap, time::Duration};
+
+use web_time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// [`Task::step`] の結果
+pub enum TaskStatus {
+    /// 終わった。キューから取り除く
+    Done,
+    /// まだ続きがある。次の機会にもう一度 [`Task::step`] を呼ぶ
+    Pending,
+}
+
+/// 少しずつ進める処理
+///
+/// 1 回の [`Self::step`] は短くしておく。時間の予算は [`Self::step`] の間でしか確かめない。
+/// [`TaskQueue`] はリソースなので、入れるタスクは `Send + Sync` でなければならない。
+/// `FnMut(&mut hecs::World) -> TaskStatus` のクロージャも使える。
+pub trait Task {
+    fn step(&mut self, world: &mut hecs::World) -> TaskStatus;
+}
+
+impl<F: FnMut(&mut hecs::World) -> TaskStatus> Task for F {
+    fn step(&mut self, world: &mut hecs::World) -> TaskStatus {
+        self(world)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// [`TaskQueue`] に入れたタスクの ID
+pub struct TaskId(u64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// 最後のフレームの [`TaskQueue`] の統計
+pub struct TaskStats {
+    /// 実行した後にキューに残っているタスクの数
+    pub queue_depth: usize,
+    /// タスクの実行にかかった時間
+    pub time_spent: Duration,
+    /// 呼んだ [`Task::step`] の回数
+    pub steps: usize,
+    /// 終わったタスクの数
+    pub completed: usize,
+}
+
+/// 優先度と時間の予算に従って、毎フレーム少しずつタスクを進めるキュー
+///
+/// リソースとして置いておくと、[`super::Scene::update`] がすべてのシステムの後に
+/// [`Self::budget`] を使い切るまでタスクを進める。無ければ作る。
+/// 優先度の高いタスクから、同じ優先度なら入れた順に進める。
+/// 予算を超えても、キューが空でなければ少なくとも 1 回は [`Task::step`] を呼ぶ。
+///
+/// タスクの実行中はキューがリソースから外れているので、タスクの中からはタスクを追加できない。
+pub struct TaskQueue {
+    /// 1 フレームにタスクを実行する時間
+    pub budget: Duration,
+    tasks: BTreeMap<(Reverse<i32>, TaskId), Box<dyn Task + Send + Sync>>,
+    next_id: u64,
+    stats: TaskStats,
+}
+
+impl Default for TaskQueue {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_BUDGET)
+    }
+}
+
+impl std::fmt::Debug for TaskQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TaskQueue")
+            .field("budget", &self.budget)
+            .field("len", &self.tasks.len())
+            .field("stats", &self.stats)
+            .finish()
+    }
+}
+
+impl TaskQueue {
+    pub const DEFAULT_BUDGET: Duration = Duration::from_millis(2);
+
+    pub const fn new(budget: Duration) -> Self {
+        Self {
+            budget,
+            tasks: BTreeMap::new(),
+            next_id: 0,
+            stats: TaskStats {
+                queue_depth: 0,
+                time_spent: Duration::ZERO,
+                steps: 0,
+                completed: 0,
+            },
+        }
+    }
+
+    /// 少しずつ進めるタスクを入れる。`priority` が大きいほど先に進める
+    pub fn push(&mut self, priority: i32, task: impl Task + Send + Sync + 'static) -> TaskId {
+        let id = TaskId(self.next_id);
+        self.next_id += 1;
+        self.tasks.insert((Reverse(priority), id), Box::new(task));
+        id
+    }
+
+    /// 1 回で終わる処理を入れる
+    pub fn push_once(
+        &mut self,
+        priority: i32,
+        task: impl FnOnce(&mut hecs::World) + Send + Sync + 'static,
+    ) -> TaskId {
+        let mut task = Some(task);
+        self.push(priority, move |world: &mut hecs::World| {
+            if let Some(task) = task.take() {
+                task(world);
+            }
+            TaskStatus::Done
+        })
+    }
+
+    /// まだ終わっていないタスクを取り除く。取り除いたら `true`
+    pub fn cancel(&mut self, id: TaskId) -> bool {
+        let key = self.tasks.keys().find(|(_, task)| *task == id).copied();
+        key.and_then(|key| self.tasks.remove(&key)).is_some()
+    }
+
+    pub fn contains(&self, id: TaskId) -> bool {
+        self.tasks.keys().any(|(_, task)| *task == id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// 最後に [`Self::run`] したときの統計
+    pub const fn stats(&self) -> TaskStats {
+        self.stats
+    }
+
+    /// [`Self::budget`] を使い切るまでタスクを進める
+    pub fn run(&mut self, world: &mut hecs::World) {
+        let start = Instant::now();
+        let mut stats = TaskStats::default();
+        while let Some(mut entry) = self.tasks.first_entry() {
+            if stats.steps > 0 && start.elapsed() >= self.budget {
+                break;
+            }
+            stats.steps += 1;
+            if entry.get_mut().step(world) == TaskStatus::Done {
+                entry.remove();
+                stats.completed += 1;
+            }
+        }
+        stats.time_spent = start.elapsed();
+        stats.queue_depth = self.tasks.len();
+        self.stats = stats;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    #[test]
+    fn runs_by_priority_until_budget() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut queue = TaskQueue::new(Duration::ZERO);
+        let logger = |name: &'static str, steps: u32| {
+            let log = Arc::clone(&log);
+            let mut left = steps;
+            move |_: &mut hecs::World| {
+                log.lock().unwrap().push(name);
+                left -= 1;
+                if left == 0 {
+                    TaskStatus::Done
+                } else {
+                    TaskStatus::Pending
+                }
+            }
+        };
+        queue.push(0, logger("path", 1));
+        queue.push(5, logger("mesh", 2));
+        let atlas = queue.push(5, logger("atlas", 1));
+
+        // 予算が 0 でも 1 回は進める
+        let mut world = hecs::World::new();
+        queue.run(&mut world);
+        assert_eq!(*log.lock().unwrap(), ["mesh"]);
+        assert_eq!(queue.stats().queue_depth, 3);
+
+        assert!(queue.cancel(atlas));
+        assert!(!queue.cancel(atlas));
+        queue.budget = Duration::from_secs(1);
+        queue.run(&mut world);
+        assert_eq!(*log.lock().unwrap(), ["mesh", "mesh", "path"]);
+        let stats = queue.stats();
+        assert_eq!((stats.steps, stats.completed, stats.queue_depth), (2, 2, 0));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn scene_runs_tasks_after_systems() {
+        let mut scene = crate::scene::Scene::default();
+        let mut queue = TaskQueue::default();
+        queue.push_once(0, |world| {
+            world.spawn((42_u32,));
+        });
+        scene.insert_resource(queue);
+        scene.update_headless(&super::super::Frame::new(Instant::now(), Duration::ZERO));
+        assert_eq!(scene.world.query_mut::<&u32>().into_iter().count(), 1);
+        assert_eq!(scene.resource::<TaskQueue>().unwrap().stats().completed, 1);
+    }
+}
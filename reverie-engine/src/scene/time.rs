@@ -0,0 +1,101 @@
+//! シミュレーションの時間の進み方
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// シミュレーションの時間の速さと一時停止
+///
+/// リソースとして [`super::Scene::insert_resource`] で追加すると、
+/// [`super::Scene::update`] がすべてのシステムに渡す [`super::Frame::delta_time`] に反映される。
+/// 無ければ等倍で進む。
+pub struct TimeScale {
+    /// 1.0 で等倍、0.5 でスローモーション。[`Self::set_scale`] で 0 以上 [`Self::MAX_SCALE`] 以下にする
+    scale: f32,
+    /// `true` の間は `delta_time` が 0 になる
+    pub paused: bool,
+}
+
+impl Default for TimeScale {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            paused: false,
+        }
+    }
+}
+
+impl TimeScale {
+    /// 速さの上限。これより速くすると、長いフレームで経過時間が [`Duration`] に収まらなくなる
+    pub const MAX_SCALE: f32 = 1000.0;
+
+    pub fn new(scale: f32) -> Self {
+        let mut time_scale = Self::default();
+        time_scale.set_scale(scale);
+        time_scale
+    }
+
+    pub const fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// 速さを変える
+    ///
+    /// 負の値は 0、[`Self::MAX_SCALE`] より大きい値や無限大は [`Self::MAX_SCALE`] にする。
+    /// NaN は受け付けず、ログに残して今の速さのままにする。
+    pub fn set_scale(&mut self, scale: f32) {
+        if scale.is_nan() {
+            tracing::warn!("ignored NaN time scale");
+            return;
+        }
+        self.scale = scale.clamp(0.0, Self::MAX_SCALE);
+    }
+
+    /// 実時間の経過時間をシミュレーションの経過時間に変換する
+    pub fn apply(&self, delta_time: Duration) -> Duration {
+        if self.paused {
+            return Duration::ZERO;
+        }
+        delta_time.mul_f32(self.scale)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// シーンが始まってからのシミュレーションの経過時間
+///
+/// [`super::Scene::update`] が [`TimeScale`] を反映した経過時間だけ進める。
+/// タイルのアニメーションのように、すべてのエンティティで揃えて動かすものに使う。
+pub struct SceneClock {
+    pub elapsed: Duration,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scales_and_pauses() {
+        let dt = Duration::from_millis(20);
+        assert_eq!(TimeScale::default().apply(dt), dt);
+        let slow = TimeScale::new(0.5);
+        assert_eq!(slow.apply(dt), Duration::from_millis(10));
+        let paused = TimeScale {
+            paused: true,
+            ..slow
+        };
+        assert_eq!(paused.apply(dt), Duration::ZERO);
+    }
+
+    #[test]
+    fn non_finite_scales_do_not_break_the_frame() {
+        let dt = Duration::from_secs(1);
+        let mut fast = TimeScale::new(f32::INFINITY);
+        assert_eq!(fast.scale(), TimeScale::MAX_SCALE);
+        assert_eq!(fast.apply(dt), dt.mul_f32(TimeScale::MAX_SCALE));
+        assert_eq!(TimeScale::new(f32::NEG_INFINITY).apply(dt), Duration::ZERO);
+        assert_eq!(TimeScale::new(-2.0).scale(), 0.0);
+
+        fast.set_scale(0.5);
+        fast.set_scale(f32::NAN);
+        assert_eq!(fast.scale(), 0.5);
+        assert_eq!(TimeScale::new(f32::NAN), TimeScale::default());
+    }
+}
@@ -0,0 +1,295 @@
+//! システムと描画の段階ごとの実行時間
+use std::{collections::VecDeque, time::Duration};
+
+use nalgebra::Translation3;
+
+use super::{
+    resource, Frame, RenderResource, RenderStage, ScreenSpaceComponent, System, TextComponent,
+    TransformComponent,
+};
+use crate::text::{Fonts, TextStyle};
+
+/// 平均と最悪値を求めるのに使うフレームの数
+pub const TIMING_WINDOW: usize = 120;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// 直近 [`TIMING_WINDOW`] フレームの実行時間
+pub struct TimingStats {
+    /// 最後のフレームの実行時間
+    pub current: Duration,
+    pub average: Duration,
+    pub worst: Duration,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Samples {
+    samples: VecDeque<Duration>,
+}
+
+impl Samples {
+    fn push(&mut self, sample: Duration) {
+        if self.samples.len() == TIMING_WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    fn stats(&self) -> TimingStats {
+        let total: Duration = self.samples.iter().sum();
+        TimingStats {
+            current: self.samples.back().copied().unwrap_or_default(),
+            average: total / self.samples.len().max(1) as u32,
+            worst: self.samples.iter().max().copied().unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// システムの [`System::update`] と、描画の段階ごとの [`System::render`] にかかった時間
+///
+/// [`super::Scene::update`] がリソースとして追加し、毎フレーム記録する。
+/// システムは [`System::name`] で区別する。描画の段階はすべてのシステムの合計で、
+/// 1 フレームに何度も描画する段階 (カメラが複数あるときなど) は合計して 1 フレーム分とする。
+/// 段階の時間は [`super::Scene::end_frame`] で締める。
+///
+/// ```ignore
+/// let timings = scene.resource::<SystemTimings>().unwrap();
+/// assert!(timings.system(std::any::type_name::<Physics>()).unwrap().worst < budget);
+/// ```
+pub struct SystemTimings {
+    systems: Vec<(&'static str, Samples)>,
+    stages: Vec<(RenderStage, Samples)>,
+    /// 締める前の、このフレームの段階ごとの時間
+    pending_stages: Vec<(RenderStage, Duration)>,
+}
+
+impl SystemTimings {
+    /// システム `name` の実行時間
+    pub fn system(&self, name: &str) -> Option<TimingStats> {
+        self.systems
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, samples)| samples.stats())
+    }
+
+    /// すべてのシステムの実行時間。実行した順
+    pub fn systems(&self) -> impl Iterator<Item = (&'static str, TimingStats)> + '_ {
+        self.systems
+            .iter()
+            .map(|(name, samples)| (*name, samples.stats()))
+    }
+
+    /// 描画の段階 `stage` の実行時間
+    pub fn stage(&self, stage: RenderStage) -> Option<TimingStats> {
+        self.stages
+            .iter()
+            .find(|(s, _)| *s == stage)
+            .map(|(_, samples)| samples.stats())
+    }
+
+    /// すべての描画の段階の実行時間
+    pub fn stages(&self) -> impl Iterator<Item = (RenderStage, TimingStats)> + '_ {
+        self.stages
+            .iter()
+            .map(|(stage, samples)| (*stage, samples.stats()))
+    }
+
+    /// 平均の実行時間が長いシステムを `n` 個、長い順に返す
+    pub fn worst_systems(&self, n: usize) -> Vec<(&'static str, TimingStats)> {
+        let mut systems: Vec<_> = self.systems().collect();
+        systems.sort_by_key(|(_, stats)| std::cmp::Reverse(stats.average));
+        systems.truncate(n);
+        systems
+    }
+
+    pub(crate) fn record_system(&mut self, name: &'static str, elapsed: Duration) {
+        match self.systems.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, samples)) => samples.push(elapsed),
+            None => {
+                let mut samples = Samples::default();
+                samples.push(elapsed);
+                self.systems.push((name, samples));
+            }
+        }
+    }
+
+    #[cfg(feature = "backend-wgpu")]
+    pub(crate) fn record_stage(&mut self, stage: RenderStage, elapsed: Duration) {
+        match self.pending_stages.iter_mut().find(|(s, _)| *s == stage) {
+            Some((_, total)) => *total += elapsed,
+            None => self.pending_stages.push((stage, elapsed)),
+        }
+    }
+
+    /// このフレームの段階ごとの時間を記録する
+    pub(crate) fn end_frame(&mut self) {
+        for (stage, elapsed) in self.pending_stages.drain(..) {
+            match self.stages.iter_mut().find(|(s, _)| *s == stage) {
+                Some((_, samples)) => samples.push(elapsed),
+                None => {
+                    let mut samples = Samples::default();
+                    samples.push(elapsed);
+                    self.stages.push((stage, samples));
+                }
+            }
+        }
+    }
+}
+
+/// `type_name` のモジュールの部分を除いた名前
+fn short_name(name: &str) -> &str {
+    let base = name.split('<').next().unwrap_or(name);
+    base.rsplit("::").next().unwrap_or(base)
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[derive(Debug)]
+/// 平均の実行時間が長いシステムを 5 つ、画面の左上に表示するシステム
+///
+/// [`FontId::DEBUG`](crate::text::FontId::DEBUG) の文字で描くので、フォントを用意しなくてよい。
+/// リソース [`Fonts`] が無ければ追加する。
+pub struct TimingOverlaySystem {
+    /// 表示する位置 (ピクセル)
+    pub position: Translation3<f32>,
+    pub text_size: f32,
+    text: Option<hecs::Entity>,
+}
+
+impl Default for TimingOverlaySystem {
+    fn default() -> Self {
+        Self {
+            position: Translation3::new(8.0, 8.0, 0.0),
+            text_size: 12.0,
+            text: None,
+        }
+    }
+}
+
+impl TimingOverlaySystem {
+    /// 表示する行の数
+    pub const LINES: usize = 5;
+
+    fn overlay_text(timings: &SystemTimings) -> String {
+        let mut text = String::from("system  now / avg / worst (ms)");
+        for (name, stats) in timings.worst_systems(Self::LINES) {
+            text.push_str(&format!(
+                "\n{}  {:.2} / {:.2} / {:.2}",
+                short_name(name),
+                millis(stats.current),
+                millis(stats.average),
+                millis(stats.worst),
+            ));
+        }
+        text
+    }
+}
+
+impl System for TimingOverlaySystem {
+    fn setup(&mut self, _resource: Option<&RenderResource<'_>>) {}
+
+    fn update(
+        &mut self,
+        _frame: &Frame<'_>,
+        world: &mut hecs::World,
+        _resource: Option<&RenderResource<'_>>,
+    ) {
+        if resource::<Fonts>(world).is_none() {
+            super::insert_resource(world, Fonts::new());
+        }
+        let Some(text) =
+            resource::<SystemTimings>(world).map(|timings| Self::overlay_text(&timings))
+        else {
+            return;
+        };
+        if let Some(mut component) = self
+            .text
+            .and_then(|entity| world.get::<&mut TextComponent>(entity).ok())
+        {
+            if component.text() != text {
+                component.set_text(text);
+            }
+            return;
+        }
+        self.text = Some(world.spawn((
+            TransformComponent::with_translation(self.position),
+            TextComponent::new(text, TextStyle::debug(self.text_size)),
+            ScreenSpaceComponent,
+        )));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rolling_window_keeps_last_frames() {
+        let mut timings = SystemTimings::default();
+        timings.record_system("slow", Duration::from_millis(500));
+        for _ in 0..TIMING_WINDOW {
+            timings.record_system("slow", Duration::from_millis(2));
+            timings.record_system("fast", Duration::from_millis(1));
+        }
+        timings.record_system("fast", Duration::from_millis(4));
+
+        // 最初の 500ms は窓から外れた
+        let slow = timings.system("slow").unwrap();
+        assert_eq!(slow.worst, Duration::from_millis(2));
+        assert_eq!(slow.average, Duration::from_millis(2));
+        let fast = timings.system("fast").unwrap();
+        assert_eq!(fast.current, Duration::from_millis(4));
+        assert_eq!(fast.worst, Duration::from_millis(4));
+        assert_eq!(
+            timings
+                .worst_systems(1)
+                .iter()
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>(),
+            ["slow"]
+        );
+        assert!(timings.system("missing").is_none());
+    }
+
+    #[test]
+    fn scene_records_each_system_by_name() {
+        struct Physics;
+        impl System for Physics {
+            fn setup(&mut self, _resource: Option<&RenderResource<'_>>) {}
+            fn update(
+                &mut self,
+                _frame: &Frame<'_>,
+                _world: &mut hecs::World,
+                _resource: Option<&RenderResource<'_>>,
+            ) {
+            }
+            fn name(&self) -> &'static str {
+                "physics"
+            }
+        }
+
+        let mut scene = crate::scene::Scene::default();
+        scene.register_system(Physics);
+        scene.register_system(TimingOverlaySystem::default());
+        scene.setup_headless().unwrap();
+        for _ in 0..3 {
+            scene.update_headless(&Frame::new(web_time::Instant::now(), Duration::ZERO));
+        }
+        let timings = scene.resource::<SystemTimings>().unwrap();
+        assert!(timings.system("physics").unwrap().worst < Duration::from_secs(1));
+        let overlay = std::any::type_name::<TimingOverlaySystem>();
+        assert!(timings.system(overlay).is_some());
+    }
+
+    #[test]
+    fn overlay_shows_short_names() {
+        assert_eq!(short_name("my_game::systems::Physics"), "Physics");
+        assert_eq!(short_name("my_game::Wrap<my_game::Inner>"), "Wrap");
+        let mut timings = SystemTimings::default();
+        timings.record_system("my_game::Physics", Duration::from_micros(1500));
+        let text = TimingOverlaySystem::overlay_text(&timings);
+        assert_eq!(text.lines().nth(1), Some("Physics  1.50 / 1.50 / 1.50"));
+    }
+}
@@ -0,0 +1,253 @@
+//! よくある使い方の誤りを実行時に見つける
+use std::collections::HashSet;
+
+use super::{EntityIndex, SpriteComponent, TextComponent, TilemapComponent, TransformComponent};
+use crate::texture::{TextureId, TextureRegistry};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// [`Validation`] が調べる誤りの種類
+pub enum ValidationCheck {
+    /// 読み込まれていない (または取り除かれた) テクスチャを指すスプライトやタイルマップ
+    MissingTexture,
+    /// [`super::Scene::setup`] の後に登録されたシステム。[`super::System::setup`] が呼ばれない
+    SystemAfterSetup,
+    /// [`TransformComponent`] の無いエンティティに付いた、描画するコンポーネント
+    MissingTransform,
+}
+
+impl ValidationCheck {
+    /// 直し方の手がかり
+    pub const fn hint(self) -> &'static str {
+        match self {
+            Self::MissingTexture => {
+                "load the texture with TextureRegistry::new_texture before using its TextureId; it is drawn as the missing-texture checker"
+            }
+            Self::SystemAfterSetup => {
+                "register the system before Scene::setup; its System::setup was never called"
+            }
+            Self::MissingTransform => {
+                "attach a TransformComponent to the entity; it is not rendered without one"
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// よくある誤りを調べ、見つけたら 1 度だけ `tracing` のエラーを出す
+///
+/// [`super::Scene::validation_mut`] で設定する。デバッグビルドでは有効、リリースビルドでは無効になっている。
+/// 無効なら何も調べない。誤りは種類とエンティティの組ごとに 1 度だけ報告する。
+/// 種類ごとに [`Self::suppress`] で報告しないようにできる。
+pub struct Validation {
+    enabled: bool,
+    suppressed: HashSet<ValidationCheck>,
+    reported: HashSet<(ValidationCheck, Option<EntityIndex>)>,
+}
+
+impl Default for Validation {
+    fn default() -> Self {
+        Self::new(cfg!(debug_assertions))
+    }
+}
+
+impl Validation {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            suppressed: HashSet::new(),
+            reported: HashSet::new(),
+        }
+    }
+
+    pub const fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// `check` の誤りを報告しないようにする
+    pub fn suppress(&mut self, check: ValidationCheck) {
+        self.suppressed.insert(check);
+    }
+
+    /// [`Self::suppress`] を取り消す
+    pub fn unsuppress(&mut self, check: ValidationCheck) {
+        self.suppressed.remove(&check);
+    }
+
+    /// `check` を調べるか
+    pub fn is_active(&self, check: ValidationCheck) -> bool {
+        self.enabled && !self.suppressed.contains(&check)
+    }
+
+    /// これまでに報告した誤り。システムの誤りではエンティティは `None`
+    pub fn reported(&self) -> impl Iterator<Item = (ValidationCheck, Option<EntityIndex>)> + '_ {
+        self.reported.iter().copied()
+    }
+
+    /// 初めて見つけた誤りなら報告する
+    fn report(&mut self, check: ValidationCheck, entity: Option<EntityIndex>, message: &str) {
+        if !self.reported.insert((check, entity)) {
+            return;
+        }
+        match entity {
+            Some(entity) => tracing::error!(?check, ?entity, hint = check.hint(), "{message}"),
+            None => tracing::error!(?check, hint = check.hint(), "{message}"),
+        }
+    }
+
+    /// [`super::Scene::setup`] の後に登録されたシステムを報告する
+    pub(crate) fn system_registered_after_setup(&mut self, name: &'static str) {
+        if self.is_active(ValidationCheck::SystemAfterSetup) {
+            self.report(
+                ValidationCheck::SystemAfterSetup,
+                None,
+                &format!("system {name} was registered after the scene was set up"),
+            );
+        }
+    }
+
+    /// ワールドのエンティティを調べる。`textures` が無ければテクスチャは調べない
+    pub(crate) fn check_world(&mut self, world: &hecs::World, textures: Option<&TextureRegistry>) {
+        if self.is_active(ValidationCheck::MissingTransform) {
+            self.check_transforms::<SpriteComponent>(world, "SpriteComponent");
+            self.check_transforms::<TextComponent>(world, "TextComponent");
+            self.check_transforms::<TilemapComponent>(world, "TilemapComponent");
+        }
+        if let Some(textures) = textures {
+            if self.is_active(ValidationCheck::MissingTexture) {
+                self.check_textures(world, textures);
+            }
+        }
+    }
+
+    fn check_transforms<C: hecs::Component>(&mut self, world: &hecs::World, component: &str) {
+        for (entity, _) in world.query::<&C>().without::<&TransformComponent>().iter() {
+            // 毎フレーム呼ばれるので、報告済みのエンティティでは文を組み立てない
+            let entity = Some(EntityIndex(entity));
+            if self
+                .reported
+                .contains(&(ValidationCheck::MissingTransform, entity))
+            {
+                continue;
+            }
+            self.report(
+                ValidationCheck::MissingTransform,
+                entity,
+                &format!("{component} is attached to an entity without a TransformComponent"),
+            );
+        }
+    }
+
+    fn check_textures(&mut self, world: &hecs::World, textures: &TextureRegistry) {
+        // 取り除かれたテクスチャは MISSING に置き換えられる
+        let is_missing = |id: TextureId| textures.resolve(id) != id;
+        for (entity, sprite) in world.query::<&SpriteComponent>().iter() {
+            if is_missing(sprite.texture()) {
+                self.report(
+                    ValidationCheck::MissingTexture,
+                    Some(EntityIndex(entity)),
+                    "sprite refers to a texture that is not loaded",
+                );
+            }
+        }
+        for (entity, tilemap) in world.query::<&TilemapComponent>().iter() {
+            if is_missing(tilemap.tileset().texture) {
+                self.report(
+                    ValidationCheck::MissingTexture,
+                    Some(EntityIndex(entity)),
+                    "tileset refers to a texture that is not loaded",
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::{Frame, RenderResource, Scene, System};
+
+    struct Noop;
+
+    impl System for Noop {
+        fn setup(&mut self, _resource: Option<&RenderResource<'_>>) {}
+        fn update(
+            &mut self,
+            _frame: &Frame<'_>,
+            _world: &mut hecs::World,
+            _resource: Option<&RenderResource<'_>>,
+        ) {
+        }
+    }
+
+    fn reported(scene: &Scene) -> Vec<(ValidationCheck, Option<EntityIndex>)> {
+        scene.validation().reported().collect()
+    }
+
+    #[test]
+    fn reports_each_mistake_once() {
+        let mut textures = TextureRegistry::default();
+        let loaded = textures.new_texture(image::RgbaImage::new(1, 1), None);
+        let unloaded = textures.new_texture(image::RgbaImage::new(1, 1), None);
+        textures.unload(unloaded).unwrap();
+
+        let mut scene = Scene::default();
+        scene.validation_mut().set_enabled(true);
+        let orphan = EntityIndex(
+            scene
+                .world
+                .spawn((SpriteComponent::new(TextureId::Single(loaded)),)),
+        );
+        let stale = scene.new_entity(
+            TransformComponent::default(),
+            SpriteComponent::new(TextureId::Single(unloaded)),
+        );
+        scene.new_entity(
+            TransformComponent::default(),
+            SpriteComponent::new(TextureId::Single(loaded)),
+        );
+        scene.setup_headless().unwrap();
+        scene.register_system(Noop);
+
+        for _ in 0..3 {
+            scene.validation.check_world(&scene.world, Some(&textures));
+        }
+        let mut found = reported(&scene);
+        found.sort_by_key(|(check, _)| *check as u8);
+        assert_eq!(
+            found,
+            [
+                (ValidationCheck::MissingTexture, Some(stale)),
+                (ValidationCheck::SystemAfterSetup, None),
+                (ValidationCheck::MissingTransform, Some(orphan)),
+            ]
+        );
+    }
+
+    #[test]
+    fn disabled_or_suppressed_checks_are_silent() {
+        let mut scene = Scene::default();
+        scene.validation_mut().set_enabled(false);
+        scene.world.spawn((SpriteComponent::new(TextureId::WHITE),));
+        scene.setup_headless().unwrap();
+        scene.register_system(Noop);
+        scene.update_headless(&Frame::new(web_time::Instant::now(), Default::default()));
+        assert!(reported(&scene).is_empty());
+
+        scene.validation_mut().set_enabled(true);
+        scene
+            .validation_mut()
+            .suppress(ValidationCheck::MissingTransform);
+        scene.update_headless(&Frame::new(web_time::Instant::now(), Default::default()));
+        assert!(reported(&scene).is_empty());
+
+        scene
+            .validation_mut()
+            .unsuppress(ValidationCheck::MissingTransform);
+        scene.update_headless(&Frame::new(web_time::Instant::now(), Default::default()));
+        assert_eq!(reported(&scene).len(), 1);
+    }
+}
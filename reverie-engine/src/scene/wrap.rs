@@ -0,0 +1,93 @@
+//! 横方向につながったワールド
+use reverie_util::math::wrap;
+
+use super::{resource, ParentComponent, ScreenSpaceComponent, TransformComponent};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// ワールドを x 方向に `width` の幅でつなげる
+///
+/// リソースとして [`super::Scene::insert_resource`] で追加すると、[`super::Scene::update`] が
+/// システムの後で、親の無いエンティティの x 座標を 0 以上 `width` 未満に収める。
+/// 継ぎ目の近くのスプライトはカメラから見て反対側にも写るように、継ぎ目の両側に描く。
+/// 距離や向きは [`reverie_util::math::wrap::wrapped_delta`] で継ぎ目をまたいで求める。
+pub struct WorldWrap {
+    pub width: f32,
+}
+
+impl WorldWrap {
+    pub const fn new(width: f32) -> Self {
+        Self { width }
+    }
+
+    /// `x` を 0 以上 [`Self::width`] 未満に収める
+    pub fn wrap(&self, x: f32) -> f32 {
+        wrap::wrap(x, self.width)
+    }
+
+    /// `a` から `b` への x 座標の最も短い差
+    pub fn delta(&self, a: f32, b: f32) -> f32 {
+        wrap::wrapped_delta(a, b, self.width)
+    }
+
+    /// 描くときに x 座標をずらす量の候補。本来の位置が先
+    #[cfg(feature = "backend-wgpu")]
+    pub(crate) fn shifts(&self) -> [f32; 3] {
+        [0.0, -self.width, self.width]
+    }
+}
+
+/// `world` に [`WorldWrap`] があれば、親の無いエンティティの x 座標を収める
+///
+/// [`ScreenSpaceComponent`] を持つものは画面の座標なので動かさない。
+/// 子は [`super::propagate_transforms`] で親に付いていく。
+pub(super) fn wrap_transforms(world: &mut hecs::World) {
+    let Some(wrap) = resource::<WorldWrap>(world).map(|wrap| *wrap) else {
+        return;
+    };
+    for (_, transform) in world
+        .query_mut::<&mut TransformComponent>()
+        .without::<&ParentComponent>()
+        .without::<&ScreenSpaceComponent>()
+    {
+        transform.translation.x = wrap.wrap(transform.translation.x);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use nalgebra::Translation3;
+    use web_time::Instant;
+
+    use super::*;
+    use crate::scene::{set_parent, EntityIndex, Frame, LocalTransformComponent, Scene};
+
+    #[test]
+    fn roots_wrap_after_update_and_children_follow() {
+        let mut scene = Scene::default();
+        scene.insert_resource(WorldWrap::new(100.0));
+        let at = |x| TransformComponent::with_translation(Translation3::new(x, 5.0, 0.0));
+        let player = EntityIndex(scene.world.spawn((at(104.0),)));
+        let hud = EntityIndex(scene.world.spawn((at(130.0), ScreenSpaceComponent)));
+        let child = EntityIndex(scene.world.spawn((at(0.0),)));
+        set_parent(&mut scene.world, child, player).unwrap();
+        scene
+            .world
+            .insert_one(child.0, LocalTransformComponent(at(3.0)))
+            .unwrap();
+
+        scene.update_headless(&Frame::new(Instant::now(), Duration::ZERO));
+        let x = |entity: EntityIndex| {
+            scene
+                .world
+                .get::<&TransformComponent>(entity.0)
+                .unwrap()
+                .translation
+                .x
+        };
+        assert_eq!(x(player), 4.0);
+        assert_eq!(x(child), 7.0);
+        assert_eq!(x(hud), 130.0);
+    }
+}
@@ -0,0 +1,65 @@
+struct VertexInput {
+  @location(0) position: vec3<f32>,
+  @location(1) uv: vec2<f32>,
+  @location(2) color: vec4<f32>
+}
+
+struct ScrollInput {
+  // テクスチャのアトラスの中の範囲。xy が左上、zw が右下
+  @location(3) rect: vec4<f32>,
+  // 1 秒あたりに動かす UV
+  @location(4) velocity: vec2<f32>,
+  // 1 秒あたりに回す角度 (ラジアン)
+  @location(5) rotation: f32
+}
+
+struct VertexOutput {
+  // テクスチャの範囲を 0 から 1 とした UV。範囲の外は折り返す
+  @location(0) local: vec2<f32>,
+  @location(1) color: vec4<f32>,
+  @location(2) @interpolate(flat) rect: vec4<f32>,
+  @builtin(position) position: vec4<f32>
+}
+
+@group(0)
+@binding(0)
+var tex: texture_2d<f32>;
+
+@group(0)
+@binding(1)
+var samp: sampler;
+
+@group(1)
+@binding(0)
+var<uniform> transform: mat4x4<f32>;
+
+// x: シーンの経過時間の秒数
+@group(2)
+@binding(0)
+var<uniform> globals: vec4<f32>;
+
+@vertex
+fn vs_main(in: VertexInput, scroll: ScrollInput) -> VertexOutput {
+  let t = globals.x;
+  let size = max(scroll.rect.zw - scroll.rect.xy, vec2<f32>(1e-6));
+  let p = (in.uv - scroll.rect.xy) / size - 0.5;
+  let angle = scroll.rotation * t;
+  let c = cos(angle);
+  let s = sin(angle);
+  let rotated = vec2<f32>(c * p.x - s * p.y, s * p.x + c * p.y) + 0.5;
+
+  var out: VertexOutput;
+  out.local = rotated - scroll.velocity * t;
+  out.color = in.color;
+  out.rect = scroll.rect;
+  out.position = transform * vec4<f32>(in.position, 1.0);
+  return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+  let size = in.rect.zw - in.rect.xy;
+  let uv = in.rect.xy + fract(in.local) * size;
+  // 折り返す所で微分が跳ばないように、折り返す前の UV で微分する
+  return textureSampleGrad(tex, samp, uv, dpdx(in.local) * size, dpdy(in.local) * size) * in.color;
+}
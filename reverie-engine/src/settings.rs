@@ -0,0 +1,449 @@
+//! 画面、音量、キー割り当てのようにプレイヤーが変える設定と、その読み書き
+//!
+//! ネイティブでは実行ファイルの隣の [`Settings::FILE_NAME`] に TOML で保存する。
+//! [`crate::EngineConfig::settings`] に渡すと、起動時にウィンドウと表示に反映し、リソースとしてシーンに追加する。
+//! 実行中に変えるときは [`crate::scene::Scene::apply_settings`] (システムからは [`apply_settings`]) を使う。
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    accessibility::{color_matrix, ColorBlindFilter, ColorBlindMode},
+    audio::{Audio, MusicPlayer},
+    input::{ActionMap, Binding},
+    scene::{insert_resource, resource, resource_mut},
+};
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+/// エンジンの設定
+///
+/// ファイルに無い項目は既定値になるので、項目を増やしても古いファイルを読める。
+pub struct Settings {
+    pub window: WindowSettings,
+    pub audio: AudioSettings,
+    pub accessibility: AccessibilitySettings,
+    pub gamepad: GamepadSettings,
+    /// アクションの名前と、それに割り当てる入力
+    ///
+    /// キーは `"KeyW"` や `"Space"` のような [`winit::keyboard::KeyCode`] の名前、
+    /// マウスボタンは `"Left"` のような [`winit::event::MouseButton`] の名前で書く。
+    /// [`Self::apply_bindings`] で [`ActionMap`] に反映する。
+    pub key_bindings: BTreeMap<String, Vec<Binding>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+/// ウィンドウと表示の設定
+///
+/// Web ではキャンバスの大きさをページが決めるので、[`Self::vsync`] 以外は使わない。
+pub struct WindowSettings {
+    /// ウィンドウの幅 (論理ピクセル)
+    pub width: u32,
+    /// ウィンドウの高さ (論理ピクセル)
+    pub height: u32,
+    /// ボーダーレスの全画面にする
+    pub fullscreen: bool,
+    /// 画面の更新に合わせて表示する
+    pub vsync: bool,
+}
+
+impl Default for WindowSettings {
+    fn default() -> Self {
+        Self {
+            width: 1280,
+            height: 720,
+            fullscreen: false,
+            vsync: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+/// 音量の設定。それぞれ 0.0 から 1.0
+pub struct AudioSettings {
+    /// 全体の音量。[`Self::sfx_volume`] と [`Self::music_volume`] に掛ける
+    pub master_volume: f32,
+    /// 効果音 ([`Audio`]) の音量
+    pub sfx_volume: f32,
+    /// 音楽 ([`MusicPlayer`]) の音量
+    pub music_volume: f32,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        Self {
+            master_volume: 1.0,
+            sfx_volume: 1.0,
+            music_volume: 1.0,
+        }
+    }
+}
+
+impl AudioSettings {
+    /// [`Audio::set_sfx_volume`] に渡す音量
+    pub fn effective_sfx_volume(&self) -> f32 {
+        (self.master_volume * self.sfx_volume).max(0.0)
+    }
+
+    /// [`MusicPlayer::set_volume`] に渡す音量
+    pub fn effective_music_volume(&self) -> f32 {
+        (self.master_volume * self.music_volume).max(0.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+/// 遊びやすさのための設定
+///
+/// どれもリソースの値を毎フレーム読むので、オプションの画面から [`apply_settings`] すればすぐに変わる。
+pub struct AccessibilitySettings {
+    /// 画面全体の色を合わせる色覚
+    pub color_blind_mode: ColorBlindMode,
+    /// [`Self::color_blind_mode`] の色覚に対して何をするか
+    pub color_blind_filter: ColorBlindFilter,
+    /// [`crate::scene::ScreenSpaceComponent`] を持つスプライトと文字列を描く倍率
+    ///
+    /// UI はウィンドウの大きさをこれで割った大きさに配置してから拡大する。
+    pub ui_scale: f32,
+}
+
+impl Default for AccessibilitySettings {
+    fn default() -> Self {
+        Self {
+            color_blind_mode: ColorBlindMode::Off,
+            color_blind_filter: ColorBlindFilter::Daltonize,
+            ui_scale: 1.0,
+        }
+    }
+}
+
+impl AccessibilitySettings {
+    /// UI の倍率の範囲
+    pub const UI_SCALE_RANGE: std::ops::RangeInclusive<f32> = 0.5..=4.0;
+
+    /// 実際に使う UI の倍率。[`Self::UI_SCALE_RANGE`] に収める
+    pub fn effective_ui_scale(&self) -> f32 {
+        if self.ui_scale.is_finite() {
+            self.ui_scale
+                .clamp(*Self::UI_SCALE_RANGE.start(), *Self::UI_SCALE_RANGE.end())
+        } else {
+            1.0
+        }
+    }
+
+    /// 画面の色に掛ける行列。変換しないときは `None`
+    pub fn color_matrix(&self) -> Option<nalgebra::Matrix3<f32>> {
+        color_matrix(self.color_blind_mode, self.color_blind_filter)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+/// ゲームパッドの設定
+pub struct GamepadSettings {
+    /// 振動させるか。`false` なら [`crate::input::RumbleSystem`] が振動をすべて止める
+    pub rumble: bool,
+}
+
+impl Default for GamepadSettings {
+    fn default() -> Self {
+        Self { rumble: true }
+    }
+}
+
+impl Settings {
+    /// 設定を保存するファイルの名前
+    pub const FILE_NAME: &'static str = "settings.toml";
+
+    /// コマンドライン引数で設定を上書きする
+    ///
+    /// 試すときに一時的に変えるためのもので、ファイルには保存しない。使えるのは次のもの。
+    ///
+    /// - `--windowed`, `--fullscreen`
+    /// - `--width <幅>`, `--height <高さ>` (`--width=1280` とも書ける)
+    /// - `--vsync`, `--no-vsync`
+    ///
+    /// ゲームが独自の引数を使えるように、知らない引数は無視する。値が正しくなければエラーを返す。
+    pub fn apply_args<I, S>(&mut self, args: I) -> anyhow::Result<()>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let arg = arg.as_ref();
+            let (name, inline) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_owned())),
+                None => (arg, None),
+            };
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| args.next().map(|v| v.as_ref().to_owned()))
+                    .with_context(|| format!("{name} needs a value"))
+            };
+            let size = |value: String| {
+                value
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|&v| v > 0)
+                    .with_context(|| format!("invalid {name}: {value}"))
+            };
+            match name {
+                "--windowed" => self.window.fullscreen = false,
+                "--fullscreen" => self.window.fullscreen = true,
+                "--width" => self.window.width = size(value()?)?,
+                "--height" => self.window.height = size(value()?)?,
+                "--vsync" => self.window.vsync = true,
+                "--no-vsync" => self.window.vsync = false,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// `names` で名前を付けたアクションの割り当てを、[`Self::key_bindings`] に書かれていれば置き換える
+    ///
+    /// 書かれていないアクションはゲームが決めた割り当てのまま残す。
+    pub fn apply_bindings<A: Copy + Eq>(&self, map: &mut ActionMap<A>, names: &[(A, &str)]) {
+        for &(action, name) in names {
+            if let Some(bindings) = self.key_bindings.get(name) {
+                map.unbind(action);
+                for &binding in bindings {
+                    map.bind(action, binding);
+                }
+            }
+        }
+    }
+
+    /// `map` の割り当てを [`Self::key_bindings`] に書き込む。[`Self::apply_bindings`] の逆
+    pub fn store_bindings<A: Copy + Eq>(&mut self, map: &ActionMap<A>, names: &[(A, &str)]) {
+        for &(action, name) in names {
+            self.key_bindings
+                .insert(name.to_owned(), map.bindings(action).collect());
+        }
+    }
+
+    /// TOML として読む
+    pub fn from_toml(toml: &str) -> anyhow::Result<Self> {
+        toml::from_str(toml).context("failed: parse settings")
+    }
+
+    /// TOML にする
+    pub fn to_toml(&self) -> anyhow::Result<String> {
+        toml::to_string_pretty(self).context("failed: serialize settings")
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Settings {
+    /// 実行ファイルと同じディレクトリの [`Self::FILE_NAME`]
+    pub fn default_path() -> anyhow::Result<std::path::PathBuf> {
+        let exe = std::env::current_exe().context("failed: get executable path")?;
+        let dir = exe.parent().context("executable has no parent directory")?;
+        Ok(dir.join(Self::FILE_NAME))
+    }
+
+    /// ファイルから読む
+    pub fn load(path: impl AsRef<std::path::Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let toml = std::fs::read_to_string(path)
+            .with_context(|| format!("failed: read {}", path.display()))?;
+        Self::from_toml(&toml).with_context(|| format!("failed: load {}", path.display()))
+    }
+
+    /// ファイルに書く
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_toml()?)
+            .with_context(|| format!("failed: write {}", path.display()))
+    }
+
+    /// ファイルから読む。無いか壊れていれば既定値にし、ファイルを書き直す
+    pub fn load_or_default(path: impl AsRef<std::path::Path>) -> Self {
+        let path = path.as_ref();
+        match Self::load(path) {
+            Ok(settings) => settings,
+            Err(err) => {
+                tracing::warn!(err = format!("{err:#}"), "use default settings");
+                let settings = Self::default();
+                if let Err(err) = settings.save(path) {
+                    tracing::error!(err = format!("{err:#}"), "failed: rewrite settings");
+                }
+                settings
+            }
+        }
+    }
+
+    /// 実行ファイルの隣の設定を読み、コマンドライン引数で上書きする
+    ///
+    /// 引数が正しくなければ警告を出して無視する。
+    pub fn load_with_args() -> Self {
+        let mut settings = match Self::default_path() {
+            Ok(path) => Self::load_or_default(path),
+            Err(err) => {
+                tracing::warn!(err = format!("{err:#}"), "use default settings");
+                Self::default()
+            }
+        };
+        if let Err(err) = settings.apply_args(std::env::args().skip(1)) {
+            tracing::warn!(err = format!("{err:#}"), "ignore command line settings");
+        }
+        settings
+    }
+}
+
+/// 設定をリソースとして `world` に置き、音量を [`Audio`] と [`MusicPlayer`] に反映する
+///
+/// ウィンドウと表示の設定は、エンジンがフレームの終わりにリソースを見て反映する。
+pub fn apply_settings(world: &mut hecs::World, settings: &Settings) {
+    if let Some(mut audio) = resource_mut::<Audio>(world) {
+        audio.set_sfx_volume(settings.audio.effective_sfx_volume());
+    }
+    if let Some(music) = resource::<MusicPlayer>(world) {
+        music.set_volume(settings.audio.effective_music_volume());
+    }
+    insert_resource(world, settings.clone());
+}
+
+#[cfg(test)]
+mod tests {
+    use winit::{event::MouseButton, keyboard::KeyCode};
+
+    use super::*;
+
+    #[test]
+    fn missing_fields_use_defaults_and_bindings_round_trip() {
+        let settings = Settings::from_toml(
+            r#"
+            [window]
+            fullscreen = true
+
+            [key_bindings]
+            jump = ["Space", "Left"]
+            "#,
+        )
+        .unwrap();
+        assert!(settings.window.fullscreen);
+        assert_eq!(settings.window.width, WindowSettings::default().width);
+        assert_eq!(settings.audio, AudioSettings::default());
+        assert_eq!(
+            settings.key_bindings["jump"],
+            [
+                Binding::Key(KeyCode::Space),
+                Binding::Mouse(MouseButton::Left)
+            ]
+        );
+        assert_eq!(
+            Settings::from_toml(&settings.to_toml().unwrap()).unwrap(),
+            settings
+        );
+    }
+
+    #[test]
+    fn accessibility_reads_from_toml_and_clamps_ui_scale() {
+        let settings = Settings::from_toml(
+            r#"
+            [accessibility]
+            color_blind_mode = "protanopia"
+            ui_scale = 10.0
+            "#,
+        )
+        .unwrap();
+        let accessibility = settings.accessibility;
+        assert_eq!(accessibility.color_blind_mode, ColorBlindMode::Protanopia);
+        assert_eq!(
+            accessibility.color_blind_filter,
+            ColorBlindFilter::Daltonize
+        );
+        assert_eq!(accessibility.effective_ui_scale(), 4.0);
+        assert!(accessibility.color_matrix().is_some());
+        assert!(AccessibilitySettings::default().color_matrix().is_none());
+    }
+
+    #[test]
+    fn args_override_window() {
+        let mut settings = Settings::default();
+        settings.window.fullscreen = true;
+        settings
+            .apply_args([
+                "--windowed",
+                "--width",
+                "640",
+                "--height=480",
+                "--no-vsync",
+                "--level=2",
+            ])
+            .unwrap();
+        assert_eq!(
+            settings.window,
+            WindowSettings {
+                width: 640,
+                height: 480,
+                fullscreen: false,
+                vsync: false,
+            }
+        );
+        assert!(settings.apply_args(["--width", "wide"]).is_err());
+        assert!(settings.apply_args(["--height"]).is_err());
+    }
+
+    #[test]
+    fn bindings_replace_only_named_actions() {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        enum Action {
+            Jump,
+            Fire,
+        }
+        let names = [(Action::Jump, "jump"), (Action::Fire, "fire")];
+        let mut map = ActionMap::new()
+            .with(Action::Jump, KeyCode::Space)
+            .with(Action::Fire, MouseButton::Left);
+        let mut settings = Settings::default();
+        settings
+            .key_bindings
+            .insert("jump".to_owned(), vec![Binding::Key(KeyCode::KeyW)]);
+
+        settings.apply_bindings(&mut map, &names);
+        assert_eq!(
+            map.bindings(Action::Jump).collect::<Vec<_>>(),
+            [Binding::Key(KeyCode::KeyW)]
+        );
+        assert_eq!(
+            map.bindings(Action::Fire).collect::<Vec<_>>(),
+            [Binding::Mouse(MouseButton::Left)]
+        );
+        settings.store_bindings(&map, &names);
+        assert_eq!(
+            settings.key_bindings["fire"],
+            [Binding::Mouse(MouseButton::Left)]
+        );
+    }
+
+    #[test]
+    fn corrupt_file_is_rewritten_with_defaults() {
+        let path =
+            std::env::temp_dir().join(format!("reverie-settings-{}.toml", std::process::id()));
+        std::fs::write(&path, "[window\nwidth = ").unwrap();
+        let settings = Settings::load_or_default(&path);
+        assert_eq!(settings, Settings::default());
+        assert_eq!(Settings::load(&path).unwrap(), Settings::default());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn apply_sets_resource_and_volumes() {
+        let mut world = hecs::World::new();
+        insert_resource(&mut world, MusicPlayer::new(48000));
+        let mut settings = Settings::default();
+        settings.audio.master_volume = 0.5;
+        settings.audio.music_volume = 0.5;
+        apply_settings(&mut world, &settings);
+
+        assert_eq!(resource::<MusicPlayer>(&world).unwrap().volume(), 0.25);
+        assert_eq!(*resource::<Settings>(&world).unwrap(), settings);
+    }
+}
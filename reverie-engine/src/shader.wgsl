@@ -1,10 +1,12 @@
 struct VertexInput {
   @location(0) position: vec3<f32>,
-  @location(1) uv: vec2<f32>
+  @location(1) uv: vec2<f32>,
+  @location(2) color: vec4<f32>
 }
 
 struct VertexOutput {
   @location(0) uv: vec2<f32>,
+  @location(1) color: vec4<f32>,
   @builtin(position) position: vec4<f32>
 }
 
@@ -24,6 +26,7 @@ var<uniform> transform: mat4x4<f32>;
 fn vs_main(in: VertexInput) -> VertexOutput {
   var out: VertexOutput;
   out.uv = in.uv;
+  out.color = in.color;
   out.position = transform * vec4<f32>(in.position, 1.0);
   return out;
 }
@@ -31,5 +34,5 @@ fn vs_main(in: VertexInput) -> VertexOutput {
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
   //return vec4<f32>(0.5, 0.5, 0.5, 1.0);
-  return textureSample(tex, samp, in.uv);
+  return textureSample(tex, samp, in.uv) * in.color;
 }
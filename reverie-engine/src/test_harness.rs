@@ -0,0 +1,158 @@
+//! 描画結果を参照画像と比較するテストのための補助
+//!
+//! feature `test-harness` を有効にすると使える。
+//! ソフトウェアレンダラのアダプタでシーンをテクスチャに描画し、その画素を読み出す。
+use std::{
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use image::RgbaImage;
+
+use crate::{
+    scene::Scene,
+    wgpu_wrapper::{offscreen::OffscreenTarget, GraphicsConfig, WgpuResource},
+};
+
+/// 参照画像を書き出すかどうかを決める環境変数
+///
+/// 値が設定されていれば、比較をせずに参照画像を上書きする。
+pub const UPDATE_ENV: &str = "REVERIE_UPDATE_GOLDEN";
+
+/// ウィンドウを使わずにシーンを描画するための環境
+pub struct TestHarness {
+    pub resource: WgpuResource<'static>,
+    width: NonZeroU32,
+    height: NonZeroU32,
+}
+
+impl TestHarness {
+    /// フォールバックアダプタ (ソフトウェアレンダラ) で初期化する
+    pub fn new(width: NonZeroU32, height: NonZeroU32) -> anyhow::Result<Self> {
+        let config = GraphicsConfig {
+            force_fallback_adapter: true,
+            ..Default::default()
+        };
+        let resource = pollster::block_on(WgpuResource::setup_headless(width, height, &config))
+            .context("failed: setup headless wgpu")?;
+        Ok(Self {
+            resource,
+            width,
+            height,
+        })
+    }
+
+    /// [`Self::new`] と同じだが、アダプタが無ければ理由を表示して `None` を返す
+    ///
+    /// GPU もソフトウェアレンダラも無い環境でテストを飛ばすのに使う。
+    pub fn new_or_skip(width: u32, height: u32) -> Option<Self> {
+        let width = NonZeroU32::new(width)?;
+        let height = NonZeroU32::new(height)?;
+        match Self::new(width, height) {
+            Ok(harness) => Some(harness),
+            Err(e) => {
+                eprintln!("skipped: no fallback adapter is available: {e:#}");
+                None
+            }
+        }
+    }
+
+    /// シーンを初期化して 1 フレーム描画し、その画素を読み出す
+    pub fn render(&mut self, scene: &mut Scene) -> anyhow::Result<RgbaImage> {
+        let r = &mut self.resource;
+        r.texture_registry.send_all_to_gpu(
+            &r.device,
+            &r.queue,
+            &r.texture_bind_group_layout,
+            &r.texture_sampler,
+            WgpuResource::TEXTURE_BINDING,
+            WgpuResource::SAMPLER_BINDING,
+        );
+        scene.setup(r).context("failed: setup scene")?;
+
+        let target = OffscreenTarget::new(r, self.width, self.height);
+        target.render(scene, r);
+        let pixels = target.request_pixels(r).wait(r)?;
+        RgbaImage::from_raw(self.width.get(), self.height.get(), pixels)
+            .context("failed: create image")
+    }
+}
+
+/// 画像を参照画像と比較する
+///
+/// 各画素の各チャンネルの差が `tolerance` 以下なら一致とみなす。
+/// 一致しなければ、参照画像の隣に `*.actual.png` と、違う画素を赤く塗った `*.diff.png` を書き出す。
+/// 環境変数 [`UPDATE_ENV`] が設定されていれば、比較せずに参照画像を書き出す。
+pub fn compare_with_reference(
+    actual: &RgbaImage,
+    reference: impl AsRef<Path>,
+    tolerance: u8,
+) -> anyhow::Result<()> {
+    let reference = reference.as_ref();
+    if std::env::var_os(UPDATE_ENV).is_some() {
+        if let Some(dir) = reference.parent() {
+            std::fs::create_dir_all(dir).context("failed: create reference directory")?;
+        }
+        actual
+            .save(reference)
+            .context("failed: save reference image")?;
+        return Ok(());
+    }
+
+    let expected = image::open(reference)
+        .with_context(|| {
+            format!(
+                "failed: open reference image {}. run with {UPDATE_ENV}=1 to create it",
+                reference.display()
+            )
+        })?
+        .to_rgba8();
+    anyhow::ensure!(
+        expected.dimensions() == actual.dimensions(),
+        "image size differs: expected {:?}, actual {:?}",
+        expected.dimensions(),
+        actual.dimensions()
+    );
+
+    let mut diff = RgbaImage::new(actual.width(), actual.height());
+    let mut mismatched = 0;
+    for ((e, a), d) in expected
+        .pixels()
+        .zip(actual.pixels())
+        .zip(diff.pixels_mut())
+    {
+        let differs = e.0.iter().zip(a.0).any(|(e, a)| e.abs_diff(a) > tolerance);
+        if differs {
+            mismatched += 1;
+            *d = image::Rgba([255, 0, 0, 255]);
+        } else {
+            // 一致した画素は薄く表示する
+            let [r, g, b, _] = a.0;
+            let gray = ((u16::from(r) + u16::from(g) + u16::from(b)) / 3) as u8;
+            *d = image::Rgba([gray / 2, gray / 2, gray / 2, 255]);
+        }
+    }
+    if mismatched == 0 {
+        return Ok(());
+    }
+
+    let actual_path = sibling(reference, "actual.png");
+    let diff_path = sibling(reference, "diff.png");
+    actual
+        .save(&actual_path)
+        .context("failed: save actual image")?;
+    diff.save(&diff_path).context("failed: save diff image")?;
+    anyhow::bail!(
+        "{mismatched} pixels differ from {} (tolerance {tolerance}). see {} and {}",
+        reference.display(),
+        actual_path.display(),
+        diff_path.display()
+    )
+}
+
+/// `foo.png` に対して `foo.<suffix>` のパスを作る
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{stem}.{suffix}"))
+}
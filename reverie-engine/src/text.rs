@@ -0,0 +1,68 @@
+//! 文字の描画
+//!
+//! TTF/OTF のフォントを [`Fonts`] に読み込み、[`crate::scene::TextComponent`] で描画する。
+//! 文字はフォールバックチェーンの先頭のフォントから順に探すので、欧文のフォントの後ろに
+//! 和文のフォントを並べれば日本語と英語を混ぜて書ける。
+//!
+//! 合字や右から左に書く文字のためのシェーピングは行わない。
+use reverie_util::color::Color;
+
+mod atlas;
+mod font;
+mod layout;
+mod reveal;
+mod rich;
+
+pub use atlas::{AtlasGlyph, GlyphAtlas};
+pub use font::{FontId, Fonts};
+pub use layout::{
+    can_break_between, layout, layout_rich, GlyphSource, LineMetrics, PositionedGlyph, TextLayout,
+};
+pub use reveal::{RevealCompleted, TextSystem, Typewriter};
+pub use rich::{InlineIcon, RichText, TextIcon, TextSpan, ICON_PLACEHOLDER};
+
+#[derive(Debug, Clone, PartialEq)]
+/// 文字の見た目
+pub struct TextStyle {
+    /// 文字を探すフォントの順番 (フォールバックチェーン)
+    ///
+    /// 行の高さとベースラインは先頭のフォントで決まる。大きさを変えた範囲がある行は高くなる。
+    pub fonts: Vec<FontId>,
+    /// 文字の大きさ。フォントの ascent から descent までのピクセル数
+    pub size: f32,
+    pub color: Color,
+    /// 行の高さの倍率
+    pub line_spacing: f32,
+}
+
+impl TextStyle {
+    pub fn new(font: FontId, size: f32) -> Self {
+        Self {
+            fonts: vec![font],
+            size,
+            color: Color::WHITE,
+            line_spacing: 1.0,
+        }
+    }
+
+    /// [`FontId::DEBUG`] で描く。フォントを用意せずに文字を出したいときに使う
+    pub fn debug(size: f32) -> Self {
+        Self::new(FontId::DEBUG, size)
+    }
+
+    /// 前のフォントに無い文字を`font`で描く
+    pub fn with_fallback(mut self, font: FontId) -> Self {
+        self.fonts.push(font);
+        self
+    }
+
+    pub const fn with_color(mut self, color: Color) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub const fn with_line_spacing(mut self, line_spacing: f32) -> Self {
+        self.line_spacing = line_spacing;
+        self
+    }
+}
@@ -0,0 +1,346 @@
+//! グリフを並べたテクスチャ
+use std::collections::HashMap;
+
+use ab_glyph::{Font, FontArc, GlyphId, PxScale};
+use etagere::{size2, BucketedAtlasAllocator};
+use nalgebra::Vector2;
+
+use super::font::FontId;
+#[cfg(feature = "backend-wgpu")]
+use crate::wgpu_wrapper::{
+    memory::{GpuMemoryCategory, TrackedAllocation},
+    texture::WgpuTexture,
+    WgpuResource,
+};
+
+/// グリフの周りに空ける隙間 (ピクセル)。隣のグリフがにじまないようにする
+const PADDING: i32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct GlyphKey {
+    font: FontId,
+    glyph: u16,
+    /// 1/4 ピクセル単位の文字の大きさ
+    size: u32,
+}
+
+impl GlyphKey {
+    fn new(font: FontId, glyph: GlyphId, size: f32) -> Self {
+        Self {
+            font,
+            glyph: glyph.0,
+            size: (size * 4.0).round().max(0.0) as u32,
+        }
+    }
+
+    fn size(self) -> f32 {
+        self.size as f32 / 4.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// アトラスに描き込んだグリフ
+pub struct AtlasGlyph {
+    /// アトラスの中の左上の位置 (ピクセル)
+    pub min: [u32; 2],
+    /// ビットマップの幅と高さ (ピクセル)
+    pub size: [u32; 2],
+    /// ベースライン上のペンの位置から見た、ビットマップの左上の位置 (y は下向き)
+    pub offset: Vector2<f32>,
+}
+
+#[cfg(feature = "backend-wgpu")]
+struct AtlasGpu {
+    texture: WgpuTexture,
+    bind_group: wgpu::BindGroup,
+    _memory: TrackedAllocation,
+}
+
+/// 必要になったグリフから順に描き込むテクスチャ
+///
+/// 白で描いて不透明度にグリフの形を入れるので、頂点の色を掛ければ好きな色になる。
+/// 入りきらなくなると縦横を倍にする。広げても既に描き込んだグリフの位置は変わらない。
+pub struct GlyphAtlas {
+    image: image::RgbaImage,
+    allocator: BucketedAtlasAllocator,
+    /// 見た目の無いグリフや入りきらなかったグリフは `None`
+    glyphs: HashMap<GlyphKey, Option<AtlasGlyph>>,
+    max_size: u32,
+    /// GPU に送っていない範囲 (min_x, min_y, max_x, max_y)
+    dirty: Option<[u32; 4]>,
+    /// `None` なら次の [`Self::prepare`] で作り直す
+    #[cfg(feature = "backend-wgpu")]
+    gpu: Option<AtlasGpu>,
+}
+
+impl std::fmt::Debug for GlyphAtlas {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GlyphAtlas")
+            .field("size", &self.size())
+            .field("#glyphs", &self.glyphs.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for GlyphAtlas {
+    fn default() -> Self {
+        // WebGL2 でも使える大きさに収める
+        Self::new(256, 2048)
+    }
+}
+
+impl GlyphAtlas {
+    pub fn new(initial_size: u32, max_size: u32) -> Self {
+        let size = initial_size.min(max_size).max(1);
+        Self {
+            image: image::RgbaImage::new(size, size),
+            allocator: BucketedAtlasAllocator::new(size2(size as i32, size as i32)),
+            glyphs: HashMap::new(),
+            max_size,
+            dirty: None,
+            #[cfg(feature = "backend-wgpu")]
+            gpu: None,
+        }
+    }
+
+    /// アトラスの幅と高さ
+    pub fn size(&self) -> (u32, u32) {
+        self.image.dimensions()
+    }
+
+    pub const fn image(&self) -> &image::RgbaImage {
+        &self.image
+    }
+
+    /// 描き込み済みのグリフを探す
+    pub fn get(&self, font: FontId, glyph: GlyphId, size: f32) -> Option<AtlasGlyph> {
+        self.glyphs
+            .get(&GlyphKey::new(font, glyph, size))
+            .copied()
+            .flatten()
+    }
+
+    /// グリフのテクスチャ座標 (min_u, min_v, max_u, max_v)
+    pub fn uv(&self, glyph: &AtlasGlyph) -> (f32, f32, f32, f32) {
+        let (width, height) = self.size();
+        let (width, height) = (width as f32, height as f32);
+        (
+            glyph.min[0] as f32 / width,
+            glyph.min[1] as f32 / height,
+            (glyph.min[0] + glyph.size[0]) as f32 / width,
+            (glyph.min[1] + glyph.size[1]) as f32 / height,
+        )
+    }
+
+    pub(crate) fn get_or_rasterize(
+        &mut self,
+        font_id: FontId,
+        font: &FontArc,
+        glyph: GlyphId,
+        size: f32,
+    ) -> Option<AtlasGlyph> {
+        let key = GlyphKey::new(font_id, glyph, size);
+        if let Some(&cached) = self.glyphs.get(&key) {
+            return cached;
+        }
+        let rasterized = self.rasterize(font, glyph, key.size());
+        self.glyphs.insert(key, rasterized);
+        rasterized
+    }
+
+    fn rasterize(&mut self, font: &FontArc, glyph: GlyphId, size: f32) -> Option<AtlasGlyph> {
+        let outlined =
+            font.outline_glyph(glyph.with_scale_and_position(PxScale::from(size), (0.0, 0.0)))?;
+        let bounds = outlined.px_bounds();
+        let (width, height) = (bounds.width() as u32, bounds.height() as u32);
+        if width == 0 || height == 0 {
+            return None;
+        }
+
+        let allocation = self.allocate(width, height)?;
+        let x = (allocation.rectangle.min.x + PADDING) as u32;
+        let y = (allocation.rectangle.min.y + PADDING) as u32;
+        outlined.draw(|dx, dy, coverage| {
+            let alpha = (coverage.clamp(0.0, 1.0) * 255.0).round() as u8;
+            self.image
+                .put_pixel(x + dx, y + dy, image::Rgba([255, 255, 255, alpha]));
+        });
+        self.mark_dirty([x, y, x + width, y + height]);
+
+        Some(AtlasGlyph {
+            min: [x, y],
+            size: [width, height],
+            offset: Vector2::new(bounds.min.x, bounds.min.y),
+        })
+    }
+
+    /// 隙間を含めた場所を確保する。足りなければアトラスを広げる
+    fn allocate(&mut self, width: u32, height: u32) -> Option<etagere::Allocation> {
+        let size = size2(width as i32 + PADDING * 2, height as i32 + PADDING * 2);
+        loop {
+            if let Some(allocation) = self.allocator.allocate(size) {
+                return Some(allocation);
+            }
+            let (current_width, current_height) = self.size();
+            if current_width >= self.max_size && current_height >= self.max_size {
+                tracing::warn!(
+                    "glyph atlas is full ({current_width}x{current_height}); glyph is not drawn"
+                );
+                return None;
+            }
+            self.grow(
+                (current_width * 2).min(self.max_size),
+                (current_height * 2).min(self.max_size),
+            );
+        }
+    }
+
+    fn grow(&mut self, width: u32, height: u32) {
+        tracing::debug!("growing glyph atlas to {width}x{height}");
+        self.allocator.grow(size2(width as i32, height as i32));
+        let mut image = image::RgbaImage::new(width, height);
+        image::imageops::replace(&mut image, &self.image, 0, 0);
+        self.image = image;
+        // テクスチャごと作り直すので、部分的な転送は要らない
+        #[cfg(feature = "backend-wgpu")]
+        {
+            self.gpu = None;
+        }
+        self.dirty = None;
+    }
+
+    fn mark_dirty(&mut self, rect: [u32; 4]) {
+        self.dirty = Some(self.dirty.map_or(rect, |dirty| {
+            [
+                dirty[0].min(rect[0]),
+                dirty[1].min(rect[1]),
+                dirty[2].max(rect[2]),
+                dirty[3].max(rect[3]),
+            ]
+        }));
+    }
+
+    /// 新しく描き込んだグリフを GPU に送る
+    ///
+    /// アトラスが広がったときや初めて呼ばれたときはテクスチャを作る。
+    #[cfg(feature = "backend-wgpu")]
+    pub(crate) fn prepare(&mut self, resource: &WgpuResource<'_>) {
+        let Some(gpu) = &self.gpu else {
+            let (width, height) = self.size();
+            let texture = WgpuTexture::from_image(
+                &resource.device,
+                &resource.queue,
+                &self.image,
+                Some("Glyph Atlas"),
+            );
+            let bind_group = texture.create_bind_group(
+                &resource.device,
+                Some("Glyph Atlas Bind Group"),
+                &resource.texture_bind_group_layout,
+                &resource.texture_sampler,
+                WgpuResource::TEXTURE_BINDING,
+                WgpuResource::SAMPLER_BINDING,
+            );
+            let memory = resource.gpu_memory.track(
+                GpuMemoryCategory::Texture,
+                "Glyph Atlas",
+                u64::from(width) * u64::from(height) * 4,
+            );
+            self.gpu = Some(AtlasGpu {
+                texture,
+                bind_group,
+                _memory: memory,
+            });
+            self.dirty = None;
+            return;
+        };
+        if let Some([min_x, min_y, max_x, max_y]) = self.dirty.take() {
+            let width = self.image.width();
+            resource.queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &gpu.texture.texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: min_x,
+                        y: min_y,
+                        z: 0,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                &self.image,
+                wgpu::ImageDataLayout {
+                    offset: u64::from(min_y * width + min_x) * 4,
+                    bytes_per_row: Some(width * 4),
+                    rows_per_image: None,
+                },
+                wgpu::Extent3d {
+                    width: max_x - min_x,
+                    height: max_y - min_y,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+    }
+
+    /// [`Self::prepare`] で作ったテクスチャのバインドグループ
+    #[cfg(feature = "backend-wgpu")]
+    pub(crate) fn bind_group(&self) -> Option<&wgpu::BindGroup> {
+        self.gpu.as_ref().map(|gpu| &gpu.bind_group)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn latin() -> FontArc {
+        FontArc::try_from_slice(include_bytes!("../../tests/fonts/latin.ttf")).unwrap()
+    }
+
+    #[test]
+    fn caches_and_skips_empty_glyphs() {
+        let font = latin();
+        let mut atlas = GlyphAtlas::new(64, 64);
+        let id = FontId(0);
+        let a = atlas
+            .get_or_rasterize(id, &font, font.glyph_id('A'), 20.0)
+            .unwrap();
+        // 四角は x が 50..450、y が 0..700 (1000 unit = 20 px)
+        assert_eq!(a.size, [8, 14]);
+        assert_eq!(a.offset, Vector2::new(1.0, -14.0));
+        assert_eq!(atlas.get(id, font.glyph_id('B'), 20.0), Some(a));
+        assert_eq!(
+            atlas.get_or_rasterize(id, &font, font.glyph_id(' '), 20.0),
+            None
+        );
+        assert_eq!(atlas.glyphs.len(), 2);
+    }
+
+    #[test]
+    fn grows_keeping_existing_glyphs() {
+        let font = latin();
+        let glyph = font.glyph_id('A');
+        let mut atlas = GlyphAtlas::new(16, 64);
+        let first = atlas
+            .get_or_rasterize(FontId(0), &font, glyph, 20.0)
+            .unwrap();
+        let before = atlas.image.get_pixel(first.min[0] + 1, first.min[1] + 1).0;
+        assert_eq!(before[3], 255);
+
+        atlas
+            .get_or_rasterize(FontId(0), &font, glyph, 21.0)
+            .unwrap();
+        assert!(atlas.size().0 > 16);
+        assert_eq!(atlas.get(FontId(0), glyph, 20.0), Some(first));
+        assert_eq!(
+            atlas.image.get_pixel(first.min[0] + 1, first.min[1] + 1).0,
+            before
+        );
+
+        // 最大まで広げても入らなければ描かない
+        assert!(atlas
+            .get_or_rasterize(FontId(0), &font, glyph, 200.0)
+            .is_none());
+        assert_eq!(atlas.size(), (64, 64));
+    }
+}
@@ -0,0 +1,177 @@
+//! フォントの読み込みとフォールバック
+use std::{collections::HashMap, sync::OnceLock};
+
+use ab_glyph::{Font, FontArc, GlyphId};
+use anyhow::Context;
+
+use super::{
+    atlas::{AtlasGlyph, GlyphAtlas},
+    TextIcon,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+/// [`Fonts`] に読み込んだフォントの番号
+pub struct FontId(pub(crate) u32);
+
+impl FontId {
+    /// エンジンに埋め込まれたデバッグ用のフォント
+    ///
+    /// ASCII の印字可能文字だけを持つ 5x7 ドットの等幅フォント。[`Fonts::load`] しなくても使え、
+    /// 最初に使われたときに読み込まれる。
+    pub const DEBUG: Self = Self(u32::MAX);
+}
+
+/// [`FontId::DEBUG`] のデータ。`assets/fonts/make_debug_font.py` で作る
+const DEBUG_FONT: &[u8] = include_bytes!("../../assets/fonts/debug.ttf");
+
+#[derive(Default)]
+/// 読み込んだフォントと、そのグリフを並べたアトラス、文中に置けるアイコン
+///
+/// リソースとして [`crate::scene::Scene::insert_resource`] で追加すると、
+/// [`crate::scene::TextComponent`] が描画される。
+pub struct Fonts {
+    fonts: Vec<FontArc>,
+    /// [`FontId::DEBUG`]
+    debug: OnceLock<FontArc>,
+    atlas: GlyphAtlas,
+    icons: HashMap<String, TextIcon>,
+}
+
+impl std::fmt::Debug for Fonts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Fonts")
+            .field("#fonts", &self.fonts.len())
+            .field("atlas", &self.atlas)
+            .field("icons", &self.icons)
+            .finish()
+    }
+}
+
+impl Fonts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// グリフのアトラスの大きさを指定して作る
+    ///
+    /// アトラスは`initial_size`から始めて、グリフが入りきらなくなると`max_size`まで倍々に広がる。
+    pub fn with_atlas_size(initial_size: u32, max_size: u32) -> Self {
+        Self {
+            atlas: GlyphAtlas::new(initial_size, max_size),
+            ..Default::default()
+        }
+    }
+
+    /// TTF/OTF のデータからフォントを読み込む
+    pub fn load(&mut self, data: Vec<u8>) -> anyhow::Result<FontId> {
+        let font = FontArc::try_from_vec(data).context("failed to parse font")?;
+        let id = FontId(self.fonts.len() as u32);
+        self.fonts.push(font);
+        Ok(id)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    /// TTF/OTF のファイルからフォントを読み込む
+    pub fn load_file(&mut self, path: impl AsRef<std::path::Path>) -> anyhow::Result<FontId> {
+        let path = path.as_ref();
+        let data =
+            std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        self.load(data)
+    }
+
+    pub fn get(&self, id: FontId) -> Option<&FontArc> {
+        lookup(&self.fonts, &self.debug, id)
+    }
+
+    /// [`Self::load`] で読み込んだフォントの数。[`FontId::DEBUG`] は含まない
+    pub fn len(&self) -> usize {
+        self.fonts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fonts.is_empty()
+    }
+
+    /// `c`を持つ最初のフォントとそのグリフを探す
+    ///
+    /// どのフォントにも無ければ、先頭のフォントの .notdef (豆腐) を返す。
+    /// `chain`が空か、読み込んでいないフォントしか無ければ `None`。
+    pub fn resolve(&self, chain: &[FontId], c: char) -> Option<(FontId, GlyphId)> {
+        let mut fonts = chain
+            .iter()
+            .filter_map(|&id| self.get(id).map(|font| (id, font)));
+        let (first, font) = fonts.next()?;
+        let glyph = font.glyph_id(c);
+        if glyph.0 != 0 {
+            return Some((first, glyph));
+        }
+        fonts
+            .map(|(id, font)| (id, font.glyph_id(c)))
+            .find(|(_, glyph)| glyph.0 != 0)
+            .or(Some((first, GlyphId(0))))
+    }
+
+    /// `<icon:name>` で文中に置けるアイコンを登録する。同じ名前があれば置き換える
+    pub fn register_icon(&mut self, name: impl Into<String>, icon: TextIcon) {
+        self.icons.insert(name.into(), icon);
+    }
+
+    pub fn icon(&self, name: &str) -> Option<&TextIcon> {
+        self.icons.get(name)
+    }
+
+    /// 登録したすべてのアイコン
+    pub fn icons(&self) -> impl Iterator<Item = &TextIcon> {
+        self.icons.values()
+    }
+
+    pub const fn atlas(&self) -> &GlyphAtlas {
+        &self.atlas
+    }
+
+    #[cfg(feature = "backend-wgpu")]
+    pub(crate) fn atlas_mut(&mut self) -> &mut GlyphAtlas {
+        &mut self.atlas
+    }
+
+    /// グリフをアトラスに描き込んで、その位置を返す
+    ///
+    /// 描き込み済みならそれを返す。空白のように見た目の無いグリフや、
+    /// アトラスに入りきらなかったグリフは `None`。
+    pub fn glyph(&mut self, font: FontId, glyph: GlyphId, size: f32) -> Option<AtlasGlyph> {
+        let data = lookup(&self.fonts, &self.debug, font)?;
+        self.atlas.get_or_rasterize(font, data, glyph, size)
+    }
+}
+
+/// 読み込んだフォントか、[`FontId::DEBUG`] なら埋め込みのフォントを返す
+fn lookup<'a>(
+    fonts: &'a [FontArc],
+    debug: &'a OnceLock<FontArc>,
+    id: FontId,
+) -> Option<&'a FontArc> {
+    if id == FontId::DEBUG {
+        return Some(debug.get_or_init(|| {
+            FontArc::try_from_slice(DEBUG_FONT).expect("the embedded debug font is valid")
+        }));
+    }
+    fonts.get(id.0 as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_font_is_available_without_loading() {
+        let mut fonts = Fonts::new();
+        assert!(fonts.is_empty());
+        let (font, glyph) = fonts.resolve(&[FontId::DEBUG], 'A').unwrap();
+        assert_eq!(font, FontId::DEBUG);
+        assert_ne!(glyph.0, 0);
+        assert!(fonts.glyph(font, glyph, 14.0).is_some());
+        // ASCII 以外は持たない
+        assert_eq!(fonts.resolve(&[FontId::DEBUG], 'あ').unwrap().1 .0, 0);
+        assert_eq!(fonts.len(), 0);
+    }
+}
@@ -0,0 +1,532 @@
+//! 文字の配置と行の折り返し
+use std::ops::Range;
+
+use ab_glyph::{Font, GlyphId, PxScale, ScaleFont};
+use nalgebra::{Point2, Vector2};
+use reverie_util::color::Color;
+
+use super::{font::FontId, rich::ICON_PLACEHOLDER, Fonts, RichText, TextStyle};
+use crate::texture::TextureId;
+
+/// タブ 1 つが何文字分の空白か
+const TAB_WIDTH: f32 = 4.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// 配置したグリフの中身
+pub enum GlyphSource {
+    /// フォントのグリフ
+    Font { font: FontId, glyph: GlyphId },
+    /// 文中のアイコン。幅は [`PositionedGlyph::advance`]
+    Icon { texture: TextureId, height: f32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// 配置したグリフ
+pub struct PositionedGlyph {
+    pub source: GlyphSource,
+    pub ch: char,
+    /// 元の文字列の中のバイト位置
+    pub byte_index: usize,
+    /// ベースライン上のペンの位置。テキストの左上が原点で、y は下向き
+    pub position: Point2<f32>,
+    /// 次の文字までの幅
+    pub advance: f32,
+    /// 文字の大きさ (ピクセル)。[`super::TextSpan::scale`] を掛けたもの
+    pub size: f32,
+    pub color: Color,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// 1 行の情報
+pub struct LineMetrics {
+    /// [`TextLayout::glyphs`] の中の範囲
+    pub glyphs: Range<usize>,
+    /// 行末の空白を除いた幅
+    pub width: f32,
+    /// ベースラインの y 座標
+    pub baseline: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+/// 文字列を配置した結果
+pub struct TextLayout {
+    glyphs: Vec<PositionedGlyph>,
+    lines: Vec<LineMetrics>,
+    size: Vector2<f32>,
+}
+
+impl TextLayout {
+    /// 改行文字などの制御文字を除いたすべての文字のグリフ
+    pub fn glyphs(&self) -> &[PositionedGlyph] {
+        &self.glyphs
+    }
+
+    pub fn lines(&self) -> &[LineMetrics] {
+        &self.lines
+    }
+
+    /// 全体の幅と高さ
+    pub const fn size(&self) -> Vector2<f32> {
+        self.size
+    }
+}
+
+/// 和文の文字か。和文の文字の前後ではどこでも改行できる
+const fn is_cjk(c: char) -> bool {
+    matches!(
+        c,
+        '\u{2E80}'..='\u{303F}' // 部首、CJK の記号と句読点
+            | '\u{3040}'..='\u{30FF}' // ひらがな、カタカナ
+            | '\u{3400}'..='\u{4DBF}'
+            | '\u{4E00}'..='\u{9FFF}'
+            | '\u{AC00}'..='\u{D7AF}' // ハングル
+            | '\u{F900}'..='\u{FAFF}'
+            | '\u{FF00}'..='\u{FFEF}' // 全角と半角の形
+            | '\u{20000}'..='\u{2FFFF}'
+    )
+}
+
+/// 行頭に置かない文字 (行頭禁則)
+const fn is_no_break_before(c: char) -> bool {
+    matches!(
+        c,
+        '、' | '。'
+            | '，'
+            | '．'
+            | '・'
+            | '：'
+            | '；'
+            | '？'
+            | '！'
+            | 'ー'
+            | '々'
+            | '〜'
+            | '…'
+            | '）'
+            | '」'
+            | '』'
+            | '】'
+            | '〕'
+            | '〉'
+            | '》'
+            | 'ぁ'
+            | 'ぃ'
+            | 'ぅ'
+            | 'ぇ'
+            | 'ぉ'
+            | 'っ'
+            | 'ゃ'
+            | 'ゅ'
+            | 'ょ'
+            | 'ゎ'
+            | 'ァ'
+            | 'ィ'
+            | 'ゥ'
+            | 'ェ'
+            | 'ォ'
+            | 'ッ'
+            | 'ャ'
+            | 'ュ'
+            | 'ョ'
+            | 'ヮ'
+            | 'ヵ'
+            | 'ヶ'
+            | ','
+            | '.'
+            | '!'
+            | '?'
+            | ':'
+            | ';'
+            | ')'
+            | ']'
+            | '}'
+    )
+}
+
+/// 行末に置かない文字 (行末禁則)
+const fn is_no_break_after(c: char) -> bool {
+    matches!(
+        c,
+        '（' | '「' | '『' | '【' | '〔' | '〈' | '《' | '(' | '[' | '{'
+    )
+}
+
+/// `prev`と`next`の間で改行できるか
+///
+/// 空白の後と、和文の文字の前後で改行できる。ただし簡単な禁則処理を行う。
+pub fn can_break_between(prev: char, next: char) -> bool {
+    if is_no_break_before(next) || is_no_break_after(prev) {
+        return false;
+    }
+    prev.is_whitespace() || is_cjk(prev) || is_cjk(next)
+}
+
+/// 配置する前の 1 文字
+struct Item {
+    source: GlyphSource,
+    ch: char,
+    byte_index: usize,
+    advance: f32,
+    size: f32,
+    color: Color,
+    /// 先頭のフォントをこの文字の大きさにしたときの ascent と descent
+    ascent: f32,
+    descent: f32,
+}
+
+/// 文字列を配置する
+///
+/// `max_width`を超える行は、改行できる位置 ([`can_break_between`]) のうち最後のもので折り返す。
+/// 改行できる位置が無いほど長い単語は文字の間で折り返す。
+/// 行末の空白は幅に数えず、はみ出してもよい。
+pub fn layout(fonts: &Fonts, text: &str, style: &TextStyle, max_width: Option<f32>) -> TextLayout {
+    layout_rich(fonts, &RichText::plain(text), style, max_width)
+}
+
+/// 範囲ごとの見た目とアイコンを含む文字列を配置する
+///
+/// 行の高さは、その行で一番大きい文字に合わせて広がる。
+/// 登録されていないアイコンはタグをそのまま文字として配置する。
+pub fn layout_rich(
+    fonts: &Fonts,
+    text: &RichText,
+    style: &TextStyle,
+    max_width: Option<f32>,
+) -> TextLayout {
+    let Some(primary) = style.fonts.iter().find_map(|&id| fonts.get(id)) else {
+        return TextLayout::default();
+    };
+    let primary = primary.as_scaled(PxScale::from(style.size));
+    let line_gap = primary.line_gap();
+
+    let mut result = TextLayout::default();
+    if text.text.is_empty() {
+        return result;
+    }
+    let mut top = 0.0;
+    let mut offset = 0;
+    for paragraph in text.text.split('\n') {
+        let mut items = Vec::new();
+        for (index, ch) in paragraph.char_indices() {
+            let byte_index = offset + index;
+            let (color, scale) = text.style_at(byte_index, style.color);
+            let context = ItemContext {
+                fonts,
+                style,
+                byte_index,
+                color,
+                size: style.size * scale,
+                ascent: primary.ascent() * scale,
+                descent: primary.descent() * scale,
+            };
+            match (ch, text.icon_at(byte_index)) {
+                (ICON_PLACEHOLDER, Some(name)) => match fonts.icon(name) {
+                    Some(icon) => {
+                        let height = context.ascent;
+                        items.push(context.item(
+                            ch,
+                            GlyphSource::Icon {
+                                texture: icon.texture,
+                                height,
+                            },
+                            height * icon.aspect,
+                        ));
+                    }
+                    None => {
+                        tracing::warn!("icon is not registered: {name}");
+                        let literal = format!("<icon:{name}>");
+                        items.extend(literal.chars().filter_map(|ch| context.glyph(ch)));
+                    }
+                },
+                _ => items.extend(context.glyph(ch)),
+            }
+        }
+        offset += paragraph.len() + 1;
+
+        for range in wrap(&items, max_width) {
+            let items = &items[range];
+            // 空の行は先頭のフォントの大きさにする
+            let ascent = items
+                .iter()
+                .map(|item| item.ascent)
+                .fold(primary.ascent(), f32::max);
+            let descent = items
+                .iter()
+                .map(|item| item.descent)
+                .fold(primary.descent(), f32::min);
+            let baseline = top + ascent;
+            let start = result.glyphs.len();
+            let mut x = 0.0;
+            let mut width: f32 = 0.0;
+            let mut prev: Option<&Item> = None;
+            for item in items {
+                if let Some(prev) = prev {
+                    x += kerning(fonts, prev, item);
+                }
+                result.glyphs.push(PositionedGlyph {
+                    source: item.source,
+                    ch: item.ch,
+                    byte_index: item.byte_index,
+                    position: Point2::new(x, baseline),
+                    advance: item.advance,
+                    size: item.size,
+                    color: item.color,
+                });
+                x += item.advance;
+                if !item.ch.is_whitespace() {
+                    width = x;
+                }
+                prev = Some(item);
+            }
+            result.size.x = result.size.x.max(width);
+            result.lines.push(LineMetrics {
+                glyphs: start..result.glyphs.len(),
+                width,
+                baseline,
+            });
+            top += (ascent - descent + line_gap) * style.line_spacing;
+        }
+    }
+    result.size.y = top;
+    result
+}
+
+/// 同じフォントの同じ大きさの文字が並んだときの詰め
+fn kerning(fonts: &Fonts, prev: &Item, item: &Item) -> f32 {
+    match (prev.source, item.source) {
+        (
+            GlyphSource::Font { font, glyph: first },
+            GlyphSource::Font {
+                font: second_font,
+                glyph: second,
+            },
+        ) if font == second_font && prev.size == item.size => fonts.get(font).map_or(0.0, |font| {
+            font.as_scaled(PxScale::from(item.size)).kern(first, second)
+        }),
+        _ => 0.0,
+    }
+}
+
+/// 1 文字を [`Item`] にするための情報
+struct ItemContext<'a> {
+    fonts: &'a Fonts,
+    style: &'a TextStyle,
+    byte_index: usize,
+    color: Color,
+    size: f32,
+    ascent: f32,
+    descent: f32,
+}
+
+impl ItemContext<'_> {
+    const fn item(&self, ch: char, source: GlyphSource, advance: f32) -> Item {
+        Item {
+            source,
+            ch,
+            byte_index: self.byte_index,
+            advance,
+            size: self.size,
+            color: self.color,
+            ascent: self.ascent,
+            descent: self.descent,
+        }
+    }
+
+    /// フォントのグリフにする。制御文字は `None`
+    fn glyph(&self, ch: char) -> Option<Item> {
+        let (resolved, width) = match ch {
+            '\t' => (' ', TAB_WIDTH),
+            _ if ch.is_control() => return None,
+            _ => (ch, 1.0),
+        };
+        let (font, glyph) = self.fonts.resolve(&self.style.fonts, resolved)?;
+        let advance = self
+            .fonts
+            .get(font)?
+            .as_scaled(PxScale::from(self.size))
+            .h_advance(glyph);
+        Some(self.item(ch, GlyphSource::Font { font, glyph }, advance * width))
+    }
+}
+
+/// 行ごとの文字の範囲を決める
+fn wrap(items: &[Item], max_width: Option<f32>) -> Vec<Range<usize>> {
+    let Some(max_width) = max_width else {
+        return std::iter::once(0..items.len()).collect();
+    };
+    let mut lines = Vec::new();
+    let mut start = 0;
+    let mut x = 0.0;
+    let mut last_break = None;
+    for (i, item) in items.iter().enumerate() {
+        if i > start && can_break_between(items[i - 1].ch, item.ch) {
+            last_break = Some(i);
+        }
+        // 空白は行末にぶら下げる
+        if i > start && !item.ch.is_whitespace() && x + item.advance > max_width {
+            let end = last_break.take().unwrap_or(i);
+            lines.push(start..end);
+            start = end;
+            x = items[start..i].iter().map(|item| item.advance).sum();
+        }
+        x += item.advance;
+    }
+    lines.push(start..items.len());
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{text::TextIcon, texture::TextureRegistry};
+
+    const SIZE: f32 = 20.0;
+
+    /// 欧文は幅 10 (空白は 5)、和文は幅 20 の四角になる
+    fn fonts() -> (Fonts, FontId, FontId) {
+        let mut fonts = Fonts::new();
+        let latin = fonts
+            .load(include_bytes!("../../tests/fonts/latin.ttf").to_vec())
+            .unwrap();
+        let cjk = fonts
+            .load(include_bytes!("../../tests/fonts/cjk.ttf").to_vec())
+            .unwrap();
+        (fonts, latin, cjk)
+    }
+
+    fn font_of(glyph: &PositionedGlyph) -> FontId {
+        match glyph.source {
+            GlyphSource::Font { font, .. } => font,
+            GlyphSource::Icon { .. } => panic!("not a font glyph"),
+        }
+    }
+
+    fn lines(layout: &TextLayout) -> Vec<String> {
+        layout
+            .lines()
+            .iter()
+            .map(|line| {
+                layout.glyphs()[line.glyphs.clone()]
+                    .iter()
+                    .map(|g| g.ch)
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn measures_and_falls_back_per_character() {
+        let (fonts, latin, cjk) = fonts();
+        let style = TextStyle::new(latin, SIZE).with_fallback(cjk);
+        let layout = layout(&fonts, "A あ\nB", &style, None);
+
+        assert_eq!(layout.size(), Vector2::new(35.0, 40.0));
+        let glyphs = layout.glyphs();
+        assert_eq!(
+            glyphs.iter().map(font_of).collect::<Vec<_>>(),
+            [latin, latin, cjk, latin]
+        );
+        // UTF-8 で 3 バイトの文字も 1 文字として数える
+        assert_eq!(
+            glyphs.iter().map(|g| g.byte_index).collect::<Vec<_>>(),
+            [0, 1, 2, 6]
+        );
+        assert_eq!(glyphs[2].position, Point2::new(15.0, 16.0));
+        assert_eq!(glyphs[3].position, Point2::new(0.0, 36.0));
+    }
+
+    #[test]
+    fn missing_glyph_uses_notdef_of_first_font() {
+        let (fonts, latin, _) = fonts();
+        let layout = layout(&fonts, "あ", &TextStyle::new(latin, SIZE), None);
+        assert_eq!(
+            layout.glyphs()[0].source,
+            GlyphSource::Font {
+                font: latin,
+                glyph: GlyphId(0)
+            }
+        );
+        assert_eq!(layout.size().x, 10.0);
+    }
+
+    #[test]
+    fn latin_wraps_at_spaces_and_splits_long_words() {
+        let (fonts, latin, _) = fonts();
+        let style = TextStyle::new(latin, SIZE);
+
+        let wrapped = layout(&fonts, "AB CD", &style, Some(30.0));
+        assert_eq!(lines(&wrapped), ["AB ", "CD"]);
+        assert_eq!(wrapped.lines()[0].width, 20.0);
+
+        let long = layout(&fonts, "ABCDEFG", &style, Some(30.0));
+        assert_eq!(lines(&long), ["ABC", "DEF", "G"]);
+    }
+
+    #[test]
+    fn cjk_wraps_between_any_characters() {
+        let (fonts, latin, cjk) = fonts();
+        let style = TextStyle::new(latin, SIZE).with_fallback(cjk);
+
+        let wrapped = layout(&fonts, "あいうえお", &style, Some(50.0));
+        assert_eq!(lines(&wrapped), ["あい", "うえ", "お"]);
+
+        // 句点は行頭に置かない
+        let kinsoku = layout(&fonts, "あい。う", &style, Some(40.0));
+        assert_eq!(lines(&kinsoku), ["あ", "い。", "う"]);
+
+        // 欧文の単語は和文との境目でだけ折り返す
+        let mixed = layout(&fonts, "あAB", &style, Some(30.0));
+        assert_eq!(lines(&mixed), ["あ", "AB"]);
+    }
+
+    #[test]
+    fn icons_and_scaled_spans_take_space() {
+        let (mut fonts, latin, _) = fonts();
+        let mut registry = TextureRegistry::default();
+        let texture = registry.new_texture(image::RgbaImage::new(2, 1), None);
+        fonts.register_icon("wide", TextIcon::new(texture.into(), 2, 1));
+        let style = TextStyle::new(latin, SIZE);
+
+        // アイコンの高さは ascent (16)、幅はその 2 倍
+        let rich = RichText::parse("A<icon:wide>B");
+        let laid_out = layout_rich(&fonts, &rich, &style, None);
+        assert_eq!(laid_out.size(), Vector2::new(52.0, 20.0));
+        assert_eq!(
+            laid_out.glyphs()[1].source,
+            GlyphSource::Icon {
+                texture: texture.into(),
+                height: 16.0
+            }
+        );
+        assert_eq!(laid_out.glyphs()[2].position.x, 42.0);
+        // アイコンの幅も折り返しに数える
+        let wrapped = layout_rich(
+            &fonts,
+            &RichText::parse("A <icon:wide>"),
+            &style,
+            Some(30.0),
+        );
+        assert_eq!(wrapped.lines().len(), 2);
+
+        // 大きくした文字がある行だけ高くなる
+        let rich = RichText::parse("<scale=2><color=#00ff00>A</color></scale>B\nC");
+        let laid_out = layout_rich(&fonts, &rich, &style, None);
+        let glyphs = laid_out.glyphs();
+        assert_eq!(glyphs[0].size, 40.0);
+        assert_eq!(glyphs[0].color, Color::rgb(0.0, 1.0, 0.0));
+        assert_eq!(glyphs[1].color, Color::WHITE);
+        assert_eq!(laid_out.lines()[0].baseline, 32.0);
+        assert_eq!(laid_out.lines()[1].baseline, 56.0);
+        assert_eq!(laid_out.size(), Vector2::new(30.0, 60.0));
+    }
+
+    #[test]
+    fn unknown_icon_is_literal() {
+        let (fonts, latin, _) = fonts();
+        let rich = RichText::parse("<icon:missing>");
+        let laid_out = layout_rich(&fonts, &rich, &TextStyle::new(latin, SIZE), None);
+        let text: String = laid_out.glyphs().iter().map(|g| g.ch).collect();
+        assert_eq!(text, "<icon:missing>");
+        assert!(laid_out.glyphs().iter().all(|g| g.byte_index == 0));
+    }
+}
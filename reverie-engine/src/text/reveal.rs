@@ -0,0 +1,187 @@
+//! 文字を少しずつ表示するタイプライター効果
+use std::time::Duration;
+
+use crate::scene::{
+    clear_events, for_each_ordered, send_event, EntityIndex, Frame, RenderResource, System,
+    TextComponent,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+/// [`TextComponent`] の文字を先頭から 1 文字ずつ表示する
+///
+/// UTF-8 で複数バイトの文字やアイコンも 1 文字として数える。
+/// 配置は文字列全体で行うので、文字が増えても行の折り返しは変わらない。
+pub struct Typewriter {
+    chars_per_second: f32,
+    /// 表示した文字数。小数部分は次の文字までの進み具合
+    progress: f32,
+    /// 文字列全体の文字数
+    total: usize,
+    completed: bool,
+}
+
+impl Typewriter {
+    pub(crate) fn new(chars_per_second: f32, text: &str) -> Self {
+        Self {
+            chars_per_second,
+            progress: 0.0,
+            total: text.chars().count(),
+            completed: false,
+        }
+    }
+
+    pub const fn chars_per_second(&self) -> f32 {
+        self.chars_per_second
+    }
+
+    /// 表示している文字数
+    pub fn revealed(&self) -> usize {
+        (self.progress.max(0.0) as usize).min(self.total)
+    }
+
+    /// すべての文字を表示したか
+    pub fn is_finished(&self) -> bool {
+        self.revealed() >= self.total
+    }
+
+    /// `text`のうち表示している部分のバイト数
+    #[cfg_attr(not(feature = "backend-wgpu"), allow(dead_code))]
+    pub(crate) fn revealed_len(&self, text: &str) -> usize {
+        text.char_indices()
+            .nth(self.revealed())
+            .map_or(text.len(), |(index, _)| index)
+    }
+
+    /// 最後まで表示する
+    pub(crate) fn finish(&mut self) {
+        self.progress = self.total as f32;
+    }
+
+    /// 時間を進める。このときに初めて最後まで表示したなら `true`
+    pub(crate) fn advance(&mut self, delta_time: Duration) -> bool {
+        if !self.is_finished() {
+            self.progress += self.chars_per_second * delta_time.as_secs_f32();
+        }
+        if self.is_finished() && !self.completed {
+            self.completed = true;
+            return true;
+        }
+        false
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// [`Typewriter`] がすべての文字を表示した
+///
+/// [`crate::scene::Events`] として [`TextSystem`] から送られる。
+pub struct RevealCompleted(pub EntityIndex);
+
+#[derive(Debug, Default)]
+/// [`TextComponent`] のタイプライター効果を進める
+pub struct TextSystem;
+
+impl TextSystem {
+    pub const fn new() -> Self {
+        Self
+    }
+
+    /// タイプライター効果を`delta_time`だけ進める
+    ///
+    /// [`System::update`] から呼ばれる。GPU に触れないのでテストからも直接呼べる。
+    pub fn apply(&mut self, world: &mut hecs::World, delta_time: Duration) {
+        clear_events::<RevealCompleted>(world);
+        let mut completed = Vec::new();
+        for_each_ordered::<&mut TextComponent>(world, |entity, text| {
+            if text.advance_reveal(delta_time) {
+                completed.push(RevealCompleted(EntityIndex(entity)));
+            }
+        });
+        for event in completed {
+            send_event(world, event);
+        }
+    }
+}
+
+impl System for TextSystem {
+    fn setup(&mut self, _resource: Option<&RenderResource<'_>>) {}
+
+    fn update(
+        &mut self,
+        frame: &Frame<'_>,
+        world: &mut hecs::World,
+        _resource: Option<&RenderResource<'_>>,
+    ) {
+        self.apply(world, frame.delta_time);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        scene::{resource, Events},
+        text::{FontId, TextStyle},
+    };
+
+    const FRAME: Duration = Duration::from_millis(100);
+
+    #[test]
+    fn reveals_whole_characters_and_sends_event_once() {
+        let mut world = hecs::World::new();
+        let style = TextStyle::new(FontId(0), 20.0);
+        let entity = world.spawn((TextComponent::new("あいう", style).with_typewriter(10.0),));
+        let mut system = TextSystem::new();
+        let completed = |world: &hecs::World| {
+            resource::<Events<RevealCompleted>>(world).map_or(0, |events| events.len())
+        };
+
+        system.apply(&mut world, FRAME.mul_f32(1.5));
+        {
+            let text = world.get::<&TextComponent>(entity).unwrap();
+            let typewriter = text.typewriter().unwrap();
+            assert_eq!(typewriter.revealed(), 1);
+            // 3 バイトの文字の途中で切らない
+            assert_eq!(typewriter.revealed_len(text.text()), 3);
+        }
+        system.apply(&mut world, FRAME.mul_f32(1.5));
+        assert_eq!(completed(&world), 1);
+        system.apply(&mut world, FRAME);
+        assert_eq!(completed(&world), 0);
+
+        // 文字列を変えると最初からやり直す。途中で全部表示することもできる
+        world
+            .get::<&mut TextComponent>(entity)
+            .unwrap()
+            .set_text("えお");
+        system.apply(&mut world, FRAME);
+        world
+            .get::<&mut TextComponent>(entity)
+            .unwrap()
+            .complete_reveal();
+        system.apply(&mut world, Duration::ZERO);
+        assert_eq!(completed(&world), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "backend-wgpu")]
+    fn hides_unrevealed_glyphs_without_reflowing() {
+        use crate::{scene::TransformComponent, text::Fonts, texture::TextureRegistry};
+
+        let mut fonts = Fonts::new();
+        let latin = fonts
+            .load(include_bytes!("../../tests/fonts/latin.ttf").to_vec())
+            .unwrap();
+        let registry = TextureRegistry::default();
+        let transform = TransformComponent::default();
+        let mut text = TextComponent::new("AB CD", TextStyle::new(latin, 20.0))
+            .with_max_width(30.0)
+            .with_typewriter(1.0);
+
+        text.prepare(&mut fonts);
+        assert!(text.quads(&fonts, &registry, &transform).is_empty());
+        let size = text.layout().unwrap().size();
+        text.advance_reveal(Duration::from_secs(4));
+        assert_eq!(text.quads(&fonts, &registry, &transform).len(), 3);
+        assert_eq!(text.layout().unwrap().size(), size);
+    }
+}
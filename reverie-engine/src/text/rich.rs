@@ -0,0 +1,290 @@
+//! 範囲ごとの色や大きさ、文中のアイコン
+use std::ops::Range;
+
+use reverie_util::color::Color;
+
+use crate::texture::TextureId;
+
+/// 文中のアイコンの位置に置く文字 (U+FFFC OBJECT REPLACEMENT CHARACTER)
+pub const ICON_PLACEHOLDER: char = '\u{FFFC}';
+
+#[derive(Debug, Clone, PartialEq)]
+/// 文字列の一部の見た目を変える
+///
+/// 範囲が重なるときは後に追加したものが優先される。
+pub struct TextSpan {
+    /// 文字列の中のバイト位置の範囲
+    pub range: Range<usize>,
+    /// [`super::TextStyle::color`] の代わりに使う色
+    pub color: Option<Color>,
+    /// [`super::TextStyle::size`] に掛ける倍率
+    pub scale: Option<f32>,
+}
+
+impl TextSpan {
+    pub const fn new(range: Range<usize>) -> Self {
+        Self {
+            range,
+            color: None,
+            scale: None,
+        }
+    }
+
+    pub const fn with_color(mut self, color: Color) -> Self {
+        self.color = Some(color);
+        self
+    }
+
+    pub const fn with_scale(mut self, scale: f32) -> Self {
+        self.scale = Some(scale);
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// 文中のアイコン
+pub struct InlineIcon {
+    /// 文字列の中の [`ICON_PLACEHOLDER`] のバイト位置
+    pub byte_index: usize,
+    /// [`super::Fonts::register_icon`] で登録した名前
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// 文中に置ける画像
+///
+/// 高さはその位置の文字の ascent に合わせ、ベースラインの上に置く。
+pub struct TextIcon {
+    pub texture: TextureId,
+    /// 幅を高さで割ったもの
+    pub aspect: f32,
+}
+
+impl TextIcon {
+    /// `width`x`height`の画像を、縦横比を保って文中に置く
+    pub fn new(texture: TextureId, width: u32, height: u32) -> Self {
+        Self {
+            texture,
+            aspect: width as f32 / height.max(1) as f32,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+/// 範囲ごとの見た目とアイコンを含む文字列
+pub struct RichText {
+    /// タグを取り除いた文字列。アイコンの位置には [`ICON_PLACEHOLDER`] が入る
+    pub text: String,
+    pub spans: Vec<TextSpan>,
+    pub icons: Vec<InlineIcon>,
+}
+
+impl RichText {
+    /// 見た目を変えない文字列
+    pub fn plain(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            ..Default::default()
+        }
+    }
+
+    /// 簡単なマークアップを読む
+    ///
+    /// - `<color=#rrggbb>` または `<color=#rrggbbaa>` から `</color>` までの色を変える
+    /// - `<scale=1.5>` から `</scale>` までの大きさを変える
+    /// - `<icon:name>` の位置に [`super::Fonts::register_icon`] で登録したアイコンを置く
+    ///
+    /// 読めないタグはそのまま文字として表示し、警告を出す。閉じていないタグは文字列の最後まで続く。
+    pub fn parse(markup: &str) -> Self {
+        let mut result = Self::default();
+        // 開いているタグと、それに対応する `spans` の中の位置
+        let mut open: Vec<(SpanKind, usize)> = Vec::new();
+        let mut rest = markup;
+        while let Some(start) = rest.find('<') {
+            result.text.push_str(&rest[..start]);
+            let tag = &rest[start + 1..];
+            let Some(end) = tag.find('>') else {
+                tracing::warn!("unterminated tag in text: {:?}", &rest[start..]);
+                result.text.push_str(&rest[start..]);
+                rest = "";
+                break;
+            };
+            let tag = &tag[..end];
+            if result.apply_tag(tag, &mut open) {
+                rest = &rest[start + end + 2..];
+            } else {
+                tracing::warn!("invalid tag in text: <{tag}>");
+                result.text.push('<');
+                rest = &rest[start + 1..];
+            }
+        }
+        result.text.push_str(rest);
+
+        if !open.is_empty() {
+            tracing::warn!("{} unclosed tag(s) in text", open.len());
+        }
+        for (_, index) in open {
+            result.spans[index].range.end = result.text.len();
+        }
+        result
+    }
+
+    /// タグを読んで反映する。読めなければ `false`
+    fn apply_tag(&mut self, tag: &str, open: &mut Vec<(SpanKind, usize)>) -> bool {
+        let position = self.text.len();
+        if let Some(name) = tag.strip_prefix("icon:") {
+            if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == '<') {
+                return false;
+            }
+            self.icons.push(InlineIcon {
+                byte_index: position,
+                name: name.to_owned(),
+            });
+            self.text.push(ICON_PLACEHOLDER);
+            return true;
+        }
+        if let Some(kind) = tag.strip_prefix('/').and_then(SpanKind::from_name) {
+            let Some(i) = open.iter().rposition(|(open_kind, _)| *open_kind == kind) else {
+                return false;
+            };
+            let (_, index) = open.remove(i);
+            self.spans[index].range.end = position;
+            return true;
+        }
+        let Some((name, value)) = tag.split_once('=') else {
+            return false;
+        };
+        let span = TextSpan::new(position..position);
+        let (kind, span) = match SpanKind::from_name(name) {
+            Some(SpanKind::Color) => match parse_color(value) {
+                Some(color) => (SpanKind::Color, span.with_color(color)),
+                None => return false,
+            },
+            Some(SpanKind::Scale) => match value.parse::<f32>() {
+                Ok(scale) if scale.is_finite() && scale > 0.0 => {
+                    (SpanKind::Scale, span.with_scale(scale))
+                }
+                _ => return false,
+            },
+            None => return false,
+        };
+        open.push((kind, self.spans.len()));
+        self.spans.push(span);
+        true
+    }
+
+    /// `byte_index`の文字の色と大きさの倍率
+    pub(crate) fn style_at(&self, byte_index: usize, color: Color) -> (Color, f32) {
+        self.spans
+            .iter()
+            .filter(|span| span.range.contains(&byte_index))
+            .fold((color, 1.0), |(color, scale), span| {
+                (span.color.unwrap_or(color), span.scale.unwrap_or(scale))
+            })
+    }
+
+    /// `byte_index`に置いたアイコンの名前
+    pub(crate) fn icon_at(&self, byte_index: usize) -> Option<&str> {
+        self.icons
+            .iter()
+            .find(|icon| icon.byte_index == byte_index)
+            .map(|icon| icon.name.as_str())
+    }
+}
+
+impl From<&str> for RichText {
+    fn from(text: &str) -> Self {
+        Self::plain(text)
+    }
+}
+
+impl From<String> for RichText {
+    fn from(text: String) -> Self {
+        Self::plain(text)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SpanKind {
+    Color,
+    Scale,
+}
+
+impl SpanKind {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "color" => Some(Self::Color),
+            "scale" => Some(Self::Scale),
+            _ => None,
+        }
+    }
+}
+
+/// `#rrggbb` か `#rrggbbaa` を読む
+fn parse_color(value: &str) -> Option<Color> {
+    let hex = value.strip_prefix('#')?;
+    if !matches!(hex.len(), 6 | 8) || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok();
+    let alpha = if hex.len() == 8 { channel(3)? } else { 255 };
+    Some(Color::from_rgba8(
+        channel(0)?,
+        channel(1)?,
+        channel(2)?,
+        alpha,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_spans_and_icons() {
+        let rich = RichText::parse(
+            "press <icon:button_a> to <color=#ff0000>jump <scale=2>now</scale></color>",
+        );
+        assert_eq!(rich.text, "press \u{FFFC} to jump now");
+        assert_eq!(
+            rich.icons,
+            [InlineIcon {
+                byte_index: 6,
+                name: "button_a".to_owned(),
+            }]
+        );
+        let jump = rich.text.find("jump").unwrap();
+        let now = rich.text.find("now").unwrap();
+        assert_eq!(
+            rich.spans,
+            [
+                TextSpan::new(jump..rich.text.len()).with_color(Color::from_rgba8(255, 0, 0, 255)),
+                TextSpan::new(now..rich.text.len()).with_scale(2.0),
+            ]
+        );
+        let red = Color::from_rgba8(255, 0, 0, 255);
+        assert_eq!(rich.style_at(0, Color::WHITE), (Color::WHITE, 1.0));
+        assert_eq!(rich.style_at(jump, Color::WHITE), (red, 1.0));
+        assert_eq!(rich.style_at(now, Color::WHITE), (red, 2.0));
+    }
+
+    #[test]
+    fn invalid_tags_are_literal() {
+        for markup in [
+            "a <b> c",
+            "<color=red>x",
+            "<scale=-1>x",
+            "</color>x",
+            "<icon:>x",
+            "1 < 2",
+        ] {
+            let rich = RichText::parse(markup);
+            assert_eq!(rich.text, markup);
+            assert!(rich.spans.is_empty() && rich.icons.is_empty(), "{markup}");
+        }
+        // 閉じていないタグは最後まで続く
+        let rich = RichText::parse("<scale=1.5>big");
+        assert_eq!(rich.text, "big");
+        assert_eq!(rich.spans, [TextSpan::new(0..3).with_scale(1.5)]);
+    }
+}
@@ -1,77 +1,297 @@
 //! テクスチャに関するモジュール
+mod loader;
+
+#[cfg(feature = "backend-wgpu")]
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, OnceLock, PoisonError},
+};
+
 use anyhow::Context;
 use etagere::{size2, AtlasAllocator};
 use image::{GenericImage, RgbaImage};
+use nalgebra::Point2;
 use slotmap::SlotMap;
+use web_time::Instant;
+
+pub use loader::{LoadPriority, TextureLoadStats, TextureSource};
+
+#[cfg(feature = "backend-wgpu")]
+use crate::wgpu_wrapper::{
+    memory::{GpuMemoryCategory, GpuMemoryTracker, TrackedAllocation},
+    texture::WgpuTexture,
+    WgpuResource,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// テクスチャを拡大・縮小するときの補間
+pub enum TextureFilter {
+    /// 最も近い画素を使う。ドット絵向け
+    #[default]
+    Nearest,
+    /// 周りの画素を混ぜる
+    Linear,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// すべてのテクスチャに共通する品質の設定
+///
+/// 起動時は [`crate::wgpu_wrapper::GraphicsConfig::texture`] で指定する。
+/// 実行中は [`TextureRegistry::request_settings`] で変え、次のフレームの始めに反映される。
+pub struct TextureSettings {
+    /// 拡大・縮小するときの補間
+    pub filter: TextureFilter,
+    /// 異方性フィルタリングの倍率。1 から 16 まで
+    ///
+    /// `filter` が [`TextureFilter::Linear`] のときだけ効く。
+    pub anisotropy: u16,
+    /// テクスチャの幅と高さの上限
+    ///
+    /// これより大きい画像は、縦横比を保って縮小してから GPU に送る。VRAM の少ない環境向け。
+    pub max_size: Option<u32>,
+    /// ミップマップの段を選ぶときの詳細度の下限。0 なら制限しない
+    ///
+    /// 正の値にすると、拡大して描くものも縮小した段から選ぶのでぼやける。1.0 で縦横半分の段になる。
+    /// wgpu のサンプラーには詳細度をずらす項目 (バイアス) が無いので、ずらす代わりに下限を決める。
+    /// ミップマップを持つのは [`TextureRegistry::new_texture`] などで作った 1 枚の画像のテクスチャだけで、
+    /// アトラスとキャンバスには効かない。
+    pub min_mip_level: f32,
+}
 
-use crate::wgpu_wrapper::texture::WgpuTexture;
+impl Default for TextureSettings {
+    fn default() -> Self {
+        Self {
+            filter: TextureFilter::Nearest,
+            anisotropy: 1,
+            max_size: None,
+            min_mip_level: 0.0,
+        }
+    }
+}
+
+impl TextureSettings {
+    /// `width`x`height` の画像を GPU に送るときの大きさ
+    pub fn clamped_size(&self, width: u32, height: u32) -> (u32, u32) {
+        let Some(max) = self.max_size else {
+            return (width, height);
+        };
+        let max = max.max(1);
+        let longest = width.max(height);
+        if longest <= max {
+            return (width, height);
+        }
+        let scale = |size: u32| ((u64::from(size) * u64::from(max)) / u64::from(longest)).max(1);
+        (scale(width) as u32, scale(height) as u32)
+    }
+}
 
 #[derive(Debug)]
 /// テクスチャ
 struct Texture {
-    data: TextureData,
+    /// 元の画像。設定が変わったときに GPU に送り直すので、送った後も持っておく
+    image: Box<RgbaImage>,
     usage: TextureUsage,
+    #[cfg_attr(not(feature = "backend-wgpu"), allow(dead_code))]
     label: Option<String>,
+    /// [`TextureRegistry::load_texture`] で読み込み中なら、その優先度
+    ///
+    /// デコードが終わるまで `image` は透明な 1x1 の画像になっている。
+    load: Option<LoadPriority>,
+    /// GPU 上のテクスチャ。まだ送っていなければ `None`
+    #[cfg(feature = "backend-wgpu")]
+    gpu: Option<GpuTexture>,
+    /// 最後に使われた [`TextureRegistry`] のフレーム
+    #[cfg(feature = "backend-wgpu")]
+    last_used: u64,
+    /// [`TextureRegistry::pin`] されていて、使われなくても解放しない
+    #[cfg(feature = "backend-wgpu")]
+    pinned: bool,
+    /// 使われないので GPU から解放した。また使われたら送り直す
+    #[cfg(feature = "backend-wgpu")]
+    evicted: bool,
+}
+
+#[cfg(feature = "backend-wgpu")]
+#[derive(Debug)]
+struct GpuTexture {
+    texture: WgpuTexture,
+    bind_group: wgpu::BindGroup,
+    /// GPU 上の大きさの登録
+    _memory: TrackedAllocation,
+}
+
+/// テクスチャを GPU に送るのに必要なもの
+#[cfg(feature = "backend-wgpu")]
+struct Uploader<'a> {
+    device: &'a wgpu::Device,
+    queue: &'a wgpu::Queue,
+    bind_group_layout: &'a wgpu::BindGroupLayout,
+    sampler: &'a wgpu::Sampler,
+    texture_binding: u32,
+    sampler_binding: u32,
+    settings: &'a TextureSettings,
+    memory: &'a GpuMemoryTracker,
 }
 
 impl Texture {
-    pub fn width(&self) -> u32 {
-        match &self.data {
-            TextureData::Cpu(image) => image.width(),
-            TextureData::Gpu(texture, _) => texture.width(),
+    fn new(image: RgbaImage, usage: TextureUsage, label: Option<String>) -> Self {
+        Self {
+            image: Box::new(image),
+            usage,
+            label,
+            load: None,
+            #[cfg(feature = "backend-wgpu")]
+            gpu: None,
+            #[cfg(feature = "backend-wgpu")]
+            last_used: 0,
+            #[cfg(feature = "backend-wgpu")]
+            pinned: false,
+            #[cfg(feature = "backend-wgpu")]
+            evicted: false,
         }
     }
 
+    /// 元の画像の幅。GPU 上では [`TextureSettings::max_size`] で縮小されていることがある
+    pub fn width(&self) -> u32 {
+        self.image.width()
+    }
+
     pub fn height(&self) -> u32 {
-        match &self.data {
-            TextureData::Cpu(image) => image.height(),
-            TextureData::Gpu(texture, _) => texture.height(),
+        self.image.height()
+    }
+
+    /// グラフィックスデバッガなどに表示する名前
+    ///
+    /// 名前が無くても単一のテクスチャとアトラスは区別できるようにする。
+    #[cfg(feature = "backend-wgpu")]
+    pub fn debug_label(&self) -> &str {
+        self.label.as_deref().unwrap_or(match self.usage {
+            TextureUsage::Single => "Unnamed Texture",
+            TextureUsage::Atlas(_) => "Unnamed Atlas Texture",
+            TextureUsage::Canvas => "Unnamed Canvas Texture",
+        })
+    }
+
+    /// 今の設定で GPU に送るときの大きさ
+    ///
+    /// キャンバスは GPU 上で描き込むので縮小しない。
+    #[cfg(feature = "backend-wgpu")]
+    fn upload_size(&self, settings: &TextureSettings) -> (u32, u32) {
+        match self.usage {
+            TextureUsage::Canvas => self.image.dimensions(),
+            _ => settings.clamped_size(self.width(), self.height()),
         }
     }
 
-    pub fn send_to_gpu(
-        &mut self,
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
-        bind_group_layout: &wgpu::BindGroupLayout,
-        sampler: &wgpu::Sampler,
-        texture_binding: u32,
-        sampler_binding: u32,
-    ) {
-        match &self.data {
-            TextureData::Cpu(image) => {
-                let texture = WgpuTexture::from_image(device, queue, image, self.label.as_deref());
-                let label = self.label.as_deref().map(|s| format!("{s} bind_group"));
-                let bind_group = texture.create_bind_group(
-                    device,
-                    label.as_deref(),
-                    bind_group_layout,
-                    sampler,
-                    texture_binding,
-                    sampler_binding,
-                );
-                self.data = TextureData::Gpu(texture, bind_group);
+    /// GPU 上の大きさ。まだ送っていなければ `None`
+    #[cfg(feature = "backend-wgpu")]
+    fn gpu_size(&self) -> Option<(u32, u32)> {
+        self.gpu
+            .as_ref()
+            .map(|gpu| (gpu.texture.width(), gpu.texture.height()))
+    }
+
+    /// まだ GPU に無ければ送る
+    #[cfg(feature = "backend-wgpu")]
+    fn send_to_gpu(&mut self, up: &Uploader<'_>) {
+        if self.gpu.is_none() {
+            self.upload(up);
+        }
+    }
+
+    /// 今の設定で GPU に送る。既に送っていれば置き換える
+    #[cfg(feature = "backend-wgpu")]
+    fn upload(&mut self, up: &Uploader<'_>) {
+        let (width, height) = self.upload_size(up.settings);
+        let label = self.debug_label();
+        let texture = if matches!(self.usage, TextureUsage::Canvas) {
+            WgpuTexture::from_image_with_usage(
+                up.device,
+                up.queue,
+                &self.image,
+                Some(label),
+                wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            )
+        } else if (width, height) == self.image.dimensions() {
+            self.new_gpu_texture(up, &self.image, label)
+        } else {
+            tracing::debug!(
+                label,
+                from = ?self.image.dimensions(),
+                to = ?(width, height),
+                "downsample texture"
+            );
+            let resized = image::imageops::resize(
+                &*self.image,
+                width,
+                height,
+                image::imageops::FilterType::Triangle,
+            );
+            self.new_gpu_texture(up, &resized, label)
+        };
+        let bind_group = up.bind_group(&texture, label);
+        let memory = up
+            .memory
+            .track(GpuMemoryCategory::Texture, label, texture.byte_size());
+        self.gpu = Some(GpuTexture {
+            texture,
+            bind_group,
+            _memory: memory,
+        });
+        self.evicted = false;
+    }
+
+    /// 1 枚の画像ならミップマップも作る。アトラスは縮小すると隣の画像と混ざるので作らない
+    #[cfg(feature = "backend-wgpu")]
+    fn new_gpu_texture(&self, up: &Uploader<'_>, image: &RgbaImage, label: &str) -> WgpuTexture {
+        match self.usage {
+            TextureUsage::Single => {
+                WgpuTexture::from_image_with_mips(up.device, up.queue, image, Some(label))
             }
-            TextureData::Gpu(_, _) => {}
+            _ => WgpuTexture::from_image(up.device, up.queue, image, Some(label)),
+        }
+    }
+
+    /// サンプラーが変わったので、バインドグループを作り直す
+    #[cfg(feature = "backend-wgpu")]
+    fn rebind(&mut self, up: &Uploader<'_>) {
+        let label = self.debug_label().to_owned();
+        if let Some(gpu) = &mut self.gpu {
+            gpu.bind_group = up.bind_group(&gpu.texture, &label);
         }
     }
+
+    #[cfg(feature = "backend-wgpu")]
+    fn bind_group(&self) -> Option<&wgpu::BindGroup> {
+        self.gpu.as_ref().map(|gpu| &gpu.bind_group)
+    }
 }
 
-#[derive(Debug)]
-/// テクスチャのデータ
-///
-/// テクスチャがCPU上にある場合は[`TextureData::Cpu`]、GPU上にある場合は[`TextureData::Gpu`]となる。
-enum TextureData {
-    Cpu(Box<RgbaImage>),
-    Gpu(WgpuTexture, wgpu::BindGroup),
+#[cfg(feature = "backend-wgpu")]
+impl Uploader<'_> {
+    fn bind_group(&self, texture: &WgpuTexture, label: &str) -> wgpu::BindGroup {
+        texture.create_bind_group(
+            self.device,
+            Some(&format!("{label} bind_group")),
+            self.bind_group_layout,
+            self.sampler,
+            self.texture_binding,
+            self.sampler_binding,
+        )
+    }
 }
 
 /// テクスチャの使用方法
 ///
 /// 1つのテクスチャを使いまわす場合は[`TextureUsage::Single`]、複数のテクスチャをアトラステクスチャとして使う場合は[`TextureUsage::Atlas`]となる。
+/// GPU 上で描き込むテクスチャは[`TextureUsage::Canvas`]となる。
 enum TextureUsage {
     Single,
     Atlas(AtlasAllocator),
+    /// [`TextureRegistry::new_canvas`] で作ったテクスチャ
+    ///
+    /// GPU 上の内容が正しいので、CPU 上の画像から送り直さない。
+    Canvas,
 }
 
 impl std::fmt::Debug for TextureUsage {
@@ -79,6 +299,7 @@ impl std::fmt::Debug for TextureUsage {
         match self {
             Self::Single => write!(f, "Single"),
             Self::Atlas(_) => write!(f, "Atlas(AtlasAllocator{{*}})"),
+            Self::Canvas => write!(f, "Canvas"),
         }
     }
 }
@@ -87,9 +308,55 @@ impl std::fmt::Debug for TextureUsage {
 /// [`TextureRegistry`]に登録されたテクスチャを指す識別子
 ///
 /// 単一のテクスチャを指す場合は[`TextureId::Single`]、アトラステクスチャのアロケーションを指す場合は[`TextureId::Atlas`]となる。
+/// エンジンが用意するテクスチャは[`TextureId::Builtin`]で、登録しなくても使える。
 pub enum TextureId {
     Single(TextureIndex),
     Atlas(Allocation),
+    Builtin(BuiltinTexture),
+}
+
+impl TextureId {
+    /// 1x1 の白いテクスチャ
+    ///
+    /// [`crate::scene::SpriteComponent::with_tint`] と組み合わせると単色の四角形を描ける。
+    pub const WHITE: Self = Self::Builtin(BuiltinTexture::White);
+
+    /// 取り除かれたテクスチャの代わりに描くテクスチャ
+    pub const MISSING: Self = Self::Builtin(BuiltinTexture::Missing);
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+/// エンジンが用意するテクスチャ
+///
+/// [`TextureRegistry`] が最初に使われたときに作る。
+pub enum BuiltinTexture {
+    /// 1x1 の白
+    White,
+    /// マゼンタと黒の市松模様。取り除かれたテクスチャの代わりに描く
+    Missing,
+}
+
+#[cfg(feature = "backend-wgpu")]
+impl BuiltinTexture {
+    const fn label(self) -> &'static str {
+        match self {
+            Self::White => "Builtin White Texture",
+            Self::Missing => "Builtin Missing Texture",
+        }
+    }
+
+    fn image(self) -> RgbaImage {
+        match self {
+            Self::White => RgbaImage::from_pixel(1, 1, image::Rgba([255, 255, 255, 255])),
+            Self::Missing => RgbaImage::from_fn(2, 2, |x, y| {
+                if (x + y) % 2 == 0 {
+                    image::Rgba([255, 0, 255, 255])
+                } else {
+                    image::Rgba([0, 0, 0, 255])
+                }
+            }),
+        }
+    }
 }
 
 impl From<TextureIndex> for TextureId {
@@ -106,8 +373,25 @@ impl From<Allocation> for TextureId {
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 /// [`TextureRegistry`]に登録された単一のテクスチャを指すインデックス
+///
+/// スロットの番号と世代の組なので、[`TextureRegistry::unload`] で取り除いた後に同じスロットが
+/// 別のテクスチャに使われても、古いインデックスは [`StaleHandle`] として拒まれる。
 pub struct TextureIndex(slotmap::DefaultKey);
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// 取り除かれたテクスチャを指すインデックスが使われた
+///
+/// [`TextureRegistry`] のメソッドは [`anyhow::Error`] に包んで返すので、`downcast_ref` で見分けられる。
+pub struct StaleHandle(pub TextureIndex);
+
+impl std::fmt::Display for StaleHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "texture {:?} was unloaded", self.0)
+    }
+}
+
+impl std::error::Error for StaleHandle {}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 /// [`TextureRegistry`]に登録されたアトラステクスチャ内のアロケーションを指す識別子
 pub struct Allocation(TextureIndex, etagere::AllocId);
@@ -116,31 +400,281 @@ pub struct Allocation(TextureIndex, etagere::AllocId);
 /// テクスチャを管理するレジストリ
 pub struct TextureRegistry {
     arena: SlotMap<slotmap::DefaultKey, Texture>,
+    /// [`Self::load_texture`] で頼まれた画像をデコードするスレッド
+    loader: loader::Loader,
+    load_stats: TextureLoadStats,
+    /// デコードが終わり、GPU に送るのを待っているテクスチャ
+    #[cfg(feature = "backend-wgpu")]
+    decoded: VecDeque<slotmap::DefaultKey>,
+    /// [`BuiltinTexture::White`]。最初に使われたときに GPU に作る
+    #[cfg(feature = "backend-wgpu")]
+    white: OnceLock<Texture>,
+    /// [`BuiltinTexture::Missing`]
+    #[cfg(feature = "backend-wgpu")]
+    missing: OnceLock<Texture>,
+    #[cfg(feature = "backend-wgpu")]
+    memory: GpuMemoryTracker,
+    #[cfg(feature = "backend-wgpu")]
+    settings: TextureSettings,
+    /// [`Self::request_settings`] で頼まれた設定
+    ///
+    /// 描画するスプライトを並列に選ぶときにレジストリを共有するので、`Cell` ではなく `Mutex` にしている
+    #[cfg(feature = "backend-wgpu")]
+    requested_settings: Mutex<Option<TextureSettings>>,
+    /// 設定が変わって GPU に送り直すテクスチャ
+    #[cfg(feature = "backend-wgpu")]
+    reloads: VecDeque<slotmap::DefaultKey>,
+    /// [`Self::collect_unused`] を呼んだ回数
+    #[cfg(feature = "backend-wgpu")]
+    frame: u64,
 }
 
 impl TextureRegistry {
-    pub fn new_texture(&mut self, image: RgbaImage, label: Option<String>) -> TextureIndex {
-        let texture = Texture {
-            data: TextureData::Cpu(Box::new(image)),
-            usage: TextureUsage::Single,
-            label,
-        };
+    /// GPU に送ったテクスチャの大きさを`memory`に登録するレジストリを作る
+    #[cfg(feature = "backend-wgpu")]
+    pub fn with_memory_tracker(memory: GpuMemoryTracker) -> Self {
+        Self {
+            memory,
+            ..Default::default()
+        }
+    }
+
+    /// テクスチャを GPU に送る前に品質の設定を決める
+    #[cfg(feature = "backend-wgpu")]
+    pub const fn with_settings(mut self, settings: TextureSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// `index` のテクスチャ。取り除かれていれば [`StaleHandle`]
+    fn texture(&self, index: TextureIndex) -> Result<&Texture, StaleHandle> {
+        self.arena.get(index.0).ok_or(StaleHandle(index))
+    }
+
+    #[cfg_attr(not(feature = "backend-wgpu"), allow(unused_mut))]
+    fn insert(&mut self, mut texture: Texture) -> TextureIndex {
+        // 作ったばかりのテクスチャはすぐには解放しない
+        #[cfg(feature = "backend-wgpu")]
+        {
+            texture.last_used = self.frame;
+        }
         TextureIndex(self.arena.insert(texture))
     }
 
+    /// `index` のテクスチャがまだ登録されているか
+    pub fn contains(&self, index: TextureIndex) -> bool {
+        self.arena.contains_key(index.0)
+    }
+
+    /// テクスチャを取り除き、GPU 上のテクスチャも解放する
+    ///
+    /// アトラスを取り除くと、その中の [`Allocation`] もすべて使えなくなる。
+    /// 取り除いた後に `index` やそれを含む [`TextureId`] を使うと [`StaleHandle`] になる。
+    /// 描画では [`TextureId::MISSING`] の代わりのテクスチャを使う。
+    pub fn unload(&mut self, index: TextureIndex) -> Result<(), StaleHandle> {
+        let texture = self.arena.remove(index.0).ok_or(StaleHandle(index))?;
+        if texture.load.is_some() {
+            self.loader.cancel(index.0);
+        }
+        #[cfg(feature = "backend-wgpu")]
+        {
+            self.reloads.retain(|key| *key != index.0);
+            self.decoded.retain(|key| *key != index.0);
+        }
+        Ok(())
+    }
+
+    /// `id` が取り除かれたテクスチャを指していれば [`TextureId::MISSING`] に置き換える
+    pub fn resolve(&self, id: TextureId) -> TextureId {
+        match id {
+            TextureId::Single(index) | TextureId::Atlas(Allocation(index, _))
+                if !self.contains(index) =>
+            {
+                TextureId::MISSING
+            }
+            _ => id,
+        }
+    }
+
+    pub fn new_texture(&mut self, image: RgbaImage, label: Option<String>) -> TextureIndex {
+        let texture = Texture::new(image, TextureUsage::Single, label);
+        self.insert(texture)
+    }
+
+    /// PNG などのエンコードされた画像データからテクスチャを作る
+    ///
+    /// ファイルシステムに触れないので、`include_bytes!` で埋め込んだデータや
+    /// Web で `fetch` したデータをそのまま渡せる。
+    ///
+    /// 呼んだスレッドでデコードする。大きな画像はフレームを止めるので、ゲームの途中では [`Self::load_texture`] を使う。
+    pub fn new_texture_from_bytes(
+        &mut self,
+        bytes: &[u8],
+        label: Option<String>,
+    ) -> anyhow::Result<TextureIndex> {
+        let image = self.decode_now(&TextureSource::Bytes(bytes.to_vec()))?;
+        Ok(self.new_texture(image, label))
+    }
+
+    /// 画像ファイルを読み込んでテクスチャを作る
+    ///
+    /// テクスチャにはグラフィックスデバッガのための名前としてファイルのパスが付く。
+    /// 呼んだスレッドでデコードする。
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn new_texture_from_path(
+        &mut self,
+        path: impl AsRef<std::path::Path>,
+    ) -> anyhow::Result<TextureIndex> {
+        let path = path.as_ref();
+        let image = self.decode_now(&TextureSource::Path(path.to_owned()))?;
+        Ok(self.new_texture(image, Some(path.display().to_string())))
+    }
+
+    /// メインスレッドでデコードし、かかった時間を [`TextureLoadStats::main_thread_decode`] に足す
+    fn decode_now(&mut self, source: &TextureSource) -> anyhow::Result<RgbaImage> {
+        let start = Instant::now();
+        let image = source.decode();
+        self.load_stats.main_thread_decode += start.elapsed();
+        image
+    }
+
+    /// デコードをバックグラウンドのスレッドに任せてテクスチャを作る
+    ///
+    /// すぐにインデックスを返し、デコードが終わって GPU に送るまでは透明な 1x1 の画像として描く。
+    /// メインスレッドで行うのは、[`WgpuResource::maintain_textures`] が GPU に送ることだけ。
+    /// デコードに失敗したテクスチャは取り除くので、[`TextureId::MISSING`] で描かれる。
+    pub fn load_texture(
+        &mut self,
+        source: TextureSource,
+        label: Option<String>,
+        priority: LoadPriority,
+    ) -> TextureIndex {
+        let mut texture = Texture::new(RgbaImage::new(1, 1), TextureUsage::Single, label);
+        texture.load = Some(priority);
+        let index = self.insert(texture);
+        self.loader.request(
+            loader::Job {
+                key: index.0,
+                source,
+            },
+            priority,
+        );
+        index
+    }
+
+    /// [`Self::load_texture`] でエンコードされた画像データを読み込む
+    pub fn load_texture_from_bytes(
+        &mut self,
+        bytes: impl Into<Vec<u8>>,
+        label: Option<String>,
+        priority: LoadPriority,
+    ) -> TextureIndex {
+        self.load_texture(TextureSource::Bytes(bytes.into()), label, priority)
+    }
+
+    /// [`Self::load_texture`] で画像ファイルを読み込む。ファイルを読むのもバックグラウンドで行う
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load_texture_from_path(
+        &mut self,
+        path: impl Into<std::path::PathBuf>,
+        priority: LoadPriority,
+    ) -> TextureIndex {
+        let path = path.into();
+        let label = path.display().to_string();
+        self.load_texture(TextureSource::Path(path), Some(label), priority)
+    }
+
+    /// 読み込み中のテクスチャの優先度を変える。次の部屋のために先読みしていたものが見えたときなどに使う
+    ///
+    /// 読み込みが終わっていれば何もしない。
+    pub fn set_load_priority(
+        &mut self,
+        index: TextureIndex,
+        priority: LoadPriority,
+    ) -> Result<(), StaleHandle> {
+        let texture = self.arena.get_mut(index.0).ok_or(StaleHandle(index))?;
+        if let Some(load) = &mut texture.load {
+            *load = priority;
+            self.loader.set_priority(index.0, priority);
+        }
+        Ok(())
+    }
+
+    /// [`Self::load_texture`] で読み込み中で、まだ描けないか
+    pub fn is_loading(&self, index: TextureIndex) -> bool {
+        self.arena
+            .get(index.0)
+            .is_some_and(|texture| texture.load.is_some())
+    }
+
+    /// このフレームの読み込みの統計
+    pub const fn load_stats(&self) -> &TextureLoadStats {
+        &self.load_stats
+    }
+
+    /// バックグラウンドでデコードが終わった画像を受け取る。受け取った数を返す
+    ///
+    /// 新しいフレームとして [`Self::load_stats`] を 0 に戻してから受け取る。
+    /// wgpu では [`WgpuResource::maintain_textures`] が毎フレーム呼び、受け取った画像を GPU に送る。
+    /// GPU を使わないときは、受け取った時点で読み込みが終わる。
+    pub fn poll_loads(&mut self) -> usize {
+        self.load_stats = TextureLoadStats::default();
+        let (decoded, main_thread_decode) = self.loader.receive();
+        self.load_stats.main_thread_decode = main_thread_decode;
+        let received = decoded.len();
+        for loader::Decoded { key, image } in decoded {
+            match image {
+                Ok(image) => {
+                    let Some(texture) = self.arena.get_mut(key) else {
+                        continue;
+                    };
+                    *texture.image = image;
+                    self.load_stats.decoded += 1;
+                    #[cfg(feature = "backend-wgpu")]
+                    self.decoded.push_back(key);
+                    #[cfg(not(feature = "backend-wgpu"))]
+                    {
+                        texture.load = None;
+                    }
+                }
+                Err(err) => {
+                    tracing::warn!(?err, "failed: load texture");
+                    if self.arena.remove(key).is_some() {
+                        self.load_stats.failed += 1;
+                    }
+                }
+            }
+        }
+        received
+    }
+
+    /// GPU 上で描き込む透明なテクスチャを作る
+    ///
+    /// [`crate::scene::DecalLayerComponent`] などが描画先に使う。描き込んだ内容は GPU にしか無いので、
+    /// [`Self::pin`] して使われなくても解放せず、[`TextureSettings::max_size`] でも縮小しない。
+    /// GPU に無ければ [`WgpuResource::maintain_textures`] が送る。
+    pub fn new_canvas(&mut self, width: u32, height: u32, label: Option<String>) -> TextureIndex {
+        #[cfg_attr(not(feature = "backend-wgpu"), allow(unused_mut))]
+        let mut texture = Texture::new(RgbaImage::new(width, height), TextureUsage::Canvas, label);
+        #[cfg(feature = "backend-wgpu")]
+        {
+            texture.pinned = true;
+        }
+        self.insert(texture)
+    }
+
     pub fn create_altas_texture(
         &mut self,
         width: u32,
         height: u32,
         label: Option<String>,
     ) -> TextureIndex {
-        let image = Box::new(RgbaImage::new(width, height));
-        let texture = Texture {
-            data: TextureData::Cpu(image),
-            usage: TextureUsage::Atlas(AtlasAllocator::new(size2(width as i32, height as i32))),
+        let texture = Texture::new(
+            RgbaImage::new(width, height),
+            TextureUsage::Atlas(AtlasAllocator::new(size2(width as i32, height as i32))),
             label,
-        };
-        TextureIndex(self.arena.insert(texture))
+        );
+        self.insert(texture)
     }
 
     pub fn allocate_sub_image(
@@ -148,12 +682,11 @@ impl TextureRegistry {
         index: TextureIndex,
         sub_image: RgbaImage,
     ) -> anyhow::Result<Allocation> {
-        let texture = self
-            .arena
-            .get_mut(index.0)
-            .with_context(|| format!("no such texture: {:?}", index))?;
+        let texture = self.arena.get_mut(index.0).ok_or(StaleHandle(index))?;
+        #[cfg(feature = "backend-wgpu")]
+        anyhow::ensure!(texture.gpu.is_none(), "texture is already sent to GPU");
         if let Texture {
-            data: TextureData::Cpu(image),
+            image,
             usage: TextureUsage::Atlas(allocator),
             ..
         } = texture
@@ -167,18 +700,19 @@ impl TextureRegistry {
                 .context("failed to copy sub_image")?;
             Ok(Allocation(index, allocation.id))
         } else {
-            anyhow::bail!("invalid texture is not for atlas or texture is not on CPU")
+            anyhow::bail!("texture is not for atlas")
         }
     }
 
     pub fn get_uv(&self, id: TextureId) -> anyhow::Result<(f32, f32, f32, f32)> {
         match id {
-            TextureId::Single(_) => Ok((0.0, 0.0, 1.0, 1.0)),
+            TextureId::Builtin(_) => Ok((0.0, 0.0, 1.0, 1.0)),
+            TextureId::Single(index) => {
+                self.texture(index)?;
+                Ok((0.0, 0.0, 1.0, 1.0))
+            }
             TextureId::Atlas(allocation) => {
-                let texture = self
-                    .arena
-                    .get(allocation.0 .0)
-                    .with_context(|| format!("no such texture: {:?}", allocation.0))?;
+                let texture = self.texture(allocation.0)?;
                 if let Texture {
                     usage: TextureUsage::Atlas(allocator),
                     ..
@@ -200,7 +734,48 @@ impl TextureRegistry {
         }
     }
 
+    /// `id` の範囲の元の画像での大きさ (ピクセル)
+    ///
+    /// [`TextureId::Builtin`] は 1x1 とみなす。
+    pub fn region_size(&self, id: TextureId) -> anyhow::Result<(u32, u32)> {
+        let index = match id {
+            TextureId::Builtin(_) => return Ok((1, 1)),
+            TextureId::Single(index) | TextureId::Atlas(Allocation(index, _)) => index,
+        };
+        let (min_u, min_v, max_u, max_v) = self.get_uv(id)?;
+        let texture = self.texture(index)?;
+        let size = |min: f32, max: f32, size: u32| ((max - min) * size as f32).round() as u32;
+        Ok((
+            size(min_u, max_u, texture.width()),
+            size(min_v, max_v, texture.height()),
+        ))
+    }
+
+    /// テクスチャ座標 `uv` の画素の不透明度
+    ///
+    /// `uv` は `id` の範囲の中の位置で、アトラスならその割り当ての中で 0.0 から 1.0 とする。
+    /// GPU に送った後も CPU 上の画像を使う。[`TextureId::Builtin`] は不透明とする。
+    pub fn alpha_at(&self, id: TextureId, uv: Point2<f32>) -> anyhow::Result<u8> {
+        let index = match id {
+            TextureId::Builtin(_) => return Ok(u8::MAX),
+            TextureId::Single(index) | TextureId::Atlas(Allocation(index, _)) => index,
+        };
+        let (min_u, min_v, max_u, max_v) = self.get_uv(id)?;
+        let image = &self.texture(index)?.image;
+        let texel = |t: f32, min: f32, max: f32, size: u32| {
+            ((max - min).mul_add(t.clamp(0.0, 1.0), min) * size as f32)
+                .floor()
+                .clamp(0.0, (size - 1) as f32) as u32
+        };
+        let x = texel(uv.x, min_u, max_u, image.width());
+        let y = texel(uv.y, min_v, max_v, image.height());
+        Ok(image.get_pixel(x, y).0[3])
+    }
+
     /// CPU 上のテクスチャを GPU に送信する
+    ///
+    /// [`TextureSettings::max_size`] より大きい画像は縮小して送る。
+    #[cfg(feature = "backend-wgpu")]
     pub fn send_all_to_gpu(
         &mut self,
         device: &wgpu::Device,
@@ -210,50 +785,481 @@ impl TextureRegistry {
         texture_binding: u32,
         sampler_binding: u32,
     ) {
+        let up = Uploader {
+            device,
+            queue,
+            bind_group_layout,
+            sampler,
+            texture_binding,
+            sampler_binding,
+            settings: &self.settings,
+            memory: &self.memory,
+        };
         for (_, texture) in self.arena.iter_mut() {
-            texture.send_to_gpu(
-                device,
-                queue,
-                bind_group_layout,
-                sampler,
-                texture_binding,
-                sampler_binding,
-            );
+            texture.send_to_gpu(&up);
         }
     }
 
+    #[cfg(feature = "backend-wgpu")]
     pub fn get_bind_group(&self, id: TextureId) -> anyhow::Result<&wgpu::BindGroup> {
-        match id {
-            TextureId::Single(index) => {
-                let texture = self
-                    .arena
-                    .get(index.0)
-                    .with_context(|| format!("no such texture: {:?}", index))?;
-                if let Texture {
-                    data: TextureData::Gpu(_, bind_group),
-                    ..
-                } = texture
-                {
-                    Ok(bind_group)
-                } else {
-                    anyhow::bail!("texture is not on GPU")
+        let index = match id {
+            TextureId::Single(index) | TextureId::Atlas(Allocation(index, _)) => index,
+            TextureId::Builtin(builtin) => {
+                return self
+                    .builtin(builtin)
+                    .and_then(Texture::bind_group)
+                    .with_context(|| format!("builtin texture {builtin:?} is not used yet"));
+            }
+        };
+        self.texture(index)?
+            .bind_group()
+            .context("texture is not on GPU")
+    }
+
+    /// GPU 上のテクスチャの大きさ
+    ///
+    /// [`TextureSettings::max_size`] で縮小していれば元の画像より小さい。まだ送っていなければ `None`。
+    #[cfg(feature = "backend-wgpu")]
+    pub fn gpu_size(&self, index: TextureIndex) -> Option<(u32, u32)> {
+        self.arena.get(index.0).and_then(Texture::gpu_size)
+    }
+
+    /// [`Self::new_canvas`] で作ったキャンバスの GPU 上のテクスチャ
+    #[cfg(feature = "backend-wgpu")]
+    pub(crate) fn canvas(&self, index: TextureIndex) -> anyhow::Result<&WgpuTexture> {
+        let texture = self.texture(index)?;
+        anyhow::ensure!(
+            matches!(texture.usage, TextureUsage::Canvas),
+            "texture is not a canvas"
+        );
+        texture
+            .gpu
+            .as_ref()
+            .map(|gpu| &gpu.texture)
+            .context("canvas is not on GPU")
+    }
+
+    /// キャンバスの内容を `image` で置き換える。GPU にあれば GPU 上の内容も書き換える
+    #[cfg(feature = "backend-wgpu")]
+    pub(crate) fn replace_canvas_image(
+        &mut self,
+        index: TextureIndex,
+        image: RgbaImage,
+        queue: &wgpu::Queue,
+    ) -> anyhow::Result<()> {
+        let texture = self.arena.get_mut(index.0).ok_or(StaleHandle(index))?;
+        anyhow::ensure!(
+            matches!(texture.usage, TextureUsage::Canvas),
+            "texture is not a canvas"
+        );
+        anyhow::ensure!(
+            image.dimensions() == texture.image.dimensions(),
+            "canvas size mismatch: expected {:?}, got {:?}",
+            texture.image.dimensions(),
+            image.dimensions()
+        );
+        if let Some(gpu) = &texture.gpu {
+            gpu.texture.write_image(queue, &image);
+        }
+        *texture.image = image;
+        Ok(())
+    }
+
+    /// 今の品質の設定
+    #[cfg(feature = "backend-wgpu")]
+    pub const fn settings(&self) -> &TextureSettings {
+        &self.settings
+    }
+
+    /// 品質の設定を変える
+    ///
+    /// 次のフレームの始めに [`WgpuResource::maintain_textures`] がサンプラーを作り直す。
+    /// [`TextureSettings::max_size`] が変わったときは、影響するテクスチャを数フレームに分けて GPU に送り直す。
+    /// 送り直すまでは前の大きさのテクスチャで描画する。
+    #[cfg(feature = "backend-wgpu")]
+    pub fn request_settings(&self, settings: TextureSettings) {
+        *self
+            .requested_settings
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(settings);
+    }
+
+    #[cfg(feature = "backend-wgpu")]
+    pub(crate) fn take_requested_settings(&self) -> Option<TextureSettings> {
+        self.requested_settings
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+    }
+
+    /// GPU に送り直すのを待っているテクスチャの数
+    #[cfg(feature = "backend-wgpu")]
+    pub fn pending_reloads(&self) -> usize {
+        self.reloads.len()
+    }
+
+    /// 設定を変え、新しい `sampler` でバインドグループを作り直す
+    ///
+    /// GPU 上の大きさが新しい [`TextureSettings::max_size`] に合わないテクスチャは、送り直す列に並べる。
+    #[cfg(feature = "backend-wgpu")]
+    pub(crate) fn apply_settings(
+        &mut self,
+        settings: TextureSettings,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+    ) {
+        self.settings = settings;
+        let up = Uploader {
+            device,
+            queue,
+            bind_group_layout,
+            sampler,
+            texture_binding: WgpuResource::TEXTURE_BINDING,
+            sampler_binding: WgpuResource::SAMPLER_BINDING,
+            settings: &self.settings,
+            memory: &self.memory,
+        };
+        for (key, texture) in self.arena.iter_mut() {
+            texture.rebind(&up);
+            let expected = texture.upload_size(&settings);
+            if texture.gpu_size().is_some_and(|size| size != expected)
+                && !self.reloads.contains(&key)
+            {
+                self.reloads.push_back(key);
+            }
+        }
+        for builtin in [self.white.get_mut(), self.missing.get_mut()]
+            .into_iter()
+            .flatten()
+        {
+            builtin.rebind(&up);
+        }
+    }
+
+    /// [`LoadPriority::Prefetch`] で読み込んだテクスチャを 1 フレームに GPU に送る数
+    pub const PREFETCH_UPLOADS_PER_FRAME: usize = 1;
+
+    /// [`Self::poll_loads`] で受け取ったテクスチャを GPU に送る
+    ///
+    /// [`LoadPriority::Immediate`] はすべて、[`LoadPriority::Prefetch`] は [`Self::PREFETCH_UPLOADS_PER_FRAME`] 個まで送る。
+    /// 読み込み中でまだ GPU に無いテクスチャには、代わりの透明な画像を送る。
+    /// [`Self::new_canvas`] で作ってまだ GPU に無いキャンバスも送る。送った数を返す。
+    #[cfg(feature = "backend-wgpu")]
+    pub(crate) fn upload_loaded(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+    ) -> usize {
+        let up = Uploader {
+            device,
+            queue,
+            bind_group_layout,
+            sampler,
+            texture_binding: WgpuResource::TEXTURE_BINDING,
+            sampler_binding: WgpuResource::SAMPLER_BINDING,
+            settings: &self.settings,
+            memory: &self.memory,
+        };
+        let arena = &mut self.arena;
+        let mut prefetch_budget = Self::PREFETCH_UPLOADS_PER_FRAME;
+        let mut uploaded = 0;
+        self.decoded.retain(|key| {
+            let Some(texture) = arena.get_mut(*key) else {
+                return false;
+            };
+            if texture.load == Some(LoadPriority::Prefetch) {
+                if prefetch_budget == 0 {
+                    return true;
                 }
+                prefetch_budget -= 1;
             }
-            TextureId::Atlas(allocation) => {
-                let texture = self
-                    .arena
-                    .get(allocation.0 .0)
-                    .with_context(|| format!("no such texture: {:?}", allocation.0))?;
-                if let Texture {
-                    data: TextureData::Gpu(_, bind_group),
-                    ..
-                } = texture
-                {
-                    Ok(bind_group)
-                } else {
-                    anyhow::bail!("texture is not on GPU")
+            texture.load = None;
+            // GPU から解放したものは、また使われたときに送る
+            if !texture.evicted {
+                texture.upload(&up);
+            }
+            uploaded += 1;
+            false
+        });
+        for (_, texture) in arena.iter_mut() {
+            let waiting = texture.load.is_some() || matches!(texture.usage, TextureUsage::Canvas);
+            if waiting && !texture.evicted {
+                texture.send_to_gpu(&up);
+            }
+        }
+        self.load_stats.uploaded += uploaded;
+        uploaded
+    }
+
+    /// 送り直す列から最大`budget`個のテクスチャを GPU に送り直す
+    ///
+    /// 送り直した数を返す。
+    #[cfg(feature = "backend-wgpu")]
+    pub(crate) fn reload_pending(
+        &mut self,
+        budget: usize,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+    ) -> usize {
+        let up = Uploader {
+            device,
+            queue,
+            bind_group_layout,
+            sampler,
+            texture_binding: WgpuResource::TEXTURE_BINDING,
+            sampler_binding: WgpuResource::SAMPLER_BINDING,
+            settings: &self.settings,
+            memory: &self.memory,
+        };
+        let mut reloaded = 0;
+        while reloaded < budget {
+            let Some(key) = self.reloads.pop_front() else {
+                break;
+            };
+            // 列に並んだ後に取り除かれたテクスチャと、GPU から解放したテクスチャは飛ばす
+            if let Some(texture) = self.arena.get_mut(key).filter(|texture| !texture.evicted) {
+                texture.upload(&up);
+                reloaded += 1;
+            }
+        }
+        reloaded
+    }
+
+    /// 使われなくても GPU から解放しないようにする。UI のアトラスなどに使う
+    #[cfg(feature = "backend-wgpu")]
+    pub fn pin(&mut self, index: TextureIndex) -> Result<(), StaleHandle> {
+        self.arena
+            .get_mut(index.0)
+            .ok_or(StaleHandle(index))?
+            .pinned = true;
+        Ok(())
+    }
+
+    /// [`Self::pin`] を取り消す
+    #[cfg(feature = "backend-wgpu")]
+    pub fn unpin(&mut self, index: TextureIndex) -> Result<(), StaleHandle> {
+        self.arena
+            .get_mut(index.0)
+            .ok_or(StaleHandle(index))?
+            .pinned = false;
+        Ok(())
+    }
+
+    /// 使われないので GPU から解放したテクスチャか
+    #[cfg(feature = "backend-wgpu")]
+    pub fn is_evicted(&self, index: TextureIndex) -> bool {
+        self.arena
+            .get(index.0)
+            .is_some_and(|texture| texture.evicted)
+    }
+
+    /// `id` のテクスチャをこのフレームで使うと記録する
+    #[cfg(feature = "backend-wgpu")]
+    pub(crate) fn mark_used(&mut self, id: TextureId) {
+        let index = match id {
+            TextureId::Single(index) | TextureId::Atlas(Allocation(index, _)) => index,
+            TextureId::Builtin(_) => return,
+        };
+        if let Some(texture) = self.arena.get_mut(index.0) {
+            texture.last_used = self.frame;
+        }
+    }
+
+    /// [`Self::mark_used`] されずに `unload_after` フレーム経ったテクスチャを GPU から解放する
+    ///
+    /// CPU 上の画像は残すので、解放したテクスチャがまた使われたら同じ呼び出しの中で送り直す。
+    /// 解放したテクスチャの数を返す。GPU のメモリ使用量の集計からもすぐに減る。
+    #[cfg(feature = "backend-wgpu")]
+    pub(crate) fn collect_unused(
+        &mut self,
+        unload_after: u32,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+    ) -> usize {
+        let up = Uploader {
+            device,
+            queue,
+            bind_group_layout,
+            sampler,
+            texture_binding: WgpuResource::TEXTURE_BINDING,
+            sampler_binding: WgpuResource::SAMPLER_BINDING,
+            settings: &self.settings,
+            memory: &self.memory,
+        };
+        let mut evicted = 0;
+        for (_, texture) in self.arena.iter_mut() {
+            if texture.last_used == self.frame {
+                if texture.evicted {
+                    tracing::debug!(label = texture.debug_label(), "reload evicted texture");
+                    texture.upload(&up);
                 }
+            } else if !texture.pinned
+                && texture.gpu.is_some()
+                && self.frame - texture.last_used >= u64::from(unload_after)
+            {
+                tracing::debug!(label = texture.debug_label(), "evict unused texture");
+                texture.gpu = None;
+                texture.evicted = true;
+                evicted += 1;
             }
         }
+        self.frame += 1;
+        evicted
+    }
+
+    /// 作成済みの組み込みのテクスチャ
+    #[cfg(feature = "backend-wgpu")]
+    fn builtin(&self, builtin: BuiltinTexture) -> Option<&Texture> {
+        match builtin {
+            BuiltinTexture::White => self.white.get(),
+            BuiltinTexture::Missing => self.missing.get(),
+        }
+    }
+
+    /// 組み込みのテクスチャのバインドグループ。まだ無ければ GPU に作る
+    #[cfg(feature = "backend-wgpu")]
+    pub(crate) fn builtin_bind_group(
+        &self,
+        builtin: BuiltinTexture,
+        resource: &WgpuResource<'_>,
+    ) -> &wgpu::BindGroup {
+        let cell = match builtin {
+            BuiltinTexture::White => &self.white,
+            BuiltinTexture::Missing => &self.missing,
+        };
+        let texture = cell.get_or_init(|| {
+            let mut texture = Texture::new(
+                builtin.image(),
+                TextureUsage::Single,
+                Some(builtin.label().to_string()),
+            );
+            texture.send_to_gpu(&Uploader {
+                device: &resource.device,
+                queue: &resource.queue,
+                bind_group_layout: &resource.texture_bind_group_layout,
+                sampler: &resource.texture_sampler,
+                texture_binding: WgpuResource::TEXTURE_BINDING,
+                sampler_binding: WgpuResource::SAMPLER_BINDING,
+                settings: &self.settings,
+                memory: &self.memory,
+            });
+            texture
+        });
+        texture
+            .bind_group()
+            .expect("builtin textures are created on GPU")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clamped_size_keeps_aspect_ratio() {
+        let settings = TextureSettings {
+            max_size: Some(256),
+            ..Default::default()
+        };
+        assert_eq!(settings.clamped_size(100, 50), (100, 50));
+        assert_eq!(settings.clamped_size(1024, 512), (256, 128));
+        assert_eq!(settings.clamped_size(300, 1200), (64, 256));
+        assert_eq!(settings.clamped_size(4096, 1), (256, 1));
+        assert_eq!(
+            TextureSettings::default().clamped_size(4096, 4096),
+            (4096, 4096)
+        );
+    }
+
+    #[test]
+    fn decodes_in_background() {
+        let mut png = std::io::Cursor::new(Vec::new());
+        image::DynamicImage::ImageRgba8(RgbaImage::from_fn(2, 1, |x, _| {
+            image::Rgba([0, 0, 0, if x == 0 { 0 } else { 255 }])
+        }))
+        .write_to(&mut png, image::ImageFormat::Png)
+        .unwrap();
+        let png = png.into_inner();
+
+        let mut registry = TextureRegistry::default();
+        let later = registry.load_texture_from_bytes(png.clone(), None, LoadPriority::Prefetch);
+        let broken = registry.load_texture_from_bytes(vec![1, 2, 3], None, LoadPriority::Immediate);
+        assert!(registry.is_loading(later));
+        // 届くまでは透明な画像
+        assert_eq!(
+            registry
+                .alpha_at(later.into(), Point2::new(0.9, 0.5))
+                .unwrap(),
+            0
+        );
+
+        let deadline = Instant::now() + std::time::Duration::from_secs(10);
+        let mut received = 0;
+        while received < 2 {
+            assert!(Instant::now() < deadline, "decoding timed out");
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            received += registry.poll_loads();
+            assert_eq!(
+                registry.load_stats().main_thread_decode,
+                std::time::Duration::ZERO
+            );
+        }
+        assert_eq!(
+            registry
+                .alpha_at(later.into(), Point2::new(0.9, 0.5))
+                .unwrap(),
+            255
+        );
+        assert!(!registry.contains(broken));
+
+        // 同期的な読み込みはメインスレッドの時間に数える
+        registry.new_texture_from_bytes(&png, None).unwrap();
+        assert!(registry.load_stats().main_thread_decode > std::time::Duration::ZERO);
+    }
+
+    #[test]
+    fn unloaded_handle_is_rejected_after_slot_reuse() {
+        let mut registry = TextureRegistry::default();
+        let red = registry.new_texture(
+            RgbaImage::from_pixel(1, 1, image::Rgba([255, 0, 0, 255])),
+            None,
+        );
+        let atlas = registry.create_altas_texture(8, 8, None);
+        let tile = registry
+            .allocate_sub_image(atlas, RgbaImage::new(4, 4))
+            .unwrap();
+        registry.unload(red).unwrap();
+        registry.unload(atlas).unwrap();
+        assert_eq!(registry.unload(red), Err(StaleHandle(red)));
+
+        // 空いたスロットが使われても、古いインデックスは新しいテクスチャを指さない
+        let blue = registry.new_texture(
+            RgbaImage::from_pixel(1, 1, image::Rgba([0, 0, 255, 128])),
+            None,
+        );
+        assert_ne!(blue, red);
+        assert!(!registry.contains(red));
+        let error = registry
+            .alpha_at(red.into(), Point2::new(0.5, 0.5))
+            .unwrap_err();
+        assert_eq!(error.downcast_ref(), Some(&StaleHandle(red)));
+        let error = registry.get_uv(tile.into()).unwrap_err();
+        assert_eq!(error.downcast_ref(), Some(&StaleHandle(atlas)));
+        assert_eq!(registry.resolve(red.into()), TextureId::MISSING);
+        assert_eq!(registry.resolve(blue.into()), TextureId::Single(blue));
+        assert_eq!(
+            registry
+                .alpha_at(blue.into(), Point2::new(0.5, 0.5))
+                .unwrap(),
+            128
+        );
     }
 }
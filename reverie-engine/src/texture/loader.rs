@@ -0,0 +1,331 @@
+//! 画像のデコードをバックグラウンドのスレッドで行う
+use std::{
+    collections::VecDeque,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Condvar, Mutex, PoisonError,
+    },
+    time::Duration,
+};
+
+use anyhow::Context;
+use image::RgbaImage;
+use web_time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Hash)]
+/// 読み込みの優先度
+pub enum LoadPriority {
+    /// 今見えているもの。できるだけ早くデコードし、届いたフレームで GPU に送る
+    #[default]
+    Immediate,
+    /// 次の部屋のように、これから使うもの
+    ///
+    /// [`Self::Immediate`] の後にデコードし、デコードするスレッドを 1 つは [`Self::Immediate`] のために空けておく。
+    /// GPU に送るのも 1 フレームに [`super::TextureRegistry::PREFETCH_UPLOADS_PER_FRAME`] 個まで。
+    Prefetch,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// デコードする画像の在処
+pub enum TextureSource {
+    /// PNG などのエンコードされた画像データ
+    Bytes(Vec<u8>),
+    /// 画像ファイル。読み込みもバックグラウンドで行う
+    #[cfg(not(target_arch = "wasm32"))]
+    Path(std::path::PathBuf),
+}
+
+impl TextureSource {
+    /// デコードして RGBA にする
+    ///
+    /// 時間がかかるので、メインスレッドで呼んだときは [`TextureLoadStats::main_thread_decode`] に数える。
+    pub(super) fn decode(&self) -> anyhow::Result<RgbaImage> {
+        let image = match self {
+            Self::Bytes(bytes) => image::load_from_memory(bytes).context("failed: decode image")?,
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Path(path) => image::open(path)
+                .with_context(|| format!("failed: load image {}", path.display()))?,
+        };
+        Ok(image.to_rgba8())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// 1 フレームのテクスチャの読み込みの統計
+///
+/// [`super::TextureRegistry::load_stats`] で取得する。[`super::TextureRegistry::poll_loads`] で 0 に戻る。
+pub struct TextureLoadStats {
+    /// メインスレッド ([`super::TextureRegistry`] を持つスレッド) で画像のデコードにかかった時間
+    ///
+    /// [`super::TextureRegistry::new_texture_from_bytes`] などの同期的な読み込みと、
+    /// スレッドの無い Web で [`super::TextureRegistry::load_texture`] したものが数えられる。
+    /// バックグラウンドで読み込んでいれば 0 になる。
+    pub main_thread_decode: Duration,
+    /// バックグラウンドでのデコードが終わって受け取った数
+    pub decoded: usize,
+    /// デコードが終わって GPU に送った数
+    pub uploaded: usize,
+    /// デコードに失敗して取り除いた数
+    pub failed: usize,
+}
+
+/// デコードを待っている画像
+#[derive(Debug)]
+pub(super) struct Job {
+    pub(super) key: slotmap::DefaultKey,
+    pub(super) source: TextureSource,
+}
+
+/// デコードの結果
+#[derive(Debug)]
+pub(super) struct Decoded {
+    pub(super) key: slotmap::DefaultKey,
+    pub(super) image: anyhow::Result<RgbaImage>,
+}
+
+/// 優先度ごとの待ち行列
+#[derive(Debug, Default)]
+struct Queues {
+    immediate: VecDeque<Job>,
+    prefetch: VecDeque<Job>,
+    /// [`LoadPriority::Prefetch`] をデコードしているスレッドの数
+    prefetching: usize,
+    /// [`LoadPriority::Prefetch`] を同時にデコードしてよいスレッドの数
+    max_prefetching: usize,
+    shutdown: bool,
+}
+
+impl Queues {
+    /// 次にデコードするもの。[`LoadPriority::Immediate`] を先にする
+    fn take(&mut self) -> Option<(Job, LoadPriority)> {
+        if let Some(job) = self.immediate.pop_front() {
+            return Some((job, LoadPriority::Immediate));
+        }
+        if self.prefetching < self.max_prefetching {
+            let job = self.prefetch.pop_front()?;
+            self.prefetching += 1;
+            return Some((job, LoadPriority::Prefetch));
+        }
+        None
+    }
+
+    fn finish(&mut self, priority: LoadPriority) {
+        if priority == LoadPriority::Prefetch {
+            self.prefetching -= 1;
+        }
+    }
+
+    fn queue_mut(&mut self, priority: LoadPriority) -> &mut VecDeque<Job> {
+        match priority {
+            LoadPriority::Immediate => &mut self.immediate,
+            LoadPriority::Prefetch => &mut self.prefetch,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    queues: Mutex<Queues>,
+    changed: Condvar,
+}
+
+impl Shared {
+    fn queues(&self) -> std::sync::MutexGuard<'_, Queues> {
+        self.queues.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// 画像をデコードするスレッドの集まり
+///
+/// スレッドは最初に読み込みを頼まれたときに作る。Web ではスレッドを作れないので、
+/// [`Self::receive`] の中で 1 フレームに 1 枚ずつメインスレッドでデコードする。
+#[derive(Debug)]
+pub(super) struct Loader {
+    shared: Arc<Shared>,
+    sender: Sender<Decoded>,
+    /// [`super::TextureRegistry`] はスレッドの間で共有するので `Mutex` に入れる
+    receiver: Mutex<Receiver<Decoded>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    started: bool,
+}
+
+impl Default for Loader {
+    fn default() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            shared: Arc::default(),
+            sender,
+            receiver: Mutex::new(receiver),
+            #[cfg(not(target_arch = "wasm32"))]
+            started: false,
+        }
+    }
+}
+
+impl Loader {
+    /// デコードするスレッドの数。1 つは [`LoadPriority::Immediate`] のために空けておくので 2 つ以上
+    #[cfg(not(target_arch = "wasm32"))]
+    fn worker_count() -> usize {
+        std::thread::available_parallelism()
+            .map_or(2, |n| n.get().saturating_sub(1))
+            .clamp(2, 4)
+    }
+
+    pub(super) fn request(&mut self, job: Job, priority: LoadPriority) {
+        #[cfg(not(target_arch = "wasm32"))]
+        if !self.started {
+            self.start();
+        }
+        self.shared.queues().queue_mut(priority).push_back(job);
+        self.shared.changed.notify_one();
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn start(&mut self) {
+        let workers = Self::worker_count();
+        self.shared.queues().max_prefetching = workers - 1;
+        for i in 0..workers {
+            let shared = Arc::clone(&self.shared);
+            let sender = self.sender.clone();
+            let spawned = std::thread::Builder::new()
+                .name(format!("reverie texture decode {i}"))
+                .spawn(move || work(&shared, &sender));
+            if let Err(err) = spawned {
+                tracing::error!(%err, "failed: spawn texture decode thread");
+            }
+        }
+        self.started = true;
+    }
+
+    /// まだデコードしていなければ優先度を変える
+    pub(super) fn set_priority(&self, key: slotmap::DefaultKey, priority: LoadPriority) {
+        let mut queues = self.shared.queues();
+        let from = match priority {
+            LoadPriority::Immediate => LoadPriority::Prefetch,
+            LoadPriority::Prefetch => LoadPriority::Immediate,
+        };
+        let from = queues.queue_mut(from);
+        if let Some(i) = from.iter().position(|job| job.key == key) {
+            let job = from.remove(i).expect("the position was found just now");
+            queues.queue_mut(priority).push_back(job);
+        }
+    }
+
+    /// まだデコードしていなければ取りやめる
+    pub(super) fn cancel(&self, key: slotmap::DefaultKey) {
+        let mut queues = self.shared.queues();
+        queues.immediate.retain(|job| job.key != key);
+        queues.prefetch.retain(|job| job.key != key);
+    }
+
+    /// デコードが終わったものと、そのためにメインスレッドでデコードした時間
+    pub(super) fn receive(&self) -> (Vec<Decoded>, Duration) {
+        #[cfg_attr(not(target_arch = "wasm32"), allow(unused_mut))]
+        let mut main_thread_decode = Duration::ZERO;
+        #[cfg(target_arch = "wasm32")]
+        {
+            let next = {
+                let mut queues = self.shared.queues();
+                queues.max_prefetching = 1;
+                queues.take()
+            };
+            if let Some((job, priority)) = next {
+                let start = Instant::now();
+                let image = job.source.decode();
+                main_thread_decode = start.elapsed();
+                self.shared.queues().finish(priority);
+                let _ = self.sender.send(Decoded {
+                    key: job.key,
+                    image,
+                });
+            }
+        }
+        let decoded = self
+            .receiver
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .try_iter()
+            .collect();
+        (decoded, main_thread_decode)
+    }
+}
+
+impl Drop for Loader {
+    fn drop(&mut self) {
+        self.shared.queues().shutdown = true;
+        self.shared.changed.notify_all();
+    }
+}
+
+/// デコードするスレッドの本体
+#[cfg(not(target_arch = "wasm32"))]
+fn work(shared: &Shared, sender: &Sender<Decoded>) {
+    loop {
+        let (job, priority) = {
+            let mut queues = shared.queues();
+            loop {
+                if queues.shutdown {
+                    return;
+                }
+                if let Some(next) = queues.take() {
+                    break next;
+                }
+                queues = shared
+                    .changed
+                    .wait(queues)
+                    .unwrap_or_else(PoisonError::into_inner);
+            }
+        };
+        let start = Instant::now();
+        let image = job.source.decode();
+        tracing::debug!(?priority, elapsed = ?start.elapsed(), "decode texture");
+        shared.queues().finish(priority);
+        // 他のスレッドが待っている Prefetch を取れるようになったかもしれない
+        shared.changed.notify_one();
+        if sender
+            .send(Decoded {
+                key: job.key,
+                image,
+            })
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(n: u32) -> Job {
+        let mut keys = slotmap::SlotMap::new();
+        Job {
+            key: keys.insert(n),
+            source: TextureSource::Bytes(vec![n as u8]),
+        }
+    }
+
+    #[test]
+    fn immediate_goes_first_and_prefetch_leaves_a_worker_free() {
+        let mut queues = Queues {
+            max_prefetching: 1,
+            ..Queues::default()
+        };
+        queues.prefetch.push_back(job(1));
+        queues.prefetch.push_back(job(2));
+        queues.immediate.push_back(job(3));
+
+        let (first, priority) = queues.take().unwrap();
+        assert_eq!(priority, LoadPriority::Immediate);
+        assert_eq!(first.source, TextureSource::Bytes(vec![3]));
+        assert_eq!(queues.take().unwrap().1, LoadPriority::Prefetch);
+        // もう 1 つの Prefetch は、デコード中のものが終わるまで待つ
+        assert!(queues.take().is_none());
+        queues.finish(LoadPriority::Prefetch);
+        assert_eq!(
+            queues.take().unwrap().0.source,
+            TextureSource::Bytes(vec![2])
+        );
+    }
+}
@@ -0,0 +1,73 @@
+struct VertexInput {
+  @location(0) position: vec2<f32>,
+  @location(1) corner: u32,
+  @location(2) tile: u32,
+  @location(3) animation: u32,
+  @location(4) tint: vec4<f32>
+}
+
+struct VertexOutput {
+  @location(0) uv: vec2<f32>,
+  @location(1) tint: vec4<f32>,
+  @builtin(position) position: vec4<f32>
+}
+
+struct Tilemap {
+  model: mat4x4<f32>,
+  uv_min: vec2<f32>,
+  uv_max: vec2<f32>,
+  // タイルセットの列数と行数
+  grid: vec2<u32>,
+  time_ms: u32,
+  _pad: u32,
+  // x: 最初のコマ, y: コマの数, z: 1 周のミリ秒
+  animations: array<vec4<u32>, 32>,
+  // x: タイル, y: このコマが終わるミリ秒
+  frames: array<vec4<u32>, 128>,
+}
+
+@group(0)
+@binding(0)
+var tex: texture_2d<f32>;
+
+@group(0)
+@binding(1)
+var samp: sampler;
+
+@group(1)
+@binding(0)
+var<uniform> transform: mat4x4<f32>;
+
+@group(2)
+@binding(0)
+var<uniform> tilemap: Tilemap;
+
+@vertex
+fn vs_main(in: VertexInput) -> VertexOutput {
+  var tile = in.tile;
+  if in.animation != 0xffffffffu {
+    let animation = tilemap.animations[in.animation];
+    let t = tilemap.time_ms % max(animation.z, 1u);
+    for (var i = 0u; i < animation.y; i++) {
+      let frame = tilemap.frames[animation.x + i];
+      if t < frame.y {
+        tile = frame.x;
+        break;
+      }
+    }
+  }
+  let cell = vec2<f32>(f32(tile % tilemap.grid.x), f32(tile / tilemap.grid.x));
+  let corner = vec2<f32>(f32(in.corner & 1u), f32(in.corner >> 1u));
+  let uv = (cell + corner) / vec2<f32>(tilemap.grid);
+
+  var out: VertexOutput;
+  out.uv = mix(tilemap.uv_min, tilemap.uv_max, uv);
+  out.tint = in.tint;
+  out.position = transform * tilemap.model * vec4<f32>(in.position, 0.0, 1.0);
+  return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+  return textureSample(tex, samp, in.uv) * in.tint;
+}
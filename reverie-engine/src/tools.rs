@@ -0,0 +1,365 @@
+//! エディタのための道具
+//!
+//! feature `tools` を有効にすると使える。
+use nalgebra::{Matrix2, Point2, Point3, Vector2, Vector3};
+use reverie_util::color::Color;
+use winit::event::{ElementState, MouseButton};
+
+use crate::{
+    scene::{CameraComponent, EntityIndex, Frame, System, TransformComponent},
+    wgpu_wrapper::WgpuResource,
+};
+
+const X_COLOR: Color = Color::rgb(0.9, 0.2, 0.2);
+const Y_COLOR: Color = Color::rgb(0.2, 0.9, 0.2);
+const FREE_COLOR: Color = Color::rgb(0.3, 0.5, 1.0);
+const ACTIVE_COLOR: Color = Color::rgb(1.0, 0.9, 0.1);
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+/// [`GizmoSystem`] で動かせるエンティティに付けるマーカーコンポーネント
+pub struct SelectedComponent;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// ギズモのどのハンドルを掴んでいるか
+pub enum GizmoAxis {
+    /// X 方向にだけ動かす
+    X,
+    /// Y 方向にだけ動かす
+    Y,
+    /// 中心の四角。XY 平面上を自由に動かす
+    Free,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Drag {
+    entity: hecs::Entity,
+    axis: GizmoAxis,
+    start_cursor: Point2<f32>,
+    start_translation: Vector2<f32>,
+    /// ワールドの X, Y 方向の単位長さが画面上で何ピクセルになるか
+    screen_axes: Matrix2<f32>,
+}
+
+#[derive(Debug)]
+/// [`SelectedComponent`] を持つエンティティに移動用のハンドルを表示し、マウスで動かせるようにするシステム
+///
+/// ハンドルは [`WgpuResource::debug_draw`] で描画され、カメラのズームに関わらず同じピクセル数の大きさになる。
+/// 左ボタンでハンドルを掴んでドラッグすると [`TransformComponent::translation`] が書き換わる。
+pub struct GizmoSystem {
+    /// 画面とワールドの変換に使うカメラ。`None` ならピクセル座標をそのままワールド座標とみなす
+    pub camera: Option<EntityIndex>,
+    /// ハンドルの長さ (ピクセル)
+    pub handle_length: f32,
+    /// ハンドルを掴んだとみなす距離 (ピクセル)。中心の四角の半分の大きさも兼ねる
+    pub hit_tolerance: f32,
+    /// 移動後の座標をこの間隔の格子に揃える
+    pub snap: Option<f32>,
+    drag: Option<Drag>,
+}
+
+impl Default for GizmoSystem {
+    fn default() -> Self {
+        Self {
+            camera: None,
+            handle_length: 60.0,
+            hit_tolerance: 8.0,
+            snap: None,
+            drag: None,
+        }
+    }
+}
+
+impl GizmoSystem {
+    pub fn new(camera: Option<EntityIndex>) -> Self {
+        Self {
+            camera,
+            ..Default::default()
+        }
+    }
+
+    pub const fn with_snap(mut self, snap: f32) -> Self {
+        self.snap = Some(snap);
+        self
+    }
+
+    /// 掴んでいるハンドル
+    pub fn dragging(&self) -> Option<(EntityIndex, GizmoAxis)> {
+        self.drag.map(|d| (EntityIndex(d.entity), d.axis))
+    }
+
+    /// ワールド座標を画面のピクセル座標に変換する
+    fn to_screen(
+        &self,
+        world: &hecs::World,
+        resource: &WgpuResource<'_>,
+        point: &Point3<f32>,
+    ) -> Option<Point2<f32>> {
+        let Some(entity) = self.camera else {
+            return Some(point.xy());
+        };
+        let mut query = world
+            .query_one::<(&CameraComponent, Option<&TransformComponent>)>(entity.0)
+            .ok()?;
+        let (camera, transform) = query.get()?;
+        let (width, height) = camera.target_size(resource);
+        camera.world_to_target(
+            &transform.cloned().unwrap_or_default(),
+            width,
+            height,
+            point,
+        )
+    }
+
+    /// エンティティの画面上の位置と、ワールドの X, Y 方向の単位長さの画面上での向きと長さ
+    fn screen_frame(
+        &self,
+        world: &hecs::World,
+        resource: &WgpuResource<'_>,
+        origin: &Point3<f32>,
+    ) -> Option<(Point2<f32>, Matrix2<f32>)> {
+        let o = self.to_screen(world, resource, origin)?;
+        let x = self.to_screen(world, resource, &(origin + Vector3::x()))? - o;
+        let y = self.to_screen(world, resource, &(origin + Vector3::y()))? - o;
+        Some((o, Matrix2::from_columns(&[x, y])))
+    }
+
+    fn pick(
+        &self,
+        world: &hecs::World,
+        resource: &WgpuResource<'_>,
+        cursor: Point2<f32>,
+    ) -> Option<Drag> {
+        let mut query = world.query::<(&TransformComponent, &SelectedComponent)>();
+        query.iter().find_map(|(entity, (transform, _))| {
+            let origin = Point3::from(transform.translation.vector);
+            let (o, axes) = self.screen_frame(world, resource, &origin)?;
+            let (x_end, y_end) = self.handle_ends(o, &axes);
+            let axis = hit_test(o, x_end, y_end, cursor, self.hit_tolerance)?;
+            Some(Drag {
+                entity,
+                axis,
+                start_cursor: cursor,
+                start_translation: origin.xy().coords,
+                screen_axes: axes,
+            })
+        })
+    }
+
+    /// ハンドルの先端の画面上の位置
+    fn handle_ends(&self, o: Point2<f32>, axes: &Matrix2<f32>) -> (Point2<f32>, Point2<f32>) {
+        let end = |v: Vector2<f32>| {
+            o + v.try_normalize(f32::EPSILON).unwrap_or_default() * self.handle_length
+        };
+        (end(axes.column(0).into()), end(axes.column(1).into()))
+    }
+
+    fn draw(&self, world: &hecs::World, resource: &WgpuResource<'_>) {
+        let debug = &resource.debug_draw;
+        let mut query = world.query::<(&TransformComponent, &SelectedComponent)>();
+        for (entity, (transform, _)) in query.iter() {
+            let origin = Point3::from(transform.translation.vector);
+            let Some((_, axes)) = self.screen_frame(world, resource, &origin) else {
+                continue;
+            };
+            // ピクセル単位の長さをワールドの長さに直して、ズームに関わらず同じ大きさにする
+            let world_length = |pixels: f32, column: usize| {
+                let per_unit = axes.column(column).norm();
+                if per_unit > f32::EPSILON {
+                    pixels / per_unit
+                } else {
+                    0.0
+                }
+            };
+            let active = self.drag.filter(|d| d.entity == entity).map(|d| d.axis);
+            let color = |axis, normal| {
+                if active == Some(axis) {
+                    ACTIVE_COLOR
+                } else {
+                    normal
+                }
+            };
+
+            let x_end = origin + Vector3::x() * world_length(self.handle_length, 0);
+            let y_end = origin + Vector3::y() * world_length(self.handle_length, 1);
+            debug.line(origin, x_end, color(GizmoAxis::X, X_COLOR));
+            debug.line(origin, y_end, color(GizmoAxis::Y, Y_COLOR));
+
+            let hx = Vector3::x() * world_length(self.hit_tolerance, 0);
+            let hy = Vector3::y() * world_length(self.hit_tolerance, 1);
+            debug.polyline(
+                &[
+                    origin - hx - hy,
+                    origin + hx - hy,
+                    origin + hx + hy,
+                    origin - hx + hy,
+                ],
+                true,
+                color(GizmoAxis::Free, FREE_COLOR),
+            );
+        }
+    }
+}
+
+/// ハンドルの当たり判定をする
+///
+/// `origin` はエンティティの画面上の位置、`x_end` と `y_end` はハンドルの先端の画面上の位置。
+/// 中心の四角を最優先にする。
+pub fn hit_test(
+    origin: Point2<f32>,
+    x_end: Point2<f32>,
+    y_end: Point2<f32>,
+    cursor: Point2<f32>,
+    tolerance: f32,
+) -> Option<GizmoAxis> {
+    let d = cursor - origin;
+    if d.x.abs() <= tolerance && d.y.abs() <= tolerance {
+        return Some(GizmoAxis::Free);
+    }
+    let dx = distance_to_segment(cursor, origin, x_end);
+    let dy = distance_to_segment(cursor, origin, y_end);
+    if dx > tolerance && dy > tolerance {
+        None
+    } else if dx <= dy {
+        Some(GizmoAxis::X)
+    } else {
+        Some(GizmoAxis::Y)
+    }
+}
+
+fn distance_to_segment(p: Point2<f32>, a: Point2<f32>, b: Point2<f32>) -> f32 {
+    let ab = b - a;
+    let len2 = ab.norm_squared();
+    if len2 <= f32::EPSILON {
+        return (p - a).norm();
+    }
+    let t = ((p - a).dot(&ab) / len2).clamp(0.0, 1.0);
+    (p - (a + ab * t)).norm()
+}
+
+/// 値を `grid` の間隔の格子に揃える
+pub fn snap_to_grid(value: f32, grid: f32) -> f32 {
+    if grid > 0.0 {
+        (value / grid).round() * grid
+    } else {
+        value
+    }
+}
+
+/// 画面上のカーソルの移動量を、ワールドの XY 平面上の移動量に変換する
+///
+/// `screen_axes` の列はワールドの X, Y 方向の単位長さの画面上での向きと長さ。
+/// `axis` が [`GizmoAxis::X`] や [`GizmoAxis::Y`] なら、もう一方の成分は 0 になる。
+pub fn drag_delta(
+    screen_axes: &Matrix2<f32>,
+    cursor_delta: Vector2<f32>,
+    axis: GizmoAxis,
+) -> Vector2<f32> {
+    let Some(inverse) = screen_axes.try_inverse() else {
+        return Vector2::zeros();
+    };
+    let delta = inverse * cursor_delta;
+    match axis {
+        GizmoAxis::X => Vector2::new(delta.x, 0.0),
+        GizmoAxis::Y => Vector2::new(0.0, delta.y),
+        GizmoAxis::Free => delta,
+    }
+}
+
+impl System for GizmoSystem {
+    fn setup(&mut self, _resource: Option<&WgpuResource<'_>>) {}
+
+    fn update(
+        &mut self,
+        frame: &Frame<'_>,
+        world: &mut hecs::World,
+        resource: Option<&WgpuResource<'_>>,
+    ) {
+        // 画面が無ければ掴むものも無い
+        let Some(resource) = resource else {
+            return;
+        };
+        let cursor = Point2::new(frame.mouse_position.x as f32, frame.mouse_position.y as f32);
+
+        for (state, button, position) in frame.mouse_clicks {
+            if *button != MouseButton::Left {
+                continue;
+            }
+            match state {
+                ElementState::Pressed => {
+                    let position = Point2::new(position.x as f32, position.y as f32);
+                    self.drag = self.pick(world, resource, position);
+                }
+                ElementState::Released => self.drag = None,
+            }
+        }
+
+        if let Some(drag) = self.drag {
+            match world.get::<&mut TransformComponent>(drag.entity) {
+                Ok(mut transform) => {
+                    let delta =
+                        drag_delta(&drag.screen_axes, cursor - drag.start_cursor, drag.axis);
+                    let mut position = drag.start_translation + delta;
+                    if let Some(grid) = self.snap {
+                        if drag.axis != GizmoAxis::Y {
+                            position.x = snap_to_grid(position.x, grid);
+                        }
+                        if drag.axis != GizmoAxis::X {
+                            position.y = snap_to_grid(position.y, grid);
+                        }
+                    }
+                    transform.translation.x = position.x;
+                    transform.translation.y = position.y;
+                }
+                // 掴んでいる間にエンティティが消えた
+                Err(_) => self.drag = None,
+            }
+        }
+
+        self.draw(world, resource);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGIN: Point2<f32> = Point2::new(100.0, 100.0);
+    const X_END: Point2<f32> = Point2::new(160.0, 100.0);
+    const Y_END: Point2<f32> = Point2::new(100.0, 40.0);
+
+    #[test]
+    fn hit_test_picks_nearest_handle() {
+        let hit = |x, y| hit_test(ORIGIN, X_END, Y_END, Point2::new(x, y), 8.0);
+        assert_eq!(hit(102.0, 98.0), Some(GizmoAxis::Free));
+        assert_eq!(hit(150.0, 104.0), Some(GizmoAxis::X));
+        assert_eq!(hit(97.0, 50.0), Some(GizmoAxis::Y));
+        assert_eq!(hit(150.0, 50.0), None);
+        assert_eq!(hit(175.0, 100.0), None);
+    }
+
+    #[test]
+    fn drag_is_constrained_to_axis() {
+        // ズーム 2 倍で Y 軸が画面の上向き
+        let axes = Matrix2::new(2.0, 0.0, 0.0, -2.0);
+        let cursor = Vector2::new(10.0, 10.0);
+        assert_eq!(
+            drag_delta(&axes, cursor, GizmoAxis::X),
+            Vector2::new(5.0, 0.0)
+        );
+        assert_eq!(
+            drag_delta(&axes, cursor, GizmoAxis::Y),
+            Vector2::new(0.0, -5.0)
+        );
+        assert_eq!(
+            drag_delta(&axes, cursor, GizmoAxis::Free),
+            Vector2::new(5.0, -5.0)
+        );
+    }
+
+    #[test]
+    fn snap_rounds_to_grid() {
+        assert_eq!(snap_to_grid(13.0, 8.0), 16.0);
+        assert_eq!(snap_to_grid(-3.0, 8.0), -0.0);
+        assert_eq!(snap_to_grid(13.0, 0.0), 13.0);
+    }
+}
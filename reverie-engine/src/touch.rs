@@ -0,0 +1,259 @@
+//! タッチ入力とジェスチャー認識
+use std::time::Duration;
+
+use web_time::Instant;
+use winit::{dpi::PhysicalPosition, event::TouchPhase};
+
+/// マウスから合成したタッチに使う ID
+pub const MOUSE_TOUCH_ID: u64 = u64::MAX;
+
+/// タップとみなす最大の押下時間
+const TAP_MAX_DURATION: Duration = Duration::from_millis(300);
+/// タップとみなす最大の移動距離 (ピクセル)
+const TAP_MAX_DISTANCE: f64 = 10.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// 画面に触れている指 1 本の状態
+pub struct Touch {
+    /// 指を識別する ID。指が離れるまで同じ値が使われる
+    pub id: u64,
+    pub position: PhysicalPosition<f64>,
+    /// このフレームで最後に起きたイベントの種類
+    pub phase: TouchPhase,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// タッチ入力から認識されたジェスチャー
+pub enum Gesture {
+    /// 短い時間触れてすぐに離した
+    Tap { position: PhysicalPosition<f64> },
+    /// 2 本の指の間隔が変わった
+    Pinch {
+        /// 2 本の指の中点
+        center: PhysicalPosition<f64>,
+        /// 指の間隔の変化率。1 より大きければ広がった (ズームイン)
+        ///
+        /// カメラのズーム倍率にそのまま掛けて使える。
+        zoom_delta: f64,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TrackedTouch {
+    touch: Touch,
+    start_position: PhysicalPosition<f64>,
+    start_time: Instant,
+    /// ピンチに使われた指はタップとみなさない
+    pinched: bool,
+}
+
+#[derive(Debug, Default)]
+/// winit のタッチイベントを集めて、フレームごとのタッチ状態とジェスチャーを作る
+pub struct TouchTracker {
+    tracked: Vec<TrackedTouch>,
+    touches: Vec<Touch>,
+    gestures: Vec<Gesture>,
+    pinch_distance: Option<f64>,
+}
+
+impl TouchTracker {
+    /// タッチイベントを 1 つ処理する
+    pub fn handle(
+        &mut self,
+        id: u64,
+        position: PhysicalPosition<f64>,
+        phase: TouchPhase,
+        now: Instant,
+    ) {
+        let index = self.tracked.iter().position(|t| t.touch.id == id);
+        match (phase, index) {
+            (TouchPhase::Started, _) => {
+                if let Some(index) = index {
+                    self.tracked.remove(index);
+                }
+                self.tracked.push(TrackedTouch {
+                    touch: Touch {
+                        id,
+                        position,
+                        phase,
+                    },
+                    start_position: position,
+                    start_time: now,
+                    pinched: false,
+                });
+                self.pinch_distance = None;
+            }
+            (_, Some(index)) => {
+                let tracked = &mut self.tracked[index];
+                tracked.touch.position = position;
+                tracked.touch.phase = phase;
+
+                if phase == TouchPhase::Ended && is_tap(tracked, now) {
+                    self.gestures.push(Gesture::Tap { position });
+                }
+                if phase == TouchPhase::Moved {
+                    self.update_pinch();
+                } else {
+                    self.pinch_distance = None;
+                }
+            }
+            // 始まりを見ていないタッチは無視する
+            (_, None) => {}
+        }
+        self.sync_touches();
+    }
+
+    /// このフレームのタッチ状態
+    ///
+    /// このフレームで離れた指も [`TouchPhase::Ended`] などとして含まれる。
+    pub fn touches(&self) -> &[Touch] {
+        &self.touches
+    }
+
+    /// このフレームで認識されたジェスチャー
+    pub fn gestures(&self) -> &[Gesture] {
+        &self.gestures
+    }
+
+    /// フレームの終わりに呼び、離れた指とジェスチャーを片付ける
+    pub fn end_frame(&mut self) {
+        self.tracked.retain(|t| is_active(t.touch.phase));
+        self.gestures.clear();
+        self.sync_touches();
+    }
+
+    fn update_pinch(&mut self) {
+        let active: Vec<_> = self
+            .tracked
+            .iter()
+            .filter(|t| is_active(t.touch.phase))
+            .collect();
+        let [a, b] = active.as_slice() else {
+            self.pinch_distance = None;
+            return;
+        };
+        let (a, b) = (a.touch.position, b.touch.position);
+        let distance = (a.x - b.x).hypot(a.y - b.y);
+        let center = PhysicalPosition::new((a.x + b.x) / 2.0, (a.y + b.y) / 2.0);
+
+        if let Some(previous) = self.pinch_distance.filter(|d| *d > 0.0) {
+            let zoom_delta = distance / previous;
+            // 1 フレームに複数回動いたときはまとめる
+            if let Some(Gesture::Pinch {
+                center: last_center,
+                zoom_delta: last_delta,
+            }) = self.gestures.last_mut()
+            {
+                *last_center = center;
+                *last_delta *= zoom_delta;
+            } else {
+                self.gestures.push(Gesture::Pinch { center, zoom_delta });
+            }
+        }
+        self.pinch_distance = Some(distance);
+        for t in self.tracked.iter_mut().filter(|t| is_active(t.touch.phase)) {
+            t.pinched = true;
+        }
+    }
+
+    fn sync_touches(&mut self) {
+        self.touches.clear();
+        self.touches.extend(self.tracked.iter().map(|t| t.touch));
+    }
+}
+
+const fn is_active(phase: TouchPhase) -> bool {
+    matches!(phase, TouchPhase::Started | TouchPhase::Moved)
+}
+
+fn is_tap(tracked: &TrackedTouch, now: Instant) -> bool {
+    let moved = (tracked.touch.position.x - tracked.start_position.x)
+        .hypot(tracked.touch.position.y - tracked.start_position.y);
+    !tracked.pinched
+        && now.duration_since(tracked.start_time) <= TAP_MAX_DURATION
+        && moved <= TAP_MAX_DISTANCE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pos(x: f64, y: f64) -> PhysicalPosition<f64> {
+        PhysicalPosition::new(x, y)
+    }
+
+    #[test]
+    fn tap_is_recognized() {
+        let mut tracker = TouchTracker::default();
+        let t0 = Instant::now();
+        tracker.handle(1, pos(10.0, 10.0), TouchPhase::Started, t0);
+        tracker.handle(
+            1,
+            pos(12.0, 11.0),
+            TouchPhase::Ended,
+            t0 + Duration::from_millis(100),
+        );
+
+        assert_eq!(
+            tracker.gestures(),
+            &[Gesture::Tap {
+                position: pos(12.0, 11.0)
+            }]
+        );
+        assert_eq!(tracker.touches().len(), 1);
+        assert_eq!(tracker.touches()[0].phase, TouchPhase::Ended);
+
+        tracker.end_frame();
+        assert!(tracker.gestures().is_empty());
+        assert!(tracker.touches().is_empty());
+    }
+
+    #[test]
+    fn long_press_or_drag_is_not_tap() {
+        let mut tracker = TouchTracker::default();
+        let t0 = Instant::now();
+        tracker.handle(1, pos(0.0, 0.0), TouchPhase::Started, t0);
+        tracker.handle(
+            1,
+            pos(0.0, 0.0),
+            TouchPhase::Ended,
+            t0 + Duration::from_secs(1),
+        );
+        tracker.handle(2, pos(0.0, 0.0), TouchPhase::Started, t0);
+        tracker.handle(2, pos(50.0, 0.0), TouchPhase::Ended, t0);
+
+        assert!(tracker.gestures().is_empty());
+    }
+
+    #[test]
+    fn pinch_reports_zoom_delta() {
+        let mut tracker = TouchTracker::default();
+        let t0 = Instant::now();
+        tracker.handle(1, pos(100.0, 100.0), TouchPhase::Started, t0);
+        tracker.handle(2, pos(200.0, 100.0), TouchPhase::Started, t0);
+        tracker.handle(2, pos(200.0, 100.0), TouchPhase::Moved, t0);
+        tracker.handle(2, pos(300.0, 100.0), TouchPhase::Moved, t0);
+
+        let [Gesture::Pinch { center, zoom_delta }] = tracker.gestures() else {
+            panic!("expected a pinch: {:?}", tracker.gestures());
+        };
+        assert!((zoom_delta - 2.0).abs() < 1e-9);
+        assert_eq!(*center, pos(200.0, 100.0));
+
+        // ピンチに使った指を離してもタップにはならない
+        tracker.end_frame();
+        tracker.handle(1, pos(100.0, 100.0), TouchPhase::Ended, t0);
+        assert!(tracker.gestures().is_empty());
+    }
+
+    #[test]
+    fn cancelled_touch_is_removed() {
+        let mut tracker = TouchTracker::default();
+        let t0 = Instant::now();
+        tracker.handle(1, pos(0.0, 0.0), TouchPhase::Started, t0);
+        tracker.handle(1, pos(0.0, 0.0), TouchPhase::Cancelled, t0);
+        assert!(tracker.gestures().is_empty());
+        tracker.end_frame();
+        assert!(tracker.touches().is_empty());
+    }
+}
@@ -0,0 +1,365 @@
+//! UI のためのコンポーネントとシステム
+use nalgebra::Vector2;
+
+use crate::{
+    scene::{
+        resource, Frame, LocalTransformComponent, ParentComponent, RenderResource, SpriteComponent,
+        System, TextComponent, TransformComponent,
+    },
+    settings::Settings,
+    text::{Fonts, TextLayout},
+};
+
+/// UI を描く倍率。[`Settings`] のリソースが無ければ 1
+///
+/// [`crate::settings::AccessibilitySettings::ui_scale`] をリソースから毎回読む。
+pub fn ui_scale(world: &hecs::World) -> f32 {
+    resource::<Settings>(world).map_or(1.0, |settings| settings.accessibility.effective_ui_scale())
+}
+
+/// ウィンドウの大きさが `window` のときに UI を配置する大きさ
+///
+/// UI は [`ui_scale`] 倍に拡大して描くので、ウィンドウの大きさをその倍率で割ったものになる。
+pub fn ui_layout_size(world: &hecs::World, window: Vector2<f32>) -> Vector2<f32> {
+    window / ui_scale(world)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// ウィンドウのどこを基準にするか
+pub enum AnchorPoint {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+    /// ウィンドウの左上を (0, 0)、右下を (1, 1) とした位置
+    Normalized(f32, f32),
+}
+
+impl AnchorPoint {
+    /// 左上を (0, 0)、右下を (1, 1) とした位置に変換する
+    pub const fn to_normalized(self) -> Vector2<f32> {
+        let (x, y) = match self {
+            Self::TopLeft => (0.0, 0.0),
+            Self::Top => (0.5, 0.0),
+            Self::TopRight => (1.0, 0.0),
+            Self::Left => (0.0, 0.5),
+            Self::Center => (0.5, 0.5),
+            Self::Right => (1.0, 0.5),
+            Self::BottomLeft => (0.0, 1.0),
+            Self::Bottom => (0.5, 1.0),
+            Self::BottomRight => (1.0, 1.0),
+            Self::Normalized(x, y) => (x, y),
+        };
+        Vector2::new(x, y)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// ウィンドウの大きさに合わせて位置を決めるコンポーネント
+///
+/// [`crate::scene::ScreenSpaceComponent`] と一緒に使う。スプライトや文字列の矩形の同じ名前の点が
+/// ウィンドウの基準点に揃う。例えば [`AnchorPoint::BottomRight`] ならスプライトの右下が
+/// ウィンドウの右下に来て、そこから `offset` だけずらされる。
+/// 矩形の大きさは [`SpriteComponent::size`] か [`TextComponent::layout`] の大きさに
+/// [`TransformComponent::scale`] を掛けたもの。どちらも無ければ拡大率だけを大きさとする。
+/// 位置は [`AnchorSystem`] が毎フレーム [`TransformComponent::translation`] に書き込む。
+///
+/// 親 ([`ParentComponent`]) があるときはウィンドウの代わりに親のスプライトの矩形を基準にし、
+/// [`LocalTransformComponent`] の位置を書き換える。パネルの中のボタンなどに使う。
+pub struct AnchorComponent {
+    pub point: AnchorPoint,
+    /// 基準点からのずれ (ピクセル)
+    pub offset: Vector2<f32>,
+}
+
+impl AnchorComponent {
+    pub const fn new(point: AnchorPoint, offset: Vector2<f32>) -> Self {
+        Self { point, offset }
+    }
+
+    /// 親の矩形の大きさとスプライトの大きさから、スプライトの中心の位置を計算する
+    pub fn position(&self, parent_size: Vector2<f32>, size: Vector2<f32>) -> Vector2<f32> {
+        let anchor = self.point.to_normalized();
+        let pivot = Vector2::new(0.5 - anchor.x, 0.5 - anchor.y);
+        anchor.component_mul(&parent_size) + self.offset + pivot.component_mul(&size)
+    }
+}
+
+#[derive(Debug, Default)]
+/// [`AnchorComponent`] を持つエンティティをウィンドウの大きさに合わせて配置するシステム
+///
+/// ウィンドウの大きさは [`ui_layout_size`] で UI の倍率を反映したものを使うので、倍率を変えると配置し直す。
+/// [`RenderResource`] が無いときは何もしない。
+pub struct AnchorSystem;
+
+impl System for AnchorSystem {
+    fn setup(&mut self, _resource: Option<&RenderResource<'_>>) {}
+
+    fn update(
+        &mut self,
+        _frame: &Frame<'_>,
+        world: &mut hecs::World,
+        resource: Option<&RenderResource<'_>>,
+    ) {
+        // ウィンドウが無ければ基準にする大きさも無い
+        #[cfg(feature = "backend-wgpu")]
+        if let Some(resource) = resource {
+            let window = Vector2::new(
+                resource.surface_config.width as f32,
+                resource.surface_config.height as f32,
+            );
+            self.apply(world, ui_layout_size(world, window));
+        }
+        #[cfg(not(feature = "backend-wgpu"))]
+        let _ = (world, resource);
+    }
+}
+
+impl AnchorSystem {
+    /// ウィンドウの大きさが`window`のときの位置を書き込む
+    ///
+    /// 親を基準にするエンティティのワールドでの位置は、
+    /// [`crate::scene::propagate_transforms`] で反映される。
+    ///
+    /// 文字列は [`Fonts`] のリソースがあれば配置してから大きさを測る。
+    pub fn apply(&self, world: &mut hecs::World, window: Vector2<f32>) {
+        if let Some(fonts) = resource::<Fonts>(world) {
+            for (_, (_, text)) in world
+                .query::<(&AnchorComponent, &mut TextComponent)>()
+                .iter()
+            {
+                text.update_layout(&fonts);
+            }
+        }
+
+        for (entity, (anchor, transform)) in world
+            .query::<(&AnchorComponent, &mut TransformComponent)>()
+            .without::<&ParentComponent>()
+            .iter()
+        {
+            let extent = Extent::of(world, entity);
+            let size = extent
+                .size
+                .component_mul(&transform.scale.vector.xy())
+                .abs();
+            let position = anchor.position(window, size) + extent.center_to_origin(size);
+            transform.translation.x = position.x;
+            transform.translation.y = position.y;
+        }
+
+        for (entity, (anchor, parent, local)) in world
+            .query::<(
+                &AnchorComponent,
+                &ParentComponent,
+                &mut LocalTransformComponent,
+            )>()
+            .iter()
+        {
+            let parent = parent.parent().0;
+            let Ok(parent_transform) = world.get::<&TransformComponent>(parent) else {
+                continue;
+            };
+            let parent_scale = parent_transform.scale.vector.xy();
+            let parent_extent = Extent::of(world, parent);
+            let parent_size = parent_extent.size.component_mul(&parent_scale).abs();
+            let extent = Extent::of(world, entity);
+            let size = extent
+                .size
+                .component_mul(&parent_scale.component_mul(&local.0.scale.vector.xy()))
+                .abs();
+            // 親の位置からのずれを、親の拡大率で割ってローカルな位置にする
+            let position = anchor.position(parent_size, size) + extent.center_to_origin(size)
+                - parent_extent.origin.component_mul(&parent_size);
+            let position = position.component_div(&parent_scale);
+            local.0.translation.x = position.x;
+            local.0.translation.y = position.y;
+        }
+    }
+}
+
+/// 配置する矩形
+struct Extent {
+    /// [`TransformComponent::scale`] を掛ける前の大きさ
+    size: Vector2<f32>,
+    /// 矩形の左上を (0, 0)、右下を (1, 1) としたときの、エンティティの位置が指す点
+    origin: Vector2<f32>,
+}
+
+impl Extent {
+    /// スプライトは中心、文字列は左上がエンティティの位置になる
+    fn of(world: &hecs::World, entity: hecs::Entity) -> Self {
+        if let Ok(sprite) = world.get::<&SpriteComponent>(entity) {
+            return Self {
+                size: sprite.size(),
+                origin: Vector2::new(0.5, 0.5),
+            };
+        }
+        if let Ok(text) = world.get::<&TextComponent>(entity) {
+            return Self {
+                size: text.layout().map_or_else(Vector2::zeros, TextLayout::size),
+                origin: Vector2::zeros(),
+            };
+        }
+        Self {
+            size: Vector2::new(1.0, 1.0),
+            origin: Vector2::new(0.5, 0.5),
+        }
+    }
+
+    /// 大きさ `size` の矩形の中心から、エンティティの位置までのずれ
+    fn center_to_origin(&self, size: Vector2<f32>) -> Vector2<f32> {
+        (self.origin - Vector2::new(0.5, 0.5)).component_mul(&size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Vector2<f32> = Vector2::new(800.0, 600.0);
+    const SIZE: Vector2<f32> = Vector2::new(100.0, 20.0);
+
+    #[test]
+    fn top_left_aligns_top_left_corner() {
+        let anchor = AnchorComponent::new(AnchorPoint::TopLeft, Vector2::new(10.0, 10.0));
+        assert_eq!(anchor.position(WINDOW, SIZE), Vector2::new(60.0, 20.0));
+    }
+
+    #[test]
+    fn bottom_right_aligns_bottom_right_corner() {
+        use nalgebra::{Scale3, Translation3};
+
+        let anchor = AnchorComponent::new(AnchorPoint::BottomRight, Vector2::new(-10.0, -10.0));
+        assert_eq!(anchor.position(WINDOW, SIZE), Vector2::new(740.0, 580.0));
+
+        // 描かれる大きさは拡大率とスプライトの大きさの積 (100x20)
+        let mut world = hecs::World::new();
+        let sprite = world.spawn((
+            TransformComponent::with_translation_and_scale(
+                Translation3::identity(),
+                Scale3::new(2.0, 4.0, 1.0),
+            ),
+            SpriteComponent::builder(crate::texture::TextureId::WHITE)
+                .size(50.0, 5.0)
+                .build(),
+            anchor,
+        ));
+        AnchorSystem.apply(&mut world, WINDOW);
+        let transform = world.get::<&TransformComponent>(sprite).unwrap();
+        assert_eq!(
+            transform.translation.vector.xy(),
+            Vector2::new(740.0, 580.0)
+        );
+    }
+
+    #[test]
+    fn text_anchors_by_layout_size() {
+        use nalgebra::{Scale3, Translation3};
+
+        use crate::{scene::insert_resource, text::TextStyle};
+
+        let mut world = hecs::World::new();
+        insert_resource(&mut world, Fonts::new());
+        let text = world.spawn((
+            TransformComponent::with_translation_and_scale(
+                Translation3::identity(),
+                Scale3::new(2.0, 2.0, 1.0),
+            ),
+            TextComponent::new("Score", TextStyle::debug(9.0)),
+            AnchorComponent::new(AnchorPoint::BottomRight, Vector2::new(-10.0, -10.0)),
+        ));
+        AnchorSystem.apply(&mut world, WINDOW);
+
+        // 文字列は左上が位置なので、右下の角が基準点に来るように大きさの分だけ戻す
+        let layout = world
+            .get::<&TextComponent>(text)
+            .unwrap()
+            .layout()
+            .unwrap()
+            .size();
+        assert!(layout.x > 0.0 && layout.y > 0.0);
+        let transform = world.get::<&TransformComponent>(text).unwrap();
+        assert_eq!(
+            transform.translation.vector.xy() + layout * 2.0,
+            Vector2::new(790.0, 590.0)
+        );
+    }
+
+    #[test]
+    fn center_follows_resize() {
+        let anchor = AnchorComponent::new(AnchorPoint::Center, Vector2::zeros());
+        assert_eq!(anchor.position(WINDOW, SIZE), Vector2::new(400.0, 300.0));
+        assert_eq!(
+            anchor.position(Vector2::new(1024.0, 768.0), SIZE),
+            Vector2::new(512.0, 384.0)
+        );
+    }
+
+    #[test]
+    fn child_anchors_to_parent_rect() {
+        use nalgebra::{Scale3, Translation3};
+
+        use crate::scene::{propagate_transforms, set_parent, EntityIndex};
+
+        let mut world = hecs::World::new();
+        let panel = world.spawn((
+            TransformComponent::with_translation_and_scale(
+                Translation3::identity(),
+                Scale3::new(200.0, 100.0, 1.0),
+            ),
+            AnchorComponent::new(AnchorPoint::Center, Vector2::zeros()),
+        ));
+        let button = world.spawn((
+            TransformComponent::with_translation_and_scale(
+                Translation3::identity(),
+                Scale3::new(20.0, 20.0, 1.0),
+            ),
+            AnchorComponent::new(AnchorPoint::TopLeft, Vector2::zeros()),
+        ));
+        set_parent(&mut world, EntityIndex(button), EntityIndex(panel)).unwrap();
+
+        AnchorSystem.apply(&mut world, WINDOW);
+        propagate_transforms(&mut world);
+        // パネルの左上は (300, 250)
+        let transform = world.get::<&TransformComponent>(button).unwrap();
+        assert_eq!(
+            transform.translation.vector.xy(),
+            Vector2::new(310.0, 260.0)
+        );
+        assert_eq!(transform.scale.vector.xy(), Vector2::new(20.0, 20.0));
+    }
+
+    #[test]
+    fn ui_scale_shrinks_layout_size() {
+        use crate::scene::insert_resource;
+
+        let mut world = hecs::World::new();
+        assert_eq!(ui_layout_size(&world, WINDOW), WINDOW);
+
+        let mut settings = Settings::default();
+        settings.accessibility.ui_scale = 2.0;
+        insert_resource(&mut world, settings);
+        let layout = ui_layout_size(&world, WINDOW);
+        assert_eq!(layout, Vector2::new(400.0, 300.0));
+
+        // 右下に揃えたものは、2 倍に拡大するとウィンドウの右下に来る
+        let anchor = AnchorComponent::new(AnchorPoint::BottomRight, Vector2::zeros());
+        assert_eq!(
+            anchor.position(layout, SIZE) * ui_scale(&world) + SIZE,
+            WINDOW
+        );
+    }
+
+    #[test]
+    fn normalized_matches_named_point() {
+        assert_eq!(
+            AnchorPoint::Normalized(1.0, 0.5).to_normalized(),
+            AnchorPoint::Right.to_normalized()
+        );
+    }
+}
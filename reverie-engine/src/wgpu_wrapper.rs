@@ -2,81 +2,290 @@
 use std::{borrow::Cow, num::NonZeroU32};
 
 use anyhow::Context;
-use nalgebra::{Matrix4, Scale3, Translation3};
+use tracing_unwrap::OptionExt;
 use wgpu::{self as w, util::DeviceExt};
 
+pub(crate) use crate::scene::get_matrix_pixel_to_render_coordinate;
 use crate::{
     scene::Scene,
-    texture::{TextureId, TextureRegistry},
+    texture::{TextureFilter, TextureId, TextureRegistry, TextureSettings},
 };
 
+use color_filter::ColorFilter;
+use debug_draw::DebugDraw;
+use globals::GlobalUniforms;
+use material::ShaderReflections;
+use memory::{GpuMemoryCategory, GpuMemoryReport, GpuMemoryTracker, TrackedAllocation};
+use render_graph::RenderPassDesc;
+use render_target::{RenderTargetDesc, RenderTargetId, RenderTargets, SizePolicy};
 use texture::WgpuTexture;
+use upload_ring::{FrameTracker, DEFAULT_UPLOAD_RING_DEPTH};
 use vertex::UvVertex;
 
+pub use frame::RenderFrame;
+pub use frame_graph::{FrameGraph, FrameGraphError, GraphTarget};
+pub use pipeline_cache::PipelineCache;
+
 pub(crate) mod buffer;
+pub mod color_filter;
+pub mod debug_draw;
+pub mod frame;
+pub mod frame_graph;
+pub mod globals;
+pub mod material;
+pub mod memory;
+pub mod offscreen;
+mod pipeline_cache;
+pub mod render_graph;
+pub mod render_target;
 pub(crate) mod texture;
-pub(crate) mod vertex;
+pub mod upload_ring;
+pub mod vertex;
+
+#[derive(Debug, Clone)]
+/// GPU の初期化に関する設定
+pub struct GraphicsConfig {
+    /// 使うバックエンド
+    ///
+    /// `None` なら環境変数 `WGPU_BACKEND` (例: `vulkan`, `gl`) を見て、それも無ければすべてを試す。
+    pub backends: Option<w::Backends>,
+    /// ソフトウェアレンダラなどのフォールバックアダプタを使う。GPU の無い CI 向け
+    pub force_fallback_adapter: bool,
+    /// 使えれば有効にする機能
+    ///
+    /// 例えばタイムスタンプクエリなら [`w::Features::TIMESTAMP_QUERY`]、
+    /// テクスチャ配列なら [`w::Features::TEXTURE_BINDING_ARRAY`]。
+    /// アダプタが対応していないものは無視され、[`WgpuResource::missing_features`] で確認できる。
+    pub optional_features: w::Features,
+    /// 毎フレーム書き換えるバッファをいくつ持って順番に使うか
+    ///
+    /// GPU が前のフレームを描き終える前に次のフレームのデータを書き込めるようにする。
+    /// 足りないと [`FrameTracker::overruns`] が増える。
+    pub upload_ring_depth: usize,
+    /// テクスチャの品質の設定
+    ///
+    /// 実行中に変えるときは [`TextureRegistry::request_settings`] を使う。
+    pub texture: TextureSettings,
+    /// 使われないまま何フレーム経ったテクスチャとパイプラインを GPU から解放するか
+    ///
+    /// `None` なら解放しない。[`WgpuResource::unload_unused_after`] で実行中に変えられる。
+    pub unload_unused_after: Option<u32>,
+}
+
+impl Default for GraphicsConfig {
+    fn default() -> Self {
+        Self {
+            backends: None,
+            force_fallback_adapter: false,
+            optional_features: w::Features::empty(),
+            upload_ring_depth: DEFAULT_UPLOAD_RING_DEPTH,
+            texture: TextureSettings::default(),
+            unload_unused_after: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// 描画先のテクスチャのフォーマット
+///
+/// 自前のパイプラインはこれに合わせて作る。[`WgpuResource::target_formats`] で取得する。
+pub struct TargetFormats {
+    /// 色のフォーマット。surface のフォーマットと同じ
+    pub color: w::TextureFormat,
+    /// 深度・ステンシルのフォーマット。深度・ステンシルバッファが無ければ `None`
+    pub depth_stencil: Option<w::TextureFormat>,
+}
+
+impl TargetFormats {
+    /// 色のフォーマットが sRGB かどうか
+    ///
+    /// sRGB なら、シェーダーが出力した線形の色は書き込むときに sRGB に変換される。
+    /// そうでなければシェーダーの出力がそのまま書き込まれるので、必要ならシェーダーで変換する。
+    pub fn is_srgb(&self) -> bool {
+        self.color.is_srgb()
+    }
+}
 
 /// wgpu を使うためのリソースをまとめた構造体
 pub struct WgpuResource<'window> {
     pub transform_uniform_buffer: w::Buffer,
     pub texture_bind_group_layout: w::BindGroupLayout,
     pub texture_sampler: w::Sampler,
+    pub uniform_bind_group_layout: w::BindGroupLayout,
     pub uniform_bind_group: w::BindGroup,
+    /// [`crate::scene::ScreenSpaceComponent`] を描くときの、UI の倍率を掛けたピクセル座標の行列
+    ///
+    /// [`crate::scene::Scene::render`] が毎フレーム書き込む。
+    pub ui_uniform_buffer: w::Buffer,
+    pub ui_bind_group: w::BindGroup,
     pub render_pipeline: w::RenderPipeline,
-    pub surface: w::Surface<'window>,
+    /// ウィンドウの surface。[`Self::setup_headless`] で作ったときは `None`
+    pub surface: Option<w::Surface<'window>>,
     pub surface_config: w::SurfaceConfiguration,
+    /// ウィンドウの大きさに合わせて作り直す描画先
+    ///
+    /// [`Self::resize`] で大きさが変わったものを作り直す。
+    pub render_targets: RenderTargets,
+    /// 深度・ステンシルバッファ。surface と同じ大きさ
+    depth_stencil: RenderTargetId,
+    pub adapter: w::Adapter,
     pub device: w::Device,
     pub queue: w::Queue,
     pub texture_registry: TextureRegistry,
+    /// GPU のメモリ使用量の集計
+    pub gpu_memory: GpuMemoryTracker,
+    /// デバッグ用の線の描画
+    pub debug_draw: DebugDraw,
+    /// シーンの時計など、シェーダーで共有するユニフォーム
+    pub globals: GlobalUniforms,
+    /// 画面全体の色に行列を掛けるパス
+    pub color_filter: ColorFilter,
+    /// システムが作ったパイプラインの置き場
+    ///
+    /// [`Self::set_surface_format`] でフォーマットが変わると、中のパイプラインは次に使うときに作り直される。
+    pub pipeline_cache: PipelineCache,
+    /// [`material::Material`] のシェーダーから読み取ったユニフォームの構造体
+    pub shader_reflections: ShaderReflections,
+    /// 送ったフレームと GPU が処理し終えたフレームの数
+    pub frames: FrameTracker,
+    /// [`Self::collect_unused_assets`] が、使われないまま何フレーム経ったものを解放するか
+    pub unload_unused_after: Option<u32>,
+    /// 要求したが有効にできなかった機能
+    missing_features: w::Features,
 }
 
 impl<'window> WgpuResource<'window> {
     pub const TEXTURE_BINDING: u32 = 0;
     pub const SAMPLER_BINDING: u32 = 1;
+    pub const DEPTH_STENCIL_FORMAT: w::TextureFormat = w::TextureFormat::Depth24PlusStencil8;
+    /// [`Self::setup_headless`] で使う色のフォーマット
+    pub const HEADLESS_FORMAT: w::TextureFormat = w::TextureFormat::Rgba8UnormSrgb;
+    /// [`Self::maintain_textures`] が 1 フレームに GPU に送り直すテクスチャの数
+    pub const TEXTURE_RELOADS_PER_FRAME: usize = 4;
 
     /// 初期化する
     ///
     /// * `surface_target`: 描画対象の surface
     /// * `width`: surface の幅
     /// * `height`: surface の高さ
-    /// * `packed_image1`: テクスチャ
-    /// * `vertex_buffer_max_elements`: 頂点バッファの最大要素数
-    /// * `index_buffer_max_elements`: インデックスバッファの最大要素数
+    /// * `config`: GPU の初期化に関する設定
     pub async fn setup<S>(
         surface_target: S,
         width: NonZeroU32,
         height: NonZeroU32,
+        config: &GraphicsConfig,
     ) -> anyhow::Result<Self>
     where
         S: Into<w::SurfaceTarget<'window>> + Send,
     {
-        let (_instance, surface, surface_format, surface_config, _adapter, device, queue) =
+        let (_instance, surface, surface_config, adapter, device, queue) =
             setup_instance_surface_adapter_device_queue(
                 surface_target,
                 width.into(),
                 height.into(),
+                config,
             )
             .await?;
         tracing::trace!(
             ?surface,
-            ?surface_format,
             ?surface_config,
             ?device,
             ?queue,
             "setup_instance_surface_adapter_device_queue"
         );
 
+        Self::setup_with_device(
+            Some(surface),
+            surface_config,
+            adapter,
+            device,
+            queue,
+            config,
+        )
+    }
+
+    /// ウィンドウを使わずに初期化する
+    ///
+    /// 描画結果はテクスチャに書き出して使う。テストやスクリーンショットの撮影向け。
+    pub async fn setup_headless(
+        width: NonZeroU32,
+        height: NonZeroU32,
+        config: &GraphicsConfig,
+    ) -> anyhow::Result<Self> {
+        let instance = create_instance(config).await;
+        let adapter = instance
+            .request_adapter(&w::RequestAdapterOptions {
+                power_preference: w::PowerPreference::default(),
+                force_fallback_adapter: config.force_fallback_adapter,
+                compatible_surface: None,
+            })
+            .await
+            .context("fail: request adapter")?;
+        tracing::trace!(?adapter, "requested adapter");
+        let (device, queue) = request_device(&adapter, config).await?;
+
+        let surface_config = w::SurfaceConfiguration {
+            usage: w::TextureUsages::RENDER_ATTACHMENT | w::TextureUsages::COPY_SRC,
+            format: Self::HEADLESS_FORMAT,
+            width: width.get(),
+            height: height.get(),
+            present_mode: w::PresentMode::AutoVsync,
+            desired_maximum_frame_latency: 2,
+            alpha_mode: w::CompositeAlphaMode::Auto,
+            view_formats: vec![],
+        };
+        Self::setup_with_device(None, surface_config, adapter, device, queue, config)
+    }
+
+    fn setup_with_device(
+        surface: Option<w::Surface<'window>>,
+        surface_config: w::SurfaceConfiguration,
+        adapter: w::Adapter,
+        device: w::Device,
+        queue: w::Queue,
+        config: &GraphicsConfig,
+    ) -> anyhow::Result<Self> {
+        let surface_format = surface_config.format;
+        let width = NonZeroU32::new(surface_config.width).context("surface width is zero")?;
+        let height = NonZeroU32::new(surface_config.height).context("surface height is zero")?;
+
+        let missing_features = config.optional_features - device.features();
+        let info = adapter.get_info();
+        tracing::info!(
+            name = info.name,
+            backend = %info.backend,
+            device_type = ?info.device_type,
+            driver = info.driver,
+            driver_info = info.driver_info,
+            features = ?device.features(),
+            "GPU adapter"
+        );
+        if !missing_features.is_empty() {
+            tracing::warn!(
+                ?missing_features,
+                "some optional features are not supported"
+            );
+        }
+
         let shader = setup_shader(&device)?;
         tracing::trace!(?shader, "setup_shader");
 
         let transform_uniform_buffer = setup_uniform_buffer(&device, width, height)?;
 
-        let sampler = setup_sampler(&device)?;
+        let sampler = setup_sampler(&device, &config.texture);
         tracing::trace!(?sampler, "setup_sampler");
 
         let (uniform_bind_group_layout, uniform_bind_group) =
             setup_uniform_bind_group(&transform_uniform_buffer, &device)?;
+        let ui_uniform_buffer = setup_uniform_buffer(&device, width, height)?;
+        let ui_bind_group = device.create_bind_group(&w::BindGroupDescriptor {
+            label: Some("UI Bind Group"),
+            layout: &uniform_bind_group_layout,
+            entries: &[w::BindGroupEntry {
+                binding: 0,
+                resource: ui_uniform_buffer.as_entire_binding(),
+            }],
+        });
         tracing::trace!(
             ?uniform_bind_group_layout,
             ?uniform_bind_group,
@@ -102,27 +311,167 @@ impl<'window> WgpuResource<'window> {
         )?;
         tracing::trace!(?render_pipeline, "setup_render_pipeline");
 
-        let texture_registry = TextureRegistry::default();
+        let gpu_memory = GpuMemoryTracker::default();
+        let mut render_targets = RenderTargets::new(width, height, gpu_memory.clone());
+        let depth_stencil = render_targets.insert(
+            &device,
+            RenderTargetDesc::depth_stencil("Depth Stencil Texture", SizePolicy::WindowScale(1.0)),
+        )?;
+
+        let debug_draw = DebugDraw::new(
+            &device,
+            surface_format,
+            &texture_bind_group_layout,
+            &uniform_bind_group_layout,
+        );
+
+        let globals = GlobalUniforms::new(&device);
+        let color_filter = ColorFilter::new(&device);
+
+        let texture_registry =
+            TextureRegistry::with_memory_tracker(gpu_memory.clone()).with_settings(config.texture);
         tracing::trace!(?texture_registry, "setup_texture_registry");
 
         Ok(Self {
             transform_uniform_buffer,
             texture_bind_group_layout,
             texture_sampler: sampler,
+            uniform_bind_group_layout,
             uniform_bind_group,
+            ui_uniform_buffer,
+            ui_bind_group,
             render_pipeline,
             surface,
             surface_config,
+            render_targets,
+            depth_stencil,
+            pipeline_cache: PipelineCache::new(TargetFormats {
+                color: surface_format,
+                depth_stencil: Some(Self::DEPTH_STENCIL_FORMAT),
+            }),
+            shader_reflections: ShaderReflections::default(),
+            frames: FrameTracker::new(config.upload_ring_depth),
+            unload_unused_after: config.unload_unused_after,
+            adapter,
             device,
             queue,
             texture_registry,
+            gpu_memory,
+            debug_draw,
+            globals,
+            color_filter,
+            missing_features,
         })
     }
 
+    /// アダプタ (GPU) の名前、バックエンド、種類、ドライバの情報
+    pub fn adapter_info(&self) -> w::AdapterInfo {
+        self.adapter.get_info()
+    }
+
+    /// アダプタが対応している機能
+    pub fn supported_features(&self) -> w::Features {
+        self.adapter.features()
+    }
+
+    /// デバイスで実際に有効になっている機能
+    pub fn enabled_features(&self) -> w::Features {
+        self.device.features()
+    }
+
+    /// [`GraphicsConfig::optional_features`] のうち、有効にできなかった機能
+    pub const fn missing_features(&self) -> w::Features {
+        self.missing_features
+    }
+
+    /// デバイスの制限
+    pub fn limits(&self) -> w::Limits {
+        self.device.limits()
+    }
+
+    /// 描画先のテクスチャのフォーマット
+    pub fn target_formats(&self) -> TargetFormats {
+        self.pipeline_cache.formats()
+    }
+
+    /// surface の色のフォーマットを変える
+    ///
+    /// 組み込みのパイプラインは作り直し、[`Self::pipeline_cache`] のパイプラインは次に使うときに作り直される。
+    /// surface が対応していないフォーマットならエラーになる。
+    pub fn set_surface_format(&mut self, format: w::TextureFormat) -> anyhow::Result<()> {
+        if let Some(surface) = &self.surface {
+            let formats = surface.get_capabilities(&self.adapter).formats;
+            anyhow::ensure!(
+                formats.contains(&format),
+                "surface does not support {format:?}; supported formats are {formats:?}"
+            );
+        }
+        if format == self.surface_config.format {
+            return Ok(());
+        }
+        tracing::info!(from = ?self.surface_config.format, to = ?format, "surface format changed");
+        self.surface_config.format = format;
+        if let Some(surface) = &self.surface {
+            surface.configure(&self.device, &self.surface_config);
+        }
+
+        self.render_pipeline = setup_render_pipeline(
+            &setup_shader(&self.device)?,
+            &[
+                &self.texture_bind_group_layout,
+                &self.uniform_bind_group_layout,
+            ],
+            format,
+            &self.device,
+        )?;
+        self.debug_draw = DebugDraw::new(
+            &self.device,
+            format,
+            &self.texture_bind_group_layout,
+            &self.uniform_bind_group_layout,
+        );
+        self.pipeline_cache.set_formats(TargetFormats {
+            color: format,
+            ..self.pipeline_cache.formats()
+        });
+        Ok(())
+    }
+
+    /// 画面の更新に合わせて表示するかを切り替える
+    ///
+    /// [`w::PresentMode::AutoVsync`] と [`w::PresentMode::AutoNoVsync`] はどの surface でも使える。
+    pub fn set_vsync(&mut self, vsync: bool) {
+        let present_mode = if vsync {
+            w::PresentMode::AutoVsync
+        } else {
+            w::PresentMode::AutoNoVsync
+        };
+        if present_mode == self.surface_config.present_mode {
+            return;
+        }
+        tracing::info!(?present_mode, "present mode changed");
+        self.surface_config.present_mode = present_mode;
+        if let Some(surface) = &self.surface {
+            surface.configure(&self.device, &self.surface_config);
+        }
+    }
+
+    pub const fn vsync(&self) -> bool {
+        !matches!(
+            self.surface_config.present_mode,
+            w::PresentMode::AutoNoVsync | w::PresentMode::Immediate
+        )
+    }
+
     pub fn resize(&mut self, width: NonZeroU32, height: NonZeroU32) {
         self.surface_config.width = width.get();
         self.surface_config.height = height.get();
-        self.surface.configure(&self.device, &self.surface_config);
+        if let Some(surface) = &self.surface {
+            surface.configure(&self.device, &self.surface_config);
+        }
+        if let Err(e) = self.render_targets.resize(&self.device, width, height) {
+            tracing::error!("failed: resize render targets: {e:#}");
+        }
 
         let matrix = get_matrix_pixel_to_render_coordinate(width, height);
         self.queue.write_buffer(
@@ -132,54 +481,258 @@ impl<'window> WgpuResource<'window> {
         );
     }
 
+    /// 深度・ステンシルバッファ。surface と同じ大きさ
+    pub fn depth_stencil_view(&self) -> &w::TextureView {
+        self.render_targets
+            .view(self.depth_stencil)
+            .expect_or_log("depth stencil target is never removed")
+    }
+
+    /// テクスチャの品質の設定を変える
+    ///
+    /// サンプラーとテクスチャのバインドグループはすぐに作り直す。[`TextureSettings::max_size`] で
+    /// 大きさが変わるテクスチャは [`Self::maintain_textures`] が数フレームに分けて GPU に送り直す。
+    /// 文字のアトラスは次に作り直すまで前のサンプラーを使う。
+    pub fn set_texture_settings(&mut self, settings: TextureSettings) {
+        tracing::info!(?settings, "texture settings changed");
+        self.texture_sampler = setup_sampler(&self.device, &settings);
+        self.texture_registry.apply_settings(
+            settings,
+            &self.device,
+            &self.queue,
+            &self.texture_bind_group_layout,
+            &self.texture_sampler,
+        );
+    }
+
+    /// フレームの始めに呼び、テクスチャの設定の変更と読み込みを反映する
+    ///
+    /// [`TextureRegistry::request_settings`] で頼まれた設定があれば [`Self::set_texture_settings`] で反映し、
+    /// 送り直しを待つテクスチャを [`Self::TEXTURE_RELOADS_PER_FRAME`] 個まで GPU に送り直す。
+    /// [`TextureRegistry::load_texture`] でデコードが終わったテクスチャも GPU に送る。
+    pub fn maintain_textures(&mut self) {
+        if let Some(settings) = self.texture_registry.take_requested_settings() {
+            self.set_texture_settings(settings);
+        }
+        self.texture_registry.reload_pending(
+            Self::TEXTURE_RELOADS_PER_FRAME,
+            &self.device,
+            &self.queue,
+            &self.texture_bind_group_layout,
+            &self.texture_sampler,
+        );
+        self.texture_registry.poll_loads();
+        self.texture_registry.upload_loaded(
+            &self.device,
+            &self.queue,
+            &self.texture_bind_group_layout,
+            &self.texture_sampler,
+        );
+    }
+
+    /// シーンが使っていないテクスチャとパイプラインを GPU から解放する
+    ///
+    /// 毎フレーム、[`Scene::update`] の後、描画の前に呼ぶ。
+    /// スプライト、タイルマップ、[`crate::text::Fonts`] のアイコンが指すテクスチャを使われているものとし、
+    /// [`Self::unload_unused_after`] フレームの間使われなかったものを解放する。
+    /// [`TextureRegistry::pin`] したテクスチャは解放しない。シーンの外 (自前の [`crate::scene::System::render`] など)
+    /// でしか使わないテクスチャは pin しておく。
+    ///
+    /// 解放したテクスチャも CPU 上の画像は残すので、また使われたらこの呼び出しの中で送り直す。
+    /// 解放したテクスチャとパイプラインの数を返す。
+    pub fn collect_unused_assets(&mut self, scene: &Scene) -> usize {
+        let Some(unload_after) = self.unload_unused_after else {
+            return 0;
+        };
+        scene.mark_used_textures(&mut self.texture_registry);
+        let textures = self.texture_registry.collect_unused(
+            unload_after,
+            &self.device,
+            &self.queue,
+            &self.texture_bind_group_layout,
+            &self.texture_sampler,
+        );
+        textures + self.pipeline_cache.evict_unused(unload_after)
+    }
+
+    /// GPU のメモリ使用量の合計と、大きい順に`top_n`個のリソース
+    ///
+    /// 毎フレーム呼んでオーバーレイに表示しても良い程度に軽い。
+    pub fn gpu_memory_report(&self, top_n: usize) -> GpuMemoryReport {
+        self.gpu_memory.report(top_n)
+    }
+
+    /// スプライトと同じ頂点とバインドグループを使うパイプラインを作る
+    ///
+    /// `shader` は [`UvVertex`] を受け取る `vs_main` と `fs_main` を持つ必要がある。
+    /// `None` ならスプライトのシェーダーを使う。描画先は [`Self::target_formats`] に合わせる。
+    pub fn create_sprite_pipeline(
+        &self,
+        shader: Option<&w::ShaderModule>,
+    ) -> anyhow::Result<w::RenderPipeline> {
+        let default_shader;
+        let shader = match shader {
+            Some(shader) => shader,
+            None => {
+                default_shader = setup_shader(&self.device)?;
+                &default_shader
+            }
+        };
+        setup_render_pipeline(
+            shader,
+            &[
+                &self.texture_bind_group_layout,
+                &self.uniform_bind_group_layout,
+            ],
+            self.surface_config.format,
+            &self.device,
+        )
+    }
+
+    /// テクスチャのバインドグループ
+    ///
+    /// [`TextureId::Builtin`] は最初に呼ばれたときに GPU に作る。
+    /// 取り除かれたテクスチャには [`TextureId::MISSING`] を使う。
     pub fn get_texture_bind_group(&self, texture: TextureId) -> anyhow::Result<&w::BindGroup> {
+        let texture = self.texture_registry.resolve(texture);
+        if let TextureId::Builtin(builtin) = texture {
+            return Ok(self.texture_registry.builtin_bind_group(builtin, self));
+        }
         self.texture_registry.get_bind_group(texture)
     }
 
+    /// シーンの [`super::scene::Scene::render_graph`] に従ってウィンドウに描画する
     pub fn render(&self, scene: &mut Scene) {
-        if let Ok(surface_texture) = self.surface.get_current_texture() {
+        self.render_with(scene, |frame, scene| frame.render_scene(scene));
+    }
+
+    /// ウィンドウのサーフェスのテクスチャに `render` で描画し、表示する
+    ///
+    /// `render` に渡す [`RenderFrame`] でパスを自分で積める。
+    pub fn render_with(
+        &self,
+        scene: &mut Scene,
+        render: impl FnOnce(&mut RenderFrame<'_, '_>, &mut Scene),
+    ) {
+        let Some(surface) = &self.surface else {
+            tracing::warn!("no surface");
+            return;
+        };
+        if let Ok(surface_texture) = surface.get_current_texture() {
             let output = surface_texture
                 .texture
                 .create_view(&wgpu::TextureViewDescriptor::default());
-
-            let mut encoder = self
-                .device
-                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                    label: Some("Main CommandEncoder"),
-                });
-            {
-                let mut rp = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                    label: Some("SpriteComponent Render Pass"),
-                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                        view: &output,
-                        resolve_target: None,
-                        ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(wgpu::Color {
-                                r: 54.0 / 255.0,
-                                g: 77.0 / 255.0,
-                                b: 118.0 / 255.0,
-                                a: 1.0,
-                            }),
-                            store: wgpu::StoreOp::Store,
-                        },
-                    })],
-                    depth_stencil_attachment: None,
-                    timestamp_writes: None,
-                    occlusion_query_set: None,
-                });
-
-                rp.set_pipeline(&self.render_pipeline);
-                rp.set_bind_group(1, &self.uniform_bind_group, &[]);
-
-                scene.render(&mut rp, self);
-            }
-            self.queue.submit(Some(encoder.finish()));
-
+            self.render_to_attachments_with(
+                scene,
+                (&output, self.depth_stencil_view()),
+                self.surface_size(),
+                render,
+            );
             surface_texture.present();
         } else {
             tracing::warn!("no surface texture");
         }
     }
+
+    /// シーンの [`super::scene::Scene::render_graph`] に従って `target` に描画する
+    ///
+    /// `target` の大きさは [`Self::surface_config`] と同じでなければならない。
+    pub fn render_to_view(&self, scene: &mut Scene, target: &w::TextureView) {
+        self.render_to_attachments(
+            scene,
+            (target, self.depth_stencil_view()),
+            self.surface_size(),
+        );
+    }
+
+    /// [`Self::surface_config`] の大きさ
+    fn surface_size(&self) -> (NonZeroU32, NonZeroU32) {
+        (
+            NonZeroU32::new(self.surface_config.width).unwrap_or(NonZeroU32::MIN),
+            NonZeroU32::new(self.surface_config.height).unwrap_or(NonZeroU32::MIN),
+        )
+    }
+
+    /// [`Self::render_to_view`] と同じだが、深度・ステンシルバッファとその大きさを指定する
+    pub(crate) fn render_to_attachments(
+        &self,
+        scene: &mut Scene,
+        attachments: (&w::TextureView, &w::TextureView),
+        size: (NonZeroU32, NonZeroU32),
+    ) {
+        self.render_to_attachments_with(scene, attachments, size, |frame, scene| {
+            frame.render_scene(scene);
+        });
+    }
+
+    /// `size` の大きさの描画先と深度・ステンシルバッファに `render` で描画し、コマンドを送ってフレームを締める
+    pub(crate) fn render_to_attachments_with(
+        &self,
+        scene: &mut Scene,
+        (target, depth_stencil): (&w::TextureView, &w::TextureView),
+        size: (NonZeroU32, NonZeroU32),
+        render: impl FnOnce(&mut RenderFrame<'_, '_>, &mut Scene),
+    ) {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Main CommandEncoder"),
+            });
+        scene.begin_frame();
+        scene.flush_decals(self, &mut encoder);
+        render(
+            &mut RenderFrame {
+                resource: self,
+                target,
+                output: target,
+                depth_stencil,
+                size,
+                encoder: &mut encoder,
+            },
+            scene,
+        );
+        self.queue.submit(Some(encoder.finish()));
+        self.frames.end_frame(&self.device, &self.queue);
+        self.debug_draw.release_to(scene.frame_arena_mut());
+        scene.end_frame();
+        self.debug_draw.borrow_from(scene.frame_arena_mut());
+    }
+
+    /// [`RenderPassDesc`] に従ってレンダーパスを始める
+    ///
+    /// 深度・ステンシルバッファには [`Self::depth_stencil_view`] を使う。
+    pub fn begin_render_pass<'encoder>(
+        &self,
+        encoder: &'encoder mut w::CommandEncoder,
+        target: &w::TextureView,
+        desc: &RenderPassDesc,
+    ) -> w::RenderPass<'encoder> {
+        self.begin_render_pass_with_depth(encoder, target, self.depth_stencil_view(), desc)
+    }
+
+    fn begin_render_pass_with_depth<'encoder>(
+        &self,
+        encoder: &'encoder mut w::CommandEncoder,
+        target: &w::TextureView,
+        depth_stencil: &w::TextureView,
+        desc: &RenderPassDesc,
+    ) -> w::RenderPass<'encoder> {
+        encoder.begin_render_pass(&w::RenderPassDescriptor {
+            label: Some(desc.label.as_deref().unwrap_or("Scene Render Pass")),
+            color_attachments: &[Some(w::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: desc.color_ops(),
+            })],
+            depth_stencil_attachment: Some(w::RenderPassDepthStencilAttachment {
+                view: depth_stencil,
+                depth_ops: Some(desc.depth_ops()),
+                stencil_ops: Some(desc.stencil_ops()),
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        })
+    }
 }
 
 #[tracing::instrument(level = "trace", skip(surface_target))]
@@ -187,10 +740,10 @@ async fn setup_instance_surface_adapter_device_queue<'window, S>(
     surface_target: S,
     width: u32,
     height: u32,
+    config: &GraphicsConfig,
 ) -> anyhow::Result<(
     w::Instance,
     w::Surface<'window>,
-    w::TextureFormat,
     w::SurfaceConfiguration,
     w::Adapter,
     w::Device,
@@ -199,7 +752,7 @@ async fn setup_instance_surface_adapter_device_queue<'window, S>(
 where
     S: Into<w::SurfaceTarget<'window>> + Send,
 {
-    let instance = w::Instance::default();
+    let instance = create_instance(config).await;
 
     let surface = instance
         .create_surface(surface_target)
@@ -209,26 +762,14 @@ where
     let adapter = instance
         .request_adapter(&w::RequestAdapterOptions {
             power_preference: w::PowerPreference::default(),
-            force_fallback_adapter: false,
+            force_fallback_adapter: config.force_fallback_adapter,
             compatible_surface: Some(&surface),
         })
         .await
         .context("fail: request adapter")?;
     tracing::trace!(?adapter, "requested adapter");
 
-    let (device, queue) = adapter
-        .request_device(
-            &w::DeviceDescriptor {
-                label: Some("Main Device"),
-                required_features: w::Features::empty(),
-                required_limits: w::Limits::default(),
-                memory_hints: w::MemoryHints::default(),
-            },
-            None,
-        )
-        .await
-        .context("fail: request device")?;
-    tracing::trace!(?device, ?queue, "requested device and queue");
+    let (device, queue) = request_device(&adapter, config).await?;
 
     let surface_caps = surface.get_capabilities(&adapter);
     let surface_format = surface_caps
@@ -250,15 +791,50 @@ where
     };
     surface.configure(&device, &config);
 
-    Ok((
-        instance,
-        surface,
-        surface_format,
-        config,
-        adapter,
-        device,
-        queue,
-    ))
+    Ok((instance, surface, config, adapter, device, queue))
+}
+
+async fn create_instance(config: &GraphicsConfig) -> w::Instance {
+    let descriptor = w::InstanceDescriptor {
+        backends: config
+            .backends
+            .or_else(w::util::backend_bits_from_env)
+            .unwrap_or_default(),
+        ..Default::default()
+    };
+    // Web では WebGPU が使えなければ WebGL2 にフォールバックする
+    #[cfg(target_arch = "wasm32")]
+    let instance = w::util::new_instance_with_webgpu_detection(descriptor).await;
+    #[cfg(not(target_arch = "wasm32"))]
+    let instance = w::Instance::new(descriptor);
+    instance
+}
+
+async fn request_device(
+    adapter: &w::Adapter,
+    config: &GraphicsConfig,
+) -> anyhow::Result<(w::Device, w::Queue)> {
+    // WebGL2 では既定の制限を満たせないので、ダウンレベルの制限を使う
+    let required_limits = if cfg!(target_arch = "wasm32") {
+        w::Limits::downlevel_webgl2_defaults().using_resolution(adapter.limits())
+    } else {
+        w::Limits::default()
+    };
+
+    let (device, queue) = adapter
+        .request_device(
+            &w::DeviceDescriptor {
+                label: Some("Main Device"),
+                required_features: config.optional_features & adapter.features(),
+                required_limits,
+                memory_hints: w::MemoryHints::default(),
+            },
+            None,
+        )
+        .await
+        .context("fail: request device")?;
+    tracing::trace!(?device, ?queue, "requested device and queue");
+    Ok((device, queue))
 }
 
 fn setup_shader(device: &w::Device) -> anyhow::Result<w::ShaderModule> {
@@ -281,24 +857,65 @@ fn setup_uniform_buffer(
     }))
 }
 
-fn get_matrix_pixel_to_render_coordinate(width: NonZeroU32, height: NonZeroU32) -> Matrix4<f32> {
-    let width = width.get() as f32;
-    let height = height.get() as f32;
-    Translation3::from([-1.0, 1.0, 0.0]).to_homogeneous()
-        * Scale3::new(2.0 / width, -2.0 / height, 1.0).to_homogeneous()
+fn create_depth_stencil_view(
+    device: &w::Device,
+    width: NonZeroU32,
+    height: NonZeroU32,
+) -> w::TextureView {
+    let texture = device.create_texture(&w::TextureDescriptor {
+        label: Some("Depth Stencil Texture"),
+        size: w::Extent3d {
+            width: width.get(),
+            height: height.get(),
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: w::TextureDimension::D2,
+        format: WgpuResource::DEPTH_STENCIL_FORMAT,
+        usage: w::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    });
+    texture.create_view(&w::TextureViewDescriptor::default())
+}
+
+/// 深度・ステンシルバッファの大きさを登録する
+fn track_depth_stencil(
+    memory: &GpuMemoryTracker,
+    width: NonZeroU32,
+    height: NonZeroU32,
+) -> TrackedAllocation {
+    // Depth24PlusStencil8 は実装によらず 1 画素あたりおよそ 4 バイト
+    let bytes = u64::from(width.get()) * u64::from(height.get()) * 4;
+    memory.track(
+        GpuMemoryCategory::RenderTarget,
+        "Depth Stencil Texture",
+        bytes,
+    )
 }
 
-fn setup_sampler(device: &w::Device) -> anyhow::Result<w::Sampler> {
-    Ok(device.create_sampler(&w::SamplerDescriptor {
+fn setup_sampler(device: &w::Device, settings: &TextureSettings) -> w::Sampler {
+    let filter = match settings.filter {
+        TextureFilter::Nearest => w::FilterMode::Nearest,
+        TextureFilter::Linear => w::FilterMode::Linear,
+    };
+    // 異方性フィルタリングはすべての補間が線形でなければ使えない
+    let anisotropy_clamp = match settings.filter {
+        TextureFilter::Nearest => 1,
+        TextureFilter::Linear => settings.anisotropy.clamp(1, 16),
+    };
+    device.create_sampler(&w::SamplerDescriptor {
         label: Some("Main Texture Sampler"),
         address_mode_u: w::AddressMode::ClampToEdge,
         address_mode_v: w::AddressMode::ClampToEdge,
         address_mode_w: w::AddressMode::ClampToEdge,
-        mag_filter: w::FilterMode::Nearest,
-        min_filter: w::FilterMode::Nearest,
-        mipmap_filter: w::FilterMode::Nearest,
+        mag_filter: filter,
+        min_filter: filter,
+        mipmap_filter: filter,
+        lod_min_clamp: settings.min_mip_level.clamp(0.0, 32.0),
+        anisotropy_clamp,
         ..Default::default()
-    }))
+    })
 }
 
 fn setup_uniform_bind_group(
@@ -336,6 +953,70 @@ fn setup_render_pipeline(
     bind_group_layouts: &[&w::BindGroupLayout],
     surface_format: w::TextureFormat,
     device: &w::Device,
+) -> anyhow::Result<w::RenderPipeline> {
+    setup_render_pipeline_with_vertex(
+        shader,
+        bind_group_layouts,
+        &[UvVertex::desc()],
+        surface_format,
+        device,
+    )
+}
+
+/// スプライトの重ね方。描画先の色にシェーダーの出力を不透明度で重ねる
+pub(crate) const SPRITE_BLEND: w::BlendState = w::BlendState {
+    color: w::BlendComponent {
+        src_factor: w::BlendFactor::SrcAlpha,
+        dst_factor: w::BlendFactor::OneMinusSrcAlpha,
+        operation: w::BlendOperation::Add,
+    },
+    alpha: w::BlendComponent::OVER,
+};
+
+/// ステンシルが参照値と等しい画素だけに描く設定
+///
+/// 参照値は重なった [`crate::scene::MaskComponent`] の数にする。マスクが無ければ参照値もステンシルも 0 なので、すべて描く。
+const MASKED_STENCIL: w::StencilState = {
+    let face = w::StencilFaceState {
+        compare: w::CompareFunction::Equal,
+        fail_op: w::StencilOperation::Keep,
+        depth_fail_op: w::StencilOperation::Keep,
+        pass_op: w::StencilOperation::Keep,
+    };
+    w::StencilState {
+        front: face,
+        back: face,
+        read_mask: 0xff,
+        write_mask: 0,
+    }
+};
+
+/// [`UvVertex`] 以外の頂点を使うパイプラインを作る。重ね方などはスプライトと同じ
+pub(crate) fn setup_render_pipeline_with_vertex(
+    shader: &w::ShaderModule,
+    bind_group_layouts: &[&w::BindGroupLayout],
+    vertex_layouts: &[w::VertexBufferLayout<'_>],
+    surface_format: w::TextureFormat,
+    device: &w::Device,
+) -> anyhow::Result<w::RenderPipeline> {
+    setup_render_pipeline_with_blend(
+        shader,
+        bind_group_layouts,
+        vertex_layouts,
+        surface_format,
+        SPRITE_BLEND,
+        device,
+    )
+}
+
+/// 重ね方を `blend` にしたパイプラインを作る。それ以外はスプライトと同じ
+pub(crate) fn setup_render_pipeline_with_blend(
+    shader: &w::ShaderModule,
+    bind_group_layouts: &[&w::BindGroupLayout],
+    vertex_layouts: &[w::VertexBufferLayout<'_>],
+    surface_format: w::TextureFormat,
+    blend: w::BlendState,
+    device: &w::Device,
 ) -> anyhow::Result<w::RenderPipeline> {
     let render_pipeline_layout = device.create_pipeline_layout(&w::PipelineLayoutDescriptor {
         label: Some("Render Pipeline Layout"),
@@ -350,7 +1031,7 @@ fn setup_render_pipeline(
             module: shader,
             entry_point: Some("vs_main"),
             compilation_options: Default::default(),
-            buffers: &[UvVertex::desc()],
+            buffers: vertex_layouts,
         },
         fragment: Some(w::FragmentState {
             module: shader,
@@ -358,14 +1039,7 @@ fn setup_render_pipeline(
             compilation_options: Default::default(),
             targets: &[Some(w::ColorTargetState {
                 format: surface_format,
-                blend: Some(w::BlendState {
-                    color: w::BlendComponent {
-                        src_factor: w::BlendFactor::SrcAlpha,
-                        dst_factor: w::BlendFactor::OneMinusSrcAlpha,
-                        operation: w::BlendOperation::Add,
-                    },
-                    alpha: w::BlendComponent::OVER,
-                }),
+                blend: Some(blend),
                 write_mask: w::ColorWrites::ALL,
             })],
         }),
@@ -378,7 +1052,14 @@ fn setup_render_pipeline(
             polygon_mode: w::PolygonMode::Fill,
             conservative: false,
         },
-        depth_stencil: None,
+        // スプライトは描画した順に重ねるので、深度テストも書き込みもしない
+        depth_stencil: Some(w::DepthStencilState {
+            format: WgpuResource::DEPTH_STENCIL_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: w::CompareFunction::Always,
+            stencil: MASKED_STENCIL,
+            bias: w::DepthBiasState::default(),
+        }),
         multisample: w::MultisampleState::default(),
         multiview: None,
         cache: None,
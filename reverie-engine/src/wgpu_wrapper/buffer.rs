@@ -2,16 +2,31 @@ use std::ops::Range;
 
 use wgpu as w;
 
-use super::vertex::UvVertex;
+use super::{
+    memory::{GpuMemoryCategory, GpuMemoryTracker, TrackedAllocation},
+    upload_ring::{FrameTracker, UploadRing},
+    vertex::UvVertex,
+};
+
+#[derive(Debug)]
+struct BufferPair {
+    vertex_buffer: w::Buffer,
+    index_buffer: w::Buffer,
+}
 
 #[derive(Debug)]
 /// 頂点バッファとインデックスバッファをまとめた構造体
+///
+/// GPU 上のバッファは [`UploadRing`] で複数持ち、更新のたびに別のものに書き込む。
 pub struct VertexIndexBuffer {
-    pub(crate) vertex_buffer: w::Buffer,
+    ring: UploadRing<BufferPair>,
     vertex_array: Vec<UvVertex>,
-    pub(crate) index_buffer: w::Buffer,
     index_array: Vec<u16>,
     pub(crate) index_buffer_range: Range<u32>,
+    max_vertices: usize,
+    max_indices: usize,
+    label: Option<String>,
+    _memory: TrackedAllocation,
 }
 
 impl VertexIndexBuffer {
@@ -20,33 +35,105 @@ impl VertexIndexBuffer {
         max_vertices: usize,
         max_indices: usize,
         label: Option<&str>,
+        memory: &GpuMemoryTracker,
+        frames: &FrameTracker,
     ) -> anyhow::Result<Self> {
-        let name_v = label.map(|label| format!("{label} [vertex part]"));
-        let name_i = label.map(|label| format!("{label} [index part]"));
-
-        let vertex_buffer = device.create_buffer(&w::BufferDescriptor {
-            label: name_v.as_deref(),
-            usage: w::BufferUsages::VERTEX | w::BufferUsages::COPY_DST,
-            size: (max_vertices * size_of::<UvVertex>()) as u64,
-            mapped_at_creation: false,
-        });
-        let index_buffer = device.create_buffer(&w::BufferDescriptor {
-            label: name_i.as_deref(),
-            usage: w::BufferUsages::INDEX | w::BufferUsages::COPY_DST,
-            size: (max_indices * size_of::<u16>()) as u64,
-            mapped_at_creation: false,
-        });
-
+        let (ring, tracked) =
+            Self::create_ring(device, max_vertices, max_indices, label, memory, frames);
         Ok(Self {
-            vertex_buffer,
+            ring,
             vertex_array: Vec::with_capacity(max_vertices),
-            index_buffer,
             index_array: Vec::with_capacity(max_indices),
             index_buffer_range: 0..0,
+            max_vertices,
+            max_indices,
+            label: label.map(str::to_string),
+            _memory: tracked,
         })
     }
 
-    pub fn start_update<'a>(&'a mut self, queue: &'a w::Queue) -> VertexIndexBufferUpdater<'a> {
+    fn create_ring(
+        device: &w::Device,
+        max_vertices: usize,
+        max_indices: usize,
+        label: Option<&str>,
+        memory: &GpuMemoryTracker,
+        frames: &FrameTracker,
+    ) -> (UploadRing<BufferPair>, TrackedAllocation) {
+        let name_v = label.map(|label| format!("{label} [vertex part]"));
+        let name_i = label.map(|label| format!("{label} [index part]"));
+
+        let ring = UploadRing::new(frames, |_| BufferPair {
+            vertex_buffer: device.create_buffer(&w::BufferDescriptor {
+                label: name_v.as_deref(),
+                usage: w::BufferUsages::VERTEX | w::BufferUsages::COPY_DST,
+                size: (max_vertices * size_of::<UvVertex>()) as u64,
+                mapped_at_creation: false,
+            }),
+            index_buffer: device.create_buffer(&w::BufferDescriptor {
+                label: name_i.as_deref(),
+                usage: w::BufferUsages::INDEX | w::BufferUsages::COPY_DST,
+                size: (max_indices * size_of::<u16>()) as u64,
+                mapped_at_creation: false,
+            }),
+        });
+
+        let pair = ring.current();
+        let tracked = memory.track(
+            GpuMemoryCategory::SpriteBuffer,
+            label.unwrap_or("Unnamed Vertex Index Buffer"),
+            (pair.vertex_buffer.size() + pair.index_buffer.size()) * ring.len() as u64,
+        );
+        (ring, tracked)
+    }
+
+    /// 最後に更新した頂点バッファ
+    pub(crate) fn vertex_buffer(&self) -> &w::Buffer {
+        &self.ring.current().vertex_buffer
+    }
+
+    /// 最後に更新したインデックスバッファ
+    pub(crate) fn index_buffer(&self) -> &w::Buffer {
+        &self.ring.current().index_buffer
+    }
+
+    /// 頂点を`vertices`個、インデックスを`indices`個書き込めるようにする
+    ///
+    /// 足りなければ今の倍以上の大きさでバッファを作り直す。
+    pub fn reserve(
+        &mut self,
+        device: &w::Device,
+        memory: &GpuMemoryTracker,
+        frames: &FrameTracker,
+        vertices: usize,
+        indices: usize,
+    ) {
+        if vertices <= self.max_vertices && indices <= self.max_indices {
+            return;
+        }
+        let max_vertices = vertices.max(self.max_vertices * 2);
+        let max_indices = indices.max(self.max_indices * 2);
+        let (ring, tracked) = Self::create_ring(
+            device,
+            max_vertices,
+            max_indices,
+            self.label.as_deref(),
+            memory,
+            frames,
+        );
+        self.ring = ring;
+        self._memory = tracked;
+        self.max_vertices = max_vertices;
+        self.max_indices = max_indices;
+    }
+
+    /// 次のバッファに切り替えて更新を始める
+    pub fn start_update<'a>(
+        &'a mut self,
+        queue: &'a w::Queue,
+        frames: &FrameTracker,
+    ) -> VertexIndexBufferUpdater<'a> {
+        self.ring.advance(frames);
         VertexIndexBufferUpdater {
             buffer: self,
             queue,
@@ -63,14 +150,14 @@ impl VertexIndexBuffer {
     ) {
         if !vertex_update.is_empty() {
             queue.write_buffer(
-                &self.vertex_buffer,
+                self.vertex_buffer(),
                 vertex_update.start as u64,
                 bytemuck::cast_slice(&self.vertex_array[vertex_update]),
             );
         }
         if !index_update.is_empty() {
             queue.write_buffer(
-                &self.index_buffer,
+                self.index_buffer(),
                 index_update.start as u64,
                 bytemuck::cast_slice::<u16, u8>(&self.index_array[index_update]),
             );
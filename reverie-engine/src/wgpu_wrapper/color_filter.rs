@@ -0,0 +1,234 @@
+//! 描画し終えた画面全体の色に行列を掛けるパス
+//!
+//! [`crate::settings::AccessibilitySettings`] の色覚に合わせた変換に使う。
+//! シーンを中間のテクスチャに描いてから、行列を掛けて本来の描画先に書き込む。
+//! [`super::FrameGraph::from_scene`] はこれをノードとして足し、
+//! [`super::RenderFrame::render_with_color_filter`] は自分で組み立てた描画の前後に挟む。
+use std::{cell::RefCell, num::NonZeroU32, rc::Rc};
+
+use nalgebra::Matrix3;
+use wgpu as w;
+
+use super::{
+    memory::{GpuMemoryCategory, TrackedAllocation},
+    WgpuResource,
+};
+
+/// [`ColorFilter`] が描画の途中の画面を置くテクスチャ
+#[derive(Debug)]
+pub(crate) struct FilterTarget {
+    pub(crate) view: w::TextureView,
+    bind_group: w::BindGroup,
+    size: (NonZeroU32, NonZeroU32),
+    format: w::TextureFormat,
+    _memory: TrackedAllocation,
+}
+
+/// 画面全体の色に 3x3 の行列を掛けるパスのリソース
+///
+/// WGSL では行列の列を `var<uniform> columns: array<vec4<f32>, 3>` として読む。
+/// 中間のテクスチャは最初に使うときに作り、描画先の大きさか形式が変わったら作り直す。
+#[derive(Debug)]
+pub struct ColorFilter {
+    buffer: w::Buffer,
+    bind_group_layout: w::BindGroupLayout,
+    target: RefCell<Option<Rc<FilterTarget>>>,
+}
+
+impl ColorFilter {
+    pub(crate) fn new(device: &w::Device) -> Self {
+        let buffer = device.create_buffer(&w::BufferDescriptor {
+            label: Some("Color Filter Uniform Buffer"),
+            size: size_of::<[[f32; 4]; 3]>() as u64,
+            usage: w::BufferUsages::UNIFORM | w::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&w::BindGroupLayoutDescriptor {
+            label: Some("Color Filter Bind Group Layout"),
+            entries: &[
+                w::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: w::ShaderStages::FRAGMENT,
+                    ty: w::BindingType::Texture {
+                        sample_type: w::TextureSampleType::Float { filterable: false },
+                        view_dimension: w::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                w::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: w::ShaderStages::FRAGMENT,
+                    ty: w::BindingType::Buffer {
+                        ty: w::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        Self {
+            buffer,
+            bind_group_layout,
+            target: RefCell::new(None),
+        }
+    }
+
+    /// `size` の大きさの中間のテクスチャ。形式は [`WgpuResource::surface_config`] と同じ
+    pub(crate) fn target(
+        &self,
+        resource: &WgpuResource<'_>,
+        size: (NonZeroU32, NonZeroU32),
+    ) -> Rc<FilterTarget> {
+        let format = resource.surface_config.format;
+        let mut target = self.target.borrow_mut();
+        if let Some(target) = target
+            .as_ref()
+            .filter(|target| target.size == size && target.format == format)
+        {
+            return Rc::clone(target);
+        }
+        let (width, height) = size;
+        let texture = resource.device.create_texture(&w::TextureDescriptor {
+            label: Some("Color Filter Source"),
+            size: w::Extent3d {
+                width: width.get(),
+                height: height.get(),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: w::TextureDimension::D2,
+            format,
+            usage: w::TextureUsages::RENDER_ATTACHMENT | w::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&w::TextureViewDescriptor::default());
+        let bind_group = resource.device.create_bind_group(&w::BindGroupDescriptor {
+            label: Some("Color Filter Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                w::BindGroupEntry {
+                    binding: 0,
+                    resource: w::BindingResource::TextureView(&view),
+                },
+                w::BindGroupEntry {
+                    binding: 1,
+                    resource: self.buffer.as_entire_binding(),
+                },
+            ],
+        });
+        let bytes = u64::from(width.get())
+            * u64::from(height.get())
+            * u64::from(format.block_copy_size(None).unwrap_or(4));
+        let memory = resource.gpu_memory.track(
+            GpuMemoryCategory::RenderTarget,
+            "Color Filter Source",
+            bytes,
+        );
+        let created = Rc::new(FilterTarget {
+            view,
+            bind_group,
+            size,
+            format,
+            _memory: memory,
+        });
+        *target = Some(Rc::clone(&created));
+        created
+    }
+
+    /// `render` で `size` の大きさの中間のテクスチャに描き、`matrix` を掛けて `output` に書き込む
+    pub(crate) fn render(
+        &self,
+        resource: &WgpuResource<'_>,
+        encoder: &mut w::CommandEncoder,
+        (output, size): (&w::TextureView, (NonZeroU32, NonZeroU32)),
+        matrix: &Matrix3<f32>,
+        render: impl FnOnce(&w::TextureView, &mut w::CommandEncoder),
+    ) {
+        let source = self.target(resource, size);
+        render(&source.view, encoder);
+        self.apply(resource, encoder, (output, size), matrix);
+    }
+
+    /// `size` の大きさの中間のテクスチャに `matrix` を掛けて `output` に書き込む
+    pub(crate) fn apply(
+        &self,
+        resource: &WgpuResource<'_>,
+        encoder: &mut w::CommandEncoder,
+        (output, size): (&w::TextureView, (NonZeroU32, NonZeroU32)),
+        matrix: &Matrix3<f32>,
+    ) {
+        let source = self.target(resource, size);
+        let mut columns = [[0.0_f32; 4]; 3];
+        for (column, values) in matrix.column_iter().zip(&mut columns) {
+            values[..3].copy_from_slice(column.as_slice());
+        }
+        resource
+            .queue
+            .write_buffer(&self.buffer, 0, bytemuck::cast_slice(&columns));
+
+        let pipeline = resource
+            .pipeline_cache
+            .get_or_create("reverie color filter", |formats| {
+                self.pipeline(resource, formats.color)
+            });
+        let mut rp = encoder.begin_render_pass(&w::RenderPassDescriptor {
+            label: Some("Color Filter Pass"),
+            color_attachments: &[Some(w::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: w::Operations {
+                    load: w::LoadOp::Clear(w::Color::BLACK),
+                    store: w::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        rp.set_pipeline(&pipeline);
+        rp.set_bind_group(0, &source.bind_group, &[]);
+        rp.draw(0..3, 0..1);
+    }
+
+    /// 画面を覆う三角形 1 つで中間のテクスチャを写すパイプラインを作る
+    fn pipeline(&self, resource: &WgpuResource<'_>, format: w::TextureFormat) -> w::RenderPipeline {
+        let device = &resource.device;
+        let shader = device.create_shader_module(w::ShaderModuleDescriptor {
+            label: Some("Shader from color_filter.wgsl"),
+            source: w::ShaderSource::Wgsl(include_str!("../color_filter.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&w::PipelineLayoutDescriptor {
+            label: Some("Color Filter Pipeline Layout"),
+            bind_group_layouts: &[&self.bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        device.create_render_pipeline(&w::RenderPipelineDescriptor {
+            label: Some("Color Filter Pipeline"),
+            layout: Some(&layout),
+            vertex: w::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            fragment: Some(w::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                compilation_options: Default::default(),
+                targets: &[Some(w::ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: w::ColorWrites::ALL,
+                })],
+            }),
+            primitive: w::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: w::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+}
@@ -0,0 +1,188 @@
+//! デバッグ用の線の描画
+use std::{borrow::Cow, cell::RefCell};
+
+use nalgebra::Point3;
+use reverie_util::color::Color;
+use tracing_unwrap::ResultExt;
+use wgpu::{self as w, util::DeviceExt};
+
+use super::{vertex::ColorVertex, WgpuResource};
+use crate::{scene::FrameArena, texture::TextureId};
+
+/// 円を近似する多角形の辺の数
+const CIRCLE_SEGMENTS: usize = 24;
+
+/// フレームごとに線を集めて、ワールドのスプライトの上に描画する
+///
+/// [`WgpuResource::debug_draw`] からシステムの中で使う。集めた線は描画後に消える。
+/// 頂点のバッファはシーンの [`FrameArena`] から借りるので、伸びた量はその集計に入る。
+pub struct DebugDraw {
+    vertices: RefCell<Vec<ColorVertex>>,
+    pipeline: w::RenderPipeline,
+}
+
+impl DebugDraw {
+    pub(crate) fn new(
+        device: &w::Device,
+        format: w::TextureFormat,
+        texture_bind_group_layout: &w::BindGroupLayout,
+        uniform_bind_group_layout: &w::BindGroupLayout,
+    ) -> Self {
+        let shader = device.create_shader_module(w::ShaderModuleDescriptor {
+            label: Some("Shader from debug.wgsl"),
+            source: w::ShaderSource::Wgsl(Cow::Borrowed(include_str!("../debug.wgsl"))),
+        });
+        // スプライトと同じレイアウトにしておけば、カメラのバインドグループを付け替えずに済む
+        let layout = device.create_pipeline_layout(&w::PipelineLayoutDescriptor {
+            label: Some("Debug Draw Pipeline Layout"),
+            bind_group_layouts: &[texture_bind_group_layout, uniform_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&w::RenderPipelineDescriptor {
+            label: Some("Debug Draw Pipeline"),
+            layout: Some(&layout),
+            vertex: w::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                compilation_options: Default::default(),
+                buffers: &[ColorVertex::desc()],
+            },
+            fragment: Some(w::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                compilation_options: Default::default(),
+                targets: &[Some(w::ColorTargetState {
+                    format,
+                    blend: Some(w::BlendState::ALPHA_BLENDING),
+                    write_mask: w::ColorWrites::ALL,
+                })],
+            }),
+            primitive: w::PrimitiveState {
+                topology: w::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            depth_stencil: Some(w::DepthStencilState {
+                format: WgpuResource::DEPTH_STENCIL_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: w::CompareFunction::Always,
+                stencil: w::StencilState::default(),
+                bias: w::DepthBiasState::default(),
+            }),
+            multisample: w::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            vertices: RefCell::new(Vec::new()),
+            pipeline,
+        }
+    }
+
+    /// 線分を描く
+    pub fn line(&self, from: Point3<f32>, to: Point3<f32>, color: Color) {
+        let color = color.into();
+        self.vertices.borrow_mut().extend([
+            ColorVertex {
+                position: from.into(),
+                color,
+            },
+            ColorVertex {
+                position: to.into(),
+                color,
+            },
+        ]);
+    }
+
+    /// 折れ線を描く。`closed` なら最後の点と最初の点もつなぐ
+    pub fn polyline(&self, points: &[Point3<f32>], closed: bool, color: Color) {
+        for pair in points.windows(2) {
+            self.line(pair[0], pair[1], color);
+        }
+        if let (true, [first, .., last]) = (closed, points) {
+            self.line(*last, *first, color);
+        }
+    }
+
+    /// XY 平面上の長方形の枠を描く
+    pub fn rect(&self, center: Point3<f32>, width: f32, height: f32, color: Color) {
+        let (w, h) = (width / 2.0, height / 2.0);
+        let corners = [(-w, -h), (w, -h), (w, h), (-w, h)]
+            .map(|(x, y)| Point3::new(center.x + x, center.y + y, center.z));
+        self.polyline(&corners, true, color);
+    }
+
+    /// XY 平面上の円を描く
+    pub fn circle(&self, center: Point3<f32>, radius: f32, color: Color) {
+        let points: [_; CIRCLE_SEGMENTS] = std::array::from_fn(|i| {
+            let angle = std::f32::consts::TAU * i as f32 / CIRCLE_SEGMENTS as f32;
+            Point3::new(
+                radius.mul_add(angle.cos(), center.x),
+                radius.mul_add(angle.sin(), center.y),
+                center.z,
+            )
+        });
+        self.polyline(&points, true, color);
+    }
+
+    /// 集めた線の頂点の数
+    pub fn vertex_count(&self) -> usize {
+        self.vertices.borrow().len()
+    }
+
+    /// 集めた線を消す。容量は次のフレームに持ち越す
+    pub fn clear(&self) {
+        self.vertices.borrow_mut().clear();
+    }
+
+    /// 集めた線を消し、頂点のバッファを `arena` に返す
+    ///
+    /// [`FrameArena`] の集計に入るように、フレームを締める前に返して、締めた後に [`Self::borrow_from`] で借り直す。
+    pub(crate) fn release_to(&self, arena: &mut FrameArena) {
+        arena.recycle(std::mem::take(&mut *self.vertices.borrow_mut()));
+    }
+
+    /// 次のフレームの線を集めるバッファを `arena` から借りる
+    pub(crate) fn borrow_from(&self, arena: &mut FrameArena) {
+        let mut vertices = self.vertices.borrow_mut();
+        // 返した後に集めた線は捨てない
+        if vertices.capacity() == 0 {
+            *vertices = arena.take();
+        }
+    }
+
+    /// 集めた線を描画する
+    ///
+    /// グループ 1 にはカメラのバインドグループが設定されている必要がある。
+    /// 描画後はスプライトのパイプラインに戻す。
+    pub(crate) fn render(&self, rp: &mut w::RenderPass<'_>, resource: &WgpuResource<'_>) {
+        let vertices = self.vertices.borrow();
+        if vertices.is_empty() {
+            return;
+        }
+        let buffer = resource
+            .device
+            .create_buffer_init(&w::util::BufferInitDescriptor {
+                label: Some("Debug Draw Vertex Buffer"),
+                contents: bytemuck::cast_slice(&vertices),
+                usage: w::BufferUsages::VERTEX,
+            });
+        // パイプラインのレイアウトをスプライトと揃えるために、白いテクスチャを設定しておく
+        let white = resource
+            .get_texture_bind_group(TextureId::WHITE)
+            .unwrap_or_log();
+        rp.set_pipeline(&self.pipeline);
+        rp.set_bind_group(0, white, &[]);
+        rp.set_vertex_buffer(0, buffer.slice(..));
+        rp.draw(0..vertices.len() as u32, 0..1);
+        rp.set_pipeline(&resource.render_pipeline);
+    }
+}
+
+impl std::fmt::Debug for DebugDraw {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DebugDraw")
+            .field("#vertices", &self.vertex_count())
+            .finish()
+    }
+}
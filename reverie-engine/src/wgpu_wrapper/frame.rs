@@ -0,0 +1,132 @@
+//! 1 フレームの描画を自分で組み立てるための入口
+use std::num::NonZeroU32;
+
+use wgpu as w;
+
+use super::{render_graph::RenderPassDesc, FrameGraph, WgpuResource};
+use crate::{
+    scene::{EntityIndex, Scene, SpriteBatches},
+    settings::Settings,
+};
+
+/// 1 フレームの描画先とコマンドエンコーダー
+///
+/// [`WgpuResource::render_with`] が作って渡す。エンジンの既定の描画は [`Self::render_scene`] だけで、
+/// [`crate::game::Game::render`] を実装すれば、その代わりに好きな順番でパスを積める。
+/// 組み込みのスプライトの描画は [`Self::prepare_sprites`] と [`Scene::draw_sprites`] で好きなパスの中から呼べる。
+/// パスの順番を読み書きする描画先から決めるときは [`FrameGraph`] を使う。
+/// コマンドの送信、画面への表示、フレームの後片付けは [`WgpuResource::render_with`] が行う。
+pub struct RenderFrame<'a, 'window> {
+    pub resource: &'a WgpuResource<'window>,
+    /// 描画先。ウィンドウに描くときはサーフェスのテクスチャ
+    ///
+    /// 色覚に合わせた変換を掛けるときは中間のテクスチャで、変換した結果は [`Self::output`] に書き込む。
+    pub target: &'a w::TextureView,
+    /// 最終的な描画先 ([`super::GraphTarget::Output`])。変換を掛けないときは [`Self::target`] と同じ
+    pub output: &'a w::TextureView,
+    pub depth_stencil: &'a w::TextureView,
+    /// [`Self::target`] と [`Self::depth_stencil`] の幅と高さ
+    pub size: (NonZeroU32, NonZeroU32),
+    pub encoder: &'a mut w::CommandEncoder,
+}
+
+impl std::fmt::Debug for RenderFrame<'_, '_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RenderFrame")
+            .field("target", &self.target)
+            .field("size", &self.size)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RenderFrame<'_, '_> {
+    /// [`Self::target`] と [`Self::depth_stencil`] に描くレンダーパスを [`RenderPassDesc`] に従って始める
+    ///
+    /// スプライトのパイプラインを設定してから返す。
+    pub fn begin_render_pass(&mut self, desc: &RenderPassDesc) -> w::RenderPass<'_> {
+        let mut rp = self.resource.begin_render_pass_with_depth(
+            self.encoder,
+            self.target,
+            self.depth_stencil,
+            desc,
+        );
+        rp.set_pipeline(&self.resource.render_pipeline);
+        rp
+    }
+
+    /// `camera` から見たスプライトを描画できるように準備する。`camera` が `None` ならピクセル座標で描く
+    ///
+    /// 返した [`SpriteBatches`] を [`Scene::draw_sprites`] に渡すと、好きなレンダーパスの中で描画できる。
+    pub fn prepare_sprites(
+        &self,
+        scene: &mut Scene,
+        camera: Option<EntityIndex>,
+    ) -> anyhow::Result<SpriteBatches> {
+        scene.prepare_sprites(camera, self.resource)
+    }
+
+    /// シーンの [`Scene::render_graph`] のパスを 1 つ描画する
+    pub fn render_scene_pass(&mut self, scene: &mut Scene, desc: &RenderPassDesc) {
+        let resource = self.resource;
+        let mut rp = self.begin_render_pass(desc);
+        scene.render_pass(desc, &mut rp, resource);
+    }
+
+    /// エンジンの既定の描画。シーンの [`Scene::render_graph`] のパスを順に描画する
+    ///
+    /// [`FrameGraph::from_scene`] を実行するのと同じで、色覚に合わせた変換もグラフのノードとして掛ける。
+    /// 描画先に描くノードを足すときは、[`FrameGraph::from_scene`] にノードを足してから [`FrameGraph::execute`] する。
+    pub fn render_scene(&mut self, scene: &mut Scene) {
+        if let Err(err) = FrameGraph::from_scene(scene).execute(self, scene) {
+            tracing::error!(%err, "failed: render scene");
+        }
+    }
+
+    /// `render` で描いた画面全体に、[`Settings`] のリソースで選んだ色覚に合わせた変換を掛ける
+    ///
+    /// [`FrameGraph`] を使わずにパスを積むときのためのもの。[`FrameGraph::from_scene`] は変換のノードを含むので、
+    /// `render` の中で実行すると変換を 2 回掛けてしまう。
+    ///
+    /// 変換するときは、`render` に渡す [`RenderFrame`] の [`Self::target`] が中間のテクスチャになり、
+    /// その後に [`super::color_filter::ColorFilter`] が本来の描画先に書き込む。
+    /// 変換しないときは `render` にこのフレームをそのまま渡す。
+    pub fn render_with_color_filter(
+        &mut self,
+        scene: &mut Scene,
+        render: impl FnOnce(&mut RenderFrame<'_, '_>, &mut Scene),
+    ) {
+        let matrix = scene
+            .resource::<Settings>()
+            .and_then(|settings| settings.accessibility.color_matrix());
+        let Some(matrix) = matrix else {
+            render(self, scene);
+            return;
+        };
+        let Self {
+            resource,
+            target,
+            depth_stencil,
+            size,
+            ..
+        } = *self;
+        resource.color_filter.render(
+            resource,
+            self.encoder,
+            (target, size),
+            &matrix,
+            |source, encoder| {
+                render(
+                    &mut RenderFrame {
+                        resource,
+                        target: source,
+                        output: source,
+                        depth_stencil,
+                        size,
+                        encoder,
+                    },
+                    scene,
+                );
+            },
+        );
+    }
+}
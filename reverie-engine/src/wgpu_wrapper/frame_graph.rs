@@ -0,0 +1,537 @@
+//! 1 フレームの描画の依存関係 (フレームグラフ)
+//!
+//! 描画の手順 (ノード) ごとに、読む描画先と書く描画先を宣言する。
+//! [`FrameGraph::execute`] は書くノードが読むノードより先になるように並べ替え、1 つのコマンドエンコーダーに順に記録する。
+//! テクスチャの使い方の切り替え (描画先からサンプルするテクスチャへ、など) のバリアは、
+//! 記録した順に従って wgpu が入れる。
+//!
+//! [`RenderFrame::render_scene`] はシーンの [`super::render_graph::RenderGraph`] のパスを、
+//! 宣言した順に描画先を書き足していくノードとして並べる。
+//! ミニマップのように描画先に描いてから使うものは、そのノードを後から足しても先に描かれる。
+//! 色覚に合わせた変換は、[`GraphTarget::FrameColor`] を読んで [`GraphTarget::Output`] に書くノードになる。
+use std::collections::HashMap;
+
+use wgpu as w;
+
+use super::{render_graph::RenderPassDesc, render_target::RenderTargetId, RenderFrame};
+use crate::{scene::Scene, settings::Settings};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// ノードが読み書きする描画先
+pub enum GraphTarget {
+    /// [`RenderFrame::target`]。ウィンドウに描くときはサーフェスのテクスチャ
+    ///
+    /// [`Self::Output`] を書くノードがあるときは、中間のテクスチャになる。
+    FrameColor,
+    /// [`RenderFrame::output`]。最終的な描画先
+    ///
+    /// これを書くノードがあるときだけ [`Self::FrameColor`] と別のテクスチャになる。
+    /// 書かれなければ [`Self::FrameColor`] がそのまま画面に出る。
+    Output,
+    /// [`RenderFrame::depth_stencil`]
+    FrameDepthStencil,
+    /// [`super::render_target::RenderTargets`] に登録した描画先
+    Target(RenderTargetId),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// ノードが描画先をどう使うか
+enum Access {
+    /// 前の内容を使う (テクスチャとして読む)
+    Read,
+    /// 前の内容を使わずに書く (クリアする)
+    Write,
+    /// 前の内容に書き足す
+    Modify,
+}
+
+type Run<'g> = Box<dyn FnOnce(&mut RenderFrame<'_, '_>, &mut Scene) + 'g>;
+
+/// [`FrameGraph`] の 1 つのノード
+///
+/// [`FrameGraph::add`] が返す。[`Self::reads`] などで描画先の使い方を宣言する。
+pub struct GraphNode<'g> {
+    name: String,
+    access: Vec<(GraphTarget, Access)>,
+    run: Run<'g>,
+}
+
+impl std::fmt::Debug for GraphNode<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GraphNode")
+            .field("name", &self.name)
+            .field("access", &self.access)
+            .finish_non_exhaustive()
+    }
+}
+
+impl GraphNode<'_> {
+    /// `target` をテクスチャとして読む。`target` を書くノードがすべて終わってから実行する
+    pub fn reads(&mut self, target: GraphTarget) -> &mut Self {
+        self.access.push((target, Access::Read));
+        self
+    }
+
+    /// `target` をクリアして書く。`target` を書くノードの中で最初に実行する
+    ///
+    /// 1 つの描画先をクリアするノードは 1 つまで。
+    pub fn writes(&mut self, target: GraphTarget) -> &mut Self {
+        self.access.push((target, Access::Write));
+        self
+    }
+
+    /// `target` の前の内容に書き足す
+    ///
+    /// 書き足すノードどうしは [`FrameGraph::add`] した順に実行する。
+    pub fn modifies(&mut self, target: GraphTarget) -> &mut Self {
+        self.access.push((target, Access::Modify));
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// フレームグラフを並べられない
+pub enum FrameGraphError {
+    /// 読み書きの依存関係が循環している
+    Cycle {
+        /// 循環に含まれるノードの名前
+        nodes: Vec<String>,
+    },
+    /// 1 つの描画先を 2 つのノードがクリアして書こうとしている
+    ///
+    /// どちらかの結果が消えてしまう。後のノードは [`GraphNode::modifies`] にするか、別の描画先に書く。
+    DoubleWrite {
+        target: GraphTarget,
+        first: String,
+        second: String,
+    },
+    /// 宣言した使い方に描画先が対応していない
+    InvalidUsage {
+        node: String,
+        target: GraphTarget,
+        /// 足りない [`w::TextureUsages`]
+        missing: w::TextureUsages,
+    },
+}
+
+impl std::fmt::Display for FrameGraphError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Cycle { nodes } => {
+                write!(f, "frame graph has a cycle: {}", nodes.join(", "))
+            }
+            Self::DoubleWrite {
+                target,
+                first,
+                second,
+            } => write!(
+                f,
+                "{target:?} is written by both {first:?} and {second:?}; use modifies() for the later one"
+            ),
+            Self::InvalidUsage {
+                node,
+                target,
+                missing,
+            } => write!(f, "{node:?} uses {target:?} which lacks {missing:?}"),
+        }
+    }
+}
+
+impl std::error::Error for FrameGraphError {}
+
+#[derive(Debug, Default)]
+/// 描画の手順とその依存関係の集まり
+///
+/// 1 フレームごとに作って [`Self::execute`] する。
+/// 依存関係に縛られないノードは [`Self::add`] した順に実行する。
+pub struct FrameGraph<'g> {
+    nodes: Vec<GraphNode<'g>>,
+}
+
+impl<'g> FrameGraph<'g> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// シーンの [`Scene::render_graph`] のパスを順に並べたグラフ
+    ///
+    /// どのパスも [`GraphTarget::FrameColor`] と [`GraphTarget::FrameDepthStencil`] に書き足すので、
+    /// パスの順番は変わらない。[`Self::add_color_filter`] の変換も足す。これがエンジンの既定の描画になる。
+    pub fn from_scene(scene: &Scene) -> Self {
+        let mut graph = Self::new();
+        graph.add_scene_passes(scene);
+        graph.add_color_filter(scene);
+        graph
+    }
+
+    /// [`Settings`] のリソースで色覚に合わせた変換を選んでいれば、それを掛けるノードを足す
+    ///
+    /// ノードは [`GraphTarget::FrameColor`] を読み、変換した結果を [`GraphTarget::Output`] に書く。
+    /// 変換しないときは何も足さずに `None` を返す。
+    pub fn add_color_filter(&mut self, scene: &Scene) -> Option<&mut GraphNode<'g>> {
+        let matrix = scene
+            .resource::<Settings>()
+            .and_then(|settings| settings.accessibility.color_matrix())?;
+        let node = self
+            .add("color filter", move |frame, _| {
+                let resource = frame.resource;
+                resource.color_filter.apply(
+                    resource,
+                    frame.encoder,
+                    (frame.output, frame.size),
+                    &matrix,
+                );
+            })
+            .reads(GraphTarget::FrameColor)
+            .writes(GraphTarget::Output);
+        Some(node)
+    }
+
+    /// シーンの [`Scene::render_graph`] のパスをノードとして足す
+    pub fn add_scene_passes(&mut self, scene: &Scene) {
+        for (i, desc) in scene.render_graph().passes.iter().enumerate() {
+            let name = desc
+                .label
+                .clone()
+                .unwrap_or_else(|| format!("scene pass {i}"));
+            self.add_scene_pass(name, desc.clone());
+        }
+    }
+
+    /// [`RenderFrame::render_scene_pass`] で `desc` のパスを描画するノードを足す
+    pub fn add_scene_pass(
+        &mut self,
+        name: impl Into<String>,
+        desc: RenderPassDesc,
+    ) -> &mut GraphNode<'g> {
+        self.add(name, move |frame, scene| {
+            frame.render_scene_pass(scene, &desc);
+        })
+        .modifies(GraphTarget::FrameColor)
+        .modifies(GraphTarget::FrameDepthStencil)
+    }
+
+    /// ノードを足す。返り値で読み書きする描画先を宣言する
+    pub fn add(
+        &mut self,
+        name: impl Into<String>,
+        run: impl FnOnce(&mut RenderFrame<'_, '_>, &mut Scene) + 'g,
+    ) -> &mut GraphNode<'g> {
+        self.nodes.push(GraphNode {
+            name: name.into(),
+            access: Vec::new(),
+            run: Box::new(run),
+        });
+        self.nodes.last_mut().expect("a node was pushed just now")
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = &GraphNode<'g>> {
+        self.nodes.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// 実行する順に並べたノードの添字
+    pub fn order(&self) -> Result<Vec<usize>, FrameGraphError> {
+        let mut deps = vec![Vec::new(); self.nodes.len()];
+        for (target, users) in self.users() {
+            // クリアするノード → 書き足すノード (足した順) → 読むノード
+            let mut writers = Vec::new();
+            let mut clearer: Option<usize> = None;
+            for &(i, access) in &users {
+                match access {
+                    Access::Write => {
+                        if let Some(first) = clearer {
+                            return Err(FrameGraphError::DoubleWrite {
+                                target,
+                                first: self.nodes[first].name.clone(),
+                                second: self.nodes[i].name.clone(),
+                            });
+                        }
+                        clearer = Some(i);
+                    }
+                    Access::Modify => writers.push(i),
+                    Access::Read => {}
+                }
+            }
+            let chain: Vec<usize> = clearer.into_iter().chain(writers).collect();
+            for pair in chain.windows(2) {
+                deps[pair[1]].push(pair[0]);
+            }
+            if let Some(&last) = chain.last() {
+                for &(i, access) in &users {
+                    if access == Access::Read && i != last {
+                        deps[i].push(last);
+                    }
+                }
+            }
+        }
+
+        let mut placed = vec![false; self.nodes.len()];
+        let mut order = Vec::with_capacity(self.nodes.len());
+        while order.len() < self.nodes.len() {
+            let next = (0..self.nodes.len())
+                .find(|&i| !placed[i] && deps[i].iter().all(|&dep| placed[dep]));
+            match next {
+                Some(i) => {
+                    placed[i] = true;
+                    order.push(i);
+                }
+                None => {
+                    let unplaced: Vec<usize> =
+                        (0..self.nodes.len()).filter(|&i| !placed[i]).collect();
+                    return Err(FrameGraphError::Cycle {
+                        nodes: first_cycle(&deps, &unplaced)
+                            .into_iter()
+                            .map(|i| self.nodes[i].name.clone())
+                            .collect(),
+                    });
+                }
+            }
+        }
+        Ok(order)
+    }
+
+    /// 描画先ごとに、使うノードと使い方を足した順に並べる
+    ///
+    /// 同じノードが同じ描画先を何度も宣言したときは、いちばん強い使い方にまとめる。
+    fn users(&self) -> Vec<(GraphTarget, Vec<(usize, Access)>)> {
+        let mut users: Vec<(GraphTarget, Vec<(usize, Access)>)> = Vec::new();
+        let mut index = HashMap::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            for &(target, access) in &node.access {
+                let slot = *index.entry(target).or_insert_with(|| {
+                    users.push((target, Vec::new()));
+                    users.len() - 1
+                });
+                let list = &mut users[slot].1;
+                match list.last_mut() {
+                    Some((last, previous)) if *last == i => {
+                        *previous = match (*previous, access) {
+                            (Access::Write, _) | (_, Access::Write) => Access::Write,
+                            (Access::Read, Access::Read) => Access::Read,
+                            _ => Access::Modify,
+                        };
+                    }
+                    _ => list.push((i, access)),
+                }
+            }
+        }
+        users
+    }
+
+    /// 描画先が宣言した使い方に対応しているか確かめる
+    fn validate_usage(&self, frame: &RenderFrame<'_, '_>) -> Result<(), FrameGraphError> {
+        for node in &self.nodes {
+            for &(target, access) in &node.access {
+                let GraphTarget::Target(id) = target else {
+                    continue;
+                };
+                let Some(desc) = frame.resource.render_targets.desc(id) else {
+                    continue;
+                };
+                let needed = match access {
+                    Access::Read => w::TextureUsages::TEXTURE_BINDING,
+                    Access::Write | Access::Modify => w::TextureUsages::RENDER_ATTACHMENT,
+                };
+                if !desc.usage.contains(needed) {
+                    return Err(FrameGraphError::InvalidUsage {
+                        node: node.name.clone(),
+                        target,
+                        missing: needed - desc.usage,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// [`GraphTarget::Output`] を書くノードがあるか
+    fn writes_output(&self) -> bool {
+        self.nodes.iter().any(|node| {
+            node.access
+                .iter()
+                .any(|&(target, access)| target == GraphTarget::Output && access != Access::Read)
+        })
+    }
+
+    /// 並べ替えて順に実行する
+    ///
+    /// 並べられなければ、どのノードも実行せずにエラーを返す。
+    /// [`GraphTarget::Output`] を書くノードがあれば、[`GraphTarget::FrameColor`] を中間のテクスチャに替えて実行する。
+    pub fn execute(
+        self,
+        frame: &mut RenderFrame<'_, '_>,
+        scene: &mut Scene,
+    ) -> Result<(), FrameGraphError> {
+        let order = self.order()?;
+        self.validate_usage(frame)?;
+        let source = self.writes_output().then(|| {
+            frame
+                .resource
+                .color_filter
+                .target(frame.resource, frame.size)
+        });
+        let mut frame = match &source {
+            Some(source) => RenderFrame {
+                resource: frame.resource,
+                target: &source.view,
+                output: frame.output,
+                depth_stencil: frame.depth_stencil,
+                size: frame.size,
+                encoder: frame.encoder,
+            },
+            None => RenderFrame {
+                encoder: frame.encoder,
+                ..*frame
+            },
+        };
+        let mut runs: Vec<_> = self.nodes.into_iter().map(|node| Some(node.run)).collect();
+        for i in order {
+            if let Some(run) = runs[i].take() {
+                run(&mut frame, scene);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 並べられなかったノード `unplaced` のうち、最初に見つかった循環に含まれるもの
+///
+/// 循環しているノードを読むだけのノードは含まない。循環が見つからなければ `unplaced` をそのまま返す。
+fn first_cycle(deps: &[Vec<usize>], unplaced: &[usize]) -> Vec<usize> {
+    // `from` から依存をたどって着くもの。`from` 自身は循環していなければ含まない
+    let reachable = |from: usize| {
+        let mut seen = vec![false; deps.len()];
+        let mut stack = vec![from];
+        while let Some(i) = stack.pop() {
+            for &j in &deps[i] {
+                if !seen[j] {
+                    seen[j] = true;
+                    stack.push(j);
+                }
+            }
+        }
+        seen
+    };
+    for &i in unplaced {
+        let from_i = reachable(i);
+        if from_i[i] {
+            return unplaced
+                .iter()
+                .copied()
+                .filter(|&j| from_i[j] && reachable(j)[i])
+                .collect();
+        }
+    }
+    unplaced.to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(n: u32) -> GraphTarget {
+        let mut keys = slotmap::SlotMap::new();
+        let mut key = keys.insert(());
+        for _ in 0..n {
+            key = keys.insert(());
+        }
+        GraphTarget::Target(RenderTargetId::from_key(key))
+    }
+
+    fn names<'a>(graph: &'a FrameGraph<'_>) -> Vec<&'a str> {
+        let nodes: Vec<_> = graph.nodes().collect();
+        graph
+            .order()
+            .unwrap()
+            .into_iter()
+            .map(|i| nodes[i].name())
+            .collect()
+    }
+
+    #[test]
+    fn writers_run_before_readers_and_keep_order_otherwise() {
+        let minimap = target(0);
+        let mut graph = FrameGraph::new();
+        graph
+            .add("world", |_, _| {})
+            .modifies(GraphTarget::FrameColor);
+        graph
+            .add("hud", |_, _| {})
+            .reads(minimap)
+            .modifies(GraphTarget::FrameColor);
+        graph.add("minimap", |_, _| {}).writes(minimap);
+        graph.add("stats", |_, _| {});
+        assert_eq!(names(&graph), ["world", "minimap", "hud", "stats"]);
+    }
+
+    #[test]
+    fn clear_runs_before_modifiers() {
+        let mut graph = FrameGraph::new();
+        graph
+            .add("overlay", |_, _| {})
+            .modifies(GraphTarget::FrameColor);
+        graph
+            .add("background", |_, _| {})
+            .writes(GraphTarget::FrameColor);
+        assert_eq!(names(&graph), ["background", "overlay"]);
+    }
+
+    #[test]
+    fn reports_double_writes_and_cycles() {
+        let mut graph = FrameGraph::new();
+        graph.add("a", |_, _| {}).writes(GraphTarget::FrameColor);
+        graph.add("b", |_, _| {}).writes(GraphTarget::FrameColor);
+        assert_eq!(
+            graph.order(),
+            Err(FrameGraphError::DoubleWrite {
+                target: GraphTarget::FrameColor,
+                first: "a".to_owned(),
+                second: "b".to_owned(),
+            })
+        );
+
+        let (x, y) = (target(0), target(1));
+        let mut graph = FrameGraph::new();
+        graph.add("blur x", |_, _| {}).reads(y).writes(x);
+        graph.add("blur y", |_, _| {}).reads(x).writes(y);
+        graph.add("present", |_, _| {}).reads(y);
+        // 循環を読むだけの present は循環に含まない
+        assert_eq!(
+            graph.order(),
+            Err(FrameGraphError::Cycle {
+                nodes: vec!["blur x".to_owned(), "blur y".to_owned()],
+            })
+        );
+    }
+
+    #[test]
+    fn color_filter_runs_after_scene_passes() {
+        use crate::{accessibility::ColorBlindMode, scene::Scene};
+
+        let mut scene = Scene::default();
+        assert_eq!(FrameGraph::from_scene(&scene).len(), 1);
+
+        let mut settings = Settings::default();
+        settings.accessibility.color_blind_mode = ColorBlindMode::Deuteranopia;
+        scene.insert_resource(settings);
+        let mut graph = FrameGraph::new();
+        graph.add_color_filter(&scene);
+        graph.add_scene_passes(&scene);
+        assert!(graph.writes_output());
+        let names = names(&graph);
+        assert_eq!(names.last(), Some(&"color filter"));
+        assert_eq!(names.len(), 2);
+    }
+}
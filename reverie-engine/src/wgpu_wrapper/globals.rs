@@ -0,0 +1,66 @@
+//! すべてのシェーダーで共有するユニフォーム
+use std::time::Duration;
+
+use wgpu as w;
+
+/// シーンの時計など、フレームごとに 1 回だけ書き込むユニフォーム
+///
+/// WGSL では `var<uniform> globals: vec4<f32>` として読む。`x` がシーンの経過時間の秒数。
+/// [`crate::scene::Scene::render`] が [`crate::scene::SceneClock`] の値を書き込む。
+#[derive(Debug)]
+pub struct GlobalUniforms {
+    buffer: w::Buffer,
+    bind_group_layout: w::BindGroupLayout,
+    bind_group: w::BindGroup,
+}
+
+impl GlobalUniforms {
+    pub(crate) fn new(device: &w::Device) -> Self {
+        let buffer = device.create_buffer(&w::BufferDescriptor {
+            label: Some("Global Uniform Buffer"),
+            size: size_of::<[f32; 4]>() as u64,
+            usage: w::BufferUsages::UNIFORM | w::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&w::BindGroupLayoutDescriptor {
+            label: Some("Global Bind Group Layout"),
+            entries: &[w::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: w::ShaderStages::VERTEX_FRAGMENT,
+                ty: w::BindingType::Buffer {
+                    ty: w::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let bind_group = device.create_bind_group(&w::BindGroupDescriptor {
+            label: Some("Global Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[w::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+        Self {
+            buffer,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    /// シーンの経過時間を書き込む
+    pub fn write_time(&self, queue: &w::Queue, elapsed: Duration) {
+        let globals = [elapsed.as_secs_f32(), 0.0, 0.0, 0.0];
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&globals));
+    }
+
+    pub const fn bind_group_layout(&self) -> &w::BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    pub const fn bind_group(&self) -> &w::BindGroup {
+        &self.bind_group
+    }
+}
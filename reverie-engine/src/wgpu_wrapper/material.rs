@@ -0,0 +1,366 @@
+//! WGSL のシェーダーで描くマテリアル
+//!
+//! ファイルから作ったマテリアルは、開発中に [`Material::reload_if_changed`] でシェーダーを読み直せる。
+//! シェーダーの `@group(2) @binding(0)` のユニフォームには、[`Material::set`] でメンバーの名前で書き込める。
+use std::{
+    borrow::Cow,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::SystemTime,
+};
+
+use anyhow::Context;
+use reverie_util::color::Color;
+use wgpu::{self as w, util::DeviceExt};
+
+use super::WgpuResource;
+
+pub use uniform::{
+    ShaderReflections, UniformError, UniformField, UniformLayout, UniformType, UniformValue,
+};
+
+mod uniform;
+
+#[derive(Debug, Clone)]
+/// マテリアルのシェーダーの置き場所
+pub enum MaterialSource {
+    /// 実行ファイルに埋め込んだシェーダー。読み直すことはない
+    Embedded(Cow<'static, str>),
+    /// ファイルのシェーダー。更新日時が変わったら読み直す
+    File(PathBuf),
+}
+
+#[derive(Debug)]
+/// スプライトと同じ頂点とバインドグループを使うマテリアル
+///
+/// シェーダーは [`WgpuResource::create_sprite_pipeline`] と同じく `vs_main` と `fs_main` を持つ。
+/// パイプラインはマテリアルの名前で [`WgpuResource::pipeline_cache`] に置く。
+///
+/// 読み直したシェーダーのコンパイルやパイプラインの検証に失敗したときは、
+/// 前のパイプラインを使い続け、エラーを [`Self::last_error`] に残す。
+///
+/// シェーダーが [`Self::UNIFORM_GROUP`] に `var<uniform>` を持てば、マテリアルごとのユニフォームバッファを作る。
+/// 構造体のメンバーの位置はシェーダーを読んだときに [`WgpuResource::shader_reflections`] で調べる。
+pub struct Material {
+    name: String,
+    source: MaterialSource,
+    /// 最後に読んだときのファイルの更新日時
+    modified: Option<SystemTime>,
+    last_error: Option<String>,
+    uniforms: Option<MaterialUniforms>,
+}
+
+#[derive(Debug)]
+/// マテリアルのユニフォームの値とバッファ
+struct MaterialUniforms {
+    layout: Rc<UniformLayout>,
+    data: Vec<u8>,
+    /// [`Self::data`] をバッファに書き込む必要があるか
+    dirty: bool,
+    buffer: w::Buffer,
+    bind_group_layout: w::BindGroupLayout,
+    bind_group: w::BindGroup,
+}
+
+impl MaterialUniforms {
+    /// `layout` のバッファを作る。`old` の値のうち、名前と型が同じものは引き継ぐ
+    fn new(
+        resource: &WgpuResource<'_>,
+        label: &str,
+        layout: Rc<UniformLayout>,
+        old: Option<&Self>,
+    ) -> Self {
+        let mut data = vec![0; layout.size() as usize];
+        if let Some(old) = old {
+            layout.copy_matching(&mut data, &old.layout, &old.data);
+        }
+        let device = &resource.device;
+        let buffer = device.create_buffer_init(&w::util::BufferInitDescriptor {
+            label: Some(label),
+            contents: &data,
+            usage: w::BufferUsages::UNIFORM | w::BufferUsages::COPY_DST,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&w::BindGroupLayoutDescriptor {
+            label: Some(label),
+            entries: &[w::BindGroupLayoutEntry {
+                binding: Material::UNIFORM_BINDING,
+                visibility: w::ShaderStages::VERTEX_FRAGMENT,
+                ty: w::BindingType::Buffer {
+                    ty: w::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: w::BufferSize::new(u64::from(layout.size())),
+                },
+                count: None,
+            }],
+        });
+        let bind_group = device.create_bind_group(&w::BindGroupDescriptor {
+            label: Some(label),
+            layout: &bind_group_layout,
+            entries: &[w::BindGroupEntry {
+                binding: Material::UNIFORM_BINDING,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+        Self {
+            layout,
+            data,
+            dirty: false,
+            buffer,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+}
+
+impl Material {
+    /// マテリアルのユニフォームのバインドグループの番号
+    pub const UNIFORM_GROUP: u32 = 2;
+    /// マテリアルのユニフォームのバインディングの番号
+    pub const UNIFORM_BINDING: u32 = 0;
+
+    /// 埋め込んだシェーダーからマテリアルを作る
+    pub fn from_wgsl(name: impl Into<String>, source: impl Into<Cow<'static, str>>) -> Self {
+        Self::new(name, MaterialSource::Embedded(source.into()))
+    }
+
+    /// ファイルのシェーダーからマテリアルを作る。ファイルは最初にパイプラインを使うときに読む
+    pub fn from_file(name: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        Self::new(name, MaterialSource::File(path.into()))
+    }
+
+    pub fn new(name: impl Into<String>, source: MaterialSource) -> Self {
+        Self {
+            name: name.into(),
+            source,
+            modified: None,
+            last_error: None,
+            uniforms: None,
+        }
+    }
+
+    /// [`WgpuResource::pipeline_cache`] での名前
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub const fn source(&self) -> &MaterialSource {
+        &self.source
+    }
+
+    /// シェーダーのファイル。埋め込んだシェーダーなら `None`
+    pub fn path(&self) -> Option<&Path> {
+        match &self.source {
+            MaterialSource::Embedded(_) => None,
+            MaterialSource::File(path) => Some(path),
+        }
+    }
+
+    /// 最後に失敗したコンパイルのエラー。成功すると消える
+    ///
+    /// デバッグ用の表示に使う。
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+
+    /// シェーダーのユニフォームの構造体。シェーダーを読む前やユニフォームが無ければ `None`
+    pub fn uniform_layout(&self) -> Option<&UniformLayout> {
+        self.uniforms.as_ref().map(|uniforms| &*uniforms.layout)
+    }
+
+    /// ユニフォームのメンバー `name` に `value` を書き込む
+    ///
+    /// バッファには次の [`Self::bind`] で送る。シェーダーを読む ([`Self::pipeline`] か [`Self::bind`]) 前は
+    /// [`UniformError::NoUniforms`] になる。
+    pub fn set<V: UniformValue>(&mut self, name: &str, value: V) -> Result<(), UniformError> {
+        let uniforms = self.uniforms.as_mut().ok_or(UniformError::NoUniforms)?;
+        uniforms.layout.write(&mut uniforms.data, name, value)?;
+        uniforms.dirty = true;
+        Ok(())
+    }
+
+    /// `vec4<f32>` のメンバーに書き込む
+    pub fn set_vec4(&mut self, name: &str, value: impl Into<[f32; 4]>) -> Result<(), UniformError> {
+        self.set(name, value.into())
+    }
+
+    /// `vec4<f32>` のメンバーに色を書き込む
+    pub fn set_color(&mut self, name: &str, color: Color) -> Result<(), UniformError> {
+        self.set(name, color)
+    }
+
+    /// パイプラインとユニフォームのバインドグループを設定する
+    ///
+    /// 書き込んだユニフォームはここでバッファに送る。バッファはフレームの描画を GPU に送るときに書き換わるので、
+    /// 1 フレームの中で値を変えながら何度も描くことはできない。
+    pub fn bind(
+        &mut self,
+        resource: &WgpuResource<'_>,
+        rp: &mut w::RenderPass<'_>,
+    ) -> anyhow::Result<()> {
+        let pipeline = self.pipeline(resource)?;
+        rp.set_pipeline(&pipeline);
+        if let Some(uniforms) = &mut self.uniforms {
+            if std::mem::take(&mut uniforms.dirty) {
+                resource
+                    .queue
+                    .write_buffer(&uniforms.buffer, 0, &uniforms.data);
+            }
+            rp.set_bind_group(Self::UNIFORM_GROUP, &uniforms.bind_group, &[]);
+        }
+        Ok(())
+    }
+
+    /// パイプラインを返す。無ければシェーダーを読んで作る
+    ///
+    /// 描画先のフォーマットが変わった後も作り直す。
+    pub fn pipeline(
+        &mut self,
+        resource: &WgpuResource<'_>,
+    ) -> anyhow::Result<Rc<w::RenderPipeline>> {
+        if let Some(pipeline) = resource.pipeline_cache.get(&self.name) {
+            return Ok(pipeline);
+        }
+        self.rebuild(resource)
+            .inspect_err(|e| self.last_error = Some(format!("{e:#}")))?;
+        self.last_error = None;
+        resource
+            .pipeline_cache
+            .get(&self.name)
+            .context("pipeline was just inserted")
+    }
+
+    /// シェーダーのファイルが更新されていれば読み直す
+    ///
+    /// 作り直せたら `true` を返す。失敗したときはエラーをログと [`Self::last_error`] に残し、
+    /// 前のパイプラインを使い続けるので、壊れたフレームは描かない。
+    /// リリースビルドと Web では何もしない。
+    pub fn reload_if_changed(&mut self, resource: &WgpuResource<'_>) -> bool {
+        if cfg!(any(not(debug_assertions), target_arch = "wasm32")) {
+            return false;
+        }
+        let MaterialSource::File(path) = &self.source else {
+            return false;
+        };
+        let modified = match std::fs::metadata(path).and_then(|m| m.modified()) {
+            Ok(modified) => modified,
+            Err(e) => {
+                tracing::warn!(path = %path.display(), "failed: read shader metadata: {e}");
+                return false;
+            }
+        };
+        if self.modified == Some(modified) {
+            return false;
+        }
+        match self.rebuild(resource) {
+            Ok(()) => {
+                tracing::info!(material = self.name, "shader reloaded");
+                self.last_error = None;
+                true
+            }
+            Err(e) => {
+                // 同じ内容で何度も失敗しないよう、失敗しても日時は覚えておく
+                self.modified = Some(modified);
+                tracing::error!(material = self.name, "failed: reload shader: {e:#}");
+                self.last_error = Some(format!("{e:#}"));
+                false
+            }
+        }
+    }
+
+    /// シェーダーを読んでパイプラインを作り、成功したら [`WgpuResource::pipeline_cache`] に入れる
+    fn rebuild(&mut self, resource: &WgpuResource<'_>) -> anyhow::Result<()> {
+        let (source, label) = match &self.source {
+            MaterialSource::Embedded(source) => (source.clone(), self.name.clone()),
+            MaterialSource::File(path) => {
+                self.modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+                let source = std::fs::read_to_string(path)
+                    .with_context(|| format!("failed: read {}", path.display()))?;
+                (Cow::Owned(source), path.display().to_string())
+            }
+        };
+        let layout = resource.shader_reflections.reflect(&label, &source)?;
+        let unchanged = match (&layout, &self.uniforms) {
+            (Some(new), Some(old)) => Rc::ptr_eq(new, &old.layout),
+            (None, None) => true,
+            _ => false,
+        };
+        // パイプラインを作れたときだけ入れ替える
+        let replacement = (!unchanged).then(|| {
+            layout.map(|layout| {
+                MaterialUniforms::new(resource, &self.name, layout, self.uniforms.as_ref())
+            })
+        });
+        let uniforms = replacement
+            .as_ref()
+            .map_or(self.uniforms.as_ref(), Option::as_ref);
+        let pipeline = compile(
+            resource,
+            &label,
+            source,
+            uniforms.map(|uniforms| &uniforms.bind_group_layout),
+        )?;
+        resource.pipeline_cache.insert(self.name.clone(), pipeline);
+        if let Some(replacement) = replacement {
+            self.uniforms = replacement;
+        }
+        Ok(())
+    }
+}
+
+/// シェーダーとパイプラインを作る。検証のエラーは wgpu に渡さずに返す
+///
+/// Web ではエラーを待てないので、wgpu に任せる。
+fn compile(
+    resource: &WgpuResource<'_>,
+    label: &str,
+    source: Cow<'_, str>,
+    uniforms: Option<&w::BindGroupLayout>,
+) -> anyhow::Result<w::RenderPipeline> {
+    let device = &resource.device;
+    let create_pipeline = |module: &w::ShaderModule| {
+        let mut layouts = vec![
+            &resource.texture_bind_group_layout,
+            &resource.uniform_bind_group_layout,
+        ];
+        layouts.extend(uniforms);
+        super::setup_render_pipeline(module, &layouts, resource.surface_config.format, device)
+    };
+    if cfg!(target_arch = "wasm32") {
+        let module = device.create_shader_module(w::ShaderModuleDescriptor {
+            label: Some(label),
+            source: w::ShaderSource::Wgsl(source),
+        });
+        return create_pipeline(&module);
+    }
+    device.push_error_scope(w::ErrorFilter::Validation);
+    let module = device.create_shader_module(w::ShaderModuleDescriptor {
+        label: Some(label),
+        source: w::ShaderSource::Wgsl(source),
+    });
+    let pipeline = create_pipeline(&module);
+    let error = pollster::block_on(device.pop_error_scope());
+
+    let messages = pollster::block_on(module.get_compilation_info()).messages;
+    let errors = messages
+        .iter()
+        .filter(|m| m.message_type == w::CompilationMessageType::Error)
+        .map(|m| {
+            m.location.map_or_else(
+                || format!("{label}: {}", m.message),
+                |location| {
+                    format!(
+                        "{label}:{}:{}: {}",
+                        location.line_number, location.line_position, m.message
+                    )
+                },
+            )
+        })
+        .collect::<Vec<_>>();
+    if !errors.is_empty() {
+        anyhow::bail!("{}", errors.join("\n"));
+    }
+    if let Some(error) = error {
+        anyhow::bail!("{label}: {error}");
+    }
+    pipeline
+}
@@ -0,0 +1,400 @@
+//! マテリアルのユニフォームの構造体を WGSL から読み取り、名前で書き込めるようにする
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    rc::Rc,
+};
+
+use nalgebra::{Matrix4, Vector2, Vector3, Vector4};
+use reverie_util::color::Color;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// ユニフォームのメンバーの型
+pub enum UniformType {
+    F32,
+    I32,
+    U32,
+    Vec2,
+    Vec3,
+    Vec4,
+    Mat4,
+    /// 上のどれでもない型。名前では書き込めない
+    Other,
+}
+
+impl std::fmt::Display for UniformType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::F32 => "f32",
+            Self::I32 => "i32",
+            Self::U32 => "u32",
+            Self::Vec2 => "vec2<f32>",
+            Self::Vec3 => "vec3<f32>",
+            Self::Vec4 => "vec4<f32>",
+            Self::Mat4 => "mat4x4<f32>",
+            Self::Other => "an unsupported type",
+        })
+    }
+}
+
+impl UniformType {
+    fn from_naga(inner: &naga::TypeInner) -> Self {
+        use naga::{ScalarKind, TypeInner, VectorSize};
+        match *inner {
+            TypeInner::Scalar(scalar) if scalar.width == 4 => match scalar.kind {
+                ScalarKind::Float => Self::F32,
+                ScalarKind::Sint => Self::I32,
+                ScalarKind::Uint => Self::U32,
+                _ => Self::Other,
+            },
+            TypeInner::Vector { size, scalar } if scalar == naga::Scalar::F32 => match size {
+                VectorSize::Bi => Self::Vec2,
+                VectorSize::Tri => Self::Vec3,
+                VectorSize::Quad => Self::Vec4,
+            },
+            TypeInner::Matrix {
+                columns: VectorSize::Quad,
+                rows: VectorSize::Quad,
+                scalar,
+            } if scalar == naga::Scalar::F32 => Self::Mat4,
+            _ => Self::Other,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// ユニフォームのメンバーの位置と型
+pub struct UniformField {
+    /// 構造体の先頭からのバイト数
+    pub offset: u32,
+    pub ty: UniformType,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// マテリアルのユニフォームの構造体のメンバー
+pub struct UniformLayout {
+    fields: HashMap<String, UniformField>,
+    size: u32,
+}
+
+impl UniformLayout {
+    /// `@group(group) @binding(binding)` の `var<uniform>` を読み取る。無ければ `None`
+    ///
+    /// 構造体ならメンバーの名前で、そうでなければ変数の名前で書き込む。
+    pub fn reflect(module: &naga::Module, group: u32, binding: u32) -> Option<Self> {
+        let (_, global) = module.global_variables.iter().find(|(_, global)| {
+            global.space == naga::AddressSpace::Uniform
+                && global
+                    .binding
+                    .as_ref()
+                    .is_some_and(|b| b.group == group && b.binding == binding)
+        })?;
+        let ty = &module.types[global.ty];
+        let (fields, size) = match &ty.inner {
+            naga::TypeInner::Struct { members, span } => (
+                members
+                    .iter()
+                    .filter_map(|member| {
+                        let field = UniformField {
+                            offset: member.offset,
+                            ty: UniformType::from_naga(&module.types[member.ty].inner),
+                        };
+                        Some((member.name.clone()?, field))
+                    })
+                    .collect(),
+                *span,
+            ),
+            inner => {
+                let field = UniformField {
+                    offset: 0,
+                    ty: UniformType::from_naga(inner),
+                };
+                let size = inner.size(module.to_ctx());
+                (
+                    global
+                        .name
+                        .clone()
+                        .into_iter()
+                        .map(|name| (name, field))
+                        .collect(),
+                    size,
+                )
+            }
+        };
+        Some(Self { fields, size })
+    }
+
+    pub fn field(&self, name: &str) -> Option<UniformField> {
+        self.fields.get(name).copied()
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = (&str, UniformField)> {
+        self.fields
+            .iter()
+            .map(|(name, field)| (name.as_str(), *field))
+    }
+
+    /// 構造体のバイト数。ユニフォームバッファの大きさになる
+    pub const fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// `data` の `name` に `value` を書き込む
+    pub(super) fn write<V: UniformValue>(
+        &self,
+        data: &mut [u8],
+        name: &str,
+        value: V,
+    ) -> Result<(), UniformError> {
+        let field = self
+            .field(name)
+            .ok_or_else(|| UniformError::UnknownName(name.to_owned()))?;
+        if field.ty != V::TYPE {
+            return Err(UniformError::TypeMismatch {
+                name: name.to_owned(),
+                expected: field.ty,
+                found: V::TYPE,
+            });
+        }
+        let bytes = value.to_bytes();
+        let offset = field.offset as usize;
+        data[offset..offset + bytes.as_ref().len()].copy_from_slice(bytes.as_ref());
+        Ok(())
+    }
+
+    /// `other` の値のうち、名前と型が同じものを `data` に写す
+    ///
+    /// シェーダーを読み直して構造体が変わったときに、設定した値を引き継ぐのに使う。
+    pub(super) fn copy_matching(&self, data: &mut [u8], other: &Self, other_data: &[u8]) {
+        for (name, field) in &self.fields {
+            let Some(old) = other.field(name).filter(|old| old.ty == field.ty) else {
+                continue;
+            };
+            let Some(len) = field.ty.size() else {
+                continue;
+            };
+            let (new, old) = (field.offset as usize, old.offset as usize);
+            data[new..new + len].copy_from_slice(&other_data[old..old + len]);
+        }
+    }
+}
+
+impl UniformType {
+    /// バイト数。[`Self::Other`] なら `None`
+    const fn size(self) -> Option<usize> {
+        Some(match self {
+            Self::F32 | Self::I32 | Self::U32 => 4,
+            Self::Vec2 => 8,
+            Self::Vec3 => 12,
+            Self::Vec4 => 16,
+            Self::Mat4 => 64,
+            Self::Other => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// ユニフォームに名前で書き込めなかった
+pub enum UniformError {
+    /// シェーダーにその名前のメンバーが無い
+    UnknownName(String),
+    /// メンバーの型と書き込む値の型が違う
+    TypeMismatch {
+        name: String,
+        expected: UniformType,
+        found: UniformType,
+    },
+    /// シェーダーがまだ読み込まれていないか、マテリアルのユニフォームを持たない
+    NoUniforms,
+}
+
+impl std::fmt::Display for UniformError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownName(name) => write!(f, "material has no uniform named `{name}`"),
+            Self::TypeMismatch {
+                name,
+                expected,
+                found,
+            } => write!(f, "uniform `{name}` is {expected}, but {found} was given"),
+            Self::NoUniforms => f.write_str("material has no loaded uniforms"),
+        }
+    }
+}
+
+impl std::error::Error for UniformError {}
+
+/// ユニフォームに書き込める値
+pub trait UniformValue {
+    const TYPE: UniformType;
+    type Bytes: AsRef<[u8]>;
+
+    fn to_bytes(&self) -> Self::Bytes;
+}
+
+macro_rules! impl_uniform_value {
+    ($($ty:ty => $uniform:ident, $len:literal;)*) => {
+        $(
+            impl UniformValue for $ty {
+                const TYPE: UniformType = UniformType::$uniform;
+                type Bytes = [u8; $len];
+
+                fn to_bytes(&self) -> Self::Bytes {
+                    bytemuck::cast(*self)
+                }
+            }
+        )*
+    };
+}
+
+impl_uniform_value! {
+    f32 => F32, 4;
+    i32 => I32, 4;
+    u32 => U32, 4;
+    [f32; 2] => Vec2, 8;
+    [f32; 3] => Vec3, 12;
+    [f32; 4] => Vec4, 16;
+    Vector2<f32> => Vec2, 8;
+    Vector3<f32> => Vec3, 12;
+    Vector4<f32> => Vec4, 16;
+    Matrix4<f32> => Mat4, 64;
+}
+
+impl UniformValue for Color {
+    const TYPE: UniformType = UniformType::Vec4;
+    type Bytes = [u8; 16];
+
+    fn to_bytes(&self) -> Self::Bytes {
+        bytemuck::cast(<[f32; 4]>::from(*self))
+    }
+}
+
+#[derive(Debug, Default)]
+/// シェーダーごとに読み取ったユニフォームの構造体
+///
+/// 同じシェーダーから作ったマテリアルは読み取り結果を共有する。
+/// [`super::super::WgpuResource::shader_reflections`] にある。
+pub struct ShaderReflections {
+    layouts: RefCell<HashMap<u64, Option<Rc<UniformLayout>>>>,
+}
+
+impl ShaderReflections {
+    /// `source` を解析して、マテリアルのユニフォームを読み取る
+    ///
+    /// 解析に失敗したら、行と列の付いたエラーを返す。
+    pub fn reflect(&self, label: &str, source: &str) -> anyhow::Result<Option<Rc<UniformLayout>>> {
+        let mut hasher = DefaultHasher::new();
+        source.hash(&mut hasher);
+        let key = hasher.finish();
+        if let Some(layout) = self.layouts.borrow().get(&key) {
+            return Ok(layout.clone());
+        }
+        let module = naga::front::wgsl::parse_str(source)
+            .map_err(|e| anyhow::anyhow!("{}", e.emit_to_string_with_path(source, label)))?;
+        let layout = UniformLayout::reflect(
+            &module,
+            super::Material::UNIFORM_GROUP,
+            super::Material::UNIFORM_BINDING,
+        )
+        .map(Rc::new);
+        self.layouts.borrow_mut().insert(key, layout.clone());
+        Ok(layout)
+    }
+
+    /// 読み取ったシェーダーの数
+    pub fn len(&self) -> usize {
+        self.layouts.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layouts.borrow().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHADER: &str = "
+struct Params {
+    progress: f32,
+    edge: vec4<f32>,
+    count: u32,
+}
+@group(2) @binding(0) var<uniform> params: Params;
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return params.edge * params.progress;
+}
+";
+
+    #[test]
+    fn reflects_offsets_and_checks_types() {
+        let reflections = ShaderReflections::default();
+        let layout = reflections.reflect("test", SHADER).unwrap().unwrap();
+        assert_eq!(layout.size(), 48);
+        assert_eq!(
+            layout.field("edge"),
+            Some(UniformField {
+                offset: 16,
+                ty: UniformType::Vec4
+            })
+        );
+
+        let mut data = vec![0; layout.size() as usize];
+        layout.write(&mut data, "progress", 0.5f32).unwrap();
+        layout.write(&mut data, "edge", Color::WHITE).unwrap();
+        layout.write(&mut data, "count", 3u32).unwrap();
+        assert_eq!(
+            bytemuck::cast_slice::<u8, f32>(&data)[..8],
+            [0.5, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0]
+        );
+        assert_eq!(bytemuck::cast_slice::<u8, u32>(&data)[8], 3);
+
+        assert_eq!(
+            layout.write(&mut data, "progress", 1u32),
+            Err(UniformError::TypeMismatch {
+                name: "progress".to_owned(),
+                expected: UniformType::F32,
+                found: UniformType::U32,
+            })
+        );
+        assert_eq!(
+            layout.write(&mut data, "speed", 1.0f32),
+            Err(UniformError::UnknownName("speed".to_owned()))
+        );
+
+        // 同じシェーダーは読み取り直さない
+        let again = reflections.reflect("test", SHADER).unwrap().unwrap();
+        assert!(Rc::ptr_eq(&layout, &again));
+        assert_eq!(reflections.len(), 1);
+    }
+
+    #[test]
+    fn keeps_values_of_matching_fields() {
+        let reflections = ShaderReflections::default();
+        let old = reflections.reflect("old", SHADER).unwrap().unwrap();
+        let new = reflections
+            .reflect(
+                "new",
+                &SHADER.replace("progress: f32,", "count2: f32, progress: f32,"),
+            )
+            .unwrap()
+            .unwrap();
+        let mut old_data = vec![0; old.size() as usize];
+        old.write(&mut old_data, "progress", 0.25f32).unwrap();
+        let mut new_data = vec![0; new.size() as usize];
+        new.copy_matching(&mut new_data, &old, &old_data);
+        assert_eq!(bytemuck::cast_slice::<u8, f32>(&new_data)[1], 0.25);
+    }
+
+    #[test]
+    fn parse_errors_have_positions() {
+        let error = ShaderReflections::default()
+            .reflect("broken.wgsl", "fn main( {}")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("broken.wgsl:1:"), "{error}");
+    }
+}
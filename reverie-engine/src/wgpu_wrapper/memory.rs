@@ -0,0 +1,29 @@
+//! GPU のメモリ使用量の集計
+//!
+//! テクスチャやバッファを作るときに大きさを登録し、[`TrackedAllocation`] が drop されたら差し引く。
+//! 集計そのものは OpenGL のバックエンドと共通の [`reverie_util::memory`] が行い、ここでは分類を決める。
+use reverie_util::memory::{AllocationInfo, MemoryCategory, MemoryReport, MemoryTracker};
+
+/// 集計の分類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GpuMemoryCategory {
+    /// [`crate::texture::TextureRegistry`] のテクスチャ
+    Texture,
+    /// スプライトやタイルマップの頂点バッファとインデックスバッファ
+    SpriteBuffer,
+    /// 深度・ステンシルバッファや画面外の描画先
+    RenderTarget,
+}
+
+impl MemoryCategory for GpuMemoryCategory {
+    const ALL: &'static [Self] = &[Self::Texture, Self::SpriteBuffer, Self::RenderTarget];
+}
+
+/// GPU のリソースの大きさを集計する
+pub type GpuMemoryTracker = MemoryTracker<GpuMemoryCategory>;
+/// [`GpuMemoryTracker::track`] で登録したリソース
+pub type TrackedAllocation = reverie_util::memory::TrackedAllocation<GpuMemoryCategory>;
+/// 1 つのリソースの大きさ
+pub type GpuAllocationInfo = AllocationInfo<GpuMemoryCategory>;
+/// [`GpuMemoryTracker::report`] の結果
+pub type GpuMemoryReport = MemoryReport<GpuMemoryCategory>;
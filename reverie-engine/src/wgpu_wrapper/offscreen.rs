@@ -0,0 +1,250 @@
+//! ウィンドウに表示せずに描画し、その画素を読み出す
+use std::{
+    cell::Cell,
+    num::NonZeroU32,
+    sync::mpsc::{self, Receiver, TryRecvError},
+};
+
+use anyhow::Context;
+use wgpu as w;
+
+use super::{
+    create_depth_stencil_view,
+    memory::{GpuMemoryCategory, TrackedAllocation},
+    track_depth_stencil, RenderFrame, WgpuResource,
+};
+use crate::scene::Scene;
+
+/// 画面外の描画先
+///
+/// スクリーンショットの比較や学習データの生成に使う。
+/// 色の形式は [`WgpuResource::surface_config`] と同じで、深度・ステンシルバッファは自前で持つ。
+/// スプライトのピクセル座標の変換はウィンドウの大きさに従うので、大きさが違う場合は
+/// `target_size` を設定した [`crate::scene::CameraComponent`] で描画する。
+#[derive(Debug)]
+pub struct OffscreenTarget {
+    texture: w::Texture,
+    view: w::TextureView,
+    depth_stencil_view: w::TextureView,
+    _memory: [TrackedAllocation; 2],
+}
+
+impl OffscreenTarget {
+    pub fn new(resource: &WgpuResource<'_>, width: NonZeroU32, height: NonZeroU32) -> Self {
+        let texture = resource.device.create_texture(&w::TextureDescriptor {
+            label: Some("Offscreen Target"),
+            size: w::Extent3d {
+                width: width.get(),
+                height: height.get(),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: w::TextureDimension::D2,
+            format: resource.surface_config.format,
+            usage: w::TextureUsages::RENDER_ATTACHMENT | w::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&w::TextureViewDescriptor::default());
+        let depth_stencil_view = create_depth_stencil_view(&resource.device, width, height);
+        let bytes_per_pixel = resource
+            .surface_config
+            .format
+            .block_copy_size(None)
+            .unwrap_or(4);
+        let color_bytes =
+            u64::from(width.get()) * u64::from(height.get()) * u64::from(bytes_per_pixel);
+        let memory = [
+            resource.gpu_memory.track(
+                GpuMemoryCategory::RenderTarget,
+                "Offscreen Target",
+                color_bytes,
+            ),
+            track_depth_stencil(&resource.gpu_memory, width, height),
+        ];
+        Self {
+            texture,
+            view,
+            depth_stencil_view,
+            _memory: memory,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.texture.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.texture.height()
+    }
+
+    fn size(&self) -> (NonZeroU32, NonZeroU32) {
+        (
+            NonZeroU32::new(self.width()).unwrap_or(NonZeroU32::MIN),
+            NonZeroU32::new(self.height()).unwrap_or(NonZeroU32::MIN),
+        )
+    }
+
+    pub const fn view(&self) -> &w::TextureView {
+        &self.view
+    }
+
+    /// シーンを 1 フレーム描画する
+    pub fn render(&self, scene: &mut Scene, resource: &WgpuResource<'_>) {
+        resource.render_to_attachments(scene, (&self.view, &self.depth_stencil_view), self.size());
+    }
+
+    /// [`WgpuResource::render_with`] と同じように、`render` で組み立てたフレームを描画する
+    pub fn render_with(
+        &self,
+        scene: &mut Scene,
+        resource: &WgpuResource<'_>,
+        render: impl FnOnce(&mut RenderFrame<'_, '_>, &mut Scene),
+    ) {
+        resource.render_to_attachments_with(
+            scene,
+            (&self.view, &self.depth_stencil_view),
+            self.size(),
+            render,
+        );
+    }
+
+    /// 画素の読み出しを始める
+    ///
+    /// コピーのコマンドを送るだけで、完了を待たない。
+    pub fn request_pixels(&self, resource: &WgpuResource<'_>) -> PixelReadRequest {
+        PixelReadRequest::new(resource, &self.texture)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReadState {
+    Pending,
+    Ready,
+    Failed,
+}
+
+/// [`OffscreenTarget::request_pixels`] で始めた読み出し
+#[derive(Debug)]
+pub struct PixelReadRequest {
+    buffer: w::Buffer,
+    receiver: Receiver<Result<(), w::BufferAsyncError>>,
+    state: Cell<ReadState>,
+    unpadded_row: u32,
+    padded_row: u32,
+    bgra: bool,
+}
+
+impl PixelReadRequest {
+    /// `texture` の画素を読み出すコマンドを送る
+    ///
+    /// `texture` は 1 画素 4 バイトの形式で、[`w::TextureUsages::COPY_SRC`] を持っていなければならない。
+    pub(crate) fn new(resource: &WgpuResource<'_>, texture: &w::Texture) -> Self {
+        let width = texture.width();
+        let height = texture.height();
+        // コピー先の 1 行のバイト数は 256 の倍数でなければならない
+        let unpadded_row = width * 4;
+        let padded_row = unpadded_row.div_ceil(w::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * w::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let buffer = resource.device.create_buffer(&w::BufferDescriptor {
+            label: Some("Offscreen Readback Buffer"),
+            size: u64::from(padded_row * height),
+            usage: w::BufferUsages::COPY_DST | w::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = resource
+            .device
+            .create_command_encoder(&w::CommandEncoderDescriptor {
+                label: Some("Offscreen Readback"),
+            });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            w::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: w::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            texture.size(),
+        );
+        resource.queue.submit(Some(encoder.finish()));
+
+        let (sender, receiver) = mpsc::channel();
+        buffer.slice(..).map_async(w::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+
+        Self {
+            buffer,
+            receiver,
+            state: Cell::new(ReadState::Pending),
+            unpadded_row,
+            padded_row,
+            bgra: matches!(
+                texture.format(),
+                w::TextureFormat::Bgra8Unorm | w::TextureFormat::Bgra8UnormSrgb
+            ),
+        }
+    }
+
+    /// 読み出しが終わっていれば、上の行から順に並んだ RGBA8 の画素を返す
+    ///
+    /// GPU の完了を待たずにすぐ戻る。終わっていないか失敗した場合は `None` を返す。
+    pub fn try_get(&self, resource: &WgpuResource<'_>) -> Option<Vec<u8>> {
+        resource.device.poll(w::Maintain::Poll);
+        self.update_state();
+        (self.state.get() == ReadState::Ready).then(|| self.copy_pixels())
+    }
+
+    /// 読み出しが終わるまで待って画素を返す
+    pub fn wait(&self, resource: &WgpuResource<'_>) -> anyhow::Result<Vec<u8>> {
+        if self.state.get() == ReadState::Pending {
+            resource.device.poll(w::Maintain::Wait);
+            let result = self.receiver.recv().context("failed: receive map result")?;
+            self.state.set(if result.is_ok() {
+                ReadState::Ready
+            } else {
+                ReadState::Failed
+            });
+            result.context("failed: map readback buffer")?;
+        }
+        anyhow::ensure!(
+            self.state.get() == ReadState::Ready,
+            "failed: map readback buffer"
+        );
+        Ok(self.copy_pixels())
+    }
+
+    fn update_state(&self) {
+        if self.state.get() != ReadState::Pending {
+            return;
+        }
+        match self.receiver.try_recv() {
+            Ok(Ok(())) => self.state.set(ReadState::Ready),
+            Ok(Err(e)) => {
+                tracing::error!("failed: map readback buffer: {e}");
+                self.state.set(ReadState::Failed);
+            }
+            Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => self.state.set(ReadState::Failed),
+        }
+    }
+
+    fn copy_pixels(&self) -> Vec<u8> {
+        let data = self.buffer.slice(..).get_mapped_range();
+        let mut pixels =
+            Vec::with_capacity(data.len() / self.padded_row as usize * self.unpadded_row as usize);
+        for row in data.chunks(self.padded_row as usize) {
+            pixels.extend_from_slice(&row[..self.unpadded_row as usize]);
+        }
+        if self.bgra {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        pixels
+    }
+}
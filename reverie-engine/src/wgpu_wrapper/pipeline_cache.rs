@@ -0,0 +1,138 @@
+//! 作ったパイプラインを名前で使い回す
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::Rc,
+};
+
+use wgpu as w;
+
+use super::TargetFormats;
+
+#[derive(Debug)]
+struct CachedPipeline {
+    /// 作ったときの描画先のフォーマット
+    formats: TargetFormats,
+    pipeline: Rc<w::RenderPipeline>,
+    /// 最後に使われたフレーム
+    last_used: Cell<u64>,
+}
+
+#[derive(Debug)]
+/// 名前を付けたレンダーパイプラインの置き場
+///
+/// [`crate::scene::System::render`] のように毎フレーム呼ばれる処理から、
+/// 最初の 1 回だけパイプラインを作るのに使う。
+///
+/// パイプラインは作ったときの [`TargetFormats`] と一緒に覚えておく。描画先のフォーマットが
+/// 変わった後は古いパイプラインを返さず、[`Self::get_or_create`] で作り直す。
+///
+/// しばらく使われず、外で [`Rc`] も持たれていないパイプラインは
+/// [`WgpuResource::collect_unused_assets`](super::WgpuResource::collect_unused_assets) が捨てる。
+pub struct PipelineCache {
+    formats: Cell<TargetFormats>,
+    pipelines: RefCell<HashMap<String, CachedPipeline>>,
+    /// [`Self::evict_unused`] を呼んだ回数
+    frame: Cell<u64>,
+}
+
+impl PipelineCache {
+    pub(crate) fn new(formats: TargetFormats) -> Self {
+        Self {
+            formats: Cell::new(formats),
+            pipelines: RefCell::default(),
+            frame: Cell::new(0),
+        }
+    }
+
+    /// 今の描画先のフォーマット
+    pub fn formats(&self) -> TargetFormats {
+        self.formats.get()
+    }
+
+    /// 描画先のフォーマットが変わったことを知らせる
+    pub(crate) fn set_formats(&self, formats: TargetFormats) {
+        self.formats.set(formats);
+    }
+
+    /// `name`のパイプラインを返す。今の描画先のフォーマットで作ったものが無ければ `None`
+    pub fn get(&self, name: &str) -> Option<Rc<w::RenderPipeline>> {
+        let formats = self.formats();
+        self.pipelines
+            .borrow()
+            .get(name)
+            .filter(|cached| cached.formats == formats)
+            .map(|cached| {
+                cached.last_used.set(self.frame.get());
+                Rc::clone(&cached.pipeline)
+            })
+    }
+
+    /// `name`のパイプラインを返す。無ければ`create`で作って登録する
+    ///
+    /// 描画先のフォーマットが変わっていれば、古いものを捨てて`create`で作り直す。
+    /// `create`には今の描画先のフォーマットが渡される。
+    pub fn get_or_create(
+        &self,
+        name: &str,
+        create: impl FnOnce(&TargetFormats) -> w::RenderPipeline,
+    ) -> Rc<w::RenderPipeline> {
+        if let Some(pipeline) = self.get(name) {
+            return pipeline;
+        }
+        let formats = self.formats();
+        let pipeline = Rc::new(create(&formats));
+        self.pipelines.borrow_mut().insert(
+            name.to_owned(),
+            CachedPipeline {
+                formats,
+                pipeline: Rc::clone(&pipeline),
+                last_used: Cell::new(self.frame.get()),
+            },
+        );
+        pipeline
+    }
+
+    /// 今の描画先のフォーマットで作ったパイプラインを登録する。同じ名前があれば置き換える
+    pub fn insert(&self, name: impl Into<String>, pipeline: w::RenderPipeline) {
+        self.pipelines.borrow_mut().insert(
+            name.into(),
+            CachedPipeline {
+                formats: self.formats(),
+                pipeline: Rc::new(pipeline),
+                last_used: Cell::new(self.frame.get()),
+            },
+        );
+    }
+
+    pub fn remove(&self, name: &str) -> Option<Rc<w::RenderPipeline>> {
+        self.pipelines
+            .borrow_mut()
+            .remove(name)
+            .map(|cached| cached.pipeline)
+    }
+
+    /// 登録されているパイプラインの数。フォーマットが変わって作り直しを待つものも含む
+    pub fn len(&self) -> usize {
+        self.pipelines.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pipelines.borrow().is_empty()
+    }
+
+    /// `unload_after` フレームの間使われず、外で [`Rc`] も持たれていないパイプラインを捨てる
+    ///
+    /// 捨てた数を返す。捨てたものは次に [`Self::get_or_create`] したときに作り直す。
+    pub(crate) fn evict_unused(&self, unload_after: u32) -> usize {
+        let frame = self.frame.get();
+        let mut pipelines = self.pipelines.borrow_mut();
+        let before = pipelines.len();
+        pipelines.retain(|_, cached| {
+            frame - cached.last_used.get() < u64::from(unload_after)
+                || Rc::strong_count(&cached.pipeline) > 1
+        });
+        self.frame.set(frame + 1);
+        before - pipelines.len()
+    }
+}
@@ -0,0 +1,117 @@
+//! シーンをどのような順番のパスで描画するかの記述
+use reverie_util::color::Color;
+use wgpu as w;
+
+use crate::scene::EntityIndex;
+
+#[derive(Debug, Clone, PartialEq)]
+/// 1 つのレンダーパスの設定
+pub struct RenderPassDesc {
+    pub label: Option<String>,
+    /// 描画を始める前に色バッファをこの色で塗りつぶす。`None` なら前の内容を残す
+    pub clear_color: Option<Color>,
+    /// 描画を始める前に深度バッファをこの値で埋める。`None` なら前の内容を残す
+    pub clear_depth: Option<f32>,
+    /// 描画を始める前にステンシルバッファをこの値で埋める。`None` なら前の内容を残す
+    pub clear_stencil: Option<u8>,
+    /// 描画に使うカメラ。`None` ならシーンのアクティブなカメラを使う
+    pub camera: Option<EntityIndex>,
+}
+
+impl Default for RenderPassDesc {
+    fn default() -> Self {
+        Self {
+            label: None,
+            clear_color: Some(Color::from_rgba8(54, 77, 118, 255)),
+            clear_depth: Some(1.0),
+            clear_stencil: Some(0),
+            camera: None,
+        }
+    }
+}
+
+impl RenderPassDesc {
+    /// 何もクリアしないパス。前のパスの結果の上に重ねて描画する
+    pub const fn overlay() -> Self {
+        Self {
+            label: None,
+            clear_color: None,
+            clear_depth: None,
+            clear_stencil: None,
+            camera: None,
+        }
+    }
+
+    pub(crate) fn color_ops(&self) -> w::Operations<w::Color> {
+        w::Operations {
+            load: self.clear_color.map_or(w::LoadOp::Load, |c| {
+                w::LoadOp::Clear(w::Color {
+                    r: c.r.into(),
+                    g: c.g.into(),
+                    b: c.b.into(),
+                    a: c.a.into(),
+                })
+            }),
+            store: w::StoreOp::Store,
+        }
+    }
+
+    pub(crate) fn depth_ops(&self) -> w::Operations<f32> {
+        w::Operations {
+            load: self.clear_depth.map_or(w::LoadOp::Load, w::LoadOp::Clear),
+            store: w::StoreOp::Store,
+        }
+    }
+
+    pub(crate) fn stencil_ops(&self) -> w::Operations<u32> {
+        w::Operations {
+            load: self
+                .clear_stencil
+                .map_or(w::LoadOp::Load, |s| w::LoadOp::Clear(s.into())),
+            store: w::StoreOp::Store,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// 順番に実行されるレンダーパスの並び
+///
+/// 例えば「スカイボックス → ワールド → UI」のように重ねて描画するときは、
+/// ワールドのパスでは深度だけ、UI のパスでは何もクリアしないようにする。
+pub struct RenderGraph {
+    pub passes: Vec<RenderPassDesc>,
+}
+
+impl Default for RenderGraph {
+    fn default() -> Self {
+        Self {
+            passes: vec![RenderPassDesc::default()],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn none_means_load() {
+        let desc = RenderPassDesc::overlay();
+        assert_eq!(desc.color_ops().load, w::LoadOp::Load);
+        assert_eq!(desc.depth_ops().load, w::LoadOp::Load);
+        assert_eq!(desc.stencil_ops().load, w::LoadOp::Load);
+    }
+
+    #[test]
+    fn some_means_clear() {
+        let desc = RenderPassDesc {
+            clear_color: Some(Color::BLACK),
+            clear_depth: Some(0.5),
+            clear_stencil: Some(3),
+            ..RenderPassDesc::overlay()
+        };
+        assert_eq!(desc.color_ops().load, w::LoadOp::Clear(w::Color::BLACK));
+        assert_eq!(desc.depth_ops().load, w::LoadOp::Clear(0.5));
+        assert_eq!(desc.stencil_ops().load, w::LoadOp::Clear(3));
+    }
+}
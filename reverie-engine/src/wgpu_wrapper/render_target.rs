@@ -0,0 +1,323 @@
+//! ウィンドウの大きさに合わせて作り直す描画先
+//!
+//! 描画先は大きさの決め方 ([`SizePolicy`]) を持って [`RenderTargets`] に登録する。
+//! ウィンドウの大きさが変わると、大きさが変わった描画先だけを作り直す。
+//! 使う側は [`RenderTargetId`] を持っておき、描画のたびに [`RenderTargets::view`] で取り出す。
+use std::num::NonZeroU32;
+
+use anyhow::Context;
+use slotmap::SlotMap;
+use wgpu as w;
+
+use super::memory::{GpuMemoryCategory, GpuMemoryTracker, TrackedAllocation};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// [`RenderTargets`] に登録した描画先を指すハンドル
+///
+/// 描画先を作り直しても変わらない。
+pub struct RenderTargetId(slotmap::DefaultKey);
+
+impl RenderTargetId {
+    #[cfg(test)]
+    pub(crate) const fn from_key(key: slotmap::DefaultKey) -> Self {
+        Self(key)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// 描画先の大きさの決め方
+pub enum SizePolicy {
+    /// 作ったときの大きさのまま変えない
+    Fixed {
+        width: NonZeroU32,
+        height: NonZeroU32,
+    },
+    /// ウィンドウの大きさに倍率を掛けた大きさ。1 より小さくはならない
+    WindowScale(f32),
+    /// 別の描画先の大きさに倍率を掛けた大きさ。ブルームの縮小バッファなどに使う
+    ///
+    /// 元の描画先が作り直されると、その後でこちらも作り直す。
+    Relative { parent: RenderTargetId, scale: f32 },
+    /// [`RenderTargets::set_explicit_size`] で指定した大きさ
+    Explicit {
+        width: NonZeroU32,
+        height: NonZeroU32,
+    },
+}
+
+#[derive(Debug, Clone)]
+/// 描画先の作り方
+pub struct RenderTargetDesc {
+    pub label: String,
+    pub format: w::TextureFormat,
+    pub usage: w::TextureUsages,
+    pub size: SizePolicy,
+}
+
+impl RenderTargetDesc {
+    /// 色を描き込み、後のパスでテクスチャとして読む描画先
+    pub fn color(label: impl Into<String>, format: w::TextureFormat, size: SizePolicy) -> Self {
+        Self {
+            label: label.into(),
+            format,
+            usage: w::TextureUsages::RENDER_ATTACHMENT | w::TextureUsages::TEXTURE_BINDING,
+            size,
+        }
+    }
+
+    /// 深度・ステンシルバッファ
+    pub fn depth_stencil(label: impl Into<String>, size: SizePolicy) -> Self {
+        Self {
+            label: label.into(),
+            format: super::WgpuResource::DEPTH_STENCIL_FORMAT,
+            usage: w::TextureUsages::RENDER_ATTACHMENT,
+            size,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    desc: RenderTargetDesc,
+    texture: w::Texture,
+    view: w::TextureView,
+    /// 作り直した回数
+    version: u64,
+    _memory: TrackedAllocation,
+}
+
+#[derive(Debug)]
+/// 描画先の置き場
+///
+/// [`super::WgpuResource::render_targets`] にある。
+pub struct RenderTargets {
+    entries: SlotMap<slotmap::DefaultKey, Entry>,
+    /// 登録した順。[`SizePolicy::Relative`] の元は必ず先に登録されているので、この順に作り直せばよい
+    order: Vec<slotmap::DefaultKey>,
+    window_size: (NonZeroU32, NonZeroU32),
+    memory: GpuMemoryTracker,
+}
+
+impl RenderTargets {
+    pub(crate) fn new(width: NonZeroU32, height: NonZeroU32, memory: GpuMemoryTracker) -> Self {
+        Self {
+            entries: SlotMap::default(),
+            order: Vec::new(),
+            window_size: (width, height),
+            memory,
+        }
+    }
+
+    /// 描画先を作って登録する
+    ///
+    /// [`SizePolicy::Relative`] の元が登録されていなければエラーになる。
+    pub fn insert(
+        &mut self,
+        device: &w::Device,
+        desc: RenderTargetDesc,
+    ) -> anyhow::Result<RenderTargetId> {
+        let (width, height) = self.resolve(&desc.size)?;
+        let (texture, view, memory) = self.create(device, &desc, width, height);
+        let key = self.entries.insert(Entry {
+            desc,
+            texture,
+            view,
+            version: 0,
+            _memory: memory,
+        });
+        self.order.push(key);
+        Ok(RenderTargetId(key))
+    }
+
+    /// 描画先を取り除く
+    ///
+    /// [`SizePolicy::Relative`] で大きさを参照している描画先が残っていればエラーになる。
+    pub fn remove(&mut self, id: RenderTargetId) -> anyhow::Result<()> {
+        let dependent = self.entries.values().find(
+            |entry| matches!(entry.desc.size, SizePolicy::Relative { parent, .. } if parent == id),
+        );
+        if let Some(dependent) = dependent {
+            anyhow::bail!(
+                "render target `{}` still depends on this target",
+                dependent.desc.label
+            );
+        }
+        self.entries
+            .remove(id.0)
+            .context("render target is not registered")?;
+        self.order.retain(|&key| key != id.0);
+        Ok(())
+    }
+
+    /// 描画先のビュー。作り直されていれば新しいものを返す
+    pub fn view(&self, id: RenderTargetId) -> Option<&w::TextureView> {
+        self.entries.get(id.0).map(|entry| &entry.view)
+    }
+
+    pub fn texture(&self, id: RenderTargetId) -> Option<&w::Texture> {
+        self.entries.get(id.0).map(|entry| &entry.texture)
+    }
+
+    /// 描画先の今の大きさ
+    pub fn size(&self, id: RenderTargetId) -> Option<(u32, u32)> {
+        self.texture(id)
+            .map(|texture| (texture.width(), texture.height()))
+    }
+
+    /// 描画先を作り直した回数
+    ///
+    /// ビューから作ったバインドグループなどを持っている場合は、これが変わったら作り直す。
+    pub fn version(&self, id: RenderTargetId) -> Option<u64> {
+        self.entries.get(id.0).map(|entry| entry.version)
+    }
+
+    pub fn desc(&self, id: RenderTargetId) -> Option<&RenderTargetDesc> {
+        self.entries.get(id.0).map(|entry| &entry.desc)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// [`SizePolicy::Explicit`] の描画先の大きさを変える
+    ///
+    /// それを元にした描画先も作り直す。
+    pub fn set_explicit_size(
+        &mut self,
+        device: &w::Device,
+        id: RenderTargetId,
+        width: NonZeroU32,
+        height: NonZeroU32,
+    ) -> anyhow::Result<()> {
+        let entry = self
+            .entries
+            .get_mut(id.0)
+            .context("render target is not registered")?;
+        let SizePolicy::Explicit {
+            width: w,
+            height: h,
+        } = &mut entry.desc.size
+        else {
+            anyhow::bail!(
+                "render target `{}` does not have an explicit size",
+                entry.desc.label
+            );
+        };
+        *w = width;
+        *h = height;
+        self.refresh(device)
+    }
+
+    /// ウィンドウの大きさが変わったことを知らせる
+    pub(crate) fn resize(
+        &mut self,
+        device: &w::Device,
+        width: NonZeroU32,
+        height: NonZeroU32,
+    ) -> anyhow::Result<()> {
+        self.window_size = (width, height);
+        self.refresh(device)
+    }
+
+    /// 大きさが変わった描画先を、元になる描画先から順に作り直す
+    fn refresh(&mut self, device: &w::Device) -> anyhow::Result<()> {
+        for i in 0..self.order.len() {
+            let key = self.order[i];
+            let (width, height) = self.resolve(&self.entries[key].desc.size)?;
+            let texture = &self.entries[key].texture;
+            if (texture.width(), texture.height()) == (width.get(), height.get()) {
+                continue;
+            }
+            let (texture, view, memory) =
+                self.create(device, &self.entries[key].desc, width, height);
+            let entry = &mut self.entries[key];
+            tracing::trace!(
+                label = entry.desc.label,
+                width,
+                height,
+                "recreate render target"
+            );
+            entry.texture = texture;
+            entry.view = view;
+            entry.version += 1;
+            entry._memory = memory;
+        }
+        Ok(())
+    }
+
+    fn resolve(&self, size: &SizePolicy) -> anyhow::Result<(NonZeroU32, NonZeroU32)> {
+        Ok(match *size {
+            SizePolicy::Fixed { width, height } | SizePolicy::Explicit { width, height } => {
+                (width, height)
+            }
+            SizePolicy::WindowScale(s) => {
+                (scale(self.window_size.0, s), scale(self.window_size.1, s))
+            }
+            SizePolicy::Relative { parent, scale: s } => {
+                let parent = self
+                    .entries
+                    .get(parent.0)
+                    .context("parent render target is not registered")?;
+                (
+                    scale(parent.texture.width(), s),
+                    scale(parent.texture.height(), s),
+                )
+            }
+        })
+    }
+
+    fn create(
+        &self,
+        device: &w::Device,
+        desc: &RenderTargetDesc,
+        width: NonZeroU32,
+        height: NonZeroU32,
+    ) -> (w::Texture, w::TextureView, TrackedAllocation) {
+        let texture = device.create_texture(&w::TextureDescriptor {
+            label: Some(&desc.label),
+            size: w::Extent3d {
+                width: width.get(),
+                height: height.get(),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: w::TextureDimension::D2,
+            format: desc.format,
+            usage: desc.usage,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&w::TextureViewDescriptor::default());
+        // 深度・ステンシルのフォーマットはコピーできないので大きさが分からない。およそ 4 バイトとする
+        let bytes_per_pixel = desc.format.block_copy_size(None).unwrap_or(4);
+        let memory = self.memory.track(
+            GpuMemoryCategory::RenderTarget,
+            desc.label.clone(),
+            u64::from(width.get()) * u64::from(height.get()) * u64::from(bytes_per_pixel),
+        );
+        (texture, view, memory)
+    }
+}
+
+/// `size` に `factor` を掛けて丸める。1 より小さくはならない
+fn scale(size: impl Into<u32>, factor: f32) -> NonZeroU32 {
+    let scaled = (size.into() as f32 * factor).round() as u32;
+    NonZeroU32::new(scaled).unwrap_or(NonZeroU32::MIN)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scale_rounds_and_never_reaches_zero() {
+        assert_eq!(scale(101u32, 0.5).get(), 51);
+        assert_eq!(scale(NonZeroU32::new(640).unwrap(), 0.25).get(), 160);
+        assert_eq!(scale(3u32, 0.1).get(), 1);
+        assert_eq!(scale(3u32, 0.0).get(), 1);
+    }
+}
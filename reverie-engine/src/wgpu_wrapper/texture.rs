@@ -13,9 +13,67 @@ impl WgpuTexture {
         queue: &w::Queue,
         image: &image::RgbaImage,
         label: Option<&str>,
+    ) -> Self {
+        Self::from_image_with_usage(device, queue, image, label, w::TextureUsages::empty())
+    }
+
+    /// [`Self::from_image`] と同じだが、`usage` の使い方も許す
+    ///
+    /// 描画先にするなら [`w::TextureUsages::RENDER_ATTACHMENT`] を渡す。
+    pub fn from_image_with_usage(
+        device: &w::Device,
+        queue: &w::Queue,
+        image: &image::RgbaImage,
+        label: Option<&str>,
+        usage: w::TextureUsages,
+    ) -> Self {
+        let texture = Self::create(device, image.dimensions(), 1, label, usage);
+        texture.write_image(queue, image);
+        texture
+    }
+
+    /// [`Self::from_image`] と同じだが、縦横を半分ずつに縮小したミップマップを 1x1 まですべて作る
+    ///
+    /// 縮小は CPU で行う。[`Self::write_image`] で書き換えても縮小した段は変わらないので、
+    /// 後から書き換えるテクスチャには使わない。
+    pub fn from_image_with_mips(
+        device: &w::Device,
+        queue: &w::Queue,
+        image: &image::RgbaImage,
+        label: Option<&str>,
     ) -> Self {
         let (width, height) = image.dimensions();
+        let levels = mip_level_count(width, height);
+        let texture = Self::create(
+            device,
+            (width, height),
+            levels,
+            label,
+            w::TextureUsages::empty(),
+        );
+        let mut level_image = std::borrow::Cow::Borrowed(image);
+        for level in 0..levels {
+            if level > 0 {
+                let (width, height) = level_image.dimensions();
+                level_image = std::borrow::Cow::Owned(image::imageops::resize(
+                    &*level_image,
+                    (width / 2).max(1),
+                    (height / 2).max(1),
+                    image::imageops::FilterType::Triangle,
+                ));
+            }
+            texture.write_level(queue, &level_image, level);
+        }
+        texture
+    }
 
+    fn create(
+        device: &w::Device,
+        (width, height): (u32, u32),
+        mip_level_count: u32,
+        label: Option<&str>,
+        usage: w::TextureUsages,
+    ) -> Self {
         let size = w::Extent3d {
             width,
             height,
@@ -24,28 +82,63 @@ impl WgpuTexture {
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label,
             size,
-            mip_level_count: 1,
+            mip_level_count,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST | usage,
             view_formats: &[],
         });
+        let view = texture.create_view(&Default::default());
+        Self { texture, view }
+    }
+
+    /// `image` で内容を置き換える。大きさは同じでなければならない
+    ///
+    /// 書き換えるのは最も大きい段だけ。
+    pub(crate) fn write_image(&self, queue: &w::Queue, image: &image::RgbaImage) {
+        self.write_level(queue, image, 0);
+    }
 
+    fn write_level(&self, queue: &w::Queue, image: &image::RgbaImage, mip_level: u32) {
+        let (width, height) = image.dimensions();
         queue.write_texture(
-            texture.as_image_copy(),
+            w::ImageCopyTexture {
+                texture: &self.texture,
+                mip_level,
+                origin: w::Origin3d::ZERO,
+                aspect: w::TextureAspect::All,
+            },
             image,
             w::ImageDataLayout {
                 offset: 0,
                 bytes_per_row: Some(width * 4),
                 rows_per_image: Some(height),
             },
-            size,
+            w::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
         );
+    }
 
-        let view = texture.create_view(&Default::default());
+    /// ミップマップの段の数
+    pub fn mip_level_count(&self) -> u32 {
+        self.texture.mip_level_count()
+    }
 
-        Self { texture, view }
+    /// すべての段を合わせたバイト数
+    pub fn byte_size(&self) -> u64 {
+        (0..self.mip_level_count())
+            .map(|level| {
+                let size = self
+                    .texture
+                    .size()
+                    .mip_level_size(level, w::TextureDimension::D2);
+                u64::from(size.width) * u64::from(size.height) * 4
+            })
+            .sum()
     }
 
     pub fn width(&self) -> u32 {
@@ -110,3 +203,21 @@ impl WgpuTexture {
         })
     }
 }
+
+/// `width`x`height` の画像を 1x1 まで半分ずつ縮小したときの段の数
+pub fn mip_level_count(width: u32, height: u32) -> u32 {
+    u32::BITS - width.max(height).max(1).leading_zeros()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mip_levels_reach_one_pixel() {
+        assert_eq!(mip_level_count(1, 1), 1);
+        assert_eq!(mip_level_count(16, 16), 5);
+        assert_eq!(mip_level_count(17, 3), 5);
+        assert_eq!(mip_level_count(64, 32), 7);
+    }
+}
@@ -0,0 +1,205 @@
+//! 毎フレーム書き換えるバッファを複数持って順番に使う仕組み
+//!
+//! 1 つのバッファを毎フレーム `queue.write_buffer` で書き換えると、前のフレームの描画が
+//! そのバッファを読み終わるまで書き込みが待たされることがある。[`UploadRing`] は
+//! [`GraphicsConfig::upload_ring_depth`](super::GraphicsConfig::upload_ring_depth) 個の
+//! バッファを持ち、フレームごとに別のものに書き込む。
+//!
+//! GPU がまだ読んでいるかもしれないバッファに書き込んでしまったときは
+//! [`FrameTracker::overruns`] が増える。
+use std::{
+    cell::Cell,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+/// [`super::GraphicsConfig::upload_ring_depth`] の既定値
+pub const DEFAULT_UPLOAD_RING_DEPTH: usize = 3;
+
+#[derive(Debug)]
+/// GPU に送ったフレームと、GPU が処理し終えたフレームを数える
+pub struct FrameTracker {
+    depth: usize,
+    /// 送ったフレームの数。今記録しているフレームの番号でもある
+    submitted: Cell<u64>,
+    /// GPU が処理し終えたフレームの数
+    completed: Arc<AtomicU64>,
+    overruns: Cell<u64>,
+}
+
+impl FrameTracker {
+    /// `depth` 個のバッファを順番に使う設定で作る。0 は 1 として扱う
+    pub fn new(depth: usize) -> Self {
+        Self {
+            depth: depth.max(1),
+            submitted: Cell::new(0),
+            completed: Arc::new(AtomicU64::new(0)),
+            overruns: Cell::new(0),
+        }
+    }
+
+    /// 1 つの [`UploadRing`] が持つバッファの数
+    pub const fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// 今記録しているフレームの番号
+    pub fn current_frame(&self) -> u64 {
+        self.submitted.get()
+    }
+
+    /// GPU が処理し終えたフレームの数
+    pub fn completed_frames(&self) -> u64 {
+        self.completed.load(Ordering::Acquire)
+    }
+
+    /// GPU が処理し終えていないフレームの数
+    pub fn frames_in_flight(&self) -> u64 {
+        self.current_frame().saturating_sub(self.completed_frames())
+    }
+
+    /// GPU が読んでいるかもしれないバッファに書き込んだ回数
+    ///
+    /// 増え続けるなら [`super::GraphicsConfig::upload_ring_depth`] を大きくする。
+    pub fn overruns(&self) -> u64 {
+        self.overruns.get()
+    }
+
+    /// フレームのコマンドを送った直後に呼ぶ
+    pub(crate) fn end_frame(&self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let frame = self.submit();
+        let completed = Arc::clone(&self.completed);
+        queue.on_submitted_work_done(move || {
+            completed.fetch_max(frame + 1, Ordering::AcqRel);
+        });
+        // 終わったフレームのコールバックを呼ばせる。待ちはしない
+        device.poll(wgpu::Maintain::Poll);
+    }
+
+    /// 今のフレームを送ったことにして、その番号を返す
+    fn submit(&self) -> u64 {
+        let frame = self.submitted.get();
+        self.submitted.set(frame + 1);
+        frame
+    }
+
+    /// `last_frame` で使ったバッファに書き込めるか調べ、まだ使われていれば数える
+    fn check_reuse(&self, last_frame: Option<u64>) {
+        // 今のフレームで既に使ったものも、書き込むと先の描画の内容が変わってしまう
+        if !last_frame.is_some_and(|frame| frame >= self.completed_frames()) {
+            return;
+        }
+        if self.overruns.get() == 0 {
+            tracing::warn!(
+                depth = self.depth,
+                frames_in_flight = self.frames_in_flight(),
+                "upload ring overrun: writing a buffer the GPU may still be reading"
+            );
+        }
+        self.overruns.set(self.overruns.get() + 1);
+    }
+}
+
+impl Default for FrameTracker {
+    fn default() -> Self {
+        Self::new(DEFAULT_UPLOAD_RING_DEPTH)
+    }
+}
+
+#[derive(Debug)]
+struct Slot<T> {
+    value: T,
+    /// 最後に書き込んだフレーム
+    last_frame: Option<u64>,
+}
+
+#[derive(Debug)]
+/// 毎フレーム書き換える GPU のリソースを [`FrameTracker::depth`] 個持ち、順番に使う
+pub struct UploadRing<T> {
+    slots: Vec<Slot<T>>,
+    current: usize,
+}
+
+impl<T> UploadRing<T> {
+    /// `make` でリソースを `frames.depth()` 個作る。引数は何番目のリソースか
+    pub fn new(frames: &FrameTracker, make: impl FnMut(usize) -> T) -> Self {
+        let slots = (0..frames.depth())
+            .map(make)
+            .map(|value| Slot {
+                value,
+                last_frame: None,
+            })
+            .collect();
+        Self { slots, current: 0 }
+    }
+
+    /// 最後に [`Self::advance`] で選んだリソース
+    pub fn current(&self) -> &T {
+        &self.slots[self.current].value
+    }
+
+    /// 次のリソースに進み、今のフレームで書き込むものとして返す
+    pub fn advance(&mut self, frames: &FrameTracker) -> &mut T {
+        self.current = (self.current + 1) % self.slots.len();
+        let slot = &mut self.slots[self.current];
+        frames.check_reuse(slot.last_frame);
+        slot.last_frame = Some(frames.current_frame());
+        &mut slot.value
+    }
+
+    /// リソースの数
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// リソースが無いかどうか。[`FrameTracker::depth`] は 1 以上なので常に `false`
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_rotates_through_slots() {
+        let frames = FrameTracker::new(3);
+        let mut ring = UploadRing::new(&frames, |i| i);
+        let mut used = Vec::new();
+        for _ in 0..4 {
+            used.push(*ring.advance(&frames));
+            frames.submit();
+            frames
+                .completed
+                .store(frames.current_frame(), Ordering::Release);
+        }
+        assert_eq!(used, [1, 2, 0, 1]);
+        assert_eq!(*ring.current(), 1);
+        assert_eq!(frames.overruns(), 0);
+    }
+
+    #[test]
+    fn overrun_when_gpu_falls_behind() {
+        let frames = FrameTracker::new(2);
+        let mut ring = UploadRing::new(&frames, |i| i);
+        // GPU が 1 フレームも終えないまま 2 フレーム送る
+        for _ in 0..2 {
+            ring.advance(&frames);
+            frames.submit();
+        }
+        assert_eq!(frames.overruns(), 0);
+        ring.advance(&frames);
+        assert_eq!(frames.overruns(), 1);
+
+        // 同じフレームで 1 周しても上書きになる
+        let frames = FrameTracker::new(2);
+        let mut ring = UploadRing::new(&frames, |i| i);
+        ring.advance(&frames);
+        ring.advance(&frames);
+        ring.advance(&frames);
+        assert_eq!(frames.overruns(), 1);
+    }
+}
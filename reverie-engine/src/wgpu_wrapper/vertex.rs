@@ -6,9 +6,11 @@ use wgpu as w;
 ///
 /// * `position`: 頂点の位置
 /// * `uv`: UV 座標
+/// * `color`: テクスチャの色に掛ける RGBA の色
 pub struct UvVertex {
     pub position: [f32; 3],
     pub uv: [f32; 2],
+    pub color: [f32; 4],
 }
 
 impl UvVertex {
@@ -27,7 +29,142 @@ impl UvVertex {
                     shader_location: 1,
                     format: w::VertexFormat::Float32x2,
                 },
+                w::VertexAttribute {
+                    offset: size_of::<[f32; 5]>() as w::BufferAddress,
+                    shader_location: 2,
+                    format: w::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+/// 色を持つ頂点
+///
+/// * `position`: 頂点の位置
+/// * `color`: RGBA の色
+pub struct ColorVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 4],
+}
+
+impl ColorVertex {
+    pub const fn desc() -> w::VertexBufferLayout<'static> {
+        w::VertexBufferLayout {
+            array_stride: size_of::<Self>() as w::BufferAddress,
+            step_mode: w::VertexStepMode::Vertex,
+            attributes: &[
+                w::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: w::VertexFormat::Float32x3,
+                },
+                w::VertexAttribute {
+                    offset: size_of::<[f32; 3]>() as w::BufferAddress,
+                    shader_location: 1,
+                    format: w::VertexFormat::Float32x4,
+                },
             ],
         }
     }
 }
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+/// タイルマップの頂点
+///
+/// * `position`: タイルマップの中での頂点の位置
+/// * `corner`: タイルの角。1 の位が右、2 の位が下
+/// * `tile`: タイルセットの中のタイルの番号
+/// * `animation`: アニメーションの番号。アニメーションしなければ [`Self::NO_ANIMATION`]
+/// * `tint`: テクスチャの色に掛ける RGBA の色
+pub struct TileVertex {
+    pub position: [f32; 2],
+    pub corner: u32,
+    pub tile: u32,
+    pub animation: u32,
+    pub tint: [f32; 4],
+}
+
+impl TileVertex {
+    pub const NO_ANIMATION: u32 = u32::MAX;
+    const ATTRIBUTES: [w::VertexAttribute; 5] = w::vertex_attr_array![
+        0 => Float32x2,
+        1 => Uint32,
+        2 => Uint32,
+        3 => Uint32,
+        4 => Float32x4,
+    ];
+
+    pub const fn desc() -> w::VertexBufferLayout<'static> {
+        w::VertexBufferLayout {
+            array_stride: size_of::<Self>() as w::BufferAddress,
+            step_mode: w::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+/// UV をスクロールするスプライトのインスタンスごとのデータ
+///
+/// * `rect`: テクスチャのアトラスの中の範囲。最小の u, v、最大の u, v の順
+/// * `velocity`: 1 秒あたりに動かす UV
+/// * `rotation`: 1 秒あたりに回す角度 (ラジアン)
+pub struct UvScrollInstance {
+    pub rect: [f32; 4],
+    pub velocity: [f32; 2],
+    pub rotation: f32,
+    pub _pad: f32,
+}
+
+impl UvScrollInstance {
+    const ATTRIBUTES: [w::VertexAttribute; 3] = w::vertex_attr_array![
+        3 => Float32x4,
+        4 => Float32x2,
+        5 => Float32,
+    ];
+
+    pub const fn desc() -> w::VertexBufferLayout<'static> {
+        w::VertexBufferLayout {
+            array_stride: size_of::<Self>() as w::BufferAddress,
+            step_mode: w::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+/// 浮かぶ文字列のグリフ 1 つ分のインスタンスのデータ
+///
+/// * `position`: グリフの左上のワールド座標
+/// * `size`: グリフの幅と高さ (ワールド座標)
+/// * `uv`: グリフのアトラスの中の範囲。最小の u, v、最大の u, v の順
+/// * `color`: 文字の色
+pub struct GlyphInstance {
+    pub position: [f32; 3],
+    pub size: [f32; 2],
+    pub uv: [f32; 4],
+    pub color: [f32; 4],
+}
+
+impl GlyphInstance {
+    const ATTRIBUTES: [w::VertexAttribute; 4] = w::vertex_attr_array![
+        0 => Float32x3,
+        1 => Float32x2,
+        2 => Float32x4,
+        3 => Float32x4,
+    ];
+
+    pub const fn desc() -> w::VertexBufferLayout<'static> {
+        w::VertexBufferLayout {
+            array_stride: size_of::<Self>() as w::BufferAddress,
+            step_mode: w::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
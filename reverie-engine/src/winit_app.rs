@@ -1,71 +1,129 @@
 //! winit のイベントループを使ったアプリケーションの実行を行うモジュール
-use std::{num::NonZeroU32, sync::Arc, time::Instant};
+use std::{num::NonZeroU32, sync::Arc};
 
 use anyhow::Context;
 use tracing_unwrap::ResultExt;
+use web_time::Instant;
 use wgpu::rwh::{HasDisplayHandle, HasWindowHandle};
 use winit::{
     application::ApplicationHandler,
     dpi::PhysicalPosition,
     event::{ElementState, KeyEvent, MouseButton, MouseScrollDelta, TouchPhase, WindowEvent},
-    event_loop::ActiveEventLoop,
+    event_loop::{ActiveEventLoop, EventLoopProxy},
     window::Window,
 };
 
 use crate::{
-    game::Game,
+    game::{EngineConfig, Game},
     scene::{Frame, Scene},
-    wgpu_wrapper::WgpuResource,
+    settings::{Settings, WindowSettings},
+    touch::{TouchTracker, MOUSE_TOUCH_ID},
+    wgpu_wrapper::{GraphicsConfig, WgpuResource},
 };
 
-pub struct App<'window, G: Game> {
+/// イベントループに送られるアプリケーション独自のイベント
+pub enum AppEvent {
+    /// 非同期に初期化したリソースの準備ができた
+    ResourceReady(AppResource<'static>),
+}
+
+pub struct App<G: Game> {
     game: G,
+    config: EngineConfig,
     scene: Option<Scene>,
-    resource: Option<AppResource<'window>>,
+    resource: Option<AppResource<'static>>,
+    /// リソースを初期化している途中かどうか
+    initializing: bool,
+    proxy: EventLoopProxy<AppEvent>,
     last_update: Instant,
     key_events: Vec<KeyEvent>,
     mouse_clicks: Vec<(ElementState, MouseButton, PhysicalPosition<f64>)>,
     mouse_wheels: Vec<(MouseScrollDelta, TouchPhase, PhysicalPosition<f64>)>,
     last_mouse_pos: PhysicalPosition<f64>,
+    /// 左ボタンが押されているか (マウスからタッチを合成するときに使う)
+    mouse_pressed: bool,
+    touch: TouchTracker,
+    /// ウィンドウと表示に反映した設定
+    applied_window: Option<WindowSettings>,
 }
 
-impl<G: Game> App<'_, G> {
-    pub fn new(game: G) -> Self {
+impl<G: Game> App<G> {
+    pub fn new(game: G, config: EngineConfig, proxy: EventLoopProxy<AppEvent>) -> Self {
         Self {
             game,
+            config,
             scene: None,
             resource: None,
+            initializing: false,
+            proxy,
             last_update: Instant::now(),
             key_events: Vec::new(),
             mouse_clicks: Vec::new(),
             mouse_wheels: Vec::new(),
             last_mouse_pos: PhysicalPosition::new(0.0, 0.0),
+            mouse_pressed: false,
+            touch: TouchTracker::default(),
+            applied_window: None,
         }
     }
 
     #[tracing::instrument(level = "trace", skip(self))]
     fn setup(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
-        if self.resource.is_none() {
-            let mut r = AppResource::new(event_loop).unwrap_or_log();
-            let mut scene = self
-                .game
-                .generate_scene(&mut r.wgpu.texture_registry)
-                .context("failed: generate scene")
-                .unwrap_or_log();
-            r.wgpu.texture_registry.send_all_to_gpu(
-                &r.wgpu.device,
-                &r.wgpu.queue,
-                &r.wgpu.texture_bind_group_layout,
-                &r.wgpu.texture_sampler,
-                WgpuResource::TEXTURE_BINDING,
-                WgpuResource::SAMPLER_BINDING,
-            );
-            println!("{:?}", scene);
-            scene.setup(&r.wgpu);
-
-            self.resource = Some(r);
-            self.scene = Some(scene);
+        if self.resource.is_some() || self.initializing {
+            return;
         }
+        self.initializing = true;
+
+        let window =
+            AppResource::create_window(event_loop, &self.config.settings.window).unwrap_or_log();
+        let resource = AppResource::new(window, self.config.graphics.clone());
+        let proxy = self.proxy.clone();
+        let notify = move |r| {
+            if proxy.send_event(AppEvent::ResourceReady(r)).is_err() {
+                tracing::warn!("event loop is already closed");
+            }
+        };
+
+        #[cfg(not(target_arch = "wasm32"))]
+        notify(pollster::block_on(resource).unwrap_or_log());
+
+        // Web ではブロックできないので、初期化が終わったらイベントループに通知してもらう
+        #[cfg(target_arch = "wasm32")]
+        wasm_bindgen_futures::spawn_local(async move {
+            notify(resource.await.unwrap_or_log());
+        });
+    }
+
+    fn on_resource_ready(&mut self, mut r: AppResource<'static>) {
+        let mut scene = self
+            .game
+            .generate_scene(&mut r.wgpu.texture_registry)
+            .context("failed: generate scene")
+            .unwrap_or_log();
+        scene.apply_settings(&self.config.settings);
+        r.wgpu.texture_registry.send_all_to_gpu(
+            &r.wgpu.device,
+            &r.wgpu.queue,
+            &r.wgpu.texture_bind_group_layout,
+            &r.wgpu.texture_sampler,
+            WgpuResource::TEXTURE_BINDING,
+            WgpuResource::SAMPLER_BINDING,
+        );
+        println!("{:?}", scene);
+        scene
+            .setup(&r.wgpu)
+            .context("failed: setup scene")
+            .unwrap_or_log();
+
+        // ウィンドウはこの設定で作ったので、表示の設定だけが反映される
+        let window = &self.config.settings.window;
+        r.apply_window_settings(window, Some(window));
+        self.applied_window = Some(self.config.settings.window.clone());
+        r.window.0.request_redraw();
+        self.resource = Some(r);
+        self.scene = Some(scene);
+        self.initializing = false;
+        self.last_update = Instant::now();
     }
 
     fn update(&mut self) {
@@ -78,22 +136,38 @@ impl<G: Game> App<'_, G> {
                 mouse_clicks: self.mouse_clicks.as_slice(),
                 mouse_wheels: self.mouse_wheels.as_slice(),
                 mouse_position: self.last_mouse_pos,
+                touches: self.touch.touches(),
+                gestures: self.touch.gestures(),
+                window_size: r.window.0.inner_size(),
+                scale_factor: r.window.0.scale_factor(),
             };
 
+            r.wgpu.maintain_textures();
             scene.update(&frame, &r.wgpu);
+            // システムやゲームが Scene::apply_settings で変えたものを反映する
+            if let Some(window) = scene.resource::<Settings>().map(|s| s.window.clone()) {
+                if self.applied_window.as_ref() != Some(&window) {
+                    r.apply_window_settings(&window, self.applied_window.as_ref());
+                    self.applied_window = Some(window);
+                }
+            }
+            r.wgpu.collect_unused_assets(scene);
 
             self.last_update = now;
             self.key_events.clear();
             self.mouse_clicks.clear();
             self.mouse_wheels.clear();
+            self.touch.end_frame();
 
-            r.wgpu.render(scene);
+            let game = &mut self.game;
+            r.wgpu
+                .render_with(scene, |frame, scene| game.render(frame, scene));
             r.window.0.request_redraw();
         }
     }
 }
 
-impl<G: Game> ApplicationHandler for App<'_, G> {
+impl<G: Game> ApplicationHandler<AppEvent> for App<G> {
     fn new_events(
         &mut self,
         _event_loop: &winit::event_loop::ActiveEventLoop,
@@ -110,6 +184,12 @@ impl<G: Game> ApplicationHandler for App<'_, G> {
         self.setup(event_loop);
     }
 
+    fn user_event(&mut self, _event_loop: &ActiveEventLoop, event: AppEvent) {
+        match event {
+            AppEvent::ResourceReady(r) => self.on_resource_ready(r),
+        }
+    }
+
     fn window_event(
         &mut self,
         _event_loop: &winit::event_loop::ActiveEventLoop,
@@ -131,16 +211,32 @@ impl<G: Game> ApplicationHandler for App<'_, G> {
                 }
             }
             WindowEvent::RedrawRequested => self.update(),
-            WindowEvent::KeyboardInput { event, .. } => {
-                if self.resource.is_some() {
-                    self.key_events.push(event);
-                }
+            WindowEvent::KeyboardInput { event, .. } if self.resource.is_some() => {
+                self.key_events.push(event);
             }
             WindowEvent::CursorMoved { position, .. } => {
                 self.last_mouse_pos = position;
+                if self.config.synthesize_touch_from_mouse && self.mouse_pressed {
+                    self.touch
+                        .handle(MOUSE_TOUCH_ID, position, TouchPhase::Moved, Instant::now());
+                }
             }
             WindowEvent::MouseInput { state, button, .. } => {
                 self.mouse_clicks.push((state, button, self.last_mouse_pos));
+                if self.config.synthesize_touch_from_mouse && button == MouseButton::Left {
+                    self.mouse_pressed = state.is_pressed();
+                    let phase = if self.mouse_pressed {
+                        TouchPhase::Started
+                    } else {
+                        TouchPhase::Ended
+                    };
+                    self.touch
+                        .handle(MOUSE_TOUCH_ID, self.last_mouse_pos, phase, Instant::now());
+                }
+            }
+            WindowEvent::Touch(touch) => {
+                self.touch
+                    .handle(touch.id, touch.location, touch.phase, Instant::now());
             }
             WindowEvent::MouseWheel { delta, phase, .. } => {
                 self.mouse_wheels.push((delta, phase, self.last_mouse_pos));
@@ -150,7 +246,7 @@ impl<G: Game> ApplicationHandler for App<'_, G> {
     }
 
     fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
-        if self.resource.is_none() {
+        if self.resource.is_none() && !self.initializing {
             event_loop.exit();
         }
     }
@@ -161,27 +257,80 @@ pub struct AppResource<'window> {
     pub wgpu: WgpuResource<'window>,
 }
 
-impl AppResource<'_> {
-    pub fn new(event_loop: &ActiveEventLoop) -> anyhow::Result<Self> {
+impl AppResource<'static> {
+    pub fn create_window(
+        event_loop: &ActiveEventLoop,
+        settings: &WindowSettings,
+    ) -> anyhow::Result<ArcWindow> {
+        let attributes = Window::default_attributes();
+        #[cfg(not(target_arch = "wasm32"))]
+        let attributes = attributes
+            .with_inner_size(winit::dpi::LogicalSize::new(
+                settings.width,
+                settings.height,
+            ))
+            .with_fullscreen(
+                settings
+                    .fullscreen
+                    .then_some(winit::window::Fullscreen::Borderless(None)),
+            );
+        #[cfg(target_arch = "wasm32")]
+        let attributes = {
+            use winit::platform::web::WindowAttributesExtWebSys;
+            let _ = settings;
+            attributes.with_append(true)
+        };
         let window = event_loop
-            .create_window(Window::default_attributes())
-            .unwrap_or_log();
-        let window = ArcWindow(Arc::new(window));
+            .create_window(attributes)
+            .context("failed: create window")?;
+        Ok(ArcWindow(Arc::new(window)))
+    }
+
+    pub async fn new(window: ArcWindow, config: GraphicsConfig) -> anyhow::Result<Self> {
         let size = window.0.inner_size();
-        let width = size
-            .width
-            .try_into()
-            .context("error: window inner width is zero")?;
-        let height = size
-            .height
-            .try_into()
-            .context("error: window inner height is zero")?;
-
-        let wgpu = pollster::block_on(WgpuResource::setup(window.clone(), width, height))
+        // Web ではキャンバスが配置されるまで大きさが 0 になることがある。
+        // 正しい大きさは Resized イベントで設定される
+        let width = NonZeroU32::new(size.width).unwrap_or(NonZeroU32::MIN);
+        let height = NonZeroU32::new(size.height).unwrap_or(NonZeroU32::MIN);
+
+        let wgpu = WgpuResource::setup(window.clone(), width, height, &config)
+            .await
             .context("failed: setup wgpu")?;
 
         Ok(Self { window, wgpu })
     }
+
+    /// ウィンドウと表示の設定を反映する。`previous` と同じ項目は変えない
+    pub fn apply_window_settings(
+        &mut self,
+        settings: &WindowSettings,
+        previous: Option<&WindowSettings>,
+    ) {
+        self.wgpu.set_vsync(settings.vsync);
+        // Web ではキャンバスの大きさをページが決める
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let window = &self.window.0;
+            if previous.map(|p| p.fullscreen) != Some(settings.fullscreen) {
+                window.set_fullscreen(
+                    settings
+                        .fullscreen
+                        .then_some(winit::window::Fullscreen::Borderless(None)),
+                );
+            }
+            let resized =
+                previous.map(|p| (p.width, p.height)) != Some((settings.width, settings.height));
+            if resized && !settings.fullscreen {
+                // 大きさが変わると Resized イベントで surface も作り直される
+                let _ = window.request_inner_size(winit::dpi::LogicalSize::new(
+                    settings.width,
+                    settings.height,
+                ));
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        let _ = previous;
+    }
 }
 
 #[derive(Clone)]
@@ -0,0 +1,110 @@
+"""テスト用の小さなフォントを作る
+
+グリフはすべて四角形で、幅が決まっているのでレイアウトのテストで位置を計算しやすい。
+
+- latin.ttf: ASCII の印字可能文字。幅 500、空白だけ幅 250
+- cjk.ttf: ひらがな、カタカナ、CJK の句読点と統合漢字。幅 1000。ASCII は含まない
+
+どちらも 1 em = 1000、ascender 800、descender -200。
+"""
+import struct
+from pathlib import Path
+
+UNITS_PER_EM = 1000
+ASCENDER = 800
+DESCENDER = -200
+
+
+def rect_glyph(x_min, y_min, x_max, y_max):
+    points = [(x_min, y_min), (x_min, y_max), (x_max, y_max), (x_max, y_min)]
+    data = struct.pack(">hhhhh", 1, x_min, y_min, x_max, y_max)
+    data += struct.pack(">H", len(points) - 1)  # endPtsOfContours
+    data += struct.pack(">H", 0)  # instructionLength
+    data += bytes([0x01] * len(points))  # on curve, 16 bit の座標
+    prev = 0
+    for x, _ in points:
+        data += struct.pack(">h", x - prev)
+        prev = x
+    prev = 0
+    for _, y in points:
+        data += struct.pack(">h", y - prev)
+        prev = y
+    if len(data) % 2:
+        data += b"\0"
+    return data
+
+
+def build_font(glyphs, cmap_groups, cmap_format):
+    """glyphs: (advance, glyf のデータ) の列。cmap_groups: (start, end, glyph id) の列"""
+    glyf = b""
+    loca = []
+    for _, data in glyphs:
+        loca.append(len(glyf))
+        glyf += data
+    loca.append(len(glyf))
+
+    head = struct.pack(
+        ">IIIIHHqqhhhhHHhhh",
+        0x00010000, 0x00010000, 0, 0x5F0F3CF5, 0x000B, UNITS_PER_EM,
+        0, 0, 0, DESCENDER, UNITS_PER_EM, ASCENDER, 0, 8, 2, 1, 0,
+    )
+    max_advance = max(advance for advance, _ in glyphs)
+    hhea = struct.pack(
+        ">IhhhHhhhhhhhhhhhH",
+        0x00010000, ASCENDER, DESCENDER, 0, max_advance, 0, 0, max_advance,
+        1, 0, 0, 0, 0, 0, 0, 0, len(glyphs),
+    )
+    maxp = struct.pack(">IH", 0x00005000, len(glyphs))
+    hmtx = b"".join(struct.pack(">Hh", advance, 0) for advance, _ in glyphs)
+    loca_data = b"".join(struct.pack(">I", offset) for offset in loca)
+
+    groups = b"".join(struct.pack(">III", *group) for group in cmap_groups)
+    subtable = struct.pack(">HHIII", cmap_format, 0, 16 + len(groups), 0, len(cmap_groups)) + groups
+    cmap = struct.pack(">HHHHI", 0, 1, 3, 10, 12) + subtable
+
+    tables = {
+        b"cmap": cmap,
+        b"glyf": glyf,
+        b"head": head,
+        b"hhea": hhea,
+        b"hmtx": hmtx,
+        b"loca": loca_data,
+        b"maxp": maxp,
+    }
+    header = struct.pack(">IHHHH", 0x00010000, len(tables), 64, 2, len(tables) * 16 - 64)
+    offset = 12 + 16 * len(tables)
+    records = b""
+    body = b""
+    for tag in sorted(tables):
+        data = tables[tag]
+        records += struct.pack(">4sIII", tag, 0, offset + len(body), len(data))
+        body += data + b"\0" * (-len(data) % 4)
+    return header + records + body
+
+
+def main():
+    out = Path(__file__).parent
+
+    # 0: .notdef, 1: 空白, 2: 印字可能な ASCII
+    latin = [
+        (500, rect_glyph(50, 0, 450, 700)),
+        (250, b""),
+        (500, rect_glyph(50, 0, 450, 700)),
+    ]
+    (out / "latin.ttf").write_bytes(
+        build_font(latin, [(0x20, 0x20, 1), (0x21, 0x7E, 2)], 13)
+    )
+
+    # 0: .notdef, 1: 全角の四角
+    cjk = [
+        (1000, rect_glyph(100, -100, 900, 700)),
+        (1000, rect_glyph(100, -100, 900, 700)),
+    ]
+    ranges = [(0x3001, 0x303F), (0x3041, 0x3096), (0x30A1, 0x30FA), (0x4E00, 0x9FFF), (0xFF01, 0xFF5E)]
+    (out / "cjk.ttf").write_bytes(
+        build_font(cjk, [(start, end, 1) for start, end in ranges], 13)
+    )
+
+
+if __name__ == "__main__":
+    main()
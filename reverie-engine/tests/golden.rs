@@ -0,0 +1,1512 @@
+//! 描画結果を参照画像と比較するテスト
+//!
+//! `cargo test -p reverie-engine --features test-harness` で実行する。
+//! 参照画像を作り直すときは `REVERIE_UPDATE_GOLDEN=1` を付ける。
+use std::time::{Duration, Instant};
+
+use nalgebra::{Point2, Point3, Scale3, Translation3, Vector2};
+use reverie_engine::{
+    accessibility::{ColorBlindFilter, ColorBlindMode},
+    particle::{ParticleBlend, ParticleEmitterComponent},
+    scene::{
+        CameraComponent, DecalLayerComponent, DrawList, EntityIndex, FloatingText,
+        FloatingTextAnimation, FloatingTextPool, FloatingTextSystem, Frame, LayerSortMode,
+        LayerSortModes, MaskComponent, NineSlice, ParallaxLayerComponent, RenderLayerComponent,
+        RenderStage, Scene, SceneClock, ScreenSpaceComponent, SpriteComponent, System,
+        SystemTimings, TextComponent, TileAnimation, TilemapComponent, Tileset, TransformComponent,
+        WorldWrap,
+    },
+    settings::Settings,
+    test_harness::{compare_with_reference, TestHarness},
+    text::{Fonts, TextIcon, TextStyle},
+    texture::{LoadPriority, TextureFilter, TextureId, TextureSettings},
+    wgpu_wrapper::{
+        material::{Material, UniformError},
+        memory::GpuMemoryCategory,
+        offscreen::OffscreenTarget,
+        render_graph::{RenderGraph, RenderPassDesc},
+        render_target::{RenderTargetDesc, SizePolicy},
+        vertex::UvVertex,
+        WgpuResource,
+    },
+};
+use reverie_util::{
+    color::Color,
+    math::{Rad, Rect},
+};
+use wgpu::util::DeviceExt;
+
+const SIZE: u32 = 64;
+const TOLERANCE: u8 = 2;
+
+fn reference(name: &str) -> std::path::PathBuf {
+    std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{name}.png"))
+}
+
+fn solid(harness: &mut TestHarness, rgba: [u8; 4]) -> TextureId {
+    harness
+        .resource
+        .texture_registry
+        .new_texture(image::RgbaImage::from_pixel(1, 1, image::Rgba(rgba)), None)
+        .into()
+}
+
+fn square(scene: &mut Scene, texture: TextureId, x: f32, y: f32, size: f32) -> EntityIndex {
+    scene.new_entity(
+        TransformComponent::with_translation_and_scale(
+            Translation3::new(x, y, 0.0),
+            Scale3::new(size, size, 1.0),
+        ),
+        SpriteComponent::new(texture),
+    )
+}
+
+#[test]
+fn sprite_layering() {
+    let Some(mut harness) = TestHarness::new_or_skip(SIZE, SIZE) else {
+        return;
+    };
+    let red = solid(&mut harness, [255, 0, 0, 255]);
+    let green = solid(&mut harness, [0, 255, 0, 255]);
+    let translucent_blue = solid(&mut harness, [0, 0, 255, 128]);
+
+    // 後に作ったスプライトほど手前に描かれる
+    let mut scene = Scene::default();
+    square(&mut scene, red, 24.0, 24.0, 32.0);
+    square(&mut scene, green, 32.0, 32.0, 32.0);
+    square(&mut scene, translucent_blue, 40.0, 40.0, 32.0);
+
+    let image = harness.render(&mut scene).unwrap();
+    compare_with_reference(&image, reference("sprite_layering"), TOLERANCE).unwrap();
+}
+
+#[test]
+fn sprite_tint() {
+    let Some(mut harness) = TestHarness::new_or_skip(SIZE, SIZE) else {
+        return;
+    };
+    let white = TextureId::WHITE;
+
+    // 組み込みの白いテクスチャに色を掛ける。右のスプライトは半透明
+    let mut scene = Scene::default();
+    let left = square(&mut scene, white, 20.0, 32.0, 24.0);
+    let right = square(&mut scene, white, 44.0, 32.0, 24.0);
+    for (entity, tint) in [
+        (left, Color::rgb(1.0, 0.5, 0.0)),
+        (right, Color::new(0.0, 0.0, 1.0, 0.5)),
+    ] {
+        scene.attach_component(entity, SpriteComponent::new(white).with_tint(tint));
+    }
+
+    let image = harness.render(&mut scene).unwrap();
+    compare_with_reference(&image, reference("sprite_tint"), TOLERANCE).unwrap();
+}
+
+#[test]
+fn nine_slice() {
+    let Some(mut harness) = TestHarness::new_or_skip(SIZE, SIZE) else {
+        return;
+    };
+    // 角が青、辺が赤、中央が緑の 3x3 のテクスチャ
+    let frame = harness
+        .resource
+        .texture_registry
+        .new_texture(
+            image::RgbaImage::from_fn(3, 3, |x, y| match (x == 1, y == 1) {
+                (true, true) => image::Rgba([0, 255, 0, 255]),
+                (false, false) => image::Rgba([0, 0, 255, 255]),
+                _ => image::Rgba([255, 0, 0, 255]),
+            }),
+            None,
+        )
+        .into();
+
+    // 48x32 に伸ばしても、角は 8x8 のまま
+    let mut scene = Scene::default();
+    scene.new_entity(
+        TransformComponent::with_translation_and_scale(
+            Translation3::new(32.0, 32.0, 0.0),
+            Scale3::new(48.0, 32.0, 1.0),
+        ),
+        SpriteComponent::builder(frame)
+            .nine_slice(NineSlice::new([1.0 / 3.0; 4], [8.0; 4]))
+            .build(),
+    );
+
+    let image = harness.render(&mut scene).unwrap();
+    assert_eq!(image.get_pixel(9, 17).0, [0, 0, 255, 255]);
+    assert_eq!(image.get_pixel(15, 23).0, [0, 0, 255, 255]);
+    assert_eq!(image.get_pixel(17, 17).0, [255, 0, 0, 255]);
+    assert_eq!(image.get_pixel(32, 32).0, [0, 255, 0, 255]);
+    assert_eq!(image.get_pixel(48, 47).0, [0, 0, 255, 255]);
+    compare_with_reference(&image, reference("nine_slice"), TOLERANCE).unwrap();
+}
+
+#[test]
+fn text_fallback_and_wrap() {
+    let Some(mut harness) = TestHarness::new_or_skip(SIZE, SIZE) else {
+        return;
+    };
+    // 欧文の文字は細い四角、和文の文字は大きな四角になるテスト用のフォント
+    let mut fonts = Fonts::with_atlas_size(16, 256);
+    let latin = fonts
+        .load(include_bytes!("fonts/latin.ttf").to_vec())
+        .unwrap();
+    let cjk = fonts
+        .load(include_bytes!("fonts/cjk.ttf").to_vec())
+        .unwrap();
+
+    let mut scene = Scene::default();
+    scene.insert_resource(fonts);
+    let style = TextStyle::new(latin, 16.0)
+        .with_fallback(cjk)
+        .with_color(Color::rgb(1.0, 1.0, 0.0));
+    scene.new_text(
+        TransformComponent::with_translation(Translation3::new(4.0, 4.0, 0.0)),
+        TextComponent::new("AB あい\nうえ", style).with_max_width(40.0),
+    );
+
+    let image = harness.render(&mut scene).unwrap();
+    compare_with_reference(&image, reference("text_fallback"), TOLERANCE).unwrap();
+}
+
+#[test]
+fn text_debug_font() {
+    let Some(mut harness) = TestHarness::new_or_skip(SIZE, SIZE) else {
+        return;
+    };
+    // フォントを読み込まずに、埋め込みのデバッグ用フォントで描く
+    let mut scene = Scene::default();
+    scene.insert_resource(Fonts::new());
+    scene.new_text(
+        TransformComponent::with_translation(Translation3::new(4.0, 4.0, 0.0)),
+        TextComponent::new("Hi!\ngpq 42", TextStyle::debug(9.0)),
+    );
+
+    let image = harness.render(&mut scene).unwrap();
+    compare_with_reference(&image, reference("text_debug_font"), TOLERANCE).unwrap();
+}
+
+#[test]
+fn text_spans_and_icons() {
+    let Some(mut harness) = TestHarness::new_or_skip(SIZE, SIZE) else {
+        return;
+    };
+    let mut fonts = Fonts::new();
+    let latin = fonts
+        .load(include_bytes!("fonts/latin.ttf").to_vec())
+        .unwrap();
+    let blue = solid(&mut harness, [0, 0, 255, 255]);
+    fonts.register_icon("key", TextIcon::new(blue, 2, 1));
+
+    // 赤い文字、ベースラインに揃えた横長のアイコン、2 倍の大きさの文字
+    let mut scene = Scene::default();
+    scene.insert_resource(fonts);
+    scene.new_text(
+        TransformComponent::with_translation(Translation3::new(4.0, 4.0, 0.0)),
+        TextComponent::from_markup(
+            "<color=#ff0000>AB</color> <icon:key>\n<scale=2>C</scale>D",
+            TextStyle::new(latin, 16.0),
+        ),
+    );
+
+    let image = harness.render(&mut scene).unwrap();
+    compare_with_reference(&image, reference("text_spans"), TOLERANCE).unwrap();
+}
+
+/// 層 0 と層 1 の間に、半透明の青い帯を自前のパイプラインで描く
+struct RangeOverlay {
+    texture: TextureId,
+}
+
+impl System for RangeOverlay {
+    fn setup(&mut self, _resource: Option<&WgpuResource<'_>>) {}
+
+    fn update(
+        &mut self,
+        _frame: &Frame<'_>,
+        _world: &mut hecs::World,
+        _resource: Option<&WgpuResource<'_>>,
+    ) {
+    }
+
+    fn render(
+        &mut self,
+        stage: RenderStage,
+        _draw_list: &DrawList,
+        rp: &mut wgpu::RenderPass<'_>,
+        resource: &WgpuResource<'_>,
+    ) {
+        if stage != RenderStage::BetweenLayers(0) {
+            return;
+        }
+        let pipeline = resource.pipeline_cache.get_or_create("range overlay", |_| {
+            resource.create_sprite_pipeline(None).unwrap()
+        });
+        let vertex = |x: f32, y: f32| UvVertex {
+            position: [x, y, 0.0],
+            uv: [0.0, 0.0],
+            color: [0.0, 0.0, 1.0, 0.5],
+        };
+        // 左上、右下、右上、左上、左下、右下
+        let vertices = [
+            vertex(8.0, 24.0),
+            vertex(56.0, 40.0),
+            vertex(56.0, 24.0),
+            vertex(8.0, 24.0),
+            vertex(8.0, 40.0),
+            vertex(56.0, 40.0),
+        ];
+        let buffer = resource
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Range Overlay"),
+                contents: bytemuck::cast_slice(&vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+        rp.set_pipeline(&pipeline);
+        rp.set_bind_group(
+            0,
+            resource.get_texture_bind_group(self.texture).unwrap(),
+            &[],
+        );
+        rp.set_vertex_buffer(0, buffer.slice(..));
+        rp.draw(0..vertices.len() as u32, 0..1);
+    }
+}
+
+#[test]
+fn render_stage_between_layers() {
+    let Some(mut harness) = TestHarness::new_or_skip(SIZE, SIZE) else {
+        return;
+    };
+    let red = solid(&mut harness, [255, 0, 0, 255]);
+    let green = solid(&mut harness, [0, 255, 0, 255]);
+    let white = solid(&mut harness, [255, 255, 255, 255]);
+
+    // 後に作った地面 (層 0) も、ユニット (層 1) より先に描かれる
+    let mut scene = Scene::default();
+    let unit = square(&mut scene, green, 32.0, 32.0, 16.0);
+    scene.attach_component(unit, RenderLayerComponent(1));
+    square(&mut scene, red, 32.0, 32.0, 40.0);
+    scene.register_system(RangeOverlay { texture: white });
+
+    let image = harness.render(&mut scene).unwrap();
+    compare_with_reference(&image, reference("render_stage"), TOLERANCE).unwrap();
+
+    // 段階ごとの時間は Scene::update が用意したリソースに記録され、end_frame で締まる
+    scene.update_headless(&Frame::new(Instant::now(), Duration::ZERO));
+    harness.render(&mut scene).unwrap();
+    scene.end_frame();
+    let timings = scene.resource::<SystemTimings>().unwrap();
+    assert!(timings.stage(RenderStage::BetweenLayers(0)).is_some());
+    assert!(timings.stage(RenderStage::AfterUi).is_some());
+    assert!(timings
+        .system(std::any::type_name::<RangeOverlay>())
+        .is_some());
+}
+
+#[test]
+fn overlay_pass_keeps_previous_contents() {
+    let Some(mut harness) = TestHarness::new_or_skip(SIZE, SIZE) else {
+        return;
+    };
+    let white = solid(&mut harness, [255, 255, 255, 255]);
+
+    let mut scene = Scene::default();
+    square(&mut scene, white, 16.0, 16.0, 16.0);
+    let camera = scene.new_camera(
+        TransformComponent::with_translation(Translation3::new(-32.0, -32.0, 0.0)),
+        CameraComponent::default(),
+    );
+    // 1 つ目のパスで黒く塗りつぶし、2 つ目のパスでは塗りつぶさずに別のカメラから描く
+    scene.set_render_graph(RenderGraph {
+        passes: vec![
+            RenderPassDesc {
+                clear_color: Some(Color::BLACK),
+                ..Default::default()
+            },
+            RenderPassDesc {
+                camera: Some(camera),
+                ..RenderPassDesc::overlay()
+            },
+        ],
+    });
+
+    let image = harness.render(&mut scene).unwrap();
+    compare_with_reference(&image, reference("overlay_pass"), TOLERANCE).unwrap();
+}
+
+#[test]
+fn screen_space_ignores_camera() {
+    let Some(mut harness) = TestHarness::new_or_skip(SIZE, SIZE) else {
+        return;
+    };
+    let red = solid(&mut harness, [255, 0, 0, 255]);
+    let white = solid(&mut harness, [255, 255, 255, 255]);
+
+    // ワールドのスプライトはカメラと一緒に動き、UI のスプライトは動かない
+    let mut scene = Scene::default();
+    square(&mut scene, red, 16.0, 16.0, 16.0);
+    let hud = square(&mut scene, white, 16.0, 16.0, 8.0);
+    scene.attach_component(hud, ScreenSpaceComponent);
+    let camera = scene.new_camera(
+        TransformComponent::with_translation(Translation3::new(-32.0, -32.0, 0.0)),
+        CameraComponent::default(),
+    );
+    scene.set_active_camera(camera);
+
+    let image = harness.render(&mut scene).unwrap();
+    compare_with_reference(&image, reference("screen_space"), TOLERANCE).unwrap();
+}
+
+#[test]
+fn frame_stats_count_culled_sprites() {
+    let Some(mut harness) = TestHarness::new_or_skip(SIZE, SIZE) else {
+        return;
+    };
+    let red = solid(&mut harness, [255, 0, 0, 255]);
+
+    // 画面の端にかかるものは描画し、完全に外れたものは描画しない
+    let mut scene = Scene::default();
+    square(&mut scene, red, 16.0, 16.0, 16.0);
+    square(&mut scene, red, -4.0, 32.0, 16.0);
+    square(&mut scene, red, -20.0, 32.0, 16.0);
+    square(&mut scene, red, 200.0, 200.0, 16.0);
+    let hud = square(&mut scene, red, 8.0, 8.0, 8.0);
+    scene.attach_component(hud, ScreenSpaceComponent);
+
+    harness.render(&mut scene).unwrap();
+    let stats = scene.frame_stats();
+    assert_eq!(stats.views().len(), 2);
+    assert_eq!(stats.views()[0].visible_sprites, 2);
+    assert_eq!(stats.views()[0].total_sprites, 4);
+    assert!(stats.views()[1].screen_space);
+    assert_eq!((stats.visible_sprites(), stats.total_sprites()), (3, 5));
+
+    // 2 フレーム目からは描画の並びのバッファを使い回す
+    assert!(stats.arena_high_water_bytes() > 0);
+    harness.render(&mut scene).unwrap();
+    assert_eq!(scene.frame_stats().arena_grown_bytes(), 0);
+}
+
+#[test]
+fn debug_draw_borrows_from_frame_arena() {
+    let Some(mut harness) = TestHarness::new_or_skip(SIZE, SIZE) else {
+        return;
+    };
+    let mut scene = Scene::default();
+    harness.render(&mut scene).unwrap();
+    let without_lines = scene.frame_stats().arena_high_water_bytes();
+
+    let draw_lines = |harness: &TestHarness| {
+        for i in 0..100 {
+            let x = i as f32;
+            harness.resource.debug_draw.line(
+                Point3::new(x, 0.0, 0.0),
+                Point3::new(x, 64.0, 0.0),
+                Color::WHITE,
+            );
+        }
+    };
+    // 線の頂点のバッファが伸びた分は集計に入り、次のフレームでは使い回す
+    draw_lines(&harness);
+    harness.render(&mut scene).unwrap();
+    assert!(scene.frame_stats().arena_grown_bytes() > 0);
+    assert!(scene.frame_stats().arena_high_water_bytes() > without_lines);
+    assert_eq!(harness.resource.debug_draw.vertex_count(), 0);
+
+    draw_lines(&harness);
+    harness.render(&mut scene).unwrap();
+    assert_eq!(scene.frame_stats().arena_grown_bytes(), 0);
+}
+
+#[test]
+fn offscreen_readback_can_be_polled() {
+    let Some(mut harness) = TestHarness::new_or_skip(SIZE, SIZE) else {
+        return;
+    };
+    let red = solid(&mut harness, [255, 0, 0, 255]);
+    let mut scene = Scene::default();
+    square(&mut scene, red, 24.0, 24.0, 32.0);
+    let expected = harness.render(&mut scene).unwrap();
+
+    let r = &harness.resource;
+    let size = std::num::NonZeroU32::new(SIZE).unwrap();
+    let target = OffscreenTarget::new(r, size, size);
+    target.render(&mut scene, r);
+    let request = target.request_pixels(r);
+    let pixels = (0..1000)
+        .find_map(|_| {
+            let pixels = request.try_get(r);
+            if pixels.is_none() {
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+            pixels
+        })
+        .expect("readback did not finish");
+    assert_eq!(pixels, expected.into_raw());
+}
+
+#[test]
+fn pipeline_cache_rebuilds_after_format_change() {
+    let Some(mut harness) = TestHarness::new_or_skip(SIZE, SIZE) else {
+        return;
+    };
+    let formats = harness.resource.target_formats();
+    assert_eq!(formats.color, WgpuResource::HEADLESS_FORMAT);
+    assert!(formats.is_srgb());
+    assert_eq!(
+        formats.depth_stencil,
+        Some(WgpuResource::DEPTH_STENCIL_FORMAT)
+    );
+
+    let created = std::cell::RefCell::new(Vec::new());
+    let material = |resource: &WgpuResource<'_>| {
+        resource
+            .pipeline_cache
+            .get_or_create("material", |formats| {
+                created.borrow_mut().push(formats.color);
+                resource.create_sprite_pipeline(None).unwrap()
+            })
+    };
+    material(&harness.resource);
+    material(&harness.resource);
+    assert_eq!(*created.borrow(), [WgpuResource::HEADLESS_FORMAT]);
+
+    // フォーマットが変わると、次に使うときに作り直す
+    harness
+        .resource
+        .set_surface_format(wgpu::TextureFormat::Rgba8Unorm)
+        .unwrap();
+    assert!(!harness.resource.target_formats().is_srgb());
+    assert!(harness.resource.pipeline_cache.get("material").is_none());
+    material(&harness.resource);
+    assert_eq!(
+        *created.borrow(),
+        [
+            WgpuResource::HEADLESS_FORMAT,
+            wgpu::TextureFormat::Rgba8Unorm
+        ]
+    );
+    assert_eq!(harness.resource.pipeline_cache.len(), 1);
+}
+
+#[test]
+fn material_keeps_last_pipeline_when_reload_fails() {
+    let Some(harness) = TestHarness::new_or_skip(SIZE, SIZE) else {
+        return;
+    };
+    let resource = &harness.resource;
+    let path = std::env::temp_dir().join(format!("reverie-material-{}.wgsl", std::process::id()));
+    let shader = include_str!("../src/shader.wgsl");
+    let write = |source: &str, age: u64| {
+        std::fs::write(&path, source).unwrap();
+        // 更新日時の精度が粗いファイルシステムでも変化が分かるようにする
+        let modified = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(age);
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+    };
+
+    write(shader, 1);
+    let mut material = Material::from_file("hot", &path);
+    let first = material.pipeline(resource).unwrap();
+    assert!(!material.reload_if_changed(resource));
+
+    // 壊れたシェーダーは前のパイプラインを残し、位置の付いたエラーを残す
+    write(&shader.replace("in.color;", "in.colour;"), 2);
+    assert!(!material.reload_if_changed(resource));
+    let error = material.last_error().unwrap();
+    assert!(error.contains("colour"), "{error}");
+    assert!(error.contains(".wgsl:"), "{error}");
+    assert!(std::rc::Rc::ptr_eq(
+        &first,
+        &material.pipeline(resource).unwrap()
+    ));
+    // 同じ内容では何度もコンパイルしない
+    assert!(!material.reload_if_changed(resource));
+
+    // レイアウトに合わないシェーダーも同じ
+    write(&shader.replace("@group(1)", "@group(3)"), 3);
+    assert!(!material.reload_if_changed(resource));
+    assert!(material.last_error().is_some());
+
+    write(shader, 4);
+    assert!(material.reload_if_changed(resource));
+    assert!(material.last_error().is_none());
+    assert!(!std::rc::Rc::ptr_eq(
+        &first,
+        &material.pipeline(resource).unwrap()
+    ));
+    std::fs::remove_file(&path).unwrap();
+}
+
+/// 画面の中央にマテリアルで四角を描く
+struct MaterialQuad {
+    material: Material,
+}
+
+impl System for MaterialQuad {
+    fn setup(&mut self, _resource: Option<&WgpuResource<'_>>) {}
+
+    fn update(
+        &mut self,
+        _frame: &Frame<'_>,
+        _world: &mut hecs::World,
+        _resource: Option<&WgpuResource<'_>>,
+    ) {
+    }
+
+    fn render(
+        &mut self,
+        stage: RenderStage,
+        _draw_list: &DrawList,
+        rp: &mut wgpu::RenderPass<'_>,
+        resource: &WgpuResource<'_>,
+    ) {
+        if stage != RenderStage::AfterWorld {
+            return;
+        }
+        let vertex = |x: f32, y: f32| UvVertex {
+            position: [x, y, 0.0],
+            uv: [0.0, 0.0],
+            color: [1.0; 4],
+        };
+        let vertices = [
+            vertex(16.0, 16.0),
+            vertex(48.0, 48.0),
+            vertex(48.0, 16.0),
+            vertex(16.0, 16.0),
+            vertex(16.0, 48.0),
+            vertex(48.0, 48.0),
+        ];
+        let buffer = resource
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Material Quad"),
+                contents: bytemuck::cast_slice(&vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+        self.material.bind(resource, rp).unwrap();
+        rp.set_bind_group(
+            0,
+            resource.get_texture_bind_group(TextureId::WHITE).unwrap(),
+            &[],
+        );
+        rp.set_vertex_buffer(0, buffer.slice(..));
+        rp.draw(0..vertices.len() as u32, 0..1);
+    }
+}
+
+#[test]
+fn material_uniforms_by_name() {
+    let Some(mut harness) = TestHarness::new_or_skip(SIZE, SIZE) else {
+        return;
+    };
+    let shader = include_str!("../src/shader.wgsl")
+        .replace(
+            "@vertex",
+            "struct Params { progress: f32, tint: vec4<f32> }\n\
+             @group(2) @binding(0) var<uniform> params: Params;\n@vertex",
+        )
+        .replace("* in.color;", "* in.color * params.tint * params.progress;");
+    let mut material = Material::from_wgsl("tinted", shader.clone());
+    assert_eq!(
+        material.set("progress", 1.0f32),
+        Err(UniformError::NoUniforms)
+    );
+    material.pipeline(&harness.resource).unwrap();
+    let layout = material.uniform_layout().unwrap();
+    assert_eq!(layout.field("tint").unwrap().offset, 16);
+
+    material.set("progress", 1.0f32).unwrap();
+    material
+        .set_color("tint", Color::rgb(0.0, 1.0, 0.0))
+        .unwrap();
+    assert!(matches!(
+        material.set_vec4("progress", [0.0; 4]),
+        Err(UniformError::TypeMismatch { .. })
+    ));
+    assert!(matches!(
+        material.set("dissolve", 1.0f32),
+        Err(UniformError::UnknownName(_))
+    ));
+
+    // 同じシェーダーは読み取り結果を使い回す
+    let mut twin = Material::from_wgsl("twin", shader);
+    twin.pipeline(&harness.resource).unwrap();
+    assert_eq!(harness.resource.shader_reflections.len(), 1);
+
+    let mut scene = Scene::default();
+    scene.register_system(MaterialQuad { material });
+    let image = harness.render(&mut scene).unwrap();
+    assert_eq!(image.get_pixel(32, 32).0, [0, 255, 0, 255]);
+}
+
+#[test]
+fn tilemap_animation_and_tint() {
+    let Some(mut harness) = TestHarness::new_or_skip(SIZE, SIZE) else {
+        return;
+    };
+    // 左半分が赤、右半分が緑の 2 枚のタイル
+    let tileset = harness
+        .resource
+        .texture_registry
+        .new_texture(
+            image::RgbaImage::from_fn(16, 8, |x, _| {
+                if x < 8 {
+                    image::Rgba([255, 0, 0, 255])
+                } else {
+                    image::Rgba([0, 255, 0, 255])
+                }
+            }),
+            None,
+        )
+        .into();
+    let mut tilemap =
+        TilemapComponent::new(Tileset::new(tileset, 2, 1), 4, 4, Vector2::new(16.0, 16.0));
+    for (x, y) in (0..4).flat_map(|y| (0..4).map(move |x| (x, y))) {
+        tilemap.set_tile(x, y, Some(0));
+    }
+    tilemap.set_tile(3, 3, Some(1));
+    tilemap.set_tint(3, 3, Color::rgb(0.0, 0.5, 0.0));
+    tilemap
+        .define_animation(
+            0,
+            TileAnimation::uniform([0, 1], Duration::from_millis(100)),
+        )
+        .unwrap();
+
+    let mut scene = Scene::default();
+    scene.new_tilemap(TransformComponent::default(), tilemap);
+    let image = harness.render(&mut scene).unwrap();
+    assert_eq!(image.get_pixel(8, 8).0, [255, 0, 0, 255]);
+    assert_eq!(image.get_pixel(40, 24).0, [255, 0, 0, 255]);
+    // 描画先は sRGB なので、0.5 は 187 くらいになる
+    let tinted = image.get_pixel(56, 56).0;
+    assert!(
+        tinted[1].abs_diff(187) <= TOLERANCE && tinted[0] == 0,
+        "{tinted:?}"
+    );
+
+    // 時計が進むと、アニメーションするタイルが揃って次のコマになる
+    scene.update_headless(&Frame::new(Instant::now(), Duration::from_millis(150)));
+    assert_eq!(
+        scene.resource::<SceneClock>().unwrap().elapsed.as_millis(),
+        150
+    );
+    let image = harness.render(&mut scene).unwrap();
+    assert_eq!(image.get_pixel(8, 8).0, [0, 255, 0, 255]);
+    assert_eq!(image.get_pixel(40, 24).0, [0, 255, 0, 255]);
+}
+
+#[test]
+fn y_sorted_characters_overlap_by_feet() {
+    let Some(mut harness) = TestHarness::new_or_skip(SIZE, SIZE) else {
+        return;
+    };
+    let red = solid(&mut harness, [255, 0, 0, 255]);
+    let green = solid(&mut harness, [0, 255, 0, 255]);
+
+    // 先に作った赤のほうが下にいるので、手前に描かれる
+    let mut scene = Scene::default();
+    let mut modes = LayerSortModes::default();
+    modes.set(1, LayerSortMode::Y);
+    scene.insert_resource(modes);
+    let character = |texture| SpriteComponent::builder(texture).size(24.0, 32.0).layer(1);
+    let at = |x, y| TransformComponent::with_translation(Translation3::new(x, y, 0.0));
+    let front = scene.new_sprite(at(28.0, 36.0), character(red));
+    scene.new_sprite(at(36.0, 28.0), character(green));
+    let image = harness.render(&mut scene).unwrap();
+    assert_eq!(image.get_pixel(32, 32).0, [255, 0, 0, 255]);
+
+    // 赤が上に移動すると、緑の後ろに回り込む
+    scene.attach_component(front, at(28.0, 20.0));
+    let image = harness.render(&mut scene).unwrap();
+    assert_eq!(image.get_pixel(32, 32).0, [0, 255, 0, 255]);
+
+    // 足元をずらせば、中心が上でも手前になる
+    scene.attach_component(
+        front,
+        SpriteComponent::builder(red)
+            .size(24.0, 32.0)
+            .sort_offset(16.0)
+            .build(),
+    );
+    let image = harness.render(&mut scene).unwrap();
+    assert_eq!(image.get_pixel(32, 32).0, [255, 0, 0, 255]);
+}
+
+#[test]
+fn uv_scroll_wraps_inside_atlas_region() {
+    let Some(mut harness) = TestHarness::new_or_skip(SIZE, SIZE) else {
+        return;
+    };
+    // 青の隣に、左半分が赤、右半分が緑の画像をアトラスに詰める
+    let registry = &mut harness.resource.texture_registry;
+    let atlas = registry.create_altas_texture(64, 64, None);
+    registry
+        .allocate_sub_image(
+            atlas,
+            image::RgbaImage::from_pixel(16, 16, image::Rgba([0, 0, 255, 255])),
+        )
+        .unwrap();
+    let belt = registry
+        .allocate_sub_image(
+            atlas,
+            image::RgbaImage::from_fn(16, 8, |x, _| {
+                if x < 8 {
+                    image::Rgba([255, 0, 0, 255])
+                } else {
+                    image::Rgba([0, 255, 0, 255])
+                }
+            }),
+        )
+        .unwrap();
+
+    let mut scene = Scene::default();
+    scene.new_sprite(
+        TransformComponent::with_translation(Translation3::new(32.0, 32.0, 0.0)),
+        SpriteComponent::builder(belt.into())
+            .size(32.0, 32.0)
+            .uv_scroll(0.5, 0.0),
+    );
+    let image = harness.render(&mut scene).unwrap();
+    assert_eq!(image.get_pixel(24, 32).0, [255, 0, 0, 255]);
+    assert_eq!(image.get_pixel(40, 32).0, [0, 255, 0, 255]);
+
+    // 1 秒で半分動き、はみ出した分はアトラスの隣ではなく同じ画像の反対側から出てくる
+    scene.update_headless(&Frame::new(Instant::now(), Duration::from_secs(1)));
+    let image = harness.render(&mut scene).unwrap();
+    assert_eq!(image.get_pixel(24, 32).0, [0, 255, 0, 255]);
+    assert_eq!(image.get_pixel(40, 32).0, [255, 0, 0, 255]);
+}
+
+#[test]
+fn render_targets_follow_resize() {
+    let Some(mut harness) = TestHarness::new_or_skip(SIZE, SIZE) else {
+        return;
+    };
+    let size = |n| std::num::NonZeroU32::new(n).unwrap();
+    let r = &mut harness.resource;
+    let hdr = r
+        .render_targets
+        .insert(
+            &r.device,
+            RenderTargetDesc::color(
+                "hdr",
+                WgpuResource::HEADLESS_FORMAT,
+                SizePolicy::WindowScale(1.0),
+            ),
+        )
+        .unwrap();
+    let bloom = r
+        .render_targets
+        .insert(
+            &r.device,
+            RenderTargetDesc::color(
+                "bloom",
+                WgpuResource::HEADLESS_FORMAT,
+                SizePolicy::Relative {
+                    parent: hdr,
+                    scale: 0.5,
+                },
+            ),
+        )
+        .unwrap();
+    let minimap = r
+        .render_targets
+        .insert(
+            &r.device,
+            RenderTargetDesc::color(
+                "minimap",
+                WgpuResource::HEADLESS_FORMAT,
+                SizePolicy::Fixed {
+                    width: size(16),
+                    height: size(16),
+                },
+            ),
+        )
+        .unwrap();
+    assert!(r.render_targets.remove(hdr).is_err());
+
+    let mut scene = Scene::default();
+    square(&mut scene, TextureId::WHITE, 8.0, 8.0, 8.0);
+    scene.setup(r).unwrap();
+
+    for (i, (width, height)) in [(100, 40), (33, 77), (33, 77), (SIZE, SIZE)]
+        .into_iter()
+        .enumerate()
+    {
+        let before = r.render_targets.version(hdr).unwrap();
+        r.resize(size(width), size(height));
+        let targets = &r.render_targets;
+        assert_eq!(targets.size(hdr), Some((width, height)));
+        assert_eq!(
+            targets.size(bloom),
+            Some((width.div_ceil(2), height.div_ceil(2)))
+        );
+        assert_eq!(targets.size(minimap), Some((16, 16)));
+        assert_eq!(targets.version(minimap), Some(0));
+        // 同じ大きさなら作り直さない
+        let resized = i != 2;
+        assert_eq!(targets.version(hdr), Some(before + u64::from(resized)));
+
+        // 深度・ステンシルバッファの大きさが描画先と違えば wgpu の検証で失敗する
+        r.render_to_view(&mut scene, targets.view(hdr).unwrap());
+    }
+    r.device.poll(wgpu::Maintain::Wait);
+}
+
+#[test]
+fn texture_settings_clamp_and_reload_over_frames() {
+    let Some(mut harness) = TestHarness::new_or_skip(SIZE, SIZE) else {
+        return;
+    };
+    let red = image::Rgba([255, 0, 0, 255]);
+    let r = &mut harness.resource;
+    let small = r
+        .texture_registry
+        .new_texture(image::RgbaImage::from_pixel(8, 8, red), None);
+    let large: Vec<_> = (0..6)
+        .map(|_| {
+            r.texture_registry
+                .new_texture(image::RgbaImage::from_pixel(64, 32, red), None)
+        })
+        .collect();
+    r.set_texture_settings(TextureSettings {
+        max_size: Some(16),
+        ..Default::default()
+    });
+
+    let mut scene = Scene::default();
+    square(&mut scene, large[0].into(), 32.0, 32.0, 32.0);
+    harness.render(&mut scene).unwrap();
+    let registry = &harness.resource.texture_registry;
+    assert_eq!(registry.gpu_size(small), Some((8, 8)));
+    assert_eq!(registry.gpu_size(large[0]), Some((16, 8)));
+    assert_eq!(registry.pending_reloads(), 0);
+
+    // 実行中に上限を外すと、次のフレームから少しずつ送り直す
+    registry.request_settings(TextureSettings {
+        filter: TextureFilter::Linear,
+        anisotropy: 4,
+        max_size: None,
+        min_mip_level: 0.5,
+    });
+    let r = &mut harness.resource;
+    r.maintain_textures();
+    assert_eq!(r.texture_registry.settings().filter, TextureFilter::Linear);
+    assert_eq!(
+        r.texture_registry.pending_reloads(),
+        large.len() - WgpuResource::TEXTURE_RELOADS_PER_FRAME
+    );
+    r.maintain_textures();
+    assert_eq!(r.texture_registry.pending_reloads(), 0);
+    for &index in &large {
+        assert_eq!(r.texture_registry.gpu_size(index), Some((64, 32)));
+    }
+    assert_eq!(r.texture_registry.gpu_size(small), Some((8, 8)));
+
+    let image = harness.render(&mut scene).unwrap();
+    assert_eq!(*image.get_pixel(32, 32), red);
+}
+
+#[test]
+fn min_mip_level_blurs_single_textures() {
+    let Some(mut harness) = TestHarness::new_or_skip(SIZE, SIZE) else {
+        return;
+    };
+    // 1 画素ごとの白黒の市松模様。1x1 の段は灰色になる
+    let checker: TextureId = harness
+        .resource
+        .texture_registry
+        .new_texture(
+            image::RgbaImage::from_fn(16, 16, |x, y| {
+                let v = if (x + y) % 2 == 0 { 0 } else { 255 };
+                image::Rgba([v, v, v, 255])
+            }),
+            None,
+        )
+        .into();
+    let mut render = |min_mip_level: f32| {
+        harness.resource.set_texture_settings(TextureSettings {
+            min_mip_level,
+            ..TextureSettings::default()
+        });
+        let mut scene = Scene::default();
+        square(&mut scene, checker, 32.0, 32.0, 16.0);
+        let image = harness.render(&mut scene).unwrap();
+        (34..38)
+            .flat_map(|y| (34..38).map(move |x| (x, y)))
+            .map(|(x, y)| image.get_pixel(x, y).0[0])
+            .collect::<Vec<_>>()
+    };
+
+    // 拡大も縮小もしないので、下限が無ければ元の段がそのまま写る
+    let sharp = render(0.0);
+    assert!(sharp.contains(&0) && sharp.contains(&255), "{sharp:?}");
+    // 1x1 の段しか使わないので、一様な灰色になる
+    let blurred = render(4.0);
+    assert!(blurred.iter().all(|&v| v == blurred[0]), "{blurred:?}");
+    assert!((64..=192).contains(&blurred[0]), "{blurred:?}");
+}
+
+#[test]
+fn unused_textures_are_evicted_and_reloaded() {
+    let Some(mut harness) = TestHarness::new_or_skip(SIZE, SIZE) else {
+        return;
+    };
+    let red = image::Rgba([255, 0, 0, 255]);
+    let r = &mut harness.resource;
+    let level = r
+        .texture_registry
+        .new_texture(image::RgbaImage::from_pixel(32, 32, red), None);
+    let ui = r
+        .texture_registry
+        .new_texture(image::RgbaImage::from_pixel(16, 16, red), None);
+    r.texture_registry.pin(ui).unwrap();
+    r.unload_unused_after = Some(3);
+
+    let mut scene = Scene::default();
+    let sprite = square(&mut scene, level.into(), 32.0, 32.0, 32.0);
+    harness.render(&mut scene).unwrap();
+    let r = &mut harness.resource;
+    let before = r.gpu_memory.category_bytes(GpuMemoryCategory::Texture);
+    let pipeline = r.create_sprite_pipeline(None).unwrap();
+    r.pipeline_cache.insert("unused", pipeline);
+    let pipeline = r.create_sprite_pipeline(None).unwrap();
+    let held = r.pipeline_cache.get_or_create("held", |_| pipeline);
+    assert_eq!(r.collect_unused_assets(&scene), 0);
+
+    // 参照が無くなってから 3 フレーム経つと解放する。pin したものは残す
+    scene.despawn(sprite).unwrap();
+    let r = &mut harness.resource;
+    let evicted: usize = (0..3).map(|_| r.collect_unused_assets(&scene)).sum();
+    // テクスチャと、外で持たれていないパイプライン
+    assert_eq!(evicted, 2);
+    assert!(r.pipeline_cache.get("unused").is_none());
+    assert!(r.pipeline_cache.get("held").is_some());
+    drop(held);
+    assert!(r.texture_registry.is_evicted(level));
+    assert_eq!(r.texture_registry.gpu_size(level), None);
+    assert_eq!(r.texture_registry.gpu_size(ui), Some((16, 16)));
+    // ミップマップの段も合わせて解放する
+    assert_eq!(
+        r.gpu_memory.category_bytes(GpuMemoryCategory::Texture),
+        before - (32 * 32 + 16 * 16 + 8 * 8 + 4 * 4 + 2 * 2 + 1) * 4
+    );
+
+    // また使われたら送り直す
+    square(&mut scene, level.into(), 32.0, 32.0, 32.0);
+    r.collect_unused_assets(&scene);
+    assert!(!r.texture_registry.is_evicted(level));
+    assert_eq!(
+        r.gpu_memory.category_bytes(GpuMemoryCategory::Texture),
+        before
+    );
+    let image = harness.render(&mut scene).unwrap();
+    assert_eq!(*image.get_pixel(32, 32), red);
+}
+
+#[test]
+fn manual_frame_matches_standard_render() {
+    let Some(mut harness) = TestHarness::new_or_skip(SIZE, SIZE) else {
+        return;
+    };
+    let red = solid(&mut harness, [255, 0, 0, 255]);
+    let mut scene = Scene::default();
+    let camera = scene.new_camera(TransformComponent::default(), CameraComponent::default());
+    scene.set_active_camera(camera);
+    square(&mut scene, red, 24.0, 24.0, 32.0);
+    let expected = harness.render(&mut scene).unwrap();
+
+    let r = &harness.resource;
+    let size = std::num::NonZeroU32::new(SIZE).unwrap();
+    let read = |target: &OffscreenTarget| target.request_pixels(r).wait(r).unwrap();
+
+    // 既定の描画と同じものを、公開されている部品で組み立てる
+    let target = OffscreenTarget::new(r, size, size);
+    target.render_with(&mut scene, r, |frame, scene| {
+        let resource = frame.resource;
+        let batches = frame.prepare_sprites(scene, Some(camera)).unwrap();
+        let mut rp = frame.begin_render_pass(&RenderPassDesc::default());
+        scene.draw_sprites(batches, &mut rp, resource);
+    });
+    assert_eq!(read(&target), expected.into_raw());
+
+    // 自分のパスで塗ってから、その上にスプライトを重ねる
+    target.render_with(&mut scene, r, |frame, scene| {
+        drop(frame.begin_render_pass(&RenderPassDesc {
+            clear_color: Some(Color::BLACK),
+            ..RenderPassDesc::default()
+        }));
+        frame.render_scene_pass(scene, &RenderPassDesc::overlay());
+    });
+    let image = image::RgbaImage::from_raw(SIZE, SIZE, read(&target)).unwrap();
+    assert_eq!(image.get_pixel(0, 0).0, [0, 0, 0, 255]);
+    assert_eq!(image.get_pixel(32, 32).0, [255, 0, 0, 255]);
+}
+
+#[cfg(unix)]
+#[test]
+fn loaded_texture_appears_after_background_decode() {
+    let Some(mut harness) = TestHarness::new_or_skip(SIZE, SIZE) else {
+        return;
+    };
+    let mut png = std::io::Cursor::new(Vec::new());
+    image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+        4,
+        4,
+        image::Rgba([255, 0, 0, 255]),
+    ))
+    .write_to(&mut png, image::ImageFormat::Png)
+    .unwrap();
+    // 名前付きパイプから読ませると、こちらが書き込むまでデコードするスレッドは待ち続ける
+    let gate = std::env::temp_dir().join(format!("reverie-loader-gate-{}.png", std::process::id()));
+    let _ = std::fs::remove_file(&gate);
+    let mkfifo = std::process::Command::new("mkfifo").arg(&gate).status();
+    assert!(mkfifo.is_ok_and(|status| status.success()), "mkfifo failed");
+    let registry = &mut harness.resource.texture_registry;
+    let red = registry.load_texture_from_path(&gate, LoadPriority::Immediate);
+    let mut scene = Scene::default();
+    square(&mut scene, red.into(), 24.0, 24.0, 32.0);
+
+    // 届くまでは透明なので何も写らない
+    harness.resource.maintain_textures();
+    let image = harness.render(&mut scene).unwrap();
+    assert!(harness.resource.texture_registry.is_loading(red));
+    assert_eq!(image.get_pixel(32, 32), image.get_pixel(0, 0));
+
+    std::fs::write(&gate, png.into_inner()).unwrap();
+    std::fs::remove_file(&gate).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while harness.resource.texture_registry.is_loading(red) {
+        assert!(Instant::now() < deadline, "decoding timed out");
+        std::thread::sleep(Duration::from_millis(1));
+        harness.resource.maintain_textures();
+        let stats = harness.resource.texture_registry.load_stats();
+        assert_eq!(stats.main_thread_decode, Duration::ZERO);
+    }
+    let image = harness.render(&mut scene).unwrap();
+    assert_eq!(image.get_pixel(32, 32).0, [255, 0, 0, 255]);
+}
+
+#[test]
+fn pixel_snap_rounds_screen_space_position() {
+    let Some(mut harness) = TestHarness::new_or_skip(SIZE, SIZE) else {
+        return;
+    };
+    harness.resource.set_texture_settings(TextureSettings {
+        filter: TextureFilter::Linear,
+        ..TextureSettings::default()
+    });
+    // 線形補間すると、小数のピクセルだけずれたときに色が変わる
+    let gradient: TextureId = harness
+        .resource
+        .texture_registry
+        .new_texture(
+            image::RgbaImage::from_fn(2, 1, |x, _| {
+                let v = if x == 0 { 0 } else { 255 };
+                image::Rgba([v, v, v, 255])
+            }),
+            None,
+        )
+        .into();
+    let mut render_at = |x: f32, pixel_snap: Option<bool>| {
+        let mut sprite = SpriteComponent::builder(gradient).size(16.0, 16.0);
+        if let Some(pixel_snap) = pixel_snap {
+            sprite = sprite.pixel_snap(pixel_snap);
+        }
+        let mut scene = Scene::default();
+        let hud = scene.new_entity(
+            TransformComponent::with_translation(Translation3::new(x, 32.0, 0.0)),
+            sprite.build(),
+        );
+        scene.attach_component(hud, ScreenSpaceComponent);
+        harness.render(&mut scene).unwrap()
+    };
+    let exact = render_at(32.0, None);
+    // 画面に固定したものは既定で合わせる
+    assert_eq!(render_at(32.4, None), exact);
+    assert_ne!(render_at(32.4, Some(false)), exact);
+}
+
+#[test]
+fn decal_layer_splats_erases_and_round_trips() {
+    let Some(mut harness) = TestHarness::new_or_skip(SIZE, SIZE) else {
+        return;
+    };
+    let red = solid(&mut harness, [255, 0, 0, 255]);
+    let bounds = Rect::new(Point2::origin(), Point2::new(SIZE as f32, SIZE as f32));
+    let mut layer =
+        DecalLayerComponent::new(&mut harness.resource.texture_registry, bounds, 1.0).unwrap();
+    // 1x1 のテクスチャを 8 倍にして 2 か所に貼り、爆発で右側だけ消す
+    for x in [16.0, 48.0] {
+        layer.splat(
+            red,
+            Point2::new(x, 16.0),
+            Rad(0.0),
+            Vector2::new(8.0, 8.0),
+            Color::WHITE,
+        );
+    }
+    layer.erase_rect(Rect::new(Point2::new(40.0, 8.0), Point2::new(56.0, 24.0)));
+    let mut scene = Scene::default();
+    let entity = scene.new_decal_layer(layer);
+    let image = harness.render(&mut scene).unwrap();
+    let background = image.get_pixel(32, 48).0;
+    assert_eq!(image.get_pixel(16, 16).0, [255, 0, 0, 255]);
+    assert_eq!(image.get_pixel(48, 16).0, background);
+    assert_eq!(image.get_pixel(16, 24).0, background);
+
+    // 保存したキャンバスを別のシーンの層に読み込む
+    let png = scene.save_decal_layer(entity, &harness.resource).unwrap();
+    let layer =
+        DecalLayerComponent::new(&mut harness.resource.texture_registry, bounds, 1.0).unwrap();
+    let mut loaded = Scene::default();
+    let entity = loaded.new_decal_layer(layer);
+    loaded
+        .load_decal_layer(entity, &mut harness.resource, &png)
+        .unwrap();
+    assert_eq!(harness.render(&mut loaded).unwrap(), image);
+}
+
+/// 半透明の赤を手前 (z = 0.2)、青を奥 (z = 0.8) に重ねて出すシーン
+fn overlapping_emitters(
+    harness: &mut TestHarness,
+    blend: ParticleBlend,
+    near_first: bool,
+) -> image::RgbaImage {
+    let white = solid(harness, [255, 255, 255, 255]);
+    let emitter = |color: Color, position: Point3<f32>| {
+        let mut emitter = ParticleEmitterComponent::new(white)
+            .with_blend(blend)
+            .with_rate(0.0)
+            .with_size(32.0)
+            .with_colors(color, color);
+        emitter.emit(position, 1);
+        emitter
+    };
+    let near = emitter(Color::new(1.0, 0.0, 0.0, 0.5), Point3::new(24.0, 32.0, 0.2));
+    let far = emitter(Color::new(0.0, 0.0, 1.0, 0.5), Point3::new(40.0, 32.0, 0.8));
+    let mut scene = Scene::default();
+    let emitters = if near_first { [near, far] } else { [far, near] };
+    for emitter in emitters {
+        scene.new_particle_emitter(TransformComponent::default(), emitter);
+    }
+    harness.render(&mut scene).unwrap()
+}
+
+#[test]
+fn particle_emitters_blend_back_to_front() {
+    let Some(mut harness) = TestHarness::new_or_skip(SIZE, SIZE) else {
+        return;
+    };
+    // 作った順によらず、奥の青の上に手前の赤が重なる
+    let image = overlapping_emitters(&mut harness, ParticleBlend::Alpha, true);
+    let reversed = overlapping_emitters(&mut harness, ParticleBlend::Alpha, false);
+    assert_eq!(image, reversed);
+    let [r, _, b, _] = image.get_pixel(32, 32).0;
+    assert!(r > b, "{:?}", image.get_pixel(32, 32));
+    compare_with_reference(&image, reference("particle_blend_sorting"), TOLERANCE).unwrap();
+
+    let premultiplied = overlapping_emitters(&mut harness, ParticleBlend::Premultiplied, false);
+    assert!(premultiplied.pixels().zip(image.pixels()).all(|(a, b)| a
+        .0
+        .iter()
+        .zip(b.0)
+        .all(|(a, b)| a.abs_diff(b) <= TOLERANCE)));
+
+    // 加算は並べ替えないが、重ねる順序で結果が変わらない
+    let additive = overlapping_emitters(&mut harness, ParticleBlend::Additive, true);
+    let reversed = overlapping_emitters(&mut harness, ParticleBlend::Additive, false);
+    assert_eq!(additive, reversed);
+    let [r, _, b, _] = additive.get_pixel(32, 32).0;
+    assert!(r > 0 && b > 0, "{:?}", additive.get_pixel(32, 32));
+}
+
+#[test]
+fn floating_text_rises_and_hides_from_cameras() {
+    let Some(mut harness) = TestHarness::new_or_skip(SIZE, SIZE) else {
+        return;
+    };
+    let mut scene = Scene::default();
+    scene.insert_resource(Fonts::new());
+    scene.register_system(FloatingTextSystem::new());
+    let spawn = |scene: &mut Scene, text| {
+        FloatingText::spawn(
+            scene,
+            text,
+            Point3::new(32.0, 40.0, 0.0),
+            TextStyle::debug(16.0),
+            1.0,
+        )
+    };
+    let first = spawn(&mut scene, "99");
+    scene.update_headless(&Frame::new(Instant::now(), Duration::from_millis(250)));
+    let image = harness.render(&mut scene).unwrap();
+    compare_with_reference(&image, reference("floating_text"), TOLERANCE).unwrap();
+    let bright = |image: &image::RgbaImage| image.pixels().filter(|p| p.0[0] > 200).count();
+    assert!(bright(&image) > 0);
+
+    // 消えたものは描かず、エンティティを次の文字列に使い回す
+    scene.update_headless(&Frame::new(Instant::now(), Duration::from_secs(1)));
+    assert_eq!(bright(&harness.render(&mut scene).unwrap()), 0);
+    assert_eq!(spawn(&mut scene, "7"), first);
+    assert_eq!(scene.resource::<FloatingTextPool>().unwrap().created(), 1);
+
+    // 層を隠したカメラには写らない
+    let transform = TransformComponent::default();
+    let main = scene.new_camera(transform.clone(), CameraComponent::default());
+    scene.set_active_camera(main);
+    assert!(bright(&harness.render(&mut scene).unwrap()) > 0);
+    let minimap = scene.new_camera(
+        transform,
+        CameraComponent::default().with_hidden_layers([FloatingTextAnimation::DEFAULT_LAYER]),
+    );
+    scene.set_active_camera(minimap);
+    assert_eq!(bright(&harness.render(&mut scene).unwrap()), 0);
+}
+
+#[test]
+fn color_blind_filter_and_ui_scale() {
+    let Some(mut harness) = TestHarness::new_or_skip(SIZE, SIZE) else {
+        return;
+    };
+    let red = solid(&mut harness, [255, 0, 0, 255]);
+    let green = solid(&mut harness, [0, 255, 0, 255]);
+    let white = solid(&mut harness, [255, 255, 255, 255]);
+
+    let mut scene = Scene::default();
+    scene.insert_resource(Settings::default());
+    square(&mut scene, red, 16.0, 16.0, 16.0);
+    square(&mut scene, green, 48.0, 16.0, 16.0);
+    let hud = square(&mut scene, white, 16.0, 24.0, 8.0);
+    scene.attach_component(hud, ScreenSpaceComponent);
+    let plain = harness.render(&mut scene).unwrap();
+    assert_eq!(plain.get_pixel(16, 12).0, [255, 0, 0, 255]);
+    assert_eq!(plain.get_pixel(16, 24).0, [255, 255, 255, 255]);
+
+    // 再起動せずにリソースを書き換えるだけで次のフレームから変わる
+    {
+        let mut settings = scene.resource_mut::<Settings>().unwrap();
+        settings.accessibility.color_blind_mode = ColorBlindMode::Protanopia;
+        settings.accessibility.color_blind_filter = ColorBlindFilter::Simulate;
+        settings.accessibility.ui_scale = 2.0;
+    }
+    let image = harness.render(&mut scene).unwrap();
+    compare_with_reference(&image, reference("color_blind_filter"), TOLERANCE).unwrap();
+
+    // 1 型の目には赤が暗く、緑が黄色く見える
+    let [r, _, _, _] = image.get_pixel(16, 12).0;
+    assert!(r < 160, "{:?}", image.get_pixel(16, 12));
+    let [r, g, _, _] = image.get_pixel(48, 12).0;
+    assert!(r > 200 && g > 200, "{:?}", image.get_pixel(48, 12));
+
+    // UI は 2 倍に拡大され、灰色はそのまま
+    let [r, g, b, _] = image.get_pixel(32, 48).0;
+    assert!(
+        r > 250 && g > 250 && b > 250,
+        "{:?}",
+        image.get_pixel(32, 48)
+    );
+    assert_eq!(image.get_pixel(16, 26), image.get_pixel(60, 60));
+}
+
+#[test]
+fn world_wrap_draws_sprites_across_the_seam() {
+    let Some(mut harness) = TestHarness::new_or_skip(SIZE, SIZE) else {
+        return;
+    };
+    let red = solid(&mut harness, [255, 0, 0, 255]);
+    let green = solid(&mut harness, [0, 255, 0, 255]);
+
+    // 幅 48 のワールドを、x = -8 から 64 ピクセル分写す
+    let mut scene = Scene::default();
+    scene.insert_resource(WorldWrap::new(48.0));
+    square(&mut scene, red, 4.0, 32.0, 6.0);
+    // 継ぎ目をまたぐ
+    square(&mut scene, green, 46.0, 32.0, 6.0);
+    let camera = scene.new_camera(
+        TransformComponent::with_translation(Translation3::new(-8.0, 0.0, 0.0)),
+        CameraComponent::default(),
+    );
+    scene.set_active_camera(camera);
+
+    let image = harness.render(&mut scene).unwrap();
+    compare_with_reference(&image, reference("world_wrap"), TOLERANCE).unwrap();
+    for (x, color) in [
+        (12, [255, 0, 0, 255]),
+        (60, [255, 0, 0, 255]),
+        (54, [0, 255, 0, 255]),
+        (6, [0, 255, 0, 255]),
+    ] {
+        assert_eq!(image.get_pixel(x, 32).0, color, "x = {x}");
+    }
+}
+
+#[test]
+fn masks_clip_descendants() {
+    let Some(mut harness) = TestHarness::new_or_skip(SIZE, SIZE) else {
+        return;
+    };
+    let gray = solid(&mut harness, [128, 128, 128, 255]);
+    let blue = solid(&mut harness, [0, 0, 255, 255]);
+    let red = solid(&mut harness, [255, 0, 0, 255]);
+    let green = solid(&mut harness, [0, 255, 0, 255]);
+    let circle: TextureId = harness
+        .resource
+        .texture_registry
+        .new_texture(
+            image::RgbaImage::from_fn(16, 16, |x, y| {
+                let (dx, dy) = (x as f32 - 7.5, y as f32 - 7.5);
+                let alpha = if dx.hypot(dy) <= 8.0 { 255 } else { 0 };
+                image::Rgba([255, 255, 255, alpha])
+            }),
+            None,
+        )
+        .into();
+
+    let mut scene = Scene::default();
+    // x が 8 から 32 の枠の中に、20 から 44 の枠を入れる
+    let panel = square(&mut scene, gray, 20.0, 32.0, 24.0);
+    scene.attach_component(panel, MaskComponent::rect(1.0, 1.0));
+    let inner = square(&mut scene, blue, 32.0, 32.0, 24.0);
+    scene.attach_component(inner, MaskComponent::rect(1.0, 1.0));
+    scene.set_parent(inner, panel).unwrap();
+    // 描く順序は層で決め、マスクは層をまたいでも効く
+    let item = square(&mut scene, red, 34.0, 32.0, 20.0);
+    scene.attach_component(item, RenderLayerComponent(1));
+    scene.set_parent(item, inner).unwrap();
+
+    // 円いミニマップ
+    let minimap = square(&mut scene, circle, 50.0, 50.0, 24.0);
+    scene.attach_component(minimap, ScreenSpaceComponent);
+    scene.attach_component(minimap, MaskComponent::sprite_alpha());
+    let map = square(&mut scene, green, 50.0, 50.0, 24.0);
+    scene.attach_component(map, ScreenSpaceComponent);
+    scene.attach_component(map, RenderLayerComponent(1));
+    scene.set_parent(map, minimap).unwrap();
+
+    let image = harness.render(&mut scene).unwrap();
+    compare_with_reference(&image, reference("mask"), TOLERANCE).unwrap();
+    let background = image.get_pixel(2, 2).0;
+    for ((x, y), color) in [
+        ((12, 32), [128, 128, 128, 255]),
+        ((22, 32), [0, 0, 255, 255]),
+        ((30, 32), [255, 0, 0, 255]),
+        // 外側の枠で切り抜かれる
+        ((36, 32), background),
+        ((50, 50), [0, 255, 0, 255]),
+        ((39, 39), background),
+    ] {
+        assert_eq!(image.get_pixel(x, y).0, color, "({x}, {y})");
+    }
+}
+
+#[test]
+fn parallax_layers_scroll_and_tile() {
+    let Some(mut harness) = TestHarness::new_or_skip(SIZE, SIZE) else {
+        return;
+    };
+    let white = solid(&mut harness, [255, 255, 255, 255]);
+    let green = solid(&mut harness, [0, 255, 0, 255]);
+    let stripe: TextureId = harness
+        .resource
+        .texture_registry
+        .new_texture(
+            image::RgbaImage::from_fn(2, 1, |x, _| {
+                if x == 0 {
+                    image::Rgba([255, 0, 0, 255])
+                } else {
+                    image::Rgba([0, 0, 255, 255])
+                }
+            }),
+            None,
+        )
+        .into();
+
+    // カメラを x = 40 に動かすと、ワールドの 40 から 104 が写る
+    let mut scene = Scene::default();
+    // 半分の速さの背景は 20 だけずれ、幅 8 ごとに並んで写る範囲を埋める
+    let background = square(&mut scene, stripe, 0.0, 16.0, 8.0);
+    scene.attach_component(background, ParallaxLayerComponent::new(0.5, 1.0).repeat_x());
+    square(&mut scene, white, 72.0, 40.0, 6.0);
+    // 1.5 倍の速さの前景は -20 ずれる
+    let foreground = square(&mut scene, green, 72.0, 40.0, 6.0);
+    scene.attach_component(foreground, ParallaxLayerComponent::new(1.5, 1.0));
+    let camera = scene.new_camera(
+        TransformComponent::with_translation(Translation3::new(40.0, 0.0, 0.0)),
+        CameraComponent::default(),
+    );
+    scene.set_active_camera(camera);
+
+    let image = harness.render(&mut scene).unwrap();
+    compare_with_reference(&image, reference("parallax"), TOLERANCE).unwrap();
+    for (x, y, color) in [
+        (2, 16, [255, 0, 0, 255]),
+        (6, 16, [0, 0, 255, 255]),
+        (50, 16, [255, 0, 0, 255]),
+        (62, 16, [0, 0, 255, 255]),
+        (32, 40, [255, 255, 255, 255]),
+        (12, 40, [0, 255, 0, 255]),
+    ] {
+        assert_eq!(image.get_pixel(x, y).0, color, "({x}, {y})");
+    }
+}
+
+#[cfg(feature = "mesh-export")]
+#[test]
+fn sprite_batch_dumps_to_ply_once() {
+    let Some(mut harness) = TestHarness::new_or_skip(SIZE, SIZE) else {
+        return;
+    };
+    let red = solid(&mut harness, [255, 0, 0, 255]);
+    let mut scene = Scene::default();
+    let tinted = scene.new_entity(
+        TransformComponent::with_translation_and_scale(
+            Translation3::new(20.0, 20.0, 0.0),
+            Scale3::new(8.0, 8.0, 1.0),
+        ),
+        SpriteComponent::new(red).with_tint(Color::new(0.25, 0.5, 1.0, 0.75)),
+    );
+    // 写らないものは書き出さない
+    square(&mut scene, red, 500.0, 20.0, 8.0);
+
+    let path = std::env::temp_dir().join(format!("reverie-sprite-dump-{}.ply", std::process::id()));
+    scene.dump_sprite_batch(&path);
+    harness.render(&mut scene).unwrap();
+    let ply = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(scene
+        .resource::<reverie_engine::scene::SpriteBatchDump>()
+        .is_none());
+
+    let (header, body) = ply.split_once("end_header\n").unwrap();
+    assert!(header.contains(&format!(
+        "comment entity {:?} layer 0: faces 0..2\n",
+        tinted.0
+    )));
+    assert!(header.contains("element vertex 4\n"));
+    let xs: Vec<f32> = body
+        .lines()
+        .take(4)
+        .map(|line| {
+            let values: Vec<f32> = line.split(' ').map(|v| v.parse().unwrap()).collect();
+            assert_eq!(values[5..], [0.25, 0.5, 1.0, 0.75]);
+            values[0]
+        })
+        .collect();
+    assert_eq!(xs, [16.0, 24.0, 16.0, 24.0]);
+
+    // 一度だけ書き出す
+    harness.render(&mut scene).unwrap();
+    assert!(!path.exists());
+}
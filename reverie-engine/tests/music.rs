@@ -0,0 +1,76 @@
+//! 曲と効果音を、シーンから [`Mixer`] を通して鳴らすテスト
+//!
+//! 出力の装置は使わず、出力側のコールバックの代わりに [`Mixer::fill`] を呼んでサンプルを確かめる。
+use std::{io::Cursor, time::Duration};
+
+use reverie_engine::{
+    audio::{Audio, AudioEmitterComponent, AudioSystem, LoopPoints, Mixer, MusicSource, OggSource},
+    headless::HeadlessRunner,
+    scene::{Scene, TaskQueue},
+    settings::Settings,
+};
+
+const TONE: &[u8] = include_bytes!("audio/tone.ogg");
+const SAMPLE_RATE: u32 = 8000;
+/// 効果音の値。曲と足しても区別できるように一定にする
+const BEEP: f32 = 0.25;
+
+/// 曲の最初の`frames`フレームをデコードする
+fn decode_tone(frames: usize) -> Vec<f32> {
+    let mut source = OggSource::new(Cursor::new(TONE)).unwrap();
+    let mut out = vec![0.0; frames * 2];
+    assert_eq!(source.read(&mut out).unwrap(), frames);
+    out
+}
+
+fn fill(mixer: &Mixer, frames: usize) -> Vec<f32> {
+    let mut out = vec![0.0; frames * 2];
+    mixer.fill(&mut out);
+    out
+}
+
+#[test]
+fn scene_music_and_effects_are_mixed_with_separate_volumes() {
+    let mixer = Mixer::new(SAMPLE_RATE);
+    let music = mixer.music();
+    let theme = music.register(
+        || Ok(Box::new(OggSource::new(Cursor::new(TONE))?) as Box<dyn MusicSource>),
+        Some(LoopPoints::WHOLE),
+    );
+    let beep = mixer
+        .add_sound(vec![BEEP; SAMPLE_RATE as usize], 1, SAMPLE_RATE)
+        .unwrap();
+
+    let mut scene = Scene::default();
+    scene.insert_resource(Audio::new(mixer.clone()));
+    scene.insert_resource(music.clone());
+    let mut tasks = TaskQueue::default();
+    tasks.push_once(0, move |world: &mut hecs::World| {
+        world.spawn((AudioEmitterComponent::new(beep).looping(),));
+    });
+    scene.insert_resource(tasks);
+    scene.register_system(AudioSystem::new());
+    let mut runner = HeadlessRunner::new(scene);
+    runner.setup().unwrap();
+    // 1 回目の更新の後でエンティティが作られ、2 回目で鳴り始める
+    runner.tick();
+    runner.tick();
+    assert_eq!(mixer.voice_count(), 1);
+    assert_eq!(fill(&mixer, 100), [BEEP; 200]);
+
+    music.play(theme, Duration::ZERO).unwrap();
+    let expected: Vec<f32> = decode_tone(400).iter().map(|tone| tone + BEEP).collect();
+    assert_eq!(fill(&mixer, 400), expected);
+
+    // 設定の画面で曲だけを消し、効果音を半分にする
+    let mut settings = Settings::default();
+    settings.audio.music_volume = 0.0;
+    settings.audio.sfx_volume = 0.5;
+    runner.scene_mut().apply_settings(&settings);
+    runner.tick();
+    // 音量はノイズが出ないように少しずつ変わる
+    let ramp = fill(&mixer, 400);
+    assert_ne!(ramp[0], ramp[799]);
+    assert_eq!(fill(&mixer, 100), [BEEP * 0.5; 200]);
+    assert_eq!(music.current(), Some(theme));
+}
@@ -1,2 +1,4 @@
+pub mod color;
 pub mod interpolation;
 pub mod math;
+pub mod memory;
@@ -5,6 +5,13 @@ pub use nalgebra;
 
 #[macro_use]
 mod macros;
+pub mod fps_camera;
+pub mod orbit_camera;
+mod rect;
+pub mod spline;
+pub mod wrap;
+
+pub use rect::Rect;
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Clone, Copy)]
 pub struct Deg<T>(pub T);
@@ -0,0 +1,241 @@
+//! 一人称視点のカメラの操作
+use nalgebra::{Isometry3, Matrix4, Point3, Vector3};
+
+use super::{calc_front_right_up, Deg, Rad};
+
+/// 見上げ・見下ろしの限界 (度)。真上や真下を向くと左右が決まらなくなるので少し手前で止める
+pub const MAX_PITCH_DEG: f32 = 89.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+/// 移動の入力。それぞれ -1.0 から 1.0
+pub struct MoveInput {
+    /// 前が正
+    pub forward: f32,
+    /// 右が正
+    pub right: f32,
+    /// 上が正
+    pub up: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// 前に進むときの向き
+pub enum FpsMoveMode {
+    /// 見ている向きにそのまま進む
+    #[default]
+    Fly,
+    /// 見上げていても水平に進む。上下の入力は Y 軸に沿って動かすので、重力のあるゲームでは `up` を 0 にする
+    Walk,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// 位置と向き (ヨー・ピッチ) を持ち、マウスの移動量と移動の入力で動く一人称視点のカメラ
+///
+/// ヨーが 0 なら +Z を向き、ピッチが正なら見上げる。ピッチは ±[`MAX_PITCH_DEG`] 度に収める。
+/// [`Self::view_matrix`] と [`Self::projection_matrix`] は OpenGL の座標系 (深度が -1 から 1) の行列。
+/// wgpu で使うときは [`Self::isometry`] をカメラの変換にする。
+pub struct FpsCameraController {
+    pub position: Point3<f32>,
+    yaw: Rad<f32>,
+    pitch: Rad<f32>,
+    /// 1 秒に進む距離
+    pub speed: f32,
+    /// マウスが 1 ピクセル動いたときに回る角度 (ラジアン)
+    pub sensitivity: f32,
+    pub mode: FpsMoveMode,
+    /// 縦方向の視野角
+    pub fov: Deg<f32>,
+    pub near: f32,
+    pub far: f32,
+}
+
+impl FpsCameraController {
+    pub fn new(position: Point3<f32>, yaw: Rad<f32>, pitch: Rad<f32>) -> Self {
+        Self {
+            position,
+            yaw,
+            pitch: clamp_pitch(pitch),
+            speed: 4.0,
+            sensitivity: 0.003,
+            mode: FpsMoveMode::Fly,
+            fov: Deg(60.0),
+            near: 0.1,
+            far: 100.0,
+        }
+    }
+
+    pub const fn with_speed(mut self, speed: f32) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    pub const fn with_sensitivity(mut self, sensitivity: f32) -> Self {
+        self.sensitivity = sensitivity;
+        self
+    }
+
+    pub const fn with_mode(mut self, mode: FpsMoveMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub const fn with_fov(mut self, fov: Deg<f32>) -> Self {
+        self.fov = fov;
+        self
+    }
+
+    /// [`FpsMoveMode::Fly`] と [`FpsMoveMode::Walk`] を切り替える
+    pub fn toggle_mode(&mut self) {
+        self.mode = match self.mode {
+            FpsMoveMode::Fly => FpsMoveMode::Walk,
+            FpsMoveMode::Walk => FpsMoveMode::Fly,
+        };
+    }
+
+    pub const fn yaw(&self) -> Rad<f32> {
+        self.yaw
+    }
+
+    pub const fn pitch(&self) -> Rad<f32> {
+        self.pitch
+    }
+
+    pub fn set_yaw(&mut self, yaw: Rad<f32>) {
+        self.yaw = yaw.normalized();
+    }
+
+    /// ピッチを変える。±[`MAX_PITCH_DEG`] 度に収める
+    pub fn set_pitch(&mut self, pitch: Rad<f32>) {
+        self.pitch = clamp_pitch(pitch);
+    }
+
+    /// マウスの移動量 (ピクセル) だけ向きを変える。右に動かすと右を、下に動かすと下を向く
+    pub fn look(&mut self, dx: f32, dy: f32) {
+        self.set_yaw(self.yaw - Rad(dx * self.sensitivity));
+        self.set_pitch(self.pitch - Rad(dy * self.sensitivity));
+    }
+
+    /// 前、右、上の向きの単位ベクトル
+    pub fn front_right_up(&self) -> (Vector3<f32>, Vector3<f32>, Vector3<f32>) {
+        calc_front_right_up(self.yaw, self.pitch)
+    }
+
+    /// `input` で進む向き。大きさは 1 以下
+    ///
+    /// [`Self::mode`] に従う。速さを掛けていないので、速度を自分で扱うゲームはこれを使う。
+    pub fn move_direction(&self, input: MoveInput) -> Vector3<f32> {
+        let (front, right, _) = self.front_right_up();
+        let front = match self.mode {
+            FpsMoveMode::Fly => front,
+            FpsMoveMode::Walk => Vector3::new(front.x, 0.0, front.z)
+                .try_normalize(f32::EPSILON)
+                .unwrap_or_else(Vector3::zeros),
+        };
+        let direction = front * input.forward + right * input.right + Vector3::y() * input.up;
+        if direction.norm_squared() > 1.0 {
+            direction.normalize()
+        } else {
+            direction
+        }
+    }
+
+    /// `look` だけ向きを変えてから、`input` に従って `delta_time` 秒だけ進む
+    pub fn update(&mut self, look: (f32, f32), input: MoveInput, delta_time: f32) {
+        self.look(look.0, look.1);
+        self.position += self.move_direction(input) * (self.speed * delta_time);
+    }
+
+    /// カメラの位置と向き。カメラは -Z を向き、+Y が上になる
+    pub fn isometry(&self) -> Isometry3<f32> {
+        let (front, _, up) = self.front_right_up();
+        Isometry3::look_at_rh(&self.position, &(self.position + front), &up).inverse()
+    }
+
+    /// ワールド座標からカメラから見た座標への変換行列
+    pub fn view_matrix(&self) -> Matrix4<f32> {
+        let (front, _, up) = self.front_right_up();
+        Matrix4::look_at_rh(&self.position, &(self.position + front), &up)
+    }
+
+    /// 透視投影の行列。深度は -1 から 1
+    pub fn projection_matrix(&self, width: u32, height: u32) -> Matrix4<f32> {
+        Matrix4::new_perspective(
+            width as f32 / height.max(1) as f32,
+            self.fov.to_rad().into(),
+            self.near,
+            self.far,
+        )
+    }
+}
+
+fn clamp_pitch(pitch: Rad<f32>) -> Rad<f32> {
+    let max = Deg(MAX_PITCH_DEG).to_rad().0;
+    Rad(pitch.0.clamp(-max, max))
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use super::*;
+
+    #[test]
+    fn pitch_is_clamped_and_look_follows_mouse() {
+        let mut camera =
+            FpsCameraController::new(Point3::origin(), Rad(0.0), Rad(0.0)).with_sensitivity(0.01);
+        camera.look(0.0, -1000.0);
+        assert_relative_eq!(camera.pitch().to_deg(), Deg(MAX_PITCH_DEG), epsilon = 1e-4);
+        camera.look(0.0, 2000.0);
+        assert_relative_eq!(camera.pitch().to_deg(), Deg(-MAX_PITCH_DEG), epsilon = 1e-4);
+
+        // 右に動かすと右を向く
+        let mut camera = FpsCameraController::new(Point3::origin(), Rad(0.0), Rad(0.0));
+        let (_, right, _) = camera.front_right_up();
+        camera.look(std::f32::consts::FRAC_PI_2 / camera.sensitivity, 0.0);
+        let (front, _, _) = camera.front_right_up();
+        assert_relative_eq!(front, right, epsilon = 1e-4);
+    }
+
+    #[test]
+    fn walk_stays_level_and_fly_follows_view() {
+        let forward = MoveInput {
+            forward: 1.0,
+            ..MoveInput::default()
+        };
+        let mut camera =
+            FpsCameraController::new(Point3::origin(), Rad(0.0), Deg(45.0_f32).to_rad())
+                .with_speed(2.0);
+        camera.update((0.0, 0.0), forward, 0.5);
+        let s = std::f32::consts::FRAC_1_SQRT_2;
+        assert_relative_eq!(camera.position, Point3::new(0.0, s, s), epsilon = 1e-5);
+
+        camera.toggle_mode();
+        assert_eq!(camera.mode, FpsMoveMode::Walk);
+        camera.position = Point3::origin();
+        camera.update((0.0, 0.0), forward, 0.5);
+        assert_relative_eq!(camera.position, Point3::new(0.0, 0.0, 1.0), epsilon = 1e-5);
+
+        // 斜めに進んでも速くならない
+        let diagonal = MoveInput {
+            forward: 1.0,
+            right: 1.0,
+            up: 1.0,
+        };
+        assert_relative_eq!(camera.move_direction(diagonal).norm(), 1.0, epsilon = 1e-5);
+    }
+
+    #[test]
+    fn matrices_agree_with_isometry() {
+        let camera = FpsCameraController::new(Point3::new(1.0, 2.0, 3.0), Rad(0.7), Rad(-0.3));
+        assert_relative_eq!(
+            camera.view_matrix(),
+            camera.isometry().inverse().to_homogeneous(),
+            epsilon = 1e-5
+        );
+        // 前にある点はカメラから見て -Z にある
+        let (front, _, _) = camera.front_right_up();
+        let ahead = camera
+            .view_matrix()
+            .transform_point(&(camera.position + front * 5.0));
+        assert_relative_eq!(ahead, Point3::new(0.0, 0.0, -5.0), epsilon = 1e-4);
+    }
+}
@@ -0,0 +1,309 @@
+//! 注視点の周りを回るカメラの操作
+use nalgebra::{Isometry3, Matrix4, Point3, Vector2, Vector3};
+
+use super::{calc_front_right_up, fps_camera::MAX_PITCH_DEG, Deg, Rad};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+/// 1 フレームの操作の入力
+pub struct OrbitInput {
+    /// 注視点の周りを回す量 (マウスの移動量、ピクセル)
+    pub orbit: Vector2<f32>,
+    /// 注視点を動かす量 (マウスの移動量、ピクセル)
+    pub pan: Vector2<f32>,
+    /// 近づく量 (ホイールの行数)。正なら近づく
+    pub zoom: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// 注視点、向き、距離の組
+struct OrbitState {
+    focus: Point3<f32>,
+    yaw: f32,
+    pitch: f32,
+    distance: f32,
+}
+
+impl OrbitState {
+    /// `target` に `t` の割合だけ近づける
+    fn approach(&mut self, target: &Self, t: f32) {
+        self.focus += (target.focus - self.focus) * t;
+        self.yaw += (target.yaw - self.yaw) * t;
+        self.pitch += (target.pitch - self.pitch) * t;
+        // 距離は比で近づけると、遠くても近くても同じ速さに見える
+        self.distance *= (target.distance / self.distance).powf(t);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// 注視点の周りを回り、注視点を動かし、注視点に近づくエディタ向けのカメラ
+///
+/// [`super::fps_camera::FpsCameraController`] と同じく、毎フレーム [`Self::update`] に入力をまとめて渡す。
+/// 入力はすぐに目標に反映し、実際の位置は [`Self::smoothing`] に従って目標に近づく。
+/// ピッチは ±[`MAX_PITCH_DEG`] 度に収めるので、真上や真下を向いて回転がおかしくなることはない。
+pub struct OrbitCameraController {
+    current: OrbitState,
+    target: OrbitState,
+    /// マウスが 1 ピクセル動いたときに回る角度 (ラジアン)
+    pub orbit_sensitivity: f32,
+    /// マウスが 1 ピクセル動いたときに注視点が動く距離。注視点までの距離に対する割合
+    pub pan_sensitivity: f32,
+    /// ホイール 1 行で注視点までの距離が縮む割合
+    pub zoom_step: f32,
+    pub min_distance: f32,
+    pub max_distance: f32,
+    /// 目標に近づく速さ。1 秒に残りの差が `exp(-smoothing)` 倍になる。0 なら補間しない
+    pub smoothing: f32,
+    /// 縦方向の視野角
+    pub fov: Deg<f32>,
+    pub near: f32,
+    pub far: f32,
+}
+
+impl OrbitCameraController {
+    pub fn new(focus: Point3<f32>, yaw: Rad<f32>, pitch: Rad<f32>, distance: f32) -> Self {
+        let state = OrbitState {
+            focus,
+            yaw: yaw.0,
+            pitch: clamp_pitch(pitch.0),
+            distance: distance.clamp(0.01, 1000.0),
+        };
+        Self {
+            current: state,
+            target: state,
+            orbit_sensitivity: 0.005,
+            pan_sensitivity: 0.001,
+            zoom_step: 0.1,
+            min_distance: 0.01,
+            max_distance: 1000.0,
+            smoothing: 15.0,
+            fov: Deg(60.0),
+            near: 0.1,
+            far: 1000.0,
+        }
+    }
+
+    pub const fn with_smoothing(mut self, smoothing: f32) -> Self {
+        self.smoothing = smoothing;
+        self
+    }
+
+    pub fn with_distance_limits(mut self, min: f32, max: f32) -> Self {
+        self.min_distance = min;
+        self.max_distance = max;
+        self.current.distance = self.current.distance.clamp(min, max);
+        self.target.distance = self.target.distance.clamp(min, max);
+        self
+    }
+
+    pub const fn with_fov(mut self, fov: Deg<f32>) -> Self {
+        self.fov = fov;
+        self
+    }
+
+    pub const fn focus(&self) -> Point3<f32> {
+        self.current.focus
+    }
+
+    pub const fn yaw(&self) -> Rad<f32> {
+        Rad(self.current.yaw)
+    }
+
+    pub const fn pitch(&self) -> Rad<f32> {
+        Rad(self.current.pitch)
+    }
+
+    pub const fn distance(&self) -> f32 {
+        self.current.distance
+    }
+
+    /// 補間の行き先の注視点
+    pub const fn target_focus(&self) -> Point3<f32> {
+        self.target.focus
+    }
+
+    /// 補間の行き先の注視点までの距離
+    pub const fn target_distance(&self) -> f32 {
+        self.target.distance
+    }
+
+    /// 注視点を変える。補間して動く
+    pub fn set_focus(&mut self, focus: Point3<f32>) {
+        self.target.focus = focus;
+    }
+
+    /// 注視点までの距離を変える。補間して動く
+    pub fn set_distance(&mut self, distance: f32) {
+        self.target.distance = distance.clamp(self.min_distance, self.max_distance);
+    }
+
+    /// 向きを変える。補間して動く
+    pub fn set_angles(&mut self, yaw: Rad<f32>, pitch: Rad<f32>) {
+        self.target.yaw = yaw.0;
+        self.target.pitch = clamp_pitch(pitch.0);
+    }
+
+    /// 補間を飛ばして目標の位置に移る
+    pub fn snap(&mut self) {
+        self.current = self.target;
+    }
+
+    /// 前、右、上の向きの単位ベクトル
+    pub fn front_right_up(&self) -> (Vector3<f32>, Vector3<f32>, Vector3<f32>) {
+        calc_front_right_up(Rad(self.current.yaw), Rad(self.current.pitch))
+    }
+
+    /// カメラの位置
+    pub fn position(&self) -> Point3<f32> {
+        let (front, _, _) = self.front_right_up();
+        self.current.focus - front * self.current.distance
+    }
+
+    /// `input` を目標に反映し、`delta_time` 秒だけ目標に近づく
+    ///
+    /// 向きは [`super::fps_camera::FpsCameraController::look`] と同じで、マウスを右に動かすと右を、下に動かすと下を向く。
+    /// つまり注視点の周りを左に、上に回る。
+    pub fn update(&mut self, input: OrbitInput, delta_time: f32) {
+        self.target.yaw -= input.orbit.x * self.orbit_sensitivity;
+        self.target.pitch = clamp_pitch(self.target.pitch - input.orbit.y * self.orbit_sensitivity);
+
+        if input.pan != Vector2::zeros() {
+            let (_, right, up) = calc_front_right_up(Rad(self.target.yaw), Rad(self.target.pitch));
+            // 画面の上でつかんだものがマウスについてくるように動かす
+            let scale = self.target.distance * self.pan_sensitivity;
+            self.target.focus += (-right * input.pan.x + up * input.pan.y) * scale;
+        }
+
+        if input.zoom != 0.0 {
+            let distance = self.target.distance * (1.0 - self.zoom_step).powf(input.zoom);
+            self.set_distance(distance);
+        }
+
+        if self.smoothing <= 0.0 {
+            self.snap();
+        } else {
+            let t = 1.0 - (-self.smoothing * delta_time).exp();
+            self.current.approach(&self.target, t);
+        }
+    }
+
+    /// 直方体 `min`..`max` が画面に収まるように注視点と距離を決める。補間して動く
+    ///
+    /// 向きは変えない。直方体を囲む球が縦の視野角に収まる距離にする。
+    pub fn frame_aabb(&mut self, min: &Point3<f32>, max: &Point3<f32>) {
+        let center = nalgebra::center(min, max);
+        let radius = (max - min).norm() / 2.0;
+        let half_fov = self.fov.to_rad().0 / 2.0;
+        self.target.focus = center;
+        self.set_distance((radius / half_fov.sin()).max(self.near + radius));
+    }
+
+    /// カメラの位置と向き。カメラは -Z を向き、+Y が上になる
+    pub fn isometry(&self) -> Isometry3<f32> {
+        let (_, _, up) = self.front_right_up();
+        Isometry3::look_at_rh(&self.position(), &self.current.focus, &up).inverse()
+    }
+
+    /// ワールド座標からカメラから見た座標への変換行列
+    pub fn view_matrix(&self) -> Matrix4<f32> {
+        let (_, _, up) = self.front_right_up();
+        Matrix4::look_at_rh(&self.position(), &self.current.focus, &up)
+    }
+
+    /// 透視投影の行列。深度は -1 から 1
+    pub fn projection_matrix(&self, width: u32, height: u32) -> Matrix4<f32> {
+        Matrix4::new_perspective(
+            width as f32 / height.max(1) as f32,
+            self.fov.to_rad().into(),
+            self.near,
+            self.far,
+        )
+    }
+}
+
+fn clamp_pitch(pitch: f32) -> f32 {
+    let max = MAX_PITCH_DEG.to_radians();
+    pitch.clamp(-max, max)
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use super::*;
+
+    fn controller() -> OrbitCameraController {
+        OrbitCameraController::new(Point3::origin(), Rad(0.0), Rad(0.0), 10.0).with_smoothing(0.0)
+    }
+
+    #[test]
+    fn orbits_pans_and_zooms_around_focus() {
+        let mut camera = controller();
+        assert_relative_eq!(
+            camera.position(),
+            Point3::new(0.0, 0.0, -10.0),
+            epsilon = 1e-5
+        );
+
+        // 真上から見下ろそうとしても手前で止まる
+        camera.update(
+            OrbitInput {
+                orbit: Vector2::new(0.0, 1.0e4),
+                ..OrbitInput::default()
+            },
+            0.0,
+        );
+        assert_relative_eq!(
+            camera.pitch().0,
+            -MAX_PITCH_DEG.to_radians(),
+            epsilon = 1e-5
+        );
+        assert!(camera.position().y > 9.9);
+        assert_relative_eq!(
+            (camera.position() - camera.focus()).norm(),
+            10.0,
+            epsilon = 1e-4
+        );
+
+        let mut camera = controller();
+        camera.update(
+            OrbitInput {
+                zoom: 2.0,
+                pan: Vector2::new(100.0, 0.0),
+                ..OrbitInput::default()
+            },
+            0.0,
+        );
+        assert_relative_eq!(camera.distance(), 8.1, epsilon = 1e-4);
+        // 右にドラッグすると注視点は左に動く
+        let (_, right, _) = camera.front_right_up();
+        assert!(camera.focus().coords.dot(&right) < 0.0);
+        // 注視点は画面の真ん中に写る
+        let focus = camera.view_matrix().transform_point(&camera.focus());
+        assert_relative_eq!(focus, Point3::new(0.0, 0.0, -8.1), epsilon = 1e-4);
+    }
+
+    #[test]
+    fn smoothing_approaches_target() {
+        let mut camera = controller().with_smoothing(10.0);
+        camera.set_distance(20.0);
+        camera.update(OrbitInput::default(), 0.05);
+        let halfway = camera.distance();
+        assert!(10.0 < halfway && halfway < 20.0);
+        for _ in 0..100 {
+            camera.update(OrbitInput::default(), 0.05);
+        }
+        assert_relative_eq!(camera.distance(), 20.0, epsilon = 1e-3);
+    }
+
+    #[test]
+    fn frame_aabb_fits_box() {
+        let mut camera = controller().with_fov(Deg(90.0));
+        let (min, max) = (Point3::new(4.0, -1.0, -1.0), Point3::new(6.0, 1.0, 1.0));
+        camera.frame_aabb(&min, &max);
+        camera.snap();
+        assert_relative_eq!(camera.focus(), Point3::new(5.0, 0.0, 0.0), epsilon = 1e-5);
+        // 囲む球の半径は √3、視野角の半分は 45°
+        let expected = 3.0_f32.sqrt() / std::f32::consts::FRAC_1_SQRT_2;
+        assert_relative_eq!(camera.distance(), expected, epsilon = 1e-4);
+    }
+}
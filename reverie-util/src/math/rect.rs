@@ -0,0 +1,92 @@
+use nalgebra::{Point2, Vector2};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// 軸に沿った長方形
+///
+/// `min` が各軸の小さい側の角、`max` が大きい側の角。
+pub struct Rect {
+    pub min: Point2<f32>,
+    pub max: Point2<f32>,
+}
+
+impl Rect {
+    /// 2 つの角から作る。角の順番は問わない
+    pub fn new(a: Point2<f32>, b: Point2<f32>) -> Self {
+        Self {
+            min: a.inf(&b),
+            max: a.sup(&b),
+        }
+    }
+
+    /// `min` の角と大きさから作る
+    pub fn from_min_size(min: Point2<f32>, size: Vector2<f32>) -> Self {
+        Self::new(min, min + size)
+    }
+
+    /// 中心と大きさから作る
+    pub fn from_center_size(center: Point2<f32>, size: Vector2<f32>) -> Self {
+        Self::new(center - size / 2.0, center + size / 2.0)
+    }
+
+    pub fn width(&self) -> f32 {
+        self.max.x - self.min.x
+    }
+
+    pub fn height(&self) -> f32 {
+        self.max.y - self.min.y
+    }
+
+    pub fn size(&self) -> Vector2<f32> {
+        self.max - self.min
+    }
+
+    pub fn center(&self) -> Point2<f32> {
+        nalgebra::center(&self.min, &self.max)
+    }
+
+    /// `point` が長方形の中 (辺の上を含む) にあるか
+    pub fn contains(&self, point: &Point2<f32>) -> bool {
+        point.x >= self.min.x
+            && point.x <= self.max.x
+            && point.y >= self.min.y
+            && point.y <= self.max.y
+    }
+
+    /// `other` と重なるか。辺が接しているだけのときも重なるとみなす
+    pub fn intersects(&self, other: &Self) -> bool {
+        self.min.x <= other.max.x
+            && other.min.x <= self.max.x
+            && self.min.y <= other.max.y
+            && other.min.y <= self.max.y
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_corners() {
+        let rect = Rect::new(Point2::new(4.0, 1.0), Point2::new(0.0, 3.0));
+        assert_eq!(rect.min, Point2::new(0.0, 1.0));
+        assert_eq!(rect.max, Point2::new(4.0, 3.0));
+        assert_eq!(rect.size(), Vector2::new(4.0, 2.0));
+        assert_eq!(rect.center(), Point2::new(2.0, 2.0));
+        assert_eq!(
+            Rect::from_center_size(Point2::new(2.0, 2.0), Vector2::new(4.0, 2.0)),
+            rect
+        );
+    }
+
+    #[test]
+    fn contains_and_intersects() {
+        let rect = Rect::from_min_size(Point2::origin(), Vector2::new(2.0, 2.0));
+        assert!(rect.contains(&Point2::new(2.0, 1.0)));
+        assert!(!rect.contains(&Point2::new(2.1, 1.0)));
+
+        let touching = Rect::from_min_size(Point2::new(2.0, 0.0), Vector2::new(1.0, 1.0));
+        let apart = Rect::from_min_size(Point2::new(3.0, 0.0), Vector2::new(1.0, 1.0));
+        assert!(rect.intersects(&touching));
+        assert!(!rect.intersects(&apart));
+    }
+}
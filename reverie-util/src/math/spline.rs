@@ -0,0 +1,348 @@
+//! Catmull-Rom スプラインと 3 次ベジェ曲線
+use std::sync::OnceLock;
+
+use nalgebra::{Point3, Vector3};
+
+/// 弧長を求めるときに 1 つの区間を分ける数
+const LENGTH_SAMPLES: usize = 16;
+
+/// `p1` から `p2` までの Catmull-Rom スプライン上の点。`t` は 0.0 から 1.0
+pub fn catmull_rom(
+    p0: &Point3<f32>,
+    p1: &Point3<f32>,
+    p2: &Point3<f32>,
+    p3: &Point3<f32>,
+    t: f32,
+) -> Point3<f32> {
+    let [a, b, c, d] = catmull_rom_coefficients(p0, p1, p2, p3);
+    Point3::from(a + b * t + c * (t * t) + d * (t * t * t))
+}
+
+/// [`catmull_rom`] の `t` での接線。`t` で微分したもの
+pub fn catmull_rom_tangent(
+    p0: &Point3<f32>,
+    p1: &Point3<f32>,
+    p2: &Point3<f32>,
+    p3: &Point3<f32>,
+    t: f32,
+) -> Vector3<f32> {
+    let [_, b, c, d] = catmull_rom_coefficients(p0, p1, p2, p3);
+    b + c * (2.0 * t) + d * (3.0 * t * t)
+}
+
+/// Catmull-Rom スプラインを `t` の多項式にしたときの係数。定数項から順
+fn catmull_rom_coefficients(
+    p0: &Point3<f32>,
+    p1: &Point3<f32>,
+    p2: &Point3<f32>,
+    p3: &Point3<f32>,
+) -> [Vector3<f32>; 4] {
+    let (p0, p1, p2, p3) = (p0.coords, p1.coords, p2.coords, p3.coords);
+    [
+        p1,
+        (p2 - p0) * 0.5,
+        (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * 0.5,
+        (p1 * 3.0 - p0 - p2 * 3.0 + p3) * 0.5,
+    ]
+}
+
+/// `p0` から `p3` までの 3 次ベジェ曲線上の点。`p1` と `p2` は制御点
+pub fn cubic_bezier(
+    p0: &Point3<f32>,
+    p1: &Point3<f32>,
+    p2: &Point3<f32>,
+    p3: &Point3<f32>,
+    t: f32,
+) -> Point3<f32> {
+    let u = 1.0 - t;
+    Point3::from(
+        p0.coords * (u * u * u)
+            + p1.coords * (3.0 * u * u * t)
+            + p2.coords * (3.0 * u * t * t)
+            + p3.coords * (t * t * t),
+    )
+}
+
+/// [`cubic_bezier`] の `t` での接線。`t` で微分したもの
+pub fn cubic_bezier_tangent(
+    p0: &Point3<f32>,
+    p1: &Point3<f32>,
+    p2: &Point3<f32>,
+    p3: &Point3<f32>,
+    t: f32,
+) -> Vector3<f32> {
+    let u = 1.0 - t;
+    (p1 - p0) * (3.0 * u * u) + (p2 - p1) * (6.0 * u * t) + (p3 - p2) * (3.0 * t * t)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// [`Spline`] の点のつなぎ方
+pub enum SplineKind {
+    /// すべての点を通る Catmull-Rom スプライン
+    CatmullRom,
+    /// 3 次ベジェ曲線をつないだもの
+    ///
+    /// 点は `[始点, 制御点, 制御点, 終点 (次の始点), 制御点, ...]` の順で、`3n + 1` 個並べる。
+    /// 余った点は使わない。
+    Bezier,
+}
+
+#[derive(Debug, Clone)]
+/// 点を滑らかにつないだ曲線
+///
+/// 位置と接線は、曲線全体を 0.0 から 1.0 とした `t` か、始点からの弧長で求める。
+/// 弧長で求めると一定の速さで動かせる。弧長の表は最初に使うときに作り、
+/// 点を書き換えると捨てて作り直す。
+pub struct Spline {
+    kind: SplineKind,
+    points: Vec<Point3<f32>>,
+    closed: bool,
+    /// 区間ごとに [`LENGTH_SAMPLES`] 個に分けた点までの、始点からの弧長
+    lengths: OnceLock<Vec<f32>>,
+}
+
+impl Spline {
+    pub fn new(kind: SplineKind, points: Vec<Point3<f32>>) -> Self {
+        Self {
+            kind,
+            points,
+            closed: false,
+            lengths: OnceLock::new(),
+        }
+    }
+
+    /// すべての点を通る Catmull-Rom スプライン
+    pub fn catmull_rom(points: Vec<Point3<f32>>) -> Self {
+        Self::new(SplineKind::CatmullRom, points)
+    }
+
+    /// 3 次ベジェ曲線をつないだもの。点の並べ方は [`SplineKind::Bezier`] を参照
+    pub fn bezier(points: Vec<Point3<f32>>) -> Self {
+        Self::new(SplineKind::Bezier, points)
+    }
+
+    /// 最後の点から最初の点に戻る輪にする。[`SplineKind::CatmullRom`] のときだけ効く
+    pub fn with_closed(mut self, closed: bool) -> Self {
+        self.set_closed(closed);
+        self
+    }
+
+    pub const fn kind(&self) -> SplineKind {
+        self.kind
+    }
+
+    pub fn points(&self) -> &[Point3<f32>] {
+        &self.points
+    }
+
+    /// 点を書き換える。弧長の表は作り直す
+    pub fn points_mut(&mut self) -> &mut Vec<Point3<f32>> {
+        self.lengths = OnceLock::new();
+        &mut self.points
+    }
+
+    /// `index` 番目の点を動かす
+    ///
+    /// # Panics
+    ///
+    /// `index` が点の数以上のとき
+    pub fn set_point(&mut self, index: usize, point: Point3<f32>) {
+        self.points_mut()[index] = point;
+    }
+
+    pub fn push(&mut self, point: Point3<f32>) {
+        self.points_mut().push(point);
+    }
+
+    pub const fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    pub fn set_closed(&mut self, closed: bool) {
+        self.closed = closed;
+        self.lengths = OnceLock::new();
+    }
+
+    /// 曲線の区間の数
+    pub fn segment_count(&self) -> usize {
+        let n = self.points.len();
+        match self.kind {
+            SplineKind::CatmullRom if self.closed && n >= 3 => n,
+            SplineKind::CatmullRom => n.saturating_sub(1),
+            SplineKind::Bezier => n.saturating_sub(1) / 3,
+        }
+    }
+
+    /// `segment` 番目の区間の 4 つの点
+    fn segment(&self, segment: usize) -> [&Point3<f32>; 4] {
+        let points = &self.points;
+        match self.kind {
+            SplineKind::CatmullRom => {
+                let n = points.len();
+                let at = |i: isize| {
+                    let i = if self.closed {
+                        i.rem_euclid(n as isize)
+                    } else {
+                        // 端では端の点を繰り返す
+                        i.clamp(0, n as isize - 1)
+                    };
+                    &points[i as usize]
+                };
+                let i = segment as isize;
+                [at(i - 1), at(i), at(i + 1), at(i + 2)]
+            }
+            SplineKind::Bezier => {
+                let i = segment * 3;
+                [&points[i], &points[i + 1], &points[i + 2], &points[i + 3]]
+            }
+        }
+    }
+
+    /// 曲線全体での `t` を、区間の番号とその中の `t` に分ける
+    fn locate(&self, t: f32) -> (usize, f32) {
+        let count = self.segment_count();
+        let t = t.clamp(0.0, 1.0) * count as f32;
+        let segment = (t.floor() as usize).min(count - 1);
+        (segment, t - segment as f32)
+    }
+
+    /// 曲線全体を 0.0 から 1.0 とした `t` での位置
+    ///
+    /// 区間が無ければ最初の点 (点も無ければ原点) を返す。
+    pub fn position(&self, t: f32) -> Point3<f32> {
+        if self.segment_count() == 0 {
+            return self.points.first().copied().unwrap_or_else(Point3::origin);
+        }
+        let (segment, t) = self.locate(t);
+        let [p0, p1, p2, p3] = self.segment(segment);
+        match self.kind {
+            SplineKind::CatmullRom => catmull_rom(p0, p1, p2, p3, t),
+            SplineKind::Bezier => cubic_bezier(p0, p1, p2, p3, t),
+        }
+    }
+
+    /// `t` での接線。長さは区間の中の `t` で微分したもので、正規化しない
+    ///
+    /// 区間が無ければ 0 を返す。
+    pub fn tangent(&self, t: f32) -> Vector3<f32> {
+        if self.segment_count() == 0 {
+            return Vector3::zeros();
+        }
+        let (segment, t) = self.locate(t);
+        let [p0, p1, p2, p3] = self.segment(segment);
+        match self.kind {
+            SplineKind::CatmullRom => catmull_rom_tangent(p0, p1, p2, p3, t),
+            SplineKind::Bezier => cubic_bezier_tangent(p0, p1, p2, p3, t),
+        }
+    }
+
+    fn lengths(&self) -> &[f32] {
+        self.lengths.get_or_init(|| {
+            let samples = self.segment_count() * LENGTH_SAMPLES;
+            let mut lengths = Vec::with_capacity(samples + 1);
+            let mut total = 0.0;
+            let mut previous = self.position(0.0);
+            lengths.push(total);
+            for i in 1..=samples {
+                let point = self.position(i as f32 / samples as f32);
+                total += (point - previous).norm();
+                lengths.push(total);
+                previous = point;
+            }
+            lengths
+        })
+    }
+
+    /// 曲線の長さ。折れ線で近似する
+    pub fn length(&self) -> f32 {
+        self.lengths().last().copied().unwrap_or(0.0)
+    }
+
+    /// 始点からの弧長が `distance` になる `t`
+    ///
+    /// `distance` は 0 から [`Self::length`] の範囲に収める。
+    pub fn t_at_distance(&self, distance: f32) -> f32 {
+        let lengths = self.lengths();
+        let samples = lengths.len().saturating_sub(1);
+        if samples == 0 {
+            return 0.0;
+        }
+        let distance = distance.clamp(0.0, self.length());
+        // distance を含む分割を探し、その中は線形に補間する
+        let i = lengths
+            .partition_point(|&length| length <= distance)
+            .clamp(1, samples);
+        let (start, end) = (lengths[i - 1], lengths[i]);
+        let fraction = if end > start {
+            (distance - start) / (end - start)
+        } else {
+            0.0
+        };
+        (i as f32 - 1.0 + fraction) / samples as f32
+    }
+
+    /// 始点からの弧長が `distance` の位置
+    pub fn position_at_distance(&self, distance: f32) -> Point3<f32> {
+        self.position(self.t_at_distance(distance))
+    }
+
+    /// 始点からの弧長が `distance` の位置での接線
+    pub fn tangent_at_distance(&self, distance: f32) -> Vector3<f32> {
+        self.tangent(self.t_at_distance(distance))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+
+    use super::*;
+
+    fn p(x: f32, y: f32) -> Point3<f32> {
+        Point3::new(x, y, 0.0)
+    }
+
+    #[test]
+    fn catmull_rom_passes_through_points() {
+        let spline = Spline::catmull_rom(vec![p(0.0, 0.0), p(1.0, 2.0), p(3.0, 1.0)]);
+        assert_eq!(spline.segment_count(), 2);
+        assert_relative_eq!(spline.position(0.0), p(0.0, 0.0));
+        assert_relative_eq!(spline.position(0.5), p(1.0, 2.0));
+        assert_relative_eq!(spline.position(1.0), p(3.0, 1.0));
+        // 中の点での接線は前後の点を結ぶ向き
+        assert_relative_eq!(spline.tangent(0.5), Vector3::new(1.5, 0.5, 0.0));
+
+        let closed = spline.with_closed(true);
+        assert_eq!(closed.segment_count(), 3);
+        assert_relative_eq!(closed.position(1.0), p(0.0, 0.0), epsilon = 1e-6);
+    }
+
+    #[test]
+    fn bezier_uses_control_points() {
+        let spline = Spline::bezier(vec![p(0.0, 0.0), p(0.0, 1.0), p(1.0, 1.0), p(1.0, 0.0)]);
+        assert_relative_eq!(spline.position(0.5), p(0.5, 0.75));
+        assert_relative_eq!(spline.tangent(0.0), Vector3::new(0.0, 3.0, 0.0));
+        assert_relative_eq!(spline.tangent(1.0), Vector3::new(0.0, -3.0, 0.0));
+    }
+
+    #[test]
+    fn arc_length_gives_constant_speed() {
+        // 点の間隔が違っても、弧長では等間隔に進む
+        let mut spline = Spline::catmull_rom(vec![p(0.0, 0.0), p(1.0, 0.0), p(3.0, 0.0)]);
+        assert_relative_eq!(spline.length(), 3.0, epsilon = 1e-3);
+        for distance in [0.5, 1.5, 2.5] {
+            assert_relative_eq!(
+                spline.position_at_distance(distance).x,
+                distance,
+                epsilon = 1e-2
+            );
+        }
+        assert_relative_eq!(spline.position_at_distance(20.0), p(3.0, 0.0));
+
+        // 点を書き換えると弧長の表を作り直す
+        spline.set_point(2, p(6.0, 0.0));
+        assert_relative_eq!(spline.length(), 6.0, epsilon = 1e-3);
+        spline.push(p(6.0, 3.0));
+        assert!(spline.length() > 9.0);
+    }
+}
@@ -0,0 +1,75 @@
+//! 横方向につながったワールドでの座標の計算
+//!
+//! 幅 `width` のワールドでは、x 座標が `width` 違う点は同じ点を表す。
+//! `width` が正の有限の値でなければ、つながっていないものとして普通に計算する。
+use nalgebra::{Point2, Vector2};
+
+fn is_wrapping(width: f32) -> bool {
+    width.is_finite() && width > 0.0
+}
+
+/// `x` を 0 以上 `width` 未満に収める
+pub fn wrap(x: f32, width: f32) -> f32 {
+    if !is_wrapping(width) {
+        return x;
+    }
+    let wrapped = x.rem_euclid(width);
+    // -0.000001 のような値は丸めると width になる
+    if wrapped >= width {
+        0.0
+    } else {
+        wrapped
+    }
+}
+
+/// `a` から `b` への x 座標の差のうち、継ぎ目をまたぐものも含めて最も短いもの
+///
+/// `-width / 2` 以上 `width / 2` 未満になる。
+pub fn wrapped_delta(a: f32, b: f32, width: f32) -> f32 {
+    if !is_wrapping(width) {
+        return b - a;
+    }
+    let delta = wrap(b - a, width);
+    if delta >= width / 2.0 {
+        delta - width
+    } else {
+        delta
+    }
+}
+
+/// `a` から `b` への最も短いベクトル。x 座標だけ継ぎ目をまたぐ
+///
+/// 追いかける向きを決めるのに使う。
+pub fn wrapped_vector(a: &Point2<f32>, b: &Point2<f32>, width: f32) -> Vector2<f32> {
+    Vector2::new(wrapped_delta(a.x, b.x, width), b.y - a.y)
+}
+
+/// 継ぎ目をまたぐものも含めた `a` と `b` の距離
+pub fn wrapped_distance(a: &Point2<f32>, b: &Point2<f32>, width: f32) -> f32 {
+    wrapped_vector(a, b, width).norm()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrap_keeps_coordinates_inside_the_width() {
+        assert_eq!(wrap(105.0, 100.0), 5.0);
+        assert_eq!(wrap(-5.0, 100.0), 95.0);
+        assert_eq!(wrap(-1e-7, 100.0), 0.0);
+        assert_eq!(wrap(-5.0, 0.0), -5.0);
+    }
+
+    #[test]
+    fn delta_crosses_the_seam_when_shorter() {
+        assert_eq!(wrapped_delta(95.0, 5.0, 100.0), 10.0);
+        assert_eq!(wrapped_delta(5.0, 95.0, 100.0), -10.0);
+        assert_eq!(wrapped_delta(20.0, 50.0, 100.0), 30.0);
+        assert_eq!(wrapped_delta(5.0, 95.0, f32::INFINITY), 90.0);
+
+        let (a, b) = (Point2::new(98.0, 0.0), Point2::new(1.0, 4.0));
+        assert_eq!(wrapped_vector(&a, &b, 100.0), Vector2::new(3.0, 4.0));
+        assert_eq!(wrapped_distance(&a, &b, 100.0), 5.0);
+    }
+}
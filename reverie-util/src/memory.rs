@@ -0,0 +1,248 @@
+//! GPU のメモリ使用量の集計
+//!
+//! バックエンドがテクスチャやバッファを作るときに大きさを登録し、[`TrackedAllocation`] が drop されたら差し引く。
+//! 実際の使用量はドライバによる詰め物や圧縮で変わるので、目安として使う。
+//! 分類はバックエンドごとに [`MemoryCategory`] を実装した型で決める。
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// 集計の分類
+pub trait MemoryCategory: Copy + Eq + Debug + 'static {
+    /// すべての分類。[`MemoryReport::by_category`] はこの順に並ぶ
+    const ALL: &'static [Self];
+
+    /// [`Self::ALL`] の中での位置
+    fn index(self) -> usize {
+        Self::ALL
+            .iter()
+            .position(|&category| category == self)
+            .expect("every category is listed in MemoryCategory::ALL")
+    }
+}
+
+#[derive(Debug)]
+struct Entry<C> {
+    category: C,
+    label: String,
+    bytes: u64,
+}
+
+#[derive(Debug)]
+struct Inner<C> {
+    next_id: u64,
+    entries: BTreeMap<u64, Entry<C>>,
+    totals: Vec<u64>,
+}
+
+#[derive(Debug)]
+/// GPU のリソースの大きさを分類 `C` ごとに集計する
+///
+/// 実体は [`Arc`] なので、クローンしたものは同じ集計を共有する。
+pub struct MemoryTracker<C> {
+    inner: Arc<Mutex<Inner<C>>>,
+}
+
+impl<C: MemoryCategory> Default for MemoryTracker<C> {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                next_id: 0,
+                entries: BTreeMap::new(),
+                totals: vec![0; C::ALL.len()],
+            })),
+        }
+    }
+}
+
+impl<C> Clone for MemoryTracker<C> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+/// 他のスレッドでパニックしていても集計だけは続ける
+fn lock<C>(inner: &Mutex<Inner<C>>) -> MutexGuard<'_, Inner<C>> {
+    inner.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl<C: MemoryCategory> MemoryTracker<C> {
+    /// リソースの大きさを登録する
+    ///
+    /// 戻り値をリソースと一緒に持っておき、リソースを解放するときに drop する。
+    pub fn track(&self, category: C, label: impl Into<String>, bytes: u64) -> TrackedAllocation<C> {
+        let mut inner = lock(&self.inner);
+        let id = inner.next_id;
+        inner.next_id += 1;
+        inner.totals[category.index()] += bytes;
+        inner.entries.insert(
+            id,
+            Entry {
+                category,
+                label: label.into(),
+                bytes,
+            },
+        );
+        drop(inner);
+        TrackedAllocation {
+            inner: Arc::clone(&self.inner),
+            id,
+        }
+    }
+
+    /// 全体の合計バイト数
+    pub fn total_bytes(&self) -> u64 {
+        lock(&self.inner).totals.iter().sum()
+    }
+
+    /// 分類ごとの合計バイト数
+    pub fn category_bytes(&self, category: C) -> u64 {
+        lock(&self.inner).totals[category.index()]
+    }
+
+    /// 合計と、大きい順に`top_n`個のリソースをまとめる
+    ///
+    /// 合計は登録のたびに更新しているので、かかる時間はリソースの数に比例する程度で済む。
+    pub fn report(&self, top_n: usize) -> MemoryReport<C> {
+        let inner = lock(&self.inner);
+        let mut largest: Vec<&Entry<C>> = inner.entries.values().collect();
+        if top_n < largest.len() {
+            largest.select_nth_unstable_by_key(top_n, |e| std::cmp::Reverse(e.bytes));
+            largest.truncate(top_n);
+        }
+        largest.sort_unstable_by_key(|e| std::cmp::Reverse(e.bytes));
+        MemoryReport {
+            total_bytes: inner.totals.iter().sum(),
+            by_category: C::ALL
+                .iter()
+                .map(|&c| (c, inner.totals[c.index()]))
+                .collect(),
+            largest: largest
+                .into_iter()
+                .map(|e| AllocationInfo {
+                    category: e.category,
+                    label: e.label.clone(),
+                    bytes: e.bytes,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug)]
+/// [`MemoryTracker::track`] で登録したリソース
+///
+/// drop すると集計から差し引かれる。
+pub struct TrackedAllocation<C: MemoryCategory> {
+    inner: Arc<Mutex<Inner<C>>>,
+    id: u64,
+}
+
+impl<C: MemoryCategory> TrackedAllocation<C> {
+    /// 登録したバイト数
+    pub fn bytes(&self) -> u64 {
+        lock(&self.inner)
+            .entries
+            .get(&self.id)
+            .map_or(0, |e| e.bytes)
+    }
+
+    /// 報告に使う名前を変える
+    pub fn set_label(&self, label: impl Into<String>) {
+        if let Some(entry) = lock(&self.inner).entries.get_mut(&self.id) {
+            entry.label = label.into();
+        }
+    }
+}
+
+impl<C: MemoryCategory> Drop for TrackedAllocation<C> {
+    fn drop(&mut self) {
+        let mut inner = lock(&self.inner);
+        if let Some(entry) = inner.entries.remove(&self.id) {
+            inner.totals[entry.category.index()] -= entry.bytes;
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// 1 つのリソースの大きさ
+pub struct AllocationInfo<C> {
+    pub category: C,
+    /// デバッグ用の名前
+    pub label: String,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// [`MemoryTracker::report`] の結果
+pub struct MemoryReport<C> {
+    pub total_bytes: u64,
+    /// [`MemoryCategory::ALL`] の順
+    pub by_category: Vec<(C, u64)>,
+    /// 大きい順
+    pub largest: Vec<AllocationInfo<C>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Category {
+        Texture,
+        Buffer,
+        Target,
+    }
+
+    impl MemoryCategory for Category {
+        const ALL: &'static [Self] = &[Self::Texture, Self::Buffer, Self::Target];
+    }
+
+    #[test]
+    fn dropped_allocation_is_subtracted() {
+        let tracker = MemoryTracker::default();
+        let a = tracker.track(Category::Texture, "a", 100);
+        let b = tracker.track(Category::Texture, "b", 300);
+        let c = tracker.track(Category::Buffer, "c", 200);
+        assert_eq!(tracker.total_bytes(), 600);
+        assert_eq!(tracker.category_bytes(Category::Texture), 400);
+        assert_eq!(b.bytes(), 300);
+
+        let report = tracker.report(2);
+        let labels: Vec<_> = report.largest.iter().map(|e| e.label.as_str()).collect();
+        assert_eq!(labels, ["b", "c"]);
+
+        drop(b);
+        assert_eq!(tracker.category_bytes(Category::Texture), 100);
+        let report = tracker.report(10);
+        assert_eq!(report.total_bytes, 300);
+        assert_eq!(report.largest.len(), 2);
+        assert_eq!(
+            report.by_category,
+            [
+                (Category::Texture, 100),
+                (Category::Buffer, 200),
+                (Category::Target, 0),
+            ]
+        );
+        drop((a, c));
+        assert_eq!(tracker.total_bytes(), 0);
+    }
+
+    #[test]
+    fn renamed_allocation_is_reported_with_new_label() {
+        let tracker = MemoryTracker::default();
+        let vbo = tracker.track(Category::Buffer, "Unnamed Vao", 64);
+        let _texture = tracker.track(Category::Texture, "grass", 1024);
+        vbo.set_label("terrain");
+
+        let report = tracker.report(1);
+        assert_eq!(report.total_bytes, 1088);
+        assert_eq!(report.largest[0].label, "grass");
+        assert_eq!(tracker.report(2).largest[1].label, "terrain");
+        // クローンは同じ集計を共有する
+        assert_eq!(tracker.clone().total_bytes(), 1088);
+    }
+}